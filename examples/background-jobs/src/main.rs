@@ -13,7 +13,11 @@ struct SendEmailJob {
 
 #[rwf::async_trait]
 impl Job for SendEmailJob {
-    async fn execute(&self, args: serde_json::Value) -> Result<(), JobError> {
+    async fn execute(
+        &self,
+        _context: &JobContext,
+        args: serde_json::Value,
+    ) -> Result<(), JobError> {
         let email: Self = serde_json::from_value(args)?;
 
         info!("executing {} with args {:?}", self.job_name(), email,);
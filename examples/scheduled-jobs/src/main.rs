@@ -9,7 +9,11 @@ struct MyJob;
 
 #[rwf::async_trait]
 impl Job for MyJob {
-    async fn execute(&self, _args: serde_json::Value) -> Result<(), JobError> {
+    async fn execute(
+        &self,
+        _context: &JobContext,
+        _args: serde_json::Value,
+    ) -> Result<(), JobError> {
         sleep(std::time::Duration::from_secs(1)).await;
         Ok(())
     }
@@ -1,4 +1,4 @@
-use rwf::job::JobModel;
+use rwf::job::{fleet_status, JobModel, WorkerStatus};
 use rwf::prelude::*;
 
 #[derive(Default)]
@@ -10,7 +10,9 @@ struct JobsContext {
     running: i64,
     errors: i64,
     latency: i64,
+    stale_workers: i64,
     jobs: Vec<JobModel>,
+    workers: Vec<WorkerStatus>,
     title: String,
 }
 
@@ -41,10 +43,14 @@ impl JobsContext {
             Duration::seconds(0).whole_seconds()
         };
 
+        let fleet = fleet_status().await?;
+
         Ok(Self {
             queued,
             errors,
             running,
+            stale_workers: fleet.stale_workers() as i64,
+            workers: fleet.workers,
             jobs,
             latency,
             title: format!("Jobs | Rust Web Framework"),
@@ -23,9 +23,10 @@ pub struct ModelController;
 #[async_trait]
 impl PageController for ModelController {
     async fn get(&self, request: &Request) -> Result<Response, Error> {
-        let model = request.query().get::<String>("name");
-        let page = request.query().get::<i64>("page").unwrap_or(1);
+        let model = request.path().query().get::<String>("name");
+        let page = request.path().query().get::<i64>("page").unwrap_or(1);
         let selected_columns = request
+            .path()
             .query()
             .get::<String>("columns")
             .unwrap_or("".to_string())
@@ -107,7 +108,7 @@ pub struct NewModelController;
 #[async_trait]
 impl PageController for NewModelController {
     async fn get(&self, request: &Request) -> Result<Response, Error> {
-        let model = request.query().get_required::<String>("name")?;
+        let model = request.path().query().get_required::<String>("name")?;
         let columns = TableColumn::for_table(&model)
             .await?
             .into_iter()
@@ -50,6 +50,15 @@ enum Subcommands {
 struct MigrateSubcommand {
     #[command(subcommand)]
     command: Migrate,
+
+    #[arg(
+        long,
+        short,
+        global = true,
+        help = "Database to migrate, configured in a [database.<name>] section",
+        default_value = "main"
+    )]
+    database: String,
 }
 
 /// Manage migrations.
@@ -59,8 +68,18 @@ enum Migrate {
     Run {
         #[arg(long, help = "Run migrations up to this version")]
         version: Option<i64>,
+
+        #[arg(
+            long,
+            help = "Accept changes made to migrations after they were applied",
+            default_value = "false"
+        )]
+        accept_changes: bool,
     },
 
+    /// Show which migrations are applied, pending or modified.
+    Status,
+
     /// Re-create your database from migrations.
     /// WARNING: this deletes all data.
     Flush {
@@ -83,6 +102,18 @@ enum Migrate {
         #[arg(long, short, help = "Migration name", default_value = "unnamed")]
         name: String,
     },
+
+    /// Write the database schema to a file.
+    Dump {
+        #[arg(help = "Schema file, defaults to the schema_dump setting")]
+        path: Option<PathBuf>,
+    },
+
+    /// Check that the database matches the schema file.
+    Verify {
+        #[arg(help = "Schema file, defaults to the schema_dump setting")]
+        path: Option<PathBuf>,
+    },
 }
 
 #[derive(Args, Debug)]
@@ -135,13 +166,19 @@ async fn main() {
 
     match args.subcommands {
         Subcommands::Migrate(migrate) => match migrate.command {
-            Migrate::Run { version } => migrate::migrate(version).await,
-            Migrate::Revert { version } => migrate::revert(version).await,
+            Migrate::Run {
+                version,
+                accept_changes,
+            } => migrate::migrate(&migrate.database, version, accept_changes).await,
+            Migrate::Status => migrate::status(&migrate.database).await,
+            Migrate::Revert { version } => migrate::revert(&migrate.database, version).await,
             Migrate::Flush { yes } => {
                 if yes {
-                    migrate::revert(None).await;
-                    migrate::migrate(None).await;
-                    let mut conn = Pool::connection()
+                    migrate::revert(&migrate.database, None).await;
+                    migrate::migrate(&migrate.database, None, false).await;
+                    let mut conn = Pool::named(&migrate.database)
+                        .expect("database is not configured")
+                        .get()
                         .await
                         .expect("failed to get connection from pool");
                     conn.query_cached("TRUNCATE TABLE rwf_jobs", &[])
@@ -152,7 +189,9 @@ async fn main() {
                     log::info!("Aborting");
                 }
             }
-            Migrate::Add { name } => migrate::add(&name).await,
+            Migrate::Add { name } => migrate::add(&migrate.database, &name).await,
+            Migrate::Dump { path } => migrate::dump(&migrate.database, path).await,
+            Migrate::Verify { path } => migrate::verify(&migrate.database, path).await,
         },
 
         Subcommands::Setup => setup::setup().await,
@@ -1,14 +1,27 @@
-use rwf::model::migrations::{Direction, Migrations};
-use std::path::Path;
+use rwf::colors::MaybeColorize;
+use rwf::config::get_config;
+use rwf::model::migrations::{Direction, MigrationStatus, Migrations};
+use rwf::model::pool::DEFAULT_DATABASE;
+use rwf::model::schema;
+use std::path::{Path, PathBuf};
 use time::OffsetDateTime;
 
 use regex::Regex;
-use tokio::fs::{create_dir, File};
+use tokio::fs::{create_dir_all, File};
 
 use crate::logging::created;
 
-pub async fn migrate(version: Option<i64>) {
-    let migrations = Migrations::sync().await.expect("failed to sync migrations");
+pub async fn migrate(database: &str, version: Option<i64>, accept_changes: bool) {
+    let mut migrations = Migrations::sync_database(database)
+        .await
+        .expect("failed to sync migrations");
+
+    if accept_changes {
+        migrations = migrations
+            .accept_changes()
+            .await
+            .expect("failed to accept changes to migrations");
+    }
 
     migrations
         .apply(Direction::Up, version)
@@ -16,8 +29,26 @@ pub async fn migrate(version: Option<i64>) {
         .expect("failed to apply migrations");
 }
 
-pub async fn revert(version: Option<i64>) {
-    let migrations = Migrations::sync().await.expect("failed to sync migrations");
+pub async fn status(database: &str) {
+    let migrations = Migrations::sync_database(database)
+        .await
+        .expect("failed to sync migrations");
+
+    for migration in migrations.migrations() {
+        let status = match migrations.status(migration) {
+            MigrationStatus::Pending => "pending".yellow(),
+            MigrationStatus::Applied => "applied".green(),
+            MigrationStatus::Modified => "modified".red(),
+        };
+
+        println!("{:<10} {}", status, migration.name());
+    }
+}
+
+pub async fn revert(database: &str, version: Option<i64>) {
+    let migrations = Migrations::sync_database(database)
+        .await
+        .expect("failed to sync migrations");
     let version = if let Some(version) = version {
         Some(version)
     } else {
@@ -30,17 +61,20 @@ pub async fn revert(version: Option<i64>) {
         .expect("failed to apply migrations");
 }
 
-pub async fn add(name: &str) {
+pub async fn add(database: &str, name: &str) {
     let regex = Regex::new("[^a-zA-Z0-9_]").unwrap();
     let name = regex.replace_all(name, "_");
     let version = OffsetDateTime::now_utc().unix_timestamp_nanos();
-    let path = Path::new("migrations");
+    let path = match database {
+        DEFAULT_DATABASE => PathBuf::from("migrations"),
+        database => Path::new("migrations").join(database),
+    };
 
     if !path.exists() {
-        create_dir(&path)
+        create_dir_all(&path)
             .await
             .expect("cannot create migrations directory");
-        created(format!("created \"migrations\" directory"));
+        created(format!("created \"{}\" directory", path.display()));
     }
 
     for suffix in ["up", "down"] {
@@ -51,3 +85,41 @@ pub async fn add(name: &str) {
         created(format!("\"{}\"", name.display()));
     }
 }
+
+fn schema_path(database: &str, path: Option<PathBuf>) -> PathBuf {
+    path.or_else(|| {
+        get_config()
+            .database
+            .named(database)
+            .and_then(|config| config.schema_dump)
+            .map(PathBuf::from)
+    })
+    .unwrap_or_else(|| {
+        log::error!("schema file not specified and the schema_dump setting is not set");
+        std::process::exit(1);
+    })
+}
+
+pub async fn dump(database: &str, path: Option<PathBuf>) {
+    let path = schema_path(database, path);
+
+    schema::dump_database(database, &path)
+        .await
+        .expect("failed to dump schema");
+}
+
+pub async fn verify(database: &str, path: Option<PathBuf>) {
+    let path = schema_path(database, path);
+
+    match schema::verify_database(database, &path).await {
+        Ok(()) => (),
+        // Differences are logged by verify.
+        Err(rwf::model::Error::SchemaDrift(_)) => std::process::exit(1),
+        Err(err) => {
+            log::error!("failed to verify schema: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    log::info!("database matches \"{}\"", path.display());
+}
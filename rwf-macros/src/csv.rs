@@ -0,0 +1,164 @@
+use super::*;
+
+use syn::{meta::ParseNestedMeta, LitStr};
+
+/// Serde attributes which change the name of a field.
+#[derive(Default)]
+struct Serde {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    skip: bool,
+    flatten: bool,
+}
+
+impl Serde {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut serde = Serde::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    serde.rename = serialize_name(&meta)?;
+                } else if meta.path.is_ident("rename_all") {
+                    serde.rename_all = serialize_name(&meta)?;
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                    serde.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    serde.flatten = true;
+                } else {
+                    skip_value(&meta)?;
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(serde)
+    }
+}
+
+/// `rename = "name"` or `rename(serialize = "name")`.
+fn serialize_name(meta: &ParseNestedMeta) -> syn::Result<Option<String>> {
+    if meta.input.peek(Token![=]) {
+        return Ok(Some(meta.value()?.parse::<LitStr>()?.value()));
+    }
+
+    let mut name = None;
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("serialize") {
+            name = Some(meta.value()?.parse::<LitStr>()?.value());
+        } else {
+            skip_value(&meta)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(name)
+}
+
+/// Attributes which don't change the names, e.g. `default = "path"` or `bound(...)`.
+fn skip_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.input.parse::<proc_macro2::Group>()?;
+    }
+
+    Ok(())
+}
+
+/// Rename a field, written in snake case, like serde's `rename_all` does.
+fn rename(field: &str, rule: &str) -> Option<String> {
+    let words = field.split('_').collect::<Vec<_>>();
+    let capitalize = |word: &&str| {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+            None => String::new(),
+        }
+    };
+
+    Some(match rule {
+        "lowercase" | "snake_case" => field.to_lowercase(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => field.to_uppercase(),
+        "PascalCase" => words.iter().map(capitalize).collect(),
+        "camelCase" => {
+            let pascal = words.iter().map(capitalize).collect::<String>();
+            let mut chars = pascal.chars();
+            match chars.next() {
+                Some(first) => first.to_lowercase().chain(chars).collect(),
+                None => pascal,
+            }
+        }
+        "kebab-case" => field.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => field.replace('_', "-").to_uppercase(),
+        _ => return None,
+    })
+}
+
+pub fn impl_derive_csv_header(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match csv_header(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn csv_header(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match input.data {
+        Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(ref fields),
+            ..
+        }) => fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "CsvHeader can only be derived for structs with named fields",
+            ))
+        }
+    };
+
+    let container = Serde::parse(&input.attrs)?;
+    let mut names = vec![];
+
+    for field in &fields.named {
+        let serde = Serde::parse(&field.attrs)?;
+
+        if serde.skip {
+            continue;
+        }
+
+        if serde.flatten {
+            return Err(syn::Error::new_spanned(
+                field,
+                "flattened fields are not supported by CsvHeader",
+            ));
+        }
+
+        let ident = field.ident.as_ref().unwrap().to_string();
+        let ident = ident.trim_start_matches("r#");
+
+        let name = match (serde.rename, &container.rename_all) {
+            (Some(name), _) => name,
+            (None, Some(rule)) => rename(ident, rule).ok_or_else(|| {
+                syn::Error::new_spanned(&input.ident, format!("unknown rename rule \"{}\"", rule))
+            })?,
+            (None, None) => ident.to_string(),
+        };
+
+        names.push(name);
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl #impl_generics rwf::http::csv::CsvHeader for #ident #ty_generics #where_clause {
+            fn csv_header() -> Vec<String> {
+                vec![#(#names.to_string()),*]
+            }
+        }
+    })
+}
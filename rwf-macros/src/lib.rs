@@ -3,12 +3,13 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 
 use syn::{
-    parse_macro_input, punctuated::Punctuated, Attribute, Data, DeriveInput, Expr, Meta, Token,
-    Type,
+    parse_macro_input, punctuated::Punctuated, Attribute, Data, DeriveInput, Expr, Field, Meta,
+    Token, Type,
 };
 
 use quote::quote;
 
+mod csv;
 mod model;
 mod prelude;
 mod render;
@@ -127,7 +128,27 @@ mod render;
 /// }
 /// ```
 ///
-#[proc_macro_derive(Model, attributes(belongs_to, has_many, table_name, foreign_key))]
+/// Models stored in a database other than `"main"` can set it with `#[database("analytics")]`.
+///
+/// ## Encrypted attributes
+///
+/// Fields marked with `#[model(encrypted)]` are encrypted before they are saved, and decrypted when
+/// they are loaded. Add `deterministic`, i.e. `#[model(encrypted, deterministic)]`, to allow
+/// looking records up by that field:
+///
+/// ```ignore
+/// #[derive(rwf_macros::Model)]
+/// struct User {
+///     id: Option<i64>,
+///     #[model(encrypted, deterministic)]
+///     ssn: String,
+/// }
+/// ```
+///
+#[proc_macro_derive(
+    Model,
+    attributes(belongs_to, has_many, table_name, foreign_key, database, model)
+)]
 pub fn derive_model(input: TokenStream) -> TokenStream {
     model::impl_derive_model(input)
 }
@@ -137,7 +158,10 @@ pub fn derive_model(input: TokenStream) -> TokenStream {
 /// This implements mappings between the `Controller`
 /// trait and the struct implementing
 /// the `WebsocketController` trait.
-#[proc_macro_derive(WebsocketController, attributes(auth, middleware, skip_csrf))]
+#[proc_macro_derive(
+    WebsocketController,
+    attributes(auth, middleware, skip_csrf, double_submit_csrf, layout)
+)]
 pub fn derive_websocket_controller(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let overrides = handle_overrides(&input.attrs);
@@ -169,7 +193,10 @@ pub fn derive_websocket_controller(input: TokenStream) -> TokenStream {
 /// This implements mappings between the `Controller`
 /// trait and the struct implementing
 /// the `ModelController` trait.
-#[proc_macro_derive(ModelController, attributes(auth, middleware, skip_csrf))]
+#[proc_macro_derive(
+    ModelController,
+    attributes(auth, middleware, skip_csrf, double_submit_csrf, layout)
+)]
 pub fn derive_model_controller(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let overrides = handle_overrides(&input.attrs);
@@ -250,6 +277,26 @@ fn handle_overrides(attributes: &[Attribute]) -> proc_macro2::TokenStream {
                     }
                 },
 
+                "double_submit_csrf" => quote! {
+                    fn csrf_mode(&self) -> rwf::controller::CsrfMode {
+                        rwf::controller::CsrfMode::DoubleSubmit
+                    }
+                },
+
+                "layout" => match &attr.meta {
+                    Meta::List(list) => {
+                        let tokens = &list.tokens;
+
+                        quote! {
+                            fn layout(&self) -> Option<&'static str> {
+                                Some(#tokens)
+                            }
+                        }
+                    }
+
+                    _ => quote! {},
+                },
+
                 _ => quote! {},
             }
         })
@@ -265,7 +312,10 @@ fn handle_overrides(attributes: &[Attribute]) -> proc_macro2::TokenStream {
 /// This implements mappings between the `Controller`
 /// trait and the struct implementing
 /// the `PageController` trait.
-#[proc_macro_derive(PageController, attributes(auth, middleware, skip_csrf))]
+#[proc_macro_derive(
+    PageController,
+    attributes(auth, middleware, skip_csrf, double_submit_csrf, layout)
+)]
 pub fn derive_page_controller(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let overrides = handle_overrides(&input.attrs);
@@ -293,7 +343,10 @@ pub fn derive_page_controller(input: TokenStream) -> TokenStream {
 /// This implements mappings between the `Controller`
 /// trait and the struct implementing
 /// the `RestController` trait.
-#[proc_macro_derive(RestController, attributes(auth, middleware, skip_csrf))]
+#[proc_macro_derive(
+    RestController,
+    attributes(auth, middleware, skip_csrf, double_submit_csrf, layout)
+)]
 pub fn derive_rest_controller(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let overrides = handle_overrides(&input.attrs);
@@ -316,6 +369,25 @@ pub fn derive_rest_controller(input: TokenStream) -> TokenStream {
     }.into()
 }
 
+/// Implement the `rwf::http::csv::CsvHeader` trait, so a CSV file of these rows
+/// has a header row even if there are no rows. The names of the columns follow
+/// the `rename`, `rename_all` and `skip` serde attributes, like the rows do.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Serialize, macros::CsvHeader)]
+/// struct Order {
+///     id: i64,
+///     #[serde(rename = "total (USD)")]
+///     total: f64,
+/// }
+/// ```
+#[proc_macro_derive(CsvHeader, attributes(serde))]
+pub fn derive_csv_header(input: TokenStream) -> TokenStream {
+    csv::impl_derive_csv_header(input)
+}
+
 /// Automatically implement the `FromRow` trait.
 /// Converts database rows to Rust struct fields.
 #[proc_macro_derive(FromRow)]
@@ -329,7 +401,7 @@ pub fn derive_from_row(input: TokenStream) -> TokenStream {
             let from_row_fields = data.fields.iter().map(|field| {
                 let ident = &field.ident;
                 quote! {
-                    #ident: row.try_get(stringify!(#ident))?,
+                    #ident: rwf::model::GetColumn::get_column(&row, stringify!(#ident))?,
                 }
             });
 
@@ -350,6 +422,95 @@ pub fn derive_from_row(input: TokenStream) -> TokenStream {
     }
 }
 
+/// Automatically implement the `ToValue` trait for a newtype,
+/// e.g. `struct UserId(i64)`. The newtype is converted to
+/// a database value the same way as the type it wraps.
+#[proc_macro_derive(ToValue)]
+pub fn derive_to_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let ident = input.ident.clone();
+    let (field, _) = newtype_field(&input);
+
+    quote! {
+        #[automatically_derived]
+        impl rwf::model::ToValue for #ident {
+            fn to_value(&self) -> rwf::model::Value {
+                rwf::model::ToValue::to_value(&self.#field)
+            }
+        }
+    }
+    .into()
+}
+
+/// Automatically implement the `FromValue` trait for a newtype,
+/// e.g. `struct UserId(i64)`. This also implements `FromSql`, so the newtype
+/// can be used as a field in models.
+#[proc_macro_derive(FromValue)]
+pub fn derive_from_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let ident = input.ident.clone();
+    let (field, ty) = newtype_field(&input);
+
+    let construct = |value: proc_macro2::TokenStream| match &field {
+        syn::Member::Named(name) => quote! { Self { #name: #value } },
+        syn::Member::Unnamed(_) => quote! { Self(#value) },
+    };
+
+    let from_value = construct(quote! { rwf::model::FromValue::from_value(value)? });
+    let from_sql = construct(quote! {
+        <#ty as rwf::tokio_postgres::types::FromSql<'a>>::from_sql(ty, raw)?
+    });
+    let from_sql_null = construct(quote! {
+        <#ty as rwf::tokio_postgres::types::FromSql<'a>>::from_sql_null(ty)?
+    });
+
+    quote! {
+        #[automatically_derived]
+        impl rwf::model::FromValue for #ident {
+            fn from_value(value: rwf::model::Value) -> Result<Self, rwf::model::Error> {
+                Ok(#from_value)
+            }
+        }
+
+        #[automatically_derived]
+        impl<'a> rwf::tokio_postgres::types::FromSql<'a> for #ident {
+            fn from_sql(
+                ty: &rwf::tokio_postgres::types::Type,
+                raw: &'a [u8],
+            ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+                Ok(#from_sql)
+            }
+
+            fn from_sql_null(
+                ty: &rwf::tokio_postgres::types::Type,
+            ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+                Ok(#from_sql_null)
+            }
+
+            fn accepts(ty: &rwf::tokio_postgres::types::Type) -> bool {
+                <#ty as rwf::tokio_postgres::types::FromSql<'a>>::accepts(ty)
+            }
+        }
+    }
+    .into()
+}
+
+fn newtype_field(input: &DeriveInput) -> (syn::Member, Type) {
+    match input.data {
+        Data::Struct(ref data) if data.fields.len() == 1 => {
+            let field = data.fields.iter().next().unwrap();
+            let member = match &field.ident {
+                Some(ident) => syn::Member::Named(ident.clone()),
+                None => syn::Member::Unnamed(0.into()),
+            };
+
+            (member, field.ty.clone())
+        }
+
+        _ => panic!("macro can only be used on structs with one field"),
+    }
+}
+
 /// Automatically implement the `ToTemplateValue` trait
 /// for the Rust struct. This allows to use the struct
 /// directly in template contexts.
@@ -461,6 +622,13 @@ pub fn drive_context(input: TokenStream) -> TokenStream {
                 }
             });
             let fields_ref = fields.clone();
+            let names = data.fields.iter().map(|field| {
+                let ident = &field.ident;
+
+                quote! {
+                    .field(stringify!(#ident))
+                }
+            });
 
             quote! {
                 #[automatically_derived]
@@ -487,6 +655,12 @@ pub fn drive_context(input: TokenStream) -> TokenStream {
                         Ok(result)
                     }
                 }
+
+                impl rwf::view::template::TemplateContext for #ident {
+                    fn context_shape() -> rwf::view::template::ContextShape {
+                        rwf::view::template::ContextShape::new()#(#names)*
+                    }
+                }
             }
             .into()
         }
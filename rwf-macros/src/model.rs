@@ -10,8 +10,15 @@ pub fn impl_derive_model(input: TokenStream) -> TokenStream {
             let ident = input.ident.clone();
             let from_row_fields = data.fields.iter().map(|field| {
                 let ident = field.ident.clone();
-                quote! {
-                    #ident: row.try_get(stringify!(#ident))?,
+
+                if encryption(field).is_some() {
+                    quote! {
+                        #ident: rwf::model::encryption::decrypt_column(&row, stringify!(#ident))?,
+                    }
+                } else {
+                    quote! {
+                        #ident: rwf::model::GetColumn::get_column(&row, stringify!(#ident))?,
+                    }
                 }
             });
             let has_id = data
@@ -50,11 +57,43 @@ pub fn impl_derive_model(input: TokenStream) -> TokenStream {
             let values = without_id.clone().map(|field| {
                 let ident = &field.ident;
 
-                quote! {
-                    self.#ident.to_value(),
+                if let Some(encryption) = encryption(field) {
+                    quote! {
+                        rwf::model::encryption::encrypt_value(self.#ident.to_value(), #encryption),
+                    }
+                } else {
+                    quote! {
+                        self.#ident.to_value(),
+                    }
                 }
             });
 
+            let encrypted_columns = data
+                .fields
+                .iter()
+                .filter_map(|field| {
+                    let ident = &field.ident;
+                    encryption(field).map(|encryption| {
+                        quote! {
+                            stringify!(#ident) => Some(#encryption),
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let encrypted = if encrypted_columns.is_empty() {
+                quote! {}
+            } else {
+                quote! {
+                    fn encrypted(column: &str) -> Option<rwf::model::Encryption> {
+                        match column {
+                            #(#encrypted_columns)*
+                            _ => None,
+                        }
+                    }
+                }
+            };
+
             let singular = snake_case(&ident.to_string());
             let foreign_key = format!("{}_id", singular);
 
@@ -80,6 +119,8 @@ pub fn impl_derive_model(input: TokenStream) -> TokenStream {
                 &input.attrs,
             );
 
+            let database = handle_override("database", quote! {}, &input.attrs);
+
             quote! {
                 #[automatically_derived]
                 impl rwf::model::FromRow for #ident {
@@ -94,6 +135,8 @@ pub fn impl_derive_model(input: TokenStream) -> TokenStream {
                 impl rwf::model::Model for #ident {
                     #table_name
                     #foreign_key
+                    #database
+                    #encrypted
 
                     fn column_names() -> &'static[&'static str] {
                         &[
@@ -120,6 +163,38 @@ pub fn impl_derive_model(input: TokenStream) -> TokenStream {
     }
 }
 
+/// Encryption of a field marked with `#[model(encrypted)]` or `#[model(encrypted, deterministic)]`.
+fn encryption(field: &Field) -> Option<proc_macro2::TokenStream> {
+    let mut encrypted = false;
+    let mut deterministic = false;
+
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("model"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("encrypted") {
+                encrypted = true;
+            } else if meta.path.is_ident("deterministic") {
+                deterministic = true;
+            } else {
+                return Err(meta.error("expected `encrypted` or `deterministic`"));
+            }
+
+            Ok(())
+        })
+        .expect("model attribute");
+    }
+
+    match (encrypted, deterministic) {
+        (true, true) => Some(quote! { rwf::model::Encryption::Deterministic }),
+        (true, false) => Some(quote! { rwf::model::Encryption::Random }),
+        (false, true) => panic!("`deterministic` requires `encrypted`"),
+        (false, false) => None,
+    }
+}
+
 fn handle_override(
     name: &str,
     default_value: proc_macro2::TokenStream,
@@ -159,6 +234,14 @@ fn handle_override(
                             }
                         }
 
+                        "database" => {
+                            quote! {
+                                fn database() -> &'static str {
+                                    #tokens
+                                }
+                            }
+                        }
+
                         _ => panic!("unexpected attribute: {}", name),
                     }
                 } else {
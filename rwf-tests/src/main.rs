@@ -155,7 +155,11 @@ struct JobOne;
 
 #[rwf::async_trait]
 impl Job for JobOne {
-    async fn execute(&self, _args: serde_json::Value) -> Result<(), rwf::job::Error> {
+    async fn execute(
+        &self,
+        _context: &JobContext,
+        _args: serde_json::Value,
+    ) -> Result<(), rwf::job::Error> {
         Ok(())
     }
 }
@@ -164,7 +168,11 @@ struct JobTwo;
 
 #[rwf::async_trait]
 impl Job for JobTwo {
-    async fn execute(&self, args: serde_json::Value) -> Result<(), rwf::job::Error> {
+    async fn execute(
+        &self,
+        _context: &JobContext,
+        args: serde_json::Value,
+    ) -> Result<(), rwf::job::Error> {
         println!("job two args: {:?}", args);
         Err(rwf::job::Error::Unknown("random error".to_string()))
     }
@@ -5,7 +5,7 @@
 //!
 use std::net::IpAddr;
 
-use crate::model::{Error, FromRow, Model, ToValue, Value};
+use crate::model::{Error, FromRow, GetColumn, Model, ToValue, Value};
 
 use time::OffsetDateTime;
 
@@ -25,14 +25,14 @@ pub struct Request {
 impl FromRow for Request {
     fn from_row(row: tokio_postgres::Row) -> Result<Self, Error> {
         Ok(Self {
-            id: row.try_get("id")?,
-            path: row.try_get("path")?,
-            method: row.try_get("method")?,
-            query: row.try_get("query")?,
-            code: row.try_get("code")?,
-            client_ip: row.try_get("client")?,
-            created_at: row.try_get("created_at")?,
-            duration: row.try_get("duration")?,
+            id: row.get_column("id")?,
+            path: row.get_column("path")?,
+            method: row.get_column("method")?,
+            query: row.get_column("query")?,
+            code: row.get_column("code")?,
+            client_ip: row.get_column("client")?,
+            created_at: row.get_column("created_at")?,
+            duration: row.get_column("duration")?,
         })
     }
 }
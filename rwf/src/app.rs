@@ -0,0 +1,547 @@
+//! Application builder, tying together the HTTP server, the database, and background jobs.
+//!
+//! Instead of starting each part of the application by hand, describe the application with [`App::new`]
+//! and start it with one of [`App::serve`], [`App::work`] or [`App::serve_and_work`]:
+//!
+//! ```rust,ignore
+//! let app = App::new()
+//!     .routes(vec![route!("/" => Index)])
+//!     .middleware(vec![RateLimiter::per_second(10).middleware()])
+//!     .jobs(vec![SendEmail::default().job()])
+//!     .workers(4)
+//!     .schedule(vec![CleanUp::default().schedule(json!({}), "0 0 * * *")?])
+//!     .templates("templates")
+//!     .build()
+//!     .await?;
+//!
+//! app.serve_and_work("0.0.0.0:8000").await?;
+//! ```
+//!
+//! When built, the application checks that the database is reachable and that all migrations are applied,
+//! including those of the databases configured in `[database.<name>]` sections, and loads the templates, so configuration problems are found before any requests are served.
+//!
+//! If `startup_grace_period` is set in the `[database]` section, the application starts even if the database isn't
+//! available yet, e.g. when both are started at the same time. It keeps trying to connect, with exponential backoff, for the
+//! grace period. In the meantime, the server only runs [database-free](crate::controller::Controller::database_free) controllers,
+//! and [readiness](crate::health::ready) checks fail.
+//!
+//! The application shuts down when the process receives Ctrl-C or [`Shutdown::shutdown`] is called. The server stops accepting
+//! connections and finishes the requests in progress, and the workers finish their current job and stop taking new ones.
+//!
+//! The parts used by the builder, like [`Server`] and [`Worker`], can still be used on their own for more control.
+use thiserror::Error as ThisError;
+use tokio::net::ToSocketAddrs;
+use tokio::select;
+use tokio::signal::ctrl_c;
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::get_config;
+use crate::controller::{MiddlewareHandler, MiddlewareSet};
+use crate::health;
+use crate::http::{Handler, Server};
+use crate::job::{clock::ScheduledJob, Clock, JobHandler, JobModel, Worker};
+use crate::model::migrations::{MigrationStatus, RustMigrations};
+use crate::model::pool::{set_pool, DEFAULT_DATABASE};
+use crate::model::{get_connection, get_pool, Migrations, Pool};
+use crate::view::template::lint::registered_shape;
+use crate::view::Template;
+
+/// Error starting the application.
+#[derive(ThisError, Debug)]
+pub enum Error {
+    #[error("database is unavailable: {0}")]
+    Database(crate::model::Error),
+
+    #[error("database pool is already in use, call database() before running any queries")]
+    PoolInUse,
+
+    #[error("migrations are not applied: {}", .0.join(", "))]
+    PendingMigrations(Vec<String>),
+
+    #[error("migrations failed: {0}")]
+    Migrations(crate::model::Error),
+
+    #[error("template \"{path}\" failed to load: {error}")]
+    Template {
+        path: PathBuf,
+        error: crate::view::Error,
+    },
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("jobs worker failed to start: {0}")]
+    Worker(#[from] crate::job::Error),
+
+    #[error("http error: {0}")]
+    Http(#[from] crate::http::Error),
+}
+
+/// Application builder, created with [`App::new`].
+pub struct AppBuilder {
+    handlers: Vec<Handler>,
+    middleware: Vec<MiddlewareHandler>,
+    database_url: Option<String>,
+    startup_grace_period: Option<Duration>,
+    jobs: Vec<JobHandler>,
+    workers: usize,
+    schedule: Vec<ScheduledJob>,
+    templates: Vec<PathBuf>,
+    migrate: bool,
+    migrations: RustMigrations,
+    #[cfg(unix)]
+    handover: bool,
+}
+
+impl AppBuilder {
+    /// Add routes served by the HTTP server.
+    pub fn routes(mut self, handlers: Vec<Handler>) -> Self {
+        self.handlers.extend(handlers);
+        self
+    }
+
+    /// Add middleware running on all routes, before the middleware of each controller.
+    pub fn middleware(mut self, middleware: Vec<MiddlewareHandler>) -> Self {
+        self.middleware.extend(middleware);
+        self
+    }
+
+    /// Connect to the database at this URL, instead of the one in the configuration.
+    pub fn database(mut self, database_url: impl ToString) -> Self {
+        self.database_url = Some(database_url.to_string());
+        self
+    }
+
+    /// Keep trying to connect to the database for this long when the application starts, instead of
+    /// the `startup_grace_period` in the configuration.
+    pub fn startup_grace_period(mut self, grace_period: Duration) -> Self {
+        self.startup_grace_period = Some(grace_period);
+        self
+    }
+
+    /// Add background jobs run by the workers.
+    pub fn jobs(mut self, jobs: Vec<JobHandler>) -> Self {
+        self.jobs.extend(jobs);
+        self
+    }
+
+    /// Number of workers running background jobs concurrently. Default is 1.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Add jobs scheduled to run periodically.
+    pub fn schedule(mut self, jobs: Vec<ScheduledJob>) -> Self {
+        self.schedule.extend(jobs);
+        self
+    }
+
+    /// Load all templates in this directory when the application starts.
+    pub fn templates(mut self, path: impl AsRef<Path>) -> Self {
+        self.templates.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Apply pending migrations when the application starts. By default, the application
+    /// refuses to start if any migrations are pending.
+    pub fn migrate(mut self) -> Self {
+        self.migrate = true;
+        self
+    }
+
+    /// Hand the listening socket over to a new process of the application when the process receives `SIGUSR2`,
+    /// e.g. after upgrading the binary. The application shuts down once the new process accepts connections.
+    /// See [`handover`](crate::http::handover).
+    #[cfg(unix)]
+    pub fn handover(mut self) -> Self {
+        self.handover = true;
+        self
+    }
+
+    /// Add migrations written in Rust, checked or applied together with the SQL migrations.
+    pub fn migrations(mut self, migrations: RustMigrations) -> Self {
+        self.migrations.extend(migrations);
+        self
+    }
+
+    /// Check the database, migrations and templates, and build the application.
+    pub async fn build(self) -> Result<App, Error> {
+        if let Some(ref database_url) = self.database_url {
+            set_pool(Pool::from_url(database_url)).map_err(|_| Error::PoolInUse)?;
+        }
+
+        // Fail early if the database is unreachable, unless it's given time to start.
+        let grace_period = self
+            .startup_grace_period
+            .unwrap_or_else(|| get_config().database.startup_grace_period().unsigned_abs());
+        let startup = match get_pool().get().await {
+            Ok(_) => {
+                check_migrations(self.migrate, &self.migrations).await?;
+                None
+            }
+            Err(err) if grace_period.is_zero() => return Err(Error::Database(err)),
+            Err(err) => {
+                warn!(
+                    "database is unavailable, retrying for {:.1}s: {}",
+                    grace_period.as_secs_f64(),
+                    err
+                );
+                health::set_starting(true);
+                Some(Startup {
+                    migrate: self.migrate,
+                    migrations: self.migrations.clone(),
+                    deadline: Instant::now() + grace_period,
+                })
+            }
+        };
+
+        for path in &self.templates {
+            preload(path)?;
+        }
+
+        let middleware = MiddlewareSet::without_default(self.middleware);
+        let handlers = self
+            .handlers
+            .into_iter()
+            .map(|handler| handler.with_middleware(middleware.clone()))
+            .collect();
+
+        let server = Server::new(handlers);
+        #[cfg(unix)]
+        let server = if self.handover {
+            server.handover()
+        } else {
+            server
+        };
+
+        Ok(App {
+            server,
+            worker: Worker::new(self.jobs),
+            workers: self.workers,
+            clock: if self.schedule.is_empty() {
+                None
+            } else {
+                Some(Clock::new(self.schedule))
+            },
+            shutdown: Shutdown::new(),
+            startup,
+        })
+    }
+}
+
+/// Database checks postponed until the database is available.
+struct Startup {
+    migrate: bool,
+    migrations: RustMigrations,
+    deadline: Instant,
+}
+
+impl Startup {
+    /// Connect to the database with exponential backoff until the deadline,
+    /// and check the migrations.
+    async fn run(self) -> Result<(), Error> {
+        let mut backoff = Duration::from_millis(100);
+
+        loop {
+            match get_pool().get().await {
+                Ok(_) => break,
+                Err(err) => {
+                    let now = Instant::now();
+                    if now >= self.deadline {
+                        return Err(Error::Database(err));
+                    }
+
+                    debug!("database is still unavailable: {}", err);
+                    sleep(backoff.min(self.deadline - now)).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+
+        check_migrations(self.migrate, &self.migrations).await?;
+        health::set_starting(false);
+        info!("database is available");
+
+        Ok(())
+    }
+}
+
+/// Longest wait between attempts to connect to the database when the application starts.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Check that the migrations of all databases are applied, or apply them.
+async fn check_migrations(migrate: bool, rust: &RustMigrations) -> Result<(), Error> {
+    // Each database has its own migrations.
+    for database in get_config().database.names() {
+        let migrations = Migrations::sync_database_with(&database, rust)
+            .await
+            .map_err(Error::Migrations)?;
+
+        if migrate {
+            migrations
+                .apply(crate::model::migrations::Direction::Up, None)
+                .await
+                .map_err(Error::Migrations)?;
+        } else {
+            migrations.verify().map_err(Error::Migrations)?;
+
+            let pending = migrations
+                .migrations()
+                .iter()
+                .filter(|migration| migrations.status(migration) == MigrationStatus::Pending)
+                .map(|migration| match database.as_str() {
+                    DEFAULT_DATABASE => migration.name(),
+                    database => format!("{}/{}", database, migration.name()),
+                })
+                .collect::<Vec<_>>();
+
+            if !pending.is_empty() {
+                return Err(Error::PendingMigrations(pending));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Load all templates in a directory and its subdirectories.
+fn preload(path: &Path) -> Result<(), Error> {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            preload(&entry?.path())?;
+        }
+    } else {
+        let template = Template::load(path).map_err(|error| Error::Template {
+            path: path.to_owned(),
+            error,
+        })?;
+
+        if let Some(shape) = registered_shape(path) {
+            for warning in template.lint(&shape) {
+                warn!("{}", warning);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Signals all parts of the application to shut down.
+///
+/// It's cheap to clone, and all clones signal the same application.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    fn new() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Shut down the application.
+    pub fn shutdown(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Wait until the application is shut down.
+    pub async fn wait(&self) {
+        let _ = self.sender.subscribe().wait_for(|shutdown| *shutdown).await;
+    }
+}
+
+/// Application, ready to be started. Created with [`AppBuilder::build`].
+pub struct App {
+    server: Server,
+    worker: Worker,
+    workers: usize,
+    clock: Option<Clock>,
+    shutdown: Shutdown,
+    startup: Option<Startup>,
+}
+
+impl App {
+    /// Describe the application. See the [module documentation](self) for an example.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> AppBuilder {
+        AppBuilder {
+            handlers: vec![],
+            middleware: vec![],
+            database_url: None,
+            startup_grace_period: None,
+            jobs: vec![],
+            workers: 1,
+            schedule: vec![],
+            templates: vec![],
+            migrate: false,
+            migrations: RustMigrations::default(),
+            #[cfg(unix)]
+            handover: false,
+        }
+    }
+
+    /// Handle to shut down the application.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Run the HTTP server only.
+    ///
+    /// If the database wasn't available when the application was built, the server starts anyway
+    /// and the application keeps trying to connect for the `startup_grace_period`. It shuts down if the database
+    /// doesn't become available in time.
+    pub async fn serve(self, addr: impl ToSocketAddrs) -> Result<(), Error> {
+        self.on_ctrl_c();
+        let shutdown = self.shutdown.clone();
+
+        let (server, database) = tokio::join!(
+            async {
+                let server = self
+                    .server
+                    .launch_with_shutdown(addr, async move { shutdown.wait().await })
+                    .await;
+                self.shutdown.shutdown();
+                server
+            },
+            Self::start(self.startup, self.shutdown.clone())
+        );
+
+        database?;
+        server?;
+
+        Ok(())
+    }
+
+    /// Run the background job workers and the scheduler only. If the database wasn't available
+    /// when the application was built, the workers wait for it to start.
+    pub async fn work(self) -> Result<(), Error> {
+        self.on_ctrl_c();
+
+        if Self::start(self.startup, self.shutdown.clone()).await? {
+            Self::work_until(self.worker, self.workers, self.clock, self.shutdown).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run the HTTP server, the background job workers and the scheduler in the same process.
+    pub async fn serve_and_work(self, addr: impl ToSocketAddrs) -> Result<(), Error> {
+        self.on_ctrl_c();
+        let shutdown = self.shutdown.clone();
+        let startup = self.startup;
+
+        let (server, work) = tokio::join!(
+            async {
+                let server = self
+                    .server
+                    .launch_with_shutdown(addr, async move { shutdown.wait().await })
+                    .await;
+                // The server stops on its own after a handover.
+                self.shutdown.shutdown();
+                server
+            },
+            async {
+                if Self::start(startup, self.shutdown.clone()).await? {
+                    Self::work_until(self.worker, self.workers, self.clock, self.shutdown.clone())
+                        .await
+                } else {
+                    Ok(())
+                }
+            }
+        );
+
+        // Stop the workers if the server fails to start, and the other way around.
+        self.shutdown.shutdown();
+
+        server?;
+        work
+    }
+
+    /// Wait for the database if it wasn't available when the application was built. Shuts down the application
+    /// if it doesn't start in time. Returns `false` if the application was shut down while waiting.
+    async fn start(startup: Option<Startup>, shutdown: Shutdown) -> Result<bool, Error> {
+        let startup = match startup {
+            Some(startup) => startup,
+            None => return Ok(true),
+        };
+
+        select! {
+            result = startup.run() => match result {
+                Ok(()) => Ok(true),
+                Err(err) => {
+                    shutdown.shutdown();
+                    Err(err)
+                }
+            },
+            _ = shutdown.wait() => Ok(false),
+        }
+    }
+
+    async fn work_until(
+        worker: Worker,
+        workers: usize,
+        clock: Option<Clock>,
+        shutdown: Shutdown,
+    ) -> Result<(), Error> {
+        // Jobs that were running when the application stopped are run again.
+        let mut conn = get_connection().await.map_err(Error::Database)?;
+        JobModel::reschedule()
+            .execute(&mut conn)
+            .await
+            .map_err(Error::Database)?;
+        drop(conn);
+
+        let mut tasks = (0..workers)
+            .map(|_| {
+                let worker = worker.clone();
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move { worker.work_until(shutdown.wait()).await })
+            })
+            .collect::<Vec<_>>();
+
+        let heartbeat = worker.clone();
+        let heartbeat_shutdown = shutdown.clone();
+        tasks.push(tokio::spawn(async move {
+            select! {
+                _ = heartbeat.heartbeat() => (),
+                _ = heartbeat_shutdown.wait() => (),
+            }
+        }));
+
+        if let Some(clock) = clock {
+            let shutdown = shutdown.clone();
+            tasks.push(tokio::spawn(async move {
+                select! {
+                    _ = clock.run() => (),
+                    _ = shutdown.wait() => info!("Clock stopped"),
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.map_err(crate::job::Error::from)?;
+        }
+
+        worker.run_hooks().await;
+
+        Ok(())
+    }
+
+    fn on_ctrl_c(&self) {
+        let shutdown = self.shutdown.clone();
+        tokio::spawn(async move {
+            if ctrl_c().await.is_ok() {
+                shutdown.shutdown();
+            }
+        });
+    }
+}
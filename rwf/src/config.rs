@@ -4,21 +4,31 @@
 use aes::Aes128;
 use aes_gcm_siv::{AesGcmSiv, Key};
 use once_cell::sync::OnceCell;
+use std::collections::HashMap;
 use std::env::var;
 use std::io::IsTerminal;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
-use time::Duration;
-use tracing::info;
+use time::{Duration, UtcOffset};
+use tracing::{info, warn};
 
 use crate::controller::middleware::csrf::Csrf;
-use crate::controller::middleware::{request_tracker::RequestTracker, Middleware};
+use crate::controller::middleware::{
+    rate_limiter::Backend, request_tracker::RequestTracker, Middleware,
+};
 use crate::controller::{AuthHandler, MiddlewareSet};
+use crate::flags::Flag;
+use crate::http::{RejectionKind, SameSite};
+use crate::model::pool::DEFAULT_DATABASE;
 use serde::{Deserialize, Serialize};
 use std::fs::read_to_string;
 use thiserror::Error;
 
 static CONFIG: OnceCell<Config> = OnceCell::new();
 
+/// Configuration files loaded by default, in order of preference.
+pub(crate) static CONFIG_FILES: &[&str] = &["rwf.toml", "Rwf.toml", "Rum.toml"];
+
 /// Configuration error.
 #[derive(Error, Debug)]
 pub enum Error {
@@ -39,6 +49,9 @@ pub enum Error {
 
     #[error("config not found")]
     NoConfig,
+
+    #[error("time zone \"{0}\" is not valid, use UTC or an offset like +02:00")]
+    Timezone(String),
 }
 
 /// Get application configuration.
@@ -68,6 +81,14 @@ pub struct Config {
     /// WebSocket connections settings.
     #[serde(default = "WebsocketConfig::default")]
     pub websocket: WebsocketConfig,
+
+    /// Rate limiter settings.
+    #[serde(default = "RateLimitConfig::default")]
+    pub rate_limit: RateLimitConfig,
+
+    /// Feature flags.
+    #[serde(default)]
+    pub flags: HashMap<String, Flag>,
 }
 
 impl Default for Config {
@@ -77,6 +98,8 @@ impl Default for Config {
             general: General::default(),
             database: DatabaseConfig::default(),
             websocket: WebsocketConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            flags: HashMap::new(),
         }
         .transform()
         .unwrap()
@@ -94,7 +117,7 @@ impl Config {
 
     /// Load configuration file from default location(s).
     pub fn load_default() -> Self {
-        for path in ["rwf.toml", "Rwf.toml", "Rum.toml"] {
+        for path in CONFIG_FILES {
             let path = Path::new(path);
             if path.is_file() {
                 return Self::load(path).unwrap_or_default();
@@ -129,12 +152,23 @@ impl Config {
 
         self.general.default_middleware = MiddlewareSet::without_default(default_middleware);
 
+        if crate::timezone::parse(&self.general.default_timezone).is_none() {
+            return Err(Error::Timezone(self.general.default_timezone.clone()));
+        }
+
         let secret_key = self.general.secret_key()?;
 
         self.general.aes_key = Key::<AesGcmSiv<Aes128>>::clone_from_slice(&secret_key[0..128 / 8]);
         self.general.secure_id_key =
             Key::<AesGcmSiv<Aes128>>::clone_from_slice(&secret_key[128 / 8..]);
 
+        self.general.previous_aes_keys = self
+            .general
+            .previous_secret_keys()?
+            .iter()
+            .map(|key| Key::<AesGcmSiv<Aes128>>::clone_from_slice(&key[0..128 / 8]))
+            .collect();
+
         Ok(self)
     }
 
@@ -160,36 +194,167 @@ pub struct General {
     pub port: u16,
     #[serde(default = "General::default_secret_key")]
     secret_key: String,
+    /// Secret keys used before the current one. Data encrypted with them can still be decrypted,
+    /// so the secret key can be rotated without losing access to it.
+    #[serde(default = "General::default_previous_secret_keys")]
+    previous_secret_keys: Vec<String>,
     /// AES-128 encryption key. Derived from the secret key. Used for encrypting cookies, sessions, and arbitrary user data.
     #[serde(skip)]
     pub aes_key: Key<AesGcmSiv<Aes128>>,
+    /// AES-128 encryption keys derived from the previous secret keys. Used for decryption only.
+    #[serde(skip)]
+    pub previous_aes_keys: Vec<Key<AesGcmSiv<Aes128>>>,
     #[serde(skip)]
     pub secure_id_key: Key<AesGcmSiv<Aes128>>,
     /// Enable logging all queries executed by the ORM.
     #[serde(default = "General::default_log_queries")]
     pub log_queries: bool,
+    /// Log a table of all routes, with their middleware and matching order, at startup.
+    #[serde(default = "General::default_log_routes")]
+    pub log_routes: bool,
     /// Enable caching templates at runtime.
     #[serde(default = "General::default_cache_templates")]
     pub cache_templates: bool,
+    /// Load feature flag overrides from the `rwf_feature_flags` table.
+    #[serde(default)]
+    pub flag_overrides: bool,
+    /// Template used to render error pages, e.g. `404 - Not Found`, instead of the built-in one.
+    /// See [`crate::http::Response::set_error_template`].
+    #[serde(default)]
+    pub error_template_path: Option<PathBuf>,
     /// Record HTTP requests made to the server in the database.
     #[serde(default = "General::default_track_requests")]
     pub track_requests: bool,
     /// Enable CSRF attack protection.
     #[serde(default = "General::default_csrf_protection")]
     pub csrf_protection: bool,
+    /// Add the `Server-Timing` header to responses.
+    #[serde(default = "General::default_server_timing")]
+    pub server_timing: bool,
+    /// Compress responses for clients that accept gzip.
+    #[serde(default = "General::default_compression")]
+    pub compression: bool,
+    /// Send errors as `application/problem+json` to clients that accept JSON.
+    #[serde(default = "General::default_problem_json")]
+    pub problem_json: bool,
+    /// Route POST requests with a `_method` form field or an `X-HTTP-Method-Override`
+    /// header as PUT, PATCH or DELETE.
+    #[serde(default = "General::default_method_override")]
+    pub method_override: bool,
+    /// Inject the debug toolbar into HTML responses. Requires the `debug-toolbar` feature.
+    #[serde(default = "General::default_debug_toolbar")]
+    pub debug_toolbar: bool,
+    /// How many [captures](crate::http::capture) are kept in memory.
+    #[serde(default = "General::default_capture_buffer_size")]
+    pub capture_buffer_size: usize,
+    /// Longest request or response body, in bytes, kept in a capture.
+    #[serde(default = "General::default_capture_body_size")]
+    pub capture_body_size: usize,
+    /// Also save captures in the `rwf_captures` table.
+    #[serde(default)]
+    pub capture_table: bool,
     #[serde(default = "General::default_cookie_max_age")]
     cookie_max_age: usize,
     #[serde(default = "General::default_session_duration")]
     session_duration: usize,
+    #[serde(default)]
+    session_max_duration: Option<usize>,
+    /// Send the session cookie with the `HttpOnly` attribute, so JavaScript can't read it.
+    #[serde(default = "General::default_session_cookie_http_only")]
+    pub session_cookie_http_only: bool,
+    /// `SameSite` attribute of the session cookie.
+    #[serde(default)]
+    pub session_cookie_same_site: SameSite,
+    /// Send the session cookie with the `Secure` attribute, so it's only sent over HTTPS.
+    #[serde(default)]
+    pub session_cookie_secure: bool,
     /// The terminal where Rwf is running is TTY.
     #[serde(default = "General::default_tty")]
     pub tty: bool,
+    /// External URL of the application, e.g. `https://example.com`. Used
+    /// to build absolute URLs, see [`crate::http::Request::base_url`].
+    #[serde(default = "General::default_public_url")]
+    pub public_url: Option<String>,
+    /// Time zone used to format timestamps and to run scheduled jobs, e.g. `UTC` or `+02:00`.
+    /// See [`crate::timezone`].
+    #[serde(default = "General::default_timezone")]
+    pub default_timezone: String,
+    /// Trust the `X-Forwarded-*` and `Forwarded` headers set by a proxy.
+    #[serde(default = "General::default_trust_proxy")]
+    pub trust_proxy: bool,
+    /// Addresses of the proxies in front of the application, e.g. `10.0.0.0/8`. If empty, only the peer
+    /// is trusted. See [`crate::http::Request::client_ip`].
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Hosts the application can be reached at. Hosts starting with a dot, e.g. `.example.com`,
+    /// match the domain and all its subdomains.
+    #[serde(default = "General::default_allowed_hosts")]
+    pub allowed_hosts: Vec<String>,
+    /// Maximum memory, in bytes, used by request and response bodies at any one time. `0` means no limit.
+    /// See [`crate::http::memory`].
+    #[serde(default = "General::default_memory_budget")]
+    pub memory_budget: usize,
     /// Maximum size allowed for an HTTP header.
     #[serde(default = "General::default_header_max_size")]
     pub header_max_size: usize,
     /// Maximum size allowed for an HTTP request.
     #[serde(default = "General::default_max_request_size")]
     pub max_request_size: usize,
+    /// Maximum size, in bytes, of a body deserialized with [`crate::http::Request::json`].
+    #[serde(default = "General::default_max_json_size")]
+    pub max_json_size: usize,
+    /// How deep arrays and objects can be nested in JSON bodies, up to 128.
+    #[serde(default = "General::default_max_json_depth")]
+    pub max_json_depth: usize,
+    /// Longest string, in bytes, allowed in JSON bodies.
+    #[serde(default = "General::default_max_json_string_size")]
+    pub max_json_string_size: usize,
+    /// Reject JSON bodies with duplicate keys in an object, instead of using the last value.
+    #[serde(default)]
+    pub reject_duplicate_json_keys: bool,
+    /// Files uploaded with `multipart/form-data` larger than this, in bytes,
+    /// are written to a temporary file instead of being kept in memory.
+    #[serde(default = "General::default_multipart_disk_threshold")]
+    pub multipart_disk_threshold: usize,
+    /// Maximum size, in bytes, of a response body. Larger responses are replaced with
+    /// `500 - Internal Server Error`, or cut off if they are streamed. `0` means no limit.
+    /// Routes can override it with [`crate::http::Handler::max_response_size`].
+    #[serde(default = "General::default_max_response_size")]
+    pub max_response_size: usize,
+    /// Level at which requests that couldn't be parsed are logged, e.g. `warn`.
+    /// Set to `off` to disable logging them.
+    #[serde(default = "General::default_rejected_log_level")]
+    pub rejected_log_level: String,
+    /// Categories of unparseable requests for which the connection is closed
+    /// without sending `400 - Bad Request`.
+    #[serde(default = "General::default_rejected_close_silently")]
+    pub rejected_close_silently: Vec<RejectionKind>,
+    /// How long a running job can go without calling [`crate::job::JobContext::checkpoint`]
+    /// before it's considered abandoned and picked up by another worker.
+    /// Configured in milliseconds.
+    #[serde(default = "General::default_job_visibility_timeout")]
+    job_visibility_timeout: usize,
+    /// How often job workers record that they are alive. Configured in milliseconds.
+    #[serde(default = "General::default_job_heartbeat_interval")]
+    job_heartbeat_interval: usize,
+    /// How long a job worker can go without a heartbeat before it's considered stale.
+    /// Configured in milliseconds.
+    #[serde(default = "General::default_job_worker_timeout")]
+    job_worker_timeout: usize,
+    /// Put the jobs of stale workers back into the queue right away, instead of waiting
+    /// for their visibility timeout.
+    #[serde(default)]
+    pub job_reclaim_stale_workers: bool,
+    /// How long the server waits for requests in progress to finish when shutting down.
+    /// Configured in milliseconds.
+    #[serde(default = "General::default_shutdown_drain_timeout")]
+    shutdown_drain_timeout: usize,
+    /// How long each shutdown hook can run. Configured in milliseconds.
+    #[serde(default = "General::default_shutdown_hook_timeout")]
+    shutdown_hook_timeout: usize,
+    /// How long the new process has to start accepting connections during a socket handover. Configured in milliseconds.
+    #[serde(default = "General::default_handover_timeout")]
+    handover_timeout: usize,
     /// Global authentication handler. Used by default
     /// in all controllers.
     #[serde(skip)]
@@ -199,23 +364,85 @@ pub struct General {
     pub default_middleware: MiddlewareSet,
 }
 
+fn in_network(ip: IpAddr, network: &str) -> bool {
+    let (address, prefix) = match network.trim().split_once('/') {
+        Some((address, prefix)) => match prefix.parse::<u32>() {
+            Ok(prefix) => (address, Some(prefix)),
+            Err(_) => return false,
+        },
+        None => (network.trim(), None),
+    };
+
+    match (ip.to_canonical(), address.parse::<IpAddr>()) {
+        (IpAddr::V4(ip), Ok(IpAddr::V4(network))) => {
+            let prefix = prefix.unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), Ok(IpAddr::V6(network))) => {
+            let prefix = prefix.unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
 impl Default for General {
     fn default() -> Self {
         Self {
             host: General::default_host(),
             port: General::default_port(),
             secret_key: General::default_secret_key(),
+            previous_secret_keys: General::default_previous_secret_keys(),
             aes_key: Key::<AesGcmSiv<Aes128>>::default(),
+            previous_aes_keys: vec![],
             secure_id_key: Key::<AesGcmSiv<Aes128>>::default(),
             log_queries: General::default_log_queries(),
+            log_routes: General::default_log_routes(),
             cache_templates: General::default_cache_templates(),
+            flag_overrides: false,
+            error_template_path: None,
             track_requests: General::default_track_requests(),
             csrf_protection: General::default_csrf_protection(),
+            server_timing: General::default_server_timing(),
+            compression: General::default_compression(),
+            problem_json: General::default_problem_json(),
+            method_override: General::default_method_override(),
+            debug_toolbar: General::default_debug_toolbar(),
+            capture_buffer_size: General::default_capture_buffer_size(),
+            capture_body_size: General::default_capture_body_size(),
+            capture_table: false,
             cookie_max_age: General::default_cookie_max_age(),
             session_duration: General::default_session_duration(),
+            session_max_duration: None,
+            session_cookie_http_only: General::default_session_cookie_http_only(),
+            session_cookie_same_site: SameSite::default(),
+            session_cookie_secure: false,
             tty: General::default_tty(),
+            public_url: General::default_public_url(),
+            default_timezone: General::default_timezone(),
+            trust_proxy: General::default_trust_proxy(),
+            trusted_proxies: vec![],
+            allowed_hosts: General::default_allowed_hosts(),
+            memory_budget: General::default_memory_budget(),
             header_max_size: General::default_header_max_size(),
             max_request_size: General::default_max_request_size(),
+            max_json_size: General::default_max_json_size(),
+            max_json_depth: General::default_max_json_depth(),
+            max_json_string_size: General::default_max_json_string_size(),
+            reject_duplicate_json_keys: false,
+            multipart_disk_threshold: General::default_multipart_disk_threshold(),
+            max_response_size: General::default_max_response_size(),
+            rejected_log_level: General::default_rejected_log_level(),
+            rejected_close_silently: General::default_rejected_close_silently(),
+            job_visibility_timeout: General::default_job_visibility_timeout(),
+            job_heartbeat_interval: General::default_job_heartbeat_interval(),
+            job_worker_timeout: General::default_job_worker_timeout(),
+            job_reclaim_stale_workers: false,
+            shutdown_drain_timeout: General::default_shutdown_drain_timeout(),
+            shutdown_hook_timeout: General::default_shutdown_hook_timeout(),
+            handover_timeout: General::default_handover_timeout(),
             default_auth: AuthHandler::default(),
             default_middleware: MiddlewareSet::without_default(vec![]),
         }
@@ -243,8 +470,21 @@ impl General {
     /// It should be provided as a base64 string
     /// encoding 256 bits of entropy.
     pub fn secret_key(&self) -> Result<Vec<u8>, Error> {
+        Self::decode_secret_key(&self.secret_key)
+    }
+
+    /// Extract the previous secret keys from configuration,
+    /// most recent first.
+    pub fn previous_secret_keys(&self) -> Result<Vec<Vec<u8>>, Error> {
+        self.previous_secret_keys
+            .iter()
+            .map(|key| Self::decode_secret_key(key))
+            .collect()
+    }
+
+    fn decode_secret_key(secret_key: &str) -> Result<Vec<u8>, Error> {
         use base64::{engine::general_purpose, Engine as _};
-        let bytes = general_purpose::STANDARD.decode(&self.secret_key)?;
+        let bytes = general_purpose::STANDARD.decode(secret_key)?;
 
         if bytes.len() == 256 / 8 {
             Ok(bytes)
@@ -253,6 +493,21 @@ impl General {
         }
     }
 
+    fn default_previous_secret_keys() -> Vec<String> {
+        match var("RWF_PREVIOUS_SECRET_KEYS") {
+            Ok(keys) => keys
+                .split(",")
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    fn default_log_routes() -> bool {
+        true_from_env("RWF_LOG_ROUTES")
+    }
+
     fn default_log_queries() -> bool {
         if true_from_env("RWF_LOG_QUERIES") {
             return true;
@@ -299,6 +554,41 @@ impl General {
         true
     }
 
+    fn default_problem_json() -> bool {
+        true_from_env("RWF_PROBLEM_JSON")
+    }
+
+    fn default_method_override() -> bool {
+        true_from_env("RWF_METHOD_OVERRIDE")
+    }
+
+    fn default_capture_buffer_size() -> usize {
+        100
+    }
+
+    fn default_capture_body_size() -> usize {
+        64 * 1024 // 64K
+    }
+
+    fn default_debug_toolbar() -> bool {
+        true_from_env("RWF_DEBUG_TOOLBAR")
+    }
+
+    fn default_compression() -> bool {
+        true
+    }
+
+    fn default_server_timing() -> bool {
+        if true_from_env("RWF_SERVER_TIMING") {
+            return true;
+        }
+
+        #[cfg(debug_assertions)]
+        return true;
+        #[cfg(not(debug_assertions))]
+        return false;
+    }
+
     fn default_cookie_max_age() -> usize {
         Duration::days(30).whole_milliseconds() as usize
     }
@@ -309,8 +599,8 @@ impl General {
         Duration::milliseconds(self.cookie_max_age as i64)
     }
 
-    /// Authenticated session duration. When the session
-    /// expires, user must re-authenticate.
+    /// Authenticated session duration, renewed on every request. When the session
+    /// expires because the user was inactive, they must re-authenticate.
     pub fn session_duration(&self) -> Duration {
         Duration::milliseconds(self.session_duration as i64)
     }
@@ -319,10 +609,75 @@ impl General {
         Duration::weeks(4).whole_milliseconds() as usize
     }
 
+    /// Maximum lifetime of an authenticated session, no matter how active the user is.
+    /// When it's reached, the session isn't renewed anymore and the user must re-authenticate.
+    pub fn session_max_duration(&self) -> Option<Duration> {
+        self.session_max_duration
+            .map(|duration| Duration::milliseconds(duration as i64))
+    }
+
+    fn default_session_cookie_http_only() -> bool {
+        true
+    }
+
     fn default_tty() -> bool {
         std::io::stderr().is_terminal()
     }
 
+    fn default_public_url() -> Option<String> {
+        var("RWF_PUBLIC_URL").ok()
+    }
+
+    fn default_timezone() -> String {
+        var("RWF_DEFAULT_TIMEZONE").unwrap_or("UTC".into())
+    }
+
+    /// Time zone configured with `default_timezone`.
+    pub fn timezone(&self) -> UtcOffset {
+        crate::timezone::parse(&self.default_timezone).unwrap_or(UtcOffset::UTC)
+    }
+
+    fn default_trust_proxy() -> bool {
+        true_from_env("RWF_TRUST_PROXY")
+    }
+
+    fn default_allowed_hosts() -> Vec<String> {
+        vec!["localhost".into(), "127.0.0.1".into(), "[::1]".into()]
+    }
+
+    /// Check that the host, e.g. from the `Host` header, is in the `allowed_hosts` list.
+    /// The port, if any, is ignored.
+    pub fn host_allowed(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host.as_str(),
+        };
+
+        self.allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_lowercase();
+            match allowed.strip_prefix('.') {
+                Some(domain) => host == domain || host.ends_with(&allowed),
+                None => host == allowed,
+            }
+        })
+    }
+
+    /// Check that the IP address is in the `trusted_proxies` list. Entries are addresses,
+    /// e.g. `10.0.0.1`, or networks, e.g. `10.0.0.0/8` or `fd00::/8`.
+    pub fn proxy_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|network| in_network(ip, network))
+    }
+
+    fn default_memory_budget() -> usize {
+        var("RWF_MEMORY_BUDGET")
+            .ok()
+            .and_then(|budget| budget.parse().ok())
+            .unwrap_or(1024 * 1024 * 1024)
+    }
+
     fn default_header_max_size() -> usize {
         16 * 1024 // 16K
     }
@@ -330,6 +685,92 @@ impl General {
     fn default_max_request_size() -> usize {
         5 * 1024 * 1024 // 5M
     }
+
+    fn default_max_json_size() -> usize {
+        1024 * 1024 // 1M
+    }
+
+    fn default_max_json_depth() -> usize {
+        64
+    }
+
+    fn default_max_json_string_size() -> usize {
+        256 * 1024 // 256K
+    }
+
+    fn default_multipart_disk_threshold() -> usize {
+        1024 * 1024 // 1M
+    }
+
+    fn default_max_response_size() -> usize {
+        var("RWF_MAX_RESPONSE_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(100 * 1024 * 1024) // 100M
+    }
+
+    fn default_rejected_log_level() -> String {
+        var("RWF_REJECTED_LOG_LEVEL").unwrap_or(String::from("warn"))
+    }
+
+    fn default_rejected_close_silently() -> Vec<RejectionKind> {
+        vec![RejectionKind::TlsHandshake]
+    }
+
+    fn default_job_visibility_timeout() -> usize {
+        Duration::minutes(5).whole_milliseconds() as usize
+    }
+
+    /// How long a running job can go without a checkpoint
+    /// before another worker reclaims it.
+    pub fn job_visibility_timeout(&self) -> Duration {
+        Duration::milliseconds(self.job_visibility_timeout as i64)
+    }
+
+    fn default_job_heartbeat_interval() -> usize {
+        Duration::seconds(5).whole_milliseconds() as usize
+    }
+
+    /// How often job workers record that they are alive.
+    pub fn job_heartbeat_interval(&self) -> Duration {
+        Duration::milliseconds(self.job_heartbeat_interval as i64)
+    }
+
+    fn default_job_worker_timeout() -> usize {
+        Duration::seconds(30).whole_milliseconds() as usize
+    }
+
+    /// How long a job worker can go without a heartbeat before it's considered stale.
+    pub fn job_worker_timeout(&self) -> Duration {
+        Duration::milliseconds(self.job_worker_timeout as i64)
+    }
+
+    fn default_shutdown_drain_timeout() -> usize {
+        Duration::seconds(30).whole_milliseconds() as usize
+    }
+
+    /// How long the server waits for requests in progress when shutting down.
+    pub fn shutdown_drain_timeout(&self) -> Duration {
+        Duration::milliseconds(self.shutdown_drain_timeout as i64)
+    }
+
+    fn default_shutdown_hook_timeout() -> usize {
+        Duration::seconds(10).whole_milliseconds() as usize
+    }
+
+    /// How long each shutdown hook can run.
+    pub fn shutdown_hook_timeout(&self) -> Duration {
+        Duration::milliseconds(self.shutdown_hook_timeout as i64)
+    }
+
+    fn default_handover_timeout() -> usize {
+        Duration::seconds(30).whole_milliseconds() as usize
+    }
+
+    /// How long the new process has to start accepting connections during a socket handover.
+    pub fn handover_timeout(&self) -> Duration {
+        Duration::milliseconds(self.handover_timeout as i64)
+    }
 }
 
 /// WebSocket connections configuration.
@@ -351,6 +792,14 @@ pub struct WebsocketConfig {
     /// closing the connection.
     #[serde(default = "WebsocketConfig::default_disconnect_count")]
     pub ping_disconnect_count: usize,
+    /// Maximum size of a message received from a client, in bytes.
+    /// Larger messages close the connection with code 1009.
+    #[serde(default = "WebsocketConfig::default_max_message_size")]
+    pub max_message_size: usize,
+    /// Maximum size of a single frame received from a client, in bytes.
+    /// Larger frames close the connection with code 1009.
+    #[serde(default = "WebsocketConfig::default_max_frame_size")]
+    pub max_frame_size: usize,
 }
 
 impl Default for WebsocketConfig {
@@ -359,6 +808,8 @@ impl Default for WebsocketConfig {
             ping_timeout: Self::default_ping_timeout(),
             ping_interval: Self::default_ping_interval(),
             ping_disconnect_count: Self::default_disconnect_count(),
+            max_message_size: Self::default_max_message_size(),
+            max_frame_size: Self::default_max_frame_size(),
         }
     }
 }
@@ -385,6 +836,52 @@ impl WebsocketConfig {
     fn default_disconnect_count() -> usize {
         3
     }
+
+    fn default_max_message_size() -> usize {
+        16 * 1024 * 1024
+    }
+
+    fn default_max_frame_size() -> usize {
+        16 * 1024 * 1024
+    }
+}
+
+/// Rate limiter configuration.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RateLimitConfig {
+    /// Where to keep the rate limit counters.
+    #[serde(default = "RateLimitConfig::default_store")]
+    pub store: Backend,
+    /// Redis connection URL, used by the `redis` store.
+    #[serde(default = "RateLimitConfig::default_redis_url")]
+    pub redis_url: String,
+    /// Path of the file used by the `kv` store.
+    #[serde(default = "RateLimitConfig::default_kv_path")]
+    pub kv_path: PathBuf,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            store: Self::default_store(),
+            redis_url: Self::default_redis_url(),
+            kv_path: Self::default_kv_path(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn default_store() -> Backend {
+        Backend::default()
+    }
+
+    fn default_redis_url() -> String {
+        var("RWF_REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".into())
+    }
+
+    fn default_kv_path() -> PathBuf {
+        PathBuf::from("rwf.kv")
+    }
 }
 
 /// Database connection configuration.
@@ -409,6 +906,26 @@ pub struct DatabaseConfig {
     /// in the pool.
     #[serde(default = "DatabaseConfig::default_pool_size")]
     pub pool_size: usize,
+    /// Number of connection failures in a row after which the pool stops trying to connect,
+    /// and fails right away with [`crate::model::Error::DatabaseUnavailable`]. `0` disables the circuit breaker.
+    #[serde(default = "DatabaseConfig::default_circuit_breaker_threshold")]
+    pub circuit_breaker_threshold: usize,
+    /// How often the pool tries to reconnect while the circuit breaker is open.
+    /// Configured in milliseconds.
+    /// Use [`DatabaseConfig::circuit_breaker_cooldown`] to get a valid [`Duration`] struct.
+    #[serde(default = "DatabaseConfig::default_circuit_breaker_cooldown")]
+    pub circuit_breaker_cooldown: usize,
+    /// How long the application keeps trying to connect to the database when it starts,
+    /// while serving routes that don't need it. `0` fails right away.
+    /// Configured in milliseconds.
+    #[serde(default)]
+    pub startup_grace_period: usize,
+    /// Write the database schema to this file after running migrations.
+    /// See [`crate::model::schema`].
+    pub schema_dump: Option<String>,
+    /// Additional databases, configured in `[database.<name>]` sections.
+    #[serde(flatten, skip_serializing)]
+    databases: HashMap<String, toml::Value>,
 }
 
 impl Default for DatabaseConfig {
@@ -420,6 +937,11 @@ impl Default for DatabaseConfig {
             idle_timeout: DatabaseConfig::default_idle_timeout(),
             checkout_timeout: DatabaseConfig::default_checkout_timeout(),
             pool_size: DatabaseConfig::default_pool_size(),
+            circuit_breaker_threshold: DatabaseConfig::default_circuit_breaker_threshold(),
+            circuit_breaker_cooldown: DatabaseConfig::default_circuit_breaker_cooldown(),
+            startup_grace_period: 0,
+            schema_dump: None,
+            databases: HashMap::new(),
         }
     }
 }
@@ -449,6 +971,92 @@ impl DatabaseConfig {
         10
     }
 
+    fn default_circuit_breaker_threshold() -> usize {
+        5
+    }
+
+    fn default_circuit_breaker_cooldown() -> usize {
+        5 * 1000
+    }
+
+    /// How often the pool tries to reconnect while the circuit breaker is open.
+    pub fn circuit_breaker_cooldown(&self) -> Duration {
+        Duration::milliseconds(self.circuit_breaker_cooldown as i64)
+    }
+
+    /// How long the application keeps trying to connect to the database when it starts.
+    pub fn startup_grace_period(&self) -> Duration {
+        Duration::milliseconds(self.startup_grace_period as i64)
+    }
+
+    /// Configuration of a database by name. The `"main"` database is configured in the `[database]` section,
+    /// or in `[database.main]`. Other databases are configured in `[database.<name>]` sections, and connect
+    /// to a local database with the same name, unless `url` or `name` is set.
+    pub fn named(&self, database: &str) -> Option<DatabaseConfig> {
+        let config = match self.databases.get(database) {
+            Some(toml::Value::Table(table)) => {
+                match toml::Value::Table(table.clone()).try_into::<DatabaseConfig>() {
+                    Ok(config) => config,
+                    Err(err) => {
+                        warn!(
+                            r#"database "{}" is not configured correctly: {}"#,
+                            database, err
+                        );
+                        return None;
+                    }
+                }
+            }
+
+            _ if database == DEFAULT_DATABASE => {
+                return Some(DatabaseConfig {
+                    databases: HashMap::new(),
+                    ..self.clone()
+                })
+            }
+
+            _ => return None,
+        };
+
+        if database == DEFAULT_DATABASE || config.url.is_some() {
+            return Some(config);
+        }
+
+        let user = config.user.clone().unwrap_or_else(Self::default_user);
+        let name = config.name.clone().unwrap_or(database.to_string());
+
+        Some(DatabaseConfig {
+            url: Some(format!("postgresql://{}@localhost/{}", user, name)),
+            ..config
+        })
+    }
+
+    /// Names of databases configured in `[database.<name>]` sections.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self
+            .databases
+            .iter()
+            .filter(|(_, value)| value.is_table())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+
+        if !names.iter().any(|name| name == DEFAULT_DATABASE) {
+            names.push(DEFAULT_DATABASE.to_string());
+        }
+
+        names.sort();
+        names
+    }
+
+    fn default_user() -> String {
+        match var("RWF_DATABASE_USER") {
+            Ok(user) => user,
+            Err(_) => match var("USER") {
+                Ok(user) => user,
+                Err(_) => "postgres".into(),
+            },
+        }
+    }
+
     /// Convert the connection config to a valid
     /// database URL as described by the
     /// Twelve Factor Application.
@@ -458,13 +1066,7 @@ impl DatabaseConfig {
             None => match var("RWF_DATABASE_URL") {
                 Ok(url) => url,
                 Err(_) => {
-                    let user = self.user.clone().unwrap_or(match var("RWF_DATABASE_USER") {
-                        Ok(user) => user,
-                        Err(_) => match var("USER") {
-                            Ok(user) => user,
-                            Err(_) => "postgres".into(),
-                        },
-                    });
+                    let user = self.user.clone().unwrap_or_else(Self::default_user);
                     let name = self.name.clone().unwrap_or(match var("RWF_DATABASE") {
                         Ok(name) => name,
                         Err(_) => user.clone(),
@@ -483,6 +1085,54 @@ mod test {
     use std::{fs::File, io::Write};
     use tempdir::TempDir;
 
+    #[test]
+    fn test_host_allowed() {
+        let general = General {
+            allowed_hosts: vec!["localhost".into(), "[::1]".into(), ".example.com".into()],
+            ..Default::default()
+        };
+
+        for host in [
+            "localhost",
+            "LOCALHOST:8000",
+            "[::1]:8000",
+            "example.com",
+            "www.example.com:443",
+        ] {
+            assert!(general.host_allowed(host), "{}", host);
+        }
+
+        for host in [
+            "evil.com",
+            "localhost.evil.com",
+            "notexample.com",
+            "example.com.evil",
+        ] {
+            assert!(!general.host_allowed(host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn test_proxy_trusted() {
+        let general = General {
+            trusted_proxies: vec![
+                "10.0.0.0/8".into(),
+                "192.168.1.1".into(),
+                "fd00::/8".into(),
+                "172.16.0.0/abc".into(),
+            ],
+            ..Default::default()
+        };
+
+        for ip in ["10.1.2.3", "192.168.1.1", "fd12::1", "::ffff:10.0.0.1"] {
+            assert!(general.proxy_trusted(ip.parse().unwrap()), "{}", ip);
+        }
+
+        for ip in ["11.0.0.1", "192.168.1.2", "fe80::1", "172.16.0.0"] {
+            assert!(!general.proxy_trusted(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
     #[test]
     fn test_load_config() {
         for config_path in ["rwf.toml", "Rum.toml"] {
@@ -506,4 +1156,38 @@ name = "test"
             assert_eq!(config.path, Some(PathBuf::from(config_path)));
         }
     }
+
+    #[test]
+    fn test_named_databases() {
+        let config: DatabaseConfig = toml::from_str(
+            r#"
+url = "postgres://app@db/app"
+
+[analytics]
+url = "postgres://app@warehouse/events"
+pool_size = 2
+
+[reports]
+user = "reporter"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.names(), vec!["analytics", "main", "reports"]);
+
+        let main = config.named("main").unwrap();
+        assert_eq!(main.database_url(), "postgres://app@db/app");
+
+        let analytics = config.named("analytics").unwrap();
+        assert_eq!(analytics.database_url(), "postgres://app@warehouse/events");
+        assert_eq!(analytics.pool_size, 2);
+
+        let reports = config.named("reports").unwrap();
+        assert_eq!(
+            reports.database_url(),
+            "postgresql://reporter@localhost/reports"
+        );
+
+        assert!(config.named("unknown").is_none());
+    }
 }
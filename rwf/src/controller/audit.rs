@@ -0,0 +1,123 @@
+//! Audit log of security-sensitive events, e.g. a user starting to impersonate another user.
+//!
+//! Events are logged at the `info` level with the `rwf::audit` target, so they can be filtered
+//! and shipped separately from other logs. Each event records both the user the session is acting as
+//! and the user who is actually authenticated; they only differ while impersonating.
+//!
+//! ```
+//! # use rwf::prelude::*;
+//! # let request = Request::default();
+//! request
+//!     .audit("password_changed")
+//!     .detail("method", "email")
+//!     .log();
+//! ```
+use super::Session;
+
+use tracing::info;
+
+/// Event recorded in the audit log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    name: String,
+    user_id: Option<i64>,
+    true_user_id: Option<i64>,
+    details: Vec<(String, String)>,
+}
+
+impl AuditEvent {
+    /// Create an event without any users attached.
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            user_id: None,
+            true_user_id: None,
+            details: vec![],
+        }
+    }
+
+    /// Attach the effective and the true user of the session to the event.
+    pub fn session(mut self, session: Option<&Session>) -> Self {
+        self.user_id = session.and_then(|session| session.user_id());
+        self.true_user_id = session.and_then(|session| session.true_user_id());
+        self
+    }
+
+    /// Add some information about the event.
+    pub fn detail(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.details.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Event name, e.g. `"impersonation_started"`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// User the session is acting as.
+    pub fn user_id(&self) -> Option<i64> {
+        self.user_id
+    }
+
+    /// User actually authenticated, e.g. the admin impersonating [`AuditEvent::user_id`].
+    pub fn true_user_id(&self) -> Option<i64> {
+        self.true_user_id
+    }
+
+    /// Information added with [`AuditEvent::detail`].
+    pub fn details(&self) -> &[(String, String)] {
+        &self.details
+    }
+
+    /// Write the event to the audit log.
+    pub fn log(&self) {
+        info!(target: "rwf::audit", "{}", self);
+    }
+}
+
+impl std::fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let user = |id: Option<i64>| id.map(|id| id.to_string()).unwrap_or("-".into());
+
+        write!(
+            f,
+            "{} user_id={} true_user_id={}",
+            self.name,
+            user(self.user_id),
+            user(self.true_user_id)
+        )?;
+
+        for (name, value) in &self.details {
+            write!(f, " {}={:?}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::Duration;
+
+    #[test]
+    fn test_audit_event() {
+        let session = Session::new_authenticated(serde_json::json!({}), 1)
+            .unwrap()
+            .impersonate(7, Duration::minutes(15))
+            .unwrap();
+
+        let event = AuditEvent::new("report_exported")
+            .session(Some(&session))
+            .detail("format", "csv");
+        assert_eq!(event.user_id(), Some(7));
+        assert_eq!(event.true_user_id(), Some(1));
+        assert_eq!(
+            event.to_string(),
+            r#"report_exported user_id=7 true_user_id=1 format="csv""#
+        );
+
+        let event = AuditEvent::new("login_failed").session(None);
+        assert_eq!(event.to_string(), "login_failed user_id=- true_user_id=-");
+    }
+}
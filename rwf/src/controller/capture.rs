@@ -0,0 +1,145 @@
+//! JSON controller for [captures](crate::http::capture), to turn them on and view them.
+//!
+//! Captures contain request and response bodies, so the controller requires a function deciding
+//! who can use it, e.g. only administrators:
+//!
+//! ```rust
+//! use rwf::prelude::*;
+//! use rwf::controller::CaptureController;
+//! use rwf::http::Server;
+//!
+//! let captures = CaptureController::new(|request| matches!(request.user_id(), Ok(1)));
+//!
+//! Server::new(vec![captures.route("/admin/captures")]);
+//! ```
+//!
+//! * `GET` returns the captures kept in memory, newest first, or, with `?request_id=`, the capture of that request.
+//! * `POST` adds the [`CaptureRule`] in the JSON body, e.g. `{"name": "orders", "path_prefix": "/api/orders"}`.
+//! * `DELETE` with `?rule=` removes the rule.
+//!
+//! Clients which aren't allowed get `403 - Forbidden`.
+use crate::http::capture::{self, CaptureRule};
+use crate::http::{CacheControl, Method};
+use crate::prelude::*;
+
+/// Function deciding if the client is allowed to use the controller.
+pub type Authorize = Box<dyn Fn(&Request) -> bool + Send + Sync>;
+
+/// Capture controller.
+pub struct CaptureController {
+    authorize: Authorize,
+}
+
+impl CaptureController {
+    /// Create the controller with the authorization function.
+    pub fn new(authorize: impl Fn(&Request) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            authorize: Box::new(authorize),
+        }
+    }
+}
+
+#[async_trait]
+impl Controller for CaptureController {
+    async fn handle(&self, request: &Request) -> Result<Response, Error> {
+        if !(self.authorize)(request) {
+            return Ok(Response::forbidden());
+        }
+
+        let query = request.path().query();
+
+        let response = match request.method() {
+            Method::Get => match query.get::<String>("request_id") {
+                Some(request_id) => match capture::find(&request_id).await? {
+                    Some(capture) => Response::new().json(&capture)?,
+                    None => Response::not_found(),
+                },
+                None => Response::new().json(capture::captures())?,
+            },
+
+            Method::Post => {
+                let rule = request.json::<CaptureRule>()?;
+                let response = Response::new().json(&rule)?.code(201);
+                capture::add_rule(rule);
+                response
+            }
+
+            Method::Delete => match query.get::<String>("rule") {
+                Some(rule) if capture::remove_rule(&rule) => Response::no_content(),
+                _ => Response::not_found(),
+            },
+
+            _ => Response::method_not_allowed(),
+        };
+
+        Ok(response.cache(CacheControl::no_store()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::request::test::dummy_ip;
+
+    async fn request(method: &str, path: &str, body: &str) -> Request {
+        let request = format!(
+            "{} {} HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        Request::read(dummy_ip(), request.as_bytes()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_capture_controller() {
+        let controller = CaptureController::new(|request| request.header("x-admin").is_some());
+
+        let response = controller
+            .handle(&request("GET", "/admin/captures", "").await)
+            .await
+            .unwrap();
+        assert_eq!(response.status().code(), 403);
+
+        let controller = CaptureController::new(|_request| true);
+
+        let response = controller
+            .handle(
+                &request(
+                    "POST",
+                    "/admin/captures",
+                    r#"{"name": "test-controller", "path_prefix": "/test-controller"}"#,
+                )
+                .await,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status().code(), 201);
+        assert!(capture::rules()
+            .iter()
+            .any(|rule| rule.name() == "test-controller"));
+
+        let response = controller
+            .handle(
+                &request(
+                    "GET",
+                    "/admin/captures?request_id=test-controller-missing",
+                    "",
+                )
+                .await,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status().code(), 404);
+
+        let response = controller
+            .handle(&request("DELETE", "/admin/captures?rule=test-controller", "").await)
+            .await
+            .unwrap();
+        assert_eq!(response.status().code(), 204);
+        assert!(!capture::rules()
+            .iter()
+            .any(|rule| rule.name() == "test-controller"));
+    }
+}
@@ -5,6 +5,7 @@
 //! the `From<YourError> for Error` trait. You can also manually wrap your errors with this error, e.g. by
 //! calling `Error::new(your_error)`.
 use crate::http::Error as HttpError;
+use crate::model::DatabaseErrorKind;
 use thiserror::Error;
 
 /// A controller error.
@@ -40,6 +41,9 @@ pub enum Error {
     #[error("config error: {0}")]
     Config(#[from] crate::config::Error),
 
+    #[error("authorization error: {0}")]
+    PolicyError(#[from] crate::controller::policy::Error),
+
     #[error("session is not set")]
     SessionMissingError,
 
@@ -52,6 +56,24 @@ impl Error {
     pub fn new(err: impl std::error::Error + Send + Sync + 'static) -> Error {
         Error::Error(Box::new(err))
     }
+
+    /// HTTP status code returned to the client. Duplicate values are `409 - Conflict`,
+    /// other constraint violations are `422 - Unprocessable Content`, errors caused by the database
+    /// being unreachable are `503 - Service Unavailable`, and invalid pagination cursors are `400 - Bad Request`.
+    pub fn code(&self) -> u16 {
+        match self {
+            Error::HttpError(err) => err.code(),
+            Error::OrmError(crate::model::Error::InvalidCursor { .. }) => 400,
+            Error::OrmError(err) => match err.kind() {
+                DatabaseErrorKind::UniqueViolation { .. } => 409,
+                kind if kind.is_constraint_violation() => 422,
+                // The database is down, which should be temporary.
+                DatabaseErrorKind::ConnectionLost => 503,
+                _ => 500,
+            },
+            _ => 500,
+        }
+    }
 }
 
 impl From<crate::http::Error> for Error {
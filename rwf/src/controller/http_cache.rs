@@ -0,0 +1,241 @@
+//! Conditional `GET` requests for [`ModelController`](super::ModelController).
+//!
+//! Models which implement [`HttpCacheable`] tell the controller when they were last changed. The controller
+//! sends a weak `ETag` and a `Last-Modified` header with the record, and answers requests with a matching
+//! `If-None-Match` or `If-Modified-Since` header with `304 - Not Modified`, without serializing the record again.
+//!
+//! ```rust,ignore
+//! impl HttpCacheable for User {
+//!     fn updated_at(&self) -> Option<OffsetDateTime> {
+//!         Some(self.updated_at)
+//!     }
+//! }
+//!
+//! #[async_trait]
+//! impl ModelController for Users {
+//!     type Model = User;
+//!
+//!     fn http_cache(&self) -> HttpCache<User> {
+//!         HttpCache::enabled()
+//!     }
+//! }
+//! ```
+use crate::http::{conditional, Request, Response};
+use crate::model::{ConnectionGuard, Model, Value};
+
+use time::OffsetDateTime;
+
+/// Model which tells HTTP clients when it was last changed, so they can cache it.
+pub trait HttpCacheable: Model {
+    /// When the record was last changed.
+    fn updated_at(&self) -> Option<OffsetDateTime>;
+
+    /// Version of the record incremented on every update, e.g. used for optimistic locking.
+    /// Used in the `ETag` instead of [`HttpCacheable::updated_at`] if set.
+    fn lock_version(&self) -> Option<i64> {
+        None
+    }
+
+    /// Column storing when the record was last changed, used to validate lists of records.
+    fn updated_at_column() -> &'static str {
+        "updated_at"
+    }
+
+    /// Cache validators of the record.
+    fn validators(&self) -> Validators {
+        let id = match self.id() {
+            Value::Integer(id) => id.to_string(),
+            Value::String(id) => id,
+            id => format!("{:?}", id),
+        };
+
+        let version = match (self.lock_version(), self.updated_at()) {
+            (Some(version), _) => format!("v{}", version),
+            (None, Some(updated_at)) => format!("{:x}", updated_at.unix_timestamp_nanos()),
+            (None, None) => "0".into(),
+        };
+
+        Validators::new(format!("{}-{}", id, version)).last_modified(self.updated_at())
+    }
+}
+
+/// `ETag` and `Last-Modified` of a response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validators {
+    etag: String,
+    last_modified: Option<OffsetDateTime>,
+}
+
+impl Validators {
+    /// Create validators with a weak `ETag`. The tag can't contain double quotes.
+    pub fn new(tag: impl ToString) -> Self {
+        Self {
+            etag: format!("W/\"{}\"", tag.to_string().replace('"', "")),
+            last_modified: None,
+        }
+    }
+
+    /// Set the `Last-Modified` header.
+    pub fn last_modified(mut self, last_modified: Option<OffsetDateTime>) -> Self {
+        self.last_modified = last_modified;
+        self
+    }
+
+    /// Value of the `ETag` header.
+    pub fn etag(&self) -> &str {
+        &self.etag
+    }
+
+    /// The client has the current version of the resource. See [`conditional::not_modified`].
+    pub fn fresh(&self, request: &Request) -> bool {
+        conditional::not_modified(
+            request,
+            Some(&self.etag),
+            self.last_modified
+                .map(conditional::format_http_date)
+                .as_deref(),
+        )
+    }
+
+    /// Add the validators to the response.
+    pub fn apply(&self, mut response: Response) -> Response {
+        response = response.header("etag", &self.etag);
+
+        if let Some(last_modified) = self.last_modified {
+            response = response.last_modified(last_modified);
+        }
+
+        response
+    }
+
+    /// `304 - Not Modified` response with the validators.
+    pub fn not_modified(&self) -> Response {
+        self.apply(Response::new().code(304))
+    }
+}
+
+/// Conditional requests handled by a [`ModelController`](super::ModelController).
+pub struct HttpCache<M> {
+    validators: Option<fn(&M) -> Validators>,
+    updated_at_column: Option<fn() -> &'static str>,
+}
+
+impl<M> HttpCache<M> {
+    /// Don't handle conditional requests. This is the default.
+    pub fn disabled() -> Self {
+        Self {
+            validators: None,
+            updated_at_column: None,
+        }
+    }
+
+    /// Validators of a record, if enabled.
+    pub fn validators(&self, model: &M) -> Option<Validators> {
+        self.validators.map(|validators| validators(model))
+    }
+
+    /// Lists of records are validated.
+    pub fn collections_enabled(&self) -> bool {
+        self.updated_at_column.is_some()
+    }
+}
+
+impl<M: HttpCacheable> HttpCache<M> {
+    /// Handle conditional requests for single records.
+    pub fn enabled() -> Self {
+        Self {
+            validators: Some(M::validators),
+            updated_at_column: None,
+        }
+    }
+
+    /// Handle conditional requests for lists of records too. The validators are the
+    /// most recent [`HttpCacheable::updated_at_column`] and the number of records, which costs
+    /// an extra aggregate query for each request.
+    pub fn collections(mut self) -> Self {
+        self.updated_at_column = Some(M::updated_at_column);
+        self
+    }
+}
+
+impl<M: Model> HttpCache<M> {
+    /// Validators of the list of records, if enabled.
+    pub async fn collection_validators(
+        &self,
+        conn: &mut ConnectionGuard,
+    ) -> Result<Option<Validators>, crate::model::Error> {
+        let column = match self.updated_at_column {
+            Some(column) => column(),
+            None => return Ok(None),
+        };
+
+        let row = conn
+            .client()
+            .query_one(
+                &format!(
+                    r#"SELECT MAX("{}")::timestamptz, COUNT(*) FROM "{}""#,
+                    column,
+                    M::table_name()
+                ),
+                &[],
+            )
+            .await?;
+
+        let updated_at: Option<OffsetDateTime> = row.try_get(0)?;
+        let count: i64 = row.try_get(1)?;
+
+        let version = match updated_at {
+            Some(updated_at) => format!("{:x}", updated_at.unix_timestamp_nanos()),
+            None => "0".into(),
+        };
+
+        Ok(Some(
+            Validators::new(format!("{}-{}", count, version)).last_modified(updated_at),
+        ))
+    }
+}
+
+impl<M> Default for HttpCache<M> {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn request(header: &str, value: &str) -> Request {
+        let request = format!("GET /users/1 HTTP/1.1\r\n{}: {}\r\n\r\n", header, value);
+        Request::read("127.0.0.1:1234".parse().unwrap(), request.as_bytes())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fresh() {
+        let updated_at = OffsetDateTime::from_unix_timestamp_nanos(1730542500_500_000_000).unwrap();
+        let validators = Validators::new("1-abc").last_modified(Some(updated_at));
+        assert_eq!(validators.etag(), "W/\"1-abc\"");
+
+        assert!(validators.fresh(&request("If-None-Match", "W/\"1-abc\"").await));
+        assert!(validators.fresh(&request("If-None-Match", "\"0-x\", \"1-abc\"").await));
+        assert!(validators.fresh(&request("If-None-Match", "*").await));
+        assert!(!validators.fresh(&request("If-None-Match", "W/\"1-abd\"").await));
+
+        assert!(
+            validators.fresh(&request("If-Modified-Since", "Sat, 02 Nov 2024 10:15:00 GMT").await)
+        );
+        assert!(
+            !validators.fresh(&request("If-Modified-Since", "Sat, 02 Nov 2024 10:14:59 GMT").await)
+        );
+
+        let response = validators.not_modified();
+        assert_eq!(response.status().code(), 304);
+        assert_eq!(response.headers().get("etag").unwrap(), "W/\"1-abc\"");
+        assert_eq!(
+            response.headers().get("last-modified").unwrap(),
+            "Sat, 02 Nov 2024 10:15:00 GMT"
+        );
+    }
+}
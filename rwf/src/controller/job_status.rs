@@ -0,0 +1,57 @@
+//! JSON controller returning the [status](crate::job::status) of a background job, so
+//! clients can poll it until the job is done.
+//!
+//! Job ids are sequential, so the controller requires a function deciding who can see the job,
+//! e.g. only the user who started it:
+//!
+//! ```rust
+//! use rwf::prelude::*;
+//! use rwf::controller::JobStatusController;
+//! use rwf::http::Server;
+//!
+//! let status = JobStatusController::new(|request, status| {
+//!     matches!(request.user_id(), Ok(user_id) if status.args["user_id"] == user_id)
+//! });
+//!
+//! Server::new(vec![status.route("/jobs/:id")]);
+//! ```
+//!
+//! The response is the [`JobStatus`](crate::job::JobStatus) serialized to JSON. Jobs that don't exist, and jobs the
+//! client isn't allowed to see, both return `404 - Not Found`.
+use crate::http::CacheControl;
+use crate::job::{self, JobStatus};
+use crate::prelude::*;
+
+/// Function deciding if the client is allowed to see the job.
+pub type Authorize = Box<dyn Fn(&Request, &JobStatus) -> bool + Send + Sync>;
+
+/// Job status controller.
+pub struct JobStatusController {
+    authorize: Authorize,
+}
+
+impl JobStatusController {
+    /// Create the controller with the authorization function.
+    pub fn new(authorize: impl Fn(&Request, &JobStatus) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            authorize: Box::new(authorize),
+        }
+    }
+}
+
+#[async_trait]
+impl Controller for JobStatusController {
+    async fn handle(&self, request: &Request) -> Result<Response, Error> {
+        let id = match request.parameter::<i64>("id") {
+            Ok(Some(id)) => id,
+            _ => return Ok(Response::not_found()),
+        };
+
+        match job::status(id).await? {
+            Some(status) if (self.authorize)(request, &status) => Ok(Response::new()
+                .json(&status)?
+                .cache(CacheControl::no_store())),
+            _ => Ok(Response::not_found()),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Content Security Policy.
+//!
+//! Sets the `Content-Security-Policy` header on responses, telling browsers which scripts, styles and other resources
+//! the page is allowed to load. By default, only resources from the same origin are allowed, and inline scripts
+//! must carry the request's nonce:
+//!
+//! ```html
+//! <script nonce="<%= csp_nonce() %>">
+//!   console.log("allowed by the policy");
+//! </script>
+//! ```
+//!
+//! The nonce is random and different for each request. It's added to the `script-src` directive of the header,
+//! so the header and the page always agree.
+//!
+//! ```
+//! use rwf::controller::middleware::ContentSecurityPolicy;
+//!
+//! let csp = ContentSecurityPolicy::new()
+//!     .directive("img-src", "'self' data:")
+//!     .directive("style-src", "'self' https://fonts.googleapis.com");
+//!
+//! assert_eq!(
+//!     csp.header_value(Some("abc")),
+//!     "default-src 'self'; object-src 'none'; base-uri 'self'; img-src 'self' data:; \
+//!      style-src 'self' https://fonts.googleapis.com; script-src 'self' 'nonce-abc'"
+//! );
+//! ```
+use super::prelude::*;
+
+/// `Content-Security-Policy` header middleware.
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, String)>,
+    nonce: bool,
+    report_only: bool,
+}
+
+impl Default for ContentSecurityPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContentSecurityPolicy {
+    /// Create a policy allowing resources from the same origin only, and inline scripts with the request's nonce.
+    pub fn new() -> Self {
+        Self {
+            directives: vec![
+                ("default-src".into(), "'self'".into()),
+                ("object-src".into(), "'none'".into()),
+                ("base-uri".into(), "'self'".into()),
+            ],
+            nonce: true,
+            report_only: false,
+        }
+    }
+
+    /// Set a directive, e.g. `img-src`, replacing its previous value.
+    pub fn directive(mut self, name: &str, value: &str) -> Self {
+        let name = name.trim().to_lowercase();
+
+        if let Some((_, existing)) = self.directives.iter_mut().find(|(n, _)| *n == name) {
+            *existing = value.to_string();
+        } else {
+            self.directives.push((name, value.to_string()));
+        }
+
+        self
+    }
+
+    /// Don't generate nonces. Inline scripts are allowed only if the policy allows them otherwise, e.g. with `'unsafe-inline'`.
+    pub fn without_nonce(mut self) -> Self {
+        self.nonce = false;
+        self
+    }
+
+    /// Send the policy in the `Content-Security-Policy-Report-Only` header, so violations are reported
+    /// by the browser but not blocked.
+    pub fn report_only(mut self) -> Self {
+        self.report_only = true;
+        self
+    }
+
+    /// Value of the header. The nonce is added to the `script-src` directive, which is created from `default-src`
+    /// if it's not set.
+    pub fn header_value(&self, nonce: Option<&str>) -> String {
+        let mut directives = self.directives.clone();
+
+        if let Some(nonce) = nonce {
+            let nonce = format!("'nonce-{}'", nonce);
+
+            match directives.iter_mut().find(|(name, _)| name == "script-src") {
+                Some((_, value)) => {
+                    value.push(' ');
+                    value.push_str(&nonce);
+                }
+                None => {
+                    let default = directives
+                        .iter()
+                        .find(|(name, _)| name == "default-src")
+                        .map(|(_, value)| format!("{} ", value))
+                        .unwrap_or_default();
+                    directives.push(("script-src".into(), format!("{}{}", default, nonce)));
+                }
+            }
+        }
+
+        directives
+            .iter()
+            .map(|(name, value)| format!("{} {}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    fn header_name(&self) -> &'static str {
+        if self.report_only {
+            "content-security-policy-report-only"
+        } else {
+            "content-security-policy"
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for ContentSecurityPolicy {
+    async fn handle_request(&self, request: Request) -> Result<Outcome, Error> {
+        if self.nonce {
+            request.nonce().generate();
+        }
+
+        Ok(Outcome::Forward(request))
+    }
+
+    async fn handle_response(
+        &self,
+        request: &Request,
+        response: Response,
+    ) -> Result<Response, Error> {
+        // Controllers can set their own policy.
+        if response.headers().get(self.header_name()).is_some() {
+            return Ok(response);
+        }
+
+        let value = self.header_value(request.nonce().get());
+        Ok(response.header(self.header_name(), value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{Controller, MiddlewareSet};
+    use crate::http::Nonce;
+    use crate::view::Template;
+
+    struct Page {
+        middleware: MiddlewareSet,
+    }
+
+    #[async_trait]
+    impl Controller for Page {
+        fn middleware(&self) -> &MiddlewareSet {
+            &self.middleware
+        }
+
+        async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+            let template = Template::from_str(r#"<script nonce="<%= csp_nonce() %>"></script>"#)?;
+            Ok(Response::new().html(template.render_default()?))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_csp_nonce() {
+        let page = Page {
+            middleware: MiddlewareSet::without_default(vec![ContentSecurityPolicy::new()
+                .directive("script-src", "'self' https://cdn.example.com")
+                .middleware()]),
+        };
+
+        let mut nonces = vec![];
+
+        for _ in 0..2 {
+            let request = Request::default();
+            let response = request
+                .nonce()
+                .clone()
+                .scope(page.handle_internal(request.clone()))
+                .await
+                .unwrap();
+
+            let nonce = request.nonce().get().unwrap().to_string();
+            let header = response
+                .headers()
+                .get("content-security-policy")
+                .unwrap()
+                .clone();
+            let body = String::from_utf8(response.body_bytes().unwrap().to_vec()).unwrap();
+
+            assert!(header.contains(&format!(
+                "script-src 'self' https://cdn.example.com 'nonce-{}'",
+                nonce
+            )));
+            assert_eq!(body, format!(r#"<script nonce="{}"></script>"#, nonce));
+            nonces.push(nonce);
+        }
+
+        assert_ne!(nonces[0], nonces[1]);
+
+        // No nonce without the middleware.
+        let request = Request::default();
+        let body = Nonce::new()
+            .scope(async {
+                Template::from_str(r#"<%= csp_nonce() %>"#)
+                    .unwrap()
+                    .render_default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(body, "");
+        assert!(request.nonce().get().is_none());
+    }
+}
@@ -15,13 +15,88 @@
 //! </form>
 //! ```
 //!
+//! ### Double-submit cookie
+//! Single-page apps that call the API with `fetch` can use a cookie instead of rendering tokens into HTML.
+//! Controllers opt in with [`CsrfMode::DoubleSubmit`]:
+//!
+//! ```rust
+//! # use rwf::prelude::*;
+//! # use rwf::controller::CsrfMode;
+//! struct Api;
+//!
+//! #[async_trait]
+//! impl Controller for Api {
+//!     fn csrf_mode(&self) -> CsrfMode {
+//!         CsrfMode::DoubleSubmit
+//!     }
+//!
+//!     async fn handle(&self, request: &Request) -> Result<Response, Error> {
+//!         Ok(Response::new().json(serde_json::json!({"ok": true}))?)
+//!     }
+//! }
+//! ```
+//!
+//! Responses set the `csrf` cookie, which JavaScript can read, and the client sends its value back in the `X-CSRF-Token` header.
+//! The value is signed with the secret key and bound to the session, so a cookie set by someone else is rejected.
+//! A new value is set when the session changes, e.g. when the user logs in. Tokens rendered with `csrf_token()` are still accepted,
+//! so server-rendered forms keep working.
+//!
 //! ### Configuration
 //! Toggle `csrf_protection` in the configuration to enable/disable CSRF protection application-wide.
 use super::prelude::*;
-use crate::{crypto::csrf_token_validate, http::Method};
+use crate::{
+    controller::Session,
+    crypto::{csrf_token_validate, random_string, sign, verify_signature},
+    http::{CookieBuilder, Method},
+};
 
 pub static CSRF_HEADER: &str = "X-CSRF-Token";
 pub static CSRF_INPUT: &str = "rwf_csrf_token";
+pub static CSRF_COOKIE: &str = "csrf";
+
+/// How CSRF protection checks requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CsrfMode {
+    /// The token rendered with `csrf_token()` is sent back in a form field or the `X-CSRF-Token` header.
+    #[default]
+    Token,
+    /// The value of the signed `csrf` cookie is sent back in the `X-CSRF-Token` header.
+    /// Tokens rendered with `csrf_token()` are accepted as well.
+    DoubleSubmit,
+}
+
+/// Session the double-submit cookie is bound to. Requests without a session are bound to an empty one.
+fn session_id(session: Option<&Session>) -> String {
+    session
+        .map(|session| session.session_id.to_string())
+        .unwrap_or_default()
+}
+
+/// Create a double-submit cookie value for the session: a random value and its signature.
+fn double_submit_token(session_id: &str) -> String {
+    let value = random_string(32);
+    let signature = sign(format!("{}.{}", value, session_id).as_bytes());
+    format!("{}.{}", value, signature)
+}
+
+/// Check the double-submit cookie value was created by us, for this session.
+fn double_submit_valid(token: &str, session_id: &str) -> bool {
+    match token.split_once('.') {
+        Some((value, signature)) => {
+            verify_signature(format!("{}.{}", value, session_id).as_bytes(), signature)
+        }
+        None => false,
+    }
+}
+
+/// Compare the header to the cookie without leaking how much of it matched.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
 
 /// CSRF protection middleware.
 pub struct Csrf;
@@ -40,12 +115,23 @@ impl Middleware for Csrf {
             return Ok(Outcome::Forward(request));
         }
 
-        if ![Method::Put, Method::Post, Method::Patch].contains(request.method()) {
+        // Forms that override the method with `_method` are still checked.
+        if ![Method::Put, Method::Post, Method::Patch].contains(request.original_method()) {
             return Ok(Outcome::Forward(request));
         }
 
         let header = request.header(CSRF_HEADER);
 
+        if request.csrf_mode() == CsrfMode::DoubleSubmit {
+            if let (Some(header), Some(cookie)) = (header, request.cookies().get(CSRF_COOKIE)) {
+                if same(header, cookie.value())
+                    && double_submit_valid(cookie.value(), &session_id(request.session()))
+                {
+                    return Ok(Outcome::Forward(request));
+                }
+            }
+        }
+
         if let Some(header) = header {
             if csrf_token_validate(header) {
                 return Ok(Outcome::Forward(request));
@@ -66,4 +152,190 @@ impl Middleware for Csrf {
 
         Ok(Outcome::Stop(request, Response::csrf_error()))
     }
+
+    /// Set the double-submit cookie, unless the client has one for the current session already.
+    async fn handle_response(
+        &self,
+        request: &Request,
+        response: Response,
+    ) -> Result<Response, Error> {
+        if request.skip_csrf() || request.csrf_mode() != CsrfMode::DoubleSubmit {
+            return Ok(response);
+        }
+
+        // The session changes when the user logs in or out.
+        let session_id = session_id(response.session().as_ref().or(request.session()));
+
+        if let Some(cookie) = request.cookies().get(CSRF_COOKIE) {
+            if double_submit_valid(cookie.value(), &session_id) {
+                return Ok(response);
+            }
+        }
+
+        // Readable by JavaScript, and never sent with cross-site requests.
+        Ok(response.cookie(
+            CookieBuilder::new()
+                .name(CSRF_COOKIE)
+                .value(double_submit_token(&session_id))
+                .path("/")
+                .strict()
+                .build(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{Controller, MiddlewareSet};
+    use crate::crypto::csrf_token;
+
+    struct Api {
+        middleware: MiddlewareSet,
+    }
+
+    #[async_trait]
+    impl Controller for Api {
+        fn middleware(&self) -> &MiddlewareSet {
+            &self.middleware
+        }
+
+        fn csrf_mode(&self) -> CsrfMode {
+            CsrfMode::DoubleSubmit
+        }
+
+        async fn handle(&self, request: &Request) -> Result<Response, Error> {
+            if request.path().path() == "/login" {
+                Ok(request.login(1))
+            } else {
+                Ok(Response::new().text("ok"))
+            }
+        }
+    }
+
+    /// Send a request to the API, returning the response and the cookies it set.
+    async fn send(
+        method: &str,
+        path: &str,
+        cookies: &[(&str, &str)],
+        header: Option<&str>,
+    ) -> (u16, Option<String>, Option<String>) {
+        let api = Api {
+            middleware: MiddlewareSet::without_default(vec![Csrf::new().middleware()]),
+        };
+
+        let mut head = format!("{} {} HTTP/1.1\r\nContent-Length: 0\r\n", method, path);
+        if !cookies.is_empty() {
+            let cookies = cookies
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("; ");
+            head.push_str(&format!("Cookie: {}\r\n", cookies));
+        }
+        if let Some(header) = header {
+            head.push_str(&format!("{}: {}\r\n", CSRF_HEADER, header));
+        }
+        head.push_str("\r\n");
+
+        let request = Request::read("127.0.0.1:1234".parse().unwrap(), head.as_bytes())
+            .await
+            .unwrap();
+        let mut response = api.handle_internal(request).await.unwrap();
+        let code = response.status().code();
+        let cookies = response.cookies();
+        let cookie = |name| {
+            cookies
+                .get_pending(name)
+                .map(|cookie| cookie.value().to_string())
+        };
+
+        (code, cookie(CSRF_COOKIE), cookie("rwf_session"))
+    }
+
+    #[tokio::test]
+    async fn test_double_submit() {
+        // The first request gets the cookie and a session.
+        let (code, csrf, session) = send("GET", "/", &[], None).await;
+        assert_eq!(code, 200);
+        let (csrf, session) = (csrf.unwrap(), session.unwrap());
+
+        let cookies = [("rwf_session", session.as_str()), (CSRF_COOKIE, &csrf)];
+
+        // The cookie is echoed in the header.
+        let (code, rotated, _) = send("POST", "/", &cookies, Some(&csrf)).await;
+        assert_eq!(code, 200);
+        assert!(rotated.is_none());
+
+        // Missing header.
+        let (code, _, _) = send("POST", "/", &cookies, None).await;
+        assert_eq!(code, 400);
+
+        // Header doesn't match the cookie.
+        let (code, _, _) = send("POST", "/", &cookies, Some(&format!("{}x", csrf))).await;
+        assert_eq!(code, 400);
+
+        // Forged cookie without our signature.
+        let forged = "attacker.signature";
+        let (code, _, _) = send(
+            "POST",
+            "/",
+            &[("rwf_session", &session), (CSRF_COOKIE, forged)],
+            Some(forged),
+        )
+        .await;
+        assert_eq!(code, 400);
+
+        // Cookie of another session.
+        let (code, _, _) = send("POST", "/", &[(CSRF_COOKIE, &csrf)], Some(&csrf)).await;
+        assert_eq!(code, 400);
+
+        // Rendered tokens still work.
+        let token = csrf_token().unwrap();
+        let (code, _, _) = send("POST", "/", &cookies, Some(&token)).await;
+        assert_eq!(code, 200);
+
+        // A new value is set on login, and the old one stops working.
+        let (code, rotated, session) = send("POST", "/login", &cookies, Some(&csrf)).await;
+        assert_eq!(code, 200);
+        let (rotated, session) = (rotated.unwrap(), session.unwrap());
+        assert_ne!(rotated, csrf);
+
+        let (code, _, _) = send(
+            "POST",
+            "/",
+            &[("rwf_session", &session), (CSRF_COOKIE, &csrf)],
+            Some(&csrf),
+        )
+        .await;
+        assert_eq!(code, 400);
+
+        let (code, _, _) = send(
+            "POST",
+            "/",
+            &[("rwf_session", &session), (CSRF_COOKIE, &rotated)],
+            Some(&rotated),
+        )
+        .await;
+        assert_eq!(code, 200);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_attributes() {
+        let api = Api {
+            middleware: MiddlewareSet::without_default(vec![Csrf::new().middleware()]),
+        };
+        let request = Request::read(
+            "127.0.0.1:1234".parse().unwrap(),
+            &b"GET / HTTP/1.1\r\n\r\n"[..],
+        )
+        .await
+        .unwrap();
+        let mut response = api.handle_internal(request).await.unwrap();
+        let cookie = response.cookies().get_pending(CSRF_COOKIE).unwrap();
+
+        assert!(!cookie.http_only());
+        assert_eq!(cookie.same_site(), Some("Strict"));
+        assert_eq!(cookie.path(), Some("/"));
+    }
 }
@@ -0,0 +1,217 @@
+//! Post-process HTML responses: minify them and inline critical CSS.
+//!
+//! Available with the `html-pipeline` feature. Like all middleware, the pipeline can be enabled for
+//! some controllers, or for a group of routes with [`Handler::with_middleware`](crate::http::Handler::with_middleware):
+//!
+//! ```rust,ignore
+//! let pipeline = MiddlewareSet::without_default(vec![
+//!     HtmlPipeline::new().critical_css("static/critical.css").middleware(),
+//! ]);
+//!
+//! let routes = vec![
+//!     route!("/" => Index),
+//!     route!("/about" => About),
+//! ]
+//! .into_iter()
+//! .map(|route| route.with_middleware(pipeline.clone()))
+//! .collect::<Vec<_>>();
+//! ```
+//!
+//! Only `text/html` responses smaller than [`HTML_REWRITER_MAX_SIZE`] are changed. Turbo Streams, files,
+//! and compressed responses are sent unchanged.
+use crate::config::get_config;
+use crate::controller::middleware::prelude::*;
+use crate::http::HTML_REWRITER_MAX_SIZE;
+use crate::view::minify::{inline_critical_css, minify_html};
+
+use once_cell::sync::OnceCell;
+use std::path::PathBuf;
+use tracing::warn;
+
+struct CriticalCss {
+    path: PathBuf,
+    cached: OnceCell<String>,
+}
+
+impl CriticalCss {
+    /// Read the file. It's cached if templates are cached too, so changes are visible during development.
+    fn css(&self) -> Option<String> {
+        let read = || match std::fs::read_to_string(&self.path) {
+            Ok(css) => Some(css),
+            Err(err) => {
+                warn!(
+                    "critical css \"{}\" can't be read: {}",
+                    self.path.display(),
+                    err
+                );
+                None
+            }
+        };
+
+        if get_config().general.cache_templates {
+            self.cached
+                .get_or_try_init(|| read().ok_or(()))
+                .ok()
+                .cloned()
+        } else {
+            read()
+        }
+    }
+}
+
+/// HTML post-processing middleware.
+pub struct HtmlPipeline {
+    minify: bool,
+    critical_css: Option<CriticalCss>,
+    max_size: usize,
+}
+
+impl Default for HtmlPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HtmlPipeline {
+    /// Minify HTML responses.
+    pub fn new() -> Self {
+        Self {
+            minify: true,
+            critical_css: None,
+            max_size: HTML_REWRITER_MAX_SIZE,
+        }
+    }
+
+    /// Don't minify responses.
+    pub fn without_minify(mut self) -> Self {
+        self.minify = false;
+        self
+    }
+
+    /// Inline the CSS from this file into `<head>`, and load the page's stylesheets asynchronously.
+    pub fn critical_css(mut self, path: impl Into<PathBuf>) -> Self {
+        self.critical_css = Some(CriticalCss {
+            path: path.into(),
+            cached: OnceCell::new(),
+        });
+        self
+    }
+
+    /// Largest response, in bytes, which is processed. Default is [`HTML_REWRITER_MAX_SIZE`].
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+}
+
+#[async_trait]
+impl Middleware for HtmlPipeline {
+    async fn handle_request(&self, request: Request) -> Result<Outcome, Error> {
+        Ok(Outcome::Forward(request))
+    }
+
+    async fn handle_response(
+        &self,
+        request: &Request,
+        response: Response,
+    ) -> Result<Response, Error> {
+        let css = self.critical_css.as_ref().and_then(|css| css.css());
+
+        Ok(response.rewrite_html(self.max_size, |html| {
+            if let Some(ref css) = css {
+                *html = inline_critical_css(html, css, request.nonce().get());
+            }
+
+            if self.minify {
+                *html = minify_html(html);
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{Controller, MiddlewareSet};
+    use crate::view::TurboStream;
+    use std::io::Write;
+
+    struct Page {
+        middleware: MiddlewareSet,
+    }
+
+    #[async_trait]
+    impl Controller for Page {
+        fn middleware(&self) -> &MiddlewareSet {
+            &self.middleware
+        }
+
+        async fn handle(&self, request: &Request) -> Result<Response, Error> {
+            let html = "<html>\n<head>\n  <link rel=\"stylesheet\" href=\"/app.css\">\n</head>\n<body>\n  <p>Hi</p>\n</body>\n</html>\n";
+
+            Ok(match request.path().path() {
+                "/text" => Response::new().text(html),
+                "/turbo" => Response::new()
+                    .turbo_stream(&[TurboStream::new(html).action("replace").target("p")]),
+                _ => Response::new().html(html),
+            })
+        }
+    }
+
+    async fn get(pipeline: HtmlPipeline, path: &str) -> Response {
+        let page = Page {
+            middleware: MiddlewareSet::without_default(vec![pipeline.middleware()]),
+        };
+        let head = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        let request = Request::read("127.0.0.1:1234".parse().unwrap(), head.as_bytes())
+            .await
+            .unwrap();
+
+        page.handle_internal(request).await.unwrap()
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8(response.body_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_html_pipeline() {
+        let response = get(HtmlPipeline::new(), "/").await;
+        assert_eq!(
+            body(&response),
+            r#"<html><head><link rel="stylesheet" href="/app.css"></head><body><p>Hi</p></body></html>"#
+        );
+        assert_eq!(
+            response.headers().get("content-length").unwrap(),
+            &body(&response).len().to_string()
+        );
+
+        let mut file = tempfile();
+        file.1.write_all(b"p { color: red; }").unwrap();
+        let response = get(
+            HtmlPipeline::new().critical_css(file.0.path().join("critical.css")),
+            "/",
+        )
+        .await;
+        assert_eq!(
+            body(&response),
+            r#"<html><head><link rel="preload" as="style" onload="this.onload=null;this.rel='stylesheet'" href="/app.css"><noscript><link rel="stylesheet" href="/app.css"></noscript><style>p { color: red; }</style></head><body><p>Hi</p></body></html>"#
+        );
+
+        // Not HTML, or too large.
+        for (pipeline, path) in [
+            (HtmlPipeline::new(), "/text"),
+            (HtmlPipeline::new(), "/turbo"),
+            (HtmlPipeline::new().max_size(10), "/"),
+        ] {
+            let response = get(pipeline, path).await;
+            assert!(body(&response).contains("<body>\n  <p>Hi</p>"), "{}", path);
+        }
+    }
+
+    fn tempfile() -> (tempdir::TempDir, std::fs::File) {
+        let dir = tempdir::TempDir::new("html_pipeline").unwrap();
+        let file = std::fs::File::create(dir.path().join("critical.css")).unwrap();
+        (dir, file)
+    }
+}
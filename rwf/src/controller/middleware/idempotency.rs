@@ -0,0 +1,675 @@
+//! Idempotent `POST` requests.
+//!
+//! Clients that retry requests, e.g. after a network error, can send the `Idempotency-Key` header with a unique value
+//! (like a UUID). The first request with that key is passed to the controller and its response is saved in the
+//! `rwf_idempotency_keys` table. Retries with the same key receive the saved response instead of executing the request
+//! again, so payments and other sensitive operations are not performed twice.
+//!
+//! Keys belong to the user who sent them: the authenticated user, the guest session or, for clients
+//! without a session, the IP address. The same key sent by someone else is a different key. Cookies are
+//! never saved with the response.
+//!
+//! Retries must be identical to the original request. If the method, path or body are different,
+//! the request is rejected with `422 - Unprocessable Entity`. If the original request is still being processed,
+//! the retry is rejected with `409 - Conflict`, or, if configured with [`Idempotency::wait`], waits for it to finish.
+//!
+//! Responses with a `5xx` status code are not saved, so the client can retry the request. The key is also
+//! released if the controller panics.
+//!
+//! ```
+//! use rwf::controller::middleware::Idempotency;
+//! use rwf::prelude::*;
+//!
+//! let idempotency = Idempotency::new()
+//!     .expires_in(Duration::hours(24))
+//!     .wait(Duration::seconds(5));
+//! ```
+//!
+//! Expired keys are removed by the [`IdempotencyCleanup`] job, which should be scheduled to run periodically
+//! by the jobs worker clock.
+use base64::{engine::general_purpose, Engine as _};
+use sha1::{Digest, Sha1};
+use time::{Duration, OffsetDateTime};
+use tokio::time::{sleep, Instant};
+use tracing::error;
+
+use super::prelude::*;
+use crate::controller::SessionId;
+use crate::http::Method;
+use crate::job::{Error as JobError, Job, JobContext};
+use crate::model::{Error as ModelError, FromRow, GetColumn, Model, Pool, ToValue, Value};
+
+static HEADER: &str = "idempotency-key";
+
+/// Saved response for an idempotency key.
+#[derive(Clone, Debug)]
+pub struct IdempotencyKey {
+    id: Option<i64>,
+    /// The key sent by the client, prefixed with a hash of the user, session or IP address it belongs to.
+    pub key: String,
+    /// Hash of the request method, path and body.
+    pub fingerprint: String,
+    /// Response status code. `None` while the request is in flight.
+    pub code: Option<i32>,
+    /// Response headers.
+    pub headers: serde_json::Value,
+    /// Response body, base64-encoded.
+    pub body: Option<String>,
+    /// When the key was first used.
+    pub created_at: OffsetDateTime,
+    /// When the key can be reused.
+    pub expires_at: OffsetDateTime,
+}
+
+impl FromRow for IdempotencyKey {
+    fn from_row(row: tokio_postgres::Row) -> Result<Self, ModelError> {
+        Ok(Self {
+            id: row.get_column("id")?,
+            key: row.get_column("key")?,
+            fingerprint: row.get_column("fingerprint")?,
+            code: row.get_column("code")?,
+            headers: row.get_column("headers")?,
+            body: row.get_column("body")?,
+            created_at: row.get_column("created_at")?,
+            expires_at: row.get_column("expires_at")?,
+        })
+    }
+}
+
+impl Model for IdempotencyKey {
+    fn id(&self) -> Value {
+        self.id.to_value()
+    }
+
+    fn table_name() -> &'static str {
+        "rwf_idempotency_keys"
+    }
+
+    fn foreign_key() -> &'static str {
+        "rwf_idempotency_key_id"
+    }
+
+    fn column_names() -> &'static [&'static str] {
+        &[
+            "key",
+            "fingerprint",
+            "code",
+            "headers",
+            "body",
+            "created_at",
+            "expires_at",
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.key.to_value(),
+            self.fingerprint.to_value(),
+            self.code.to_value(),
+            self.headers.to_value(),
+            self.body.to_value(),
+            self.created_at.to_value(),
+            self.expires_at.to_value(),
+        ]
+    }
+}
+
+impl IdempotencyKey {
+    /// Claim the key for a new request. Returns `None` if the key is already
+    /// in use and hasn't expired.
+    async fn claim(
+        key: &str,
+        fingerprint: &str,
+        expires_in: Duration,
+    ) -> Result<Option<Self>, ModelError> {
+        let mut conn = Pool::connection().await?;
+
+        Self::find_by_sql(
+            "INSERT INTO rwf_idempotency_keys (key, fingerprint, expires_at)
+            VALUES ($1, $2, NOW() + make_interval(secs => $3::double precision))
+            ON CONFLICT (key) DO UPDATE SET
+                fingerprint = EXCLUDED.fingerprint,
+                code = NULL,
+                headers = '{}'::jsonb,
+                body = NULL,
+                created_at = NOW(),
+                expires_at = EXCLUDED.expires_at
+            WHERE rwf_idempotency_keys.expires_at < NOW()
+            RETURNING *",
+            &[
+                key.to_value(),
+                fingerprint.to_value(),
+                expires_in.as_seconds_f64().to_value(),
+            ],
+        )
+        .fetch_optional(&mut conn)
+        .await
+    }
+
+    async fn find(key: &str) -> Result<Option<Self>, ModelError> {
+        let mut conn = Pool::connection().await?;
+        Self::filter("key", key).fetch_optional(&mut conn).await
+    }
+
+    async fn release(key: &str, fingerprint: &str) -> Result<(), ModelError> {
+        let mut conn = Pool::connection().await?;
+        Self::find_by_sql(
+            "DELETE FROM rwf_idempotency_keys WHERE key = $1 AND fingerprint = $2 AND code IS NULL",
+            &[key.to_value(), fingerprint.to_value()],
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn complete(
+        key: &str,
+        fingerprint: &str,
+        response: &Response,
+        body: &[u8],
+    ) -> Result<(), ModelError> {
+        // Cookies are for the client that made the request.
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case("set-cookie"))
+            .map(|(name, value)| (name.clone(), serde_json::Value::String(value.clone())))
+            .collect::<serde_json::Map<_, _>>();
+
+        let mut conn = Pool::connection().await?;
+        Self::find_by_sql(
+            "UPDATE rwf_idempotency_keys SET code = $3, headers = $4, body = $5
+            WHERE key = $1 AND fingerprint = $2 AND code IS NULL",
+            &[
+                key.to_value(),
+                fingerprint.to_value(),
+                (response.status().code() as i32).to_value(),
+                serde_json::Value::Object(headers).to_value(),
+                general_purpose::STANDARD.encode(body).to_value(),
+            ],
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Recreate the saved response.
+    fn response(&self) -> Response {
+        let body = self
+            .body
+            .as_ref()
+            .and_then(|body| general_purpose::STANDARD.decode(body).ok())
+            .unwrap_or_default();
+
+        let mut response = Response::new()
+            .body(body)
+            .code(self.code.unwrap_or(200) as u16);
+
+        if let serde_json::Value::Object(ref headers) = self.headers {
+            for (name, value) in headers {
+                if let Some(value) = value.as_str() {
+                    response = response.header(name, value);
+                }
+            }
+        }
+
+        response.header("idempotent-replayed", "true")
+    }
+}
+
+/// Key claimed by a request in flight. Released if the request is dropped
+/// before its response is saved, e.g. because the controller panicked.
+struct Claim {
+    key: String,
+    fingerprint: String,
+    done: bool,
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let key = std::mem::take(&mut self.key);
+            let fingerprint = std::mem::take(&mut self.fingerprint);
+
+            runtime.spawn(async move {
+                if let Err(err) = IdempotencyKey::release(&key, &fingerprint).await {
+                    error!("failed to release idempotency key: {}", err);
+                }
+            });
+        }
+    }
+}
+
+/// Idempotency middleware.
+pub struct Idempotency {
+    expires_in: Duration,
+    wait: Option<Duration>,
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Idempotency {
+    /// Create idempotency middleware. Keys expire after 24 hours and concurrent
+    /// requests with the same key are rejected.
+    pub fn new() -> Self {
+        Self {
+            expires_in: Duration::hours(24),
+            wait: None,
+        }
+    }
+
+    /// How long the saved response is returned for retries.
+    pub fn expires_in(mut self, expires_in: Duration) -> Self {
+        self.expires_in = expires_in;
+        self
+    }
+
+    /// Instead of rejecting a retry while the original request is still in flight,
+    /// wait up to this long for it to finish.
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.wait = Some(wait);
+        self
+    }
+
+    /// Get the idempotency key and fingerprint, if the request has one.
+    fn key(request: &Request) -> Option<(String, String)> {
+        if request.method() != &Method::Post {
+            return None;
+        }
+
+        let key = request.header(HEADER)?.trim();
+
+        if key.is_empty() {
+            return None;
+        }
+
+        let owner = match request.session_id() {
+            Some(SessionId::Authenticated(id)) => format!("user:{}", id),
+            Some(SessionId::Guest(id)) => format!("session:{}", id),
+            None => format!("ip:{}", request.client_ip()),
+        };
+        let owner = hex(&Sha1::digest(owner.as_bytes()));
+
+        let mut hasher = Sha1::new();
+        hasher.update(request.method().to_string().as_bytes());
+        hasher.update(b" ");
+        hasher.update(request.path().base().as_bytes());
+        hasher.update(b"\n");
+        hasher.update(request.body());
+        let fingerprint = hex(&hasher.finalize());
+
+        Some((format!("{}:{}", owner, key), fingerprint))
+    }
+}
+
+#[async_trait]
+impl Middleware for Idempotency {
+    async fn handle_request(&self, request: Request) -> Result<Outcome, Error> {
+        let (key, fingerprint) = match Self::key(&request) {
+            Some(key) => key,
+            None => return Ok(Outcome::Forward(request)),
+        };
+
+        let started_at = Instant::now();
+        let wait = self.wait.map(|wait| wait.unsigned_abs());
+
+        loop {
+            if IdempotencyKey::claim(&key, &fingerprint, self.expires_in)
+                .await?
+                .is_some()
+            {
+                request.guard(Claim {
+                    key,
+                    fingerprint,
+                    done: false,
+                });
+                return Ok(Outcome::Forward(request));
+            }
+
+            let existing = match IdempotencyKey::find(&key).await? {
+                Some(existing) => existing,
+                // Original request failed and released the key just now.
+                None => continue,
+            };
+
+            if existing.fingerprint != fingerprint {
+                let response = Response::new()
+                    .code(422)
+                    .text("Idempotency-Key was already used for a different request");
+                return Ok(Outcome::Stop(request, response));
+            }
+
+            if existing.code.is_some() {
+                return Ok(Outcome::Stop(request, existing.response()));
+            }
+
+            match wait {
+                Some(wait) if started_at.elapsed() < wait => {
+                    sleep(std::time::Duration::from_millis(50)).await
+                }
+                _ => {
+                    let response = Response::new()
+                        .code(409)
+                        .text("A request with this Idempotency-Key is in progress");
+                    return Ok(Outcome::Stop(request, response));
+                }
+            }
+        }
+    }
+
+    async fn handle_response(
+        &self,
+        request: &Request,
+        response: Response,
+    ) -> Result<Response, Error> {
+        let mut claim = match request.take_guard::<Claim>() {
+            Some(claim) => claim,
+            None => return Ok(response),
+        };
+        let (key, fingerprint) = (&claim.key, &claim.fingerprint);
+
+        match response.body_bytes() {
+            // Pages with the request's nonce can't be replayed, since the nonce
+            // must be different for each response.
+            Some(body) if contains_nonce(request, body) => {
+                IdempotencyKey::release(key, fingerprint).await?
+            }
+
+            Some(body) if response.status().code() < 500 => {
+                IdempotencyKey::complete(key, fingerprint, &response, body).await?
+            }
+
+            // Let the client retry server errors. Files aren't saved,
+            // so they are executed every time.
+            _ => IdempotencyKey::release(key, fingerprint).await?,
+        }
+
+        claim.done = true;
+
+        Ok(response)
+    }
+}
+
+/// Remove expired idempotency keys. Schedule it with the jobs clock, e.g.:
+///
+/// ```
+/// # use rwf::prelude::*;
+/// use rwf::controller::middleware::idempotency::IdempotencyCleanup;
+///
+/// let cleanup = IdempotencyCleanup
+///     .schedule(serde_json::Value::Null, "0 * * * *")
+///     .unwrap();
+/// ```
+#[derive(Default, Debug, Clone)]
+pub struct IdempotencyCleanup;
+
+#[async_trait]
+impl Job for IdempotencyCleanup {
+    async fn execute(
+        &self,
+        context: &JobContext,
+        _args: serde_json::Value,
+    ) -> Result<(), JobError> {
+        let mut conn = context.pool().get().await?;
+        IdempotencyKey::find_by_sql(
+            "DELETE FROM rwf_idempotency_keys WHERE expires_at < NOW()",
+            &[],
+        )
+        .execute(&mut conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+fn contains_nonce(request: &Request, body: &[u8]) -> bool {
+    match request.nonce().get() {
+        Some(nonce) => body
+            .windows(nonce.len())
+            .any(|window| window == nonce.as_bytes()),
+        None => false,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{Controller, MiddlewareSet};
+    use crate::http::Request;
+    use crate::model::migrations;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Payments {
+        middleware: MiddlewareSet,
+        charges: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Controller for Payments {
+        async fn handle(&self, request: &Request) -> Result<Response, Error> {
+            sleep(std::time::Duration::from_millis(200)).await;
+            let charge = self.charges.fetch_add(1, Ordering::SeqCst) + 1;
+
+            if request.body() == b"fail" {
+                return Ok(Response::internal_error(std::io::Error::other("declined")));
+            }
+
+            if request.body() == b"panic" && charge == 1 {
+                panic!("lost connection to the payment processor");
+            }
+
+            Ok(Response::new()
+                .code(201)
+                .header("set-cookie", format!("last_charge={}", charge))
+                .json(serde_json::json!({ "charge": charge }))?)
+        }
+
+        fn middleware(&self) -> &MiddlewareSet {
+            &self.middleware
+        }
+
+        fn skip_csrf(&self) -> bool {
+            true
+        }
+    }
+
+    impl Payments {
+        fn new(idempotency: Idempotency) -> Arc<Self> {
+            Arc::new(Self {
+                middleware: MiddlewareSet::without_default(vec![idempotency.middleware()]),
+                charges: Arc::new(AtomicUsize::new(0)),
+            })
+        }
+
+        fn charges(&self) -> usize {
+            self.charges.load(Ordering::SeqCst)
+        }
+    }
+
+    async fn bootstrap() -> Result<(), ModelError> {
+        let mut transaction = Pool::begin().await?;
+        migrations::bootstrap(&mut transaction).await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn post(key: &str, body: &str) -> Request {
+        post_from("127.0.0.1:1234", key, body).await
+    }
+
+    async fn post_from(peer: &str, key: &str, body: &str) -> Request {
+        let request = format!(
+            "POST /payments HTTP/1.1\r\nIdempotency-Key: {}\r\nContent-Length: {}\r\n\r\n{}",
+            key,
+            body.len(),
+            body
+        );
+        Request::read(peer.parse().unwrap(), request.as_bytes())
+            .await
+            .unwrap()
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8(response.body_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    // The global pool is bound to the runtime of the test that created it,
+    // so all scenarios run in one test.
+    #[tokio::test]
+    async fn test_idempotency() -> Result<(), Error> {
+        bootstrap().await?;
+
+        replay().await?;
+        keys_are_scoped().await?;
+        server_errors_are_not_saved().await?;
+        panic_releases_key().await?;
+        concurrent_duplicate_conflict().await?;
+        concurrent_duplicate_wait().await?;
+
+        Ok(())
+    }
+
+    async fn replay() -> Result<(), Error> {
+        let controller = Payments::new(Idempotency::new());
+        let key = uuid::Uuid::new_v4().to_string();
+
+        let first = controller
+            .handle_internal(post(&key, r#"{"amount": 5}"#).await)
+            .await?;
+        assert_eq!(first.status().code(), 201);
+
+        let retry = controller
+            .handle_internal(post(&key, r#"{"amount": 5}"#).await)
+            .await?;
+        assert_eq!(retry.status().code(), 201);
+        assert_eq!(body(&retry), body(&first));
+        assert_eq!(retry.headers().get("idempotent-replayed").unwrap(), "true");
+        assert!(retry.headers().get("set-cookie").is_none());
+        assert_eq!(controller.charges(), 1);
+
+        // Same key, different request.
+        let mismatch = controller
+            .handle_internal(post(&key, r#"{"amount": 500}"#).await)
+            .await?;
+        assert_eq!(mismatch.status().code(), 422);
+        assert_eq!(controller.charges(), 1);
+
+        Ok(())
+    }
+
+    async fn keys_are_scoped() -> Result<(), Error> {
+        let controller = Payments::new(Idempotency::new());
+        let key = uuid::Uuid::new_v4().to_string();
+
+        let first = controller
+            .handle_internal(post_from("127.0.0.1:1234", &key, "{}").await)
+            .await?;
+        let other = controller
+            .handle_internal(post_from("127.0.0.2:1234", &key, "{}").await)
+            .await?;
+
+        assert_eq!(other.status().code(), 201);
+        assert!(other.headers().get("idempotent-replayed").is_none());
+        assert_ne!(body(&other), body(&first));
+        assert_eq!(controller.charges(), 2);
+
+        Ok(())
+    }
+
+    async fn panic_releases_key() -> Result<(), Error> {
+        let controller = Payments::new(Idempotency::new());
+        let key = uuid::Uuid::new_v4().to_string();
+
+        let request = post(&key, "panic").await;
+        let panicked = {
+            let controller = controller.clone();
+            tokio::spawn(async move { controller.handle_internal(request).await })
+        };
+        assert!(panicked.await.unwrap_err().is_panic());
+
+        // The key is released in the background.
+        let started_at = Instant::now();
+        let retry = loop {
+            let retry = controller
+                .handle_internal(post(&key, "panic").await)
+                .await?;
+            if retry.status().code() != 409 || started_at.elapsed().as_secs() > 5 {
+                break retry;
+            }
+            sleep(std::time::Duration::from_millis(50)).await;
+        };
+
+        assert_eq!(retry.status().code(), 201);
+        assert_eq!(controller.charges(), 2);
+
+        Ok(())
+    }
+
+    async fn server_errors_are_not_saved() -> Result<(), Error> {
+        let controller = Payments::new(Idempotency::new());
+        let key = uuid::Uuid::new_v4().to_string();
+
+        for _ in 0..2 {
+            let response = controller.handle_internal(post(&key, "fail").await).await?;
+            assert_eq!(response.status().code(), 500);
+        }
+
+        assert_eq!(controller.charges(), 2);
+
+        Ok(())
+    }
+
+    async fn concurrent_duplicate_conflict() -> Result<(), Error> {
+        let controller = Payments::new(Idempotency::new());
+        let key = uuid::Uuid::new_v4().to_string();
+
+        let (first, second) = tokio::join!(
+            controller.handle_internal(post(&key, "{}").await),
+            controller.handle_internal(post(&key, "{}").await),
+        );
+
+        let mut codes = vec![first?.status().code(), second?.status().code()];
+        codes.sort();
+
+        assert_eq!(codes, vec![201, 409]);
+        assert_eq!(controller.charges(), 1);
+
+        Ok(())
+    }
+
+    async fn concurrent_duplicate_wait() -> Result<(), Error> {
+        let controller = Payments::new(Idempotency::new().wait(Duration::seconds(5)));
+        let key = uuid::Uuid::new_v4().to_string();
+
+        let (first, second) = tokio::join!(
+            controller.handle_internal(post(&key, "{}").await),
+            controller.handle_internal(post(&key, "{}").await),
+        );
+        let (first, second) = (first?, second?);
+
+        assert_eq!(first.status().code(), 201);
+        assert_eq!(second.status().code(), 201);
+        assert_eq!(body(&first), body(&second));
+        assert_eq!(controller.charges(), 1);
+
+        Ok(())
+    }
+}
@@ -26,8 +26,21 @@ pub mod prelude;
 pub mod secure_id;
 pub use secure_id::SecureId;
 
+pub mod csp;
 pub mod csrf;
+#[cfg(feature = "html-pipeline")]
+pub mod html_pipeline;
+pub mod idempotency;
+pub mod replay_guard;
 pub mod request_tracker;
+pub mod signed_url;
+
+pub use csp::ContentSecurityPolicy;
+#[cfg(feature = "html-pipeline")]
+pub use html_pipeline::HtmlPipeline;
+pub use idempotency::Idempotency;
+pub use replay_guard::ReplayGuard;
+pub use signed_url::VerifySignedUrl;
 
 /// The result of middleware processing a request.
 pub enum Outcome {
@@ -79,6 +92,11 @@ impl MiddlewareHandler {
         }
     }
 
+    /// Name of the middleware, e.g. `rwf::controller::middleware::RateLimiter`.
+    pub fn name(&self) -> &'static str {
+        self.middleware.deref().middleware_name()
+    }
+
     async fn handle_request(&self, request: Request) -> Result<Outcome, Error> {
         debug!(
             "{} {} => {}",
@@ -0,0 +1,68 @@
+//! Rate limit counters stored in the embedded [key-value store](crate::kv).
+//!
+//! Requires the `kv` feature. Counters survive restarts, but aren't shared between app instances
+//! running on different machines.
+use async_trait::async_trait;
+
+use std::time::Duration;
+
+use crate::kv::KvStore;
+
+use super::store::{millis, now, sliding_count, Error, RateLimitStore};
+
+#[async_trait]
+impl RateLimitStore for KvStore {
+    async fn incr(&self, key: &str, window: Duration) -> Result<u64, Error> {
+        let window = millis(window);
+        let now = now();
+        let index = now / window;
+        let mut count = 0;
+
+        // Window index, requests in the current window and requests in the previous one.
+        self.update(
+            &format!("rwf:rate:{}", key),
+            Some(Duration::from_millis(window * 2)),
+            |value| {
+                let counter = value
+                    .filter(|value| value.len() == 24)
+                    .map(|value| {
+                        let field = |i: usize| {
+                            u64::from_le_bytes(value[i * 8..(i + 1) * 8].try_into().unwrap())
+                        };
+                        (field(0), field(1), field(2))
+                    })
+                    .unwrap_or_default();
+
+                let (current, previous) = match counter {
+                    (window, current, previous) if window == index => (current, previous),
+                    (window, current, _) if window + 1 == index => (0, current),
+                    _ => (0, 0),
+                };
+                let current = current + 1;
+                count = sliding_count(current, previous, now % window, window);
+
+                [index, current, previous]
+                    .iter()
+                    .flat_map(|field| field.to_le_bytes())
+                    .collect()
+            },
+        )?;
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::store::conformance;
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_kv_store() {
+        let dir = tempdir::TempDir::new("kv").unwrap();
+        let store = KvStore::open(dir.path().join("rwf.kv")).unwrap();
+
+        conformance(Arc::new(store)).await;
+    }
+}
@@ -0,0 +1,250 @@
+//! Limit how many requests our clients can perform per unit of time.
+//!
+//! Clients that exceed those limits will have their requests rejected with HTTP `429 - Too Many`.
+//! Requests are counted in a sliding window: the count in the current window is added to the count in the
+//! previous one, weighted by how much of the previous window the sliding window still covers. This avoids
+//! letting twice the limit through around the time a fixed window would reset, see [`RateLimitStore`].
+//!
+//! Clients are bucketed per IP. Behind a proxy, enable the `trust_proxy` setting so the client IP is read from the
+//! `X-Forwarded-For` or `Forwarded` header, see [`Request::client_ip`](crate::http::Request::client_ip). Each response has the `X-Rwf-Request-Rate` header set with the current requests per unit of time,
+//! which could help clients self-throttle their request rate.
+//!
+//! Counters are kept in memory by default. Apps running several instances can share them with the
+//! other instances by using a different [`RateLimitStore`], configured with the `store` setting in the
+//! `[rate_limit]` section. If the store fails, requests are allowed through and the failure is logged.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tracing::warn;
+
+use super::{
+    super::{Error, Problem, Request, Response},
+    Middleware, Outcome,
+};
+use async_trait::async_trait;
+
+#[cfg(feature = "kv")]
+pub mod kv;
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod store;
+
+pub use postgres::PostgresStore;
+#[cfg(feature = "redis")]
+pub use redis::RedisStore;
+pub use store::{Backend, MemoryStore, RateLimitStore};
+
+static STORE_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// How many requests were allowed because the rate limit store failed, since the server started.
+pub fn store_failures() -> u64 {
+    STORE_FAILURES.load(Ordering::Relaxed)
+}
+
+enum Frequency {
+    Minute(u64),
+    Second(u64),
+    Hour(u64),
+    Day(u64),
+}
+
+impl Frequency {
+    pub fn limit(&self) -> u64 {
+        use Frequency::*;
+
+        match self {
+            Minute(limit) => *limit,
+            Second(limit) => *limit,
+            Hour(limit) => *limit,
+            Day(limit) => *limit,
+        }
+    }
+
+    fn window(&self) -> Duration {
+        use Frequency::*;
+
+        match self {
+            Second(_) => Duration::from_secs(1),
+            Minute(_) => Duration::from_secs(60),
+            Hour(_) => Duration::from_secs(3600),
+            Day(_) => Duration::from_secs(3600 * 24),
+        }
+    }
+}
+
+/// Simple rate limiter.
+pub struct RateLimiter {
+    frequency: Frequency,
+    store: Arc<dyn RateLimitStore>,
+    rates: Mutex<HashMap<IpAddr, f32>>,
+}
+
+impl RateLimiter {
+    /// New rate limiter with this many requests per second.
+    fn new(frequency: Frequency) -> Self {
+        Self {
+            frequency,
+            store: store::from_config(),
+            rates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create rate limiter with this limit of requests per second.
+    pub fn per_second(limit: u64) -> Self {
+        Self::new(Frequency::Second(limit))
+    }
+
+    /// Create rate limiter with this limit of requests per minute.
+    pub fn per_minute(limit: u64) -> Self {
+        Self::new(Frequency::Minute(limit))
+    }
+
+    /// Create rate limiter with this limit of requests per hour. There is no advanced warning
+    /// for clients that reach this limit quickly. If they spend all their requests in the first minute of the hour,
+    /// they will be blocked for sending any more for the remainer of the hour.
+    pub fn per_hour(limit: u64) -> Self {
+        Self::new(Frequency::Hour(limit))
+    }
+
+    /// Create rate limiter with this limit of requests per day. There is no advanced warning
+    /// for clients that reach this limit quickly. If they spend all their requests in the first hour of the day,
+    /// they will be blocked for sending any more for the remainer of the day.
+    pub fn per_day(limit: u64) -> Self {
+        Self::new(Frequency::Day(limit))
+    }
+
+    /// Keep the counters in this store instead of the one configured in the `[rate_limit]` section.
+    pub fn store(mut self, store: impl RateLimitStore + 'static) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    fn key(&self, peer: &IpAddr) -> String {
+        format!(
+            "{}:{}:{}",
+            self.frequency.window().as_secs(),
+            self.frequency.limit(),
+            peer
+        )
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimiter {
+    async fn handle_request(&self, request: Request) -> Result<Outcome, Error> {
+        let peer = request.client_ip();
+        let window = self.frequency.window();
+
+        let count = match self.store.incr(&self.key(&peer), window).await {
+            Ok(count) => count,
+            Err(err) => {
+                STORE_FAILURES.fetch_add(1, Ordering::Relaxed);
+                warn!(error = %err, "rate limit store failed, allowing request");
+                return Ok(Outcome::Forward(request));
+            }
+        };
+
+        self.rates
+            .lock()
+            .insert(peer, count as f32 / window.as_secs_f32());
+
+        if count > self.frequency.limit() {
+            let response = if Problem::accepted(&request) {
+                let retry_after = window.as_secs();
+
+                Problem::new(429, "Too Many Requests")
+                    .detail(format!(
+                        "Request limit of {} per {} seconds exceeded.",
+                        self.frequency.limit(),
+                        retry_after
+                    ))
+                    .instance(request.path().path())
+                    .extension("retry_after", retry_after)
+                    .response()
+                    .header("retry-after", retry_after)
+            } else {
+                Response::too_many()
+            };
+
+            Ok(Outcome::Stop(request, response))
+        } else {
+            Ok(Outcome::Forward(request))
+        }
+    }
+
+    async fn handle_response(
+        &self,
+        request: &Request,
+        response: Response,
+    ) -> Result<Response, Error> {
+        if let Some(rate) = self.rates.lock().get(&request.client_ip()).copied() {
+            Ok(response.header("x-rwf-request-rate", rate.to_string()))
+        } else {
+            Ok(response)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::request::test::dummy_ip;
+
+    struct Broken;
+
+    #[async_trait]
+    impl RateLimitStore for Broken {
+        async fn incr(&self, _key: &str, _window: Duration) -> Result<u64, store::Error> {
+            Err(crate::model::Error::PoolTimeout.into())
+        }
+    }
+
+    async fn request() -> Request {
+        Request::read(dummy_ip(), &b"GET / HTTP/1.1\r\n\r\n"[..])
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter() {
+        let limiter = RateLimiter::per_minute(2).store(MemoryStore::default());
+
+        for _ in 0..2 {
+            assert!(matches!(
+                limiter.handle_request(request().await).await.unwrap(),
+                Outcome::Forward(_)
+            ));
+        }
+
+        match limiter.handle_request(request().await).await.unwrap() {
+            Outcome::Stop(_, response) => assert_eq!(response.status().code(), 429),
+            _ => panic!("expected 429"),
+        }
+
+        let response = limiter
+            .handle_response(&request().await, Response::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers().get("x-rwf-request-rate").unwrap(),
+            "0.05"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fail_open() {
+        let limiter = RateLimiter::per_second(0).store(Broken);
+        let before = store_failures();
+
+        assert!(matches!(
+            limiter.handle_request(request().await).await.unwrap(),
+            Outcome::Forward(_)
+        ));
+        assert!(store_failures() > before);
+    }
+}
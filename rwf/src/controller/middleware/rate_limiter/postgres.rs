@@ -0,0 +1,108 @@
+//! Rate limit counters stored in Postgres.
+//!
+//! Each request is one UPSERT into the `rwf_rate_limits` table, so this store is best suited for apps
+//! that don't get a lot of traffic. Timestamps come from the database, so the clocks of the app instances don't need to agree.
+use async_trait::async_trait;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::store::{millis, sliding_count, Error, RateLimitStore};
+use crate::model::Pool;
+
+/// Remove expired counters every this many requests.
+const CLEANUP_EVERY: u64 = 100;
+
+static INCR: &str = "WITH now AS (
+    SELECT (EXTRACT(EPOCH FROM clock_timestamp()) * 1000)::BIGINT AS ms
+), current AS (
+    INSERT INTO rwf_rate_limits (key, window_start, count, expires_at)
+    SELECT $1, ms / $2 * $2, 1, NOW() + make_interval(secs => $2::double precision / 500)
+    FROM now
+    ON CONFLICT (key, window_start) DO UPDATE SET count = rwf_rate_limits.count + 1
+    RETURNING count, window_start
+)
+SELECT
+    current.count,
+    COALESCE(previous.count, 0) AS previous,
+    (SELECT ms FROM now) - current.window_start AS elapsed
+FROM current
+LEFT JOIN rwf_rate_limits previous
+    ON previous.key = $1 AND previous.window_start = current.window_start - $2";
+
+/// Counters kept in the `rwf_rate_limits` table.
+#[derive(Default)]
+pub struct PostgresStore {
+    pool: Option<Pool>,
+    requests: AtomicU64,
+}
+
+impl PostgresStore {
+    /// Create store using the global connection pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use this connection pool instead of the global one.
+    pub fn pool(mut self, pool: Pool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn incr(&self, key: &str, window: Duration) -> Result<u64, Error> {
+        let window = millis(window) as i64;
+        let conn = match self.pool {
+            Some(ref pool) => pool.get().await?,
+            None => Pool::connection().await?,
+        };
+
+        if self
+            .requests
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(CLEANUP_EVERY)
+        {
+            conn.client()
+                .execute("DELETE FROM rwf_rate_limits WHERE expires_at < NOW()", &[])
+                .await
+                .map_err(crate::model::Error::from)?;
+        }
+
+        let row = conn
+            .client()
+            .query_one(INCR, &[&key, &window])
+            .await
+            .map_err(crate::model::Error::from)?;
+
+        let current: i64 = row.get("count");
+        let previous: i64 = row.get("previous");
+        let elapsed: i64 = row.get("elapsed");
+
+        Ok(sliding_count(
+            current as u64,
+            previous as u64,
+            elapsed as u64,
+            window as u64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::store::conformance;
+    use super::*;
+    use crate::model::migrations::bootstrap;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_postgres_store() {
+        let pool = Pool::from_env();
+        let mut transaction = pool.transaction().await.unwrap();
+        bootstrap(&mut transaction).await.unwrap();
+        transaction.commit().await.unwrap();
+
+        conformance(Arc::new(PostgresStore::new().pool(pool))).await;
+    }
+}
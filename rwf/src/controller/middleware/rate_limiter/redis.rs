@@ -0,0 +1,105 @@
+//! Rate limit counters stored in Redis.
+//!
+//! Requires the `redis` feature. Each request runs one Lua script, which increments the counter and reads
+//! the previous window atomically. Timestamps come from the Redis server, so the clocks of the app instances don't need to agree.
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use redis::aio::ConnectionManager;
+use redis::{Client, Script};
+use tokio::sync::OnceCell;
+
+use std::time::Duration;
+
+use super::store::{millis, Error, RateLimitStore};
+
+/// Counters for each window are kept in their own key, which expires after the next window.
+/// The keys share a hash tag, so they are in the same slot in Redis Cluster.
+static SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r"
+local window = tonumber(ARGV[1])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local index = math.floor(now / window)
+
+local current_key = KEYS[1] .. ':' .. index
+local current = redis.call('INCR', current_key)
+if current == 1 then
+    redis.call('PEXPIRE', current_key, window * 2)
+end
+
+local previous = tonumber(redis.call('GET', KEYS[1] .. ':' .. (index - 1)) or '0')
+local elapsed = now % window
+
+return current + math.floor(previous * (window - elapsed) / window)
+",
+    )
+});
+
+/// Counters kept in Redis.
+pub struct RedisStore {
+    url: String,
+    connection: OnceCell<ConnectionManager>,
+}
+
+impl RedisStore {
+    /// Create store connecting to Redis at this URL, e.g. `redis://127.0.0.1:6379`.
+    /// The connection is opened on first use and re-established automatically if it fails.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            connection: OnceCell::new(),
+        }
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager, Error> {
+        let connection = self
+            .connection
+            .get_or_try_init(|| async {
+                let client = Client::open(self.url.as_str())?;
+                ConnectionManager::new(client).await
+            })
+            .await?;
+
+        Ok(connection.clone())
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for RedisStore {
+    async fn incr(&self, key: &str, window: Duration) -> Result<u64, Error> {
+        let mut connection = self.connection().await?;
+
+        let count: u64 = SCRIPT
+            .key(format!("rwf:rate:{{{}}}", key))
+            .arg(millis(window))
+            .invoke_async(&mut connection)
+            .await?;
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::store::conformance;
+    use super::*;
+    use std::sync::Arc;
+
+    /// Runs only if `RWF_TEST_REDIS_URL` is set, e.g. to `redis://127.0.0.1:6379`.
+    #[tokio::test]
+    async fn test_redis_store() {
+        let url = match std::env::var("RWF_TEST_REDIS_URL") {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+
+        conformance(Arc::new(RedisStore::new(&url))).await;
+
+        let unreachable = RedisStore::new("redis://127.0.0.1:1");
+        assert!(unreachable
+            .incr("unreachable", Duration::from_secs(1))
+            .await
+            .is_err());
+    }
+}
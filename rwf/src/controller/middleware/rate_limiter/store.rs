@@ -0,0 +1,250 @@
+//! Where the rate limiter keeps its counters.
+//!
+//! The in-memory store is the default. It's fast, but each instance of the app counts requests separately,
+//! so the effective limit is multiplied by the number of instances. Apps running several instances should
+//! use a shared store: Redis (with the `redis` feature) or, for low-traffic apps, Postgres.
+//! Single-instance apps that want their counters to survive restarts without a database can use the
+//! embedded [key-value store](crate::kv) (with the `kv` feature).
+//!
+//! All stores count requests using a sliding window: the count in the current window is added to the count
+//! of the previous window, weighted by how much of the previous window still overlaps the sliding one.
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::get_config;
+
+#[cfg(feature = "kv")]
+use crate::kv::KvStore;
+
+use super::postgres::PostgresStore;
+#[cfg(feature = "redis")]
+use super::redis::RedisStore;
+
+/// Evict expired counters once the in-memory store holds this many.
+const MAX_MEMORY_KEYS: usize = 10_000;
+
+/// Rate limit store error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("database error: {0}")]
+    Database(#[from] crate::model::Error),
+
+    #[cfg(feature = "redis")]
+    #[error("redis error: {0}")]
+    Redis(#[from] ::redis::RedisError),
+
+    #[cfg(feature = "kv")]
+    #[error("kv store error: {0}")]
+    Kv(#[from] crate::kv::Error),
+}
+
+/// Storage for rate limit counters.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Count a request for the key and return the number of requests made
+    /// in the sliding window ending now, including this one.
+    ///
+    /// Must be atomic: concurrent calls for the same key, from any instance of the app,
+    /// each see a different count.
+    async fn incr(&self, key: &str, window: Duration) -> Result<u64, Error>;
+}
+
+/// Rate limit store, configured with the `store` setting in the `[rate_limit]` section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// Counters are kept in the memory of each app instance.
+    #[default]
+    Memory,
+    /// Counters are kept in the `rwf_rate_limits` table.
+    Postgres,
+    /// Counters are kept in Redis. Requires the `redis` feature.
+    Redis,
+    /// Counters are kept in the embedded key-value store, in the file set with `kv_path`.
+    /// Requires the `kv` feature.
+    Kv,
+}
+
+/// Create the store configured in the `[rate_limit]` section.
+pub fn from_config() -> Arc<dyn RateLimitStore> {
+    let config = &get_config().rate_limit;
+
+    match config.store {
+        Backend::Memory => Arc::new(MemoryStore::default()),
+        Backend::Postgres => Arc::new(PostgresStore::default()),
+        #[cfg(feature = "redis")]
+        Backend::Redis => Arc::new(RedisStore::new(&config.redis_url)),
+        #[cfg(not(feature = "redis"))]
+        Backend::Redis => {
+            tracing::error!(
+                "rate limit store \"redis\" requires the \"redis\" feature, using memory instead"
+            );
+            Arc::new(MemoryStore::default())
+        }
+        #[cfg(feature = "kv")]
+        Backend::Kv => match KvStore::open(&config.kv_path) {
+            Ok(store) => Arc::new(store),
+            Err(err) => {
+                tracing::error!(
+                    "rate limit store \"kv\" failed to open: {}, using memory instead",
+                    err
+                );
+                Arc::new(MemoryStore::default())
+            }
+        },
+        #[cfg(not(feature = "kv"))]
+        Backend::Kv => {
+            tracing::error!(
+                "rate limit store \"kv\" requires the \"kv\" feature, using memory instead"
+            );
+            Arc::new(MemoryStore::default())
+        }
+    }
+}
+
+/// Weighted request count in the sliding window.
+///
+/// # Arguments
+///
+/// * `current` - Requests in the current fixed window.
+/// * `previous` - Requests in the previous fixed window.
+/// * `elapsed` - How far into the current window we are, in milliseconds.
+/// * `window` - Window length, in milliseconds.
+///
+pub(crate) fn sliding_count(current: u64, previous: u64, elapsed: u64, window: u64) -> u64 {
+    let overlap = window.saturating_sub(elapsed);
+    current + previous * overlap / window.max(1)
+}
+
+pub(crate) fn millis(duration: Duration) -> u64 {
+    (duration.as_millis() as u64).max(1)
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    window: u64,
+    current: u64,
+    previous: u64,
+}
+
+/// Counters kept in memory.
+#[derive(Default)]
+pub struct MemoryStore {
+    counters: Mutex<HashMap<String, Counter>>,
+}
+
+impl MemoryStore {
+    fn incr_at(&self, key: &str, window: Duration, now: u64) -> u64 {
+        let window = millis(window);
+        let index = now / window;
+        let mut counters = self.counters.lock();
+
+        if counters.len() >= MAX_MEMORY_KEYS {
+            counters.retain(|_, counter| counter.window + 1 >= index);
+        }
+
+        let counter = counters.entry(key.to_string()).or_default();
+
+        if counter.window != index {
+            counter.previous = if counter.window + 1 == index {
+                counter.current
+            } else {
+                0
+            };
+            counter.current = 0;
+            counter.window = index;
+        }
+
+        counter.current += 1;
+
+        sliding_count(counter.current, counter.previous, now % window, window)
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn incr(&self, key: &str, window: Duration) -> Result<u64, Error> {
+        Ok(self.incr_at(key, window, now()))
+    }
+}
+
+/// Checks every store must pass.
+#[cfg(test)]
+pub(crate) async fn conformance(store: Arc<dyn RateLimitStore>) {
+    // Long enough for the test to run in a single window.
+    let window = Duration::from_secs(3600);
+    let key = uuid::Uuid::new_v4().to_string();
+    let other = uuid::Uuid::new_v4().to_string();
+
+    let mut requests = tokio::task::JoinSet::new();
+    for _ in 0..10 {
+        let (store, key) = (store.clone(), key.clone());
+        requests.spawn(async move { store.incr(&key, window).await.unwrap() });
+    }
+
+    let mut counts = requests.join_all().await;
+    counts.sort();
+
+    // Each request sees a different count.
+    assert_eq!(counts, (1..=10).collect::<Vec<_>>());
+
+    // Keys are counted separately.
+    assert_eq!(store.incr(&other, window).await.unwrap(), 1);
+    assert_eq!(store.incr(&key, window).await.unwrap(), 11);
+
+    // Counts expire.
+    let window = Duration::from_millis(50);
+    let key = format!("{}_short", key);
+    store.incr(&key, window).await.unwrap();
+    store.incr(&key, window).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(store.incr(&key, window).await.unwrap(), 1);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sliding_count() {
+        assert_eq!(sliding_count(3, 10, 0, 1000), 13);
+        assert_eq!(sliding_count(3, 10, 500, 1000), 8);
+        assert_eq!(sliding_count(3, 10, 999, 1000), 3);
+        assert_eq!(sliding_count(1, 0, 10, 1000), 1);
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStore::default();
+        let window = Duration::from_secs(1);
+
+        for expected in 1..=5 {
+            assert_eq!(store.incr_at("a", window, 10_000), expected);
+        }
+        assert_eq!(store.incr_at("b", window, 10_000), 1);
+
+        // Half of the previous window still counts.
+        assert_eq!(store.incr_at("a", window, 11_500), 1 + 5 / 2);
+
+        // Previous window is too old.
+        assert_eq!(store.incr_at("a", window, 13_000), 1);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_conformance() {
+        conformance(Arc::new(MemoryStore::default())).await;
+    }
+}
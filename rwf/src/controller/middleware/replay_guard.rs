@@ -0,0 +1,417 @@
+//! Reject replayed webhooks.
+//!
+//! Webhook providers sign their requests with a timestamp, so old requests are rejected, but a captured request
+//! can still be sent again within the allowed tolerance. The replay guard remembers the identifier of each request,
+//! either its signature or the provider's event id, and rejects requests with an identifier it has already seen
+//! with `409 - Conflict`:
+//!
+//! ```
+//! use rwf::controller::middleware::ReplayGuard;
+//! use std::time::Duration;
+//!
+//! // Keyed by the signature header.
+//! let guard = ReplayGuard::signature("stripe-signature").ttl(Duration::from_secs(600));
+//!
+//! // Keyed by the event id in the JSON body.
+//! let guard = ReplayGuard::new(|request| {
+//!     let event = request.json_raw().ok()?;
+//!     event["id"].as_str().map(|id| id.to_string())
+//! });
+//! ```
+//!
+//! The guard doesn't check the signature. Verify it in the controller, or in a middleware that runs before the guard.
+//! The body isn't consumed by the guard, so the controller can still verify the signature of the raw body and parse it as JSON.
+//! Identifiers of requests that don't receive a `2xx` response are forgotten, so the provider can retry them, and forged
+//! requests can't block legitimate ones.
+//!
+//! Identifiers are kept in memory by default, for the duration of the TTL, which should be longer than the provider's
+//! signature tolerance. Apps running several instances can share them by implementing [`ReplayStore`].
+//! With the `kv` feature, the embedded [`KvStore`](crate::kv::KvStore) keeps them across restarts.
+use parking_lot::Mutex;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::prelude::*;
+
+/// Keep identifiers this long by default.
+const DEFAULT_TTL: Duration = Duration::from_secs(600);
+
+/// Remember this many identifiers by default.
+const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+/// Storage for the identifiers of requests the guard has seen.
+#[async_trait]
+pub trait ReplayStore: Send + Sync {
+    /// Record the identifier for `ttl`. Returns `false` if it's already recorded and hasn't expired.
+    ///
+    /// Must be atomic: of concurrent calls with the same key, only one returns `true`.
+    async fn insert(&self, key: &str, ttl: Duration) -> Result<bool, Error>;
+
+    /// Forget the identifier.
+    async fn remove(&self, key: &str) -> Result<(), Error>;
+}
+
+/// Identifiers kept in memory.
+///
+/// When the store is full, expired identifiers are removed, and if that's not enough,
+/// the ones closest to expiring.
+pub struct MemoryReplayStore {
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Instant>>,
+}
+
+impl Default for MemoryReplayStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl MemoryReplayStore {
+    /// Create a store which remembers up to `max_entries` identifiers.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries: max_entries.max(1),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn insert_at(&self, key: &str, ttl: Duration, now: Instant) -> bool {
+        let mut entries = self.entries.lock();
+
+        if let Some(expires_at) = entries.get(key) {
+            if *expires_at > now {
+                return false;
+            }
+        }
+
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            entries.retain(|_, expires_at| *expires_at > now);
+
+            while entries.len() >= self.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, expires_at)| **expires_at)
+                    .map(|(key, _)| key.clone());
+
+                match oldest {
+                    Some(oldest) => entries.remove(&oldest),
+                    None => break,
+                };
+            }
+        }
+
+        entries.insert(key.to_string(), now + ttl);
+
+        true
+    }
+
+    /// Number of identifiers in the store, including expired ones.
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    /// The store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl ReplayStore for MemoryReplayStore {
+    async fn insert(&self, key: &str, ttl: Duration) -> Result<bool, Error> {
+        Ok(self.insert_at(key, ttl, Instant::now()))
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), Error> {
+        self.entries.lock().remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kv")]
+#[async_trait]
+impl ReplayStore for crate::kv::KvStore {
+    async fn insert(&self, key: &str, ttl: Duration) -> Result<bool, Error> {
+        crate::kv::KvStore::insert(self, &format!("rwf:replay:{}", key), [], Some(ttl))
+            .map_err(Error::new)
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), Error> {
+        crate::kv::KvStore::remove(self, &format!("rwf:replay:{}", key)).map_err(Error::new)
+    }
+}
+
+/// Checks every store must pass.
+#[cfg(test)]
+pub(crate) async fn conformance(store: Arc<dyn ReplayStore>) {
+    let ttl = Duration::from_secs(3600);
+    let key = uuid::Uuid::new_v4().to_string();
+
+    assert!(store.insert(&key, ttl).await.unwrap());
+    assert!(!store.insert(&key, ttl).await.unwrap());
+
+    store.remove(&key).await.unwrap();
+    assert!(store.insert(&key, ttl).await.unwrap());
+
+    // Identifiers expire.
+    let key = uuid::Uuid::new_v4().to_string();
+    assert!(store.insert(&key, Duration::from_millis(50)).await.unwrap());
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(store.insert(&key, ttl).await.unwrap());
+
+    // Only one of concurrent inserts succeeds.
+    let key = uuid::Uuid::new_v4().to_string();
+    let mut inserts = tokio::task::JoinSet::new();
+    for _ in 0..10 {
+        let (store, key) = (store.clone(), key.clone());
+        inserts.spawn(async move { store.insert(&key, ttl).await.unwrap() });
+    }
+    let inserted = inserts.join_all().await;
+    assert_eq!(inserted.iter().filter(|inserted| **inserted).count(), 1);
+}
+
+type Identifier = dyn Fn(&Request) -> Option<String> + Send + Sync;
+
+/// Replay guard middleware.
+pub struct ReplayGuard {
+    identifier: Box<Identifier>,
+    ttl: Duration,
+    store: Arc<dyn ReplayStore>,
+}
+
+impl ReplayGuard {
+    /// Create the guard, identifying requests with the `identifier` closure, e.g. returning the event id
+    /// from the body. Requests without an identifier are rejected with `400 - Bad Request`.
+    pub fn new(identifier: impl Fn(&Request) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self {
+            identifier: Box::new(identifier),
+            ttl: DEFAULT_TTL,
+            store: Arc::new(MemoryReplayStore::default()),
+        }
+    }
+
+    /// Create the guard, identifying requests by the value of the signature header.
+    pub fn signature(header: &str) -> Self {
+        let header = header.to_lowercase();
+
+        Self::new(move |request| {
+            request
+                .header(&header)
+                .map(|signature| signature.trim())
+                .filter(|signature| !signature.is_empty())
+                .map(|signature| signature.to_string())
+        })
+    }
+
+    /// How long identifiers are remembered. Default: 10 minutes.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Remember up to this many identifiers in memory. Default: 100,000.
+    pub fn max_entries(self, max_entries: usize) -> Self {
+        self.store(MemoryReplayStore::new(max_entries))
+    }
+
+    /// Keep identifiers in a different store.
+    pub fn store(mut self, store: impl ReplayStore + 'static) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Identifiers are namespaced by path, so endpoints of different providers can share a store.
+    fn key(&self, request: &Request) -> Option<String> {
+        (self.identifier)(request).map(|id| format!("{} {}", request.path().base(), id))
+    }
+}
+
+#[async_trait]
+impl Middleware for ReplayGuard {
+    async fn handle_request(&self, request: Request) -> Result<Outcome, Error> {
+        let key = match self.key(&request) {
+            Some(key) => key,
+            None => return Ok(Outcome::Stop(request, Response::bad_request())),
+        };
+
+        if self.store.insert(&key, self.ttl).await? {
+            Ok(Outcome::Forward(request))
+        } else {
+            let response = Response::new()
+                .code(409)
+                .text("This request was already received");
+            Ok(Outcome::Stop(request, response))
+        }
+    }
+
+    async fn handle_response(
+        &self,
+        request: &Request,
+        response: Response,
+    ) -> Result<Response, Error> {
+        // Only called for requests the guard forwarded, not for replays.
+        if !(200..300).contains(&response.status().code()) {
+            if let Some(key) = self.key(request) {
+                self.store.remove(&key).await?;
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{Controller, MiddlewareSet};
+    use crate::crypto::{sign, verify_signature};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Webhook {
+        middleware: MiddlewareSet,
+        received: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Controller for Webhook {
+        fn middleware(&self) -> &MiddlewareSet {
+            &self.middleware
+        }
+
+        fn skip_csrf(&self) -> bool {
+            true
+        }
+
+        async fn handle(&self, request: &Request) -> Result<Response, Error> {
+            let signature = request
+                .header("x-signature")
+                .map(|s| s.as_str())
+                .unwrap_or("");
+
+            if !verify_signature(request.body(), signature) {
+                return Ok(Response::forbidden());
+            }
+
+            let event = request.json_raw()?;
+            self.received.fetch_add(1, Ordering::SeqCst);
+
+            Ok(Response::new().text(event["id"].as_str().unwrap_or_default()))
+        }
+    }
+
+    impl Webhook {
+        fn new(guard: ReplayGuard) -> Self {
+            Self {
+                middleware: MiddlewareSet::without_default(vec![guard.middleware()]),
+                received: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    async fn post(body: &str, signature: &str) -> Request {
+        let request = format!(
+            "POST /webhooks HTTP/1.1\r\nX-Signature: {}\r\nContent-Length: {}\r\n\r\n{}",
+            signature,
+            body.len(),
+            body
+        );
+        Request::read("127.0.0.1:1234".parse().unwrap(), request.as_bytes())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replay_signature() -> Result<(), Error> {
+        let webhook = Webhook::new(ReplayGuard::signature("X-Signature"));
+        let body = r#"{"id": "evt_1"}"#;
+        let signature = sign(body.as_bytes());
+
+        let first = webhook
+            .handle_internal(post(body, &signature).await)
+            .await?;
+        let replay = webhook
+            .handle_internal(post(body, &signature).await)
+            .await?;
+
+        assert_eq!(first.status().code(), 200);
+        assert_eq!(first.body_bytes().unwrap(), b"evt_1");
+        assert_eq!(replay.status().code(), 409);
+        assert_eq!(webhook.received.load(Ordering::SeqCst), 1);
+
+        // Forged requests don't block the real one.
+        let forged = webhook
+            .handle_internal(post(r#"{"id": "evt_2"}"#, "forged").await)
+            .await?;
+        assert_eq!(forged.status().code(), 403);
+        let forged = webhook
+            .handle_internal(post(r#"{"id": "evt_2"}"#, "forged").await)
+            .await?;
+        assert_eq!(forged.status().code(), 403);
+
+        let missing = webhook.handle_internal(post(body, "").await).await?;
+        assert_eq!(missing.status().code(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_event_id() -> Result<(), Error> {
+        let webhook = Webhook::new(ReplayGuard::new(|request| {
+            let event = request.json_raw().ok()?;
+            event["id"].as_str().map(|id| id.to_string())
+        }));
+
+        // The provider signs each delivery of the same event differently.
+        let first = r#"{"id": "evt_1", "attempt": 1}"#;
+        let second = r#"{"id": "evt_1", "attempt": 2}"#;
+
+        let first = webhook
+            .handle_internal(post(first, &sign(first.as_bytes())).await)
+            .await?;
+        let second = webhook
+            .handle_internal(post(second, &sign(second.as_bytes())).await)
+            .await?;
+
+        assert_eq!(first.status().code(), 200);
+        assert_eq!(second.status().code(), 409);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryReplayStore::new(2);
+        let ttl = Duration::from_secs(10);
+        let now = Instant::now();
+
+        assert!(store.insert_at("a", ttl, now));
+        assert!(!store.insert_at("a", ttl, now));
+        assert!(store.insert_at("b", ttl * 2, now));
+
+        // Full, "a" expires first and is evicted.
+        assert!(store.insert_at("c", ttl * 3, now));
+        assert_eq!(store.len(), 2);
+        assert!(!store.insert_at("b", ttl, now));
+        assert!(store.insert_at("a", ttl, now));
+
+        // Expired.
+        assert!(store.insert_at("c", ttl, now + ttl * 4));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_conformance() {
+        conformance(Arc::new(MemoryReplayStore::default())).await;
+    }
+
+    #[cfg(feature = "kv")]
+    #[tokio::test]
+    async fn test_kv_store_conformance() {
+        let dir = tempdir::TempDir::new("kv").unwrap();
+        let store = crate::kv::KvStore::open(dir.path().join("rwf.kv")).unwrap();
+
+        conformance(Arc::new(store)).await;
+    }
+}
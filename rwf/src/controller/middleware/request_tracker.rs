@@ -40,13 +40,13 @@ impl Middleware for RequestTracker {
         request: &Request,
         mut response: Response,
     ) -> Result<Response, Error> {
-        let method = request.method().to_string();
+        let method = request.original_method().to_string();
         let path = request.path().path().to_string();
         let query = request.path().query().to_json();
         let code = response.status().code() as i32;
         let duration =
             ((OffsetDateTime::now_utc() - request.received_at()).as_seconds_f64() * 1000.0) as f32;
-        let client = request.peer().ip();
+        let client = request.client_ip();
 
         let client_id = match request
             .cookies()
@@ -0,0 +1,94 @@
+//! Allow access only with a [signed URL](crate::http::signed_url).
+//!
+//! Requests with a URL that's not signed, was changed, or has expired are rejected with `403 - Forbidden`.
+//! The signature parameters are removed before the request reaches the controller.
+use crate::controller::middleware::prelude::*;
+use crate::http::signed_url::{strip, verify};
+
+use tracing::debug;
+
+/// Verify signed URLs.
+#[derive(Default)]
+pub struct VerifySignedUrl;
+
+impl VerifySignedUrl {
+    /// Create the middleware.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl Middleware for VerifySignedUrl {
+    async fn handle_request(&self, mut request: Request) -> Result<Outcome, Error> {
+        match verify(request.path()) {
+            Ok(()) => {
+                let path = strip(request.path());
+                request.head_mut().replace_path(path);
+                Ok(Outcome::Forward(request))
+            }
+
+            Err(err) => {
+                debug!("{} {}", request.path().base(), err);
+                Ok(Outcome::Stop(request, Response::forbidden()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{Controller, MiddlewareSet};
+    use crate::http::signed_url;
+    use time::Duration;
+
+    struct Download {
+        middleware: MiddlewareSet,
+    }
+
+    #[async_trait]
+    impl Controller for Download {
+        fn middleware(&self) -> &MiddlewareSet {
+            &self.middleware
+        }
+
+        async fn handle(&self, request: &Request) -> Result<Response, Error> {
+            Ok(Response::new().text(request.path().query().len()))
+        }
+    }
+
+    async fn get(url: &str) -> Response {
+        let download = Download {
+            middleware: MiddlewareSet::without_default(vec![VerifySignedUrl::new().middleware()]),
+        };
+        let head = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", url);
+        let request = Request::read("127.0.0.1:1234".parse().unwrap(), head.as_bytes())
+            .await
+            .unwrap();
+
+        download.handle_internal(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_signed_url() {
+        let url = signed_url(
+            "/downloads/1",
+            &[("file", "report.pdf")],
+            Duration::hours(1),
+        );
+
+        let response = get(&url).await;
+        assert_eq!(response.status().code(), 200);
+        // Only the "file" parameter reaches the controller.
+        assert_eq!(response.body_bytes().unwrap(), b"1");
+
+        for url in [
+            url.replace("report", "secrets"),
+            "/downloads/1?file=report.pdf".to_string(),
+            signed_url("/downloads/1", &[], Duration::seconds(-10)),
+        ] {
+            assert_eq!(get(&url).await.status().code(), 403, "{}", url);
+        }
+    }
+}
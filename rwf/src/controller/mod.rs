@@ -26,10 +26,16 @@
 //!
 use async_trait::async_trait;
 
+pub mod audit;
 pub mod auth;
+pub mod capture;
 pub mod engine;
 pub mod error;
+pub mod http_cache;
+pub mod job_status;
 pub mod middleware;
+pub mod patch;
+pub mod policy;
 pub mod ser;
 pub mod static_files;
 pub mod turbo_stream;
@@ -44,25 +50,39 @@ pub mod rack;
 #[cfg(feature = "rack")]
 pub use rack::RackController;
 
-pub use auth::{AllowAll, AuthHandler, Authentication, BasicAuth, DenyAll, Session, SessionId};
+pub use audit::AuditEvent;
+pub use auth::{
+    AllowAll, AuthHandler, Authentication, BasicAuth, DenyAll, Impersonate, Impersonation, Session,
+    SessionExpiry, SessionId,
+};
+pub use capture::CaptureController;
 pub use engine::Engine;
 pub use error::Error;
+pub use http_cache::{HttpCache, HttpCacheable, Validators};
+pub use job_status::JobStatusController;
+pub use middleware::csrf::CsrfMode;
 pub use middleware::{Middleware, MiddlewareHandler, MiddlewareSet, Outcome, RateLimiter};
+pub use patch::{PatchError, PatchFormat};
+pub use policy::Policy;
 pub use static_files::StaticFiles;
 pub use turbo_stream::TurboStream;
 
 use super::http::{
-    websocket::{self, DataFrame},
-    Handler, Method, Request, Response, Stream, ToParameter,
+    error_hook, memory, problem,
+    websocket::{self, DataFrame, Incoming},
+    Handler, Method, Problem, Request, Response, Stream, ToParameter,
 };
-use super::model::{get_connection, Insert, Model, Query, ToValue, Update, Value};
+use super::model::{ConnectionGuard, Insert, Model, Pool, Query, ToValue, Update, Value};
 use crate::colors::MaybeColorize;
 use crate::comms::Comms;
 use crate::config::get_config;
+use crate::errors::{ErrorKind, ErrorReport};
+use crate::health;
+use crate::view::form::FormErrors;
 
 use tokio::select;
 use tokio::time::{interval, timeout};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use serde::{Deserialize, Serialize};
 
@@ -154,6 +174,49 @@ pub trait Controller: Sync + Send {
         false
     }
 
+    /// This controller doesn't use the database, so it keeps serving requests while the database is
+    /// [unavailable](crate::health#database-outages), e.g. a liveness check or static pages. Requests to other controllers
+    /// get `503 - Service Unavailable` until the database is back.
+    fn database_free(&self) -> bool {
+        false
+    }
+
+    /// How [CSRF](https://owasp.org/www-community/attacks/csrf) protection checks requests to this controller. By default,
+    /// requests must have a token rendered into the page. APIs used by JavaScript can use [`CsrfMode::DoubleSubmit`] instead.
+    fn csrf_mode(&self) -> CsrfMode {
+        CsrfMode::Token
+    }
+
+    /// Layout used for pages rendered with [`Response::template`]. The page is rendered
+    /// first, and its output is available in the layout as the `yield` variable. By default,
+    /// controllers don't have a layout.
+    ///
+    /// Responses can override the layout with [`Response::layout`] or opt out with [`Response::without_layout`].
+    /// Responses that aren't rendered from templates, e.g. JSON, are never placed inside a layout.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rwf::prelude::*;
+    ///
+    /// struct Admin;
+    ///
+    /// #[async_trait]
+    /// impl Controller for Admin {
+    ///     fn layout(&self) -> Option<&'static str> {
+    ///         Some("templates/layouts/admin.html")
+    ///     }
+    ///
+    ///     async fn handle(&self, request: &Request) -> Result<Response, Error> {
+    ///         let context = context!("title" => "Admin");
+    ///         Ok(Response::new().template("templates/admin/index.html", &context)?)
+    ///     }
+    /// }
+    /// ```
+    fn layout(&self) -> Option<&'static str> {
+        None
+    }
+
     /// Create a basic route handler for this controller.
     ///
     /// This method can be used to register a controller with the HTTP server.
@@ -217,26 +280,115 @@ pub trait Controller: Sync + Send {
             return auth.auth().denied(&request).await;
         }
 
-        let request = request.set_skip_csrf(self.skip_csrf());
+        let request = request
+            .set_skip_csrf(self.skip_csrf())
+            .set_csrf_mode(self.csrf_mode());
 
         // Run the middleware chain (forward).
         let outcome = self.middleware().handle_request(request).await?;
 
         let response = match outcome {
-            (Outcome::Forward(request), executed) => match self.handle(&request).await {
+            (Outcome::Forward(request), executed) => match async {
+                let response = self.handle(&request).await?;
+                Ok::<_, Error>(response.render_page(self.layout(), &request).await?)
+            }
+            .await
+            {
                 Ok(response) => {
+                    if let Some(error) = response.error() {
+                        error_hook::call(error, &request);
+                    }
+
                     self.middleware()
                         .handle_response(&request, response.from_request(&request)?, executed)
                         .await?
                 }
                 Err(err) => {
                     error!("{}", err);
+                    error_hook::call(&err, &request);
+
+                    let client_error = [400, 403, 409, 413, 415, 422, 503].contains(&err.code());
+
+                    if !client_error {
+                        ErrorReport::new(ErrorKind::Controller, &err)
+                            .request(&request)
+                            .tag("controller", self.controller_name())
+                            .send();
+                    }
 
                     let response = match err {
+                        Error::HttpError(err) if Problem::accepted(&request) => {
+                            let code = err.code();
+                            let problem =
+                                Problem::from(err.as_ref()).instance(request.path().path());
+
+                            if code == 503 {
+                                problem
+                                    .extension("retry_after", memory::RETRY_AFTER)
+                                    .response()
+                                    .header("retry-after", memory::RETRY_AFTER)
+                            } else {
+                                problem.response()
+                            }
+                        }
+
+                        Error::OrmError(ref error) if err.code() == 503 => {
+                            health::unavailable(&request, Some(error))
+                        }
+
+                        Error::OrmError(ref error) if err.code() == 400 => {
+                            if Problem::accepted(&request) {
+                                Problem::new(400, problem::title(400))
+                                    .detail(error)
+                                    .instance(request.path().path())
+                                    .response()
+                            } else {
+                                Response::bad_request()
+                            }
+                        }
+
+                        Error::OrmError(ref error) if error.kind().is_constraint_violation() => {
+                            let code = err.code();
+                            let kind = error.kind();
+                            let mut errors = FormErrors::new();
+                            if let Some(column) = kind.column() {
+                                errors.add(column, &kind);
+                            }
+
+                            if Problem::accepted(&request) {
+                                Problem::new(code, problem::title(code))
+                                    .detail(&kind)
+                                    .instance(request.path().path())
+                                    .extension("errors", errors)
+                                    .response()
+                            } else {
+                                Response::error_page(
+                                    code,
+                                    &format!("{} - {}", code, problem::title(code)),
+                                    &kind.to_string(),
+                                )
+                            }
+                        }
+
+                        err if Problem::accepted(&request) => {
+                            let problem = Problem::new(500, problem::title(500))
+                                .instance(request.path().path());
+
+                            #[cfg(debug_assertions)]
+                            let problem = problem.detail(&err);
+                            #[cfg(not(debug_assertions))]
+                            let _ = err;
+
+                            problem.response()
+                        }
+
                         Error::HttpError(err) => match err.code() {
                             400 => Response::bad_request(),
                             403 => Response::forbidden(),
                             413 => Response::content_too_large(),
+                            415 => Response::unsupported_media_type(),
+                            422 => Response::unprocessable_content(),
+                            503 => Response::service_unavailable(memory::RETRY_AFTER),
                             _ => Response::internal_error(err),
                         },
 
@@ -331,10 +483,18 @@ pub trait PageController: Controller {
         Ok(Response::method_not_allowed())
     }
 
+    /// Respond to a HEAD request to this controller. By default, the request is handled by
+    /// [`PageController::get`], and the server sends its response without the body.
+    async fn head(&self, request: &Request) -> Result<Response, Error> {
+        PageController::get(self, request).await
+    }
+
     /// Perform the request GET/POST split automatically.
     async fn handle(&self, request: &Request) -> Result<Response, Error> {
         if request.get() {
             PageController::get(self, request).await
+        } else if request.method() == &Method::Head {
+            PageController::head(self, request).await
         } else if request.post() {
             PageController::post(self, request).await
         } else {
@@ -357,6 +517,8 @@ pub trait PageController: Controller {
 /// - patch (PATCH /:id)
 /// - delete (DELETE /:id)
 ///
+/// `HEAD` requests are handled by `list` and `get`, and their responses are sent without the body.
+///
 /// By default, all methods will respond with `501 - Not Implemented`. It's up to the user
 /// to implement each method according to their needs.
 ///
@@ -397,14 +559,14 @@ pub trait RestController: Controller {
 
         match parameter {
             Ok(Some(id)) => match method {
-                Method::Get => self.get(request, &id).await,
+                Method::Get | Method::Head => self.get(request, &id).await,
                 Method::Put => self.update(request, &id).await,
                 Method::Delete => self.delete(request, &id).await,
                 Method::Patch => self.patch(request, &id).await,
                 _ => Ok(Response::method_not_allowed()),
             },
             Ok(None) => match method {
-                Method::Get => self.list(request).await,
+                Method::Get | Method::Head => self.list(request).await,
                 Method::Post => self.create(request).await,
                 _ => Ok(Response::method_not_allowed()),
             },
@@ -446,17 +608,46 @@ pub trait RestController: Controller {
 
 /// A controller that extends the [`RestController`] to
 /// automatically performs CRUD actions on database models.
+///
+/// `PATCH` requests sent with `Content-Type: application/merge-patch+json` are applied as
+/// [JSON Merge Patch](patch) documents: only the columns present in the body are changed, and only the
+/// columns that changed are saved.
 #[async_trait]
+#[allow(unused_variables)]
 pub trait ModelController: Controller {
     type Model: Model + Serialize + Send + Sync + for<'a> Deserialize<'a>;
 
+    /// Columns that can't be changed with a `PATCH`, in addition to the primary key.
+    /// They are ignored if present in the patch.
+    fn immutable_columns(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Ignore keys in a merge patch which aren't columns of the model, instead of rejecting
+    /// the request with `422 - Unprocessable Entity`.
+    fn lenient_patch(&self) -> bool {
+        false
+    }
+
+    /// Validate a record before it's saved by a merge patch. Errors are returned to the client with
+    /// `422 - Unprocessable Entity`.
+    fn validate(&self, model: &Self::Model) -> Result<(), FormErrors> {
+        Ok(())
+    }
+
+    /// Answer conditional `GET` requests with `304 - Not Modified`. Disabled by default;
+    /// models which implement [`HttpCacheable`] can enable it with [`HttpCache::enabled`].
+    fn http_cache(&self) -> HttpCache<Self::Model> {
+        HttpCache::disabled()
+    }
+
     async fn handle(&self, request: &Request) -> Result<Response, Error> {
         let method = request.method();
         let parameter = request.parameter::<i64>("id");
 
         match parameter {
             Ok(Some(id)) => match method {
-                Method::Get => ModelController::get(self, request, &id).await,
+                Method::Get | Method::Head => ModelController::get(self, request, &id).await,
                 Method::Put => ModelController::update(self, request, &id).await,
                 Method::Delete => return Ok(Response::not_found()),
                 Method::Patch => ModelController::patch(self, request, &id).await,
@@ -464,7 +655,7 @@ pub trait ModelController: Controller {
             },
 
             Ok(None) => match method {
-                Method::Get => ModelController::list(self, request).await,
+                Method::Get | Method::Head => ModelController::list(self, request).await,
                 Method::Post => ModelController::create(self, request).await,
                 _ => Ok(Response::method_not_allowed()),
             },
@@ -481,11 +672,19 @@ pub trait ModelController: Controller {
     }
 
     async fn list(&self, request: &Request) -> Result<Response, Error> {
-        let mut conn = get_connection().await?;
-        let page_size = request.query().get::<i64>("page_size").unwrap_or(25);
-        let page = request.query().get::<i64>("page").unwrap_or(1);
+        let mut conn = connection::<Self::Model>().await?;
+        let page_size = request.path().query().get::<i64>("page_size").unwrap_or(25);
+        let page = request.path().query().get::<i64>("page").unwrap_or(1);
         let offset = (std::cmp::max(1, page) - 1) * page_size;
 
+        let validators = self.http_cache().collection_validators(&mut conn).await?;
+
+        if let Some(ref validators) = validators {
+            if validators.fresh(request) {
+                return Ok(validators.not_modified());
+            }
+        }
+
         let models = Self::Model::all()
             .limit(page_size)
             .offset(offset)
@@ -493,38 +692,48 @@ pub trait ModelController: Controller {
             .await?;
         let response = match Response::new().json(models) {
             Ok(response) => response,
-            Err(err) => Response::internal_error(err),
+            Err(err) => return Ok(Response::internal_error(err)),
         };
 
-        Ok(response)
+        Ok(match validators {
+            Some(validators) => validators.apply(response),
+            None => response,
+        })
     }
 
-    async fn get(&self, _request: &Request, id: &i64) -> Result<Response, Error> {
-        let mut conn = get_connection().await?;
+    async fn get(&self, request: &Request, id: &i64) -> Result<Response, Error> {
+        let mut conn = connection::<Self::Model>().await?;
 
         match Self::Model::find_by(Self::Model::primary_key(), *id)
             .fetch(&mut conn)
             .await
         {
-            Ok(model) => match Response::new().json(model) {
-                Ok(response) => Ok(response),
-                Err(err) => Ok(Response::internal_error(err)),
-            },
+            Ok(model) => {
+                let validators = self.http_cache().validators(&model);
+
+                if let Some(ref validators) = validators {
+                    if validators.fresh(request) {
+                        return Ok(validators.not_modified());
+                    }
+                }
+
+                match Response::new().json(model) {
+                    Ok(response) => Ok(match validators {
+                        Some(validators) => validators.apply(response),
+                        None => response,
+                    }),
+                    Err(err) => Ok(Response::internal_error(err)),
+                }
+            }
 
             Err(_) => Ok(Response::not_found()),
         }
     }
 
     async fn create(&self, request: &Request) -> Result<Response, Error> {
-        let model = match request.json::<Self::Model>() {
-            Ok(model) => model,
-            Err(err) => {
-                println!("ser err: {:?}", err);
-                return Ok(Response::bad_request());
-            }
-        };
+        let model = request.json::<Self::Model>()?;
 
-        let mut conn = get_connection().await?;
+        let mut conn = connection::<Self::Model>().await?;
 
         let model = Query::Insert(Insert::<Self::Model>::from_columns(
             &Self::Model::column_names(),
@@ -546,13 +755,63 @@ pub trait ModelController: Controller {
             return Ok(Response::bad_request());
         }
 
-        let mut conn = get_connection().await?;
+        let mut conn = connection::<Self::Model>().await?;
         let model = model.save().fetch(&mut conn).await?;
         Ok(Response::new().json(model)?)
     }
 
+    /// Dispatch the `PATCH` request based on the format of its body.
     async fn patch(&self, request: &Request, id: &i64) -> Result<Response, Error> {
-        let mut conn = get_connection().await?;
+        match PatchFormat::from_request(request) {
+            Some(PatchFormat::Json) => ModelController::patch_json(self, request, id).await,
+            Some(PatchFormat::MergePatch) => ModelController::merge_patch(self, request, id).await,
+            Some(PatchFormat::JsonPatch) | None => Ok(PatchFormat::unsupported()),
+        }
+    }
+
+    /// Apply a JSON Merge Patch to the record, and save the columns that changed.
+    /// Nothing is written to the database if the patch doesn't change anything.
+    async fn merge_patch(&self, request: &Request, id: &i64) -> Result<Response, Error> {
+        let body = request.json_raw()?;
+        let mut conn = connection::<Self::Model>().await?;
+
+        let model = match Self::Model::find(*id).fetch_optional(&mut conn).await? {
+            Some(model) => model,
+            None => return Ok(Response::not_found()),
+        };
+
+        let patched = match patch::merge_patch(
+            &model,
+            &body,
+            self.immutable_columns(),
+            self.lenient_patch(),
+        ) {
+            Ok(patched) => patched,
+            Err(err) => return Ok(err.response()),
+        };
+
+        if patched.unchanged() {
+            return Ok(Response::new().json(model)?);
+        }
+
+        if let Err(errors) = self.validate(&patched.model) {
+            return Ok(PatchError::Validation(errors).response());
+        }
+
+        let model = Query::Update(Update::<Self::Model>::from_columns(
+            *id,
+            &patched.changed,
+            &patched.changes(),
+        ))
+        .fetch(&mut conn)
+        .await?;
+
+        Ok(Response::new().json(model)?)
+    }
+
+    /// Set the columns present in the JSON body. Keys which aren't columns of the model are ignored.
+    async fn patch_json(&self, request: &Request, id: &i64) -> Result<Response, Error> {
+        let mut conn = connection::<Self::Model>().await?;
         let exists = Self::Model::find(*id).count(&mut conn).await?;
 
         if exists == 0 {
@@ -583,6 +842,11 @@ pub trait ModelController: Controller {
     }
 }
 
+/// Connection to the database the model is stored in.
+async fn connection<T: Model>() -> Result<ConnectionGuard, Error> {
+    Ok(Pool::named(T::database())?.get().await?)
+}
+
 /// A controller that handles WebSocket connections.
 #[async_trait]
 #[allow(unused_variables)]
@@ -619,6 +883,18 @@ pub trait WebsocketController: Controller {
         Ok(())
     }
 
+    /// Maximum size of a message received from a client, in bytes. Larger messages close
+    /// the connection with code 1009. Defaults to the `max_message_size` setting in the `[websocket]` section.
+    fn max_message_size(&self) -> usize {
+        get_config().websocket.max_message_size
+    }
+
+    /// Maximum size of a single frame received from a client, in bytes. Larger frames close
+    /// the connection with code 1009. Defaults to the `max_frame_size` setting in the `[websocket]` section.
+    fn max_frame_size(&self) -> usize {
+        get_config().websocket.max_frame_size
+    }
+
     async fn handle_stream(
         &self,
         request: &Request,
@@ -644,6 +920,8 @@ pub trait WebsocketController: Controller {
         let mut receiver = Comms::receiver(&session_id);
         let mut check = interval(config.websocket.ping_interval().unsigned_abs());
         let mut lost_pings = 0_i64;
+        let mut reader =
+            websocket::Reader::new(self.max_message_size()).max_frame_size(self.max_frame_size());
 
         self.client_connected(&session_id).await?;
 
@@ -652,13 +930,10 @@ pub trait WebsocketController: Controller {
                 _ = check.tick() => {
                     debug!("{} check session \"{}\"", "websocket".purple(), session_id);
 
-                    let closed = match timeout(
+                    let closed = !matches!(timeout(
                         config.websocket.ping_timeout().unsigned_abs(),
                         DataFrame::new_ping().flush(&mut stream)
-                    ).await {
-                        Ok(Ok(_)) => false,
-                        _ => true,
-                    };
+                    ).await, Ok(Ok(_)));
 
                     lost_pings += 1;
 
@@ -686,25 +961,42 @@ pub trait WebsocketController: Controller {
                     }
                 }
 
-                frame = DataFrame::read(&mut stream) => {
-                    let frame = frame?;
+                incoming = reader.read(&mut stream) => {
+                    match incoming {
+                        Ok(Incoming::Message(message)) => {
+                            self.client_message(&session_id, message).await?;
+                        }
 
-                    if frame.is_pong() {
-                        debug!("{} session \"{}\" is alive", "websocket".purple(), session_id);
-                        lost_pings -= 1;
+                        Ok(Incoming::Pong) => {
+                            debug!("{} session \"{}\" is alive", "websocket".purple(), session_id);
+                            lost_pings -= 1;
 
-                        // Protect against weird clients.
-                        if lost_pings < 0 {
-                            lost_pings = 0;
+                            // Protect against weird clients.
+                            if lost_pings < 0 {
+                                lost_pings = 0;
+                            }
                         }
 
-                        continue;
-                    } else if frame.is_ping() {
-                        DataFrame::new_pong(frame).flush(&mut stream).await?;
-                        continue;
-                    }
+                        Ok(Incoming::Ping(payload)) => {
+                            websocket::send_pong(&mut stream, &payload).await?;
+                        }
+
+                        // Complete the close handshake.
+                        Ok(Incoming::Close(code)) => {
+                            websocket::send_close(&mut stream, code.unwrap_or(websocket::CLOSE_NORMAL)).await?;
+                            break;
+                        }
 
-                    self.client_message(&session_id, frame.message()).await?;
+                        Err(err) if err.websocket_close_code().is_some() => {
+                            warn!("{} session \"{}\": {}", "websocket".purple(), session_id, err);
+                            let code = err.websocket_close_code().unwrap_or(websocket::CLOSE_PROTOCOL_ERROR);
+                            websocket::send_close(&mut stream, code).await?;
+                            websocket::drain(&mut stream, config.websocket.ping_timeout().unsigned_abs()).await;
+                            break;
+                        }
+
+                        Err(err) => return Err(err.into()),
+                    }
                 }
 
             }
@@ -0,0 +1,421 @@
+//! Partial updates of models with `PATCH` requests.
+//!
+//! [`ModelController`](super::ModelController) picks how to apply the request body by its `Content-Type`:
+//!
+//! - `application/merge-patch+json`: [JSON Merge Patch](https://www.rfc-editor.org/rfc/rfc7396). Only the keys present
+//!   in the body are changed, and `null` clears a column.
+//! - `application/json`: the columns in the body are set to the values as-is. Unknown keys are ignored.
+//!
+//! [JSON Patch](https://www.rfc-editor.org/rfc/rfc6902) (`application/json-patch+json`) is recognized, but not supported yet,
+//! and is rejected with `415 - Unsupported Media Type`, like other content types.
+//!
+//! ```
+//! use rwf::controller::patch::merge;
+//! use serde_json::json;
+//!
+//! let mut user = json!({"name": "Alice", "settings": {"theme": "dark", "lang": "en"}});
+//! merge(&mut user, &json!({"settings": {"lang": null}}));
+//!
+//! assert_eq!(user, json!({"name": "Alice", "settings": {"theme": "dark"}}));
+//! ```
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value as JsonValue;
+use thiserror::Error;
+
+use crate::http::{Problem, Request, Response};
+use crate::model::{Model, Value};
+use crate::view::form::FormErrors;
+
+/// Content type of JSON Merge Patch documents.
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+
+/// Content type of JSON Patch documents.
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+/// Value of the `Accept-Patch` header, listing the supported patch formats.
+pub const ACCEPT_PATCH: &str = "application/merge-patch+json, application/json";
+
+/// Format of a `PATCH` request body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatchFormat {
+    /// `application/json`, or no content type.
+    Json,
+    /// `application/merge-patch+json`.
+    MergePatch,
+    /// `application/json-patch+json`.
+    JsonPatch,
+}
+
+impl PatchFormat {
+    /// Get the format from the request's `Content-Type`. Returns `None` if it's not a patch format.
+    pub fn from_request(request: &Request) -> Option<Self> {
+        let content_type = match request.header("content-type") {
+            Some(content_type) => content_type,
+            None => return Some(PatchFormat::Json),
+        };
+
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+
+        if media_type.eq_ignore_ascii_case("application/json") {
+            Some(PatchFormat::Json)
+        } else if media_type.eq_ignore_ascii_case(MERGE_PATCH) {
+            Some(PatchFormat::MergePatch)
+        } else if media_type.eq_ignore_ascii_case(JSON_PATCH) {
+            Some(PatchFormat::JsonPatch)
+        } else {
+            None
+        }
+    }
+
+    /// Response to a request in a format that isn't supported.
+    pub fn unsupported() -> Response {
+        Response::new()
+            .code(415)
+            .text("415 - Unsupported Media Type")
+            .header("accept-patch", ACCEPT_PATCH)
+    }
+}
+
+/// Apply a JSON Merge Patch to a document, as specified by [RFC 7396](https://www.rfc-editor.org/rfc/rfc7396#section-2).
+pub fn merge(target: &mut JsonValue, patch: &JsonValue) {
+    let patch = match patch.as_object() {
+        Some(patch) => patch,
+        None => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = JsonValue::Object(Default::default());
+    }
+
+    if let Some(target) = target.as_object_mut() {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge(target.entry(key.clone()).or_insert(JsonValue::Null), value);
+            }
+        }
+    }
+}
+
+/// Error applying a patch to a model.
+#[derive(Debug, Error)]
+pub enum PatchError {
+    #[error("patch must be a JSON object")]
+    NotAnObject,
+
+    #[error("unknown fields: {}", .0.join(", "))]
+    UnknownFields(Vec<String>),
+
+    #[error("{0}")]
+    Invalid(#[from] serde_json::Error),
+
+    #[error("record is not valid")]
+    Validation(FormErrors),
+}
+
+impl PatchError {
+    /// Response describing the error to the client.
+    pub fn response(&self) -> Response {
+        match self {
+            PatchError::NotAnObject => Problem::new(400, "Bad Request").detail(self).response(),
+
+            PatchError::UnknownFields(fields) => Problem::new(422, "Unknown fields")
+                .detail(self)
+                .extension("unknown_fields", fields)
+                .response(),
+
+            PatchError::Invalid(_) => Problem::new(422, "Invalid record").detail(self).response(),
+
+            PatchError::Validation(errors) => Problem::new(422, "Invalid record")
+                .detail(self)
+                .extension("errors", errors)
+                .response(),
+        }
+    }
+}
+
+/// Model with a patch applied.
+#[derive(Debug)]
+pub struct Patched<T> {
+    /// The model, with the new values.
+    pub model: T,
+    /// Columns with values different from the original model.
+    pub changed: Vec<&'static str>,
+}
+
+impl<T: Model> Patched<T> {
+    /// Nothing changed, so the record doesn't need to be saved.
+    pub fn unchanged(&self) -> bool {
+        self.changed.is_empty()
+    }
+
+    /// Values of the changed columns, to save them with an `UPDATE`.
+    pub fn changes(&self) -> Vec<Value> {
+        T::column_names()
+            .iter()
+            .zip(self.model.values())
+            .filter(|(column, _)| self.changed.contains(column))
+            .map(|(_, value)| value)
+            .collect()
+    }
+}
+
+/// Apply a JSON Merge Patch to a model.
+///
+/// The primary key and the `immutable` columns can't be changed and are ignored if present in the patch.
+/// Keys which aren't columns of the model are rejected, unless `lenient` is set, in which case they are ignored.
+/// `null` clears a column; columns that can't be `NULL` fail to deserialize.
+pub fn merge_patch<T>(
+    model: &T,
+    patch: &JsonValue,
+    immutable: &[&str],
+    lenient: bool,
+) -> Result<Patched<T>, PatchError>
+where
+    T: Model + Serialize + DeserializeOwned,
+{
+    let patch = patch.as_object().ok_or(PatchError::NotAnObject)?;
+
+    let columns = T::column_names();
+    let ignored = |key: &str| key == T::primary_key() || immutable.contains(&key);
+
+    let mut unknown = patch
+        .keys()
+        .filter(|key| !columns.contains(&key.as_str()) && !ignored(key))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if !unknown.is_empty() && !lenient {
+        unknown.sort();
+        return Err(PatchError::UnknownFields(unknown));
+    }
+
+    let original = serde_json::to_value(model)?;
+    let mut merged = original.clone();
+
+    if let Some(record) = merged.as_object_mut() {
+        for (key, value) in patch {
+            if ignored(key) || !columns.contains(&key.as_str()) {
+                continue;
+            }
+
+            // Null clears the column, instead of removing the field from the record.
+            if value.is_null() {
+                record.insert(key.clone(), JsonValue::Null);
+            } else {
+                merge(record.entry(key.clone()).or_insert(JsonValue::Null), value);
+            }
+        }
+    }
+
+    let changed = columns
+        .iter()
+        .filter(|column| original.get(**column) != merged.get(**column))
+        .copied()
+        .collect();
+    let model = serde_json::from_value(merged)?;
+
+    Ok(Patched { model, changed })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::{Error, FromRow, GetColumn, ToValue};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct User {
+        id: Option<i64>,
+        email: String,
+        name: Option<String>,
+        settings: JsonValue,
+        created_by: String,
+    }
+
+    impl FromRow for User {
+        fn from_row(row: tokio_postgres::Row) -> Result<Self, Error> {
+            Ok(Self {
+                id: row.get_column("id")?,
+                email: row.get_column("email")?,
+                name: row.get_column("name")?,
+                settings: row.get_column("settings")?,
+                created_by: row.get_column("created_by")?,
+            })
+        }
+    }
+
+    impl Model for User {
+        fn id(&self) -> Value {
+            self.id.to_value()
+        }
+
+        fn table_name() -> &'static str {
+            "users"
+        }
+
+        fn foreign_key() -> &'static str {
+            "user_id"
+        }
+
+        fn column_names() -> &'static [&'static str] {
+            &["email", "name", "settings", "created_by"]
+        }
+
+        fn values(&self) -> Vec<Value> {
+            vec![
+                self.email.to_value(),
+                self.name.to_value(),
+                self.settings.to_value(),
+                self.created_by.to_value(),
+            ]
+        }
+    }
+
+    fn user() -> User {
+        User {
+            id: Some(1),
+            email: "alice@example.com".into(),
+            name: Some("Alice".into()),
+            settings: json!({"theme": "dark", "lang": "en"}),
+            created_by: "admin".into(),
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        // Examples from RFC 7396, Appendix A.
+        for (target, patch, expected) in [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (
+                json!({"a": [{"b": "c"}]}),
+                json!({"a": [1]}),
+                json!({"a": [1]}),
+            ),
+            (json!(["a", "b"]), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ] {
+            let mut document = target.clone();
+            merge(&mut document, &patch);
+            assert_eq!(document, expected, "{} + {}", target, patch);
+        }
+    }
+
+    #[test]
+    fn test_merge_patch_clear() {
+        let patched = merge_patch(
+            &user(),
+            &json!({"name": null, "settings": {"lang": null, "beta": true}}),
+            &[],
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(patched.model.name, None);
+        assert_eq!(patched.model.email, "alice@example.com");
+        assert_eq!(
+            patched.model.settings,
+            json!({"theme": "dark", "beta": true})
+        );
+        assert_eq!(patched.changed, vec!["name", "settings"]);
+        assert_eq!(
+            patched.changes(),
+            vec![
+                None::<String>.to_value(),
+                json!({"theme": "dark", "beta": true}).to_value()
+            ]
+        );
+
+        // Required columns can't be cleared.
+        let err = merge_patch(&user(), &json!({"email": null}), &[], false).unwrap_err();
+        assert!(matches!(err, PatchError::Invalid(_)));
+        assert_eq!(err.response().status().code(), 422);
+    }
+
+    #[test]
+    fn test_merge_patch_immutable() {
+        let patched = merge_patch(
+            &user(),
+            &json!({"id": 2, "created_by": "mallory", "email": "bob@example.com"}),
+            &["created_by"],
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(patched.model.id, Some(1));
+        assert_eq!(patched.model.created_by, "admin");
+        assert_eq!(patched.model.email, "bob@example.com");
+        assert_eq!(patched.changed, vec!["email"]);
+    }
+
+    #[test]
+    fn test_merge_patch_noop() {
+        let patched = merge_patch(
+            &user(),
+            &json!({"email": "alice@example.com", "settings": {"theme": "dark"}}),
+            &[],
+            false,
+        )
+        .unwrap();
+
+        assert!(patched.unchanged());
+        assert!(patched.changes().is_empty());
+    }
+
+    #[test]
+    fn test_merge_patch_unknown() {
+        let err = merge_patch(
+            &user(),
+            &json!({"role": "admin", "email": "bob@example.com", "admin": true}),
+            &[],
+            false,
+        )
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "unknown fields: admin, role");
+        let response = err.response();
+        assert_eq!(response.status().code(), 422);
+        let body: JsonValue = serde_json::from_slice(response.body_bytes().unwrap()).unwrap();
+        assert_eq!(body["unknown_fields"], json!(["admin", "role"]));
+
+        let patched = merge_patch(
+            &user(),
+            &json!({"role": "admin", "email": "bob@example.com"}),
+            &[],
+            true,
+        )
+        .unwrap();
+        assert_eq!(patched.changed, vec!["email"]);
+
+        assert!(matches!(
+            merge_patch(&user(), &json!([]), &[], false),
+            Err(PatchError::NotAnObject)
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+//! Authorization policy, deciding what users can do.
+//!
+//! The application implements [`Policy`] and installs it with [`set_policy`]. Controllers check it with [`can`],
+//! and templates with the `can` function, so navigation and buttons are shown only to users who can use them:
+//!
+//! ```html
+//! <% if can("update", post) %>
+//!     <a href="/posts/<%= post.id %>/edit">Edit</a>
+//! <% end %>
+//! ```
+//!
+//! Resources are passed to the policy as template values, so a model is a [`Value::Hash`] of its columns,
+//! whether the check is made in a controller or a template.
+//!
+//! In templates, checks that fail, e.g. because the action is unknown, are errors in debug builds,
+//! so mistakes are caught during development, and deny the action in release builds. Identical checks made
+//! while rendering a template are evaluated once.
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use thiserror::Error;
+
+use std::sync::Arc;
+
+use crate::flags::Subject;
+use crate::http::Request;
+use crate::view::template::{Error as TemplateError, ToTemplateValue, Value};
+
+static POLICY: Lazy<RwLock<Option<Arc<dyn Policy>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Authorization errors.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Error {
+    #[error("no authorization policy is set")]
+    NoPolicy,
+
+    #[error("unknown action \"{0}\"")]
+    UnknownAction(String),
+
+    #[error("invalid resource: {0}")]
+    InvalidResource(String),
+
+    #[error("{0}")]
+    Template(String),
+}
+
+impl From<TemplateError> for Error {
+    fn from(error: TemplateError) -> Self {
+        Error::Template(error.to_string())
+    }
+}
+
+/// Rules deciding which actions users can perform on resources.
+///
+/// # Example
+///
+/// ```
+/// use rwf::controller::policy::{Error, Policy};
+/// use rwf::view::Value;
+///
+/// struct Posts;
+///
+/// impl Policy for Posts {
+///     fn can(&self, user_id: Option<i64>, action: &str, resource: &Value) -> Result<bool, Error> {
+///         let author_id = match resource {
+///             Value::Hash(post) => post.get("author_id").cloned(),
+///             _ => None,
+///         };
+///
+///         match action {
+///             "read" => Ok(true),
+///             "update" | "delete" => Ok(user_id.is_some() && author_id == user_id.map(Value::Integer)),
+///             action => Err(Error::UnknownAction(action.to_string())),
+///         }
+///     }
+/// }
+/// ```
+pub trait Policy: Send + Sync + 'static {
+    /// Can the user perform the action on the resource? `user_id` is `None` for visitors who aren't logged in,
+    /// and `resource` is [`Value::Null`] for actions that don't apply to a resource, e.g. `can("create_post")`.
+    fn can(&self, user_id: Option<i64>, action: &str, resource: &Value) -> Result<bool, Error>;
+}
+
+/// Install the policy, replacing the current one.
+pub fn set_policy(policy: impl Policy) {
+    *POLICY.write() = Some(Arc::new(policy));
+}
+
+/// Can the user making the request perform the action on the resource?
+pub fn can(
+    request: &Request,
+    action: &str,
+    resource: &impl ToTemplateValue,
+) -> Result<bool, Error> {
+    check(
+        Subject::from_request(request).user_id,
+        action,
+        &resource.to_template_value()?,
+    )
+}
+
+/// Can the user perform the action on the resource?
+pub fn check(user_id: Option<i64>, action: &str, resource: &Value) -> Result<bool, Error> {
+    let policy = POLICY.read().clone();
+
+    match policy {
+        Some(policy) => policy.can(user_id, action, resource),
+        None => Err(Error::NoPolicy),
+    }
+}
+
+/// Can the user making the request currently being handled by this task perform the action?
+/// Used by the `can` template function.
+pub fn check_current(action: &str, resource: &Value) -> Result<bool, Error> {
+    check(
+        Subject::current().and_then(|subject| subject.user_id),
+        action,
+        resource,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::view::Template;
+
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CHECKS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Roles {
+        admins: Vec<i64>,
+    }
+
+    impl Policy for Roles {
+        fn can(&self, user_id: Option<i64>, action: &str, resource: &Value) -> Result<bool, Error> {
+            CHECKS.fetch_add(1, Ordering::SeqCst);
+
+            let admin = user_id.is_some_and(|id| self.admins.contains(&id));
+            let author = match resource {
+                Value::Hash(post) => post.get("author_id") == user_id.map(Value::Integer).as_ref(),
+                _ => false,
+            };
+
+            match action {
+                "update" => Ok(admin || author),
+                "delete" | "admin" => Ok(admin),
+                action => Err(Error::UnknownAction(action.to_string())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_policy() {
+        let template = Template::from_str(
+            r#"<% if can("admin") %><nav>Admin</nav><% end %><% for post in posts %><%= post.title %><% if can("update", post) %> [edit]<% end %><% if can("delete", post) %> [delete]<% end %><% if can("update", post) %>.<% end %>
+<% end %>"#,
+        )
+        .unwrap();
+
+        let post = |title: &str, author_id: i64| {
+            Value::Hash(HashMap::from([
+                ("title".to_string(), Value::String(title.into())),
+                ("author_id".to_string(), Value::Integer(author_id)),
+            ]))
+        };
+        let context = [(
+            "posts",
+            Value::List(vec![post("First", 1), post("Second", 2)]),
+        )];
+
+        // No policy.
+        assert_eq!(check(Some(1), "update", &Value::Null), Err(Error::NoPolicy));
+
+        set_policy(Roles { admins: vec![1] });
+
+        let render = |user_id: i64| {
+            Subject::user(user_id).scope(async { template.render(context.clone()).unwrap() })
+        };
+
+        CHECKS.store(0, Ordering::SeqCst);
+        let admin = render(1).await;
+        // The repeated `can("update", post)` is cached.
+        assert_eq!(CHECKS.load(Ordering::SeqCst), 5);
+
+        let author = render(2).await;
+        assert_eq!(CHECKS.load(Ordering::SeqCst), 10);
+
+        assert_eq!(
+            admin,
+            "<nav>Admin</nav>First [edit] [delete].\nSecond [edit] [delete].\n"
+        );
+        assert_eq!(author, "First\nSecond [edit].\n");
+        assert_ne!(admin, author);
+
+        // Visitors who aren't logged in.
+        let guest = template.render(context.clone()).unwrap();
+        assert_eq!(guest, "First\nSecond\n");
+
+        // Unknown actions are errors in debug builds.
+        let template = Template::from_str(r#"<% if can("publish") %>publish<% end %>"#).unwrap();
+        let err = template.render_default().unwrap_err();
+        assert!(
+            err.to_string().contains(r#"unknown action "publish""#),
+            "{}",
+            err
+        );
+
+        // Controllers check the policy with the same values.
+        let request = Request::default();
+        assert!(!can(&request, "update", &post("First", 1)).unwrap());
+        let err: crate::controller::Error =
+            can(&request, "publish", &Value::Null).unwrap_err().into();
+        assert_eq!(
+            err.to_string(),
+            r#"authorization error: unknown action "publish""#
+        );
+        assert_eq!(
+            check(
+                Some(2),
+                "update",
+                &post("Second", 2).to_template_value().unwrap()
+            ),
+            Ok(true)
+        );
+    }
+}
@@ -60,8 +60,8 @@ impl Controller for RackController {
         let loaded = self.loaded.clone();
 
         let req_path = request.path().path().to_string();
-        let method = request.method().to_string();
-        let query = request.query().to_string();
+        let method = request.original_method().to_string();
+        let query = request.path().query().to_string();
         let req_uri = format!("{}{}", req_path, query);
         let body = request.body().to_vec();
         let content_type = request
@@ -54,6 +54,10 @@ impl StaticFiles {
 
 #[async_trait]
 impl Controller for StaticFiles {
+    fn database_free(&self) -> bool {
+        true
+    }
+
     async fn handle(&self, request: &Request) -> Result<Response, Error> {
         let path = request.path().to_std();
 
@@ -102,7 +106,11 @@ impl Controller for StaticFiles {
 
                 let response = Response::new();
 
-                Ok(response.body((path, file, metadata)))
+                Ok(response
+                    .body((path, file, metadata))
+                    .cacheable()
+                    .conditional(request)
+                    .range(request))
             }
             Err(_) => return Ok(Response::not_found()),
         }
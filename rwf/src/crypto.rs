@@ -1,13 +1,17 @@
 //! Cryptography wrappers, using AES-128.
 //!
 //! Can encrypt/decrypt arbitrary data using the application secret key.
+//! Data encrypted with one of the previous secret keys, configured in `previous_secret_keys`,
+//! can still be decrypted, so the secret key can be rotated.
 use aes_gcm_siv::{
     aead::{Aead, KeyInit},
-    Aes128GcmSiv, Nonce,
+    Aes128GcmSiv, Key, Nonce,
 };
 use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
 use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use time::OffsetDateTime;
 
@@ -73,11 +77,43 @@ impl Encrypted {
 /// let ciphertext = encrypt(b"hello world").expect("encryption failed");
 /// ```
 pub fn encrypt(data: &[u8]) -> Result<String, Error> {
-    let config = get_config();
-    let nonce = nonce();
+    encrypt_with_nonce(&get_config().general.aes_key, data, nonce())
+}
 
-    let key = config.general.aes_key;
-    let cipher = Aes128GcmSiv::new(&key);
+/// Encrypt some bytes so the same data always produces the same ciphertext.
+///
+/// This allows to look up encrypted data by equality, but reveals which values are equal
+/// to anyone who can read the ciphertexts. Use [`encrypt`] unless lookups are needed.
+///
+/// # Example
+///
+/// ```
+/// use rwf::crypto::{decrypt, encrypt_deterministic};
+///
+/// let ciphertext = encrypt_deterministic(b"hello world").expect("encryption failed");
+///
+/// assert_eq!(ciphertext, encrypt_deterministic(b"hello world").unwrap());
+/// assert_eq!(decrypt(&ciphertext).unwrap(), b"hello world");
+/// ```
+pub fn encrypt_deterministic(data: &[u8]) -> Result<String, Error> {
+    let key = get_config().general.aes_key;
+
+    // The nonce is derived from the data and the key. AES-GCM-SIV
+    // doesn't reveal anything besides equality when nonces are reused.
+    let mut hasher = Sha256::new();
+    hasher.update(key);
+    hasher.update(data);
+    let nonce = hasher.finalize()[0..96 / 8].to_vec();
+
+    encrypt_with_nonce(&key, data, nonce)
+}
+
+fn encrypt_with_nonce(
+    key: &Key<Aes128GcmSiv>,
+    data: &[u8],
+    nonce: Vec<u8>,
+) -> Result<String, Error> {
+    let cipher = Aes128GcmSiv::new(key);
     let aes_nonce = Nonce::from_slice(&nonce); // 96-bits; unique per message
     let ciphertext = cipher
         .encrypt(aes_nonce, data)
@@ -86,16 +122,40 @@ pub fn encrypt(data: &[u8]) -> Result<String, Error> {
     Encrypted { ciphertext, nonce }.to_bytes()
 }
 
+/// Decrypt data encrypted with [`encrypt`] or [`encrypt_deterministic`], using the current secret key,
+/// or one of the previous ones.
 pub fn decrypt(data: &str) -> Result<Vec<u8>, Error> {
-    let config = get_config();
+    let general = &get_config().general;
+    let keys = std::iter::once(&general.aes_key).chain(general.previous_aes_keys.iter());
+
+    decrypt_with_keys(data, keys)
+}
+
+fn decrypt_with_keys<'a>(
+    data: &str,
+    keys: impl Iterator<Item = &'a Key<Aes128GcmSiv>>,
+) -> Result<Vec<u8>, Error> {
     let encrypted = Encrypted::from_base64(data)?;
 
-    let key = config.general.aes_key;
-    let cipher = Aes128GcmSiv::new(&key);
+    if encrypted.nonce.len() != 96 / 8 {
+        return Err(Error::Generic("incorrect nonce length"));
+    }
+
     let aes_nonce = Nonce::from_slice(&encrypted.nonce);
-    let plaintext = cipher.decrypt(aes_nonce, encrypted.ciphertext.as_ref())?;
+    let mut result = Err(Error::Generic("no encryption key configured"));
 
-    Ok(plaintext)
+    for key in keys {
+        let cipher = Aes128GcmSiv::new(key);
+        result = cipher
+            .decrypt(aes_nonce, encrypted.ciphertext.as_ref())
+            .map_err(Error::from);
+
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    result
 }
 
 pub fn encrypt_number(n: i64) -> Result<String, Error> {
@@ -170,6 +230,65 @@ pub fn decrypt_number(s: &str) -> Result<i64, Error> {
     Ok(i64::from_be_bytes(plaintext.try_into().unwrap()))
 }
 
+/// Sign some bytes with HMAC-SHA256, using the global configured secret key.
+///
+/// The signature is encoded with URL-safe base64, so it can be used in links.
+///
+/// # Example
+///
+/// ```
+/// use rwf::crypto::{sign, verify_signature};
+///
+/// let signature = sign(b"hello world");
+///
+/// assert!(verify_signature(b"hello world", &signature));
+/// assert!(!verify_signature(b"hello world!", &signature));
+/// ```
+pub fn sign(data: &[u8]) -> String {
+    let key = get_config()
+        .general
+        .secret_key()
+        .expect("secret key is validated when the config is loaded");
+
+    sign_with_key(&key, data)
+}
+
+/// Check a signature created with [`sign`], using the current secret key, or one of the previous ones.
+pub fn verify_signature(data: &[u8], signature: &str) -> bool {
+    let general = &get_config().general;
+    let keys = general
+        .secret_key()
+        .into_iter()
+        .chain(general.previous_secret_keys().unwrap_or_default());
+
+    verify_signature_with_keys(data, signature, keys)
+}
+
+fn sign_with_key(key: &[u8], data: &[u8]) -> String {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("hmac accepts keys of any size");
+    mac.update(data);
+    general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+fn verify_signature_with_keys(
+    data: &[u8],
+    signature: &str,
+    keys: impl Iterator<Item = Vec<u8>>,
+) -> bool {
+    let signature = match general_purpose::URL_SAFE_NO_PAD.decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+
+    keys.into_iter().any(|key| {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(&key).expect("hmac accepts keys of any size");
+        mac.update(data);
+        mac.verify_slice(&signature).is_ok()
+    })
+}
+
 /// Generate a random string of length n.
 pub fn random_string(n: usize) -> String {
     rand::thread_rng()
@@ -179,6 +298,9 @@ pub fn random_string(n: usize) -> String {
         .collect()
 }
 
+/// How far apart the clocks of app instances can be.
+const CLOCK_SKEW: time::Duration = time::Duration::minutes(1);
+
 /// Generate a CSRF protection token.
 pub fn csrf_token() -> Result<String, Error> {
     // Our encryption is salted, re-using some known plain text isn't an issue.
@@ -211,7 +333,11 @@ pub fn csrf_token_validate(token: &str) -> bool {
                 return false;
             }
 
-            (OffsetDateTime::now_utc() - created_at) < get_config().general.session_duration()
+            let age = OffsetDateTime::now_utc() - created_at;
+
+            // Tokens from the future were not generated by us, unless
+            // the clocks of our servers are slightly out of sync.
+            age > -CLOCK_SKEW && age < get_config().general.session_duration()
         }
         Err(_) => false,
     }
@@ -229,6 +355,76 @@ mod test {
         assert_eq!(text, String::from_utf8_lossy(&plain));
     }
 
+    #[test]
+    fn test_decrypt_rotated_key() {
+        let old = Key::<Aes128GcmSiv>::clone_from_slice(&[1; 16]);
+        let new = Key::<Aes128GcmSiv>::clone_from_slice(&[2; 16]);
+
+        let cipher = encrypt_with_nonce(&old, b"secret", nonce()).unwrap();
+
+        assert!(decrypt_with_keys(&cipher, [&new].into_iter()).is_err());
+        assert_eq!(
+            decrypt_with_keys(&cipher, [&new, &old].into_iter()).unwrap(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    fn test_verify_rotated_signature() {
+        let old = vec![1; 32];
+        let new = vec![2; 32];
+
+        let signature = sign_with_key(&old, b"data");
+
+        assert!(!verify_signature_with_keys(
+            b"data",
+            &signature,
+            [new.clone()].into_iter()
+        ));
+        assert!(verify_signature_with_keys(
+            b"data",
+            &signature,
+            [new, old.clone()].into_iter()
+        ));
+        assert!(!verify_signature_with_keys(
+            b"date",
+            &signature,
+            [old.clone()].into_iter()
+        ));
+        assert!(!verify_signature_with_keys(
+            b"data",
+            "not base64!",
+            [old].into_iter()
+        ));
+    }
+
+    #[test]
+    fn test_encrypt_deterministic() {
+        let cipher = encrypt_deterministic(b"secret").unwrap();
+
+        assert_eq!(cipher, encrypt_deterministic(b"secret").unwrap());
+        assert_ne!(cipher, encrypt_deterministic(b"secret!").unwrap());
+        assert_ne!(cipher, encrypt(b"secret").unwrap());
+        assert_eq!(decrypt(&cipher).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_csrf_token() {
+        let token = |created_at: OffsetDateTime| {
+            encrypt(format!("{}_csrf", created_at.unix_timestamp()).as_bytes()).unwrap()
+        };
+        let now = OffsetDateTime::now_utc();
+
+        assert!(csrf_token_validate(&csrf_token().unwrap()));
+        assert!(csrf_token_validate(&token(
+            now + time::Duration::seconds(10)
+        )));
+        assert!(!csrf_token_validate(&token(now + time::Duration::hours(1))));
+        assert!(!csrf_token_validate(&token(
+            now - get_config().general.session_duration() - time::Duration::seconds(1)
+        )));
+    }
+
     #[test]
     fn test_encrypt_number() {
         let n = 2345;
@@ -0,0 +1,820 @@
+//! Startup self-check, finding configuration problems before the application is deployed.
+//!
+//! The doctor checks that the configuration file is valid and uses secure settings, the databases
+//! are reachable and migrated, the templates parse, no routes are shadowed by other routes, and the static files
+//! folder exists:
+//!
+//! ```rust,ignore
+//! let report = Doctor::new()
+//!     .routes(vec![route!("/" => Index)])
+//!     .templates("templates")
+//!     .static_files("static")
+//!     .run()
+//!     .await;
+//!
+//! println!("{}", report);
+//! std::process::exit(report.exit_code());
+//! ```
+//!
+//! Checks never panic or exit the process, so the doctor can be wrapped in a CLI command. Applications can add
+//! their own checks by implementing the [`Diagnostic`] trait.
+use async_trait::async_trait;
+use serde::Serialize;
+use time::Duration;
+
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, CONFIG_FILES};
+use crate::http::{Handler, Router};
+use crate::model::migrations::MigrationStatus;
+use crate::model::{Migrations, Pool};
+use crate::view::template::lint::registered_shape;
+use crate::view::Template;
+
+/// Secret keys published in the Rwf examples and documentation.
+static EXAMPLE_SECRET_KEYS: &[&str] = &["TRtZ2Ww4EeY3xfA82Bo9bNCQbkLiUZmiDO6wOE0W0qw="];
+
+/// Result of a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Everything is fine.
+    Pass,
+    /// The application will work, but probably not as intended.
+    Warn,
+    /// The application won't work correctly.
+    Fail,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Status::Pass => write!(f, "PASS"),
+            Status::Warn => write!(f, "WARN"),
+            Status::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// A check performed by the doctor.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    /// What was checked, e.g. `"config"`.
+    pub name: String,
+    /// Result of the check.
+    pub status: Status,
+    /// Explanation of the result.
+    pub message: String,
+}
+
+impl Check {
+    /// Check passed.
+    pub fn pass(name: impl ToString, message: impl ToString) -> Self {
+        Self::new(name, Status::Pass, message)
+    }
+
+    /// Check found a problem that doesn't prevent the application from working.
+    pub fn warn(name: impl ToString, message: impl ToString) -> Self {
+        Self::new(name, Status::Warn, message)
+    }
+
+    /// Check failed.
+    pub fn fail(name: impl ToString, message: impl ToString) -> Self {
+        Self::new(name, Status::Fail, message)
+    }
+
+    fn new(name: impl ToString, status: Status, message: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Check added by the application, run after the built-in checks.
+#[async_trait]
+pub trait Diagnostic: Send + Sync {
+    /// Perform the check.
+    async fn check(&self) -> Check;
+}
+
+/// Results of all checks, in the order they were performed.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Worst result of all checks.
+    pub fn status(&self) -> Status {
+        self.checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(Status::Pass)
+    }
+
+    /// None of the checks failed. Warnings are allowed.
+    pub fn is_ok(&self) -> bool {
+        self.status() != Status::Fail
+    }
+
+    /// Checks with this result.
+    pub fn filter(&self, status: Status) -> Vec<&Check> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == status)
+            .collect()
+    }
+
+    /// Process exit code for a CLI command: `1` if any of the checks failed, `0` otherwise.
+    pub fn exit_code(&self) -> i32 {
+        if self.is_ok() {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn push(&mut self, check: Check) {
+        self.checks.push(check);
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let width = self
+            .checks
+            .iter()
+            .map(|check| check.name.len())
+            .max()
+            .unwrap_or(0);
+
+        for check in &self.checks {
+            writeln!(
+                f,
+                "{}  {:width$}  {}",
+                check.status,
+                check.name,
+                check.message,
+                width = width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run all checks with the default settings. See [`Doctor::new`].
+pub async fn run() -> Report {
+    Doctor::new().run().await
+}
+
+/// Startup self-check. See the [module documentation](self) for an example.
+pub struct Doctor {
+    config: Option<PathBuf>,
+    database: bool,
+    handlers: Vec<Handler>,
+    templates: Vec<PathBuf>,
+    static_files: Option<PathBuf>,
+    diagnostics: Vec<Box<dyn Diagnostic>>,
+}
+
+impl Default for Doctor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Doctor {
+    /// Check the configuration file found in the current directory (e.g. `rwf.toml`), the databases,
+    /// and the `"static"` folder.
+    pub fn new() -> Self {
+        Self {
+            config: None,
+            database: true,
+            handlers: vec![],
+            templates: vec![],
+            static_files: None,
+            diagnostics: vec![],
+        }
+    }
+
+    /// Check this configuration file instead of the default one.
+    pub fn config(mut self, path: impl AsRef<Path>) -> Self {
+        self.config = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Don't connect to the databases.
+    pub fn without_database(mut self) -> Self {
+        self.database = false;
+        self
+    }
+
+    /// Check that these routes aren't shadowed by each other.
+    pub fn routes(mut self, handlers: Vec<Handler>) -> Self {
+        self.handlers.extend(handlers);
+        self
+    }
+
+    /// Check that all templates in this directory parse.
+    pub fn templates(mut self, path: impl AsRef<Path>) -> Self {
+        self.templates.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Check that the static files are served from this folder. Default is `"static"`.
+    pub fn static_files(mut self, path: impl AsRef<Path>) -> Self {
+        self.static_files = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Add a check performed by the application.
+    pub fn check(mut self, diagnostic: impl Diagnostic + 'static) -> Self {
+        self.diagnostics.push(Box::new(diagnostic));
+        self
+    }
+
+    /// Perform all checks.
+    pub async fn run(self) -> Report {
+        let mut report = Report::default();
+
+        if let Some(config) = self.check_config(&mut report) {
+            Self::check_secret_keys(&config, &mut report);
+            Self::check_session_duration(&config, &mut report);
+
+            if self.database {
+                Self::check_databases(&config, &mut report).await;
+            }
+        }
+
+        for path in &self.templates {
+            Self::check_templates(path, &mut report);
+        }
+
+        if !self.handlers.is_empty() {
+            Self::check_routes(self.handlers, &mut report);
+        }
+
+        Self::check_static_files(self.static_files.as_deref(), &mut report);
+
+        for diagnostic in &self.diagnostics {
+            report.push(diagnostic.check().await);
+        }
+
+        report
+    }
+
+    /// Load the configuration file. The other configuration checks need it to be valid.
+    fn check_config(&self, report: &mut Report) -> Option<(Config, toml::Table)> {
+        let path = match self.config {
+            Some(ref path) => path.clone(),
+            None => match CONFIG_FILES
+                .iter()
+                .map(Path::new)
+                .find(|path| path.is_file())
+            {
+                Some(path) => path.to_owned(),
+                None => {
+                    report.push(Check::warn(
+                        "config",
+                        "configuration file not found, using environment variables and defaults",
+                    ));
+                    return Some((Config::default(), toml::Table::new()));
+                }
+            },
+        };
+
+        let file = match std::fs::read_to_string(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                report.push(Check::fail(
+                    "config",
+                    format!("\"{}\" can't be read: {}", path.display(), err),
+                ));
+                return None;
+            }
+        };
+
+        let table = match file.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(err) => {
+                report.push(Check::fail(
+                    "config",
+                    format!(
+                        "\"{}\" is not valid TOML: {}",
+                        path.display(),
+                        err.message()
+                    ),
+                ));
+                return None;
+            }
+        };
+
+        match Config::load(path.as_path()) {
+            Ok(config) => {
+                report.push(Check::pass(
+                    "config",
+                    format!("\"{}\" loaded", path.display()),
+                ));
+                Some((config, table))
+            }
+
+            Err(err) => {
+                report.push(Check::fail(
+                    "config",
+                    format!("\"{}\" is not valid: {}", path.display(), err),
+                ));
+                None
+            }
+        }
+    }
+
+    fn check_secret_keys((config, table): &(Config, toml::Table), report: &mut Report) {
+        let configured = table
+            .get("general")
+            .and_then(|general| general.get("secret_key"))
+            .is_some()
+            || std::env::var("RWF_SECRET_KEY").is_ok();
+
+        let examples = EXAMPLE_SECRET_KEYS
+            .iter()
+            .map(|key| decode_key(key))
+            .collect::<Vec<_>>();
+
+        let check = match config.general.secret_key() {
+            Err(err) => Check::fail("secret key", err),
+            Ok(_) if !configured => Check::warn(
+                "secret key",
+                "secret_key is not set, a random key is used and sessions won't survive a restart",
+            ),
+            Ok(key) if examples.contains(&key) => Check::fail(
+                "secret key",
+                "secret_key is copied from the documentation, generate a new one",
+            ),
+            Ok(key) => match config.general.previous_secret_keys() {
+                Err(err) => Check::fail("secret key", format!("previous_secret_keys: {}", err)),
+                Ok(previous) if previous.iter().any(|previous| examples.contains(previous)) => {
+                    Check::warn(
+                        "secret key",
+                        "previous_secret_keys contains a key copied from the documentation",
+                    )
+                }
+                Ok(previous) if previous.contains(&key) => Check::warn(
+                    "secret key",
+                    "previous_secret_keys contains the current secret_key",
+                ),
+                Ok(_) => Check::pass("secret key", "secret_key is valid"),
+            },
+        };
+
+        report.push(check);
+    }
+
+    fn check_session_duration((config, _): &(Config, toml::Table), report: &mut Report) {
+        let duration = config.general.session_duration();
+
+        let check = if duration <= Duration::ZERO {
+            Check::fail("session duration", "sessions expire immediately")
+        } else if duration < Duration::minutes(5) {
+            Check::warn(
+                "session duration",
+                format!("sessions expire after {} seconds", duration.whole_seconds()),
+            )
+        } else if duration > Duration::days(365) {
+            Check::warn(
+                "session duration",
+                format!("sessions expire after {} days", duration.whole_days()),
+            )
+        } else {
+            Check::pass(
+                "session duration",
+                format!("sessions expire after {} minutes", duration.whole_minutes()),
+            )
+        };
+
+        report.push(check);
+    }
+
+    async fn check_databases((config, _): &(Config, toml::Table), report: &mut Report) {
+        for database in config.database.names() {
+            let name = format!("database \"{}\"", database);
+
+            let url = match config.database.named(&database) {
+                Some(database) => database.database_url(),
+                None => {
+                    report.push(Check::fail(name, "not configured correctly"));
+                    continue;
+                }
+            };
+
+            if let Err(err) = Pool::from_url(&url).get().await {
+                report.push(Check::fail(name, format!("unavailable: {}", err)));
+                continue;
+            }
+
+            report.push(Check::pass(name, "reachable"));
+
+            let name = format!("migrations \"{}\"", database);
+
+            let migrations = match Migrations::sync_database(&database).await {
+                Ok(migrations) => migrations,
+                Err(err) => {
+                    report.push(Check::fail(name, err));
+                    continue;
+                }
+            };
+
+            if let Err(err) = migrations.verify() {
+                report.push(Check::fail(name, err));
+                continue;
+            }
+
+            let pending = migrations
+                .migrations()
+                .iter()
+                .filter(|migration| migrations.status(migration) == MigrationStatus::Pending)
+                .map(|migration| migration.name())
+                .collect::<Vec<_>>();
+
+            if pending.is_empty() {
+                report.push(Check::pass(name, "all migrations applied"));
+            } else {
+                report.push(Check::fail(
+                    name,
+                    format!("pending: {}", pending.join(", ")),
+                ));
+            }
+        }
+    }
+
+    fn check_templates(path: &Path, report: &mut Report) {
+        let name = format!("templates \"{}\"", path.display());
+
+        if !path.is_dir() {
+            report.push(Check::fail(name, "directory not found"));
+            return;
+        }
+
+        let mut loaded = 0;
+        let mut errors = vec![];
+        let mut warnings = vec![];
+        Self::load_templates(path, &mut loaded, &mut errors, &mut warnings);
+
+        if errors.is_empty() && warnings.is_empty() {
+            report.push(Check::pass(name, format!("{} templates loaded", loaded)));
+        } else if errors.is_empty() {
+            report.push(Check::warn(name, warnings.join("\n")));
+        } else {
+            report.push(Check::fail(name, errors.join("\n")));
+        }
+    }
+
+    fn load_templates(
+        path: &Path,
+        loaded: &mut usize,
+        errors: &mut Vec<String>,
+        warnings: &mut Vec<String>,
+    ) {
+        if path.is_dir() {
+            match std::fs::read_dir(path) {
+                Ok(entries) => {
+                    for entry in entries.flatten() {
+                        Self::load_templates(&entry.path(), loaded, errors, warnings);
+                    }
+                }
+                Err(err) => errors.push(format!("\"{}\": {}", path.display(), err)),
+            }
+        } else {
+            match Template::load(path) {
+                Ok(template) => {
+                    *loaded += 1;
+
+                    // Templates without a registered shape aren't linted.
+                    if let Some(shape) = registered_shape(path) {
+                        warnings.extend(template.lint(&shape).iter().map(|w| w.to_string()));
+                    }
+                }
+                Err(err) => errors.push(format!("\"{}\": {}", path.display(), err)),
+            }
+        }
+    }
+
+    fn check_routes(handlers: Vec<Handler>, report: &mut Report) {
+        let router = match Router::new(handlers) {
+            Ok(router) => router,
+            Err(err) => {
+                report.push(Check::fail("routes", err));
+                return;
+            }
+        };
+
+        let routes = router.report().routes;
+        let shadowed = routes
+            .iter()
+            .filter_map(|route| {
+                route
+                    .shadowed_by
+                    .as_ref()
+                    .map(|other| format!("\"{}\" is shadowed by \"{}\"", route.path, other))
+            })
+            .collect::<Vec<_>>();
+
+        if shadowed.is_empty() {
+            report.push(Check::pass(
+                "routes",
+                format!("{} routes, all reachable", routes.len()),
+            ));
+        } else {
+            report.push(Check::fail("routes", shadowed.join(", ")));
+        }
+    }
+
+    fn check_static_files(path: Option<&Path>, report: &mut Report) {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new("static"), false),
+        };
+        let name = format!("static files \"{}\"", path.display());
+
+        if path.is_dir() {
+            report.push(Check::pass(name, "directory found"));
+        } else if required {
+            report.push(Check::fail(name, "directory not found"));
+        } else {
+            report.push(Check::warn(name, "directory not found"));
+        }
+    }
+}
+
+fn decode_key(key: &str) -> Vec<u8> {
+    use base64::{engine::general_purpose, Engine as _};
+    general_purpose::STANDARD.decode(key).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::Controller;
+    use std::fs::{create_dir, write};
+    use tempdir::TempDir;
+
+    static SECRET_KEY: &str = "1sOzs1hcQ1mAiu2IeSCc9BXyx7QPxWUfBXsKSU5zBH8=";
+
+    #[derive(Default)]
+    struct Index;
+
+    #[async_trait]
+    impl Controller for Index {
+        async fn handle(
+            &self,
+            _request: &crate::http::Request,
+        ) -> Result<crate::http::Response, crate::controller::Error> {
+            Ok(crate::http::Response::new())
+        }
+    }
+
+    struct Disk;
+
+    #[async_trait]
+    impl Diagnostic for Disk {
+        async fn check(&self) -> Check {
+            Check::warn("disk", "90% full")
+        }
+    }
+
+    fn scratch(config: &str) -> (TempDir, Doctor) {
+        let dir = TempDir::new("rwf_doctor").unwrap();
+        let path = dir.path().join("rwf.toml");
+        write(&path, config).unwrap();
+        create_dir(dir.path().join("static")).unwrap();
+        let doctor = Doctor::new()
+            .config(&path)
+            .static_files(dir.path().join("static"))
+            .without_database();
+        (dir, doctor)
+    }
+
+    fn find<'a>(report: &'a Report, name: &str) -> &'a Check {
+        report
+            .checks
+            .iter()
+            .find(|check| check.name == name)
+            .unwrap_or_else(|| panic!("check \"{}\" not performed:\n{}", name, report))
+    }
+
+    #[tokio::test]
+    async fn test_doctor_pass() {
+        let (dir, doctor) = scratch(&format!(
+            "[general]\nsecret_key = \"{}\"\nsession_duration = 3600000\n",
+            SECRET_KEY
+        ));
+        write(dir.path().join("index.html"), "<%= title %>").unwrap();
+
+        let report = doctor
+            .templates(dir.path())
+            .routes(vec![
+                Index::default().route("/"),
+                Index::default().route("/users"),
+            ])
+            .run()
+            .await;
+
+        assert_eq!(report.status(), Status::Pass, "{}", report);
+        assert_eq!(report.exit_code(), 0);
+        assert_eq!(
+            find(&report, "session duration").message,
+            "sessions expire after 60 minutes"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_doctor_config_errors() {
+        for (config, message) in [
+            ("[general\n", "is not valid TOML"),
+            (
+                "[general]\nsecret_key = \"dG9vIHNob3J0\"\n",
+                "incorrect length",
+            ),
+            ("[general]\nsecret_key = \"not base64!\"\n", "not valid"),
+            (
+                &format!(
+                    "[general]\nsecret_key = \"{}\"\ndefault_timezone = \"Mars\"\n",
+                    SECRET_KEY
+                ),
+                "time zone",
+            ),
+        ] {
+            let (_dir, doctor) = scratch(config);
+            let report = doctor.run().await;
+            let check = find(&report, "config");
+
+            assert_eq!(check.status, Status::Fail, "{}", config);
+            assert!(check.message.contains(message), "{}", check.message);
+            assert!(!report.is_ok());
+            assert_eq!(report.exit_code(), 1);
+
+            // Checks which need the configuration aren't performed.
+            assert!(report.checks.iter().all(|check| check.name != "secret key"));
+        }
+
+        let report = Doctor::new()
+            .config("/does/not/exist/rwf.toml")
+            .without_database()
+            .run()
+            .await;
+        assert!(find(&report, "config").message.contains("can't be read"));
+    }
+
+    #[tokio::test]
+    async fn test_doctor_secret_keys() {
+        let example = EXAMPLE_SECRET_KEYS[0];
+
+        for (config, status, message) in [
+            (
+                format!("[general]\nsecret_key = \"{}\"\n", example),
+                Status::Fail,
+                "copied from the documentation",
+            ),
+            (
+                format!(
+                    "[general]\nsecret_key = \"{}\"\nprevious_secret_keys = [\"{}\"]\n",
+                    SECRET_KEY, example
+                ),
+                Status::Warn,
+                "previous_secret_keys contains a key copied",
+            ),
+            (
+                format!(
+                    "[general]\nsecret_key = \"{}\"\nprevious_secret_keys = [\"{}\"]\n",
+                    SECRET_KEY, SECRET_KEY
+                ),
+                Status::Warn,
+                "contains the current secret_key",
+            ),
+        ] {
+            let (_dir, doctor) = scratch(&config);
+            let report = doctor.run().await;
+            let check = find(&report, "secret key");
+
+            assert_eq!(check.status, status, "{}", report);
+            assert!(check.message.contains(message), "{}", check.message);
+        }
+
+        if std::env::var("RWF_SECRET_KEY").is_err() {
+            let (_dir, doctor) = scratch("[general]\n");
+            let report = doctor.run().await;
+            let check = find(&report, "secret key");
+            assert_eq!(check.status, Status::Warn);
+            assert!(check.message.contains("random key"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_doctor_session_duration() {
+        for (duration, status) in [
+            (0, Status::Fail),
+            (1000, Status::Warn),
+            (400 * 24 * 3600 * 1000_usize, Status::Warn),
+            (24 * 3600 * 1000, Status::Pass),
+        ] {
+            let (_dir, doctor) = scratch(&format!(
+                "[general]\nsecret_key = \"{}\"\nsession_duration = {}\n",
+                SECRET_KEY, duration
+            ));
+            let report = doctor.run().await;
+            assert_eq!(find(&report, "session duration").status, status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_doctor_database_unavailable() {
+        let (_dir, doctor) = scratch(&format!(
+            "[general]\nsecret_key = \"{}\"\n\n[database]\nurl = \"postgres://rwf@127.0.0.1:1/rwf\"\n",
+            SECRET_KEY
+        ));
+        let mut doctor = doctor;
+        doctor.database = true;
+
+        let report = doctor.run().await;
+        let check = find(&report, "database \"main\"");
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.message.starts_with("unavailable"));
+        assert!(report
+            .checks
+            .iter()
+            .all(|check| !check.name.starts_with("migrations")));
+    }
+
+    #[tokio::test]
+    async fn test_doctor_templates() {
+        let (dir, doctor) = scratch(&format!("[general]\nsecret_key = \"{}\"\n", SECRET_KEY));
+        let templates = dir.path().join("templates");
+        create_dir(&templates).unwrap();
+        create_dir(templates.join("users")).unwrap();
+        write(templates.join("index.html"), "<%= title %>").unwrap();
+        write(templates.join("users/edit.html"), "<% if %>").unwrap();
+
+        let report = doctor
+            .templates(&templates)
+            .templates(dir.path().join("missing"))
+            .run()
+            .await;
+
+        let check = find(&report, &format!("templates \"{}\"", templates.display()));
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.message.contains("edit.html"), "{}", check.message);
+        assert!(!check.message.contains("index.html"));
+
+        let check = find(
+            &report,
+            &format!("templates \"{}\"", dir.path().join("missing").display()),
+        );
+        assert_eq!(check.message, "directory not found");
+    }
+
+    #[tokio::test]
+    async fn test_doctor_routes() {
+        let (_dir, doctor) = scratch(&format!("[general]\nsecret_key = \"{}\"\n", SECRET_KEY));
+        let report = doctor
+            .routes(vec![
+                Index::default().route("/users/:id"),
+                Index::default().route("/users/:user_id"),
+                Index::default().route("/"),
+            ])
+            .run()
+            .await;
+
+        let check = find(&report, "routes");
+        assert_eq!(check.status, Status::Fail);
+        assert!(
+            check.message.contains("is shadowed by"),
+            "{}",
+            check.message
+        );
+    }
+
+    #[tokio::test]
+    async fn test_doctor_static_files() {
+        let (dir, doctor) = scratch(&format!("[general]\nsecret_key = \"{}\"\n", SECRET_KEY));
+        let missing = dir.path().join("public");
+        let report = doctor.static_files(&missing).run().await;
+
+        let check = find(&report, &format!("static files \"{}\"", missing.display()));
+        assert_eq!(check.status, Status::Fail);
+    }
+
+    #[tokio::test]
+    async fn test_doctor_custom_check() {
+        let (_dir, doctor) = scratch(&format!("[general]\nsecret_key = \"{}\"\n", SECRET_KEY));
+        let report = doctor.check(Disk).run().await;
+
+        assert_eq!(report.checks.last().unwrap().name, "disk");
+        assert_eq!(report.status(), Status::Warn);
+        assert!(report.is_ok());
+        assert_eq!(report.filter(Status::Warn).len(), 1);
+        assert!(report.to_string().contains("WARN  disk"));
+    }
+}
@@ -0,0 +1,293 @@
+//! Error reporting.
+//!
+//! Errors that would otherwise only end up in the logs are forwarded to an [`ErrorReporter`]. This includes
+//! controller errors that result in a 5xx response, panics caught by the HTTP server, and
+//! background jobs that failed their last retry.
+//!
+//! By default, reports are written to the log using [`TracingReporter`]. Applications can send them
+//! somewhere else, e.g. Sentry, by installing their own reporter at startup:
+//!
+//! ```
+//! use rwf::errors::{set_reporter, ErrorReport, ErrorReporter};
+//!
+//! struct Stderr;
+//!
+//! #[rwf::async_trait]
+//! impl ErrorReporter for Stderr {
+//!     async fn report(&self, report: &ErrorReport) {
+//!         eprintln!("{}", report.message);
+//!     }
+//! }
+//!
+//! set_reporter(Stderr);
+//! ```
+//!
+//! Reporting is fire-and-forget: each report is handled in a background Tokio task and never delays the response
+//! sent to the client.
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tracing::error;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::http::{redact, Request};
+
+#[cfg(feature = "sentry")]
+pub mod sentry;
+
+static REPORTER: Lazy<RwLock<Arc<dyn ErrorReporter>>> =
+    Lazy::new(|| RwLock::new(Arc::new(TracingReporter)));
+
+/// Receives error reports.
+#[async_trait]
+pub trait ErrorReporter: Send + Sync {
+    /// Handle the report. This runs in a background task.
+    async fn report(&self, report: &ErrorReport);
+}
+
+/// What caused the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Controller returned an error resulting in a 5xx response.
+    Controller,
+    /// A panic caught by the server or the jobs worker.
+    Panic,
+    /// Background job failed its last retry.
+    Job,
+}
+
+/// Request that caused the error, with sensitive headers removed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportedRequest {
+    /// HTTP method.
+    pub method: String,
+    /// Request path, without the query string.
+    pub path: String,
+    /// Request headers, without cookies and credentials.
+    pub headers: BTreeMap<String, String>,
+    /// Authenticated user, if any.
+    pub user_id: Option<i64>,
+}
+
+impl From<&Request> for ReportedRequest {
+    fn from(request: &Request) -> Self {
+        let headers = request
+            .headers()
+            .iter()
+            .filter(|(name, _)| !redact::sensitive_header(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        Self {
+            method: request.method().to_string(),
+            path: request.path().path().to_string(),
+            headers,
+            user_id: request.user_id().ok(),
+        }
+    }
+}
+
+/// Error report.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    /// What caused the report.
+    pub kind: ErrorKind,
+    /// Error message.
+    pub message: String,
+    /// Messages of the errors in the source chain, starting with the immediate cause.
+    pub sources: Vec<String>,
+    /// Request that caused the error, if any.
+    pub request: Option<ReportedRequest>,
+    /// Additional context, e.g. controller name or job id.
+    pub tags: BTreeMap<String, String>,
+    /// When the error happened.
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+}
+
+impl ErrorReport {
+    /// Create a report from an error, collecting its source chain.
+    pub fn new(kind: ErrorKind, error: &(dyn std::error::Error + 'static)) -> Self {
+        let mut sources = vec![];
+        let mut source = error.source();
+
+        while let Some(err) = source {
+            sources.push(err.to_string());
+            source = err.source();
+        }
+
+        Self {
+            sources,
+            ..Self::message(kind, error.to_string())
+        }
+    }
+
+    /// Create a report from a message, e.g. a panic payload.
+    pub fn message(kind: ErrorKind, message: impl ToString) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+            sources: vec![],
+            request: None,
+            tags: BTreeMap::new(),
+            timestamp: OffsetDateTime::now_utc(),
+        }
+    }
+
+    /// Attach the request to the report. The `x-request-id` header, if set, is added as the `request_id` tag.
+    pub fn request(mut self, request: &Request) -> Self {
+        if let Some(request_id) = request.header("x-request-id") {
+            self.tags
+                .insert("request_id".to_string(), request_id.to_string());
+        }
+        self.request = Some(ReportedRequest::from(request));
+        self
+    }
+
+    /// Add a tag to the report.
+    pub fn tag(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.tags.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Send the report to the installed reporter in the background.
+    pub fn send(self) {
+        report(self)
+    }
+}
+
+/// Reporter which writes reports to the log. This is the default reporter.
+#[derive(Debug, Default, Clone)]
+pub struct TracingReporter;
+
+#[async_trait]
+impl ErrorReporter for TracingReporter {
+    async fn report(&self, report: &ErrorReport) {
+        let sources = report
+            .sources
+            .iter()
+            .map(|source| format!(": {}", source))
+            .collect::<String>();
+        let request = report
+            .request
+            .as_ref()
+            .map(|request| format!(" [{} {}]", request.method, request.path))
+            .unwrap_or_default();
+        let tags = report
+            .tags
+            .iter()
+            .map(|(name, value)| format!(" {}={}", name, value))
+            .collect::<String>();
+
+        error!(
+            "{:?} error{}{}: {}{}",
+            report.kind, request, tags, report.message, sources
+        );
+    }
+}
+
+/// Install the error reporter, replacing the current one.
+pub fn set_reporter(reporter: impl ErrorReporter + 'static) {
+    *REPORTER.write() = Arc::new(reporter);
+}
+
+/// Send the report to the installed reporter.
+///
+/// The reporter is executed in a background task. If called outside of the Tokio runtime,
+/// the report is dropped.
+pub fn report(report: ErrorReport) {
+    let reporter = REPORTER.read().clone();
+
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        runtime.spawn(async move {
+            reporter.report(&report).await;
+        });
+    }
+}
+
+/// Get a printable message from a panic payload.
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+    struct Channel(UnboundedSender<ErrorReport>);
+
+    #[async_trait]
+    impl ErrorReporter for Channel {
+        async fn report(&self, report: &ErrorReport) {
+            let _ = self.0.send(report.clone());
+        }
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("outer")]
+    struct Outer(#[source] std::io::Error);
+
+    #[tokio::test]
+    async fn test_report() {
+        let (tx, mut rx) = unbounded_channel();
+        set_reporter(Channel(tx));
+
+        let request = "GET /orders?page=1 HTTP/1.1\r\n".to_owned()
+            + "Cookie: theme=dark\r\n"
+            + "Authorization: Bearer 1234\r\n"
+            + "X-Request-Id: abc\r\n"
+            + "Accept: */*\r\n"
+            + "Content-Length: 0\r\n\r\n";
+        let request = Request::read("127.0.0.1:1234".parse().unwrap(), request.as_bytes())
+            .await
+            .unwrap();
+        let error = Outer(std::io::Error::other("disk full"));
+
+        ErrorReport::new(ErrorKind::Controller, &error)
+            .request(&request)
+            .tag("controller", "Orders")
+            .send();
+
+        // Other tests may be reporting errors at the same time.
+        let report = loop {
+            let report = rx.recv().await.unwrap();
+            if report.message == "outer" {
+                break report;
+            }
+        };
+        set_reporter(TracingReporter);
+
+        assert_eq!(report.kind, ErrorKind::Controller);
+        assert_eq!(report.message, "outer");
+        assert_eq!(report.sources, vec!["disk full".to_string()]);
+        assert_eq!(report.tags["controller"], "Orders");
+        assert_eq!(report.tags["request_id"], "abc");
+
+        let request = report.request.unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/orders");
+        assert_eq!(request.user_id, None);
+        assert!(request.headers.contains_key("accept"));
+        assert!(!request.headers.contains_key("cookie"));
+        assert!(!request.headers.contains_key("authorization"));
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = std::panic::catch_unwind(|| panic!("boom {}", 1)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "boom 1");
+    }
+}
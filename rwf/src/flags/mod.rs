@@ -0,0 +1,439 @@
+//! Feature flags.
+//!
+//! Flags are defined in the `[flags]` section of the configuration. A flag can be on or off for everyone,
+//! on for a percentage of users, and always on for some users:
+//!
+//! ```toml
+//! [flags.new_checkout]
+//! percentage = 25
+//! users = [1, 42]
+//!
+//! [flags.dark_mode]
+//! enabled = true
+//! ```
+//!
+//! Controllers check flags with [`enabled`], and templates with `flag_enabled("new_checkout")`.
+//!
+//! Users are assigned to the percentage with a stable hash of the flag name and their user ID,
+//! or their session ID if they aren't logged in, so they see the same thing on every request, and
+//! increasing the percentage only adds users.
+//!
+//! With `flag_overrides` enabled, flags can be changed at runtime with [`set_override`]. Overrides are stored
+//! in the `rwf_feature_flags` table, replace the flag defined in the configuration, and are
+//! reloaded every [`CACHE_TTL`].
+//!
+//! Every check is logged at the `info` level with the `rwf::flags` target, so exposures
+//! can be analyzed later.
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::task_local;
+use tracing::{info, warn};
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::config::get_config;
+use crate::http::Request;
+use crate::model::{Error, Model, Pool};
+
+pub mod model;
+pub use model::FlagOverride;
+
+/// How long overrides are cached before they are loaded from the database again.
+pub const CACHE_TTL: Duration = Duration::from_secs(10);
+
+static OVERRIDES: Lazy<RwLock<Overrides>> = Lazy::new(|| RwLock::new(Overrides::default()));
+static WARNED: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+task_local! {
+    static SUBJECT: Subject;
+}
+
+/// Feature flag rules.
+///
+/// A flag is on for users in `users`. For other users, it's on for `percentage` of them if it's set,
+/// and for everyone if `enabled` is set otherwise.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Flag {
+    /// On for everyone, unless `percentage` is set.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Percentage of users, between 0 and 100, the flag is on for.
+    #[serde(default)]
+    pub percentage: Option<u8>,
+    /// Users the flag is always on for.
+    #[serde(default)]
+    pub users: Vec<i64>,
+}
+
+impl Flag {
+    /// Flag on for everyone.
+    pub fn on() -> Self {
+        Self {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    /// Flag off for everyone.
+    pub fn off() -> Self {
+        Self::default()
+    }
+
+    /// Flag on for a percentage of users.
+    pub fn percentage(percentage: u8) -> Self {
+        Self {
+            percentage: Some(percentage.min(100)),
+            ..Default::default()
+        }
+    }
+
+    /// Flag also on for these users.
+    pub fn users(mut self, users: &[i64]) -> Self {
+        self.users = users.to_vec();
+        self
+    }
+
+    /// Check the flag for the user or visitor.
+    pub fn enabled_for(&self, name: &str, subject: &Subject) -> bool {
+        if let Some(user_id) = subject.user_id {
+            if self.users.contains(&user_id) {
+                return true;
+            }
+        }
+
+        match self.percentage {
+            Some(percentage) => match subject.key() {
+                Some(key) => bucket(name, &key) < percentage as u64,
+                None => percentage >= 100,
+            },
+            None => self.enabled,
+        }
+    }
+}
+
+/// User or visitor flags are checked for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Subject {
+    /// Logged in user.
+    pub user_id: Option<i64>,
+    /// Session of a visitor who isn't logged in.
+    pub session_id: Option<String>,
+}
+
+impl Subject {
+    /// Logged in user.
+    pub fn user(user_id: i64) -> Self {
+        Self {
+            user_id: Some(user_id),
+            session_id: None,
+        }
+    }
+
+    /// Visitor with this session.
+    pub fn guest(session_id: impl ToString) -> Self {
+        Self {
+            user_id: None,
+            session_id: Some(session_id.to_string()),
+        }
+    }
+
+    /// The user or visitor making the request.
+    pub fn from_request(request: &Request) -> Self {
+        match request.user_id() {
+            Ok(user_id) => Self::user(user_id),
+            Err(_) => Self {
+                user_id: None,
+                session_id: request.session_id().map(|id| id.to_string()),
+            },
+        }
+    }
+
+    /// The user or visitor of the request currently being handled by this task.
+    pub fn current() -> Option<Self> {
+        SUBJECT.try_with(|subject| subject.clone()).ok()
+    }
+
+    /// Run the future with this subject set as [`Subject::current`].
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        SUBJECT.scope(self, future).await
+    }
+
+    /// Key used to place the subject into the percentage.
+    fn key(&self) -> Option<String> {
+        match (self.user_id, &self.session_id) {
+            (Some(user_id), _) => Some(format!("user:{}", user_id)),
+            (None, Some(session_id)) => Some(format!("session:{}", session_id)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Stable bucket, between 0 and 99, of the subject for this flag.
+fn bucket(name: &str, key: &str) -> u64 {
+    let hash = Sha256::new()
+        .chain_update(name.as_bytes())
+        .chain_update(b":")
+        .chain_update(key.as_bytes())
+        .finalize();
+    let bytes: [u8; 8] = hash[..8].try_into().expect("8 bytes");
+
+    u64::from_be_bytes(bytes) % 100
+}
+
+#[derive(Default)]
+struct Overrides {
+    flags: HashMap<String, Flag>,
+    loaded_at: Option<Instant>,
+    loading: bool,
+}
+
+/// Is the flag on for the user making the request?
+///
+/// # Example
+///
+/// ```rust,ignore
+/// if flags::enabled("new_checkout", request) {
+///     return render!(request, "templates/checkout_v2.html");
+/// }
+/// ```
+pub fn enabled(name: &str, request: &Request) -> bool {
+    check(name, &Subject::from_request(request))
+}
+
+/// Is the flag on for the user or visitor? Unknown flags are off.
+pub fn check(name: &str, subject: &Subject) -> bool {
+    reload_if_stale();
+
+    let enabled = match find(name, &OVERRIDES.read().flags, &get_config().flags) {
+        Some(flag) => flag.enabled_for(name, subject),
+        None => {
+            if WARNED.lock().insert(name.to_string()) {
+                warn!("feature flag \"{}\" is not defined, it's off", name);
+            }
+            false
+        }
+    };
+
+    info!(
+        target: "rwf::flags",
+        flag = name,
+        user_id = subject.user_id,
+        session_id = subject.session_id.as_deref(),
+        enabled,
+        "flag exposure"
+    );
+
+    enabled
+}
+
+/// Is the flag on for the request currently being handled by this task? Used by the
+/// `flag_enabled` template function.
+pub fn check_current(name: &str) -> bool {
+    check(name, &Subject::current().unwrap_or_default())
+}
+
+/// The flag, with overrides taking precedence over the configuration.
+fn find(
+    name: &str,
+    overrides: &HashMap<String, Flag>,
+    config: &HashMap<String, Flag>,
+) -> Option<Flag> {
+    overrides.get(name).or_else(|| config.get(name)).cloned()
+}
+
+/// Change the flag at runtime. The override is saved in the database
+/// and replaces the flag defined in the configuration.
+pub async fn set_override(name: &str, flag: Flag) -> Result<(), Error> {
+    let mut conn = Pool::connection().await?;
+    let record = match FlagOverride::find_by("name", name)
+        .fetch_optional(&mut conn)
+        .await?
+    {
+        Some(record) => record.update(&flag),
+        None => FlagOverride::new(name, &flag),
+    };
+    record.save().execute(&mut conn).await?;
+
+    OVERRIDES.write().flags.insert(name.to_string(), flag);
+
+    Ok(())
+}
+
+/// Remove the override, going back to the flag defined in the configuration.
+pub async fn remove_override(name: &str) -> Result<(), Error> {
+    let mut conn = Pool::connection().await?;
+    FlagOverride::all()
+        .filter("name", name)
+        .delete_all()
+        .execute(&mut conn)
+        .await?;
+
+    OVERRIDES.write().flags.remove(name);
+
+    Ok(())
+}
+
+/// Load the overrides from the database.
+pub async fn reload() -> Result<(), Error> {
+    let mut conn = Pool::connection().await?;
+    let records = FlagOverride::all().fetch_all(&mut conn).await?;
+
+    let mut overrides = OVERRIDES.write();
+    overrides.flags = records
+        .iter()
+        .map(|record| (record.name.clone(), record.flag()))
+        .collect();
+    overrides.loaded_at = Some(Instant::now());
+
+    Ok(())
+}
+
+/// Reload overrides in the background if they are older than [`CACHE_TTL`].
+/// The current ones are used until the new ones are loaded.
+fn reload_if_stale() {
+    if !get_config().general.flag_overrides {
+        return;
+    }
+
+    {
+        let mut overrides = OVERRIDES.write();
+        let fresh = overrides
+            .loaded_at
+            .map(|loaded_at| loaded_at.elapsed() < CACHE_TTL)
+            .unwrap_or(false);
+
+        if fresh || overrides.loading {
+            return;
+        }
+
+        overrides.loading = true;
+    }
+
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            runtime.spawn(async move {
+                if let Err(err) = reload().await {
+                    warn!("feature flag overrides failed to load: {}", err);
+                    // Try again after the TTL.
+                    OVERRIDES.write().loaded_at = Some(Instant::now());
+                }
+                OVERRIDES.write().loading = false;
+            });
+        }
+        Err(_) => OVERRIDES.write().loading = false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stable_hashing() {
+        let flag = Flag::percentage(30);
+
+        // Same answer on every check.
+        for user_id in 0..100 {
+            let subject = Subject::user(user_id);
+            let enabled = flag.enabled_for("checkout", &subject);
+            for _ in 0..5 {
+                assert_eq!(flag.enabled_for("checkout", &subject), enabled);
+            }
+        }
+
+        // About the right share of users.
+        let enabled = (0..10_000)
+            .filter(|user_id| flag.enabled_for("checkout", &Subject::user(*user_id)))
+            .count();
+        assert!((2_700..3_300).contains(&enabled), "{}", enabled);
+
+        // Increasing the percentage only adds users.
+        let more = Flag::percentage(60);
+        for user_id in 0..1_000 {
+            let subject = Subject::user(user_id);
+            if flag.enabled_for("checkout", &subject) {
+                assert!(more.enabled_for("checkout", &subject));
+            }
+        }
+
+        // Flags are independent of each other.
+        let same = (0..1_000)
+            .filter(|user_id| {
+                let subject = Subject::user(*user_id);
+                flag.enabled_for("checkout", &subject) == flag.enabled_for("search", &subject)
+            })
+            .count();
+        assert!(same < 1_000);
+
+        let guest = Subject::guest("abc");
+        assert_eq!(
+            flag.enabled_for("checkout", &guest),
+            flag.enabled_for("checkout", &Subject::guest("abc"))
+        );
+        assert!(!flag.enabled_for("checkout", &Subject::default()));
+        assert!(Flag::percentage(100).enabled_for("checkout", &Subject::default()));
+        assert!(!Flag::percentage(0).enabled_for("checkout", &guest));
+    }
+
+    #[test]
+    fn test_flag_rules() {
+        let user = Subject::user(5);
+        assert!(Flag::on().enabled_for("a", &user));
+        assert!(!Flag::off().enabled_for("a", &user));
+        assert!(Flag::off().users(&[5]).enabled_for("a", &user));
+        assert!(Flag::percentage(0).users(&[5]).enabled_for("a", &user));
+        assert!(!Flag::off().users(&[5]).enabled_for("a", &Subject::user(6)));
+        assert_eq!(Flag::percentage(150).percentage, Some(100));
+
+        let config: HashMap<String, Flag> = toml::from_str(
+            r#"
+            [new_checkout]
+            percentage = 25
+            users = [1, 42]
+
+            [dark_mode]
+            enabled = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config["new_checkout"], Flag::percentage(25).users(&[1, 42]));
+        assert_eq!(config["dark_mode"], Flag::on());
+    }
+
+    #[test]
+    fn test_override_precedence() {
+        let config = HashMap::from([
+            ("beta".to_string(), Flag::on()),
+            ("search".to_string(), Flag::on()),
+        ]);
+        let overrides = HashMap::from([("beta".to_string(), Flag::off().users(&[1]))]);
+
+        assert_eq!(
+            find("beta", &overrides, &config),
+            Some(Flag::off().users(&[1]))
+        );
+        assert_eq!(find("search", &overrides, &config), Some(Flag::on()));
+        assert_eq!(find("missing", &overrides, &config), None);
+
+        // Overrides changed at runtime are used right away.
+        let name = "test_override_precedence";
+        assert!(!check(name, &Subject::user(2)));
+        OVERRIDES.write().flags.insert(name.into(), Flag::on());
+        assert!(check(name, &Subject::user(2)));
+        OVERRIDES.write().flags.remove(name);
+
+        // Unknown flags are off.
+        assert!(!check(name, &Subject::user(2)));
+        assert!(WARNED.lock().contains(name));
+
+        let record = FlagOverride::new("beta", &Flag::percentage(10).users(&[3]));
+        assert_eq!(record.flag(), Flag::percentage(10).users(&[3]));
+        let record = record.update(&Flag::on());
+        assert_eq!(record.flag(), Flag::on());
+        assert_eq!(record.name, "beta");
+    }
+}
@@ -0,0 +1,100 @@
+//! The `"rwf_feature_flags"` model record.
+use crate::model::{Error, FromRow, GetColumn, Model, ToValue, Value};
+use time::OffsetDateTime;
+
+use super::Flag;
+
+/// Flag changed at runtime, which replaces the flag defined in the configuration.
+#[derive(Clone, Debug)]
+pub struct FlagOverride {
+    id: Option<i64>,
+    /// Flag name.
+    pub name: String,
+    /// Flag is on for everyone not covered by the other rules.
+    pub enabled: bool,
+    /// Percentage of users the flag is on for.
+    pub percentage: Option<i32>,
+    /// Users the flag is always on for.
+    pub users: serde_json::Value,
+    /// When the override was last changed.
+    pub updated_at: OffsetDateTime,
+}
+
+impl FlagOverride {
+    /// Create an override for the flag.
+    pub fn new(name: &str, flag: &Flag) -> Self {
+        Self {
+            id: None,
+            name: name.to_string(),
+            enabled: flag.enabled,
+            percentage: flag.percentage.map(|percentage| percentage as i32),
+            users: serde_json::json!(flag.users),
+            updated_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    /// Replace the rules with the ones of the flag.
+    pub fn update(mut self, flag: &Flag) -> Self {
+        let id = self.id.take();
+        Self {
+            id,
+            ..Self::new(&self.name, flag)
+        }
+    }
+
+    /// The flag defined by this override.
+    pub fn flag(&self) -> Flag {
+        Flag {
+            enabled: self.enabled,
+            percentage: self
+                .percentage
+                .map(|percentage| percentage.clamp(0, 100) as u8),
+            users: serde_json::from_value(self.users.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+impl FromRow for FlagOverride {
+    fn from_row(row: tokio_postgres::Row) -> Result<Self, Error> {
+        Ok(Self {
+            id: row.get_column("id")?,
+            name: row.get_column("name")?,
+            enabled: row.get_column("enabled")?,
+            percentage: row.get_column("percentage")?,
+            users: row.get_column("users")?,
+            updated_at: row.get_column("updated_at")?,
+        })
+    }
+}
+
+impl Model for FlagOverride {
+    fn primary_key() -> &'static str {
+        "id"
+    }
+
+    fn table_name() -> &'static str {
+        "rwf_feature_flags"
+    }
+
+    fn foreign_key() -> &'static str {
+        "rwf_feature_flag_id"
+    }
+
+    fn id(&self) -> Value {
+        self.id.to_value()
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.name.to_value(),
+            self.enabled.to_value(),
+            self.percentage.to_value(),
+            self.users.to_value(),
+            self.updated_at.to_value(),
+        ]
+    }
+
+    fn column_names() -> &'static [&'static str] {
+        &["name", "enabled", "percentage", "users", "updated_at"]
+    }
+}
@@ -0,0 +1,19 @@
+use rwf::prelude::*;
+
+#[derive(Default, macros::RestController)]
+pub struct BlogPosts;
+
+#[async_trait]
+impl RestController for BlogPosts {
+    type Resource = i64;
+
+    /// GET /blog_posts
+    async fn list(&self, _request: &Request) -> Result<Response, Error> {
+        Ok(Response::not_implemented())
+    }
+
+    /// GET /blog_posts/:id
+    async fn get(&self, _request: &Request, _id: &i64) -> Result<Response, Error> {
+        Ok(Response::not_implemented())
+    }
+}
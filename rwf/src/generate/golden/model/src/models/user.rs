@@ -0,0 +1,10 @@
+use rwf::prelude::*;
+
+#[derive(Clone, macros::Model)]
+pub struct User {
+    pub id: Option<i64>,
+    pub email: String,
+    pub active: bool,
+    pub bio: Option<String>,
+    pub created_at: OffsetDateTime,
+}
@@ -0,0 +1,46 @@
+use rwf::prelude::*;
+
+use crate::models::User;
+
+#[derive(Default, macros::RestController)]
+pub struct Users;
+
+#[async_trait]
+impl RestController for Users {
+    type Resource = i64;
+
+    /// GET /users
+    async fn list(&self, _request: &Request) -> Result<Response, Error> {
+        let mut conn = Pool::connection().await?;
+        let users = User::all().order("id").fetch_all(&mut conn).await?;
+
+        render!("templates/users/index.html", "users" => users)
+    }
+
+    /// GET /users/:id
+    async fn get(&self, _request: &Request, id: &i64) -> Result<Response, Error> {
+        let mut conn = Pool::connection().await?;
+        let user = match User::find(*id).fetch_optional(&mut conn).await? {
+            Some(user) => user,
+            None => return Ok(Response::not_found()),
+        };
+
+        render!("templates/users/show.html", "user" => user)
+    }
+
+    /// POST /users
+    async fn create(&self, request: &Request) -> Result<Response, Error> {
+        let form = request.form_data()?;
+        let mut conn = Pool::connection().await?;
+
+        let user = User::create(&[
+            ("email", form.get_required::<String>("email")?.to_value()),
+            ("active", form.get::<bool>("active").unwrap_or_default().to_value()),
+            ("bio", form.get::<String>("bio").to_value()),
+        ])
+        .fetch(&mut conn)
+        .await?;
+
+        Ok(Response::new().redirect(format!("/users/{}", user.id.unwrap_or_default())))
+    }
+}
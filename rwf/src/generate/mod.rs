@@ -0,0 +1,644 @@
+//! Code generators for controllers, models and scaffolds.
+//!
+//! Generators write new files into an Rwf project, which makes them handy to call from build tools and
+//! project setup scripts:
+//!
+//! ```rust,ignore
+//! use rwf::generate;
+//!
+//! // src/controllers/users.rs
+//! generate::controller("users")?;
+//!
+//! // src/models/user.rs and a migration creating the "users" table.
+//! generate::model("User", &[("email", "text"), ("active", "bool")])?;
+//! ```
+//!
+//! Generators refuse to overwrite existing files, unless forced with [`Generator::force`]. Each generator returns
+//! a [`Summary`] of the files it wrote, and the routes that should be added to the server.
+//!
+//! #### Column types
+//!
+//! | Type | Rust | PostgreSQL |
+//! |------|------|------------|
+//! | `text`, `string`, `varchar` | `String` | `TEXT` |
+//! | `bool`, `boolean` | `bool` | `BOOLEAN` |
+//! | `int`, `integer`, `bigint` | `i64` | `BIGINT` |
+//! | `float`, `double` | `f64` | `DOUBLE PRECISION` |
+//! | `timestamp`, `timestamptz` | `OffsetDateTime` | `TIMESTAMPTZ` |
+//!
+//! Types ending with `?`, e.g. `text?`, create nullable columns and `Option` fields. Timestamp
+//! columns that aren't nullable default to `NOW()`.
+use regex::Regex;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, write};
+use std::path::{Path, PathBuf};
+
+use crate::view::{Template, ToTemplateValue, Value};
+
+/// Error returned by generators.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("\"{0}\" already exists")]
+    Exists(PathBuf),
+
+    #[error("\"{0}\" is not a valid name")]
+    InvalidName(String),
+
+    #[error("column \"{0}\" has unsupported type \"{1}\"")]
+    UnsupportedType(String, String),
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Template(#[from] crate::view::Error),
+}
+
+/// What the generator did with the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// File didn't exist and was created.
+    Created,
+    /// File existed and was replaced.
+    Overwritten,
+}
+
+/// File written by a generator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedFile {
+    pub path: PathBuf,
+    pub action: Action,
+}
+
+/// Files written by a generator.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    /// Files, in the order they were written.
+    pub files: Vec<GeneratedFile>,
+    /// Routes to add to the server, e.g. `rest!("/users" => controllers::users::Users)`.
+    pub routes: Vec<String>,
+}
+
+impl Summary {
+    /// Paths of all written files.
+    pub fn paths(&self) -> Vec<&Path> {
+        self.files.iter().map(|file| file.path.as_path()).collect()
+    }
+}
+
+/// Generates code inside an Rwf project.
+///
+/// By default, files are written relative to the current directory: controllers to `src/controllers`,
+/// models to `src/models`, migrations to `migrations`, and templates to `templates`.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    root: PathBuf,
+    controllers: PathBuf,
+    models: PathBuf,
+    migrations: PathBuf,
+    templates: PathBuf,
+    force: bool,
+    version: Option<u64>,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new(".")
+    }
+}
+
+/// File to be written.
+struct Output {
+    path: PathBuf,
+    content: String,
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    rust_type: String,
+    sql: String,
+    input: Option<&'static str>,
+    parse: String,
+}
+
+impl Column {
+    fn new(name: &str, ty: &str) -> Result<Self, Error> {
+        let name = identifier(name)?;
+        let (ty, nullable) = match ty.strip_suffix('?') {
+            Some(ty) => (ty, true),
+            None => (ty, false),
+        };
+
+        let (rust_type, sql, input) = match ty.to_lowercase().as_str() {
+            "text" | "string" | "varchar" => ("String", "TEXT", Some(r#"type="text""#)),
+            "bool" | "boolean" => ("bool", "BOOLEAN", Some(r#"type="checkbox" value="true""#)),
+            "int" | "integer" | "bigint" => ("i64", "BIGINT", Some(r#"type="number""#)),
+            "float" | "double" => (
+                "f64",
+                "DOUBLE PRECISION",
+                Some(r#"type="number" step="any""#),
+            ),
+            "timestamp" | "timestamptz" => ("OffsetDateTime", "TIMESTAMPTZ", None),
+            _ => return Err(Error::UnsupportedType(name, ty.to_string())),
+        };
+
+        let (rust_type, sql) = if nullable {
+            (format!("Option<{}>", rust_type), sql.to_string())
+        } else if input.is_none() {
+            (
+                rust_type.to_string(),
+                format!("{} NOT NULL DEFAULT NOW()", sql),
+            )
+        } else {
+            (rust_type.to_string(), format!("{} NOT NULL", sql))
+        };
+
+        // Unchecked checkboxes aren't sent with the form.
+        let parse = if nullable {
+            format!(r#"form.get::<{}>("{}")"#, ty_of(&rust_type), name)
+        } else if ty_of(&rust_type) == "bool" {
+            format!(r#"form.get::<bool>("{}").unwrap_or_default()"#, name)
+        } else {
+            format!(r#"form.get_required::<{}>("{}")?"#, rust_type, name)
+        };
+
+        Ok(Self {
+            name,
+            rust_type,
+            sql,
+            input,
+            parse,
+        })
+    }
+}
+
+impl ToTemplateValue for Column {
+    fn to_template_value(&self) -> Result<Value, crate::view::Error> {
+        let mut hash = HashMap::new();
+        hash.insert("name".into(), self.name.to_template_value()?);
+        hash.insert(
+            "label".into(),
+            crate::capitalize(&self.name.replace('_', " ")).to_template_value()?,
+        );
+        hash.insert("rust_type".into(), self.rust_type.to_template_value()?);
+        hash.insert("sql".into(), self.sql.to_template_value()?);
+        hash.insert(
+            "input".into(),
+            self.input.unwrap_or_default().to_template_value()?,
+        );
+        hash.insert("parse".into(), self.parse.to_template_value()?);
+        Ok(Value::Hash(hash))
+    }
+}
+
+/// Inner type of `Option<T>`.
+fn ty_of(rust_type: &str) -> &str {
+    rust_type
+        .strip_prefix("Option<")
+        .and_then(|ty| ty.strip_suffix('>'))
+        .unwrap_or(rust_type)
+}
+
+/// Names used by the code generated for a model.
+struct Names {
+    /// Model struct, e.g. `User`.
+    model: String,
+    /// Model module and variable, e.g. `user`.
+    singular: String,
+    /// Table name, controller module and variable, e.g. `users`.
+    plural: String,
+}
+
+impl Names {
+    fn new(model: &str) -> Result<Self, Error> {
+        let singular = identifier(model)?;
+        let plural = pluralizer::pluralize(&singular, 2, false);
+
+        Ok(Self {
+            model: crate::pascal_case(&singular),
+            singular,
+            plural,
+        })
+    }
+}
+
+impl Generator {
+    /// Create generator writing files into the project located at `root`.
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+
+        Self {
+            root: root.to_owned(),
+            controllers: root.join("src").join("controllers"),
+            models: root.join("src").join("models"),
+            migrations: root.join("migrations"),
+            templates: root.join("templates"),
+            force: false,
+            version: None,
+        }
+    }
+
+    /// Write controllers into this directory.
+    pub fn controllers(mut self, path: impl AsRef<Path>) -> Self {
+        self.controllers = path.as_ref().to_owned();
+        self
+    }
+
+    /// Write models into this directory.
+    pub fn models(mut self, path: impl AsRef<Path>) -> Self {
+        self.models = path.as_ref().to_owned();
+        self
+    }
+
+    /// Write migrations into this directory.
+    pub fn migrations(mut self, path: impl AsRef<Path>) -> Self {
+        self.migrations = path.as_ref().to_owned();
+        self
+    }
+
+    /// Write templates into this directory.
+    pub fn templates(mut self, path: impl AsRef<Path>) -> Self {
+        self.templates = path.as_ref().to_owned();
+        self
+    }
+
+    /// Overwrite existing files.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Version of generated migrations. Defaults to the current timestamp.
+    pub fn version(mut self, version: u64) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Generate a REST controller with `list` and `get` methods.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the controller, e.g. `users`.
+    ///
+    pub fn controller(&self, name: &str) -> Result<Summary, Error> {
+        let module = identifier(name)?;
+        let name = crate::pascal_case(&module);
+        let path = format!("/{}", module);
+
+        let content = render(
+            include_str!("templates/controller.rs.tpl"),
+            &[("name", name.as_str()), ("path", path.as_str())],
+            &[],
+        )?;
+
+        let route = format!(r#"rest!("{}" => controllers::{}::{})"#, path, module, name);
+
+        self.write(
+            vec![Output {
+                path: self.controllers.join(format!("{}.rs", module)),
+                content,
+            }],
+            vec![route],
+        )
+    }
+
+    /// Generate a model and a migration creating its table.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the model, e.g. `User`.
+    /// * `columns` - Column names and [types](self#column-types), e.g. `[("email", "text")]`. The `id` column is added automatically.
+    ///
+    pub fn model(&self, name: &str, columns: &[(&str, &str)]) -> Result<Summary, Error> {
+        let names = Names::new(name)?;
+        let columns = columns
+            .iter()
+            .map(|(name, ty)| Column::new(name, ty))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.write(self.model_outputs(&names, &columns)?, vec![])
+    }
+
+    /// Generate a model with its migration, a controller using that model, and templates
+    /// for listing, showing, and creating records.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the model, e.g. `User`.
+    /// * `columns` - Column names and [types](self#column-types), e.g. `[("email", "text")]`.
+    ///
+    pub fn scaffold(&self, name: &str, columns: &[(&str, &str)]) -> Result<Summary, Error> {
+        let names = Names::new(name)?;
+        let columns = columns
+            .iter()
+            .map(|(name, ty)| Column::new(name, ty))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fields = columns
+            .iter()
+            .filter(|column| column.input.is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let controller = crate::pascal_case(&names.plural);
+        let path = format!("/{}", names.plural);
+        let templates = self.templates.join(&names.plural);
+        // Templates are loaded relative to the project root.
+        let templates_path = templates
+            .strip_prefix(&self.root)
+            .unwrap_or(&templates)
+            .display()
+            .to_string();
+        let title = crate::pascal_case(&names.plural);
+
+        let variables = [
+            ("name", controller.as_str()),
+            ("model", names.model.as_str()),
+            ("singular", names.singular.as_str()),
+            ("plural", names.plural.as_str()),
+            ("path", path.as_str()),
+            ("templates", templates_path.as_str()),
+            ("title", title.as_str()),
+        ];
+        let lists = [("columns", &columns), ("fields", &fields)];
+
+        let mut outputs = self.model_outputs(&names, &columns)?;
+
+        outputs.push(Output {
+            path: self.controllers.join(format!("{}.rs", names.plural)),
+            content: render(
+                include_str!("templates/scaffold.rs.tpl"),
+                &variables,
+                &lists,
+            )?,
+        });
+
+        for (name, template) in [
+            ("index.html", include_str!("templates/index.html.tpl")),
+            ("show.html", include_str!("templates/show.html.tpl")),
+            ("form.html", include_str!("templates/form.html.tpl")),
+        ] {
+            outputs.push(Output {
+                path: templates.join(name),
+                content: view(&render(template, &variables, &lists)?),
+            });
+        }
+
+        let route = format!(
+            r#"rest!("{}" => controllers::{}::{})"#,
+            path, names.plural, controller
+        );
+
+        self.write(outputs, vec![route])
+    }
+
+    fn model_outputs(&self, names: &Names, columns: &[Column]) -> Result<Vec<Output>, Error> {
+        let version = self
+            .version
+            .unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp_nanos() as u64);
+        let variables = [
+            ("model", names.model.as_str()),
+            ("table", names.plural.as_str()),
+        ];
+        let columns = columns.to_vec();
+        let lists = [("columns", &columns)];
+
+        let mut outputs = vec![Output {
+            path: self.models.join(format!("{}.rs", names.singular)),
+            content: render(include_str!("templates/model.rs.tpl"), &variables, &lists)?,
+        }];
+
+        for (direction, template) in [
+            ("up", include_str!("templates/up.sql.tpl")),
+            ("down", include_str!("templates/down.sql.tpl")),
+        ] {
+            outputs.push(Output {
+                path: self.migrations.join(format!(
+                    "{}_create_{}.{}.sql",
+                    version, names.plural, direction
+                )),
+                content: render(template, &variables, &lists)?,
+            });
+        }
+
+        Ok(outputs)
+    }
+
+    /// Write all files, or none if any of them exist and overwriting isn't allowed.
+    fn write(&self, outputs: Vec<Output>, routes: Vec<String>) -> Result<Summary, Error> {
+        if !self.force {
+            if let Some(output) = outputs.iter().find(|output| output.path.exists()) {
+                return Err(Error::Exists(output.path.clone()));
+            }
+        }
+
+        let mut summary = Summary {
+            files: vec![],
+            routes,
+        };
+
+        for output in outputs {
+            let action = if output.path.exists() {
+                Action::Overwritten
+            } else {
+                Action::Created
+            };
+
+            if let Some(parent) = output.path.parent() {
+                create_dir_all(parent)?;
+            }
+
+            write(&output.path, output.content)?;
+
+            summary.files.push(GeneratedFile {
+                path: output.path,
+                action,
+            });
+        }
+
+        Ok(summary)
+    }
+}
+
+/// Generate a controller in the current directory. See [`Generator::controller`].
+pub fn controller(name: &str) -> Result<Summary, Error> {
+    Generator::default().controller(name)
+}
+
+/// Generate a model in the current directory. See [`Generator::model`].
+pub fn model(name: &str, columns: &[(&str, &str)]) -> Result<Summary, Error> {
+    Generator::default().model(name, columns)
+}
+
+/// Generate a scaffold in the current directory. See [`Generator::scaffold`].
+pub fn scaffold(name: &str, columns: &[(&str, &str)]) -> Result<Summary, Error> {
+    Generator::default().scaffold(name, columns)
+}
+
+fn render(
+    template: &str,
+    variables: &[(&str, &str)],
+    lists: &[(&str, &Vec<Column>)],
+) -> Result<String, Error> {
+    let mut context = crate::view::Context::new();
+
+    for (name, value) in variables {
+        context.set(name, *value)?;
+    }
+
+    for (name, list) in lists {
+        let list = list
+            .iter()
+            .map(|column| column.to_template_value())
+            .collect::<Result<Vec<_>, _>>()?;
+        context.set(name, list)?;
+    }
+
+    Ok(Template::from_str(template)?.render(&context)?)
+}
+
+/// Templates generating views write Rwf tags as `[% %]`, so
+/// they aren't evaluated by the generator.
+fn view(rendered: &str) -> String {
+    rendered.replace("[%", "<%").replace("%]", "%>")
+}
+
+/// Convert the name to snake_case and make sure it's a valid Rust identifier.
+fn identifier(name: &str) -> Result<String, Error> {
+    let identifier = crate::snake_case(name);
+    let valid = Regex::new("^[a-z_][a-z0-9_]*$").unwrap();
+
+    if valid.is_match(&identifier) && identifier != "_" {
+        Ok(identifier)
+    } else {
+        Err(Error::InvalidName(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::read_to_string;
+    use tempdir::TempDir;
+
+    const COLUMNS: &[(&str, &str)] = &[
+        ("email", "text"),
+        ("active", "bool"),
+        ("bio", "text?"),
+        ("created_at", "timestamptz"),
+    ];
+
+    /// Compare generated files to the golden files in `src/generate/golden`.
+    /// Set `RWF_UPDATE_GOLDEN=1` to update the golden files instead.
+    fn check_golden(root: &Path, golden: &str, summary: &Summary) {
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/generate/golden")
+            .join(golden);
+        let update = std::env::var("RWF_UPDATE_GOLDEN").is_ok();
+
+        for path in summary.paths() {
+            let generated = read_to_string(path).unwrap();
+            let golden = golden.join(path.strip_prefix(root).unwrap());
+
+            if update {
+                create_dir_all(golden.parent().unwrap()).unwrap();
+                write(&golden, &generated).unwrap();
+            } else {
+                assert_eq!(
+                    generated,
+                    read_to_string(&golden).unwrap(),
+                    "{} doesn't match golden file",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_controller() {
+        let dir = TempDir::new("generate").unwrap();
+        let generator = Generator::new(dir.path());
+
+        let summary = generator.controller("blog_posts").unwrap();
+        assert_eq!(
+            summary.routes,
+            vec![r#"rest!("/blog_posts" => controllers::blog_posts::BlogPosts)"#]
+        );
+        assert_eq!(summary.files[0].action, Action::Created);
+        check_golden(dir.path(), "controller", &summary);
+
+        assert!(matches!(
+            generator.controller("blog_posts"),
+            Err(Error::Exists(_))
+        ));
+
+        let summary = generator.force(true).controller("blog_posts").unwrap();
+        assert_eq!(summary.files[0].action, Action::Overwritten);
+    }
+
+    #[test]
+    fn test_model() {
+        let dir = TempDir::new("generate").unwrap();
+        let summary = Generator::new(dir.path())
+            .version(1)
+            .model("User", COLUMNS)
+            .unwrap();
+
+        assert_eq!(
+            summary.paths(),
+            vec![
+                dir.path().join("src/models/user.rs"),
+                dir.path().join("migrations/1_create_users.up.sql"),
+                dir.path().join("migrations/1_create_users.down.sql"),
+            ]
+        );
+        assert!(summary.routes.is_empty());
+        check_golden(dir.path(), "model", &summary);
+    }
+
+    #[test]
+    fn test_scaffold() {
+        let dir = TempDir::new("generate").unwrap();
+        let generator = Generator::new(dir.path()).version(1);
+
+        // Nothing is written if any of the files exist.
+        create_dir_all(dir.path().join("src/controllers")).unwrap();
+        write(dir.path().join("src/controllers/users.rs"), "").unwrap();
+        assert!(matches!(
+            generator.scaffold("User", COLUMNS),
+            Err(Error::Exists(_))
+        ));
+        assert!(!dir.path().join("src/models/user.rs").exists());
+
+        let summary = generator.force(true).scaffold("User", COLUMNS).unwrap();
+        assert_eq!(summary.files.len(), 7);
+        assert_eq!(summary.files[3].action, Action::Overwritten);
+        assert_eq!(
+            summary.routes,
+            vec![r#"rest!("/users" => controllers::users::Users)"#]
+        );
+        check_golden(dir.path(), "scaffold", &summary);
+
+        for path in summary.paths() {
+            if path.extension().unwrap() == "html" {
+                Template::new(path).expect("generated template should compile");
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let generator = Generator::new("/nonexistent");
+        assert!(matches!(
+            generator.controller("users; drop"),
+            Err(Error::InvalidName(_))
+        ));
+        assert!(matches!(
+            generator.model("User", &[("email", "blob")]),
+            Err(Error::UnsupportedType(_, _))
+        ));
+    }
+}
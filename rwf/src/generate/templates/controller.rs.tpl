@@ -0,0 +1,19 @@
+use rwf::prelude::*;
+
+#[derive(Default, macros::RestController)]
+pub struct <%- name %>;
+
+#[async_trait]
+impl RestController for <%- name %> {
+    type Resource = i64;
+
+    /// GET <%- path %>
+    async fn list(&self, _request: &Request) -> Result<Response, Error> {
+        Ok(Response::not_implemented())
+    }
+
+    /// GET <%- path %>/:id
+    async fn get(&self, _request: &Request, _id: &i64) -> Result<Response, Error> {
+        Ok(Response::not_implemented())
+    }
+}
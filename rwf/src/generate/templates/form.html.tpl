@@ -0,0 +1,8 @@
+<form method="post" action="<%- path %>">
+  [%= csrf_token() %]
+<% for column in fields %>  <label>
+    <%- column.label %>
+    <input <%- column.input %> name="<%- column.name %>">
+  </label>
+<% end %>  <button type="submit">Create</button>
+</form>
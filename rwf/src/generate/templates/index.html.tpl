@@ -0,0 +1,20 @@
+<h1><%- title %></h1>
+
+<table>
+  <thead>
+    <tr>
+      <th>ID</th>
+<% for column in columns %>      <th><%- column.label %></th>
+<% end %>    </tr>
+  </thead>
+  <tbody>
+    [% for <%- singular %> in <%- plural %> %]
+    <tr>
+      <td><a href="<%- path %>/[%= <%- singular %>.id %]">[%= <%- singular %>.id %]</a></td>
+<% for column in columns %>      <td>[%= <%- singular %>.<%- column.name %> %]</td>
+<% end %>    </tr>
+    [% end %]
+  </tbody>
+</table>
+
+[%% "<%- templates %>/form.html" %]
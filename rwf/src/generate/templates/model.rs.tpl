@@ -0,0 +1,7 @@
+use rwf::prelude::*;
+
+#[derive(Clone, macros::Model)]
+pub struct <%- model %> {
+    pub id: Option<i64>,
+<% for column in columns %>    pub <%- column.name %>: <%- column.rust_type %>,
+<% end %>}
@@ -0,0 +1,44 @@
+use rwf::prelude::*;
+
+use crate::models::<%- model %>;
+
+#[derive(Default, macros::RestController)]
+pub struct <%- name %>;
+
+#[async_trait]
+impl RestController for <%- name %> {
+    type Resource = i64;
+
+    /// GET <%- path %>
+    async fn list(&self, _request: &Request) -> Result<Response, Error> {
+        let mut conn = Pool::connection().await?;
+        let <%- plural %> = <%- model %>::all().order("id").fetch_all(&mut conn).await?;
+
+        render!("<%- templates %>/index.html", "<%- plural %>" => <%- plural %>)
+    }
+
+    /// GET <%- path %>/:id
+    async fn get(&self, _request: &Request, id: &i64) -> Result<Response, Error> {
+        let mut conn = Pool::connection().await?;
+        let <%- singular %> = match <%- model %>::find(*id).fetch_optional(&mut conn).await? {
+            Some(<%- singular %>) => <%- singular %>,
+            None => return Ok(Response::not_found()),
+        };
+
+        render!("<%- templates %>/show.html", "<%- singular %>" => <%- singular %>)
+    }
+
+    /// POST <%- path %>
+    async fn create(&self, request: &Request) -> Result<Response, Error> {
+        let form = request.form_data()?;
+        let mut conn = Pool::connection().await?;
+
+        let <%- singular %> = <%- model %>::create(&[
+<% for column in fields %>            ("<%- column.name %>", <%- column.parse %>.to_value()),
+<% end %>        ])
+        .fetch(&mut conn)
+        .await?;
+
+        Ok(Response::new().redirect(format!("<%- path %>/{}", <%- singular %>.id.unwrap_or_default())))
+    }
+}
@@ -0,0 +1,8 @@
+<h1><%- model %> #[%= <%- singular %>.id %]</h1>
+
+<dl>
+<% for column in columns %>  <dt><%- column.label %></dt>
+  <dd>[%= <%- singular %>.<%- column.name %> %]</dd>
+<% end %></dl>
+
+<a href="<%- path %>">Back to <%- plural %></a>
@@ -0,0 +1,4 @@
+CREATE TABLE <%- table %> (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY<% for column in columns %>,
+    <%- column.name %> <%- column.sql %><% end %>
+);
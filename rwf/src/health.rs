@@ -0,0 +1,106 @@
+//! Health of the application, for liveness and readiness checks used by load balancers and orchestrators.
+//!
+//! The application is ready when it isn't [shutting down](crate::shutdown), and its database is available:
+//!
+//! ```rust,ignore
+//! async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+//!     if rwf::health::ready() {
+//!         Ok(Response::new().text("ok"))
+//!     } else {
+//!         Ok(Response::service_unavailable(5))
+//!     }
+//! }
+//! ```
+//!
+//! ## Database outages
+//!
+//! The database is unavailable while the [application](crate::app) is waiting for it to start, if `startup_grace_period`
+//! is set, or when the circuit breaker of its [pool](crate::model::pool) is open. Until it's back, requests to controllers that
+//! aren't [`database_free`](crate::controller::Controller::database_free) are answered with `503 - Service Unavailable`
+//! and a `Retry-After` header, without running the controller.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::http::{problem, Problem, Request, Response};
+use crate::model::{get_pool, Error};
+use crate::shutdown;
+
+static STARTING: AtomicBool = AtomicBool::new(false);
+
+/// The application hasn't stopped. Liveness checks should use this instead of [`ready`],
+/// so an outage of the database doesn't get the application restarted.
+pub fn live() -> bool {
+    shutdown::phase() != shutdown::Phase::Stopped
+}
+
+/// The application can serve all requests: it isn't shutting down, and the database is available.
+pub fn ready() -> bool {
+    shutdown::ready() && database_available()
+}
+
+/// The application connected to the database when it started, and the circuit breaker
+/// of the database pool is closed.
+pub fn database_available() -> bool {
+    !starting() && get_pool().available()
+}
+
+/// The application is waiting for the database to start.
+pub fn starting() -> bool {
+    STARTING.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_starting(starting: bool) {
+    STARTING.store(starting, Ordering::Relaxed);
+}
+
+/// `503 - Service Unavailable` response sent while the database is unavailable.
+pub(crate) fn unavailable(request: &Request, error: Option<&Error>) -> Response {
+    let retry_after = error
+        .and_then(|error| error.retry_after())
+        .unwrap_or_else(|| get_pool().circuit().cooldown())
+        .max(Duration::from_secs(1))
+        .as_secs();
+
+    if Problem::accepted(request) {
+        Problem::new(503, problem::title(503))
+            .detail("The database is unavailable.")
+            .instance(request.path().path())
+            .extension("retry_after", retry_after)
+            .response()
+            .header("retry-after", retry_after)
+    } else {
+        Response::service_unavailable(retry_after)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::{async_trait, Controller};
+
+    struct Orders;
+
+    #[async_trait]
+    impl Controller for Orders {
+        async fn handle(&self, _request: &Request) -> Result<Response, crate::controller::Error> {
+            Err(Error::DatabaseUnavailable {
+                database: "main".into(),
+                retry_after: Duration::from_secs(7),
+            })?
+        }
+    }
+
+    #[tokio::test]
+    async fn test_database_unavailable() {
+        let request = Request::read(
+            "127.0.0.1:1234".parse().unwrap(),
+            &b"GET /orders HTTP/1.1\r\n\r\n"[..],
+        )
+        .await
+        .unwrap();
+        let response = Orders.handle_internal(request).await.unwrap();
+
+        assert_eq!(response.status().code(), 503);
+        assert_eq!(response.headers().get("retry-after").unwrap(), "7");
+    }
+}
@@ -0,0 +1,381 @@
+//! Content negotiation with the `Accept` header.
+//!
+//! The same controller can respond with HTML to browsers and with JSON to API clients,
+//! by registering a closure for each format. The format the client prefers is rendered:
+//!
+//! ```
+//! use rwf::http::{Error, Request, Response};
+//!
+//! fn show(request: &Request) -> Result<Response, Error> {
+//!     Response::negotiate(request)
+//!         .html(|| Ok(Response::new().html("<h1>Alice</h1>")))
+//!         .json(|| Response::new().json(serde_json::json!({"name": "Alice"})))
+//!         .respond()
+//! }
+//! ```
+//!
+//! Formats are matched by quality (`q`), then by how specific the client's media range is,
+//! so `text/html` is preferred to `text/*`, which is preferred to `*/*`. If the client accepts
+//! several formats equally, the one registered first is used. Requests without an `Accept` header,
+//! or with a header that can't be parsed, get the first registered format.
+use super::{Request, Response};
+
+/// A media range from the `Accept` header, e.g. `text/html;q=0.9`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    media_type: String,
+    params: Vec<(String, String)>,
+    quality: f32,
+}
+
+impl MediaRange {
+    /// Parse the value of the `Accept` header. Media ranges are ordered by quality, then by
+    /// specificity, highest first. Ranges which can't be parsed are skipped.
+    pub fn parse_header(value: &str) -> Vec<Self> {
+        let mut ranges = value.split(',').filter_map(Self::parse).collect::<Vec<_>>();
+
+        // Stable, so ranges the client ranked equally stay in its order.
+        ranges.sort_by(|a, b| {
+            b.quality
+                .total_cmp(&a.quality)
+                .then(b.specificity().cmp(&a.specificity()))
+        });
+
+        ranges
+    }
+
+    /// Parse a single media range, e.g. `application/json;q=0.5`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let media_type = parts.next()?.trim().to_ascii_lowercase();
+
+        let (kind, subtype) = media_type.split_once('/')?;
+        let token = |s: &str| {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_graphic() && !"/()<>@,;:\\\"[]?={}".contains(c))
+        };
+        if !token(kind) || !token(subtype) || (kind == "*" && subtype != "*") {
+            return None;
+        }
+
+        let mut params = vec![];
+        let mut quality = 1.0;
+
+        for param in parts {
+            let (name, value) = param.split_once('=')?;
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim().trim_matches('"');
+
+            if name == "q" {
+                quality = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| (0.0..=1.0).contains(q))?;
+            } else {
+                params.push((name, value.to_string()));
+            }
+        }
+
+        Some(Self {
+            media_type,
+            params,
+            quality,
+        })
+    }
+
+    /// Media type, lowercase, without parameters, e.g. `text/html` or `text/*`.
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// Value of a parameter other than `q`. Parameter names are case-insensitive.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Quality, between 0 and 1. A quality of 0 means the client doesn't accept the media type.
+    pub fn quality(&self) -> f32 {
+        self.quality
+    }
+
+    /// Does this range include the media type, e.g. `text/*` includes `text/html`?
+    pub fn matches(&self, media_type: &str) -> bool {
+        let media_type = media_type.to_ascii_lowercase();
+
+        match self.media_type.as_str() {
+            "*/*" => true,
+            range => match range.strip_suffix("/*") {
+                Some(kind) => media_type
+                    .split_once('/')
+                    .map(|(other, _)| other == kind)
+                    .unwrap_or(false),
+                None => range == media_type,
+            },
+        }
+    }
+
+    /// `*/*` is the least specific, then `type/*`, then `type/subtype`, then ranges with parameters.
+    fn specificity(&self) -> u8 {
+        if self.media_type == "*/*" {
+            0
+        } else if self.media_type.ends_with("/*") {
+            1
+        } else if self.params.is_empty() {
+            2
+        } else {
+            3
+        }
+    }
+}
+
+/// Quality of the media type for the client, from the most specific range which includes it.
+/// `None` if no range includes it.
+fn quality(accepts: &[MediaRange], media_type: &str) -> Option<f32> {
+    accepts
+        .iter()
+        .filter(|range| range.matches(media_type))
+        .max_by_key(|range| range.specificity())
+        .map(|range| range.quality)
+}
+
+type Render<'a, E> = Box<dyn FnOnce() -> Result<Response, E> + 'a>;
+
+/// Response chosen by content negotiation. Created with [`Response::negotiate`].
+pub struct Respond<'a, E> {
+    accepts: Vec<MediaRange>,
+    formats: Vec<(String, Render<'a, E>)>,
+}
+
+impl<'a, E> Respond<'a, E> {
+    /// Negotiate the response to the request.
+    pub fn new(request: &Request) -> Self {
+        Self {
+            accepts: request.accepts(),
+            formats: vec![],
+        }
+    }
+
+    /// Respond with `text/html`.
+    pub fn html(self, render: impl FnOnce() -> Result<Response, E> + 'a) -> Self {
+        self.format("text/html", render)
+    }
+
+    /// Respond with `application/json`.
+    pub fn json(self, render: impl FnOnce() -> Result<Response, E> + 'a) -> Self {
+        self.format("application/json", render)
+    }
+
+    /// Respond with `text/plain`.
+    pub fn text(self, render: impl FnOnce() -> Result<Response, E> + 'a) -> Self {
+        self.format("text/plain", render)
+    }
+
+    /// Respond with `application/xml`.
+    pub fn xml(self, render: impl FnOnce() -> Result<Response, E> + 'a) -> Self {
+        self.format("application/xml", render)
+    }
+
+    /// Respond with any media type, e.g. `text/csv`.
+    pub fn format(
+        mut self,
+        media_type: impl ToString,
+        render: impl FnOnce() -> Result<Response, E> + 'a,
+    ) -> Self {
+        self.formats
+            .push((media_type.to_string(), Box::new(render)));
+        self
+    }
+
+    /// Media type of the format which will be rendered, if the client accepts any of them.
+    pub fn selected(&self) -> Option<&str> {
+        self.select().map(|index| self.formats[index].0.as_str())
+    }
+
+    /// Render the format the client prefers. If the client doesn't accept any of them,
+    /// respond with `406 - Not Acceptable`.
+    pub fn respond(self) -> Result<Response, E> {
+        self.fallback(|| Ok(Response::not_acceptable()))
+    }
+
+    /// Render the format the client prefers. If the client doesn't accept any of them,
+    /// render the fallback instead.
+    pub fn fallback(
+        mut self,
+        render: impl FnOnce() -> Result<Response, E> + 'a,
+    ) -> Result<Response, E> {
+        let response = match self.select() {
+            Some(index) => (self.formats.swap_remove(index).1)()?,
+            None => render()?,
+        };
+
+        Ok(response.vary("accept"))
+    }
+
+    fn select(&self) -> Option<usize> {
+        if self.formats.is_empty() {
+            return None;
+        }
+
+        // Missing or malformed header: the client accepts anything.
+        if self.accepts.is_empty() {
+            return Some(0);
+        }
+
+        let mut best: Option<(usize, f32)> = None;
+
+        for (index, (media_type, _)) in self.formats.iter().enumerate() {
+            let quality = quality(&self.accepts, media_type).unwrap_or(0.0);
+
+            if quality > 0.0 && best.map(|(_, best)| quality > best).unwrap_or(true) {
+                best = Some((index, quality));
+            }
+        }
+
+        best.map(|(index, _)| index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::Error;
+
+    fn request(accept: Option<&str>) -> Request {
+        let mut request = Request::default();
+        if let Some(accept) = accept {
+            request.head_mut().headers_mut().insert("accept", accept);
+        }
+        request
+    }
+
+    #[test]
+    fn test_parse() {
+        let accepts = MediaRange::parse_header(
+            "text/*;q=0.5, Text/HTML;level=1, application/json;q=0.9, */*;q=0.1, text/html, bad, image/png;q=2",
+        );
+        let media_types = accepts
+            .iter()
+            .map(|range| (range.media_type(), range.quality()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            media_types,
+            vec![
+                ("text/html", 1.0),
+                ("text/html", 1.0),
+                ("application/json", 0.9),
+                ("text/*", 0.5),
+                ("*/*", 0.1),
+            ]
+        );
+        assert_eq!(accepts[0].param("level"), Some("1"));
+        assert_eq!(accepts[1].param("level"), None);
+
+        assert!(accepts[3].matches("text/plain"));
+        assert!(!accepts[3].matches("application/json"));
+        assert!(accepts[4].matches("image/png"));
+
+        for malformed in [
+            "",
+            "html",
+            "*/json",
+            "text/",
+            "text/html;q=abc",
+            "text/html;q",
+        ] {
+            assert!(MediaRange::parse(malformed).is_none(), "{}", malformed);
+        }
+    }
+
+    fn negotiate(accept: Option<&str>) -> Option<String> {
+        let request = request(accept);
+        let respond = Respond::<Error>::new(&request)
+            .html(|| Ok(Response::new().html("html")))
+            .json(|| Ok(Response::new().json("json")?));
+        let selected = respond.selected().map(|s| s.to_string());
+
+        let response = respond.respond().unwrap();
+        assert_eq!(response.headers().get("vary").unwrap(), "accept");
+
+        if selected.is_none() {
+            assert_eq!(response.status().code(), 406);
+        }
+
+        selected
+    }
+
+    #[test]
+    fn test_negotiate() {
+        for (accept, expected) in [
+            // Browsers.
+            (
+                Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+                Some("text/html"),
+            ),
+            // API clients.
+            (Some("application/json"), Some("application/json")),
+            (
+                Some("application/json, text/html;q=0.5"),
+                Some("application/json"),
+            ),
+            // Wildcards prefer the first registered format.
+            (Some("*/*"), Some("text/html")),
+            (Some("text/*"), Some("text/html")),
+            (Some("application/*"), Some("application/json")),
+            // The most specific range decides.
+            (Some("*/*, text/html;q=0"), Some("application/json")),
+            (Some("text/*;q=0.2, */*;q=0.5"), Some("application/json")),
+            // Missing or malformed.
+            (None, Some("text/html")),
+            (Some(""), Some("text/html")),
+            (Some("garbage;;"), Some("text/html")),
+            // Nothing matches.
+            (Some("image/png"), None),
+            (Some("text/html;q=0, application/json;q=0"), None),
+        ] {
+            assert_eq!(
+                negotiate(accept).as_deref(),
+                expected,
+                "{}",
+                accept.unwrap_or("none")
+            );
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let request = request(Some("text/csv"));
+
+        let response = Response::negotiate(&request)
+            .json(|| Ok::<_, Error>(Response::new().json("json")?))
+            .fallback(|| Ok(Response::new().text("fallback")))
+            .unwrap();
+        assert_eq!(response.status().code(), 200);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/plain"
+        );
+
+        let response = Response::negotiate(&request)
+            .json(|| Ok::<_, Error>(Response::new().json("json")?))
+            .format("text/csv", || {
+                Ok(Response::new()
+                    .text("a,b")
+                    .header("content-type", "text/csv"))
+            })
+            .respond()
+            .unwrap();
+        assert_eq!(response.headers().get("content-type").unwrap(), "text/csv");
+
+        // Only the selected format is rendered.
+        let response = Response::negotiate(&request)
+            .html(|| -> Result<Response, Error> { panic!("not rendered") })
+            .respond()
+            .unwrap();
+        assert_eq!(response.status().code(), 406);
+    }
+}
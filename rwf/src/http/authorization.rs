@@ -14,51 +14,89 @@ pub enum Authorization {
 }
 
 impl Authorization {
+    /// Parse the value of the `Authorization` header. The scheme is case-insensitive.
+    /// Returns `None` if the header is malformed or the scheme isn't supported.
     pub fn parse(header: &str) -> Option<Authorization> {
-        let mut parts = header.split(" ");
-        match parts.next() {
-            Some(ident) => match ident {
-                "Basic" => match parts.next() {
-                    Some(auth) => Self::basic(auth),
-                    None => None,
-                },
+        let (scheme, credentials) = Self::split(header)?;
 
-                "Bearer" => match parts.next() {
-                    Some(auth) => Some(Authorization::Bearer {
-                        token: auth.to_owned(),
-                    }),
-                    None => None,
-                },
-
-                "Token" => match parts.next() {
-                    Some(auth) => Some(Authorization::Token {
-                        token: auth.to_owned(),
-                    }),
-                    None => None,
-                },
+        if scheme.eq_ignore_ascii_case("basic") {
+            Self::basic(credentials)
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            Some(Authorization::Bearer {
+                token: credentials.to_owned(),
+            })
+        } else if scheme.eq_ignore_ascii_case("token") {
+            Some(Authorization::Token {
+                token: credentials.to_owned(),
+            })
+        } else {
+            None
+        }
+    }
 
-                _ => None,
-            },
+    /// Split the header into the scheme and the credentials, e.g. `Bearer abc` into `("Bearer", "abc")`.
+    /// Credentials can't have spaces.
+    pub(crate) fn split(header: &str) -> Option<(&str, &str)> {
+        let (scheme, credentials) = header.trim().split_once(' ')?;
+        let credentials = credentials.trim_start();
 
-            None => None,
+        if scheme.is_empty() || credentials.is_empty() || credentials.contains(char::is_whitespace)
+        {
+            None
+        } else {
+            Some((scheme, credentials))
         }
     }
 
     fn basic(value: &str) -> Option<Authorization> {
-        if let Ok(decoded) = BASE64_STANDARD.decode(value.as_bytes()) {
-            let decoded = String::from_utf8_lossy(&decoded);
-            let mut parts = decoded.split(":");
+        let decoded = BASE64_STANDARD.decode(value.as_bytes()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        // Passwords can contain colons, user IDs can't.
+        let (user, password) = decoded.split_once(':')?;
 
-            if let Some(user) = parts.next() {
-                if let Some(password) = parts.next() {
-                    return Some(Authorization::Basic {
-                        user: user.to_owned(),
-                        password: password.to_owned(),
-                    });
-                }
-            }
-        }
+        Some(Authorization::Basic {
+            user: user.to_owned(),
+            password: password.to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        None
+    #[test]
+    fn test_parse() {
+        let basic = |user: &str, password: &str| Authorization::Basic {
+            user: user.into(),
+            password: password.into(),
+        };
+
+        for (header, expected) in [
+            (
+                "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==",
+                Some(basic("Aladdin", "open sesame")),
+            ),
+            ("basic  YTpiOmM=", Some(basic("a", "b:c"))),
+            ("Basic Og==", Some(basic("", ""))),
+            (
+                "BEARER abc.def",
+                Some(Authorization::Bearer {
+                    token: "abc.def".into(),
+                }),
+            ),
+            ("Basic", None),
+            ("Basic not-base64!", None),
+            // No colon.
+            ("Basic YWxhZGRpbg==", None),
+            // Not UTF-8.
+            ("Basic /w==", None),
+            ("Bearer ", None),
+            ("Bearer a b", None),
+            ("Digest username=a", None),
+            ("", None),
+        ] {
+            assert_eq!(Authorization::parse(header), expected, "{}", header);
+        }
     }
 }
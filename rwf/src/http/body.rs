@@ -1,22 +1,28 @@
 //! Handle sending a response body to the client.
 //!
-//! The body can be text, HTML, raw bytes, JSON and a static file. The `Content-Type` and `Content-Length` headers
-//! are set automatically.
+//! The body can be text, HTML, raw bytes, JSON, XML, a static file, or a stream. The `Content-Type` and `Content-Length` headers
+//! are set automatically. Streams are sent using chunked transfer encoding, since their size isn't known in advance.
 use std::fmt::Debug;
 use std::fs::Metadata;
+use std::io::SeekFrom;
 use std::marker::Unpin;
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use tokio::fs::File;
-use tokio::io::{copy, AsyncWrite, AsyncWriteExt};
+use tokio::io::{copy, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the chunks a stream body is sent in.
+pub const CHUNK_SIZE: usize = 64 * 1024;
 
 /// Response body.
-#[derive(Debug)]
 pub enum Body {
     /// Static file.
     File {
         path: PathBuf,
         file: File,
         metadata: Metadata,
+        /// Send only these bytes of the file, e.g. to answer a range request.
+        range: Option<Range<u64>>,
     },
     /// UTF-8 encoded HTML.
     Html(String),
@@ -26,6 +32,37 @@ pub enum Body {
     Text(String),
     /// UTF-8 encoded JSON string.
     Json(Vec<u8>),
+    /// UTF-8 encoded XML.
+    Xml(String),
+    /// Read from the reader while it's sent, e.g. a large export, without loading it into memory.
+    Stream(Box<dyn AsyncRead + Send + Sync + Unpin>),
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Body::*;
+
+        match self {
+            File {
+                path,
+                file,
+                metadata,
+                range,
+            } => f
+                .debug_struct("File")
+                .field("path", path)
+                .field("file", file)
+                .field("metadata", metadata)
+                .field("range", range)
+                .finish(),
+            Html(html) => f.debug_tuple("Html").field(html).finish(),
+            Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Text(text) => f.debug_tuple("Text").field(text).finish(),
+            Json(json) => f.debug_tuple("Json").field(json).finish(),
+            Xml(xml) => f.debug_tuple("Xml").field(xml).finish(),
+            Stream(_) => f.debug_tuple("Stream").finish_non_exhaustive(),
+        }
+    }
 }
 
 impl Body {
@@ -39,8 +76,21 @@ impl Body {
         Self::Html(text.to_string())
     }
 
+    /// Create new body read from a stream.
+    pub fn stream(reader: impl AsyncRead + Send + Sync + Unpin + 'static) -> Self {
+        Self::Stream(Box::new(reader))
+    }
+
+    /// The body is a stream and its size isn't known.
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Body::Stream(_))
+    }
+
     /// Send the body to the stream. This handles copying the file
     /// using an efficient Tokio primitive.
+    ///
+    /// Stream bodies are chunk-encoded, in chunks of up to [`CHUNK_SIZE`] bytes, and terminated with
+    /// the last chunk, so the connection can be reused afterwards.
     pub async fn send(
         &mut self,
         mut stream: impl AsyncWrite + Unpin,
@@ -48,27 +98,103 @@ impl Body {
         use Body::*;
 
         match self {
-            File { file, .. } => {
-                copy(file, &mut stream).await?;
+            File { file, range, .. } => {
+                match range {
+                    Some(range) => {
+                        file.seek(SeekFrom::Start(range.start)).await?;
+                        copy(&mut file.take(range.end - range.start), &mut stream).await?;
+                    }
+                    None => {
+                        copy(file, &mut stream).await?;
+                    }
+                }
                 Ok(())
             }
             Bytes(bytes) => Ok(stream.write_all(bytes).await?),
             Text(text) => Ok(stream.write_all(text.as_bytes()).await?),
             Html(html) => Ok(stream.write_all(html.as_bytes()).await?),
             Json(json) => Ok(stream.write_all(json.as_slice()).await?),
+            Xml(xml) => Ok(stream.write_all(xml.as_bytes()).await?),
+            Stream(reader) => {
+                let mut buffer = vec![0u8; CHUNK_SIZE];
+
+                loop {
+                    let read = reader.read(&mut buffer).await?;
+                    if read == 0 {
+                        break;
+                    }
+
+                    stream
+                        .write_all(format!("{:x}\r\n", read).as_bytes())
+                        .await?;
+                    stream.write_all(&buffer[..read]).await?;
+                    stream.write_all(b"\r\n").await?;
+                }
+
+                Ok(stream.write_all(b"0\r\n\r\n").await?)
+            }
+        }
+    }
+
+    /// The body contents, unless it's a file or a stream.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        use Body::*;
+
+        match self {
+            File { .. } | Stream(_) => None,
+            Bytes(bytes) => Some(bytes),
+            Html(html) => Some(html.as_bytes()),
+            Json(json) => Some(json),
+            Text(text) => Some(text.as_bytes()),
+            Xml(xml) => Some(xml.as_bytes()),
         }
     }
 
     /// Get the body size. Used in the `Content-Length` header.
+    ///
+    /// The size of a stream isn't known, so it's `0`.
     pub fn len(&self) -> usize {
         use Body::*;
 
         match self {
+            Stream(_) => 0,
+            File {
+                range: Some(range), ..
+            } => (range.end - range.start) as usize,
             File { metadata, .. } => metadata.len() as usize,
             Bytes(bytes) => bytes.len(),
             Html(html) => html.as_bytes().len(),
             Json(json) => json.len(),
             Text(text) => text.as_bytes().len(),
+            Xml(xml) => xml.len(),
+        }
+    }
+
+    /// Keep only the bytes in the range, e.g. to answer a range request. The range must be
+    /// within the body. Text bodies become raw bytes, since the range may split a character.
+    pub fn slice(self, range: Range<u64>) -> Self {
+        use Body::*;
+
+        match self {
+            File {
+                path,
+                file,
+                metadata,
+                range: current,
+            } => {
+                let offset = current.map(|current| current.start).unwrap_or(0);
+                File {
+                    path,
+                    file,
+                    metadata,
+                    range: Some(offset + range.start..offset + range.end),
+                }
+            }
+            Stream(reader) => Stream(reader),
+            body => {
+                let bytes = body.as_bytes().unwrap_or_default();
+                Bytes(bytes[range.start as usize..range.end as usize].to_vec())
+            }
         }
     }
 
@@ -79,102 +205,101 @@ impl Body {
         use Body::*;
 
         match self {
-            File { path, .. } => {
-                // Guessing the mime by the extension.
-                let extension = match path.extension() {
-                    Some(extension) => extension.to_str().expect("OsStr to_str"),
-                    None => "",
-                };
-
-                // https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types
-                match extension {
-                    "aac" => "audio/aac",
-                    "abw" => "application/x-abiword",
-                    "arc" => "application/x-freearc",
-                    "avif" => "image/avif",
-                    "avi" => "video/x-msvideo",
-                    "azw" => "application/vnd.amazon.ebook",
-                    "bin" => "application/octet-stream",
-                    "bmp" => "image/bmp",
-                    "bz" => "application/x-bzip",
-                    "bz2" => "application/x-bzip2",
-                    "cda" => "application/x-cdf",
-                    "csh" => "application/x-csh",
-                    "css" => "text/css",
-                    "csv" => "text/csv",
-                    "doc" => "application/msword",
-                    "docx" => {
-                        "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
-                    }
-                    "eot" => "application/vnd.ms-fontobject",
-                    "epub" => "application/epub+zip",
-                    "gz" => "application/gzip",
-                    "gif" => "image/gif",
-                    "htm" => "text/html",
-                    "html" => "text/html",
-                    "ico" => "image/vnd.microsoft.icon",
-                    "ics" => "text/calendar",
-                    "jar" => "application/java-archive",
-                    "jpeg" => "image/jpeg",
-                    "jpg" => "image/jpeg",
-                    "js" => "text/javascript",
-                    "json" => "application/json",
-                    "jsonld" => "application/ld+json",
-                    "mid" => "audio/midi",
-                    "midi" => "audio/midi",
-                    "mjs" => "text/javascript",
-                    "mp3" => "audio/mpeg",
-                    "mp4" => "video/mp4",
-                    "mpeg" => "video/mpeg",
-                    "mpkg" => "application/vnd.apple.installer+xml",
-                    "odp" => "application/vnd.oasis.opendocument.presentation",
-                    "ods" => "application/vnd.oasis.opendocument.spreadsheet",
-                    "odt" => "application/vnd.oasis.opendocument.text",
-                    "oga" => "audio/ogg",
-                    "ogv" => "video/ogg",
-                    "ogx" => "application/ogg",
-                    "opus" => "audio/opus",
-                    "otf" => "font/otf",
-                    "png" => "image/png",
-                    "pdf" => "application/pdf",
-                    "php" => "application/x-httpd-php",
-                    "ppt" => "application/vnd.ms-powerpoint",
-                    "pptx" => {
-                        "application/vnd.openxmlformats-officedocument.presentationml.presentation"
-                    }
-                    "rar" => "application/vnd.rar",
-                    "rtf" => "application/rtf",
-                    "sh" => "application/x-sh",
-                    "svg" => "image/svg+xml",
-                    "tar" => "application/x-tar",
-                    "tif" => "image/tiff",
-                    "tiff" => "image/tiff",
-                    "ts" => "video/mp2t",
-                    "ttf" => "font/ttf",
-                    "txt" => "text/plain",
-                    "vsd" => "application/vnd.visio",
-                    "wav" => "audio/wav",
-                    "weba" => "audio/webm",
-                    "webm" => "video/webm",
-                    "webp" => "image/webp",
-                    "woff" => "font/woff",
-                    "woff2" => "font/woff2",
-                    "xhtml" => "application/xhtml+xml",
-                    "xls" => "application/vnd.ms-excel",
-                    "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-                    "xml" => "application/xml",
-                    "xul" => "application/vnd.mozilla.xul+xml",
-                    "zip" => "application/zip",
-                    "3gp" => "video/3gpp",
-                    "3g2" => "video/3gpp2",
-                    "7z" => "application/x-7z-compressed",
-                    _ => "application/octet-stream",
-                }
-            }
+            File { path, .. } => Self::mime_type_from_path(path),
             Text(_) => "text/plain",
             Html(_) => "text/html",
             Json(_) => "application/json",
-            Bytes(_) => "application/octet-stream",
+            Xml(_) => "application/xml",
+            Bytes(_) | Stream(_) => "application/octet-stream",
+        }
+    }
+
+    /// Guess the MIME type of a file based on its extension.
+    pub fn mime_type_from_path(path: &Path) -> &'static str {
+        let extension = match path.extension() {
+            Some(extension) => extension.to_str().unwrap_or(""),
+            None => "",
+        };
+
+        // https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types
+        match extension {
+            "aac" => "audio/aac",
+            "abw" => "application/x-abiword",
+            "arc" => "application/x-freearc",
+            "avif" => "image/avif",
+            "avi" => "video/x-msvideo",
+            "azw" => "application/vnd.amazon.ebook",
+            "bin" => "application/octet-stream",
+            "bmp" => "image/bmp",
+            "bz" => "application/x-bzip",
+            "bz2" => "application/x-bzip2",
+            "cda" => "application/x-cdf",
+            "csh" => "application/x-csh",
+            "css" => "text/css",
+            "csv" => "text/csv",
+            "doc" => "application/msword",
+            "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "eot" => "application/vnd.ms-fontobject",
+            "epub" => "application/epub+zip",
+            "gz" => "application/gzip",
+            "gif" => "image/gif",
+            "htm" => "text/html",
+            "html" => "text/html",
+            "ico" => "image/vnd.microsoft.icon",
+            "ics" => "text/calendar",
+            "jar" => "application/java-archive",
+            "jpeg" => "image/jpeg",
+            "jpg" => "image/jpeg",
+            "js" => "text/javascript",
+            "json" => "application/json",
+            "jsonld" => "application/ld+json",
+            "mid" => "audio/midi",
+            "midi" => "audio/midi",
+            "mjs" => "text/javascript",
+            "mp3" => "audio/mpeg",
+            "mp4" => "video/mp4",
+            "mpeg" => "video/mpeg",
+            "mpkg" => "application/vnd.apple.installer+xml",
+            "odp" => "application/vnd.oasis.opendocument.presentation",
+            "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+            "odt" => "application/vnd.oasis.opendocument.text",
+            "oga" => "audio/ogg",
+            "ogv" => "video/ogg",
+            "ogx" => "application/ogg",
+            "opus" => "audio/opus",
+            "otf" => "font/otf",
+            "png" => "image/png",
+            "pdf" => "application/pdf",
+            "php" => "application/x-httpd-php",
+            "ppt" => "application/vnd.ms-powerpoint",
+            "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "rar" => "application/vnd.rar",
+            "rtf" => "application/rtf",
+            "sh" => "application/x-sh",
+            "svg" => "image/svg+xml",
+            "tar" => "application/x-tar",
+            "tif" => "image/tiff",
+            "tiff" => "image/tiff",
+            "ts" => "video/mp2t",
+            "ttf" => "font/ttf",
+            "txt" => "text/plain",
+            "vsd" => "application/vnd.visio",
+            "wav" => "audio/wav",
+            "weba" => "audio/webm",
+            "webm" => "video/webm",
+            "webp" => "image/webp",
+            "woff" => "font/woff",
+            "woff2" => "font/woff2",
+            "xhtml" => "application/xhtml+xml",
+            "xls" => "application/vnd.ms-excel",
+            "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "xml" => "application/xml",
+            "xul" => "application/vnd.mozilla.xul+xml",
+            "zip" => "application/zip",
+            "3gp" => "video/3gpp",
+            "3g2" => "video/3gpp2",
+            "7z" => "application/x-7z-compressed",
+            _ => "application/octet-stream",
         }
     }
 }
@@ -197,6 +322,7 @@ impl From<(PathBuf, File, Metadata)> for Body {
             path: file.0,
             file: file.1,
             metadata: file.2,
+            range: None,
         }
     }
 }
@@ -208,3 +334,25 @@ impl TryFrom<serde_json::Value> for Body {
         Ok(Self::Json(serde_json::to_vec(&json)?))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send_stream() {
+        let data = vec![b'a'; CHUNK_SIZE + 10];
+        let mut body = Body::stream(std::io::Cursor::new(data));
+        let mut wire = vec![];
+        body.send(&mut wire).await.unwrap();
+
+        let mut expected = format!("{:x}\r\n", CHUNK_SIZE).into_bytes();
+        expected.extend(vec![b'a'; CHUNK_SIZE]);
+        expected.extend(b"\r\na\r\naaaaaaaaaa\r\n0\r\n\r\n");
+
+        assert_eq!(wire, expected);
+        assert!(body.is_stream());
+        assert!(body.as_bytes().is_none());
+        assert_eq!(body.mime_type(), "application/octet-stream");
+    }
+}
@@ -0,0 +1,427 @@
+//! Request bodies read while the controller runs, instead of being loaded into memory first.
+//!
+//! Enable it on a route with [`Handler::stream_body`](super::Handler::stream_body), and read the body
+//! with [`Request::body_stream`](super::Request::body_stream):
+//!
+//! ```rust,ignore
+//! use tokio::io::copy;
+//!
+//! let mut body = request.body_stream().unwrap();
+//! let mut file = File::create("upload.bin").await?;
+//! copy(&mut body, &mut file).await?;
+//! ```
+//!
+//! Bodies with a `Content-Length` and chunked bodies are supported. Whatever the controller doesn't read is read
+//! and thrown away by the server before the connection is used for the next request.
+use std::io;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Buf, Bytes};
+use futures_util::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use super::{body::CHUNK_SIZE, Error, Head};
+use crate::config::get_config;
+
+/// Longest chunk size line, including extensions, and trailer line of a chunked body.
+const MAX_LINE_SIZE: usize = 4096;
+
+/// Chunks buffered between the connection and the controller.
+const BUFFERED_CHUNKS: usize = 4;
+
+/// How the length of the body is determined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Framing {
+    /// `Content-Length` header, or no body.
+    Length(usize),
+    /// `Transfer-Encoding: chunked`.
+    Chunked,
+}
+
+impl Framing {
+    /// Framing of the request body, from its headers.
+    pub(crate) fn new(head: &Head) -> Result<Self, Error> {
+        match head.header("transfer-encoding") {
+            Some(encoding) => {
+                // Both headers are used to smuggle requests past proxies.
+                if head.header("content-length").is_some() {
+                    return Err(Error::MalformedRequest(
+                        "content-length with transfer-encoding",
+                    ));
+                }
+
+                let chunked = encoding
+                    .rsplit(',')
+                    .next()
+                    .map(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+                    .unwrap_or(false);
+
+                if chunked {
+                    Ok(Framing::Chunked)
+                } else {
+                    Err(Error::MalformedRequest("unsupported transfer-encoding"))
+                }
+            }
+
+            None => match head.header("content-length") {
+                Some(_) => head
+                    .content_length()
+                    .map(Framing::Length)
+                    .ok_or(Error::MalformedRequest("invalid content-length")),
+                None => Ok(Framing::Length(0)),
+            },
+        }
+    }
+}
+
+/// Reads the request body from the connection, chunk by chunk.
+#[derive(Debug)]
+pub(crate) struct BodyReader {
+    framing: Framing,
+    // Bytes left in the body, or in the current chunk of a chunked body.
+    remaining: usize,
+    // Read the size line of the first chunk.
+    started: bool,
+    done: bool,
+}
+
+impl BodyReader {
+    pub(crate) fn new(framing: Framing) -> Self {
+        let remaining = match framing {
+            Framing::Length(length) => length,
+            Framing::Chunked => 0,
+        };
+
+        Self {
+            framing,
+            remaining,
+            started: false,
+            done: framing == Framing::Length(0),
+        }
+    }
+
+    /// Read the next part of the body, at most [`CHUNK_SIZE`] bytes. `None` means the whole body was read.
+    pub(crate) async fn next(
+        &mut self,
+        mut stream: impl AsyncRead + Unpin,
+    ) -> Result<Option<Bytes>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+
+        if self.framing == Framing::Chunked && self.remaining == 0 {
+            // Each chunk is followed by a line break.
+            if self.started && !read_line(&mut stream).await?.is_empty() {
+                return Err(Error::MalformedRequest("chunk longer than its size"));
+            }
+            self.started = true;
+
+            let line = read_line(&mut stream).await?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            self.remaining = usize::from_str_radix(size, 16)
+                .map_err(|_| Error::MalformedRequest("invalid chunk size"))?;
+
+            if self.remaining == 0 {
+                // Trailers aren't used, but they need to be read.
+                while !read_line(&mut stream).await?.is_empty() {}
+                self.done = true;
+                return Ok(None);
+            }
+        }
+
+        let mut chunk = vec![0u8; self.remaining.min(CHUNK_SIZE)];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(Error::MalformedRequest("incorrect content length"));
+        }
+        chunk.truncate(read);
+        self.remaining -= read;
+
+        if self.framing != Framing::Chunked && self.remaining == 0 {
+            self.done = true;
+        }
+
+        Ok(Some(Bytes::from(chunk)))
+    }
+
+    /// Read the whole body into memory, failing with `Error::ContentTooLarge` if it's larger than the limit.
+    pub(crate) async fn read_to_end(
+        mut self,
+        mut stream: impl AsyncRead + Unpin,
+        head: Head,
+        limit: usize,
+    ) -> Result<(Head, Vec<u8>), Error> {
+        let mut body = Vec::new();
+
+        while let Some(chunk) = self.next(&mut stream).await? {
+            if body.len() + chunk.len() > limit {
+                return Err(Error::ContentTooLarge(head));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok((head, body))
+    }
+}
+
+/// Read a line ending with CRLF, without the line break.
+async fn read_line(mut stream: impl AsyncRead + Unpin) -> Result<String, Error> {
+    let mut line = Vec::new();
+
+    loop {
+        match stream.read_u8().await? {
+            b'\n' => break,
+            byte => line.push(byte),
+        }
+
+        if line.len() > MAX_LINE_SIZE {
+            return Err(Error::MalformedRequest("chunk line too long"));
+        }
+    }
+
+    match line.pop() {
+        Some(b'\r') => String::from_utf8(line).map_err(|_| Error::MalformedRequest("chunk line")),
+        _ => Err(Error::MalformedRequest("nl before cr")),
+    }
+}
+
+/// Request body, received while the controller reads it.
+///
+/// It's both an [`AsyncRead`] and a [`Stream`] of chunks.
+#[derive(Debug)]
+pub struct BodyStream {
+    receiver: Receiver<io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl BodyStream {
+    /// Stream of a body already in memory.
+    pub(crate) fn buffered(body: &[u8]) -> Self {
+        let (sender, receiver) = channel(1);
+        if !body.is_empty() {
+            let _ = sender.try_send(Ok(Bytes::copy_from_slice(body)));
+        }
+
+        Self {
+            receiver,
+            chunk: Bytes::new(),
+        }
+    }
+
+    /// Stream receiving the body read by the pump from the connection.
+    pub(crate) fn new(framing: Framing) -> (Self, BodyPump) {
+        let (sender, receiver) = channel(BUFFERED_CHUNKS);
+
+        (
+            Self {
+                receiver,
+                chunk: Bytes::new(),
+            },
+            BodyPump {
+                reader: BodyReader::new(framing),
+                sender,
+            },
+        )
+    }
+
+    /// Next chunk of the body. `None` means the whole body was received.
+    pub async fn chunk(&mut self) -> Option<io::Result<Bytes>> {
+        if self.chunk.has_remaining() {
+            return Some(Ok(std::mem::take(&mut self.chunk)));
+        }
+
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for BodyStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.chunk.has_remaining() {
+            return Poll::Ready(Some(Ok(std::mem::take(&mut self.chunk))));
+        }
+
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl AsyncRead for BodyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while !self.chunk.has_remaining() {
+            match ready!(self.receiver.poll_recv(cx)) {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let len = self.chunk.len().min(buf.remaining());
+        buf.put_slice(&self.chunk[..len]);
+        self.chunk.advance(len);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reads the body from the connection and passes it to the [`BodyStream`].
+#[derive(Debug)]
+pub(crate) struct BodyPump {
+    reader: BodyReader,
+    sender: Sender<io::Result<Bytes>>,
+}
+
+impl BodyPump {
+    /// Read the body until its end. The part the controller doesn't read is thrown away, up to `max_request_size`
+    /// bytes. Returns `false` if the connection can't be used for another request, because the body is
+    /// malformed or larger than that.
+    pub(crate) async fn run(self, mut stream: impl AsyncRead + Unpin) -> bool {
+        let BodyPump { mut reader, sender } = self;
+        let mut sender = Some(sender);
+        let mut drained = 0;
+        let limit = get_config().general.max_request_size;
+
+        loop {
+            match reader.next(&mut stream).await {
+                Ok(Some(chunk)) => {
+                    let len = chunk.len();
+
+                    if let Some(ref receiver) = sender {
+                        if receiver.send(Ok(chunk)).await.is_ok() {
+                            continue;
+                        }
+                        // The controller is done with the body.
+                        sender = None;
+                    }
+
+                    drained += len;
+                    if drained > limit {
+                        return false;
+                    }
+                }
+
+                Ok(None) => return true,
+
+                Err(err) => {
+                    if let Some(sender) = sender {
+                        let _ = sender
+                            .send(Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                err.to_string(),
+                            )))
+                            .await;
+                    }
+
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn head(headers: &str) -> Head {
+        Head::read(format!("POST /upload HTTP/1.1\r\n{}\r\n", headers).as_bytes())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_framing() {
+        assert_eq!(Framing::new(&head("").await).unwrap(), Framing::Length(0));
+        assert_eq!(
+            Framing::new(&head("Content-Length: 5\r\n").await).unwrap(),
+            Framing::Length(5)
+        );
+        assert_eq!(
+            Framing::new(&head("Transfer-Encoding: gzip, Chunked\r\n").await).unwrap(),
+            Framing::Chunked
+        );
+        assert!(Framing::new(&head("Transfer-Encoding: gzip\r\n").await).is_err());
+        assert!(Framing::new(&head("Content-Length: five\r\n").await).is_err());
+        assert!(
+            Framing::new(&head("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n").await)
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunked() {
+        let mut stream =
+            &b"5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\nGET"[..];
+        let mut reader = BodyReader::new(Framing::Chunked);
+        let mut body = vec![];
+
+        while let Some(chunk) = reader.next(&mut stream).await.unwrap() {
+            body.extend_from_slice(&chunk);
+        }
+
+        assert_eq!(body, b"hello, world");
+        // The next request is left on the connection.
+        assert_eq!(stream, b"GET");
+
+        for malformed in [
+            &b"5\r\nhello world\r\n0\r\n\r\n"[..],
+            b"x\r\nhello\r\n0\r\n\r\n",
+            b"5\nhello\r\n0\r\n\r\n",
+            b"5\r\nhel",
+        ] {
+            let mut stream = malformed;
+            let mut reader = BodyReader::new(Framing::Chunked);
+
+            let result = loop {
+                match reader.next(&mut stream).await {
+                    Ok(Some(_)) => continue,
+                    result => break result,
+                }
+            };
+            assert!(result.is_err(), "{:?}", malformed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let (mut body, pump) = BodyStream::new(Framing::Length(11));
+        let stream = &b"hello worldGET"[..];
+
+        let (reusable, read) = tokio::join!(pump.run(stream), async move {
+            let mut read = String::new();
+            body.read_to_string(&mut read).await.unwrap();
+            read
+        });
+        assert!(reusable);
+        assert_eq!(read, "hello world");
+
+        // Unread bodies are drained.
+        let (body, pump) = BodyStream::new(Framing::Chunked);
+        drop(body);
+        let mut stream = &b"5\r\nhello\r\n0\r\n\r\nGET"[..];
+        assert!(pump.run(&mut stream).await);
+        assert_eq!(stream, b"GET");
+
+        // The connection can't be reused after a malformed body.
+        let (mut body, pump) = BodyStream::new(Framing::Length(11));
+        let (reusable, chunk) = tokio::join!(pump.run(&b"hello"[..]), async move {
+            body.chunk().await.unwrap().unwrap();
+            body.chunk().await.unwrap()
+        });
+        assert!(!reusable);
+        assert!(chunk.is_err());
+
+        let mut body = BodyStream::buffered(b"hello");
+        let mut read = String::new();
+        body.read_to_string(&mut read).await.unwrap();
+        assert_eq!(read, "hello");
+    }
+}
@@ -0,0 +1,179 @@
+//! The `Cache-Control` header.
+//!
+//! ```
+//! use rwf::http::{CacheControl, Response};
+//! use time::Duration;
+//!
+//! let response = Response::new().html("<h1>Pricing</h1>").cache(
+//!     CacheControl::new()
+//!         .public()
+//!         .max_age(Duration::minutes(5))
+//!         .stale_while_revalidate(Duration::minutes(1)),
+//! );
+//!
+//! assert_eq!(
+//!     response.headers().get("cache-control").unwrap(),
+//!     "public, max-age=300, stale-while-revalidate=60"
+//! );
+//! ```
+use time::Duration;
+
+/// Who can cache the response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Visibility {
+    Public,
+    Private,
+}
+
+/// Caching rules of a response, sent in the `Cache-Control` header.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheControl {
+    visibility: Option<Visibility>,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    no_store: bool,
+    no_cache: bool,
+    must_revalidate: bool,
+    immutable: bool,
+}
+
+impl CacheControl {
+    /// Empty rules. Use the builder methods to add directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The response must not be stored at all, e.g. because it has personal data. Sent as `no-store`.
+    pub fn no_store() -> Self {
+        Self {
+            no_store: true,
+            ..Default::default()
+        }
+    }
+
+    /// The response can be stored, but must be checked with the server before each use. Sent as `no-cache`.
+    pub fn no_cache() -> Self {
+        Self {
+            no_cache: true,
+            ..Default::default()
+        }
+    }
+
+    /// Shared caches, like CDNs, can store the response.
+    pub fn public(mut self) -> Self {
+        self.visibility = Some(Visibility::Public);
+        self
+    }
+
+    /// Only the browser can store the response, e.g. because it depends on the user.
+    pub fn private(mut self) -> Self {
+        self.visibility = Some(Visibility::Private);
+        self
+    }
+
+    /// How long the response is fresh. Sent in seconds as `max-age`.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// How long the response is fresh in shared caches, instead of `max-age`. Sent as `s-maxage`.
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// How long a stale response can be used while it's revalidated in the background.
+    pub fn stale_while_revalidate(mut self, duration: Duration) -> Self {
+        self.stale_while_revalidate = Some(duration);
+        self
+    }
+
+    /// Once stale, the response must not be used until it's revalidated.
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// The response never changes while it's fresh, e.g. a file with a hash in its name.
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+}
+
+impl std::fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seconds = |duration: Duration| duration.whole_seconds().max(0);
+        let mut directives = vec![];
+
+        match self.visibility {
+            Some(Visibility::Public) => directives.push("public".to_string()),
+            Some(Visibility::Private) => directives.push("private".to_string()),
+            None => (),
+        }
+
+        if self.no_store {
+            directives.push("no-store".into());
+        }
+
+        if self.no_cache {
+            directives.push("no-cache".into());
+        }
+
+        if let Some(max_age) = self.max_age {
+            directives.push(format!("max-age={}", seconds(max_age)));
+        }
+
+        if let Some(s_maxage) = self.s_maxage {
+            directives.push(format!("s-maxage={}", seconds(s_maxage)));
+        }
+
+        if let Some(duration) = self.stale_while_revalidate {
+            directives.push(format!("stale-while-revalidate={}", seconds(duration)));
+        }
+
+        if self.must_revalidate {
+            directives.push("must-revalidate".into());
+        }
+
+        if self.immutable {
+            directives.push("immutable".into());
+        }
+
+        write!(f, "{}", directives.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache_control() {
+        assert_eq!(CacheControl::new().to_string(), "");
+        assert_eq!(CacheControl::no_store().to_string(), "no-store");
+        assert_eq!(
+            CacheControl::no_cache().private().to_string(),
+            "private, no-cache"
+        );
+        assert_eq!(
+            CacheControl::new()
+                .public()
+                .max_age(Duration::days(365))
+                .immutable()
+                .to_string(),
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(
+            CacheControl::new()
+                .private()
+                .max_age(Duration::seconds(-5))
+                .s_maxage(Duration::minutes(10))
+                .must_revalidate()
+                .to_string(),
+            "private, max-age=0, s-maxage=600, must-revalidate"
+        );
+    }
+}
@@ -0,0 +1,567 @@
+//! Capture full requests and responses for debugging, e.g. when a client reports an unexpected response.
+//!
+//! Captures are turned on with rules. A request is captured if all the conditions of a rule match it:
+//!
+//! ```
+//! use rwf::http::capture::{self, CaptureRule};
+//!
+//! capture::add_rule(
+//!     CaptureRule::new("orders-api")
+//!         .path_prefix("/api/orders")
+//!         .user_id(1234)
+//!         .sample_rate(0.5),
+//! );
+//! ```
+//!
+//! Each capture has the method, path, headers and body of the request, and the status, headers and body of the response,
+//! with credentials removed by [`redact`](super::redact). Bodies are cut at the `capture_body_size` setting. Captures are
+//! kept in memory, newest first, up to the `capture_buffer_size` setting, and also saved in the `rwf_captures` table if
+//! `capture_table` is enabled.
+//!
+//! Captures are identified by the `X-Request-Id` header of the request, which is also added to the response, or
+//! a new UUID if the client didn't send one. They can be viewed with the [`CaptureController`](crate::controller::CaptureController).
+//!
+//! When there are no rules, the only cost is checking a flag for each request.
+use std::collections::{BTreeMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::{redact, Request, Response};
+use crate::config::get_config;
+use crate::controller::SessionId;
+use crate::model::{Error as ModelError, FromRow, GetColumn, Model, Pool, ToValue, Value};
+
+/// Header identifying the request.
+pub static REQUEST_ID: &str = "x-request-id";
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static RULES: Lazy<RwLock<Vec<CaptureRule>>> = Lazy::new(|| RwLock::new(vec![]));
+static BUFFER: Lazy<Mutex<VecDeque<Capture>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// Which requests to capture. Conditions which aren't set match all requests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRule {
+    name: String,
+    #[serde(default)]
+    path_prefix: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default = "CaptureRule::default_sample_rate")]
+    sample_rate: f64,
+    #[serde(default)]
+    user_id: Option<i64>,
+    #[serde(default)]
+    session_id: Option<String>,
+}
+
+impl CaptureRule {
+    /// Create a rule matching all requests. The name identifies the rule, e.g. to remove it.
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            path_prefix: None,
+            headers: BTreeMap::new(),
+            sample_rate: Self::default_sample_rate(),
+            user_id: None,
+            session_id: None,
+        }
+    }
+
+    /// Name of the rule.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Capture requests with a path starting with the prefix, e.g. `/api/orders`.
+    pub fn path_prefix(mut self, prefix: impl ToString) -> Self {
+        self.path_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Capture requests with the header set to this value. Can be used more than once, in which
+    /// case all headers must match.
+    pub fn header(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.headers
+            .insert(name.to_string().to_lowercase(), value.to_string());
+        self
+    }
+
+    /// Capture this fraction of the matching requests, between `0.0` and `1.0`. The default is `1.0`, all of them.
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Capture requests made by this user.
+    pub fn user_id(mut self, user_id: i64) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    /// Capture requests made with this guest session.
+    pub fn session_id(mut self, session_id: impl ToString) -> Self {
+        self.session_id = Some(session_id.to_string());
+        self
+    }
+
+    /// Does the rule match the request? The sample rate is checked last, so only requests matching
+    /// the other conditions are sampled.
+    pub fn matches(&self, request: &Request) -> bool {
+        if let Some(ref prefix) = self.path_prefix {
+            if !request.path().path().starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+
+        for (name, value) in &self.headers {
+            if request.header(name) != Some(value) {
+                return false;
+            }
+        }
+
+        if let Some(user_id) = self.user_id {
+            if request.user_id().ok() != Some(user_id) {
+                return false;
+            }
+        }
+
+        if let Some(ref session_id) = self.session_id {
+            match request.session_id() {
+                Some(SessionId::Guest(id)) if &id == session_id => (),
+                _ => return false,
+            }
+        }
+
+        self.sample_rate >= 1.0 || rand::thread_rng().gen::<f64>() < self.sample_rate
+    }
+
+    fn default_sample_rate() -> f64 {
+        1.0
+    }
+}
+
+/// Add the rule, replacing any rule with the same name.
+pub fn add_rule(rule: CaptureRule) {
+    let mut rules = RULES.write();
+    rules.retain(|existing| existing.name != rule.name);
+    rules.push(rule);
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Remove the rule with this name. Returns `false` if there wasn't one.
+pub fn remove_rule(name: &str) -> bool {
+    let mut rules = RULES.write();
+    let before = rules.len();
+    rules.retain(|rule| rule.name != name);
+    ACTIVE.store(!rules.is_empty(), Ordering::Relaxed);
+    rules.len() != before
+}
+
+/// Remove all rules, turning capture off. Captures already made are kept.
+pub fn clear_rules() {
+    RULES.write().clear();
+    ACTIVE.store(false, Ordering::Relaxed);
+}
+
+/// Rules currently installed.
+pub fn rules() -> Vec<CaptureRule> {
+    RULES.read().clone()
+}
+
+/// Captures kept in memory, newest first.
+pub fn captures() -> Vec<Capture> {
+    BUFFER.lock().iter().cloned().collect()
+}
+
+/// Find the capture of the request, in memory or, if `capture_table` is enabled, in the database.
+pub async fn find(request_id: &str) -> Result<Option<Capture>, ModelError> {
+    let capture = BUFFER
+        .lock()
+        .iter()
+        .find(|capture| capture.request_id == request_id)
+        .cloned();
+
+    match capture {
+        Some(capture) => Ok(Some(capture)),
+        None if get_config().general.capture_table => {
+            let mut conn = Pool::connection().await?;
+            Capture::filter("request_id", request_id)
+                .take_one()
+                .fetch_optional(&mut conn)
+                .await
+        }
+        None => Ok(None),
+    }
+}
+
+/// Request and response captured for debugging.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capture {
+    #[serde(skip)]
+    id: Option<i64>,
+    /// Value of the `X-Request-Id` header.
+    pub request_id: String,
+    /// Name of the rule which matched the request.
+    pub rule: String,
+    /// When the response was sent.
+    #[serde(with = "time::serde::rfc3339")]
+    pub captured_at: OffsetDateTime,
+    /// How long the request took, in milliseconds.
+    pub duration: f64,
+    /// The request.
+    pub request: CapturedRequest,
+    /// The response.
+    pub response: CapturedResponse,
+}
+
+/// Request, with credentials removed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedRequest {
+    /// HTTP method sent by the client.
+    pub method: String,
+    /// Path, without the query string.
+    pub path: String,
+    /// Query parameters.
+    pub query: serde_json::Value,
+    /// Client IP, see [`Request::client_ip`].
+    pub client_ip: IpAddr,
+    /// Authenticated user, if any.
+    pub user_id: Option<i64>,
+    /// Request headers.
+    pub headers: BTreeMap<String, String>,
+    /// Request body, as text.
+    pub body: String,
+    /// The body was longer than the `capture_body_size` setting.
+    pub truncated: bool,
+}
+
+/// Response, with credentials removed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapturedResponse {
+    /// HTTP status code.
+    pub status: u16,
+    /// Response headers.
+    pub headers: BTreeMap<String, String>,
+    /// Response body, as text.
+    pub body: String,
+    /// The body was longer than the `capture_body_size` setting.
+    pub truncated: bool,
+}
+
+impl Capture {
+    /// Capture the request and its response.
+    pub fn new(
+        request: &Request,
+        response: &Response,
+        rule: &str,
+        duration: Duration,
+        max_body_size: usize,
+    ) -> Self {
+        let request_id = response
+            .headers()
+            .get(REQUEST_ID)
+            .or(request.header(REQUEST_ID))
+            .cloned()
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let mut query = request.path().query().to_json();
+        redact::json(&mut query);
+
+        let (body, truncated) = truncate(
+            redact::body(
+                request.header("content-type").map(|s| s.as_str()),
+                request.body(),
+            ),
+            max_body_size,
+        );
+
+        let request = CapturedRequest {
+            method: request.original_method().to_string(),
+            path: request.path().path().to_string(),
+            query,
+            client_ip: request.client_ip(),
+            user_id: request.user_id().ok(),
+            headers: redact::headers(request.headers()),
+            body,
+            truncated,
+        };
+
+        let headers = response.headers();
+        let body = match (response.body_bytes(), headers.get("content-encoding")) {
+            (Some(body), Some(encoding)) => format!("[{} bytes, {}]", body.len(), encoding),
+            (Some(body), None) => {
+                redact::body(headers.get("content-type").map(|s| s.as_str()), body)
+            }
+            (None, _) => "[file or stream]".to_string(),
+        };
+        let (body, truncated) = truncate(body, max_body_size);
+
+        let response = CapturedResponse {
+            status: response.status().code(),
+            headers: redact::headers(headers),
+            body,
+            truncated,
+        };
+
+        Self {
+            id: None,
+            request_id,
+            rule: rule.to_string(),
+            captured_at: OffsetDateTime::now_utc(),
+            duration: duration.as_secs_f64() * 1000.0,
+            request,
+            response,
+        }
+    }
+}
+
+impl FromRow for Capture {
+    fn from_row(row: tokio_postgres::Row) -> Result<Self, ModelError> {
+        let request: serde_json::Value = row.get_column("request")?;
+        let response: serde_json::Value = row.get_column("response")?;
+
+        Ok(Self {
+            id: row.get_column("id")?,
+            request_id: row.get_column("request_id")?,
+            rule: row.get_column("rule")?,
+            captured_at: row.get_column("created_at")?,
+            duration: row.get_column("duration")?,
+            request: serde_json::from_value(request)
+                .map_err(|err| ModelError::ValueError("request", err.to_string()))?,
+            response: serde_json::from_value(response)
+                .map_err(|err| ModelError::ValueError("response", err.to_string()))?,
+        })
+    }
+}
+
+impl Model for Capture {
+    fn id(&self) -> Value {
+        self.id.to_value()
+    }
+
+    fn table_name() -> &'static str {
+        "rwf_captures"
+    }
+
+    fn foreign_key() -> &'static str {
+        "rwf_capture_id"
+    }
+
+    fn column_names() -> &'static [&'static str] {
+        &[
+            "request_id",
+            "rule",
+            "request",
+            "response",
+            "duration",
+            "created_at",
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.request_id.to_value(),
+            self.rule.to_value(),
+            serde_json::to_value(&self.request)
+                .unwrap_or_default()
+                .to_value(),
+            serde_json::to_value(&self.response)
+                .unwrap_or_default()
+                .to_value(),
+            self.duration.to_value(),
+            self.captured_at.to_value(),
+        ]
+    }
+}
+
+/// Is any rule installed? Checked for every request.
+pub(crate) fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Capture the request if a rule matches it. The `X-Request-Id` header is added to the response
+/// of captured requests.
+pub(crate) fn record(request: &Request, response: Response, duration: Duration) -> Response {
+    let rule = match RULES.read().iter().find(|rule| rule.matches(request)) {
+        Some(rule) => rule.name.clone(),
+        None => return response,
+    };
+
+    let config = &get_config().general;
+    let capture = Capture::new(
+        request,
+        &response,
+        &rule,
+        duration,
+        config.capture_body_size,
+    );
+    let response = response.header(REQUEST_ID, &capture.request_id);
+
+    if config.capture_table {
+        let capture = capture.clone();
+        tokio::spawn(async move {
+            let request_id = capture.request_id.clone();
+            let result = match Pool::connection().await {
+                Ok(mut conn) => capture.save().execute(&mut conn).await.map(|_| ()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                warn!("capture \"{}\" not saved: {}", request_id, err);
+            }
+        });
+    }
+
+    push(capture, config.capture_buffer_size);
+
+    response
+}
+
+fn push(capture: Capture, size: usize) {
+    let mut buffer = BUFFER.lock();
+    buffer.push_front(capture);
+    buffer.truncate(size);
+}
+
+/// Cut the text at `max` bytes, on a character boundary.
+fn truncate(mut text: String, max: usize) -> (String, bool) {
+    if text.len() <= max {
+        return (text, false);
+    }
+
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    text.truncate(end);
+    (text, true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::Session;
+    use crate::http::request::test::dummy_ip;
+
+    async fn request(path: &str, headers: &str, body: &str) -> Request {
+        let request = format!(
+            "POST {} HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+            path,
+            headers,
+            body.len(),
+            body
+        );
+        Request::read(dummy_ip(), request.as_bytes()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rule_matches() {
+        let req = request("/api/orders/5?debug=1", "X-Tenant: acme\r\n", "").await;
+
+        assert!(CaptureRule::new("all").matches(&req));
+        assert!(CaptureRule::new("prefix").path_prefix("/api").matches(&req));
+        assert!(!CaptureRule::new("prefix")
+            .path_prefix("/admin")
+            .matches(&req));
+        assert!(CaptureRule::new("header")
+            .header("X-Tenant", "acme")
+            .matches(&req));
+        assert!(!CaptureRule::new("header")
+            .header("x-tenant", "acme")
+            .header("x-debug", "1")
+            .matches(&req));
+        assert!(!CaptureRule::new("never").sample_rate(0.0).matches(&req));
+
+        // Guests don't have a user ID.
+        let guest = req.clone().set_session(Some(Session::anonymous()));
+        let id = match guest.session_id() {
+            Some(SessionId::Guest(id)) => id,
+            _ => unreachable!(),
+        };
+        assert!(!CaptureRule::new("user").user_id(5).matches(&guest));
+        assert!(CaptureRule::new("session").session_id(&id).matches(&guest));
+        assert!(!CaptureRule::new("session").session_id(&id).matches(&req));
+
+        let user = req.set_session(Some(
+            Session::new_authenticated(serde_json::json!({}), 5).unwrap(),
+        ));
+        assert!(CaptureRule::new("user").user_id(5).matches(&user));
+        assert!(!CaptureRule::new("user").user_id(6).matches(&user));
+    }
+
+    #[tokio::test]
+    async fn test_capture() {
+        let req = request(
+            "/login?token=abc&page=2",
+            "Authorization: Bearer abc\r\nContent-Type: application/json\r\nX-Request-Id: req-1\r\n",
+            r#"{"email": "a@b.com", "password": "hunter2"}"#,
+        )
+        .await;
+        let response = Response::new()
+            .html("<p>Welcome back, a@b.com</p>")
+            .header("set-cookie", "rwf_session=secret");
+
+        let capture = Capture::new(&req, &response, "login", Duration::from_millis(5), 20);
+        assert_eq!(capture.request_id, "req-1");
+        assert_eq!(capture.rule, "login");
+        assert_eq!(capture.request.method, "POST");
+        assert_eq!(capture.request.path, "/login");
+        assert_eq!(capture.request.query["token"], redact::REDACTED);
+        assert_eq!(capture.request.query["page"], "2");
+        assert_eq!(capture.request.headers["authorization"], redact::REDACTED);
+        assert_eq!(capture.request.body, r#"{"email":"a@b.com",""#);
+        assert!(capture.request.truncated);
+        assert_eq!(capture.response.status, 200);
+        assert_eq!(capture.response.headers["set-cookie"], redact::REDACTED);
+        assert_eq!(capture.response.body, "<p>Welcome back, a@b");
+        assert!(capture.response.truncated);
+
+        let capture = Capture::new(&req, &response, "login", Duration::ZERO, 1024);
+        assert!(!capture.request.body.contains("hunter2"));
+        assert!(!capture.request.truncated);
+
+        // Without the header, the request gets a new ID.
+        let req = request("/", "", "").await;
+        let capture = Capture::new(&req, &Response::new(), "all", Duration::ZERO, 1024);
+        assert!(Uuid::parse_str(&capture.request_id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_buffer() {
+        let req = request("/", "", "").await;
+
+        for i in 0..10 {
+            let mut capture = Capture::new(&req, &Response::new(), "all", Duration::ZERO, 1024);
+            capture.request_id = format!("test-buffer-{}", i);
+            push(capture, 3);
+        }
+
+        let captures = captures();
+        assert_eq!(captures.len(), 3);
+        assert_eq!(captures[0].request_id, "test-buffer-9");
+        assert_eq!(captures[2].request_id, "test-buffer-7");
+
+        assert_eq!(
+            find("test-buffer-8").await.unwrap().unwrap().request_id,
+            "test-buffer-8"
+        );
+        assert!(find("test-buffer-1").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("héllo".into(), 2), ("h".into(), true));
+        assert_eq!(truncate("héllo".into(), 3), ("hé".into(), true));
+        assert_eq!(truncate("héllo".into(), 6), ("héllo".into(), false));
+    }
+}
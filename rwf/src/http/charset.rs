@@ -0,0 +1,145 @@
+//! Character sets of request and response bodies.
+//!
+//! Bodies are UTF-8 unless the `Content-Type` header says otherwise, e.g. `text/plain; charset=iso-8859-1`.
+//! Besides UTF-8, ISO-8859-1 (Latin-1) and Windows-1252 are supported with the `charsets` feature,
+//! which is enabled by default.
+//!
+//! ```
+//! use rwf::http::Charset;
+//!
+//! let charset = Charset::from_label("latin1").unwrap();
+//! assert_eq!(charset.decode(b"caf\xe9"), "café");
+//! assert_eq!(charset.encode("café"), b"caf\xe9");
+//! ```
+use super::Error;
+
+/// Character set of a body.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Charset {
+    /// UTF-8, the default.
+    #[default]
+    Utf8,
+    /// ISO-8859-1, also known as Latin-1.
+    #[cfg(feature = "charsets")]
+    Latin1,
+    /// Windows-1252, a superset of Latin-1 used by Windows, and by browsers for forms labeled Latin-1.
+    #[cfg(feature = "charsets")]
+    Windows1252,
+}
+
+/// Characters of Windows-1252 between `0x80` and `0x9F`, where Latin-1 has control characters.
+/// Bytes that aren't assigned are decoded like in Latin-1.
+#[cfg(feature = "charsets")]
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+impl Charset {
+    /// Find the character set by its name, e.g. `utf-8`, or `ISO-8859-1`. Names are case-insensitive.
+    ///
+    /// Returns an error, which is `415 - Unsupported Media Type` when returned by a controller,
+    /// if the character set isn't supported.
+    pub fn from_label(label: &str) -> Result<Self, Error> {
+        let label = label.trim().trim_matches('"').to_ascii_lowercase();
+
+        match label.as_str() {
+            // ASCII is a subset of UTF-8.
+            "utf-8" | "utf8" | "unicode-1-1-utf-8" | "us-ascii" | "ascii" => Ok(Charset::Utf8),
+            #[cfg(feature = "charsets")]
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "l1" => Ok(Charset::Latin1),
+            #[cfg(feature = "charsets")]
+            "windows-1252" | "cp1252" | "x-cp1252" => Ok(Charset::Windows1252),
+            _ => Err(Error::UnsupportedCharset(label)),
+        }
+    }
+
+    /// Name of the character set used in the `Content-Type` header, e.g. `utf-8`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            #[cfg(feature = "charsets")]
+            Charset::Latin1 => "iso-8859-1",
+            #[cfg(feature = "charsets")]
+            Charset::Windows1252 => "windows-1252",
+        }
+    }
+
+    /// Decode bytes into a string. Invalid UTF-8 sequences are replaced with `�`.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Charset::Utf8 => String::from_utf8_lossy(bytes).to_string(),
+            #[cfg(feature = "charsets")]
+            Charset::Latin1 => bytes.iter().map(|byte| *byte as char).collect(),
+            #[cfg(feature = "charsets")]
+            Charset::Windows1252 => bytes
+                .iter()
+                .map(|byte| match byte {
+                    0x80..=0x9f => WINDOWS_1252[(byte - 0x80) as usize],
+                    byte => *byte as char,
+                })
+                .collect(),
+        }
+    }
+
+    /// Encode a string into bytes. Characters that can't be encoded are replaced with `?`.
+    pub fn encode(&self, value: &str) -> Vec<u8> {
+        match self {
+            Charset::Utf8 => value.as_bytes().to_vec(),
+            #[cfg(feature = "charsets")]
+            Charset::Latin1 => value
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                .collect(),
+            #[cfg(feature = "charsets")]
+            Charset::Windows1252 => value
+                .chars()
+                .map(
+                    |c| match WINDOWS_1252.iter().position(|special| *special == c) {
+                        Some(position) => 0x80 + position as u8,
+                        None => u8::try_from(c).unwrap_or(b'?'),
+                    },
+                )
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for Charset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(Charset::from_label("UTF-8").unwrap(), Charset::Utf8);
+        assert_eq!(Charset::from_label("\"utf-8\"").unwrap(), Charset::Utf8);
+        assert!(matches!(
+            Charset::from_label("koi8-r"),
+            Err(Error::UnsupportedCharset(label)) if label == "koi8-r"
+        ));
+    }
+
+    #[cfg(feature = "charsets")]
+    #[test]
+    fn test_single_byte() {
+        let latin1 = Charset::from_label("ISO-8859-1").unwrap();
+        assert_eq!(latin1, Charset::Latin1);
+        assert_eq!(latin1.decode(b"\xe9t\xe9"), "été");
+        assert_eq!(latin1.encode("été €"), b"\xe9t\xe9 ?");
+
+        let windows = Charset::from_label("cp1252").unwrap();
+        assert_eq!(windows.decode(b"\x80 \x93ok\x94"), "€ “ok”");
+        assert_eq!(windows.encode("€ “ok” é"), b"\x80 \x93ok\x94 \xe9");
+
+        // Every byte round-trips.
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(latin1.encode(&latin1.decode(&bytes)), bytes);
+        assert_eq!(windows.encode(&windows.decode(&bytes)), bytes);
+    }
+}
@@ -0,0 +1,369 @@
+//! Response compression with gzip.
+//!
+//! Responses are compressed by the server for clients that accept gzip in the `Accept-Encoding` header, if their
+//! content type is text, like HTML, JSON, CSS or server-sent events. See [`Response::compress`](super::Response::compress).
+//!
+//! Streams are compressed as they are sent. The encoder is flushed whenever the stream has nothing more to send
+//! right away, so events arrive as soon as they are written, instead of waiting in the encoder. Small chunks
+//! ready at the same time are compressed together, since each flush makes the compression less effective.
+use std::io::{self, Write};
+use std::marker::Unpin;
+
+use flate2::{write::GzEncoder, Compression};
+use futures_util::FutureExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+use super::{body::CHUNK_SIZE, writer::WriterError, Request, ResponseWriter};
+
+/// Bodies smaller than this many bytes aren't compressed, since it wouldn't save much.
+pub const MIN_SIZE: usize = 1024;
+
+/// Chunks of a stream smaller than this are held back, and compressed with the following ones, if more data is
+/// ready right away.
+pub const MIN_CHUNK_SIZE: usize = 4096;
+
+/// Content coding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// `gzip`.
+    Gzip,
+}
+
+impl Encoding {
+    /// Content coding accepted by the client, from the `Accept-Encoding` header.
+    pub fn accepted(request: &Request) -> Option<Self> {
+        let header = request.header("accept-encoding")?;
+        let mut gzip = None;
+        let mut any = None;
+
+        for coding in header.split(',') {
+            let mut parts = coding.split(';');
+            let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map(|(_, q)| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+
+            match name.as_str() {
+                "gzip" | "x-gzip" => gzip = Some(quality),
+                "*" => any = Some(quality),
+                _ => (),
+            }
+        }
+
+        match gzip.or(any) {
+            Some(quality) if quality > 0.0 => Some(Encoding::Gzip),
+            _ => None,
+        }
+    }
+
+    /// Name of the coding in the `Content-Encoding` header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// Is the content type worth compressing? Text is, images, videos and archives are usually compressed already.
+pub fn compressible(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/x-ndjson"
+                | "application/wasm"
+        )
+}
+
+/// Compress the whole body.
+pub fn compress(encoding: Encoding, body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = StreamEncoder::new(encoding);
+    encoder.write(body)?;
+    encoder.finish()
+}
+
+/// Compresses a body sent in parts, see the [module documentation](self).
+pub struct StreamEncoder {
+    encoder: GzEncoder<Vec<u8>>,
+    pending: usize,
+}
+
+impl StreamEncoder {
+    /// Create an encoder.
+    pub fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Gzip => Self {
+                encoder: GzEncoder::new(Vec::new(), Compression::default()),
+                pending: 0,
+            },
+        }
+    }
+
+    /// Compress a part of the body. The compressed bytes may be held in the encoder until it's flushed.
+    pub fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.encoder.write_all(chunk)?;
+        self.pending += chunk.len();
+        Ok(())
+    }
+
+    /// Bytes written since the last flush.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Compressed bytes of everything written so far, which the client can decompress right away.
+    pub fn flush(&mut self) -> io::Result<Vec<u8>> {
+        self.encoder.flush()?;
+        self.pending = 0;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+
+    /// The rest of the compressed body, including the gzip footer.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        self.encoder.finish()
+    }
+}
+
+/// Send everything the reader returns as the compressed body.
+pub(crate) async fn copy_body<S: AsyncWrite + Unpin>(
+    writer: &mut ResponseWriter<S>,
+    mut reader: impl AsyncRead + Unpin,
+    encoding: Encoding,
+) -> Result<usize, WriterError> {
+    let mut encoder = StreamEncoder::new(encoding);
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut copied = 0;
+    let mut done = false;
+
+    while !done {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        encoder.write(&buffer[..read])?;
+        copied += read;
+
+        // Hold back small chunks while more data is ready.
+        while encoder.pending() < MIN_CHUNK_SIZE {
+            match reader.read(&mut buffer).now_or_never() {
+                Some(Ok(0)) => {
+                    done = true;
+                    break;
+                }
+                Some(Ok(read)) => {
+                    encoder.write(&buffer[..read])?;
+                    copied += read;
+                }
+                Some(Err(err)) => return Err(err.into()),
+                None => break,
+            }
+        }
+
+        if !done {
+            writer.write_body_chunk(&encoder.flush()?).await?;
+        }
+    }
+
+    writer.write_body_chunk(&encoder.finish()?).await?;
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::Response;
+    use flate2::write::GzDecoder;
+    use std::time::Duration;
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    fn request(accept_encoding: &str) -> Request {
+        let mut request = Request::default();
+        request
+            .head_mut()
+            .headers_mut()
+            .insert("accept-encoding", accept_encoding);
+        request
+    }
+
+    #[test]
+    fn test_accepted() {
+        for (header, accepted) in [
+            ("gzip, deflate, br", true),
+            ("GZIP;q=0.5", true),
+            ("x-gzip", true),
+            ("*", true),
+            ("br, *;q=0.1", true),
+            ("gzip;q=0", false),
+            ("gzip;q=0, *", false),
+            ("*;q=0", false),
+            ("identity", false),
+            ("br", false),
+            ("", false),
+        ] {
+            assert_eq!(
+                Encoding::accepted(&request(header)).is_some(),
+                accepted,
+                "{}",
+                header
+            );
+        }
+        assert!(Encoding::accepted(&Request::default()).is_none());
+    }
+
+    #[test]
+    fn test_compressible() {
+        for content_type in [
+            "text/html; charset=utf-8",
+            "text/event-stream",
+            "text/vnd.turbo-stream.html",
+            "application/json",
+            "application/problem+json",
+            "image/svg+xml",
+        ] {
+            assert!(compressible(content_type), "{}", content_type);
+        }
+
+        for content_type in ["image/png", "application/zip", "application/octet-stream"] {
+            assert!(!compressible(content_type), "{}", content_type);
+        }
+    }
+
+    #[test]
+    fn test_stream_encoder() {
+        let mut encoder = StreamEncoder::new(Encoding::Gzip);
+        let mut decoder = GzDecoder::new(Vec::new());
+
+        // Each event can be decompressed as soon as it's flushed.
+        for event in ["data: one\n\n", "data: two\n\n", "data: three\n\n"] {
+            encoder.write(event.as_bytes()).unwrap();
+            assert_eq!(encoder.pending(), event.len());
+
+            decoder.write_all(&encoder.flush().unwrap()).unwrap();
+            decoder.flush().unwrap();
+            assert!(String::from_utf8_lossy(decoder.get_ref()).ends_with(event));
+            assert_eq!(encoder.pending(), 0);
+        }
+
+        decoder.write_all(&encoder.finish().unwrap()).unwrap();
+        assert_eq!(
+            decoder.finish().unwrap(),
+            b"data: one\n\ndata: two\n\ndata: three\n\n"
+        );
+    }
+
+    // Decode a chunked body from the wire, as far as it was received.
+    fn decode_chunks(wire: &[u8], decoder: &mut GzDecoder<Vec<u8>>) -> usize {
+        let mut offset = 0;
+        let mut chunks = 0;
+
+        while let Some(end) = wire[offset..].windows(2).position(|w| w == b"\r\n") {
+            let size = usize::from_str_radix(
+                std::str::from_utf8(&wire[offset..offset + end]).unwrap(),
+                16,
+            )
+            .unwrap();
+            let start = offset + end + 2;
+            if wire.len() < start + size + 2 {
+                break;
+            }
+            decoder.write_all(&wire[start..start + size]).unwrap();
+            decoder.flush().unwrap();
+            offset = start + size + 2;
+            chunks += 1;
+        }
+
+        chunks
+    }
+
+    #[tokio::test]
+    async fn test_sse_events_arrive() {
+        let (mut events, reader) = duplex(1024);
+        let (client, mut server) = duplex(64 * 1024);
+
+        let response = Response::new()
+            .stream(reader)
+            .header("content-type", "text/event-stream")
+            .compress(&request("gzip"));
+        assert_eq!(response.headers().get("content-encoding").unwrap(), "gzip");
+
+        let send = tokio::spawn(async move { response.send(client).await.unwrap() });
+
+        let mut wire = vec![];
+        let mut buf = vec![0u8; 4096];
+
+        let mut read_head = true;
+        for event in ["data: one\n\n", "data: two\n\n", "data: three\n\n"] {
+            events.write_all(event.as_bytes()).await.unwrap();
+
+            // The event arrives while the stream is still open.
+            loop {
+                let read = tokio::time::timeout(Duration::from_secs(5), server.read(&mut buf))
+                    .await
+                    .expect("event wasn't flushed")
+                    .unwrap();
+                wire.extend_from_slice(&buf[..read]);
+
+                if read_head {
+                    if let Some(end) = wire.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let head = String::from_utf8_lossy(&wire[..end]).to_string();
+                        assert!(head.contains("content-encoding: gzip"), "{}", head);
+                        assert!(head.contains("transfer-encoding: chunked"), "{}", head);
+                        wire.drain(..end + 4);
+                        read_head = false;
+                    } else {
+                        continue;
+                    }
+                }
+
+                let mut decoder = GzDecoder::new(Vec::new());
+                decode_chunks(&wire, &mut decoder);
+                if String::from_utf8_lossy(decoder.get_ref()).ends_with(event) {
+                    break;
+                }
+            }
+        }
+
+        drop(events);
+        send.await.unwrap();
+        server.read_to_end(&mut wire).await.unwrap();
+
+        let mut decoder = GzDecoder::new(Vec::new());
+        decode_chunks(&wire, &mut decoder);
+        assert_eq!(
+            decoder.finish().unwrap(),
+            b"data: one\n\ndata: two\n\ndata: three\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_small_chunks_coalesce() {
+        let mut reader = &b"data: 1\n\n".repeat(1000)[..];
+        let mut writer = ResponseWriter::new(vec![]);
+        writer.header("content-type", "text/event-stream").unwrap();
+        copy_body(&mut writer, &mut reader, Encoding::Gzip)
+            .await
+            .unwrap();
+        writer.finish().await.unwrap();
+
+        let wire = writer.into_inner();
+        let body = &wire[wire.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4..];
+        let mut decoder = GzDecoder::new(Vec::new());
+        // All the data was ready at once, so it's flushed in a few chunks, not one per read.
+        assert!(decode_chunks(body, &mut decoder) <= 4);
+        assert_eq!(decoder.finish().unwrap(), b"data: 1\n\n".repeat(1000));
+    }
+}
@@ -0,0 +1,188 @@
+//! Limit on how many requests a route handles at the same time.
+//!
+//! Expensive endpoints, e.g. reports, can use up all workers and slow down the rest of the application.
+//! A route with a limit handles at most that many requests concurrently, across all connections:
+//!
+//! ```rust,ignore
+//! route!("/reports" => Reports)
+//!     .max_concurrency(4)
+//!     .queue_timeout(Duration::from_millis(500))
+//! ```
+//!
+//! Requests over the limit wait in a queue for up to the queue timeout, and are rejected with `503 - Service Unavailable`
+//! if no slot opens up in time. Without a queue timeout, they are rejected immediately. Time spent in the queue counts towards
+//! the request's duration, and is reported as `queue` in the `Server-Timing` header.
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::timeout;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::Error;
+
+/// Seconds the client is asked to wait before retrying a request rejected
+/// because the route is at its limit.
+pub const RETRY_AFTER: u64 = 1;
+
+#[derive(Debug)]
+struct Inner {
+    semaphore: Semaphore,
+    limit: usize,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+/// Concurrency limit of a route.
+///
+/// It's cheap to clone, and clones share the limit and its statistics.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    inner: Arc<Inner>,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimit {
+    /// Allow at most `limit` concurrent requests. Requests over the limit are rejected immediately.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                semaphore: Semaphore::new(limit),
+                limit,
+                queued: AtomicUsize::new(0),
+                rejected: AtomicU64::new(0),
+            }),
+            queue_timeout: Duration::ZERO,
+        }
+    }
+
+    /// Wait up to this long for a slot before rejecting a request.
+    pub fn queue_timeout(mut self, queue_timeout: Duration) -> Self {
+        self.queue_timeout = queue_timeout;
+        self
+    }
+
+    /// How long requests wait for a slot before they are rejected.
+    pub fn queue_timeout_duration(&self) -> Duration {
+        self.queue_timeout
+    }
+
+    /// Wait for a slot to handle a request. The slot is released when the permit is dropped.
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, Error> {
+        if let Ok(permit) = self.inner.semaphore.try_acquire() {
+            return Ok(permit);
+        }
+
+        if !self.queue_timeout.is_zero() {
+            let _queued = Queued::new(&self.inner.queued);
+
+            if let Ok(Ok(permit)) =
+                timeout(self.queue_timeout, self.inner.semaphore.acquire()).await
+            {
+                return Ok(permit);
+            }
+        }
+
+        self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+
+        Err(Error::ConcurrencyLimitExceeded {
+            limit: self.inner.limit,
+        })
+    }
+
+    /// Maximum number of concurrent requests.
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Number of requests being handled right now.
+    pub fn current(&self) -> usize {
+        self.inner.limit - self.inner.semaphore.available_permits()
+    }
+
+    /// Number of requests waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.inner.queued.load(Ordering::Relaxed)
+    }
+
+    /// How many requests were rejected because the route was at its limit.
+    pub fn rejected(&self) -> u64 {
+        self.inner.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Current statistics.
+    pub fn stats(&self) -> ConcurrencyStats {
+        ConcurrencyStats {
+            limit: self.limit(),
+            current: self.current(),
+            queued: self.queued(),
+            rejected: self.rejected(),
+        }
+    }
+}
+
+/// Snapshot of a concurrency limit's statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ConcurrencyStats {
+    /// Maximum number of concurrent requests.
+    pub limit: usize,
+    /// Requests being handled.
+    pub current: usize,
+    /// Requests waiting for a slot.
+    pub queued: usize,
+    /// Requests rejected since the server started.
+    pub rejected: u64,
+}
+
+/// Counts a request as queued until it's dropped, even if the request is cancelled.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl<'a> Queued<'a> {
+    fn new(queued: &'a AtomicUsize) -> Self {
+        queued.fetch_add(1, Ordering::Relaxed);
+        Self(queued)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let limit = ConcurrencyLimit::new(2);
+
+        let first = limit.acquire().await.unwrap();
+        let _second = limit.acquire().await.unwrap();
+        assert_eq!(limit.current(), 2);
+
+        assert!(matches!(
+            limit.acquire().await,
+            Err(Error::ConcurrencyLimitExceeded { limit: 2 })
+        ));
+        assert_eq!(limit.rejected(), 1);
+
+        // Queued requests get the slot once it's released.
+        let queue = limit.clone().queue_timeout(Duration::from_secs(5));
+        let waiting = tokio::spawn(async move {
+            let _permit = queue.acquire().await.unwrap();
+        });
+
+        while limit.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        drop(first);
+        waiting.await.unwrap();
+
+        assert_eq!(limit.queued(), 0);
+        assert_eq!(limit.current(), 1);
+        assert_eq!(limit.rejected(), 1);
+    }
+}
@@ -0,0 +1,321 @@
+//! Conditional requests.
+//!
+//! Responses with an `ETag` or a `Last-Modified` header can be cached by the client. When it asks for the resource again,
+//! it sends the validators back with `If-None-Match` and `If-Modified-Since`, and if they still match, the response is replaced with
+//! an empty `304 - Not Modified`, saving the bandwidth of sending it again.
+//!
+//! [`Response::cacheable`](super::Response::cacheable) computes the validators automatically, from the body hash or the file
+//! modification time, and [`Response::etag`](super::Response::etag) sets the `ETag` explicitly. The check is done
+//! automatically for all responses returned by controllers.
+//!
+//! Requests which change a resource, e.g. `PUT`, can send `If-Match` with the `ETag` of the version they changed, or
+//! `If-Unmodified-Since` with its `Last-Modified` date. If the resource changed since, the update would overwrite someone else's,
+//! so the controller should refuse it with `412 - Precondition Failed`, see [`Request::precondition`].
+use once_cell::sync::Lazy;
+use time::format_description::{self, FormatItem};
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+use super::{Method, Request};
+
+/// Format of dates in HTTP headers, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+static HTTP_DATE: Lazy<Vec<FormatItem<'static>>> = Lazy::new(|| {
+    format_description::parse(
+        "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT",
+    )
+    .expect("valid http date format")
+});
+
+/// Format the date for an HTTP header, e.g. `Last-Modified`.
+pub fn format_http_date(date: OffsetDateTime) -> String {
+    date.to_offset(UtcOffset::UTC)
+        .format(&HTTP_DATE)
+        .unwrap_or_default()
+}
+
+/// Parse a date from an HTTP header, e.g. `If-Modified-Since`.
+pub fn parse_http_date(date: &str) -> Option<OffsetDateTime> {
+    PrimitiveDateTime::parse(date.trim(), &HTTP_DATE)
+        .ok()
+        .map(|date| date.assume_utc())
+}
+
+/// Check if the `If-None-Match` header lists the `ETag`, using the weak comparison:
+/// `W/"1"` matches `"1"`.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim().trim_start_matches("W/");
+
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Entity tags in the `If-Match` header.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IfMatch {
+    /// `*`, any current version of the resource.
+    Any,
+    /// Entity tags, including quotes and the `W/` prefix of weak tags, e.g. `"v5"`.
+    Tags(Vec<String>),
+}
+
+impl IfMatch {
+    /// Parse the value of the `If-Match` header. Returns `None` if it's malformed.
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.trim() == "*" {
+            return Some(IfMatch::Any);
+        }
+
+        parse_etags(value).map(IfMatch::Tags)
+    }
+
+    /// Does the header match the `ETag`? Uses the strong comparison: neither tag can be weak.
+    pub fn matches(&self, etag: &str) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Tags(tags) => {
+                let etag = etag.trim();
+                !etag.starts_with("W/") && tags.iter().any(|tag| tag == etag)
+            }
+        }
+    }
+}
+
+/// Parse a list of entity tags, e.g. `"1", W/"2"`. Tags can contain commas, so the list
+/// isn't simply split on them.
+fn parse_etags(value: &str) -> Option<Vec<String>> {
+    let mut tags = vec![];
+    let mut rest = value.trim();
+
+    while !rest.is_empty() {
+        let weak = rest.starts_with("W/");
+        let tag = rest.strip_prefix("W/").unwrap_or(rest);
+        let end = tag.strip_prefix('"')?.find('"')? + 2;
+        let (tag, after) = tag.split_at(end);
+
+        tags.push(if weak {
+            format!("W/{}", tag)
+        } else {
+            tag.to_string()
+        });
+
+        rest = after.trim_start();
+        if !rest.is_empty() {
+            rest = rest.strip_prefix(',')?.trim_start();
+        }
+    }
+
+    if tags.is_empty() {
+        None
+    } else {
+        Some(tags)
+    }
+}
+
+/// Result of evaluating `If-Match` and `If-Unmodified-Since`, see [`Request::precondition`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precondition {
+    /// The client changed the current version of the resource, or didn't send preconditions.
+    Passed,
+    /// The resource changed since the client last saw it. Respond with
+    /// [`Response::precondition_failed`](super::Response::precondition_failed).
+    Failed,
+}
+
+impl Precondition {
+    /// Did the preconditions pass?
+    pub fn passed(&self) -> bool {
+        *self == Precondition::Passed
+    }
+}
+
+/// Evaluate the `If-Match` and `If-Unmodified-Since` headers against the current version of the resource,
+/// as required by RFC 9110.
+///
+/// `If-Match` is checked first; `If-Unmodified-Since` is used only if the client didn't send an `If-Match`.
+/// A malformed `If-Match`, or one listing only weak tags, fails, so a broken client can't overwrite changes.
+/// A malformed `If-Unmodified-Since` is ignored.
+pub fn precondition(
+    request: &Request,
+    etag: Option<&str>,
+    last_modified: Option<OffsetDateTime>,
+) -> Precondition {
+    if let Some(if_match) = request.header("if-match") {
+        let passed = match (IfMatch::parse(if_match), etag) {
+            (Some(IfMatch::Any), _) => true,
+            (Some(if_match), Some(etag)) => if_match.matches(etag),
+            _ => false,
+        };
+
+        return if passed {
+            Precondition::Passed
+        } else {
+            Precondition::Failed
+        };
+    }
+
+    match (request.if_unmodified_since(), last_modified) {
+        // HTTP dates don't have fractions of a second.
+        (Some(since), Some(last_modified))
+            if last_modified.unix_timestamp() > since.unix_timestamp() =>
+        {
+            Precondition::Failed
+        }
+        _ => Precondition::Passed,
+    }
+}
+
+/// The client has the current version of the resource, and can be sent `304 - Not Modified`.
+///
+/// `If-None-Match` is checked first. `If-Modified-Since` is used only if the client didn't send an
+/// `If-None-Match`, as required by RFC 9110. Only `GET` and `HEAD` requests can be answered with `304`.
+pub fn not_modified(request: &Request, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+    if !matches!(request.method(), Method::Get | Method::Head) {
+        return false;
+    }
+
+    if let Some(if_none_match) = request.header("if-none-match") {
+        return match etag {
+            Some(etag) => etag_matches(if_none_match, etag),
+            None => false,
+        };
+    }
+
+    match (
+        request
+            .header("if-modified-since")
+            .and_then(|since| parse_http_date(since)),
+        last_modified.and_then(parse_http_date),
+    ) {
+        (Some(since), Some(last_modified)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn request(method: &str, header: &str, value: &str) -> Request {
+        let request = format!(
+            "{} /users/1 HTTP/1.1\r\n{}: {}\r\n\r\n",
+            method, header, value
+        );
+        Request::read("127.0.0.1:1234".parse().unwrap(), request.as_bytes())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_http_date() {
+        let date = OffsetDateTime::from_unix_timestamp(784111777).unwrap();
+        let formatted = format_http_date(date);
+        assert_eq!(formatted, "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date(&formatted), Some(date));
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("W/\"1-abc\"", "W/\"1-abc\""));
+        assert!(etag_matches("\"0-x\", \"1-abc\"", "W/\"1-abc\""));
+        assert!(etag_matches("*", "\"1\""));
+        assert!(!etag_matches("W/\"1-abd\"", "W/\"1-abc\""));
+    }
+
+    #[tokio::test]
+    async fn test_not_modified() {
+        let etag = Some("W/\"1\"");
+        let last_modified = Some("Sat, 02 Nov 2024 10:15:00 GMT");
+
+        let fresh = request("GET", "If-None-Match", "\"1\"").await;
+        assert!(not_modified(&fresh, etag, last_modified));
+        assert!(!not_modified(&fresh, None, last_modified));
+
+        let stale = request("GET", "If-None-Match", "\"2\"").await;
+        assert!(!not_modified(&stale, etag, last_modified));
+
+        let fresh = request("GET", "If-Modified-Since", "Sat, 02 Nov 2024 10:15:00 GMT").await;
+        assert!(not_modified(&fresh, etag, last_modified));
+
+        let stale = request("GET", "If-Modified-Since", "Sat, 02 Nov 2024 10:14:59 GMT").await;
+        assert!(!not_modified(&stale, etag, last_modified));
+
+        let post = request("POST", "If-None-Match", "\"1\"").await;
+        assert!(!not_modified(&post, etag, last_modified));
+    }
+
+    #[test]
+    fn test_if_match() {
+        assert_eq!(IfMatch::parse(" * "), Some(IfMatch::Any));
+        assert_eq!(
+            IfMatch::parse(r#""1", W/"2","a,b""#),
+            Some(IfMatch::Tags(vec![
+                r#""1""#.to_string(),
+                r#"W/"2""#.to_string(),
+                r#""a,b""#.to_string()
+            ]))
+        );
+
+        for malformed in ["", "1", r#""1"#, r#""1" "2""#, r#""1",,"2""#, r#""1", *"#] {
+            assert!(IfMatch::parse(malformed).is_none(), "{}", malformed);
+        }
+
+        let if_match = IfMatch::parse(r#""1", W/"2""#).unwrap();
+        assert!(if_match.matches(r#""1""#));
+        // Strong comparison.
+        assert!(!if_match.matches(r#""2""#));
+        assert!(!if_match.matches(r#"W/"2""#));
+        assert!(!if_match.matches(r#""3""#));
+    }
+
+    #[tokio::test]
+    async fn test_precondition() {
+        let etag = Some("\"5\"");
+        let last_modified = parse_http_date("Sat, 02 Nov 2024 10:15:00 GMT");
+
+        for (value, expected) in [
+            ("\"5\"", Precondition::Passed),
+            ("\"4\", \"5\"", Precondition::Passed),
+            ("*", Precondition::Passed),
+            ("\"4\"", Precondition::Failed),
+            ("W/\"5\"", Precondition::Failed),
+            ("5", Precondition::Failed),
+        ] {
+            let put = request("PUT", "If-Match", value).await;
+            assert_eq!(
+                precondition(&put, etag, last_modified),
+                expected,
+                "{}",
+                value
+            );
+        }
+
+        // The resource doesn't have an ETag.
+        let put = request("PUT", "If-Match", "\"5\"").await;
+        assert_eq!(
+            precondition(&put, None, last_modified),
+            Precondition::Failed
+        );
+
+        let fresh = request(
+            "PUT",
+            "If-Unmodified-Since",
+            "Sat, 02 Nov 2024 10:15:00 GMT",
+        )
+        .await;
+        assert!(precondition(&fresh, etag, last_modified).passed());
+        assert!(precondition(&fresh, etag, None).passed());
+
+        let stale = request(
+            "PUT",
+            "If-Unmodified-Since",
+            "Sat, 02 Nov 2024 10:14:59 GMT",
+        )
+        .await;
+        assert!(!precondition(&stale, etag, last_modified).passed());
+
+        let invalid = request("PUT", "If-Unmodified-Since", "yesterday").await;
+        assert!(precondition(&invalid, etag, last_modified).passed());
+    }
+}
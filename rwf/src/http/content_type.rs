@@ -0,0 +1,84 @@
+//! The `Content-Type` header.
+//!
+//! ```
+//! use rwf::http::{Charset, ContentType};
+//!
+//! let content_type = ContentType::parse("text/HTML; Charset=\"UTF-8\"");
+//!
+//! assert_eq!(content_type.media_type(), "text/html");
+//! assert_eq!(content_type.param("charset"), Some("UTF-8"));
+//! assert_eq!(content_type.charset().unwrap(), Charset::Utf8);
+//! ```
+use super::{Charset, Error};
+
+/// Media type and parameters of a `Content-Type` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentType {
+    media_type: String,
+    params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    /// Parse the header value, e.g. `multipart/form-data; boundary=abc`.
+    pub fn parse(value: &str) -> Self {
+        let mut parts = value.split(';');
+        let media_type = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| {
+                (
+                    name.trim().to_ascii_lowercase(),
+                    value.trim().trim_matches('"').to_string(),
+                )
+            })
+            .collect();
+
+        Self { media_type, params }
+    }
+
+    /// Media type, lowercase, without parameters, e.g. `text/html`.
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// Value of a parameter, e.g. `boundary`. Parameter names are case-insensitive.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Character set of the body, UTF-8 if it's not set.
+    pub fn charset(&self) -> Result<Charset, Error> {
+        match self.param("charset") {
+            Some(charset) => Charset::from_label(charset),
+            None => Ok(Charset::Utf8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let content_type =
+            ContentType::parse("multipart/form-data; charset=utf-8; boundary=----abc");
+        assert_eq!(content_type.media_type(), "multipart/form-data");
+        assert_eq!(content_type.param("boundary"), Some("----abc"));
+        assert_eq!(content_type.param("BOUNDARY"), Some("----abc"));
+        assert_eq!(content_type.param("name"), None);
+
+        let content_type = ContentType::parse("application/json");
+        assert_eq!(content_type.media_type(), "application/json");
+        assert_eq!(content_type.charset().unwrap(), Charset::Utf8);
+
+        let content_type = ContentType::parse("text/plain; charset=koi8-r");
+        assert!(matches!(
+            content_type.charset(),
+            Err(Error::UnsupportedCharset(_))
+        ));
+    }
+}
@@ -1,12 +1,17 @@
 //! Browser cookies.
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use time::{Duration, OffsetDateTime};
 
+use super::conditional::{format_http_date, parse_http_date};
 use super::url::urldecode;
 use super::Error;
 use crate::config::get_config;
 use crate::controller::Session;
-use crate::crypto::{decrypt, encrypt};
+use crate::crypto::{decrypt, encrypt, sign, verify_signature};
+
+/// Version of the signed cookie format, so the format can change without breaking existing cookies.
+const SIGNED_COOKIE_VERSION: &str = "v1";
 
 /// Cookies storage and interface.
 #[derive(Debug, Clone, Default)]
@@ -61,6 +66,45 @@ impl Cookies {
         }
     }
 
+    /// Add a signed cookie and send it to the client. The value stays readable, but can't be changed
+    /// without the change being detected, since the signature is computed with the secret key.
+    ///
+    /// The cookie is sent as `value.v1.signature`. The signature covers the cookie name, so the value
+    /// can't be copied into another signed cookie.
+    pub fn add_signed(&mut self, cookie: impl ToCookie) {
+        let mut cookie = cookie.to_cookie();
+        let signature = sign(Self::signed_payload(SIGNED_COOKIE_VERSION, &cookie).as_bytes());
+        cookie.value = format!("{}.{}.{}", cookie.value, SIGNED_COOKIE_VERSION, signature);
+        self.cookies.insert(cookie.name.clone(), cookie);
+    }
+
+    /// Get a signed cookie received from the client, with the signature removed. If the signature
+    /// is missing or doesn't match, `None` is returned. Signatures created with one of the
+    /// previous secret keys are accepted.
+    pub fn get_signed(&self, name: &str) -> Option<Cookie> {
+        let mut cookie = self.cookies.get(name)?.clone();
+
+        let signed = std::mem::take(&mut cookie.value);
+        let mut parts = signed.rsplitn(3, '.');
+        let (signature, version, value) = (parts.next()?, parts.next()?, parts.next()?);
+
+        if version != SIGNED_COOKIE_VERSION {
+            return None;
+        }
+
+        cookie.value = value.to_string();
+
+        if verify_signature(Self::signed_payload(version, &cookie).as_bytes(), signature) {
+            Some(cookie)
+        } else {
+            None
+        }
+    }
+
+    fn signed_payload(version: &str, cookie: &Cookie) -> String {
+        format!("{}\0{}\0{}", version, cookie.name, cookie.value)
+    }
+
     /// Add a cookie to the response and send it to the client, using the `Set-Cookie` header.
     pub fn add(&mut self, cookie: impl ToCookie) {
         let cookie = cookie.to_cookie();
@@ -89,15 +133,67 @@ impl Cookies {
 
     /// Set a sessionn cookie and send it to the client. The cookie expires
     /// when the session does.
+    ///
+    /// The cookie is `HttpOnly` and `SameSite=Lax` by default. This can be changed
+    /// with the `session_cookie_*` settings.
     pub fn add_session(&mut self, session: &Session) -> Result<(), Error> {
+        let config = &get_config().general;
         let value = serde_json::to_string(session)?;
-        self.add_private(
-            CookieBuilder::new()
-                .name("rwf_session")
-                .value(value)
-                .expiration(OffsetDateTime::from_unix_timestamp(session.expiration)?)
-                .build(),
-        )
+        let mut builder = CookieBuilder::new()
+            .name("rwf_session")
+            .value(value)
+            .expires(OffsetDateTime::from_unix_timestamp(session.expiration)?)
+            .same_site(config.session_cookie_same_site);
+
+        if config.session_cookie_http_only {
+            builder = builder.http_only();
+        }
+
+        if config.session_cookie_secure {
+            builder = builder.secure();
+        }
+
+        self.add_private(builder.build())
+    }
+
+    /// Iterate over all cookies. On a response, these are the cookies that will be sent to the client,
+    /// including the session cookie.
+    pub fn iter(&self) -> impl Iterator<Item = &Cookie> {
+        self.cookies.values()
+    }
+
+    /// Get a cookie that will be sent to the client. Private cookies, including the session,
+    /// are returned encrypted.
+    pub fn get_pending(&self, name: &str) -> Option<&Cookie> {
+        self.cookies.get(name)
+    }
+
+    /// Remove a cookie so it won't be sent to the client. Returns the cookie, if it was set.
+    pub fn remove_pending(&mut self, name: &str) -> Option<Cookie> {
+        self.cookies.remove(name)
+    }
+
+    /// Transform all cookies that will be sent to the client, e.g. to make sure they are all `Secure`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::{Cookies, CookieBuilder};
+    ///
+    /// let mut cookies = Cookies::new();
+    /// cookies.add(("theme", "dark"));
+    /// cookies.map_pending(|cookie| CookieBuilder::from(cookie).secure().build());
+    ///
+    /// assert!(cookies.get_pending("theme").unwrap().secure());
+    /// ```
+    pub fn map_pending(&mut self, mut f: impl FnMut(Cookie) -> Cookie) {
+        self.cookies = std::mem::take(&mut self.cookies)
+            .into_values()
+            .map(|cookie| {
+                let cookie = f(cookie);
+                (cookie.name.clone(), cookie)
+            })
+            .collect();
     }
 
     /// Convert cookies to `Set-Cookie` headers which will be sent to the client.
@@ -148,6 +244,45 @@ impl ToCookie for Cookie {
     }
 }
 
+/// The cookie's `SameSite` attribute, which controls if the browser sends it with requests
+/// coming from other sites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SameSite {
+    /// Only sent with requests from the same site.
+    Strict,
+    /// Also sent when the user follows a link from another site. This is the default.
+    #[default]
+    Lax,
+    /// Sent with all requests. Browsers require the cookie to be `Secure`,
+    /// so the attribute is always added.
+    None,
+}
+
+impl SameSite {
+    /// Value of the attribute.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+impl std::str::FromStr for SameSite {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(()),
+        }
+    }
+}
+
 /// A browser cookie.
 #[derive(Debug, Clone, Default)]
 pub struct Cookie {
@@ -159,7 +294,7 @@ pub struct Cookie {
     domain: Option<String>,
     http_only: bool,
     secure: bool,
-    same_site: Option<String>,
+    same_site: Option<SameSite>,
 }
 
 impl Cookie {
@@ -180,6 +315,21 @@ impl Cookie {
         for part in parts {
             match Self::key_value(part) {
                 (Some(key), value) => match key.as_str().trim() {
+                    "Path" => {
+                        if let Some(value) = value {
+                            builder = builder.path(value);
+                        }
+                    }
+                    "SameSite" => {
+                        if let Some(Ok(same_site)) = value.map(|value| value.parse()) {
+                            builder = builder.same_site(same_site);
+                        }
+                    }
+                    "Expires" => {
+                        if let Some(expires) = value.and_then(|value| parse_http_date(&value)) {
+                            builder = builder.expires(expires);
+                        }
+                    }
                     "Domain" => {
                         if let Some(value) = value {
                             builder = builder.domain(value);
@@ -212,7 +362,7 @@ impl Cookie {
     }
 
     fn key_value(s: &str) -> (Option<String>, Option<String>) {
-        let mut parts = s.split("=");
+        let mut parts = s.splitn(2, "=");
         if let Some(key) = parts.next() {
             if let Some(value) = parts.next() {
                 (Some(key.to_owned()), Some(value.to_owned()))
@@ -257,16 +407,33 @@ impl Cookie {
     pub fn max_age(&self) -> Option<Duration> {
         self.max_age
     }
+
+    /// Get the cookie's `Path` attribute if any is set.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Get the cookie's `Domain` attribute if any is set.
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    /// Get the cookie's `Expires` attribute if any is set.
+    pub fn expires(&self) -> Option<OffsetDateTime> {
+        self.expiration
+    }
+
+    /// Get the cookie's `SameSite` attribute if any is set. If not, `Lax` is sent to the client.
+    pub fn same_site(&self) -> Option<&str> {
+        self.same_site.map(|same_site| same_site.as_str())
+    }
 }
 
 impl std::fmt::Display for Cookie {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}={}", self.name, self.value)?;
-        if let Some(ref max_age) = self.max_age {
-            write!(f, "; Max-Age: {}", max_age.whole_seconds())?;
-        }
 
-        if self.secure {
+        if self.secure || self.same_site == Some(SameSite::None) {
             write!(f, "; Secure")?;
         }
 
@@ -284,24 +451,18 @@ impl std::fmt::Display for Cookie {
             write!(f, "; Domain={}", domain)?;
         }
 
-        if let Some(ref same_site) = self.same_site {
-            write!(f, "; SameSite={}", same_site)?;
-        } else {
-            write!(f, "; SameSite=Lax")?;
-        }
+        write!(
+            f,
+            "; SameSite={}",
+            self.same_site.unwrap_or_default().as_str()
+        )?;
 
         if let Some(ref max_age) = self.max_age {
             write!(f, "; Max-Age={}", max_age.whole_seconds())?;
         }
 
-        if let Some(ref expiration) = self.expiration {
-            write!(
-                f,
-                "; Expires={}",
-                expiration
-                    .format(&time::format_description::well_known::Rfc2822)
-                    .unwrap()
-            )?;
+        if let Some(expiration) = self.expiration {
+            write!(f, "; Expires={}", format_http_date(expiration))?;
         }
 
         Ok(())
@@ -340,6 +501,11 @@ impl CookieBuilder {
         self
     }
 
+    /// Set cookie `Expires` attribute. Same as [`CookieBuilder::expiration`].
+    pub fn expires(self, expires: OffsetDateTime) -> Self {
+        self.expiration(expires)
+    }
+
     /// Set cookie `MaxAge` attribute.
     pub fn max_age(mut self, max_age: Duration) -> Self {
         self.cookie.max_age = Some(max_age);
@@ -370,16 +536,20 @@ impl CookieBuilder {
         self
     }
 
-    /// Set cookie `SameSite` attribute to `Lax`.
-    pub fn lax(mut self) -> Self {
-        self.cookie.same_site = Some("Lax".to_string());
+    /// Set cookie `SameSite` attribute.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.cookie.same_site = Some(same_site);
         self
     }
 
+    /// Set cookie `SameSite` attribute to `Lax`.
+    pub fn lax(self) -> Self {
+        self.same_site(SameSite::Lax)
+    }
+
     /// Set cookie `SameSite` attribute to `Strict`.
-    pub fn strict(mut self) -> Self {
-        self.cookie.same_site = Some("Strict".to_string());
-        self
+    pub fn strict(self) -> Self {
+        self.same_site(SameSite::Strict)
     }
 
     /// Create the cookie.
@@ -388,6 +558,13 @@ impl CookieBuilder {
     }
 }
 
+impl From<Cookie> for CookieBuilder {
+    /// Modify an existing cookie.
+    fn from(cookie: Cookie) -> Self {
+        Self { cookie }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -428,4 +605,143 @@ mod test {
             "super_secret_key"
         );
     }
+
+    #[test]
+    fn test_pending_cookies() {
+        let mut cookies = Cookies::new();
+        cookies.add(("hello", "world"));
+        cookies.add(
+            CookieBuilder::new()
+                .name("theme")
+                .value("dark")
+                .strict()
+                .build(),
+        );
+        cookies.add_private(("secret", "value")).expect("private");
+
+        let mut names = cookies.iter().map(|c| c.name()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["hello", "secret", "theme"]);
+
+        assert_eq!(
+            cookies.get_pending("theme").unwrap().same_site(),
+            Some("Strict")
+        );
+        assert_ne!(cookies.get_pending("secret").unwrap().value(), "value");
+
+        let removed = cookies.remove_pending("hello").expect("removed");
+        assert_eq!(removed.value(), "world");
+        assert!(cookies.get_pending("hello").is_none());
+        assert!(cookies.remove_pending("hello").is_none());
+
+        cookies.map_pending(|cookie| CookieBuilder::from(cookie).secure().build());
+        assert!(cookies.iter().all(|c| c.secure()));
+        assert_eq!(
+            cookies.get_pending("theme").unwrap().same_site(),
+            Some("Strict")
+        );
+
+        // Renaming a cookie replaces it.
+        cookies.map_pending(|cookie| {
+            let name = format!("__Host-{}", cookie.name());
+            CookieBuilder::from(cookie).name(name).build()
+        });
+        assert!(cookies.get_pending("theme").is_none());
+        assert_eq!(cookies.get_pending("__Host-theme").unwrap().value(), "dark");
+
+        let headers = String::from_utf8(cookies.to_headers()).unwrap();
+        assert_eq!(headers.matches("set-cookie: ").count(), 2);
+        assert_eq!(headers.matches("; Secure").count(), 2);
+    }
+
+    #[test]
+    fn test_cookie_attributes() {
+        let expires = OffsetDateTime::from_unix_timestamp(784111777).unwrap();
+        let cookie = CookieBuilder::new()
+            .name("theme")
+            .value("dark")
+            .path("/settings")
+            .domain("example.com")
+            .max_age(Duration::hours(1))
+            .expires(expires)
+            .same_site(SameSite::Strict)
+            .http_only()
+            .secure()
+            .build();
+
+        assert_eq!(
+            cookie.to_string(),
+            "theme=dark; Secure; HttpOnly; Path=/settings; Domain=example.com; SameSite=Strict; Max-Age=3600; Expires=Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+
+        let parsed = Cookie::parse(&cookie.to_string()).unwrap();
+        assert_eq!(parsed.path(), Some("/settings"));
+        assert_eq!(parsed.domain(), Some("example.com"));
+        assert_eq!(parsed.same_site(), Some("Strict"));
+        assert_eq!(parsed.max_age(), Some(Duration::hours(1)));
+        assert_eq!(parsed.expires(), Some(expires));
+        assert!(parsed.http_only() && parsed.secure());
+
+        // Browsers drop `SameSite=None` cookies that aren't secure.
+        let cookie = CookieBuilder::new()
+            .name("embed")
+            .value("1")
+            .same_site(SameSite::None)
+            .build();
+        assert_eq!(cookie.to_string(), "embed=1; Secure; Path=/; SameSite=None");
+
+        // Values can contain `=`.
+        let cookie = Cookie::parse("token=abc==").unwrap();
+        assert_eq!(cookie.value(), "abc==");
+    }
+
+    #[test]
+    fn test_signed_cookies() {
+        let mut cookies = Cookies::new();
+        cookies.add_signed(("bucket", "b"));
+        cookies.add_signed(("other", "a.b"));
+
+        let value = cookies.get_pending("bucket").unwrap().value().to_string();
+        assert!(value.starts_with("b.v1."));
+
+        let received = Cookies::parse(&format!(
+            "bucket={}; other={}",
+            value,
+            cookies.get_pending("other").unwrap().value()
+        ));
+        assert_eq!(received.get_signed("bucket").unwrap().value(), "b");
+        assert_eq!(received.get_signed("other").unwrap().value(), "a.b");
+        assert!(received.get_signed("missing").is_none());
+
+        let signature = value.trim_start_matches("b.v1.");
+        for forged in [
+            format!("a.v1.{}", signature),
+            format!("b.v2.{}", signature),
+            "b".to_string(),
+            format!("b.v1.{}x", signature),
+        ] {
+            let received = Cookies::parse(&format!("bucket={}", forged));
+            assert!(received.get_signed("bucket").is_none(), "{}", forged);
+        }
+
+        // The signature is bound to the cookie name.
+        let received = Cookies::parse(&format!("moved={}", value));
+        assert!(received.get_signed("moved").is_none());
+    }
+
+    #[test]
+    fn test_session_cookie() {
+        let mut cookies = Cookies::new();
+        cookies.add_session(&Session::anonymous()).unwrap();
+
+        let cookie = cookies.get_pending("rwf_session").unwrap();
+        assert!(cookie.http_only());
+        assert!(!cookie.secure());
+        assert_eq!(cookie.same_site(), Some("Lax"));
+
+        let header = String::from_utf8(cookies.to_headers()).unwrap();
+        assert!(header.contains("; HttpOnly"));
+        assert!(header.contains("; SameSite=Lax"));
+        assert!(header.contains(" GMT"));
+    }
 }
@@ -0,0 +1,945 @@
+//! Write CSV files, e.g. to export a table from an admin page.
+//!
+//! Cells are quoted following [RFC 4180](https://www.rfc-editor.org/rfc/rfc4180), and cells that a spreadsheet
+//! would run as a formula, i.e. starting with `=`, `+`, `-`, `@`, a tab or a carriage return, are prefixed with `'`
+//! so they are shown as text. Numbers, e.g. `-5`, are left as they are. Used by [`Response::csv`](super::Response::csv) and [`Response::csv_stream`](super::Response::csv_stream).
+//!
+//! Large tables can be exported without loading them into memory with [`rows`], which fetches
+//! the records in batches while the response is sent:
+//!
+//! ```rust,ignore
+//! let conn = Pool::connection().await?;
+//! let rows = csv::rows(Order::all(), conn, |order| {
+//!     vec![order.id.to_string(), order.email.clone(), order.total.to_string()]
+//! });
+//!
+//! let response = Response::csv_stream(["id", "email", "total"], rows).download("orders.csv");
+//! ```
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::ser::{self, Impossible, Serialize};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::io::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::body::CHUNK_SIZE;
+use crate::model::{ConnectionGuard, Error as ModelError, Model, Query};
+
+/// Byte order mark, which tells Excel the file is encoded with UTF-8.
+pub const BOM: &str = "\u{feff}";
+
+/// How many records [`rows`] fetches at a time.
+pub const BATCH_SIZE: i64 = 1_000;
+
+/// Error writing a row.
+#[derive(thiserror::Error, Debug)]
+pub enum CsvError {
+    #[error("{0}")]
+    Serialize(String),
+
+    #[error("nested values can't be written in a cell")]
+    Nested,
+
+    #[error("enum variants with data can't be written as a row")]
+    Variant,
+
+    #[error("cell isn't valid UTF-8")]
+    Utf8,
+
+    #[error("row {0} doesn't have the same fields as the header row")]
+    Fields(usize),
+}
+
+impl ser::Error for CsvError {
+    fn custom<T: Display>(msg: T) -> Self {
+        CsvError::Serialize(msg.to_string())
+    }
+}
+
+/// Names of the columns of rows serialized with [`to_csv`], so the header row
+/// is written even if there are no rows.
+///
+/// Derive it for structs with `#[derive(macros::CsvHeader)]`, which follows the same serde attributes as
+/// the rows, e.g. `rename`, `rename_all` and `skip`.
+///
+/// # Example
+///
+/// ```
+/// use rwf::http::csv::{to_csv, CsvHeader};
+/// use rwf::macros;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize, macros::CsvHeader)]
+/// #[serde(rename_all = "camelCase")]
+/// struct Order {
+///     order_id: i64,
+///     #[serde(rename(serialize = "Total"))]
+///     total: f64,
+///     #[serde(skip)]
+///     internal_note: String,
+/// }
+///
+/// assert_eq!(Order::csv_header(), vec!["orderId", "Total"]);
+/// assert_eq!(to_csv(Vec::<Order>::new(), true).unwrap(), "orderId,Total\r\n");
+/// ```
+pub trait CsvHeader {
+    /// Names of the columns. Empty if the rows don't have names, e.g. tuples, or if the names
+    /// are only known from the rows, e.g. maps.
+    fn csv_header() -> Vec<String>;
+}
+
+impl<T: CsvHeader + ?Sized> CsvHeader for &T {
+    fn csv_header() -> Vec<String> {
+        T::csv_header()
+    }
+}
+
+macro_rules! no_csv_header {
+    ($([$($generics:tt)*] $ty:ty),* $(,)?) => {
+        $(
+            impl<$($generics)*> CsvHeader for $ty {
+                fn csv_header() -> Vec<String> {
+                    vec![]
+                }
+            }
+        )*
+    };
+}
+
+no_csv_header!(
+    [T] Vec<T>,
+    [T, const N: usize] [T; N],
+    [T] [T],
+    [K, V] BTreeMap<K, V>,
+    [K, V, S] HashMap<K, V, S>,
+    [] serde_json::Value,
+    [A] (A,),
+    [A, B] (A, B),
+    [A, B, C] (A, B, C),
+    [A, B, C, D] (A, B, C, D),
+    [A, B, C, D, E] (A, B, C, D, E),
+    [A, B, C, D, E, F] (A, B, C, D, E, F),
+    [A, B, C, D, E, F, G] (A, B, C, D, E, F, G),
+    [A, B, C, D, E, F, G, H] (A, B, C, D, E, F, G, H),
+);
+
+/// Row of a CSV file.
+pub trait ToCsvRow {
+    /// Convert to the cells of the row. Returns an error
+    /// if the row couldn't be produced, e.g. a query failed.
+    fn to_csv_row(self) -> Result<Vec<String>, Error>;
+}
+
+impl<T: ToString> ToCsvRow for Vec<T> {
+    fn to_csv_row(self) -> Result<Vec<String>, Error> {
+        Ok(self.iter().map(|cell| cell.to_string()).collect())
+    }
+}
+
+impl<T: ToString, const N: usize> ToCsvRow for [T; N] {
+    fn to_csv_row(self) -> Result<Vec<String>, Error> {
+        Ok(self.iter().map(|cell| cell.to_string()).collect())
+    }
+}
+
+impl<R: ToCsvRow, E: std::error::Error + Send + Sync + 'static> ToCsvRow for Result<R, E> {
+    fn to_csv_row(self) -> Result<Vec<String>, Error> {
+        self.map_err(Error::other)?.to_csv_row()
+    }
+}
+
+/// Escape a cell. Cells containing commas, quotes or line breaks are quoted,
+/// and cells which would be evaluated as formulas are prefixed with `'` if `formula_guard` is set.
+/// Numbers, e.g. `-5` or `+1.5`, aren't formulas and are left as they are.
+///
+/// # Example
+///
+/// ```
+/// use rwf::http::csv::escape;
+///
+/// assert_eq!(escape("plain", true), "plain");
+/// assert_eq!(escape("a, \"b\"", true), "\"a, \"\"b\"\"\"");
+/// assert_eq!(escape("=1+1", true), "'=1+1");
+/// assert_eq!(escape("=1+1", false), "=1+1");
+/// assert_eq!(escape("-5", true), "-5");
+/// ```
+pub fn escape(cell: &str, formula_guard: bool) -> Cow<'_, str> {
+    let cell = if formula_guard
+        && cell.starts_with(['=', '+', '-', '@', '\t', '\r'])
+        && !is_number(cell)
+    {
+        Cow::Owned(format!("'{}", cell))
+    } else {
+        Cow::Borrowed(cell)
+    };
+
+    if cell.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", cell.replace('"', "\"\"")))
+    } else {
+        cell
+    }
+}
+
+/// A signed number, e.g. `-5` or `+1.5e3`, but not `-inf` or `-NaN`.
+fn is_number(cell: &str) -> bool {
+    cell.starts_with(['+', '-'])
+        && cell[1..].starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && cell.parse::<f64>().is_ok()
+}
+
+fn write_row(buffer: &mut Vec<u8>, row: &[String], formula_guard: bool) {
+    for (i, cell) in row.iter().enumerate() {
+        if i > 0 {
+            buffer.push(b',');
+        }
+        buffer.extend_from_slice(escape(cell, formula_guard).as_bytes());
+    }
+    buffer.extend_from_slice(b"\r\n");
+}
+
+/// Reader producing a CSV file from a stream of rows.
+///
+/// Rows are written in batches of about [`CHUNK_SIZE`] bytes, as the reader is read.
+/// If a row fails, reading fails. The response headers have been sent
+/// by then, so the client sees an incomplete response.
+pub struct CsvStream<S> {
+    rows: S,
+    buffer: Vec<u8>,
+    position: usize,
+    formula_guard: bool,
+    finished: bool,
+}
+
+impl<S> CsvStream<S>
+where
+    S: Stream + Unpin,
+    S::Item: ToCsvRow,
+{
+    /// Create a reader with the header row and the rows of the file.
+    pub fn new(headers: impl IntoIterator<Item = impl ToString>, rows: S) -> Self {
+        let mut stream = Self {
+            rows,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            position: 0,
+            formula_guard: true,
+            finished: false,
+        };
+
+        let headers = headers
+            .into_iter()
+            .map(|header| header.to_string())
+            .collect::<Vec<_>>();
+        if !headers.is_empty() {
+            stream.write(&headers);
+        }
+
+        stream
+    }
+
+    /// Start the file with a [`BOM`], so Excel opens it with the right encoding.
+    pub fn bom(mut self, bom: bool) -> Self {
+        if bom {
+            self.buffer.splice(0..0, BOM.bytes());
+        }
+        self
+    }
+
+    /// Prefix cells that would be evaluated as formulas with `'`. Enabled by default.
+    pub fn formula_guard(mut self, formula_guard: bool) -> Self {
+        self.formula_guard = formula_guard;
+        self
+    }
+
+    fn write(&mut self, row: &[String]) {
+        write_row(&mut self.buffer, row, self.formula_guard);
+    }
+}
+
+impl<S> AsyncRead for CsvStream<S>
+where
+    S: Stream + Unpin,
+    S::Item: ToCsvRow,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.position == this.buffer.len() {
+            this.buffer.clear();
+            this.position = 0;
+
+            while this.buffer.len() < CHUNK_SIZE && !this.finished {
+                match this.rows.poll_next_unpin(cx) {
+                    Poll::Ready(Some(row)) => {
+                        let row = row.to_csv_row()?;
+                        this.write(&row);
+                    }
+                    Poll::Ready(None) => this.finished = true,
+                    Poll::Pending if this.buffer.is_empty() => return Poll::Pending,
+                    Poll::Pending => break,
+                }
+            }
+        }
+
+        let remaining = &this.buffer[this.position..];
+        let n = remaining.len().min(buf.remaining());
+        buf.put_slice(&remaining[..n]);
+        this.position += n;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Write the rows as a CSV file. Structs and maps are written with a header row of their field names,
+/// taken from the first row. If there are no rows, the header row comes from [`CsvHeader`], so an empty
+/// list of structs still has one. Sequences and tuples are written without a header row. Cells are quoted as needed, and guarded against formulas if `formula_guard` is set.
+///
+/// Fields must be values which fit in a cell: numbers, strings, booleans, dates, options and enums without data.
+///
+/// # Example
+///
+/// ```
+/// use rwf::http::csv::to_csv;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize, rwf::macros::CsvHeader)]
+/// struct User<'a> {
+///     id: i64,
+///     name: &'a str,
+/// }
+///
+/// let users = vec![User { id: 1, name: "Smith, Alice" }];
+/// assert_eq!(to_csv(users, true).unwrap(), "id,name\r\n1,\"Smith, Alice\"\r\n");
+/// assert_eq!(to_csv(Vec::<User>::new(), true).unwrap(), "id,name\r\n");
+/// ```
+pub fn to_csv<T: Serialize + CsvHeader>(
+    rows: impl IntoIterator<Item = T>,
+    formula_guard: bool,
+) -> Result<String, CsvError> {
+    let mut csv = vec![];
+    let mut header = None;
+
+    for (i, row) in rows.into_iter().enumerate() {
+        let mut serializer = RowSerializer::default();
+        row.serialize(&mut serializer)?;
+
+        match header {
+            None => {
+                if serializer.named {
+                    write_row(&mut csv, &serializer.names, formula_guard);
+                }
+                header = Some(serializer.names);
+            }
+
+            // Maps can have different keys in each row.
+            Some(ref names) if serializer.named && *names != serializer.names => {
+                return Err(CsvError::Fields(i + 1));
+            }
+
+            Some(_) => (),
+        }
+
+        write_row(&mut csv, &serializer.cells, formula_guard);
+    }
+
+    if header.is_none() {
+        let names = T::csv_header();
+        if !names.is_empty() {
+            write_row(&mut csv, &names, formula_guard);
+        }
+    }
+
+    String::from_utf8(csv).map_err(|_| CsvError::Utf8)
+}
+
+/// Collects the cells of a row, and the names of its fields, if it has any.
+#[derive(Default)]
+struct RowSerializer {
+    names: Vec<String>,
+    cells: Vec<String>,
+    named: bool,
+}
+
+impl RowSerializer {
+    fn cell<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CsvError> {
+        self.cells.push(value.serialize(CellSerializer)?);
+        Ok(())
+    }
+}
+
+macro_rules! serialize_cell {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method(self, value: $ty) -> Result<Self::Ok, Self::Error> {
+                self.cell(&value)
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for &mut RowSerializer {
+    type Ok = ();
+    type Error = CsvError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Impossible<(), CsvError>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), CsvError>;
+
+    // A single value is a row with one cell.
+    serialize_cell! {
+        serialize_bool(bool), serialize_i8(i8), serialize_i16(i16), serialize_i32(i32),
+        serialize_i64(i64), serialize_u8(u8), serialize_u16(u16), serialize_u32(u32),
+        serialize_u64(u64), serialize_f32(f32), serialize_f64(f64), serialize_char(char),
+        serialize_str(&str), serialize_bytes(&[u8]),
+    }
+
+    fn serialize_none(self) -> Result<(), CsvError> {
+        self.cell(&())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), CsvError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), CsvError> {
+        self.cell(&())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), CsvError> {
+        self.cell(&())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), CsvError> {
+        self.cell(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), CsvError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), CsvError> {
+        Err(CsvError::Variant)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self, CsvError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, CsvError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, CsvError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, CsvError> {
+        Err(CsvError::Variant)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self, CsvError> {
+        self.named = true;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, CsvError> {
+        self.named = true;
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, CsvError> {
+        Err(CsvError::Variant)
+    }
+}
+
+impl ser::SerializeSeq for &mut RowSerializer {
+    type Ok = ();
+    type Error = CsvError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CsvError> {
+        self.cell(value)
+    }
+
+    fn end(self) -> Result<(), CsvError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut RowSerializer {
+    type Ok = ();
+    type Error = CsvError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CsvError> {
+        self.cell(value)
+    }
+
+    fn end(self) -> Result<(), CsvError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut RowSerializer {
+    type Ok = ();
+    type Error = CsvError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CsvError> {
+        self.cell(value)
+    }
+
+    fn end(self) -> Result<(), CsvError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut RowSerializer {
+    type Ok = ();
+    type Error = CsvError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), CsvError> {
+        self.names.push(key.serialize(CellSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CsvError> {
+        self.cell(value)
+    }
+
+    fn end(self) -> Result<(), CsvError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut RowSerializer {
+    type Ok = ();
+    type Error = CsvError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), CsvError> {
+        self.names.push(name.to_string());
+        self.cell(value)
+    }
+
+    fn end(self) -> Result<(), CsvError> {
+        Ok(())
+    }
+}
+
+/// Converts a single value into the text of a cell.
+struct CellSerializer;
+
+macro_rules! serialize_display {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method(self, value: $ty) -> Result<String, CsvError> {
+                Ok(value.to_string())
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for CellSerializer {
+    type Ok = String;
+    type Error = CsvError;
+    type SerializeSeq = Impossible<String, CsvError>;
+    type SerializeTuple = Impossible<String, CsvError>;
+    type SerializeTupleStruct = Impossible<String, CsvError>;
+    type SerializeTupleVariant = Impossible<String, CsvError>;
+    type SerializeMap = Impossible<String, CsvError>;
+    type SerializeStruct = Impossible<String, CsvError>;
+    type SerializeStructVariant = Impossible<String, CsvError>;
+
+    serialize_display! {
+        serialize_bool(bool), serialize_i8(i8), serialize_i16(i16), serialize_i32(i32),
+        serialize_i64(i64), serialize_u8(u8), serialize_u16(u16), serialize_u32(u32),
+        serialize_u64(u64), serialize_f32(f32), serialize_f64(f64), serialize_char(char),
+        serialize_str(&str),
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<String, CsvError> {
+        String::from_utf8(value.to_vec()).map_err(|_| CsvError::Utf8)
+    }
+
+    fn serialize_none(self) -> Result<String, CsvError> {
+        Ok(String::new())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, CsvError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, CsvError> {
+        Ok(String::new())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String, CsvError> {
+        Ok(String::new())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<String, CsvError> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, CsvError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, CsvError> {
+        Err(CsvError::Nested)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, CsvError> {
+        Err(CsvError::Nested)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, CsvError> {
+        Err(CsvError::Nested)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, CsvError> {
+        Err(CsvError::Nested)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, CsvError> {
+        Err(CsvError::Nested)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, CsvError> {
+        Err(CsvError::Nested)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, CsvError> {
+        Err(CsvError::Nested)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, CsvError> {
+        Err(CsvError::Nested)
+    }
+}
+
+/// Stream the records returned by the query, converting each one into a row with `map`.
+///
+/// Records are fetched [`BATCH_SIZE`] at a time, ordered by primary key, by a background task
+/// which waits for the rows to be sent before fetching more, so only about one batch is kept in memory.
+/// The query must not be ordered already.
+pub fn rows<T, F>(
+    query: Query<T>,
+    mut conn: ConnectionGuard,
+    mut map: F,
+) -> impl Stream<Item = Result<Vec<String>, ModelError>> + Send + Sync + Unpin + 'static
+where
+    T: Model + Send + Sync + 'static,
+    F: FnMut(&T) -> Vec<String> + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::channel(BATCH_SIZE as usize);
+    let query = query.order(T::primary_key());
+
+    tokio::spawn(async move {
+        let mut cursor = None;
+
+        loop {
+            let batch = match cursor {
+                Some(ref cursor) => query.clone().after(std::slice::from_ref(cursor)),
+                None => query.clone(),
+            };
+
+            let records = match batch.limit(BATCH_SIZE).fetch_all(&mut conn).await {
+                Ok(records) => records,
+                Err(err) => {
+                    let _ = tx.send(Err(err)).await;
+                    break;
+                }
+            };
+
+            for record in &records {
+                // The client went away.
+                if tx.send(Ok(map(record))).await.is_err() {
+                    return;
+                }
+            }
+
+            match records.last() {
+                Some(record) if records.len() as i64 == BATCH_SIZE => cursor = Some(record.id()),
+                _ => break,
+            }
+        }
+    });
+
+    stream::poll_fn(move |cx| rx.poll_recv(cx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn read<S>(reader: CsvStream<S>) -> std::io::Result<String>
+    where
+        S: Stream + Unpin,
+        S::Item: ToCsvRow,
+    {
+        let mut reader = reader;
+        let mut csv = String::new();
+        reader.read_to_string(&mut csv).await?;
+        Ok(csv)
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("", true), "");
+        assert_eq!(escape("a,b", true), "\"a,b\"");
+        assert_eq!(escape("say \"hi\"", true), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape("line\nbreak", true), "\"line\nbreak\"");
+        assert_eq!(escape("crlf\r\n", true), "\"crlf\r\n\"");
+        assert_eq!(
+            escape("=HYPERLINK(\"http://evil.com\",\"Click\")", true),
+            "\"'=HYPERLINK(\"\"http://evil.com\"\",\"\"Click\"\")\""
+        );
+        assert_eq!(escape("+1", true), "+1");
+        assert_eq!(escape("-1", true), "-1");
+        assert_eq!(escape("-1.5e3", true), "-1.5e3");
+        assert_eq!(escape("+.5", true), "+.5");
+        assert_eq!(escape("-1+1", true), "'-1+1");
+        assert_eq!(escape("-inf", true), "'-inf");
+        assert_eq!(escape("-", true), "'-");
+        assert_eq!(escape("@SUM(A1)", true), "'@SUM(A1)");
+        assert_eq!(escape("\tcmd", true), "'\tcmd");
+        assert_eq!(escape("a=b", true), "a=b");
+        assert_eq!(escape("é", true), "é");
+    }
+
+    #[tokio::test]
+    async fn test_csv_stream() {
+        let rows = stream::iter(vec![
+            vec!["1", "plain"],
+            vec!["2", "comma, here"],
+            vec!["3", "\"quoted\""],
+            vec!["4", "multi\nline"],
+            vec!["5", "=HYPERLINK(\"http://evil.com\")"],
+        ]);
+        let csv = read(CsvStream::new(["id", "value"], rows)).await.unwrap();
+        assert_eq!(
+            csv,
+            "id,value\r\n1,plain\r\n2,\"comma, here\"\r\n3,\"\"\"quoted\"\"\"\r\n4,\"multi\nline\"\r\n5,\"'=HYPERLINK(\"\"http://evil.com\"\")\"\r\n"
+        );
+
+        let rows = stream::iter(vec![["=1"]]);
+        let csv = read(CsvStream::new(["a"], rows).bom(true).formula_guard(false))
+            .await
+            .unwrap();
+        assert_eq!(csv, "\u{feff}a\r\n=1\r\n");
+
+        // No header row.
+        let rows = stream::iter(vec![["a", "b"]]);
+        let csv = read(CsvStream::new(Vec::<String>::new(), rows))
+            .await
+            .unwrap();
+        assert_eq!(csv, "a,b\r\n");
+
+        // Several batches.
+        let rows = stream::iter((0..20_000).map(|i| vec![i.to_string(), "x".repeat(10)]));
+        let csv = read(CsvStream::new(["id", "x"], rows)).await.unwrap();
+        assert_eq!(csv.lines().count(), 20_001);
+        assert!(csv.ends_with("19999,xxxxxxxxxx\r\n"));
+
+        // Failed rows fail the reader.
+        let rows = stream::iter(vec![Ok(vec!["1"]), Err(Error::other("query failed"))]);
+        assert!(read(CsvStream::new(["id"], rows)).await.is_err());
+    }
+
+    #[test]
+    fn test_to_csv() {
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "lowercase")]
+        enum Status {
+            Active,
+            Banned,
+        }
+
+        // Borrowed fields, serialize only.
+        #[derive(Serialize)]
+        struct User<'a> {
+            id: i64,
+            #[serde(rename = "full name")]
+            name: &'a str,
+            bio: Option<&'a str>,
+            status: Status,
+        }
+
+        impl CsvHeader for User<'_> {
+            fn csv_header() -> Vec<String> {
+                vec![
+                    "id".into(),
+                    "full name".into(),
+                    "bio".into(),
+                    "status".into(),
+                ]
+            }
+        }
+
+        let users = vec![
+            User {
+                id: 1,
+                name: "Smith, Alice",
+                bio: Some("says \"hi\"\non two lines"),
+                status: Status::Active,
+            },
+            User {
+                id: 2,
+                name: "=cmd|' /C calc'!A0",
+                bio: None,
+                status: Status::Banned,
+            },
+        ];
+        assert_eq!(
+            to_csv(&users, true).unwrap(),
+            "id,full name,bio,status\r\n\
+            1,\"Smith, Alice\",\"says \"\"hi\"\"\non two lines\",active\r\n\
+            2,'=cmd|' /C calc'!A0,,banned\r\n"
+        );
+
+        // Without rows, the field names come from CsvHeader.
+        assert_eq!(
+            to_csv(Vec::<User>::new(), true).unwrap(),
+            "id,full name,bio,status\r\n"
+        );
+        assert_eq!(to_csv(Vec::<(i64, String)>::new(), true).unwrap(), "");
+
+        // Tuples don't have names.
+        assert_eq!(
+            to_csv(vec![(1, "a,b"), (2, "c")], true).unwrap(),
+            "1,\"a,b\"\r\n2,c\r\n"
+        );
+
+        let maps = vec![BTreeMap::from([("a", 1), ("b", 2)])];
+        assert_eq!(to_csv(maps, true).unwrap(), "a,b\r\n1,2\r\n");
+
+        // Maps with different keys don't fit under the header row.
+        let maps = vec![BTreeMap::from([("a", 1)]), BTreeMap::from([("b", 2)])];
+        assert!(matches!(to_csv(maps, true), Err(CsvError::Fields(2))));
+
+        // Nested values don't fit in a cell.
+        #[derive(Serialize)]
+        struct Nested {
+            tags: Vec<String>,
+        }
+
+        impl CsvHeader for Nested {
+            fn csv_header() -> Vec<String> {
+                vec!["tags".into()]
+            }
+        }
+        assert!(matches!(
+            to_csv(vec![Nested { tags: vec![] }], true),
+            Err(CsvError::Nested)
+        ));
+    }
+
+    #[test]
+    fn test_csv_response() {
+        use crate::http::Response;
+
+        let rows = stream::iter(vec![Ok::<_, ModelError>(vec!["1".to_string()])]);
+        let response = Response::csv_stream(["id"], rows).download("export.csv");
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(
+            response.headers().get("content-disposition").unwrap(),
+            r#"attachment; filename="export.csv""#
+        );
+
+        let response = Response::new().csv([("1", "=1+1")]).unwrap();
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(response.body_bytes().unwrap(), b"1,'=1+1\r\n");
+
+        // Negative numbers stay numbers.
+        let response = Response::new().csv([(-5, -1.5)]).unwrap();
+        assert_eq!(response.body_bytes().unwrap(), b"-5,-1.5\r\n");
+
+        let response = Response::new().csv_unguarded([("1", "=1+1")]).unwrap();
+        assert_eq!(response.body_bytes().unwrap(), b"1,=1+1\r\n");
+    }
+}
@@ -0,0 +1,123 @@
+//! Debug toolbar, injected at the bottom of HTML pages during development.
+//!
+//! The toolbar shows the [timings](super::Timings) of the request, the SQL of each query executed by the ORM,
+//! and the contents of the session. It's available with the `debug-toolbar` feature and is added to the
+//! server when the `debug_toolbar` setting is enabled:
+//!
+//! ```toml
+//! [dependencies]
+//! rwf = { version = "0.1", features = ["debug-toolbar"] }
+//! ```
+use super::Request;
+use crate::controller::SessionId;
+use crate::view::form::escape_html;
+
+use std::time::Duration;
+
+/// Element id of the toolbar.
+pub static TOOLBAR_ID: &str = "rwf-debug-toolbar";
+
+static STYLE: &str = "#rwf-debug-toolbar{position:fixed;bottom:0;right:0;z-index:2147483647;max-width:100%;max-height:50vh;overflow:auto;font:12px/1.4 monospace;background:#1e1e2e;color:#cdd6f4;border-top-left-radius:6px;padding:4px 8px}\
+#rwf-debug-toolbar summary{cursor:pointer}\
+#rwf-debug-toolbar table{border-collapse:collapse}\
+#rwf-debug-toolbar td{padding:0 8px 0 0;vertical-align:top}\
+#rwf-debug-toolbar pre{margin:0;white-space:pre-wrap}";
+
+/// Add the toolbar to the page, before the closing `</body>` tag. Fragments without one,
+/// e.g. Turbo Stream or HTMX partials, are left unchanged.
+pub fn inject(request: &Request, body: &mut String) {
+    if let Some(position) = body.to_ascii_lowercase().rfind("</body>") {
+        body.insert_str(position, &render(request));
+    }
+}
+
+/// Render the toolbar for the request. The toolbar doesn't show the method, so the
+/// response to a `HEAD` request is the same size as the response to `GET`.
+pub fn render(request: &Request) -> String {
+    let timings = request.timings();
+    let queries = timings.queries();
+
+    let nonce = match request.nonce().get() {
+        Some(nonce) => format!(r#" nonce="{}""#, escape_html(nonce)),
+        None => String::new(),
+    };
+
+    let summary = timings
+        .entries()
+        .iter()
+        .map(|(name, duration)| format!("{} {}", escape_html(name), millis(*duration)))
+        .chain(std::iter::once(format!("{} queries", queries.len())))
+        .collect::<Vec<_>>()
+        .join(" &middot; ");
+
+    let queries = queries
+        .iter()
+        .map(|(sql, duration)| {
+            format!(
+                "<tr><td>{}</td><td><pre>{}</pre></td></tr>",
+                millis(*duration),
+                escape_html(sql)
+            )
+        })
+        .collect::<String>();
+
+    let session = match request.session() {
+        Some(session) => {
+            let id = match session.session_id {
+                SessionId::Authenticated(id) => format!("user {}", id),
+                SessionId::Guest(ref id) => format!("guest {}", id),
+            };
+
+            format!(
+                "<pre>{}\n{}</pre>",
+                escape_html(&id),
+                escape_html(&serde_json::to_string_pretty(&session.payload).unwrap_or_default())
+            )
+        }
+        None => "<pre>no session</pre>".to_string(),
+    };
+
+    format!(
+        r#"<style{nonce}>{style}</style><div id="{id}"><details><summary>{path} &middot; {summary}</summary><table>{queries}</table>{session}</details></div>"#,
+        nonce = nonce,
+        style = STYLE,
+        id = TOOLBAR_ID,
+        path = escape_html(request.path().path()),
+        summary = summary,
+        queries = queries,
+        session = session,
+    )
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_inject() {
+        let request = Request::default();
+        request.timings().collect_queries();
+        request.timings().record("db", Duration::from_micros(1500));
+        request.timings().record_query(
+            || r#"SELECT * FROM "users" WHERE "email" = '<b>'"#.into(),
+            Duration::from_micros(1500),
+        );
+
+        let mut body = "<html><body><h1>Users</h1></BODY></html>".to_string();
+        inject(&request, &mut body);
+
+        assert!(body.starts_with("<html><body><h1>Users</h1><style>"));
+        assert!(body.ends_with("</div></BODY></html>"));
+        assert!(body.contains("db 1.500 ms &middot; 1 queries"));
+        assert!(body.contains("&quot;email&quot; = &#x27;&lt;b&gt;&#x27;"));
+
+        // Fragments don't get a toolbar.
+        let mut body = "<p>fragment</p>".to_string();
+        inject(&request, &mut body);
+        assert_eq!(body, "<p>fragment</p>");
+    }
+}
@@ -1,6 +1,7 @@
 //! Errors returned by the HTTP protocol implementation.
 use thiserror::Error;
 
+use super::rejection::Rejection;
 use super::Head;
 
 #[derive(Error, Debug)]
@@ -14,6 +15,13 @@ pub enum Error {
     #[error("json")]
     Json(#[from] serde_json::Error),
 
+    #[cfg(feature = "xml")]
+    #[error("xml: {0}")]
+    Xml(#[from] quick_xml::SeError),
+
+    #[error("csv: {0}")]
+    Csv(#[from] super::csv::CsvError),
+
     #[error("{0}")]
     Controller(crate::controller::Error),
 
@@ -29,9 +37,24 @@ pub enum Error {
     #[error("{0}")]
     Time(time::error::ComponentRange),
 
+    #[error("invalid route: {0}")]
+    InvalidRoute(String),
+
     #[error("parameter is missing")]
     MissingParameter,
 
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+
+    #[error("invalid form: {0}")]
+    InvalidForm(super::InvalidField),
+
+    #[error("invalid json: {0}")]
+    InvalidJson(super::JsonError),
+
+    #[error("unsupported content type: {0}")]
+    UnsupportedContentType(String),
+
     #[error("timeout exceeded")]
     Timeout(#[from] tokio::time::error::Elapsed),
 
@@ -43,17 +66,83 @@ pub enum Error {
 
     #[error("content too large")]
     ContentTooLarge(Head),
+
+    #[error("charset \"{0}\" is not supported")]
+    UnsupportedCharset(String),
+
+    #[error("host \"{0}\" is not allowed")]
+    UntrustedHost(String),
+
+    #[error(
+        "memory budget exceeded: {requested} bytes requested, {usage} of {limit} bytes in use"
+    )]
+    MemoryBudgetExceeded {
+        requested: usize,
+        usage: usize,
+        limit: usize,
+    },
+
+    #[error("response body of {size} bytes exceeds the limit of {limit} bytes")]
+    ResponseTooLarge { size: usize, limit: usize },
+
+    #[error("route is handling its limit of {limit} concurrent requests")]
+    ConcurrencyLimitExceeded { limit: usize },
+
+    #[error("request rejected: {0}")]
+    Rejected(Box<Rejection>),
+
+    #[error("websocket message of {size} bytes exceeds the limit of {limit} bytes")]
+    WebsocketMessageTooBig { size: usize, limit: usize },
+
+    #[error("websocket frame of {size} bytes exceeds the limit of {limit} bytes")]
+    WebsocketFrameTooBig { size: usize, limit: usize },
+
+    #[error("websocket protocol error: {0}")]
+    WebsocketProtocol(&'static str),
+
+    #[error("websocket text message is not valid UTF-8")]
+    WebsocketInvalidUtf8,
+
+    #[error("websocket handshake failed: {0}")]
+    WebsocketHandshake(String),
+
+    #[error("websocket connection closed")]
+    WebsocketClosed(Option<u16>),
 }
 
 impl Error {
     pub fn code(&self) -> u16 {
         match self {
             Self::MissingParameter => 400,
+            Self::InvalidQuery(_) => 400,
+            Self::InvalidForm(_) => 400,
+            Self::InvalidJson(err) if err.is_syntax() => 400,
+            Self::InvalidJson(_) => 422,
             Self::Forbidden => 403,
             Self::ContentTooLarge(_) => 413,
+            Self::UnsupportedCharset(_) => 415,
+            Self::UnsupportedContentType(_) => 415,
+            Self::Rejected(_) => 400,
+            Self::UntrustedHost(_) => 400,
+            Self::MemoryBudgetExceeded { .. } => 503,
+            Self::ConcurrencyLimitExceeded { .. } => 503,
             _ => 500,
         }
     }
+
+    /// Status code to close a WebSocket connection with, if the error was caused by what the other side sent.
+    pub fn websocket_close_code(&self) -> Option<u16> {
+        use super::websocket::{CLOSE_INVALID_DATA, CLOSE_PROTOCOL_ERROR, CLOSE_TOO_BIG};
+
+        match self {
+            Self::WebsocketProtocol(_) => Some(CLOSE_PROTOCOL_ERROR),
+            Self::WebsocketInvalidUtf8 => Some(CLOSE_INVALID_DATA),
+            Self::WebsocketMessageTooBig { .. } | Self::WebsocketFrameTooBig { .. } => {
+                Some(CLOSE_TOO_BIG)
+            }
+            _ => None,
+        }
+    }
 }
 
 impl From<crate::controller::Error> for Error {
@@ -67,3 +156,9 @@ impl From<time::error::ComponentRange> for Error {
         Error::Time(error)
     }
 }
+
+impl From<Rejection> for Error {
+    fn from(rejection: Rejection) -> Error {
+        Error::Rejected(Box::new(rejection))
+    }
+}
@@ -0,0 +1,105 @@
+//! Hook called with errors returned by controllers.
+//!
+//! Unlike [`ErrorReporter`](crate::errors::ErrorReporter), which receives a report in a background task,
+//! the hook is called right away with the error itself and the request that caused it, so it can
+//! inspect the error type, e.g. to send it to Sentry with its own client:
+//!
+//! ```
+//! use rwf::http::on_error;
+//!
+//! on_error(Box::new(|error, request| {
+//!     if let Some(error) = error.downcast_ref::<std::io::Error>() {
+//!         eprintln!("{} {}: {}", request.method(), request.path().path(), error);
+//!     }
+//! }));
+//! ```
+//!
+//! The hook is called when a controller returns an error, or a response built with
+//! [`Response::internal_error`](super::Response::internal_error). It runs on the connection task, so it should return quickly;
+//! panics are caught and logged.
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use tracing::error;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+
+use super::Request;
+use crate::errors::panic_message;
+
+/// Function called with the error and the request that caused it.
+pub type ErrorHook = Box<dyn Fn(&(dyn std::error::Error + 'static), &Request) + Send + Sync>;
+
+static HOOK: Lazy<RwLock<Option<Arc<ErrorHook>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Install the error hook, replacing the current one.
+pub fn on_error(hook: ErrorHook) {
+    *HOOK.write() = Some(Arc::new(hook));
+}
+
+/// Call the hook, if one is installed.
+pub(crate) fn call(error: &(dyn std::error::Error + 'static), request: &Request) {
+    let hook = HOOK.read().clone();
+
+    if let Some(hook) = hook {
+        if let Err(payload) = catch_unwind(AssertUnwindSafe(|| hook(error, request))) {
+            error!("error hook panicked: {}", panic_message(payload.as_ref()));
+        }
+    }
+}
+
+/// Error a `500 - Internal Server Error` response was built from, passed to the hook
+/// when a controller returns the response instead of the error.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{message}")]
+pub struct InternalError {
+    message: String,
+}
+
+impl InternalError {
+    /// Create the error from the original one.
+    pub fn new(error: &dyn std::error::Error) -> Self {
+        Self {
+            message: error.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_error_hook() {
+        let request = Request::default();
+        let error = std::io::Error::other("disk full");
+
+        // No hook installed.
+        call(&error, &request);
+
+        let errors = Arc::new(Mutex::new(vec![]));
+        let seen = errors.clone();
+        on_error(Box::new(move |error, request| {
+            // Other tests may be returning errors at the same time.
+            if error.to_string() == "disk full" {
+                seen.lock()
+                    .unwrap()
+                    .push(format!("{} {}", request.path().path(), error));
+            }
+        }));
+        call(&error, &request);
+        call(&InternalError::new(&error), &request);
+
+        // Panics are caught.
+        on_error(Box::new(|_, _| panic!("hook failed")));
+        call(&error, &request);
+        *HOOK.write() = None;
+
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec!["/ disk full".to_string(), "/ disk full".to_string()]
+        );
+    }
+}
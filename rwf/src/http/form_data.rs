@@ -1,11 +1,16 @@
 //! Handle parsing forms.
 //!
 //! Both `x-www-form-urlencoded` and `multipart/form-data` formats are supported.
-use super::{urldecode, Error, Query, Request};
+//!
+//! URL-encoded forms are decoded with the charset from the `Content-Type` header, or from the
+//! `_charset_` field browsers fill in with the encoding they used, and UTF-8 otherwise.
+use super::{url::percent_decode_bytes, Charset, Error, Query, Request};
 use std::str::FromStr;
 
 use std::collections::hash_map::{HashMap, IntoIter};
 
+pub use super::multipart::{ContentDisposition, Multipart};
+
 /// Data stored in the form.
 #[derive(Clone, Debug)]
 pub enum FormData {
@@ -20,22 +25,18 @@ impl FormData {
     /// Extract form data from request.
     pub fn from_request(request: &Request) -> Result<Self, Error> {
         let content_type = request
-            .header("content-type")
+            .content_type()
             .ok_or(Error::MalformedRequest("content-type header is required"))?;
 
-        if content_type.contains("application/x-www-form-urlencoded") {
-            Self::from_url_encoded(request)
-        } else if content_type.contains("multipart/form-data") {
+        if content_type.media_type() == "application/x-www-form-urlencoded" {
+            Self::from_url_encoded(request.body(), content_type.param("charset"))
+        } else if content_type.media_type() == "multipart/form-data" {
             // Extract the multipart boundary from the Content-Type header.
-            if let Some(boundary) = content_type.split(";").last() {
-                let boundary = boundary.split("=").last();
-                if let Some(boundary) = boundary {
-                    let multipart = Multipart::read(request.body(), boundary.trim())?;
-
-                    Ok(Self::Multipart(multipart))
-                } else {
-                    Err(Error::MalformedRequest("multipart missing boundary"))
-                }
+            if let Some(boundary) = content_type.param("boundary") {
+                // Files are kept in memory, so they can be borrowed by `FormData::file`.
+                let multipart = Multipart::parse(request.body(), boundary)?;
+
+                Ok(Self::Multipart(multipart))
             } else {
                 Err(Error::MalformedRequest("multipart missing boundary"))
             }
@@ -46,8 +47,36 @@ impl FormData {
         }
     }
 
-    fn from_url_encoded(request: &Request) -> Result<Self, Error> {
-        Ok(Self::UrlEncoded(Query::parse(&request.string())))
+    fn from_url_encoded(body: &[u8], charset: Option<&str>) -> Result<Self, Error> {
+        // Percent-encoded bytes are in the form's charset, so they are decoded before the charset.
+        let pairs = body
+            .split(|byte| *byte == b'&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut parts = pair.splitn(2, |byte| *byte == b'=');
+                let name = parts.next().unwrap_or_default();
+                let value = parts.next().unwrap_or_default();
+                (
+                    percent_decode_bytes(name, true),
+                    percent_decode_bytes(value, true),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let charset = match charset {
+            Some(charset) => Charset::from_label(charset)?,
+            None => match pairs.iter().find(|(name, _)| name == b"_charset_") {
+                Some((_, charset)) => Charset::from_label(&String::from_utf8_lossy(charset))?,
+                None => Charset::Utf8,
+            },
+        };
+
+        let pairs = pairs
+            .iter()
+            .map(|(name, value)| (charset.decode(name), charset.decode(value)))
+            .collect();
+
+        Ok(Self::UrlEncoded(Query::from_pairs(pairs)))
     }
 
     /// Get a value submitted via the form. Works on all values except files.
@@ -69,39 +98,19 @@ impl FormData {
     pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
         match self {
             FormData::UrlEncoded(query) => query.get::<T>(name),
-            FormData::Multipart(multipart) => {
-                let entry = multipart.get(name);
-                if let Some(entry) = entry {
-                    if entry.content_disposition.filename.is_none() {
-                        if let Ok(s) = entry.to_string() {
-                            match T::from_str(&s) {
-                                Ok(s) => Some(s),
-                                Err(_) => None,
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
+            FormData::Multipart(multipart) => multipart
+                .field(name)
+                .and_then(|value| T::from_str(value).ok()),
         }
     }
 
     /// Get file data from a `multipart/form-data` form.
     pub fn file<'a>(&'a self, name: &str) -> Option<File<'a>> {
         match self {
-            FormData::Multipart(multipart) => multipart.get(name).map(|f| File {
-                body: f.as_bytes(),
-                name: f
-                    .content_disposition
-                    .filename
-                    .clone()
-                    .unwrap_or("".to_string()),
-                content_type: f.content_type(),
+            FormData::Multipart(multipart) => multipart.file(name).map(|f| File {
+                body: f.bytes().unwrap_or_default(),
+                name: f.filename().to_string(),
+                content_type: Some(f.content_type().to_string()),
             }),
             _ => None,
         }
@@ -111,16 +120,11 @@ impl FormData {
     pub fn into_iter(self) -> IntoIter<String, String> {
         match self {
             FormData::UrlEncoded(query) => query.into_iter(),
-            FormData::Multipart(multipart) => {
-                let entries = multipart
-                    .entries
-                    .into_iter()
-                    .filter(|entry| entry.1.content_disposition.filename.is_none())
-                    .map(|entry| (entry.0, entry.1.to_string().unwrap_or("".to_string())))
-                    .into_iter()
-                    .collect::<HashMap<String, String>>();
-                entries.into_iter()
-            }
+            FormData::Multipart(multipart) => multipart
+                .into_fields()
+                .into_iter()
+                .collect::<HashMap<String, String>>()
+                .into_iter(),
         }
     }
 
@@ -142,66 +146,12 @@ impl FormData {
     }
 }
 
-/// Form encoded with `multipart/form-data` format.
-#[derive(Debug, Clone)]
-pub struct Multipart {
-    entries: HashMap<String, MultipartEntry>,
-}
-
-/// Multipart form submission entry.
-#[derive(Debug, Clone)]
-pub struct MultipartEntry {
-    data: Vec<u8>,
-    content_disposition: ContentDisposition,
-    content_type: Option<String>,
-}
-
-impl MultipartEntry {
-    /// Convert the multipart entry to string, if it's valid UTF-8 data.
-    pub fn to_string(&self) -> Result<String, Error> {
-        Ok(String::from_utf8(self.data.clone())?)
-    }
-
-    /// Get the multipart entry as bytes.
-    pub fn as_bytes(&self) -> &[u8] {
-        &self.data
-    }
-
-    /// Get the Content-Type header passed in the multipart form
-    /// for this entry.
-    pub fn content_type(&self) -> Option<String> {
-        if let Some(ref content_type) = self.content_type {
-            content_type.split(":").last().map(|s| s.trim().to_string())
-        } else {
-            None
-        }
-    }
-}
-
-// Read a single "HTTP line".
-macro_rules! read_line {
-    ($reader: expr) => {{
-        let mut buf = Vec::new();
-
-        while let Some(c) = $reader.next() {
-            if *c as char == '\r' {
-                let _ = $reader.next();
-                break;
-            } else {
-                buf.push(*c);
-            }
-        }
-
-        buf
-    }};
-}
-
 /// A file uploaded via a `multipart/form-data` form.
 ///
 /// The file is loaded into memory. Typically, you don't want to handle large file uploads via multipart forms.
 /// The browser doesn't provide a progress bar, so the user won't know how far along the upload is. This makes
 /// this method unreliable for anything beyond small files that can be uploaded almost instantly, and which can fit into memory
-/// without causing any issues.
+/// without causing any issues. Use [`Request::multipart`] to write large files to disk instead.
 #[derive(Debug, Clone)]
 pub struct File<'a> {
     body: &'a [u8],
@@ -230,143 +180,6 @@ impl File<'_> {
     }
 }
 
-impl Multipart {
-    /// Read multi-part body from request's body.
-    fn read(body: &[u8], boundary: &str) -> Result<Self, Error> {
-        let mut entries = HashMap::new();
-        let mut reader = body.into_iter();
-
-        let start_boundary = format!("--{}", boundary).as_bytes().to_vec();
-        let end_boundary = format!("--{}--", boundary).as_bytes().to_vec();
-
-        let mut buf = Vec::new();
-        let mut content_disposition: Option<ContentDisposition> = None;
-        let mut content_type: Option<String> = None;
-
-        while reader.len() > 0 {
-            let line = read_line!(reader);
-
-            if line.is_empty() {
-                break;
-            }
-
-            if line == start_boundary {
-                if let Some(content_disposition) = content_disposition.take() {
-                    entries.insert(
-                        content_disposition.name.clone(),
-                        MultipartEntry {
-                            data: buf.clone(),
-                            content_disposition,
-                            content_type: content_type.take(),
-                        },
-                    );
-                    buf.clear();
-                }
-                let ct = ContentDisposition::parse(&String::from_utf8(read_line!(reader))?)?;
-
-                // Check if we're parsing a file.
-                if ct.filename.is_some() {
-                    // Get content type.
-                    let ct = read_line!(reader);
-                    if ct.to_ascii_lowercase().starts_with(b"content-type") {
-                        content_type = Some(String::from_utf8(ct)?);
-                        // Read and discard "\r\n".
-                        let _ = read_line!(reader);
-                    }
-                } else {
-                    // Read and discard "\r\n".
-                    let _ = read_line!(reader);
-                }
-                content_disposition = Some(ct);
-            } else if line == end_boundary {
-                // We've reached the end of the form data.
-                if let Some(content_disposition) = content_disposition.take() {
-                    entries.insert(
-                        content_disposition.name.clone(),
-                        MultipartEntry {
-                            data: buf.clone(),
-                            content_disposition,
-                            content_type: content_type.take(),
-                        },
-                    );
-                }
-                break;
-            } else {
-                buf.extend(line);
-            }
-        }
-
-        Ok(Multipart { entries })
-    }
-
-    /// Get a multi-part entry, if it exists.
-    pub fn get(&self, name: &str) -> Option<&MultipartEntry> {
-        self.entries.get(name)
-    }
-}
-
-/// HTTP `Content-Disposition` header.
-#[derive(Debug, Clone)]
-pub struct ContentDisposition {
-    /// The name of the input.
-    pub name: String,
-    /// File name of the input, if it's a file upload.
-    pub filename: Option<String>,
-}
-
-impl ContentDisposition {
-    // Parse the Content-Disposition header.
-    fn parse(header: &str) -> Result<ContentDisposition, Error> {
-        let mut names = header.split(":").into_iter().map(|s| s.trim());
-
-        if let Some(header) = names.next() {
-            if header.to_lowercase() != "content-disposition" {
-                return Err(Error::MalformedRequest(
-                    "content-disposition header is missing",
-                ));
-            }
-        }
-
-        if let Some(params) = names.next() {
-            let mut params = params.split(";").into_iter().map(|s| urldecode(s.trim()));
-            let _form_data = params.next();
-
-            let mut content_name: Option<String> = None;
-            let mut filename: Option<String> = None;
-
-            for param in params {
-                let mut parts = param.split("=").into_iter();
-                let name = parts.next();
-                let value = parts.next();
-
-                if let Some(name) = name {
-                    match name {
-                        "name" => {
-                            if let Some(value) = value {
-                                content_name = Some(value.replace("\"", ""));
-                            }
-                        }
-
-                        "filename" => {
-                            if let Some(value) = value {
-                                filename = Some(value.replace("\"", ""));
-                            }
-                        }
-
-                        _ => (),
-                    }
-                }
-            }
-
-            if let Some(name) = content_name {
-                return Ok(ContentDisposition { name, filename });
-            }
-        }
-
-        Err(Error::MalformedRequest("multipart/form-data is malformed"))
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -401,21 +214,15 @@ Content-Type: text/plain
         .map(|s| format!("{}\r\n", s))
         .collect::<String>();
 
-        let mp = Multipart::read(multipart.as_bytes(), "ExampleBoundaryString").unwrap();
+        let mp = Multipart::parse(multipart.as_bytes(), "ExampleBoundaryString").unwrap();
 
-        assert_eq!(mp.entries.len(), 2);
+        assert_eq!(mp.fields().len() + mp.files().len(), 2);
+        assert_eq!(mp.field("description").unwrap(), "Description input value");
         assert_eq!(
-            mp.get("description").unwrap().to_string().unwrap(),
-            "Description input value"
-        );
-        assert_eq!(
-            mp.get("myFile").unwrap().as_bytes(),
+            mp.file("myFile").unwrap().bytes().unwrap(),
             b"[content of the file foo.txt chosen by the user]"
         );
-        assert_eq!(
-            mp.get("myFile").unwrap().content_type().unwrap(),
-            "text/plain"
-        );
+        assert_eq!(mp.file("myFile").unwrap().content_type(), "text/plain");
 
         let req = format!(
             "POST /upload HTTP/1.1\r\nContent-Length: {}\r\nContent-Type: multipart/form-data; boundary=ExampleBoundaryString\r\n\r\n{}",
@@ -437,4 +244,82 @@ Content-Type: text/plain
         let input = form_data.get::<String>("description").unwrap();
         assert_eq!(input, "Description input value");
     }
+
+    async fn form(content_type: &str, body: &[u8]) -> Request {
+        let mut req = format!(
+            "POST /form HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        req.extend_from_slice(body);
+
+        Request::read("127.0.0.1:6000".parse().unwrap(), &req[..])
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_charset() {
+        let utf8 = form(
+            "application/x-www-form-urlencoded",
+            b"name=caf%C3%A9&city=S%C3%A3o+Paulo",
+        )
+        .await;
+        let form_data = utf8.form_data().unwrap();
+        assert_eq!(form_data.get::<String>("name").unwrap(), "café");
+        assert_eq!(form_data.get::<String>("city").unwrap(), "São Paulo");
+
+        // Unknown charsets are rejected with 415.
+        let unknown = form("application/x-www-form-urlencoded; charset=koi8-r", b"a=b").await;
+        let err = unknown.form_data().unwrap_err();
+        assert!(matches!(err, Error::UnsupportedCharset(_)));
+        assert_eq!(err.code(), 415);
+    }
+
+    #[cfg(feature = "charsets")]
+    #[tokio::test]
+    async fn test_latin1() {
+        // Encoded by a browser, and sent raw by a script.
+        for body in [&b"name=caf%E9"[..], &b"name=caf\xe9"[..]] {
+            let latin1 = form(
+                "application/x-www-form-urlencoded; charset=ISO-8859-1",
+                body,
+            )
+            .await;
+            let form_data = latin1.form_data().unwrap();
+            assert_eq!(form_data.get::<String>("name").unwrap(), "café");
+        }
+
+        // Round trip through a form rendered in Latin-1.
+        let encoded = Charset::Latin1.encode("crème brûlée");
+        let mut body = b"dessert=".to_vec();
+        for byte in encoded {
+            body.extend_from_slice(format!("%{:02X}", byte).as_bytes());
+        }
+        let latin1 = form(
+            "application/x-www-form-urlencoded; charset=iso-8859-1",
+            &body,
+        )
+        .await;
+        assert_eq!(
+            latin1
+                .form_data()
+                .unwrap()
+                .get::<String>("dessert")
+                .unwrap(),
+            "crème brûlée"
+        );
+
+        // The charset browsers fill in the `_charset_` field.
+        let windows = form(
+            "application/x-www-form-urlencoded",
+            b"_charset_=windows-1252&quote=%93caf%E9%94",
+        )
+        .await;
+        assert_eq!(
+            windows.form_data().unwrap().get::<String>("quote").unwrap(),
+            "“café”"
+        );
+    }
 }
@@ -4,11 +4,13 @@
 //! See [`crate::http::router`] documentation for routing implementation details.
 use super::{
     path::{PathType, PathWithRegex},
-    Path,
+    ConcurrencyLimit, Path, Request, Response,
 };
-use crate::controller::Controller;
+use crate::config::get_config;
+use crate::controller::{middleware::Outcome, Controller, Error, MiddlewareSet};
 
 use std::ops::Deref;
+use std::time::Duration;
 
 /// Route handler.
 ///
@@ -19,16 +21,26 @@ pub struct Handler {
     name: Option<String>,
     controller: Box<dyn Controller>,
     rank: i64,
+    path_type: PathType,
+    concurrency: Option<ConcurrencyLimit>,
+    middleware: MiddlewareSet,
+    max_response_size: Option<usize>,
+    stream_body: bool,
 }
 
 impl Handler {
     /// Create new route handler for the specified path, controller and path type.
     pub fn new(path: &str, controller: impl Controller + 'static, path_type: PathType) -> Self {
         Self {
-            path: Path::parse(path).unwrap().with_regex(path_type).unwrap(),
+            path: Path::pattern(path).with_regex(path_type).unwrap(),
             controller: Box::new(controller),
             name: None,
             rank: 0,
+            path_type,
+            concurrency: None,
+            middleware: MiddlewareSet::without_default(vec![]),
+            max_response_size: None,
+            stream_body: false,
         }
     }
 
@@ -72,6 +84,16 @@ impl Handler {
         self
     }
 
+    /// Get the route name, if one is set.
+    pub fn route_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Get the kind of path this handler serves.
+    pub fn path_type(&self) -> PathType {
+        self.path_type
+    }
+
     /// Get the path and its correspoding regex, used in the router.
     pub fn path_with_regex(&self) -> &PathWithRegex {
         &self.path
@@ -89,6 +111,99 @@ impl Handler {
         self
     }
 
+    /// Handle at most this many requests concurrently, across all connections.
+    /// Requests over the limit are rejected with `503 - Service Unavailable`, unless a queue timeout is set.
+    pub fn max_concurrency(mut self, limit: usize) -> Self {
+        let queue_timeout = self
+            .concurrency
+            .as_ref()
+            .map(|concurrency| concurrency.queue_timeout_duration())
+            .unwrap_or_default();
+        self.concurrency = Some(ConcurrencyLimit::new(limit).queue_timeout(queue_timeout));
+        self
+    }
+
+    /// How long requests over the concurrency limit wait for a slot before they are rejected.
+    /// Has no effect unless [`Handler::max_concurrency`] is set.
+    pub fn queue_timeout(mut self, queue_timeout: Duration) -> Self {
+        self.concurrency = self
+            .concurrency
+            .map(|concurrency| concurrency.queue_timeout(queue_timeout));
+        self
+    }
+
+    /// Share a concurrency limit with other routes, or keep a handle to it to read its statistics.
+    pub fn concurrency_limit(mut self, limit: ConcurrencyLimit) -> Self {
+        self.concurrency = Some(limit);
+        self
+    }
+
+    /// Get the concurrency limit, if one is set.
+    pub fn concurrency(&self) -> Option<&ConcurrencyLimit> {
+        self.concurrency.as_ref()
+    }
+
+    /// Allow response bodies of up to this many bytes on this route, instead of the `max_response_size`
+    /// setting, e.g. to serve large files. `0` means no limit.
+    pub fn max_response_size(mut self, limit: usize) -> Self {
+        self.max_response_size = Some(limit);
+        self
+    }
+
+    /// Maximum size of response bodies on this route. `0` means no limit.
+    pub fn response_size_limit(&self) -> usize {
+        self.max_response_size
+            .unwrap_or_else(|| get_config().general.max_response_size)
+    }
+
+    /// Pass the request body to the controller as a stream, see [`Request::body_stream`], instead of reading it
+    /// into memory before the controller runs, e.g. for large uploads. The `max_request_size` setting doesn't apply.
+    pub fn stream_body(mut self) -> Self {
+        self.stream_body = true;
+        self
+    }
+
+    /// Does this route stream the request body?
+    pub fn streams_body(&self) -> bool {
+        self.stream_body
+    }
+
+    /// Run this middleware before the controller's own middleware.
+    ///
+    /// Used to add middleware to all routes of an [`App`](crate::app::App).
+    pub fn with_middleware(mut self, middleware: MiddlewareSet) -> Self {
+        self.middleware = middleware;
+        self
+    }
+
+    /// Middleware running before the controller's own middleware.
+    pub fn middleware(&self) -> &MiddlewareSet {
+        &self.middleware
+    }
+
+    /// Pass the request through the route's middleware and the controller.
+    pub async fn handle_internal(&self, request: Request) -> Result<Response, Error> {
+        match self.middleware.handle_request(request).await? {
+            (Outcome::Forward(request), executed) => {
+                let response = self.controller.handle_internal(request.clone()).await?;
+                self.middleware
+                    .handle_response(&request, response, executed)
+                    .await
+            }
+
+            (Outcome::Stop(request, response), executed) => {
+                self.middleware
+                    .handle_response(&request, response.from_request(&request)?, executed)
+                    .await
+            }
+        }
+    }
+
+    /// Get the controller serving this route.
+    pub fn controller(&self) -> &dyn Controller {
+        self.controller.as_ref()
+    }
+
     /// Get the controller name served by this route handler.
     pub fn controller_name(&self) -> &'static str {
         self.deref().controller_name()
@@ -0,0 +1,265 @@
+//! Zero-downtime restarts, by handing the listening socket over to a new process.
+//!
+//! The server can start from a listening socket it inherited, instead of binding the address itself:
+//!
+//! - with [systemd socket activation](https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html),
+//!   if `LISTEN_FDS` and `LISTEN_PID` are set for this process;
+//! - from a socket passed explicitly with [`Server::from_raw_fd`](super::Server::from_raw_fd) or [`Server::listener`](super::Server::listener);
+//! - from the server it's replacing, during a handover.
+//!
+//! With [`Server::handover`](super::Server::handover), the server starts a new process of the same binary, with the same
+//! arguments, when the process receives `SIGUSR2`, e.g. after the binary was upgraded. The new process gets the listening
+//! socket and tells the old one when it's accepting connections. The old process then stops accepting them, and
+//! drains the requests in progress like it does when [shutting down](crate::shutdown). Connections are accepted
+//! by either process the whole time, so none are refused.
+//!
+//! If the new process exits, or doesn't start accepting connections within `handover_timeout`, it's stopped,
+//! and the old process keeps serving.
+use std::io::{Error, ErrorKind, Write};
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tracing::{info, warn};
+
+/// Environment variable with the listening socket passed to the new process.
+pub const LISTEN_FD: &str = "RWF_LISTEN_FD";
+
+/// Environment variable with the socket the new process uses to tell the old one it's ready.
+pub const READY_FD: &str = "RWF_READY_FD";
+
+/// First socket passed by systemd.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+// Inherited sockets are owned by the first server which takes them.
+static LISTENER_TAKEN: AtomicBool = AtomicBool::new(false);
+static READY_SENT: AtomicBool = AtomicBool::new(false);
+
+/// Listening socket inherited from the process which started this one, during a handover or
+/// with systemd socket activation. `None` if there isn't one, or it was taken already.
+pub fn inherited() -> Result<Option<TcpListener>, Error> {
+    let fd = match env_fd(LISTEN_FD)? {
+        Some(fd) => fd,
+        None => match systemd_fd()? {
+            Some(fd) => fd,
+            None => return Ok(None),
+        },
+    };
+
+    if LISTENER_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+
+    // SAFETY: the descriptor was passed to this process to listen on, and is taken only once.
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    // Don't close a descriptor which isn't ours to close.
+    if let Err(err) = listener.local_addr() {
+        let _ = listener.into_raw_fd();
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("inherited descriptor {} isn't a TCP socket: {}", fd, err),
+        ));
+    }
+
+    Ok(Some(listener))
+}
+
+fn env_fd(name: &str) -> Result<Option<RawFd>, Error> {
+    match std::env::var(name) {
+        Ok(fd) => parse_fd(name, &fd).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_fd(name: &str, fd: &str) -> Result<RawFd, Error> {
+    fd.trim().parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} isn't a file descriptor: \"{}\"", name, fd),
+        )
+    })
+}
+
+/// Socket passed by systemd, if it was passed to this process. Only the first socket is used.
+fn systemd_fd() -> Result<Option<RawFd>, Error> {
+    listen_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// First socket passed by systemd, from the values of `LISTEN_PID` and `LISTEN_FDS`.
+/// The sockets are only for this process if `LISTEN_PID` is its id.
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, id: u32) -> Result<Option<RawFd>, Error> {
+    if pid.map(str::trim) != Some(&id.to_string()) {
+        return Ok(None);
+    }
+
+    let count = match fds {
+        Some(fds) => parse_fd("LISTEN_FDS", fds)?,
+        None => return Ok(None),
+    };
+
+    if count > 1 {
+        warn!(
+            "systemd passed {} sockets, listening on the first one",
+            count
+        );
+    }
+
+    if count > 0 {
+        Ok(Some(SD_LISTEN_FDS_START))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Tell the process which started this one during a handover that the server is accepting connections.
+/// Does nothing if this process wasn't started by a handover.
+pub fn notify_ready() -> Result<(), Error> {
+    let fd = match env_fd(READY_FD)? {
+        Some(fd) => fd,
+        None => return Ok(()),
+    };
+
+    if READY_SENT.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    // SAFETY: the descriptor was passed to this process to signal readiness, and is used only once.
+    let mut ready = unsafe { UnixStream::from_raw_fd(fd) };
+    ready.write_all(b"1")
+}
+
+/// New process started by a handover, which isn't accepting connections yet.
+pub struct Handover {
+    child: Child,
+    ready: UnixStream,
+}
+
+impl Handover {
+    /// Start a new process of the current binary, with the same arguments, and pass it the listening socket.
+    pub fn start(listener: &impl AsRawFd) -> Result<Self, Error> {
+        let listen_fd = listener.as_raw_fd();
+        let (ready, child_ready) = UnixStream::pair()?;
+        let ready_fd = child_ready.as_raw_fd();
+
+        let mut command = Command::new(std::env::current_exe()?);
+        command
+            .args(std::env::args_os().skip(1))
+            .env(LISTEN_FD, listen_fd.to_string())
+            .env(READY_FD, ready_fd.to_string())
+            .env_remove("LISTEN_PID")
+            .env_remove("LISTEN_FDS");
+
+        // SAFETY: only calls fcntl, which is async-signal-safe, between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                for fd in [listen_fd, ready_fd] {
+                    let flags = libc::fcntl(fd, libc::F_GETFD);
+                    if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+                        return Err(Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+        info!("handing over to process {}", child.id());
+
+        Ok(Self { child, ready })
+    }
+
+    /// Process id of the new process.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Wait for the new process to accept connections. If it exits or doesn't become ready
+    /// in time, it's stopped and an error is returned.
+    pub async fn ready(mut self, limit: Duration) -> Result<(), Error> {
+        let result = wait_ready(self.ready, limit).await;
+
+        if result.is_err() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+
+        result
+    }
+}
+
+/// Wait for the other end of the socket to signal readiness.
+pub(crate) async fn wait_ready(ready: UnixStream, limit: Duration) -> Result<(), Error> {
+    ready.set_nonblocking(true)?;
+    let mut ready = tokio::net::UnixStream::from_std(ready)?;
+    let mut byte = [0u8; 1];
+
+    match tokio::time::timeout(limit, ready.read(&mut byte)).await {
+        Ok(Ok(1)) => Ok(()),
+        Ok(Ok(_)) => Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "new process exited before accepting connections",
+        )),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(Error::new(
+            ErrorKind::TimedOut,
+            format!(
+                "new process didn't accept connections within {:.3}s",
+                limit.as_secs_f64()
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(Some("42"), Some("1"), 42).unwrap(), Some(3));
+        assert_eq!(listen_fds(Some(" 42\n"), Some("2"), 42).unwrap(), Some(3));
+
+        // The sockets were passed to another process, e.g. the parent.
+        assert_eq!(listen_fds(Some("41"), Some("1"), 42).unwrap(), None);
+        assert_eq!(listen_fds(None, Some("1"), 42).unwrap(), None);
+
+        assert_eq!(listen_fds(Some("42"), Some("0"), 42).unwrap(), None);
+        assert_eq!(listen_fds(Some("42"), None, 42).unwrap(), None);
+        assert_eq!(
+            listen_fds(Some("42"), Some("three"), 42)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready() {
+        let (ready, mut child) = UnixStream::pair().unwrap();
+        child.write_all(b"1").unwrap();
+        assert!(wait_ready(ready, Duration::from_secs(1)).await.is_ok());
+
+        // The new process exited.
+        let (ready, child) = UnixStream::pair().unwrap();
+        drop(child);
+        let err = wait_ready(ready, Duration::from_secs(1)).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        // The new process hangs.
+        let (ready, _child) = UnixStream::pair().unwrap();
+        let err = wait_ready(ready, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+}
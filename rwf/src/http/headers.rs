@@ -2,9 +2,12 @@
 use std::collections::{hash_map::Iter, HashMap};
 
 /// HTTP headers.
+///
+/// Headers are sent in alphabetical order, unless an order is set with [`Headers::set_order`].
 #[derive(Clone, Debug, Default)]
 pub struct Headers {
     headers: HashMap<String, String>,
+    order: Vec<String>,
 }
 
 impl Headers {
@@ -12,9 +15,55 @@ impl Headers {
     pub fn new() -> Self {
         Self {
             headers: HashMap::new(),
+            order: vec![],
         }
     }
 
+    /// Send these headers first, in this order, e.g. for clients that expect a header
+    /// at the start. Other headers follow in alphabetical order. Case insensitive.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Headers;
+    ///
+    /// let mut headers = Headers::new();
+    /// headers.insert("Content-Type", "text/plain");
+    /// headers.insert("Date", "Tue, 15 Nov 1994 08:12:31 GMT");
+    /// headers.insert("Server", "rwf");
+    /// headers.set_order(["server", "date"]);
+    ///
+    /// assert_eq!(
+    ///     headers.to_bytes(),
+    ///     b"server: rwf\r\ndate: Tue, 15 Nov 1994 08:12:31 GMT\r\ncontent-type: text/plain\r\n"
+    /// );
+    /// ```
+    pub fn set_order(&mut self, names: impl IntoIterator<Item = impl ToString>) {
+        self.order = names
+            .into_iter()
+            .map(|name| name.to_string().to_lowercase())
+            .collect();
+    }
+
+    /// Headers in the order they are sent.
+    pub fn ordered(&self) -> Vec<(&String, &String)> {
+        let mut ordered = self
+            .order
+            .iter()
+            .filter_map(|name| self.headers.get_key_value(name))
+            .collect::<Vec<_>>();
+
+        let mut rest = self
+            .headers
+            .iter()
+            .filter(|(name, _)| !self.order.contains(name))
+            .collect::<Vec<_>>();
+        rest.sort();
+
+        ordered.extend(rest);
+        ordered
+    }
+
     /// Insert a header name and value.
     ///
     /// The name will be converted to lowercase.
@@ -53,7 +102,7 @@ impl Headers {
     /// Used to send headers over the wire to the client as part of a response.
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-        for (name, value) in &self.headers {
+        for (name, value) in self.ordered() {
             bytes.extend_from_slice(name.as_bytes());
             bytes.extend_from_slice(b": ");
             bytes.extend_from_slice(value.as_bytes());
@@ -65,6 +114,9 @@ impl Headers {
 
 impl From<HashMap<String, String>> for Headers {
     fn from(headers: HashMap<String, String>) -> Self {
-        Self { headers }
+        Self {
+            headers,
+            order: vec![],
+        }
     }
 }
@@ -0,0 +1,707 @@
+//! Deserialize JSON request bodies, reporting where they don't match the expected type.
+//!
+//! Syntax errors have the line and column where parsing failed. Bodies which are valid JSON
+//! but don't match the type, e.g. a missing field or a string instead of a number, have the path
+//! to the value, e.g. `items[2].price`, so the client can be told which field is wrong.
+//!
+//! Before deserializing, bodies are checked against the limits in [`Limits`]: how deep arrays and objects can be
+//! nested, how long strings can be, and whether objects can have the same key twice. Integers which don't fit
+//! in an `i64` or `u64` are rejected instead of being rounded, unless the `arbitrary-precision` feature is enabled.
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use serde_json::{Map, Value};
+
+use crate::config::General;
+
+/// Deepest nesting of arrays and objects `serde_json` can parse.
+pub const MAX_DEPTH: usize = 128;
+
+/// JSON body which couldn't be deserialized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    path: Vec<Segment>,
+    message: String,
+    position: Option<Position>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Position {
+    line: usize,
+    column: usize,
+    offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl JsonError {
+    /// Path to the value which doesn't match the type, e.g. `items[2].price`. Empty for
+    /// syntax errors and errors about the whole body.
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+
+        for segment in &self.path {
+            match segment {
+                Segment::Key(key) if path.is_empty() => path.push_str(key),
+                Segment::Key(key) => {
+                    path.push('.');
+                    path.push_str(key);
+                }
+                Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+            }
+        }
+
+        path
+    }
+
+    /// What's wrong, e.g. `missing field` or `invalid type: string "a", expected i64`.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Line of the syntax error, starting at 1.
+    pub fn line(&self) -> Option<usize> {
+        self.position.map(|position| position.line)
+    }
+
+    /// Column of the syntax error, in bytes, starting at 1.
+    pub fn column(&self) -> Option<usize> {
+        self.position.map(|position| position.column)
+    }
+
+    /// Offset of the syntax error from the start of the body, in bytes, starting at 0.
+    pub fn offset(&self) -> Option<usize> {
+        self.position.map(|position| position.offset)
+    }
+
+    /// The body isn't valid JSON or is over the limits, as opposed to valid JSON not matching the type.
+    pub fn is_syntax(&self) -> bool {
+        self.position.is_some()
+    }
+
+    fn syntax(err: serde_json::Error, bytes: &[u8]) -> Self {
+        let message = err.to_string();
+        // The position is reported separately.
+        let message = match message.rfind(" at line ") {
+            Some(end) => message[..end].to_string(),
+            None => message,
+        };
+
+        // serde_json counts columns in bytes, pointing at the last byte it read.
+        let line_start = bytes
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == b'\n')
+            .nth(err.line().saturating_sub(2))
+            .map(|(offset, _)| offset + 1)
+            .filter(|_| err.line() > 1)
+            .unwrap_or(0);
+
+        Self {
+            path: vec![],
+            message,
+            position: Some(Position {
+                line: err.line(),
+                column: err.column(),
+                offset: (line_start + err.column().saturating_sub(1)).min(bytes.len()),
+            }),
+        }
+    }
+
+    fn at(bytes: &[u8], offset: usize, message: impl ToString) -> Self {
+        let before = &bytes[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map(|newline| newline + 1)
+            .unwrap_or(0);
+
+        Self {
+            path: vec![],
+            message: message.to_string(),
+            position: Some(Position {
+                line: before.iter().filter(|byte| **byte == b'\n').count() + 1,
+                column: offset - line_start + 1,
+                offset,
+            }),
+        }
+    }
+
+    fn within(mut self, segment: Segment) -> Self {
+        self.path.insert(0, segment);
+        self
+    }
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.position {
+            Some(position) => write!(
+                f,
+                "{} at line {} column {} (byte {})",
+                self.message, position.line, position.column, position.offset
+            ),
+            None if self.path.is_empty() => write!(f, "{}", self.message),
+            None => write!(f, "{}: {}", self.path(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl de::Error for JsonError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self {
+            path: vec![],
+            message: msg.to_string(),
+            position: None,
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self::custom("missing field").within(Segment::Key(field.to_string()))
+    }
+
+    fn unknown_field(field: &str, _expected: &'static [&'static str]) -> Self {
+        Self::custom("unknown field").within(Segment::Key(field.to_string()))
+    }
+}
+
+/// Limits on the structure of JSON bodies, set by the `max_json_depth`, `max_json_string_size`
+/// and `reject_duplicate_json_keys` settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// How deep arrays and objects can be nested, up to [`MAX_DEPTH`].
+    pub depth: usize,
+    /// Longest string, in bytes, as sent, i.e. with escape sequences.
+    pub string_size: usize,
+    /// Reject objects with the same key twice. Otherwise, the last value is used.
+    pub reject_duplicate_keys: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            depth: MAX_DEPTH,
+            string_size: usize::MAX,
+            reject_duplicate_keys: false,
+        }
+    }
+}
+
+impl From<&General> for Limits {
+    fn from(config: &General) -> Self {
+        Self {
+            depth: config.max_json_depth.min(MAX_DEPTH),
+            string_size: config.max_json_string_size,
+            reject_duplicate_keys: config.reject_duplicate_json_keys,
+        }
+    }
+}
+
+/// Deserialize the JSON text.
+pub(crate) fn from_str<T: DeserializeOwned>(text: &str) -> Result<T, JsonError> {
+    from_slice(text.as_bytes())
+}
+
+/// Deserialize the JSON bytes, which must be UTF-8.
+pub(crate) fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, JsonError> {
+    from_slice_with(bytes, &Limits::default())
+}
+
+/// Deserialize the JSON bytes, which must be UTF-8, if they are within the limits.
+pub(crate) fn from_slice_with<T: DeserializeOwned>(
+    bytes: &[u8],
+    limits: &Limits,
+) -> Result<T, JsonError> {
+    check(bytes, limits)?;
+    from_value(serde_json::from_slice(bytes).map_err(|err| JsonError::syntax(err, bytes))?)
+}
+
+enum Container {
+    Array,
+    Object { keys: HashSet<String>, key: bool },
+}
+
+/// Check the limits without parsing the values. Syntax errors are left to `serde_json`.
+fn check(bytes: &[u8], limits: &Limits) -> Result<(), JsonError> {
+    let mut stack = vec![];
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        match bytes[offset] {
+            open @ (b'[' | b'{') => {
+                if stack.len() >= limits.depth {
+                    return Err(JsonError::at(
+                        bytes,
+                        offset,
+                        format!("nested deeper than {} levels", limits.depth),
+                    ));
+                }
+
+                stack.push(match open {
+                    b'[' => Container::Array,
+                    _ => Container::Object {
+                        keys: HashSet::new(),
+                        key: true,
+                    },
+                });
+            }
+
+            b']' | b'}' => {
+                stack.pop();
+            }
+
+            separator @ (b',' | b':') => {
+                if let Some(Container::Object { key, .. }) = stack.last_mut() {
+                    *key = separator == b',';
+                }
+            }
+
+            b'"' => {
+                let start = offset;
+                offset += 1;
+
+                while offset < bytes.len() && bytes[offset] != b'"' {
+                    offset += if bytes[offset] == b'\\' { 2 } else { 1 };
+
+                    if offset - start - 1 > limits.string_size {
+                        return Err(JsonError::at(
+                            bytes,
+                            start,
+                            format!("string longer than {} bytes", limits.string_size),
+                        ));
+                    }
+                }
+
+                if let Some(Container::Object { keys, key: true }) = stack.last_mut() {
+                    if limits.reject_duplicate_keys {
+                        let raw = &bytes[start..(offset + 1).min(bytes.len())];
+                        let name = serde_json::from_slice::<String>(raw)
+                            .unwrap_or_else(|_| String::from_utf8_lossy(raw).into_owned());
+
+                        if !keys.insert(name.clone()) {
+                            return Err(JsonError::at(
+                                bytes,
+                                start,
+                                format!("duplicate key \"{}\"", name),
+                            ));
+                        }
+                    }
+                }
+            }
+
+            b'-' | b'0'..=b'9' => {
+                let start = offset;
+                while offset + 1 < bytes.len()
+                    && matches!(
+                        bytes[offset + 1],
+                        b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E'
+                    )
+                {
+                    offset += 1;
+                }
+
+                let number = &bytes[start..=offset];
+                let integer = !number.iter().any(|byte| matches!(byte, b'.' | b'e' | b'E'));
+
+                // Without arbitrary precision, serde_json would round these to the nearest f64.
+                if integer && !cfg!(feature = "arbitrary-precision") {
+                    let number = std::str::from_utf8(number).unwrap_or_default();
+                    if number.parse::<i64>().is_err() && number.parse::<u64>().is_err() {
+                        return Err(JsonError::at(
+                            bytes,
+                            start,
+                            "integer doesn't fit in 64 bits",
+                        ));
+                    }
+                }
+            }
+
+            _ => (),
+        }
+
+        offset += 1;
+    }
+
+    Ok(())
+}
+
+fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, JsonError> {
+    T::deserialize(Tracked(value))
+}
+
+/// A JSON value, adding its key or index to errors from the values inside it.
+struct Tracked(Value);
+
+macro_rules! forward_to_value {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                // Arbitrary precision numbers are kept as text, and errors about them wouldn't have the value.
+                #[cfg(feature = "arbitrary-precision")]
+                if let Value::Number(ref number) = self.0 {
+                    if let Some(number) = number.as_u64() {
+                        return IntoDeserializer::<JsonError>::into_deserializer(number).$method(visitor);
+                    } else if let Some(number) = number.as_i64() {
+                        return IntoDeserializer::<JsonError>::into_deserializer(number).$method(visitor);
+                    } else if number.is_f64() {
+                        if let Some(number) = number.as_f64() {
+                            return IntoDeserializer::<JsonError>::into_deserializer(number).$method(visitor);
+                        }
+                    }
+                }
+
+                self.0.$method(visitor).map_err(de::Error::custom)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Tracked {
+    type Error = JsonError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Array(values) => {
+                let len = values.len();
+                let mut seq = Seq {
+                    values: values.into_iter().enumerate(),
+                };
+                let value = visitor.visit_seq(&mut seq)?;
+
+                match seq.values.next() {
+                    None => Ok(value),
+                    Some(_) => Err(de::Error::invalid_length(len, &"fewer elements in array")),
+                }
+            }
+            Value::Object(map) => visitor.visit_map(Fields::new(map)),
+            value => value.deserialize_any(visitor).map_err(de::Error::custom),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0
+            .deserialize_enum(name, variants, visitor)
+            .map_err(de::Error::custom)
+    }
+
+    forward_to_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_unit
+        deserialize_identifier
+    }
+
+    forward_to_deserialize_any! {
+        unit_struct seq tuple tuple_struct map struct ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, JsonError> for Tracked {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Elements of an array.
+struct Seq {
+    values: std::iter::Enumerate<std::vec::IntoIter<Value>>,
+}
+
+impl<'de> SeqAccess<'de> for &mut Seq {
+    type Error = JsonError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        match self.values.next() {
+            Some((index, value)) => seed
+                .deserialize(Tracked(value))
+                .map(Some)
+                .map_err(|err| err.within(Segment::Index(index))),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Keys and values of an object.
+struct Fields {
+    fields: serde_json::map::IntoIter,
+    current: Option<(String, Value)>,
+}
+
+impl Fields {
+    fn new(map: Map<String, Value>) -> Self {
+        Self {
+            fields: map.into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for Fields {
+    type Error = JsonError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.fields.next() {
+            Some((key, value)) => {
+                let result = seed.deserialize(key.as_str().into_deserializer());
+                self.current = Some((key, value));
+                result.map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, value) = self
+            .current
+            .take()
+            .ok_or_else(|| de::Error::custom("value is missing"))?;
+
+        seed.deserialize(Tracked(value))
+            .map_err(|err| err.within(Segment::Key(key)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct Order {
+        customer: Customer,
+        items: Vec<Item>,
+        note: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Customer {
+        email: String,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Size {
+        Small,
+        Large,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Item {
+        quantity: u8,
+        size: Size,
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let order = from_str::<Order>(
+            r#"{"customer": {"email": "a@b.com"}, "items": [{"quantity": 2, "size": "large"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            order,
+            Order {
+                customer: Customer {
+                    email: "a@b.com".into()
+                },
+                items: vec![Item {
+                    quantity: 2,
+                    size: Size::Large
+                }],
+                note: None,
+            }
+        );
+
+        let value = from_slice::<Value>(br#"{"a": [1, 2.5, null]}"#).unwrap();
+        assert_eq!(value["a"][1], 2.5);
+    }
+
+    #[test]
+    fn test_errors() {
+        for (json, path, message) in [
+            (r#"{"items": []}"#, "customer", "missing field"),
+            (
+                r#"{"customer": {}, "items": []}"#,
+                "customer.email",
+                "missing field",
+            ),
+            (
+                r#"{"customer": {"email": "a"}, "items": [{"quantity": 1, "size": "small"}, {"quantity": 300, "size": "small"}]}"#,
+                "items[1].quantity",
+                "invalid value: integer `300`, expected u8",
+            ),
+            (
+                r#"{"customer": {"email": "a"}, "items": [{"quantity": 1, "size": "huge"}]}"#,
+                "items[0].size",
+                "unknown variant `huge`, expected `small` or `large`",
+            ),
+            (
+                r#"{"customer": {"email": 5}, "items": []}"#,
+                "customer.email",
+                "invalid type: integer `5`, expected a string",
+            ),
+            (
+                r#"{"customer": {"email": "a"}, "items": [], "coupon": "x"}"#,
+                "coupon",
+                "unknown field",
+            ),
+            (
+                "[]",
+                "",
+                "invalid length 0, expected struct Order with 3 elements",
+            ),
+        ] {
+            let err = from_str::<Order>(json).unwrap_err();
+            assert_eq!(err.path(), path, "{}", json);
+            assert_eq!(err.message(), message, "{}", json);
+            assert!(!err.is_syntax());
+        }
+
+        let err = from_str::<Order>("{\n  \"customer\": }").unwrap_err();
+        assert!(err.is_syntax());
+        assert_eq!(err.line(), Some(2));
+        assert_eq!(err.column(), Some(15));
+        assert_eq!(err.message(), "expected value");
+        assert_eq!(err.offset(), Some(16));
+        assert_eq!(
+            err.to_string(),
+            "expected value at line 2 column 15 (byte 16)"
+        );
+    }
+
+    #[test]
+    fn test_depth() {
+        let deep = format!("{}{}", "[".repeat(10_000), "]".repeat(10_000));
+        let err = from_slice::<Value>(deep.as_bytes()).unwrap_err();
+        assert!(err.is_syntax());
+        assert_eq!(err.message(), "nested deeper than 128 levels");
+        assert_eq!(err.offset(), Some(128));
+
+        let limits = Limits {
+            depth: 2,
+            ..Default::default()
+        };
+        assert!(from_slice_with::<Value>(br#"{"a": [1, "[[["]}"#, &limits).is_ok());
+        let err = from_slice_with::<Value>(b"{\n  \"a\": [{}]\n}", &limits).unwrap_err();
+        assert_eq!(err.message(), "nested deeper than 2 levels");
+        assert_eq!(
+            (err.line(), err.column(), err.offset()),
+            (Some(2), Some(9), Some(10))
+        );
+    }
+
+    #[test]
+    fn test_string_size() {
+        let limits = Limits {
+            string_size: 1024 * 1024,
+            ..Default::default()
+        };
+
+        // 100 MB, which could be under the body size limit.
+        let mut body = Vec::with_capacity(100 * 1024 * 1024 + 2);
+        body.push(b'"');
+        body.resize(100 * 1024 * 1024 + 1, b'a');
+        body.push(b'"');
+
+        let err = from_slice_with::<String>(&body, &limits).unwrap_err();
+        assert_eq!(err.message(), "string longer than 1048576 bytes");
+        assert_eq!(err.offset(), Some(0));
+
+        let limits = Limits {
+            string_size: 4,
+            ..Default::default()
+        };
+        assert!(from_slice_with::<Value>(br#"{"abcd": "\"\""}"#, &limits).is_ok());
+        assert!(from_slice_with::<Value>(br#"{"abcde": 1}"#, &limits).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let body = br#"{"role": "user", "nested": {"role": 1}, "items": [{"role": 2}], "\u0072ole": "admin"}"#;
+
+        // The last value wins by default.
+        let value = from_slice::<Value>(body).unwrap();
+        assert_eq!(value["role"], "admin");
+
+        let limits = Limits {
+            reject_duplicate_keys: true,
+            ..Default::default()
+        };
+        let err = from_slice_with::<Value>(body, &limits).unwrap_err();
+        assert!(err.is_syntax());
+        assert_eq!(err.message(), "duplicate key \"role\"");
+        assert_eq!(err.offset(), Some(64));
+
+        // Same keys in different objects, and keys used as values, are fine.
+        assert!(from_slice_with::<Value>(
+            br#"[{"a": "a"}, {"a": {"a": 1}}, {"b": "a", "a": "b"}]"#,
+            &limits
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_big_numbers() {
+        assert_eq!(
+            from_slice::<Value>(b"[-9223372036854775808, 18446744073709551615, 1.5e300]").unwrap()
+                [1],
+            u64::MAX
+        );
+
+        let result = from_slice::<Value>(b"[1, 18446744073709551616]");
+
+        if cfg!(feature = "arbitrary-precision") {
+            assert_eq!(result.unwrap()[1].to_string(), "18446744073709551616");
+            assert_eq!(
+                from_slice::<Vec<u128>>(b"[1, 18446744073709551616]").unwrap()[1],
+                u64::MAX as u128 + 1
+            );
+        } else {
+            let err = result.unwrap_err();
+            assert_eq!(err.message(), "integer doesn't fit in 64 bits");
+            assert_eq!(err.offset(), Some(4));
+        }
+    }
+}
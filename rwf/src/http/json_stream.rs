@@ -0,0 +1,132 @@
+//! Stream a JSON array, serializing its items while it's sent.
+//!
+//! Used by [`Response::json_stream`](super::Response::json_stream) to send large lists, e.g. tens of thousands
+//! of rows, without serializing all of them into memory first.
+use serde::Serialize;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::body::CHUNK_SIZE;
+
+/// Reader producing a JSON array from an iterator.
+///
+/// Items are serialized in batches of about [`CHUNK_SIZE`] bytes, as the reader is read.
+/// If an item can't be serialized, reading fails. The response headers have been sent
+/// by then, so the client sees an incomplete response.
+pub struct JsonArray<I> {
+    items: I,
+    buffer: Vec<u8>,
+    position: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl<I> JsonArray<I>
+where
+    I: Iterator,
+    I::Item: Serialize,
+{
+    /// Create a reader for the items.
+    pub fn new(items: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            items: items.into_iter(),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            position: 0,
+            started: false,
+            finished: false,
+        }
+    }
+
+    /// Serialize the next batch of items into the buffer.
+    fn fill(&mut self) -> Result<(), Error> {
+        self.buffer.clear();
+        self.position = 0;
+
+        while self.buffer.len() < CHUNK_SIZE && !self.finished {
+            match self.items.next() {
+                Some(item) => {
+                    self.buffer.push(if self.started { b',' } else { b'[' });
+                    self.started = true;
+                    serde_json::to_writer(&mut self.buffer, &item)
+                        .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+                }
+
+                None => {
+                    if !self.started {
+                        self.buffer.push(b'[');
+                    }
+                    self.buffer.push(b']');
+                    self.finished = true;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<I> AsyncRead for JsonArray<I>
+where
+    I: Iterator + Unpin,
+    I::Item: Serialize,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.position == this.buffer.len() && !this.finished {
+            this.fill()?;
+        }
+
+        let remaining = &this.buffer[this.position..];
+        let n = remaining.len().min(buf.remaining());
+        buf.put_slice(&remaining[..n]);
+        this.position += n;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    async fn read<I>(reader: JsonArray<I>) -> std::io::Result<String>
+    where
+        I: Iterator + Unpin,
+        I::Item: Serialize,
+    {
+        let mut reader = reader;
+        let mut json = String::new();
+        reader.read_to_string(&mut json).await?;
+        Ok(json)
+    }
+
+    #[tokio::test]
+    async fn test_json_array() {
+        assert_eq!(read(JsonArray::new(Vec::<i64>::new())).await.unwrap(), "[]");
+        assert_eq!(read(JsonArray::new(vec![1])).await.unwrap(), "[1]");
+        assert_eq!(
+            read(JsonArray::new(vec!["a", "b"])).await.unwrap(),
+            r#"["a","b"]"#
+        );
+
+        // Several batches.
+        let items = (0..50_000).map(|i| serde_json::json!({ "id": i }));
+        let json = read(JsonArray::new(items)).await.unwrap();
+        let parsed: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.len(), 50_000);
+        assert_eq!(parsed[49_999]["id"], 49_999);
+
+        // Maps with non-string keys can't be serialized.
+        let items = vec![std::collections::HashMap::from([((1, 2), 3)])];
+        assert!(read(JsonArray::new(items)).await.is_err());
+    }
+}
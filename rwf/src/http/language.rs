@@ -0,0 +1,202 @@
+//! Language negotiation with the `Accept-Language` header.
+//!
+//! ```
+//! use rwf::http::Request;
+//!
+//! fn locale(request: &Request) -> &'static str {
+//!     request.preferred_language(&["en", "fr-CA"]).unwrap_or("en")
+//! }
+//! ```
+//!
+//! Supported languages are matched against the client's language ranges in order of preference. A range matches
+//! a language with the same tag, or, failing that, with the same primary subtag, so `fr-FR` matches `fr-CA`.
+//! Tags are case-insensitive.
+
+/// A language range from the `Accept-Language` header, e.g. `fr-CA;q=0.8`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageRange {
+    tag: String,
+    quality: f32,
+}
+
+impl LanguageRange {
+    /// Parse the value of the `Accept-Language` header. Ranges are ordered by quality, highest first.
+    /// Ranges which can't be parsed are skipped.
+    pub fn parse_header(value: &str) -> Vec<Self> {
+        let mut ranges = value.split(',').filter_map(Self::parse).collect::<Vec<_>>();
+
+        // Stable, so ranges the client ranked equally stay in its order.
+        ranges.sort_by(|a, b| b.quality.total_cmp(&a.quality));
+
+        ranges
+    }
+
+    /// Parse a single language range, e.g. `en-US;q=0.5` or `*`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let tag = parts.next()?.trim();
+
+        let valid = tag == "*"
+            || tag.split('-').enumerate().all(|(i, subtag)| {
+                (1..=8).contains(&subtag.len())
+                    && if i == 0 {
+                        subtag.chars().all(|c| c.is_ascii_alphabetic())
+                    } else {
+                        subtag.chars().all(|c| c.is_ascii_alphanumeric())
+                    }
+            });
+        if !valid {
+            return None;
+        }
+
+        let mut quality = 1.0;
+
+        for param in parts {
+            let (name, value) = param.split_once('=')?;
+
+            if name.trim().eq_ignore_ascii_case("q") {
+                quality = value
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| (0.0..=1.0).contains(q))?;
+            } else {
+                return None;
+            }
+        }
+
+        Some(Self {
+            tag: tag.to_string(),
+            quality,
+        })
+    }
+
+    /// Language tag, as sent by the client, e.g. `en-US`, or `*` for any language.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Primary subtag, e.g. `en` for `en-US`.
+    pub fn primary(&self) -> &str {
+        primary(&self.tag)
+    }
+
+    /// Quality, between 0 and 1. A quality of 0 means the client doesn't want the language.
+    pub fn quality(&self) -> f32 {
+        self.quality
+    }
+}
+
+fn primary(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or_default()
+}
+
+/// The supported language the client prefers, see the [module documentation](self).
+pub(crate) fn preferred<'a>(ranges: &[LanguageRange], supported: &[&'a str]) -> Option<&'a str> {
+    // Languages the client explicitly doesn't want.
+    let rejected = |language: &str| {
+        ranges
+            .iter()
+            .any(|range| range.quality == 0.0 && range.tag.eq_ignore_ascii_case(language))
+    };
+
+    for range in ranges.iter().filter(|range| range.quality > 0.0) {
+        let candidates = || {
+            supported
+                .iter()
+                .copied()
+                .filter(|language| !rejected(language))
+        };
+
+        if range.tag == "*" {
+            if let Some(language) = candidates().next() {
+                return Some(language);
+            }
+            continue;
+        }
+
+        let exact = candidates().find(|language| range.tag.eq_ignore_ascii_case(language));
+        let similar = || {
+            candidates().find(|language| range.primary().eq_ignore_ascii_case(primary(language)))
+        };
+
+        if let Some(language) = exact.or_else(similar) {
+            return Some(language);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let ranges = LanguageRange::parse_header(
+            "fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5, x_y, en-;q=1",
+        );
+        let tags = ranges
+            .iter()
+            .map(|range| (range.tag(), range.quality()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tags,
+            vec![
+                ("fr-CH", 1.0),
+                ("fr", 0.9),
+                ("en", 0.8),
+                ("de", 0.7),
+                ("*", 0.5),
+            ]
+        );
+        assert_eq!(ranges[0].primary(), "fr");
+
+        for malformed in [
+            "",
+            "englishman",
+            "1en",
+            "en-US;q=2",
+            "en;q=abc",
+            "en;level=1",
+            "en US",
+            "\u{e9}",
+        ] {
+            assert!(LanguageRange::parse(malformed).is_none(), "{}", malformed);
+        }
+
+        assert!(LanguageRange::parse_header(";;;,=,").is_empty());
+        assert_eq!(
+            LanguageRange::parse("zh-Hant-TW").unwrap().tag(),
+            "zh-Hant-TW"
+        );
+    }
+
+    #[test]
+    fn test_preferred() {
+        let preferred = |header: &str, supported: &[&'static str]| {
+            preferred(&LanguageRange::parse_header(header), supported)
+        };
+
+        assert_eq!(
+            preferred("fr-CH, en;q=0.8", &["en", "fr-CH"]),
+            Some("fr-CH")
+        );
+        // Same primary subtag.
+        assert_eq!(
+            preferred("fr-CH, en;q=0.8", &["en", "fr-FR"]),
+            Some("fr-FR")
+        );
+        assert_eq!(preferred("fr, en;q=0.8", &["en", "fr-CA"]), Some("fr-CA"));
+        // Exact matches first.
+        assert_eq!(preferred("en-GB", &["en-US", "en-GB"]), Some("en-GB"));
+        assert_eq!(preferred("EN-gb", &["en-GB"]), Some("en-GB"));
+        assert_eq!(preferred("de, es;q=0.5", &["en", "fr"]), None);
+        assert_eq!(preferred("de, *;q=0.1", &["en", "fr"]), Some("en"));
+        assert_eq!(preferred("*, en;q=0", &["en", "fr"]), Some("fr"));
+        assert_eq!(preferred("en;q=0", &["en"]), None);
+        assert_eq!(preferred("", &["en"]), None);
+    }
+}
@@ -0,0 +1,214 @@
+//! Custom fields added to the access log entry of a request.
+//!
+//! Each request gets its own [`LogFields`], available with [`crate::http::Request::log_fields`]. Middleware and controllers
+//! can add fields with [`crate::http::Request::log_field`], and they are appended to the request's access log entry:
+//!
+//! ```
+//! # use rwf::http::LogFields;
+//! let fields = LogFields::new();
+//!
+//! fields.set("tenant", "acme");
+//! fields.set("order_id", 42);
+//! fields.set("tenant", "globex"); // Later writes win.
+//!
+//! assert_eq!(fields.to_string(), r#"tenant="globex" order_id=42"#);
+//! ```
+//!
+//! Fields are kept in the order they were first set. Fields beyond [`MAX_FIELDS`] and values longer than [`MAX_VALUE_SIZE`]
+//! are ignored.
+use parking_lot::Mutex;
+use tracing::debug;
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::Arc;
+
+/// Maximum number of fields logged for a request.
+pub const MAX_FIELDS: usize = 32;
+
+/// Maximum size of a field name or text value, in bytes.
+pub const MAX_VALUE_SIZE: usize = 256;
+
+/// Value of a log field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl Display for LogValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            // Quoted and escaped, so values can't break up the log line.
+            LogValue::String(value) => write!(f, "{:?}", value),
+            LogValue::Integer(value) => write!(f, "{}", value),
+            LogValue::Float(value) => write!(f, "{}", value),
+            LogValue::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<&str> for LogValue {
+    fn from(value: &str) -> Self {
+        LogValue::String(value.to_string())
+    }
+}
+
+impl From<String> for LogValue {
+    fn from(value: String) -> Self {
+        LogValue::String(value)
+    }
+}
+
+impl From<&String> for LogValue {
+    fn from(value: &String) -> Self {
+        LogValue::String(value.clone())
+    }
+}
+
+impl From<bool> for LogValue {
+    fn from(value: bool) -> Self {
+        LogValue::Boolean(value)
+    }
+}
+
+impl From<f32> for LogValue {
+    fn from(value: f32) -> Self {
+        LogValue::Float(value as f64)
+    }
+}
+
+impl From<f64> for LogValue {
+    fn from(value: f64) -> Self {
+        LogValue::Float(value)
+    }
+}
+
+macro_rules! from_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for LogValue {
+                fn from(value: $ty) -> Self {
+                    LogValue::Integer(value as i64)
+                }
+            }
+        )*
+    };
+}
+
+from_integer!(i8, i16, i32, i64, u8, u16, u32, isize);
+
+/// Custom fields logged with a request.
+///
+/// It's safe to clone since the fields are behind an [`std::sync::Arc`].
+#[derive(Debug, Default, Clone)]
+pub struct LogFields {
+    inner: Arc<Mutex<Vec<(String, LogValue)>>>,
+}
+
+impl LogFields {
+    /// Create new empty set of fields.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set field `name` to `value`, replacing the previous value if the field is already set.
+    pub fn set(&self, name: &str, value: impl Into<LogValue>) {
+        let value = value.into();
+
+        if name.len() > MAX_VALUE_SIZE {
+            debug!(
+                "log field \"{}...\" ignored, name is too long",
+                name.chars().take(16).collect::<String>()
+            );
+            return;
+        }
+
+        if let LogValue::String(ref value) = value {
+            if value.len() > MAX_VALUE_SIZE {
+                debug!("log field \"{}\" ignored, value is too long", name);
+                return;
+            }
+        }
+
+        let mut fields = self.inner.lock();
+
+        if let Some((_, existing)) = fields.iter_mut().find(|(field, _)| field == name) {
+            *existing = value;
+        } else if fields.len() < MAX_FIELDS {
+            fields.push((name.to_string(), value));
+        } else {
+            debug!(
+                "log field \"{}\" ignored, request already has {} fields",
+                name, MAX_FIELDS
+            );
+        }
+    }
+
+    /// Get the value of field `name`.
+    pub fn get(&self, name: &str) -> Option<LogValue> {
+        self.inner
+            .lock()
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Number of fields set.
+    pub fn len(&self) -> usize {
+        self.inner.lock().len()
+    }
+
+    /// No fields are set.
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().is_empty()
+    }
+}
+
+impl Display for LogFields {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let fields = self.inner.lock();
+
+        for (i, (name, value)) in fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log_fields() {
+        let fields = LogFields::new();
+        let shared = fields.clone();
+
+        fields.set("tenant", "acme");
+        shared.set("admin", true);
+        fields.set("total", 9.5);
+        shared.set("tenant", "globex\n");
+        assert_eq!(
+            fields.to_string(),
+            r#"tenant="globex\n" admin=true total=9.5"#
+        );
+
+        fields.set("note", "a".repeat(MAX_VALUE_SIZE + 1));
+        assert!(fields.get("note").is_none());
+
+        for i in 0..MAX_FIELDS * 2 {
+            fields.set(&format!("field_{}", i), i as i64);
+        }
+        assert_eq!(fields.len(), MAX_FIELDS);
+
+        // Existing fields can still be updated.
+        fields.set("admin", false);
+        assert_eq!(fields.get("admin"), Some(LogValue::Boolean(false)));
+    }
+}
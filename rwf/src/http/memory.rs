@@ -0,0 +1,158 @@
+//! Coarse accounting of memory used by requests and responses.
+//!
+//! Large buffers, i.e. request bodies, multipart forms and response bodies, reserve their size against a global budget
+//! before they are used. If the reservation would exceed the budget, the request is rejected with `503 - Service Unavailable`,
+//! so a burst of large requests can't use up all the memory. Reservations are released when they are dropped.
+//!
+//! The budget is configured with the `memory_budget` setting (in bytes). Buffers smaller than [`MIN_RESERVATION`] aren't counted.
+use once_cell::sync::Lazy;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use super::Error;
+use crate::config::get_config;
+
+/// Buffers smaller than this aren't counted.
+pub const MIN_RESERVATION: usize = 16 * 1024;
+
+/// Seconds the client is asked to wait before retrying a request rejected
+/// because the budget is exhausted.
+pub const RETRY_AFTER: u64 = 1;
+
+static GLOBAL: Lazy<Budget> = Lazy::new(|| Budget::new(get_config().general.memory_budget));
+
+/// Memory budget shared by all requests.
+#[derive(Debug)]
+pub struct Budget {
+    limit: usize,
+    usage: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+impl Budget {
+    /// Create a budget of this many bytes. A limit of `0` means no limit, but usage is still counted.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            usage: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// The budget used by the server, configured with the `memory_budget` setting.
+    pub fn global() -> &'static Budget {
+        &GLOBAL
+    }
+
+    /// Reserve memory for a buffer of this size. Returns an error if the budget would be exceeded.
+    pub fn reserve(&'static self, bytes: usize) -> Result<Reservation, Error> {
+        if bytes < MIN_RESERVATION {
+            return Ok(Reservation::default());
+        }
+
+        let reserved = self
+            .usage
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |usage| {
+                let usage = usage.checked_add(bytes)?;
+
+                if self.limit > 0 && usage > self.limit {
+                    None
+                } else {
+                    Some(usage)
+                }
+            });
+
+        match reserved {
+            Ok(_) => Ok(Reservation {
+                budget: Some(self),
+                bytes,
+            }),
+
+            Err(usage) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(Error::MemoryBudgetExceeded {
+                    requested: bytes,
+                    usage,
+                    limit: self.limit,
+                })
+            }
+        }
+    }
+
+    /// Bytes currently reserved.
+    pub fn usage(&self) -> usize {
+        self.usage.load(Ordering::Acquire)
+    }
+
+    /// Maximum number of bytes that can be reserved, or `0` if there is no limit.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// How many reservations were rejected because they would exceed the budget.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+/// Memory reserved for a buffer. Released when dropped.
+#[derive(Debug, Default)]
+pub struct Reservation {
+    budget: Option<&'static Budget>,
+    bytes: usize,
+}
+
+impl Reservation {
+    /// Reserve memory in the global budget.
+    pub fn new(bytes: usize) -> Result<Self, Error> {
+        Budget::global().reserve(bytes)
+    }
+
+    /// Number of bytes reserved. Small buffers are not counted, so this can be `0`.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(budget) = self.budget {
+            budget.usage.fetch_sub(self.bytes, Ordering::AcqRel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn budget(limit: usize) -> &'static Budget {
+        Box::leak(Box::new(Budget::new(limit)))
+    }
+
+    #[test]
+    fn test_reserve() {
+        let limited = budget(100 * 1024);
+
+        let small = limited.reserve(1024).unwrap();
+        assert_eq!(small.bytes(), 0);
+        assert_eq!(limited.usage(), 0);
+
+        let first = limited.reserve(60 * 1024).unwrap();
+        assert_eq!(limited.usage(), 60 * 1024);
+
+        let err = limited.reserve(60 * 1024).unwrap_err();
+        assert!(matches!(err, Error::MemoryBudgetExceeded { .. }));
+        assert_eq!(err.code(), 503);
+        assert_eq!(limited.rejected(), 1);
+        assert_eq!(limited.usage(), 60 * 1024);
+
+        drop(first);
+        assert_eq!(limited.usage(), 0);
+        let _second = limited.reserve(60 * 1024).unwrap();
+
+        let unlimited = budget(0);
+        let _large = unlimited.reserve(usize::MAX / 2).unwrap();
+        assert_eq!(unlimited.usage(), usize::MAX / 2);
+    }
+}
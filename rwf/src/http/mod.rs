@@ -7,43 +7,95 @@
 //! Currently, only HTTP/1.1 is supported. Support for HTTP/2 is a work in progress. You can put the Rwf application behind a load balancer (like nginx) that supports
 //! HTTP/2 to take advantage of its performance enhancements.
 #![allow(dead_code)]
+pub mod accept;
 pub mod authorization;
 pub mod body;
+pub mod body_stream;
+pub mod cache_control;
+pub mod capture;
+pub mod charset;
+pub mod compression;
+pub mod concurrency;
+pub mod conditional;
+pub mod content_type;
 pub mod cookies;
+pub mod csv;
 pub mod error;
+pub mod error_hook;
 pub mod form;
 pub mod form_data;
 pub mod handler;
+#[cfg(unix)]
+pub mod handover;
 pub mod head;
 pub mod headers;
+pub mod json;
+pub mod json_stream;
+pub mod language;
+pub mod log_fields;
+pub mod memory;
+pub mod multipart;
+pub mod nonce;
 pub mod path;
+pub mod problem;
 pub mod rack;
+pub mod range;
+pub mod redact;
+pub mod rejection;
 pub mod request;
 pub mod response;
 pub mod router;
 pub mod server;
+pub mod signed_url;
+pub mod timings;
 pub mod url;
+pub mod versioning;
 pub mod websocket;
+pub mod writer;
 
 #[cfg(feature = "wsgi")]
 pub mod wsgi;
 
+#[cfg(feature = "debug-toolbar")]
+pub mod debug_toolbar;
+
+pub use accept::{MediaRange, Respond};
 pub use authorization::Authorization;
 pub use body::Body;
-pub use cookies::{Cookie, CookieBuilder, Cookies};
+pub use body_stream::BodyStream;
+pub use cache_control::CacheControl;
+pub use charset::Charset;
+pub use concurrency::{ConcurrencyLimit, ConcurrencyStats};
+pub use conditional::{IfMatch, Precondition};
+pub use content_type::ContentType;
+pub use cookies::{Cookie, CookieBuilder, Cookies, SameSite};
 pub use error::Error;
+pub use error_hook::on_error;
 pub use form::{Form, FromFormData};
 pub use form_data::FormData;
 pub use handler::Handler;
 pub use head::{Head, Method};
 pub use headers::Headers;
-pub use path::{Params, Path, Query, ToParameter};
+pub use json::JsonError;
+pub use language::LanguageRange;
+pub use log_fields::{LogFields, LogValue};
+pub use memory::{Budget, Reservation};
+pub use multipart::{Multipart, UploadedFile};
+pub use nonce::Nonce;
+pub use path::{Constraint, InvalidField, ParamValue, Params, Path, Query, ToParameter};
+pub use problem::Problem;
+pub use range::ByteRange;
+pub use rejection::{Rejection, RejectionKind};
 pub use request::Request;
 pub use response::Response;
-pub use router::Router;
-pub use server::{Server, Stream};
-pub use url::{urldecode, urlencode};
+pub use router::{RouteReport, Router, RoutesReport};
+pub use server::{HtmlRewriter, Server, Stream, HTML_REWRITER_MAX_SIZE};
+pub use signed_url::signed_url;
+pub use timings::Timings;
+pub use url::{urldecode, urlencode, Url};
+pub use versioning::{ApiVersion, VersionStrategy, Versioned};
 pub use websocket::{Message, ToMessage};
+pub use writer::ResponseWriter;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Protocol {
@@ -0,0 +1,861 @@
+//! Parse `multipart/form-data` request bodies, e.g. forms with file uploads.
+//!
+//! ```rust,ignore
+//! let multipart = request.multipart().await?;
+//!
+//! let title = multipart.field("title").unwrap_or_default();
+//! if let Some(file) = multipart.file("avatar") {
+//!     file.persist("uploads/avatar.png").await?;
+//! }
+//! ```
+//!
+//! The body is parsed while it's read. Files larger than the `multipart_disk_threshold` setting are written
+//! to a temporary file as they are received, which is deleted when the [`UploadedFile`] is dropped, unless it's persisted.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use super::{body::CHUNK_SIZE, url::percent_decode_bytes, Error, Reservation};
+
+/// Longest boundary allowed by RFC 2046.
+const MAX_BOUNDARY_LENGTH: usize = 70;
+
+/// Longest headers of a part, including the whitespace after the boundary.
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+
+/// Body of a `multipart/form-data` request, split into text fields and files.
+#[derive(Debug, Clone)]
+pub struct Multipart {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+    // Parts kept in memory.
+    _memory: Arc<Reservation>,
+}
+
+impl Multipart {
+    /// Parse a body already in memory, with the boundary from the `Content-Type` header.
+    /// All files are kept in memory.
+    ///
+    /// Anything before the first boundary (the preamble) and after the last one (the epilogue) is ignored.
+    pub fn parse(body: &[u8], boundary: &str) -> Result<Self, Error> {
+        let mut parser = Parser::new(boundary)?;
+        let mut builder = Builder::default();
+        parser.push(body);
+
+        while let Some(event) = parser.next(true)? {
+            match event {
+                Event::Part(part) => builder.start(part),
+                Event::Data(data) => builder.memory(&data),
+                Event::End => builder.finish_memory()?,
+                Event::Finished => break,
+            }
+        }
+
+        builder.build()
+    }
+
+    /// Parse the body while it's read, e.g. from [`Request::body_stream`](super::Request::body_stream).
+    /// Files larger than `disk_threshold` bytes are written to temporary files as they are received,
+    /// instead of being kept in memory.
+    pub async fn read(
+        mut body: impl AsyncRead + Unpin,
+        boundary: &str,
+        disk_threshold: usize,
+    ) -> Result<Self, Error> {
+        let mut parser = Parser::new(boundary)?;
+        let mut builder = Builder::default();
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        let mut eof = false;
+
+        loop {
+            match parser.next(eof)? {
+                Some(Event::Part(part)) => builder.start(part),
+                Some(Event::Data(data)) => builder.write(&data, disk_threshold).await?,
+                Some(Event::End) => builder.finish().await?,
+                Some(Event::Finished) => break,
+                None => {
+                    let n = body.read(&mut chunk).await?;
+                    eof = n == 0;
+                    parser.push(&chunk[..n]);
+                }
+            }
+        }
+
+        builder.build()
+    }
+
+    /// Value of a text field. If the field was submitted several times, the first value is returned.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All text fields, in the order they were submitted.
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Uploaded file. If several files were submitted with the same name, the first one is returned.
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    /// All uploaded files, in the order they were submitted, e.g. from an `<input type="file" multiple>`.
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    pub(crate) fn into_fields(self) -> Vec<(String, String)> {
+        self.fields
+    }
+}
+
+/// Headers of a part.
+#[derive(Debug)]
+struct PartHeaders {
+    disposition: ContentDisposition,
+    content_type: Option<String>,
+}
+
+#[derive(Debug)]
+enum Event {
+    /// Headers of the next part. Its data follows.
+    Part(PartHeaders),
+    /// Data of the current part. Parts are split into several events if they aren't received at once.
+    Data(Vec<u8>),
+    /// End of the current part.
+    End,
+    /// The closing boundary. What follows is ignored.
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Start,
+    Preamble,
+    Boundary,
+    Headers,
+    Data,
+    Finished,
+}
+
+/// Splits a body into parts as it's received, without looking ahead further than
+/// the headers of a part or the length of the boundary.
+#[derive(Debug)]
+struct Parser {
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: State,
+}
+
+impl Parser {
+    fn new(boundary: &str) -> Result<Self, Error> {
+        if boundary.is_empty() || boundary.len() > MAX_BOUNDARY_LENGTH {
+            return Err(Error::MalformedRequest("multipart boundary is invalid"));
+        }
+
+        Ok(Self {
+            delimiter: [b"\r\n--", boundary.as_bytes()].concat(),
+            buffer: vec![],
+            state: State::Start,
+        })
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if self.state != State::Finished {
+            self.buffer.extend_from_slice(data);
+        }
+    }
+
+    /// Next event, or `None` if more of the body is needed. `eof` means the whole body was pushed.
+    fn next(&mut self, eof: bool) -> Result<Option<Event>, Error> {
+        loop {
+            match self.state {
+                // The first boundary doesn't need a line break in front of it, unless there is a preamble.
+                State::Start => {
+                    let first = &self.delimiter[2..];
+
+                    if self.buffer.starts_with(first) {
+                        self.buffer.drain(..first.len());
+                        self.state = State::Boundary;
+                    } else if self.buffer.len() >= first.len() || eof {
+                        self.state = State::Preamble;
+                    } else {
+                        return Ok(None);
+                    }
+                }
+
+                State::Preamble => match find(&self.buffer, &self.delimiter) {
+                    Some(position) => {
+                        self.buffer.drain(..position + self.delimiter.len());
+                        self.state = State::Boundary;
+                    }
+                    None if eof => {
+                        return Err(Error::MalformedRequest("multipart boundary is missing"))
+                    }
+                    None => {
+                        let keep = self.delimiter.len() - 1;
+                        let len = self.buffer.len().saturating_sub(keep);
+                        self.buffer.drain(..len);
+                        return Ok(None);
+                    }
+                },
+
+                State::Boundary => {
+                    // The last boundary is followed by "--" and the epilogue.
+                    if self.buffer.starts_with(b"--") {
+                        self.buffer.clear();
+                        self.state = State::Finished;
+                        return Ok(Some(Event::Finished));
+                    }
+
+                    // Whitespace is allowed after the boundary.
+                    let padding = self
+                        .buffer
+                        .iter()
+                        .take_while(|byte| **byte == b' ' || **byte == b'\t')
+                        .count();
+                    let rest = &self.buffer[padding..];
+
+                    if rest.starts_with(b"\r\n") {
+                        self.buffer.drain(..padding + 2);
+                        self.state = State::Headers;
+                    } else if !eof
+                        && padding < MAX_HEADERS_SIZE
+                        && (rest.is_empty() || rest == b"\r" || self.buffer == b"-")
+                    {
+                        return Ok(None);
+                    } else {
+                        return Err(Error::MalformedRequest("multipart/form-data is malformed"));
+                    }
+                }
+
+                State::Headers => {
+                    let (headers, length) = if self.buffer.starts_with(b"\r\n") {
+                        (&self.buffer[..0], 2)
+                    } else {
+                        match find(&self.buffer, b"\r\n\r\n") {
+                            Some(end) => (&self.buffer[..end], end + 4),
+                            None if eof || self.buffer.len() > MAX_HEADERS_SIZE => {
+                                return Err(Error::MalformedRequest(
+                                    "multipart/form-data is malformed",
+                                ))
+                            }
+                            None => return Ok(None),
+                        }
+                    };
+
+                    let part = Self::headers(headers)?;
+                    self.buffer.drain(..length);
+                    self.state = State::Data;
+
+                    return Ok(Some(Event::Part(part)));
+                }
+
+                State::Data => match find(&self.buffer, &self.delimiter) {
+                    Some(0) => {
+                        self.buffer.drain(..self.delimiter.len());
+                        self.state = State::Boundary;
+                        return Ok(Some(Event::End));
+                    }
+                    // The delimiter is left in the buffer, so the part ends on the next call.
+                    Some(end) => {
+                        let data = self.buffer.drain(..end).collect();
+                        return Ok(Some(Event::Data(data)));
+                    }
+                    None if eof => {
+                        return Err(Error::MalformedRequest(
+                            "multipart/form-data is missing the closing boundary",
+                        ))
+                    }
+                    None => {
+                        // The end of the buffer could be the start of the delimiter.
+                        let keep = self.delimiter.len() - 1;
+                        let len = self.buffer.len().saturating_sub(keep);
+
+                        if len == 0 {
+                            return Ok(None);
+                        }
+
+                        let data = self.buffer.drain(..len).collect();
+                        return Ok(Some(Event::Data(data)));
+                    }
+                },
+
+                State::Finished => return Ok(Some(Event::Finished)),
+            }
+        }
+    }
+
+    fn headers(headers: &[u8]) -> Result<PartHeaders, Error> {
+        let mut disposition = None;
+        let mut content_type = None;
+
+        for header in String::from_utf8_lossy(headers).split("\r\n") {
+            if let Some((name, value)) = header.split_once(':') {
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-disposition" => {
+                        disposition = Some(ContentDisposition::parse_value(value)?)
+                    }
+                    "content-type" => content_type = Some(value.trim().to_string()),
+                    _ => (),
+                }
+            }
+        }
+
+        let disposition = disposition.ok_or(Error::MalformedRequest(
+            "content-disposition header is missing",
+        ))?;
+
+        Ok(PartHeaders {
+            disposition,
+            content_type,
+        })
+    }
+}
+
+/// Collects the parts into fields and files.
+#[derive(Default)]
+struct Builder {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+    part: Option<PartHeaders>,
+    memory: Vec<u8>,
+    disk: Option<(TempFile, File)>,
+    in_memory: usize,
+}
+
+impl Builder {
+    fn start(&mut self, part: PartHeaders) {
+        self.part = Some(part);
+    }
+
+    fn memory(&mut self, data: &[u8]) {
+        self.memory.extend_from_slice(data);
+    }
+
+    /// Keep the data in memory, unless the part is a file larger than `disk_threshold`,
+    /// which is written to a temporary file as it's received.
+    async fn write(&mut self, data: &[u8], disk_threshold: usize) -> Result<(), Error> {
+        if let Some((ref mut temp, ref mut file)) = self.disk {
+            file.write_all(data).await?;
+            temp.size += data.len();
+            return Ok(());
+        }
+
+        let is_file = self
+            .part
+            .as_ref()
+            .map(|part| part.disposition.filename.is_some())
+            .unwrap_or(false);
+
+        if is_file && self.memory.len() + data.len() > disk_threshold {
+            let (mut temp, mut file) = TempFile::create().await?;
+            file.write_all(&self.memory).await?;
+            file.write_all(data).await?;
+            temp.size = self.memory.len() + data.len();
+            self.memory = vec![];
+            self.disk = Some((temp, file));
+        } else {
+            self.memory(data);
+        }
+
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), Error> {
+        match self.disk.take() {
+            Some((temp, mut file)) => {
+                file.flush().await?;
+                self.add(FileData::Disk(Arc::new(temp)))
+            }
+            None => self.finish_memory(),
+        }
+    }
+
+    fn finish_memory(&mut self) -> Result<(), Error> {
+        let data = std::mem::take(&mut self.memory);
+        self.in_memory += data.len();
+        self.add(FileData::Memory(data))
+    }
+
+    fn add(&mut self, data: FileData) -> Result<(), Error> {
+        let Some(part) = self.part.take() else {
+            return Ok(());
+        };
+
+        match (part.disposition.filename, data) {
+            (None, FileData::Memory(data)) => {
+                self.fields
+                    .push((part.disposition.name, String::from_utf8(data)?));
+            }
+            (None, FileData::Disk(_)) => unreachable!("only files are written to disk"),
+            (Some(filename), data) => self.files.push(UploadedFile {
+                name: part.disposition.name,
+                filename,
+                content_type: part.content_type,
+                size: data.len(),
+                data,
+            }),
+        }
+
+        Ok(())
+    }
+
+    fn build(self) -> Result<Multipart, Error> {
+        Ok(Multipart {
+            _memory: Arc::new(Reservation::new(self.in_memory)?),
+            fields: self.fields,
+            files: self.files,
+        })
+    }
+}
+
+/// A file uploaded with a `multipart/form-data` form.
+#[derive(Debug, Clone)]
+pub struct UploadedFile {
+    name: String,
+    filename: String,
+    content_type: Option<String>,
+    size: usize,
+    data: FileData,
+}
+
+impl UploadedFile {
+    /// Name of the form field.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// File name provided by the browser. It's chosen by the client, so don't use it as a path.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Content type of the file, if provided by the browser. If not set,
+    /// `application/octet-stream` is used, which is the catch-all for an unknown encoding.
+    pub fn content_type(&self) -> &str {
+        self.content_type
+            .as_deref()
+            .unwrap_or("application/octet-stream")
+    }
+
+    /// Size of the file, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// File contents, if the file is small enough to be kept in memory.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self.data {
+            FileData::Memory(ref data) => Some(data),
+            FileData::Disk(_) => None,
+        }
+    }
+
+    /// Path of the temporary file, if the file was too large to be kept in memory.
+    pub fn path(&self) -> Option<&Path> {
+        match self.data {
+            FileData::Memory(_) => None,
+            FileData::Disk(ref file) => Some(&file.path),
+        }
+    }
+
+    /// Read the file contents, from memory or from the temporary file.
+    pub async fn read(&self) -> Result<Vec<u8>, Error> {
+        match self.data {
+            FileData::Memory(ref data) => Ok(data.clone()),
+            FileData::Disk(ref file) => Ok(tokio::fs::read(&file.path).await?),
+        }
+    }
+
+    /// Save the file, e.g. to the uploads directory.
+    pub async fn persist(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        match self.data {
+            FileData::Memory(ref data) => tokio::fs::write(path, data).await?,
+            FileData::Disk(ref file) => {
+                tokio::fs::copy(&file.path, path).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum FileData {
+    Memory(Vec<u8>),
+    // Shared by clones, and deleted when the last one is dropped.
+    Disk(Arc<TempFile>),
+}
+
+impl FileData {
+    fn len(&self) -> usize {
+        match self {
+            Self::Memory(data) => data.len(),
+            Self::Disk(file) => file.size,
+        }
+    }
+}
+
+/// File in the temporary directory, deleted when dropped.
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+    size: usize,
+}
+
+impl TempFile {
+    /// Create an empty temporary file, which is then written to with the returned handle.
+    async fn create() -> Result<(Self, File), Error> {
+        let path = std::env::temp_dir().join(format!("rwf-upload-{}", Uuid::new_v4()));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+
+        Ok((Self { path, size: 0 }, file))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// HTTP `Content-Disposition` header.
+#[derive(Debug, Clone)]
+pub struct ContentDisposition {
+    /// The name of the input.
+    pub name: String,
+    /// File name of the input, if it's a file upload.
+    pub filename: Option<String>,
+}
+
+impl ContentDisposition {
+    /// Parse the Content-Disposition header, e.g. `Content-Disposition: form-data; name="file"`.
+    pub fn parse(header: &str) -> Result<ContentDisposition, Error> {
+        match header.split_once(':') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("content-disposition") => {
+                Self::parse_value(value)
+            }
+            _ => Err(Error::MalformedRequest(
+                "content-disposition header is missing",
+            )),
+        }
+    }
+
+    fn parse_value(value: &str) -> Result<ContentDisposition, Error> {
+        let mut name = None;
+        let mut filename = None;
+        let mut extended_filename = None;
+
+        for param in params(value).into_iter().skip(1) {
+            let Some((key, value)) = param.split_once('=') else {
+                continue;
+            };
+
+            match key.trim().to_ascii_lowercase().as_str() {
+                "name" => name = Some(unquote(value)),
+                "filename" => filename = Some(unquote(value)),
+                // RFC 5987, e.g. `filename*=UTF-8''caf%C3%A9.txt`.
+                "filename*" => {
+                    extended_filename = value
+                        .trim()
+                        .split_once("''")
+                        .filter(|(charset, _)| charset.eq_ignore_ascii_case("utf-8"))
+                        .and_then(|(_, encoded)| {
+                            String::from_utf8(percent_decode_bytes(encoded.as_bytes(), false)).ok()
+                        })
+                }
+                _ => (),
+            }
+        }
+
+        match name {
+            Some(name) => Ok(ContentDisposition {
+                name,
+                filename: extended_filename.or(filename),
+            }),
+            None => Err(Error::MalformedRequest("multipart/form-data is malformed")),
+        }
+    }
+}
+
+/// Split header parameters on `;`, except inside quoted strings.
+fn params(value: &str) -> Vec<&str> {
+    let mut params = vec![];
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    params.push(&value[start..]);
+    params
+}
+
+/// Remove quotes from a parameter value. Browsers encode quotes and line breaks in names
+/// as `%22`, `%0D` and `%0A`, and other clients escape them with a backslash.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(quoted) => {
+            let mut unquoted = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unquoted.extend(chars.next()),
+                    c => unquoted.push(c),
+                }
+            }
+
+            unquoted
+                .replace("%22", "\"")
+                .replace("%0D", "\r")
+                .replace("%0A", "\n")
+        }
+        None => value.to_string(),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    fn body(lines: &[&str]) -> Vec<u8> {
+        lines.join("\r\n").into_bytes()
+    }
+
+    /// Body received a few bytes at a time.
+    struct Trickle<'a> {
+        body: &'a [u8],
+        size: usize,
+    }
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let len = self.size.min(self.body.len()).min(buf.remaining());
+            buf.put_slice(&self.body[..len]);
+            self.body = &self.body[len..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Parse the body in memory, and while it's received in chunks of different sizes.
+    async fn parse(body: &[u8], boundary: &str) -> Result<Multipart, Error> {
+        let parsed = Multipart::parse(body, boundary);
+
+        for size in [1, 2, 3, 7, 64] {
+            let read = Multipart::read(Trickle { body, size }, boundary, usize::MAX).await;
+
+            match (&parsed, read) {
+                (Ok(parsed), Ok(read)) => {
+                    assert_eq!(parsed.fields(), read.fields());
+                    assert_eq!(parsed.files().len(), read.files().len());
+                    for (a, b) in parsed.files().iter().zip(read.files()) {
+                        assert_eq!(a.name(), b.name());
+                        assert_eq!(a.bytes(), b.bytes());
+                    }
+                }
+                (Err(_), Err(_)) => (),
+                (parsed, read) => panic!("chunks of {}: {:?} != {:?}", size, parsed, read),
+            }
+        }
+
+        parsed
+    }
+
+    #[tokio::test]
+    async fn test_parse() {
+        let body = body(&[
+            "--boundary",
+            r#"Content-Disposition: form-data; name="title""#,
+            "",
+            "Hello",
+            "--boundary",
+            r#"content-disposition: form-data; name="file"; filename="a;b \"c\".bin""#,
+            "Content-Type: application/octet-stream",
+            "",
+            // Line breaks and boundary-like text inside the file are kept.
+            "line\r\n-boundary\r\n\r\n",
+            "--boundary",
+            r#"Content-Disposition: form-data; name="empty"; filename="""#,
+            "",
+            "",
+            "--boundary--",
+            "",
+        ]);
+
+        let multipart = parse(&body, "boundary").await.unwrap();
+        assert_eq!(multipart.field("title"), Some("Hello"));
+        assert_eq!(multipart.fields().len(), 1);
+
+        let file = multipart.file("file").unwrap();
+        assert_eq!(file.filename(), r#"a;b "c".bin"#);
+        assert_eq!(file.content_type(), "application/octet-stream");
+        assert_eq!(file.bytes().unwrap(), b"line\r\n-boundary\r\n\r\n");
+        assert_eq!(file.size(), 19);
+
+        let empty = multipart.file("empty").unwrap();
+        assert_eq!(empty.filename(), "");
+        assert_eq!(empty.size(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_preamble_and_epilogue() {
+        let body = body(&[
+            "This is the preamble.",
+            "--boundary",
+            r#"Content-Disposition: form-data; name="a""#,
+            "",
+            "1",
+            "--boundary--",
+            "This is the epilogue.",
+            "--boundary",
+            r#"Content-Disposition: form-data; name="b""#,
+            "",
+            "2",
+        ]);
+
+        let multipart = parse(&body, "boundary").await.unwrap();
+        assert_eq!(multipart.fields(), &[("a".to_string(), "1".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_final_crlf() {
+        // No line break after the closing boundary, and whitespace after a boundary.
+        let body = body(&[
+            "--boundary \t",
+            r#"Content-Disposition: form-data; name="a""#,
+            "",
+            "1",
+            "--boundary--",
+        ]);
+
+        let multipart = parse(&body, "boundary").await.unwrap();
+        assert_eq!(multipart.field("a"), Some("1"));
+
+        // Nothing but the closing boundary.
+        let multipart = parse(b"--boundary--", "boundary").await.unwrap();
+        assert!(multipart.fields().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_malformed() {
+        for (body, boundary) in [
+            // Truncated before the closing boundary.
+            (
+                body(&[
+                    "--boundary",
+                    r#"Content-Disposition: form-data; name="a""#,
+                    "",
+                    "1",
+                ]),
+                "boundary",
+            ),
+            // No boundary at all.
+            (b"hello".to_vec(), "boundary"),
+            // No name.
+            (
+                body(&[
+                    "--boundary",
+                    "Content-Disposition: form-data",
+                    "",
+                    "1",
+                    "--boundary--",
+                ]),
+                "boundary",
+            ),
+            // No Content-Disposition.
+            (body(&["--boundary", "", "1", "--boundary--"]), "boundary"),
+            // Garbage after the boundary.
+            (body(&["--boundaryx", "--boundary--"]), "boundary"),
+            (b"----".to_vec(), ""),
+            (b"--a--".to_vec(), &"a".repeat(71)),
+        ] {
+            assert!(
+                parse(&body, boundary).await.is_err(),
+                "{}",
+                String::from_utf8_lossy(&body)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disk() {
+        let data = vec![7u8; 2048];
+        let mut body = body(&[
+            "--boundary",
+            r#"Content-Disposition: form-data; name="large"; filename*=UTF-8''caf%C3%A9.bin"#,
+            "",
+            "",
+        ]);
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+        // The file is written to disk as it's received.
+        let multipart = Multipart::read(
+            Trickle {
+                body: &body,
+                size: 100,
+            },
+            "boundary",
+            1024,
+        )
+        .await
+        .unwrap();
+        let file = multipart.file("large").unwrap();
+        assert_eq!(file.filename(), "café.bin");
+        assert!(file.bytes().is_none());
+        assert_eq!(file.size(), 2048);
+        assert_eq!(file.read().await.unwrap(), data);
+
+        let path = file.path().unwrap().to_path_buf();
+        assert!(path.exists());
+
+        // Clones share the file.
+        let copy = multipart.clone();
+        drop(multipart);
+        assert!(path.exists());
+        drop(copy);
+        assert!(!path.exists());
+    }
+}
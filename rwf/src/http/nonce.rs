@@ -0,0 +1,84 @@
+//! Per-request nonce used in the `Content-Security-Policy` header.
+//!
+//! Each request gets its own [`Nonce`], available with [`crate::http::Request::nonce`]. The nonce is empty until it's generated
+//! by the [`ContentSecurityPolicy`](crate::controller::middleware::ContentSecurityPolicy) middleware, so requests served without
+//! a policy don't pay for it. Templates read the nonce of the request being rendered with `csp_nonce()`:
+//!
+//! ```html
+//! <script nonce="<%= csp_nonce() %>">
+//!   console.log("allowed by the policy");
+//! </script>
+//! ```
+use base64::{engine::general_purpose, Engine as _};
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use tokio::task_local;
+
+use std::future::Future;
+use std::sync::Arc;
+
+task_local! {
+    static CURRENT: Nonce;
+}
+
+/// Random value, unique to each request.
+///
+/// It's safe to clone since the value is behind an [`std::sync::Arc`].
+#[derive(Debug, Default, Clone)]
+pub struct Nonce {
+    inner: Arc<OnceCell<String>>,
+}
+
+impl Nonce {
+    /// Create new empty nonce.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the nonce, generating it if it wasn't generated yet.
+    pub fn generate(&self) -> &str {
+        self.inner.get_or_init(|| {
+            let bytes = rand::thread_rng().gen::<[u8; 16]>();
+            general_purpose::STANDARD.encode(bytes)
+        })
+    }
+
+    /// Get the nonce, if it was generated.
+    pub fn get(&self) -> Option<&str> {
+        self.inner.get().map(|nonce| nonce.as_str())
+    }
+
+    /// Nonce of the request currently being handled by this task, if it was generated.
+    pub fn current() -> Option<String> {
+        CURRENT
+            .try_with(|nonce| nonce.get().map(|nonce| nonce.to_string()))
+            .ok()
+            .flatten()
+    }
+
+    /// Run the future with this nonce set as [`Nonce::current`].
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_nonce() {
+        let nonce = Nonce::new();
+        assert!(nonce.get().is_none());
+
+        let value = nonce.generate().to_string();
+        assert_eq!(general_purpose::STANDARD.decode(&value).unwrap().len(), 16);
+        assert_eq!(nonce.generate(), value);
+        assert_ne!(Nonce::new().generate(), value);
+
+        let shared = nonce.clone();
+        let current = nonce.scope(async { Nonce::current() }).await;
+        assert_eq!(current.as_deref(), shared.get());
+        assert!(Nonce::current().is_none());
+    }
+}
@@ -0,0 +1,202 @@
+//! Constraints on path parameters, checked when a request is routed.
+//!
+//! A parameter can be followed by a type, e.g. `/users/:id<i64>`, or a regular expression,
+//! e.g. `/posts/:slug([a-z0-9-]+)`. Requests with a value that doesn't satisfy the constraint
+//! don't match the route, so they are routed to the next matching route, or get `404 - Not Found`.
+//!
+//! Regular expressions must match the whole value. They use ASCII semantics, so `\d` only matches
+//! `0` to `9` and `(?i)` only folds ASCII letters, no matter which characters the client sends.
+use regex::bytes::{Regex, RegexBuilder};
+use uuid::Uuid;
+
+use super::Error;
+
+/// Longest regular expression allowed in a constraint.
+pub const MAX_PATTERN_LENGTH: usize = 256;
+
+/// Memory, in bytes, a compiled constraint can use, which bounds the time it takes to match a value.
+const SIZE_LIMIT: usize = 64 * 1024;
+
+/// Constraint on the value of a path parameter.
+#[derive(Debug, Clone)]
+pub enum Constraint {
+    /// `<i64>`, a signed 64-bit integer.
+    I64,
+    /// `<u64>`, an unsigned 64-bit integer.
+    U64,
+    /// `<uuid>`, a hyphenated UUID, in lowercase or uppercase.
+    Uuid,
+    /// `(regex)`, matching the whole value.
+    Regex(Regex),
+}
+
+impl Constraint {
+    /// Parse a constraint, e.g. `<i64>` or `([a-z]+)`.
+    pub fn parse(constraint: &str) -> Result<Self, Error> {
+        if let Some(kind) = constraint
+            .strip_prefix('<')
+            .and_then(|kind| kind.strip_suffix('>'))
+        {
+            return match kind.trim().to_ascii_lowercase().as_str() {
+                "i64" => Ok(Self::I64),
+                "u64" => Ok(Self::U64),
+                "uuid" => Ok(Self::Uuid),
+                kind => Err(Error::InvalidRoute(format!(
+                    "unknown parameter type \"{}\"",
+                    kind
+                ))),
+            };
+        }
+
+        let pattern = constraint
+            .strip_prefix('(')
+            .and_then(|pattern| pattern.strip_suffix(')'))
+            .ok_or_else(|| Error::InvalidRoute(format!("invalid constraint \"{}\"", constraint)))?;
+
+        if pattern.len() > MAX_PATTERN_LENGTH {
+            return Err(Error::InvalidRoute(format!(
+                "constraint is longer than {} characters",
+                MAX_PATTERN_LENGTH
+            )));
+        }
+
+        let regex = RegexBuilder::new(&format!("^(?:{})$", pattern))
+            .unicode(false)
+            .size_limit(SIZE_LIMIT)
+            .dfa_size_limit(SIZE_LIMIT)
+            .nest_limit(16)
+            .build()?;
+
+        Ok(Self::Regex(regex))
+    }
+
+    /// Parse the value, if it satisfies the constraint.
+    pub fn check(&self, value: &str) -> Option<ParamValue> {
+        match self {
+            Self::I64 => value.parse().ok().map(ParamValue::I64),
+            Self::U64 => value.parse().ok().map(ParamValue::U64),
+            // Only the hyphenated form, so each UUID has one URL, apart from its case.
+            Self::Uuid if value.len() == 36 => Uuid::try_parse(value).ok().map(ParamValue::Uuid),
+            Self::Uuid => None,
+            Self::Regex(regex) => regex
+                .is_match(value.as_bytes())
+                .then(|| ParamValue::String(value.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::I64 => write!(f, "i64"),
+            Self::U64 => write!(f, "u64"),
+            Self::Uuid => write!(f, "uuid"),
+            Self::Regex(regex) => {
+                let pattern = regex.as_str();
+                let pattern = pattern
+                    .strip_prefix("^(?:")
+                    .and_then(|pattern| pattern.strip_suffix(")$"))
+                    .unwrap_or(pattern);
+                write!(f, "({})", pattern)
+            }
+        }
+    }
+}
+
+/// Value of a path parameter, parsed according to its constraint.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+    I64(i64),
+    U64(u64),
+    Uuid(Uuid),
+    /// Parameters without a type.
+    String(String),
+}
+
+/// Split a parameter segment, e.g. `:id<i64>`, into its name and constraint.
+pub(crate) fn split(segment: &str) -> (&str, Option<&str>) {
+    match segment.find(['<', '(']) {
+        Some(start) => (&segment[..start], Some(&segment[start..])),
+        None => (segment, None),
+    }
+}
+
+/// Split a route pattern into its segments, e.g. `/posts/:slug([a-z/]+)` into `["", "posts", ":slug([a-z/]+)"]`.
+/// Slashes inside a regular expression don't separate segments.
+pub(crate) fn segments(pattern: &str) -> Vec<&str> {
+    let mut segments = vec![];
+    let mut depth = 0usize;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in pattern.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if depth > 0 => escaped = true,
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '/' if depth == 0 => {
+                segments.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    segments.push(&pattern[start..]);
+    segments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_constraints() {
+        let int = Constraint::parse("<i64>").unwrap();
+        assert_eq!(int.check("-5"), Some(ParamValue::I64(-5)));
+        assert_eq!(int.check("5a"), None);
+        assert_eq!(int.check("99999999999999999999"), None);
+        // Digits from other scripts aren't numbers in a URL.
+        assert_eq!(int.check("٥"), None);
+
+        let uuid = Constraint::parse("<UUID>").unwrap();
+        let id = Uuid::new_v4();
+        assert_eq!(
+            uuid.check(&id.to_string().to_uppercase()),
+            Some(ParamValue::Uuid(id))
+        );
+        assert_eq!(uuid.check(&id.simple().to_string()), None);
+        assert_eq!(uuid.check("5"), None);
+
+        let slug = Constraint::parse(r"([a-z0-9-]+)").unwrap();
+        assert_eq!(slug.to_string(), "([a-z0-9-]+)");
+        assert!(slug.check("hello-world").is_some());
+        assert!(slug.check("hello world").is_none());
+        // Anchored, so the whole value must match.
+        assert!(Constraint::parse(r"(\d+)").unwrap().check("12a").is_none());
+        // ASCII only.
+        assert!(Constraint::parse(r"(\d+)").unwrap().check("١٢").is_none());
+        let kelvin = Constraint::parse(r"((?i)k)").unwrap();
+        assert!(kelvin.check("K").is_some());
+        assert!(kelvin.check("\u{212A}").is_none());
+        // Alternatives stay inside the anchors.
+        let either = Constraint::parse("(a|b)").unwrap();
+        assert!(either.check("ab").is_none());
+
+        assert_eq!(
+            segments(r"/posts/:slug([a-z\)/]+)/edit"),
+            vec!["", "posts", r":slug([a-z\)/]+)", "edit"]
+        );
+
+        for invalid in [
+            "<f32>",
+            "([a-z]",
+            "(a{1000}{1000})",
+            "{}",
+            &format!("({})", "a".repeat(300)),
+        ] {
+            assert!(Constraint::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -7,6 +7,9 @@ use super::Error;
 use std::fmt::Debug;
 use std::path::PathBuf;
 
+pub mod constraint;
+pub use constraint::{Constraint, ParamValue};
+
 pub mod with_regex;
 pub use with_regex::{PathType, PathWithRegex};
 
@@ -17,7 +20,7 @@ pub mod params;
 pub use params::Params;
 
 pub mod query;
-pub use query::Query;
+pub use query::{InvalidField, Query};
 
 /// HTTP URL path.
 #[derive(Clone, Debug)]
@@ -92,6 +95,21 @@ impl Path {
         Ok(Path { base, query })
     }
 
+    /// Route pattern, e.g. `/users/:id<i64>`. Unlike [`Path::parse`], the pattern has no query,
+    /// so `?` can be used in parameter constraints.
+    pub fn pattern(pattern: &str) -> Path {
+        let base = if pattern.starts_with("/") {
+            pattern.to_string()
+        } else {
+            "/".to_string() + pattern
+        };
+
+        Path {
+            base,
+            query: Query::new(),
+        }
+    }
+
     pub fn to_std(&self) -> PathBuf {
         std::path::Path::new(&self.base).to_owned()
     }
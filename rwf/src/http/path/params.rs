@@ -1,11 +1,14 @@
 use regex::Regex;
 use std::collections::HashMap;
 
+use super::{Constraint, ParamValue};
+
 /// Handle URL parameters, e.g. `/api/orders/:id/create`.
 #[derive(Debug)]
 pub struct Params {
     params: HashMap<String, usize>,
     regex: Regex,
+    constraints: Vec<(String, Constraint)>,
 }
 
 impl Params {
@@ -15,7 +18,22 @@ impl Params {
     /// and the offsets for the captures in the regex where the parameters
     /// are expected to be.
     pub fn new(regex: Regex, params: HashMap<String, usize>) -> Self {
-        Self { params, regex }
+        Self {
+            params,
+            regex,
+            constraints: vec![],
+        }
+    }
+
+    /// Check these parameters against their constraints, e.g. `("id", Constraint::I64)`.
+    pub fn with_constraints(mut self, constraints: Vec<(String, Constraint)>) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    /// Constraints on the parameters, in the order they appear in the path.
+    pub fn constraints(&self) -> &[(String, Constraint)] {
+        &self.constraints
     }
 
     /// Extract a parameter from the URL.
@@ -39,6 +57,35 @@ impl Params {
         None
     }
 
+    /// Extract a parameter from the URL, parsed according to its constraint,
+    /// e.g. [`ParamValue::I64`] for `:id<i64>`. Parameters without a type are strings.
+    pub fn value(&self, base: &str, name: &str) -> Option<ParamValue> {
+        let value = self.parameter(base, name)?;
+
+        match self.constraints.iter().find(|(param, _)| param == name) {
+            Some((_, constraint)) => constraint.check(value),
+            None => Some(ParamValue::String(value.to_string())),
+        }
+    }
+
+    /// Does the URL match the regex, with all parameters satisfying their constraints?
+    pub fn matches(&self, base: &str) -> bool {
+        if self.constraints.is_empty() {
+            return self.regex.is_match(base);
+        }
+
+        match self.regex.captures(base) {
+            Some(captures) => self.constraints.iter().all(|(name, constraint)| {
+                self.params
+                    .get(name)
+                    .and_then(|index| captures.get(*index))
+                    .map(|value| constraint.check(value.as_str()).is_some())
+                    .unwrap_or(false)
+            }),
+            None => false,
+        }
+    }
+
     pub fn regex(&self) -> &Regex {
         &self.regex
     }
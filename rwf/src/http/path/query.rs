@@ -1,51 +1,123 @@
+//! URL query, e.g. `?page=2&tag=rust&tag=web`.
+//!
+//! Names and values are percent-decoded once, with `+` decoded as a space. If a name is repeated,
+//! [`Query::get`] returns the last value, and all values are available from [`Query::multimap`].
 use std::collections::{hash_map::IntoIter, HashMap};
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 
-use crate::http::urldecode;
+use serde::de::{
+    self,
+    value::{SeqDeserializer, StrDeserializer},
+    DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Unexpected, Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+use crate::http::url::percent_decode;
 use crate::http::Error;
 
 #[derive(Debug, Clone)]
 pub struct Query {
     query: HashMap<String, String>,
+    // All pairs, in order, including repeated names. Cleared if the query is changed.
+    pairs: Option<Vec<(String, String)>>,
 }
 
 impl Query {
     pub fn new() -> Self {
         Self {
             query: HashMap::new(),
+            pairs: None,
         }
     }
 
     pub fn parse(data: &str) -> Self {
         let mut query = Self::new();
+        let mut pairs = vec![];
 
         // Remove the anchor if any.
         let without_anchor = data.split("#").next().expect("path anchor");
-        let query_parts = without_anchor.split("&");
-        for part in query_parts {
-            let key_value = part.split("=").collect::<Vec<_>>();
 
-            if key_value.len() > 2 {
-                continue;
-            }
+        for part in without_anchor.split("&").filter(|part| !part.is_empty()) {
+            // Values can contain "=", e.g. base64.
+            let (key, value) = part.split_once('=').unwrap_or((part, ""));
 
             // Decode any URL-encoded values back into UTF-8.
-            let key = urldecode(&key_value.first().expect("path query key"));
-            let value = urldecode(&key_value.last().unwrap_or(&"")); // ?key=&value=two
+            let key = percent_decode(key, true);
+            let value = percent_decode(value, true);
 
-            query.insert(key, value);
+            query.query.insert(key.clone(), value.clone());
+            pairs.push((key, value));
         }
 
+        query.pairs = Some(pairs);
+        query
+    }
+
+    /// Query from names and values already decoded, e.g. from a form body. Repeated names are kept.
+    pub(crate) fn from_pairs(pairs: Vec<(String, String)>) -> Self {
+        let mut query = Self::new();
+        query.query = pairs.iter().cloned().collect();
+        query.pairs = Some(pairs);
         query
     }
 
+    /// All names and values, in the order they appear in the URL, including repeated names.
+    pub fn pairs(&self) -> Vec<(&str, &str)> {
+        match self.pairs {
+            Some(ref pairs) => pairs
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+            None => self
+                .query
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+        }
+    }
+
+    /// All values of each name, e.g. `?tag=rust&tag=web` is `{"tag": ["rust", "web"]}`.
+    pub fn multimap(&self) -> HashMap<String, Vec<String>> {
+        let mut multimap: HashMap<String, Vec<String>> = HashMap::new();
+
+        for (key, value) in self.pairs() {
+            multimap
+                .entry(key.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+
+        multimap
+    }
+
+    /// Deserialize the query into a struct. Repeated names fill `Vec` fields, and other fields
+    /// get the last value. Missing or empty values are `None` for `Option` fields.
+    ///
+    /// Errors are returned as [`Error::InvalidQuery`], which is `400 - Bad Request`.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        self.deserialize_fields()
+            .map_err(|err| Error::InvalidQuery(err.to_string()))
+    }
+
+    /// Deserialize the query into a struct, like [`Query::deserialize`], returning the field which
+    /// couldn't be deserialized, if any, in the error.
+    pub fn deserialize_fields<T: DeserializeOwned>(&self) -> Result<T, InvalidField> {
+        let mut grouped: Vec<(&str, Vec<&str>)> = vec![];
+
+        for (key, value) in self.pairs() {
+            match grouped.iter_mut().find(|(name, _)| *name == key) {
+                Some((_, values)) => values.push(value),
+                None => grouped.push((key, vec![value])),
+            }
+        }
+
+        T::deserialize(QueryDeserializer { pairs: grouped })
+    }
+
     pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
         match self.query.get(name) {
-            Some(value) => match urldecode(value).parse::<T>() {
-                Ok(value) => Some(value),
-                Err(_) => None,
-            },
+            Some(value) => value.parse::<T>().ok(),
 
             None => None,
         }
@@ -90,6 +162,429 @@ impl Deref for Query {
 
 impl DerefMut for Query {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.pairs = None;
         &mut self.query
     }
 }
+
+/// A value which couldn't be deserialized, with the name of its field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidField {
+    field: Option<String>,
+    reason: String,
+}
+
+impl InvalidField {
+    /// Name of the field, if the error is about a single field.
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+
+    /// Why the value is invalid, e.g. `is required`.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    fn in_field(mut self, field: &str) -> Self {
+        if self.field.is_none() {
+            self.field = Some(field.to_string());
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for InvalidField {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.field {
+            Some(ref field) => write!(f, "\"{}\" {}", field, self.reason),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for InvalidField {}
+
+impl de::Error for InvalidField {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Self {
+            field: None,
+            reason: msg.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self {
+            field: Some(field.to_string()),
+            reason: "is required".to_string(),
+        }
+    }
+
+    fn unknown_field(field: &str, _expected: &'static [&'static str]) -> Self {
+        Self {
+            field: Some(field.to_string()),
+            reason: "is not allowed".to_string(),
+        }
+    }
+
+    fn duplicate_field(field: &'static str) -> Self {
+        Self {
+            field: Some(field.to_string()),
+            reason: "is repeated".to_string(),
+        }
+    }
+}
+
+/// Deserializes the query as a map of names to their values.
+struct QueryDeserializer<'a> {
+    pairs: Vec<(&'a str, Vec<&'a str>)>,
+}
+
+impl<'de> de::Deserializer<'de> for QueryDeserializer<'_> {
+    type Error = InvalidField;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(Fields {
+            pairs: self.pairs.into_iter(),
+            current: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Names and their values, adding the name to errors from the value.
+struct Fields<'a> {
+    pairs: std::vec::IntoIter<(&'a str, Vec<&'a str>)>,
+    current: Option<(&'a str, Vec<&'a str>)>,
+}
+
+impl<'de> MapAccess<'de> for Fields<'_> {
+    type Error = InvalidField;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.pairs.next() {
+            Some((name, values)) => {
+                self.current = Some((name, values));
+                seed.deserialize(StrDeserializer::<InvalidField>::new(name))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (name, values) = self
+            .current
+            .take()
+            .ok_or_else(|| de::Error::custom("value is missing"))?;
+
+        seed.deserialize(Values(values))
+            .map_err(|err| err.in_field(name))
+    }
+}
+
+/// All values of a name. Sequences get all of them, and other types get the last one.
+struct Values<'a>(Vec<&'a str>);
+
+impl Values<'_> {
+    fn last(&self) -> Value<'_> {
+        Value(self.0.last().copied().unwrap_or_default())
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, InvalidField> for Values<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! forward_to_last {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.last().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Values<'_> {
+    type Error = InvalidField;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.0.len() > 1 {
+            self.deserialize_seq(visitor)
+        } else {
+            self.last().deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(SeqDeserializer::new(self.0.into_iter().map(Value)))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.last().0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.last().deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_last! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 bytes byte_buf unit unit_struct tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// A single value, parsed from text.
+struct Value<'a>(&'a str);
+
+impl<'de, 'a> IntoDeserializer<'de, InvalidField> for Value<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.trim().parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::invalid_value(Unexpected::Str(self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Value<'_> {
+    type Error = InvalidField;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_str(self.0)
+    }
+
+    // `true`, `1`, `on` (what checkboxes send), `yes`, or a name without a value are true.
+    // `false`, `0`, `off` and `no` are false.
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0.to_ascii_lowercase().as_str() {
+            "" | "true" | "1" | "on" | "yes" => visitor.visit_bool(true),
+            "false" | "0" | "off" | "no" => visitor.visit_bool(false),
+            _ => Err(de::Error::invalid_value(Unexpected::Str(self.0), &visitor)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(IntoDeserializer::<InvalidField>::into_deserializer(self.0))
+    }
+
+    parse_value! {
+        deserialize_i8 => visit_i8
+        deserialize_i16 => visit_i16
+        deserialize_i32 => visit_i32
+        deserialize_i64 => visit_i64
+        deserialize_i128 => visit_i128
+        deserialize_u8 => visit_u8
+        deserialize_u16 => visit_u16
+        deserialize_u32 => visit_u32
+        deserialize_u64 => visit_u64
+        deserialize_u128 => visit_u128
+        deserialize_f32 => visit_f32
+        deserialize_f64 => visit_f64
+        deserialize_char => visit_char
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Sort {
+        Name,
+        Date,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Search {
+        q: String,
+        page: Option<i64>,
+        per_page: Option<u32>,
+        #[serde(default)]
+        tag: Vec<String>,
+        #[serde(default)]
+        archived: bool,
+        sort: Option<Sort>,
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let query = Query::parse(
+            "q=caf%C3%A9+au+lait&tag=a&page=2&tag=b%26c&archived=on&per_page=&sort=date",
+        );
+        let search = query.deserialize::<Search>().unwrap();
+
+        assert_eq!(
+            search,
+            Search {
+                q: "café au lait".into(),
+                page: Some(2),
+                per_page: None,
+                tag: vec!["a".into(), "b&c".into()],
+                archived: true,
+                sort: Some(Sort::Date),
+            }
+        );
+
+        let search = Query::parse("q=&archived=false&tag=only")
+            .deserialize::<Search>()
+            .unwrap();
+        assert_eq!(search.q, "");
+        assert_eq!(search.tag, vec!["only"]);
+        assert!(!search.archived);
+        assert_eq!(search.page, None);
+
+        // A name without a value is true.
+        assert!(
+            Query::parse("q=x&archived")
+                .deserialize::<Search>()
+                .unwrap()
+                .archived
+        );
+
+        // Repeated names get the last value, like `Query::get`.
+        let query = Query::parse("q=one&q=two");
+        assert_eq!(query.deserialize::<Search>().unwrap().q, "two");
+        assert_eq!(query.get::<String>("q").unwrap(), "two");
+
+        for invalid in [
+            "page=1",
+            "q=x&page=two",
+            "q=x&archived=maybe",
+            "q=x&sort=size",
+        ] {
+            let err = Query::parse(invalid).deserialize::<Search>().unwrap_err();
+            assert!(matches!(err, Error::InvalidQuery(_)), "{}", invalid);
+            assert_eq!(err.code(), 400);
+        }
+
+        let err = Query::parse("q=x&page=two")
+            .deserialize_fields::<Search>()
+            .unwrap_err();
+        assert_eq!(err.field(), Some("page"));
+        assert!(err.to_string().starts_with("\"page\" invalid value"));
+
+        let err = Query::parse("page=1")
+            .deserialize_fields::<Search>()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "\"q\" is required");
+    }
+
+    #[test]
+    fn test_multimap() {
+        let query = Query::parse("a=1&b=x%3Dy&a=2&c&=empty#fragment");
+
+        assert_eq!(
+            query.pairs(),
+            vec![
+                ("a", "1"),
+                ("b", "x=y"),
+                ("a", "2"),
+                ("c", ""),
+                ("", "empty")
+            ]
+        );
+
+        let multimap = query.multimap();
+        assert_eq!(multimap["a"], vec!["1", "2"]);
+        assert_eq!(multimap["b"], vec!["x=y"]);
+        assert_eq!(multimap["c"], vec![""]);
+
+        // Values with "=" are kept whole.
+        assert_eq!(
+            Query::parse("token=abc==").get::<String>("token").unwrap(),
+            "abc=="
+        );
+
+        // Changes made to the query are reflected.
+        let mut query = Query::parse("a=1&a=2");
+        query.insert("b".into(), "3".into());
+        let mut pairs = query.pairs();
+        pairs.sort();
+        assert_eq!(pairs, vec![("a", "2"), ("b", "3")]);
+    }
+}
@@ -16,6 +16,24 @@ impl ToParameter for i64 {
     }
 }
 
+impl ToParameter for u64 {
+    fn to_parameter(s: &str) -> Result<u64, Error> {
+        match s.parse() {
+            Ok(id) => Ok(id),
+            Err(_) => Err(Error::MalformedRequest("u64")),
+        }
+    }
+}
+
+impl ToParameter for uuid::Uuid {
+    fn to_parameter(s: &str) -> Result<uuid::Uuid, Error> {
+        match uuid::Uuid::try_parse(s) {
+            Ok(id) => Ok(id),
+            Err(_) => Err(Error::MalformedRequest("uuid")),
+        }
+    }
+}
+
 impl ToParameter for String {
     fn to_parameter(s: &str) -> Result<String, Error> {
         Ok(s.to_string())
@@ -3,9 +3,13 @@
 //! 1. Route requests to a controller
 //! 2. Extract parameters from the URL
 //!
-//! Parameters are denoted by the column-name notation, e.g. `:param1`.
+//! Parameters are denoted by the column-name notation, e.g. `:param1`, and can be followed
+//! by a [constraint](super::constraint), e.g. `:id<i64>`.
 
-use super::{Error, Params, Path};
+use super::{
+    constraint::{self, Constraint},
+    Error, Params, Path,
+};
 use regex::Regex;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -16,9 +20,10 @@ use std::sync::Arc;
 pub struct PathWithRegex {
     path: Path,
     params: Arc<Params>,
+    unconstrained: String,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum PathType {
     Rest,
     Wildcard,
@@ -29,19 +34,32 @@ impl PathWithRegex {
     /// Create the path-specified regex.
     pub(crate) fn new(path: Path, path_type: PathType) -> Result<Self, Error> {
         let mut params = HashMap::new();
+        let mut constraints = vec![];
         // Parameter regex groups start at 1 since the first group
         // is the path base URL.
         let mut i = 1;
-        let mut iter = path.base().split("/");
         let mut regex = Vec::new();
-        while let Some(part) = iter.next() {
-            let re = if part.starts_with(":") {
+        let mut unconstrained = Vec::new();
+        for part in constraint::segments(path.base()) {
+            let re = if let Some(param) = part.strip_prefix(":") {
+                let (name, constraint) = constraint::split(param);
+                unconstrained.push(&part[..name.len() + 1]);
+
                 // Parameter name and group number.
-                params.insert(part[1..].to_owned(), i);
+                params.insert(name.to_owned(), i);
                 i += 1;
-                "([a-zA-Z0-9_-]+)"
+
+                match constraint {
+                    // Constraints are checked on the whole segment once the route matches.
+                    Some(constraint) => {
+                        constraints.push((name.to_owned(), Constraint::parse(constraint)?));
+                        "([^/]+)"
+                    }
+                    None => "([a-zA-Z0-9_-]+)",
+                }
             } else {
                 // Match the URL part as-is.
+                unconstrained.push(part);
                 part
             };
             regex.push(re);
@@ -73,10 +91,12 @@ impl PathWithRegex {
         }
 
         let regex = Regex::new(&regex)?;
+        let unconstrained = unconstrained.join("/");
 
         Ok(Self {
             path,
-            params: Arc::new(Params::new(regex, params)),
+            params: Arc::new(Params::new(regex, params).with_constraints(constraints)),
+            unconstrained,
         })
     }
 
@@ -97,6 +117,21 @@ impl PathWithRegex {
         self.params.clone()
     }
 
+    /// Does the path match this route, including the constraints on its parameters?
+    pub fn matches(&self, base: &str) -> bool {
+        self.params.matches(base)
+    }
+
+    /// Do any parameters have constraints?
+    pub fn constrained(&self) -> bool {
+        !self.params.constraints().is_empty()
+    }
+
+    /// The path without the constraints on its parameters, e.g. `/users/:id` for `/users/:id<i64>`.
+    pub fn unconstrained(&self) -> &str {
+        &self.unconstrained
+    }
+
     /// Get the regex used to route to this path.
     pub fn regex(&self) -> &Regex {
         self.params.regex()
@@ -0,0 +1,235 @@
+//! Machine-readable error responses, as defined by [RFC 9457](https://www.rfc-editor.org/rfc/rfc9457).
+//!
+//! A problem is a JSON object describing the error, sent with the `application/problem+json` content type:
+//!
+//! ```
+//! # use rwf::http::Response;
+//! # use serde_json::json;
+//! let response: Response = Response::problem(422, "Invalid order")
+//!     .detail("The order is missing a shipping address.")
+//!     .instance("/orders/5")
+//!     .extension("errors", json!({"address": ["is required"]}))
+//!     .into();
+//!
+//! assert_eq!(response.status().code(), 422);
+//! ```
+//!
+//! Errors returned by controllers are sent as problems to clients that accept JSON, if the `problem_json`
+//! setting is enabled. Otherwise, they get an HTML error page.
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::{Body, Error, Request, Response};
+use crate::config::get_config;
+use crate::view::FormErrors;
+
+/// Content type of problem responses.
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+/// Problem details of an error response.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    type_uri: String,
+    title: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    #[serde(flatten)]
+    extensions: Map<String, Value>,
+}
+
+impl Problem {
+    /// Create a problem with an HTTP status code and a short summary of the problem.
+    pub fn new(status: u16, title: impl ToString) -> Self {
+        Self {
+            type_uri: "about:blank".to_string(),
+            title: title.to_string(),
+            status,
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    /// Explanation specific to this occurrence of the problem.
+    pub fn detail(mut self, detail: impl ToString) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// URI identifying the problem type. Defaults to `about:blank`.
+    pub fn type_uri(mut self, type_uri: impl ToString) -> Self {
+        self.type_uri = type_uri.to_string();
+        self
+    }
+
+    /// URI identifying this occurrence of the problem, e.g. the request path.
+    pub fn instance(mut self, instance: impl ToString) -> Self {
+        self.instance = Some(instance.to_string());
+        self
+    }
+
+    /// Add a member to the problem, e.g. validation errors for each field.
+    ///
+    /// Members defined by the RFC (`type`, `title`, `status`, `detail`, `instance`) can't be overridden.
+    pub fn extension(mut self, name: &str, value: impl Serialize) -> Self {
+        if !["type", "title", "status", "detail", "instance"].contains(&name) {
+            let value = serde_json::to_value(value).unwrap_or(Value::Null);
+            self.extensions.insert(name.to_string(), value);
+        }
+        self
+    }
+
+    /// HTTP status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Get an extension member.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.extensions.get(name)
+    }
+
+    /// Should error responses to this request be sent as problems?
+    ///
+    /// True if the `problem_json` setting is enabled and the client accepts JSON.
+    pub fn accepted(request: &Request) -> bool {
+        if !get_config().general.problem_json {
+            return false;
+        }
+
+        match request.head().header("accept") {
+            Some(accept) => accept.split(',').any(|media_type| {
+                let media_type = media_type.split(';').next().unwrap_or("").trim();
+                media_type.eq_ignore_ascii_case("application/json")
+                    || media_type.eq_ignore_ascii_case(CONTENT_TYPE)
+            }),
+            None => false,
+        }
+    }
+
+    /// Create a response with the problem as the body.
+    pub fn response(self) -> Response {
+        let code = self.status;
+        // Serializing a struct of strings and JSON values can't fail.
+        let body = serde_json::to_vec(&self).unwrap_or_default();
+
+        Response::new()
+            .body(Body::Json(body))
+            .header("content-type", CONTENT_TYPE)
+            .code(code)
+    }
+}
+
+impl From<Problem> for Response {
+    fn from(problem: Problem) -> Response {
+        problem.response()
+    }
+}
+
+impl From<&Error> for Problem {
+    fn from(error: &Error) -> Self {
+        let status = error.code();
+        let problem = Problem::new(status, title(status));
+
+        match error {
+            // Details of server errors are shown in development only.
+            #[cfg(not(debug_assertions))]
+            _ if status >= 500 => problem,
+            Error::MissingParameter | Error::Forbidden => problem,
+            // Which field is wrong, in the same format as validation errors.
+            Error::InvalidJson(err) if !err.is_syntax() => {
+                let mut errors = FormErrors::new();
+                errors.add(err.path(), err.message());
+                problem.detail(error).extension("errors", errors)
+            }
+            error => problem.detail(error),
+        }
+    }
+}
+
+/// Title of common HTTP error codes.
+pub(crate) fn title(status: u16) -> &'static str {
+    match status {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Content",
+        429 => "Too Many Requests",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_problem_schema() {
+        let problem = Response::problem(422, "Invalid order")
+            .type_uri("https://example.com/problems/invalid-order")
+            .detail("Order is missing an address.")
+            .instance("/orders/5")
+            .extension("errors", json!({"address": ["is required"]}))
+            .extension("status", 200);
+        let response = problem.response();
+
+        assert_eq!(response.status().code(), 422);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/problem+json"
+        );
+
+        let body: Value = serde_json::from_slice(response.body_bytes().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "type": "https://example.com/problems/invalid-order",
+                "title": "Invalid order",
+                "status": 422,
+                "detail": "Order is missing an address.",
+                "instance": "/orders/5",
+                "errors": {"address": ["is required"]},
+            })
+        );
+
+        let body: Value = serde_json::from_slice(
+            Problem::new(404, "Not Found")
+                .response()
+                .body_bytes()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            json!({"type": "about:blank", "title": "Not Found", "status": 404})
+        );
+
+        let err = crate::http::json::from_str::<Vec<u8>>("[1, -1]").unwrap_err();
+        let problem = Problem::from(&Error::InvalidJson(err));
+        assert_eq!(problem.status(), 422);
+        assert_eq!(
+            problem.extensions["errors"],
+            json!({"[1]": ["invalid value: integer `-1`, expected u8"]})
+        );
+
+        let problem = Problem::from(&Error::UntrustedHost("evil.com".into()));
+        assert_eq!(problem.status(), 400);
+        assert_eq!(problem.title, "Bad Request");
+        assert_eq!(
+            problem.detail.as_deref(),
+            Some("host \"evil.com\" is not allowed")
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! Byte range requests.
+//!
+//! Browsers playing audio and video, and download managers resuming downloads, ask for parts of a response with the `Range` header,
+//! e.g. `Range: bytes=0-`. [`Response::range`](super::Response::range) answers them with `206 - Partial Content` and the requested bytes,
+//! or with `416 - Range Not Satisfiable` if the range is outside the body.
+//!
+//! Only single ranges are supported. Requests for multiple ranges get the whole body, which is allowed by RFC 9110.
+use std::ops::Range;
+
+/// Byte range requested with the `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=start-end`, both inclusive.
+    FromTo(u64, u64),
+    /// `bytes=start-`, until the end of the body.
+    From(u64),
+    /// `bytes=-length`, the last bytes of the body.
+    Last(u64),
+}
+
+impl ByteRange {
+    /// Parse the value of the `Range` header. Returns `None` if the header isn't a valid
+    /// byte range, in which case it should be ignored.
+    pub fn parse(header: &str) -> Option<Vec<ByteRange>> {
+        let (unit, ranges) = header.trim().split_once('=')?;
+
+        if !unit.trim().eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+
+        let ranges = ranges
+            .split(',')
+            .map(|range| {
+                let (start, end) = range.trim().split_once('-')?;
+
+                match (start.trim(), end.trim()) {
+                    ("", "") => None,
+                    ("", length) => Some(ByteRange::Last(length.parse().ok()?)),
+                    (start, "") => Some(ByteRange::From(start.parse().ok()?)),
+                    (start, end) => {
+                        let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                        if start > end {
+                            None
+                        } else {
+                            Some(ByteRange::FromTo(start, end))
+                        }
+                    }
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        if ranges.is_empty() {
+            None
+        } else {
+            Some(ranges)
+        }
+    }
+
+    /// The bytes of a body of `len` bytes in this range. Returns `None` if the range
+    /// is not satisfiable, i.e. it starts after the end of the body.
+    pub fn resolve(&self, len: u64) -> Option<Range<u64>> {
+        match *self {
+            ByteRange::FromTo(start, end) if start < len => {
+                Some(start..end.saturating_add(1).min(len))
+            }
+            ByteRange::From(start) if start < len => Some(start..len),
+            ByteRange::Last(length) if length > 0 && len > 0 => {
+                Some(len.saturating_sub(length)..len)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ByteRange::parse("bytes=0-499"),
+            Some(vec![ByteRange::FromTo(0, 499)])
+        );
+        assert_eq!(ByteRange::parse("bytes=0-"), Some(vec![ByteRange::From(0)]));
+        assert_eq!(
+            ByteRange::parse("bytes=-500"),
+            Some(vec![ByteRange::Last(500)])
+        );
+        assert_eq!(
+            ByteRange::parse("bytes=0-1, 5-"),
+            Some(vec![ByteRange::FromTo(0, 1), ByteRange::From(5)])
+        );
+
+        assert_eq!(ByteRange::parse("items=0-1"), None);
+        assert_eq!(ByteRange::parse("bytes=5-1"), None);
+        assert_eq!(ByteRange::parse("bytes=-"), None);
+        assert_eq!(ByteRange::parse("bytes=a-b"), None);
+        assert_eq!(ByteRange::parse("bytes="), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(ByteRange::FromTo(0, 499).resolve(1000), Some(0..500));
+        assert_eq!(ByteRange::FromTo(900, 1999).resolve(1000), Some(900..1000));
+        assert_eq!(ByteRange::From(10).resolve(1000), Some(10..1000));
+        assert_eq!(ByteRange::Last(100).resolve(1000), Some(900..1000));
+        assert_eq!(ByteRange::Last(2000).resolve(1000), Some(0..1000));
+
+        assert_eq!(ByteRange::FromTo(1000, 1001).resolve(1000), None);
+        assert_eq!(ByteRange::From(1000).resolve(1000), None);
+        assert_eq!(ByteRange::Last(0).resolve(1000), None);
+        assert_eq!(ByteRange::From(0).resolve(0), None);
+    }
+}
@@ -0,0 +1,220 @@
+//! Remove credentials and other secrets from requests and responses before they are
+//! stored, e.g. by [error reports](crate::errors) and [captures](super::capture).
+//!
+//! Headers like `Authorization` and `Cookie`, and body fields like `password`, are redacted by default.
+//! Applications can add their own at startup:
+//!
+//! ```
+//! use rwf::http::redact;
+//!
+//! redact::header("x-partner-key");
+//! redact::field("ssn");
+//! ```
+//!
+//! Names are case-insensitive. Fields are redacted in JSON bodies, at any depth, and in URL-encoded forms.
+use std::collections::{BTreeMap, HashSet};
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde_json::Value;
+
+use super::{url::percent_decode_bytes, Headers};
+use crate::controller::middleware::csrf::CSRF_INPUT;
+
+/// Replaces redacted values.
+pub const REDACTED: &str = "[REDACTED]";
+
+static HEADERS: Lazy<RwLock<HashSet<String>>> = Lazy::new(|| {
+    RwLock::new(
+        [
+            "authorization",
+            "proxy-authorization",
+            "cookie",
+            "set-cookie",
+            "x-api-key",
+            "x-csrf-token",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect(),
+    )
+});
+
+static FIELDS: Lazy<RwLock<HashSet<String>>> = Lazy::new(|| {
+    RwLock::new(
+        [
+            "password",
+            "password_confirmation",
+            "current_password",
+            "token",
+            "access_token",
+            "refresh_token",
+            "secret",
+            "client_secret",
+            "api_key",
+            CSRF_INPUT,
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect(),
+    )
+});
+
+/// Redact the header from now on.
+pub fn header(name: impl ToString) {
+    HEADERS.write().insert(name.to_string().to_lowercase());
+}
+
+/// Redact the body field from now on.
+pub fn field(name: impl ToString) {
+    FIELDS.write().insert(name.to_string().to_lowercase());
+}
+
+/// Is the header redacted?
+pub fn sensitive_header(name: &str) -> bool {
+    HEADERS.read().contains(&name.to_lowercase())
+}
+
+/// Is the body field redacted?
+pub fn sensitive_field(name: &str) -> bool {
+    FIELDS.read().contains(&name.to_lowercase())
+}
+
+/// Copy of the headers, with the values of sensitive headers replaced by [`REDACTED`].
+pub fn headers(headers: &Headers) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if sensitive_header(name) {
+                REDACTED.to_string()
+            } else {
+                value.clone()
+            };
+
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+/// Body as text, with sensitive fields redacted. JSON and URL-encoded bodies are redacted field by field.
+/// Other text is returned as is, and binary bodies are replaced by their size, since their contents
+/// can't be checked.
+pub fn body(content_type: Option<&str>, body: &[u8]) -> String {
+    let media_type = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|media_type| media_type.trim().to_lowercase())
+        .unwrap_or_default();
+
+    if media_type == "application/json" || media_type.ends_with("+json") {
+        if let Ok(mut value) = serde_json::from_slice::<Value>(body) {
+            json(&mut value);
+            return value.to_string();
+        }
+    } else if media_type == "application/x-www-form-urlencoded" {
+        if let Ok(text) = std::str::from_utf8(body) {
+            return form(text);
+        }
+    }
+
+    let text = media_type.starts_with("text/")
+        || media_type.ends_with("+xml")
+        || media_type == "application/xml";
+
+    match std::str::from_utf8(body) {
+        Ok(body) if text || media_type.is_empty() => body.to_string(),
+        _ => format!("[{} bytes]", body.len()),
+    }
+}
+
+/// Redact sensitive fields in the JSON value, at any depth.
+pub fn json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if sensitive_field(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    json(value);
+                }
+            }
+        }
+
+        Value::Array(values) => values.iter_mut().for_each(json),
+
+        _ => (),
+    }
+}
+
+/// Redact sensitive fields in the URL-encoded form.
+pub fn form(body: &str) -> String {
+    let sensitive = |name: &str| {
+        sensitive_field(&String::from_utf8_lossy(&percent_decode_bytes(
+            name.as_bytes(),
+            true,
+        )))
+    };
+
+    body.split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if sensitive(name) => format!("{}={}", name, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_headers() {
+        header("X-Partner-Key");
+
+        let mut headers = Headers::default();
+        headers.insert("authorization", "Bearer abc");
+        headers.insert("x-partner-key", "123");
+        headers.insert("accept", "*/*");
+
+        let redacted = super::headers(&headers);
+        assert_eq!(redacted["authorization"], REDACTED);
+        assert_eq!(redacted["x-partner-key"], REDACTED);
+        assert_eq!(redacted["accept"], "*/*");
+    }
+
+    #[test]
+    fn test_body() {
+        field("SSN");
+
+        let body = super::body(
+            Some("application/json; charset=utf-8"),
+            br#"{"email": "a@b.com", "password": "hunter2", "users": [{"ssn": "123", "Token": {"a": 1}}]}"#,
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!({"email": "a@b.com", "password": REDACTED, "users": [{"ssn": REDACTED, "Token": REDACTED}]})
+        );
+
+        assert_eq!(
+            super::body(
+                Some("application/x-www-form-urlencoded"),
+                b"email=a%40b.com&pass%77ord=hunter2&rwf_csrf_token=abc&flag"
+            ),
+            "email=a%40b.com&pass%77ord=[REDACTED]&rwf_csrf_token=[REDACTED]&flag"
+        );
+
+        assert_eq!(super::body(Some("text/plain"), b"hello"), "hello");
+        assert_eq!(super::body(None, b"hello"), "hello");
+        assert_eq!(
+            super::body(Some("application/octet-stream"), b"hello"),
+            "[5 bytes]"
+        );
+        assert_eq!(super::body(Some("image/png"), &[0xff, 0xd8]), "[2 bytes]");
+        // Invalid JSON can't be checked field by field.
+        assert_eq!(
+            super::body(Some("application/json"), b"{\"password\": "),
+            "[13 bytes]"
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Requests rejected because they couldn't be parsed.
+//!
+//! Scanners, misconfigured clients, and TLS connections made to a plaintext port send bytes that aren't
+//! valid HTTP. Each of those is logged with the client address, the first line of the request (truncated and with
+//! control characters escaped), and the number of bytes received, and counted by [`RejectionKind`].
+//!
+//! The log level is configured with the `rejected_log_level` setting. By default, clients get a `400 - Bad Request`,
+//! except for TLS handshakes, which are closed without a response. The categories that should
+//! be closed silently are configured with the `rejected_close_silently` setting.
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, trace, warn};
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::Response;
+use crate::config::get_config;
+
+/// Maximum number of characters of the first line included in the log.
+const MAX_FIRST_LINE: usize = 128;
+
+static COUNTS: [AtomicU64; RejectionKind::ALL.len()] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Why the request was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionKind {
+    /// The method isn't a valid HTTP token.
+    BadMethod,
+    /// The HTTP version isn't `HTTP/x.y`.
+    InvalidVersion,
+    /// A header line is longer than the `header_max_size` setting.
+    HeaderTooLarge,
+    /// The client is trying to use TLS on a plaintext port.
+    TlsHandshake,
+    /// The request line or a header is missing parts.
+    Malformed,
+}
+
+impl RejectionKind {
+    /// All rejection categories.
+    pub const ALL: [RejectionKind; 5] = [
+        RejectionKind::BadMethod,
+        RejectionKind::InvalidVersion,
+        RejectionKind::HeaderTooLarge,
+        RejectionKind::TlsHandshake,
+        RejectionKind::Malformed,
+    ];
+
+    fn index(&self) -> usize {
+        Self::ALL
+            .iter()
+            .position(|kind| kind == self)
+            .unwrap_or_default()
+    }
+
+    /// Name of the category, e.g. `bad_method`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RejectionKind::BadMethod => "bad_method",
+            RejectionKind::InvalidVersion => "invalid_version",
+            RejectionKind::HeaderTooLarge => "header_too_large",
+            RejectionKind::TlsHandshake => "tls_handshake",
+            RejectionKind::Malformed => "malformed",
+        }
+    }
+
+    /// How many requests were rejected for this reason since the server started.
+    pub fn count(&self) -> u64 {
+        COUNTS[self.index()].load(Ordering::Relaxed)
+    }
+}
+
+/// A request that couldn't be parsed.
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    kind: RejectionKind,
+    reason: &'static str,
+    first_line: String,
+    bytes: usize,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind.name(), self.reason)
+    }
+}
+
+impl Rejection {
+    /// Create new rejection.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Rejection category.
+    /// * `reason` - What exactly is wrong with the request.
+    /// * `first_line` - The first line of the request, as received.
+    /// * `bytes` - Number of bytes received before the request was rejected.
+    ///
+    pub fn new(kind: RejectionKind, reason: &'static str, first_line: &str, bytes: usize) -> Self {
+        Self {
+            kind,
+            reason,
+            first_line: sanitize(first_line),
+            bytes,
+        }
+    }
+
+    /// Rejection category.
+    pub fn kind(&self) -> RejectionKind {
+        self.kind
+    }
+
+    /// What exactly is wrong with the request.
+    pub fn reason(&self) -> &'static str {
+        self.reason
+    }
+
+    /// First line of the request, truncated and with control characters escaped.
+    pub fn first_line(&self) -> &str {
+        &self.first_line
+    }
+
+    /// Number of bytes received before the request was rejected.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Count the rejection and log it at the configured level.
+    pub fn record(&self, peer: &SocketAddr) {
+        COUNTS[self.kind.index()].fetch_add(1, Ordering::Relaxed);
+
+        macro_rules! log {
+            ($level:ident) => {
+                $level!(
+                    peer = %peer,
+                    kind = self.kind.name(),
+                    reason = self.reason,
+                    first_line = %self.first_line,
+                    bytes = self.bytes,
+                    "request rejected"
+                )
+            };
+        }
+
+        match get_config().general.rejected_log_level.as_str() {
+            "error" => log!(error),
+            "warn" => log!(warn),
+            "info" => log!(info),
+            "debug" => log!(debug),
+            "trace" => log!(trace),
+            _ => (),
+        }
+    }
+
+    /// Response sent to the client, or `None` if the connection should be closed
+    /// without one.
+    pub fn response(&self) -> Option<Response> {
+        if get_config()
+            .general
+            .rejected_close_silently
+            .contains(&self.kind)
+        {
+            None
+        } else {
+            Some(Response::bad_request())
+        }
+    }
+}
+
+fn sanitize(line: &str) -> String {
+    line.chars()
+        .take(MAX_FIRST_LINE)
+        .map(|c| {
+            if c.is_control() {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::{Error, Head};
+
+    async fn reject(bytes: &[u8]) -> Rejection {
+        match Head::read(bytes).await {
+            Err(Error::Rejected(rejection)) => *rejection,
+            result => panic!("expected rejection, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_categories() {
+        let rejection = reject(b"GE(T / HTTP/1.1\r\n\r\n").await;
+        assert_eq!(rejection.kind(), RejectionKind::BadMethod);
+        assert_eq!(rejection.first_line(), "GE(T / HTTP/1.1");
+        assert_eq!(rejection.bytes(), 17);
+
+        let rejection = reject(b"GET / SPDY/3\r\n\r\n").await;
+        assert_eq!(rejection.kind(), RejectionKind::InvalidVersion);
+
+        let rejection = reject(b"GET /\r\n\r\n").await;
+        assert_eq!(rejection.kind(), RejectionKind::Malformed);
+
+        let rejection = reject(b"GET / HTTP/1.1\r\nno colon\r\n\r\n").await;
+        assert_eq!(rejection.kind(), RejectionKind::Malformed);
+        assert_eq!(rejection.first_line(), "GET / HTTP/1.1");
+        assert_eq!(rejection.bytes(), 26);
+
+        // TLS ClientHello: handshake record, TLS 1.0, followed by binary data.
+        let rejection = reject(&[0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00]).await;
+        assert_eq!(rejection.kind(), RejectionKind::TlsHandshake);
+        assert_eq!(rejection.bytes(), 1);
+        assert!(rejection.response().is_none());
+
+        let mut long = b"GET /".to_vec();
+        long.extend(vec![b'a'; get_config().general.header_max_size]);
+        long.extend(b" HTTP/1.1\r\n\r\n");
+        let rejection = reject(&long).await;
+        assert_eq!(rejection.kind(), RejectionKind::HeaderTooLarge);
+        assert_eq!(rejection.first_line().len(), MAX_FIRST_LINE);
+        assert_eq!(rejection.response().unwrap().status().code(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_control_characters() {
+        let rejection = reject(b"GET\x1b[31m / HTTP/1.1\r\n\r\n").await;
+        assert_eq!(rejection.kind(), RejectionKind::BadMethod);
+        assert_eq!(rejection.first_line(), "GET\\u{1b}[31m / HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn test_valid_requests() {
+        for request in [
+            &b"OPTIONS * HTTP/1.1\r\n\r\n"[..],
+            b"PROPFIND /files HTTP/1.0\r\nHost: localhost\r\n\r\n",
+        ] {
+            assert!(Head::read(request).await.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_count() {
+        let before = RejectionKind::Malformed.count();
+        Rejection::new(RejectionKind::Malformed, "path", "GET", 3)
+            .record(&"127.0.0.1:1234".parse().unwrap());
+        assert!(RejectionKind::Malformed.count() > before);
+    }
+}
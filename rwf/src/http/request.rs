@@ -1,27 +1,46 @@
 //! HTTP request.
 
+use std::any::Any;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::marker::Unpin;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
-use serde::Deserialize;
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::{Deserializer, Value};
 use time::OffsetDateTime;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
-use super::{Cookies, Error, FormData, FromFormData, Head, Params, Response, ToParameter};
+use super::{
+    body_stream::{BodyPump, BodyReader, Framing},
+    conditional::{self, parse_http_date, IfMatch, Precondition},
+    json,
+    language::{self, LanguageRange},
+    range::ByteRange,
+    BodyStream, Budget, Charset, Cookie, Cookies, Error, FormData, FromFormData, Head, LogFields,
+    LogValue, MediaRange, Method, Multipart, Nonce, ParamValue, Params, Query, Reservation,
+    Response, Timings, ToParameter, Url,
+};
 use crate::{
-    config::get_config,
-    controller::{Session, SessionId},
+    config::{get_config, General},
+    controller::{
+        middleware::csrf::{CsrfMode, CSRF_INPUT},
+        AuditEvent, Impersonate, Session, SessionId,
+    },
     model::{ConnectionGuard, Model},
+    view::{form::METHOD_OVERRIDE_INPUT, provider::Provided},
 };
 
+/// Header used by API clients to override the request method.
+pub static METHOD_OVERRIDE_HEADER: &str = "X-HTTP-Method-Override";
+
 /// HTTP request.
 ///
-/// The request is fully loaded into memory. It's safe to clone
-/// since the contents are behind an [`std::sync::Arc`].
+/// The request is fully loaded into memory, unless its route streams the body, see [`Request::body_stream`].
+/// It's safe to clone since the contents are behind an [`std::sync::Arc`].
 #[derive(Debug, Clone)]
 pub struct Request {
     head: Head,
@@ -31,6 +50,11 @@ pub struct Request {
     received_at: OffsetDateTime,
     // Don't check for valid CSRF token.
     skip_csrf: bool,
+    csrf_mode: CsrfMode,
+    timings: Timings,
+    log_fields: LogFields,
+    nonce: Nonce,
+    api_version: Option<String>,
 }
 
 impl Default for Request {
@@ -42,25 +66,71 @@ impl Default for Request {
             params: None,
             received_at: OffsetDateTime::now_utc(),
             skip_csrf: false,
+            csrf_mode: CsrfMode::default(),
+            timings: Timings::new(),
+            log_fields: LogFields::new(),
+            nonce: Nonce::new(),
+            api_version: None,
         }
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default)]
 struct Inner {
     body: Vec<u8>,
+    // Body received while the controller runs.
+    body_stream: Mutex<Option<BodyStream>>,
+    streamed: bool,
+    // Released when the last copy of the request is dropped.
+    _memory: Arc<Reservation>,
     cookies: Cookies,
     peer: Option<SocketAddr>,
+    // Body decoded with its charset, on first use.
+    text: OnceLock<String>,
+    // Template variables resolved by providers.
+    provided: Provided,
+    // Dropped with the last copy of the request, see Request::guard.
+    guards: Mutex<Vec<Box<dyn Any + Send + Sync>>>,
 }
 
 impl Request {
     /// Read the request in its entirety from a stream.
-    pub async fn read(peer: SocketAddr, mut stream: impl AsyncRead + Unpin) -> Result<Self, Error> {
+    pub async fn read(peer: SocketAddr, stream: impl AsyncRead + Unpin) -> Result<Self, Error> {
+        Self::read_with_budget(peer, stream, Budget::global()).await
+    }
+
+    async fn read_with_budget(
+        peer: SocketAddr,
+        mut stream: impl AsyncRead + Unpin,
+        budget: &'static Budget,
+    ) -> Result<Self, Error> {
         let head = Head::read(&mut stream).await?;
-        let content_length = head.content_length().unwrap_or(0);
+        Self::read_body(peer, head, stream, budget).await
+    }
+
+    /// Read the body of the request, after its head was read with [`Head::read`].
+    pub(crate) async fn read_body(
+        peer: SocketAddr,
+        head: Head,
+        mut stream: impl AsyncRead + Unpin,
+        budget: &'static Budget,
+    ) -> Result<Self, Error> {
+        let max_request_size = get_config().general.max_request_size;
+
+        // The size of chunked bodies isn't known until they are read.
+        let content_length = match Framing::new(&head)? {
+            Framing::Length(content_length) => content_length,
+            Framing::Chunked => {
+                let (head, body) = BodyReader::new(Framing::Chunked)
+                    .read_to_end(&mut stream, head, max_request_size)
+                    .await?;
+                let memory = budget.reserve(body.len())?;
+                return Self::new(peer, head, body, memory, None);
+            }
+        };
 
         // Handle requests which are too large.
-        if content_length > get_config().general.max_request_size {
+        if content_length > max_request_size {
             // Throw away whatever we receive.
             let mut throw_away = vec![0u8; 4096];
             let mut content_length = content_length as i64;
@@ -77,12 +147,39 @@ impl Request {
             return Err(Error::ContentTooLarge(head));
         }
 
+        let memory = budget.reserve(content_length)?;
         let mut body = vec![0u8; content_length];
         stream
             .read_exact(&mut body)
             .await
             .map_err(|_| Error::MalformedRequest("incorrect content length"))?;
 
+        Self::new(peer, head, body, memory, None)
+    }
+
+    /// Request which body is read from the connection by the returned pump while the controller runs,
+    /// see [`Request::body_stream`].
+    pub(crate) fn streaming(peer: SocketAddr, head: Head) -> Result<(Self, BodyPump), Error> {
+        let (body_stream, pump) = BodyStream::new(Framing::new(&head)?);
+        Ok((
+            Self::new(
+                peer,
+                head,
+                vec![],
+                Reservation::default(),
+                Some(body_stream),
+            )?,
+            pump,
+        ))
+    }
+
+    fn new(
+        peer: SocketAddr,
+        head: Head,
+        body: Vec<u8>,
+        memory: Reservation,
+        body_stream: Option<BodyStream>,
+    ) -> Result<Self, Error> {
         let cookies = head.cookies();
 
         Ok(Request {
@@ -91,11 +188,22 @@ impl Request {
             session: cookies.get_session()?,
             inner: Arc::new(Inner {
                 body,
+                streamed: body_stream.is_some(),
+                body_stream: Mutex::new(body_stream),
+                _memory: Arc::new(memory),
                 peer: Some(peer),
                 cookies,
+                text: OnceLock::new(),
+                provided: Provided::default(),
+                guards: Mutex::default(),
             }),
             received_at: OffsetDateTime::now_utc(),
             skip_csrf: false,
+            csrf_mode: CsrfMode::default(),
+            timings: Timings::new(),
+            log_fields: LogFields::new(),
+            nonce: Nonce::new(),
+            api_version: None,
         })
     }
 
@@ -107,12 +215,192 @@ impl Request {
             .expect("peer is not set on the request")
     }
 
+    /// IP address of the client.
+    ///
+    /// This is the peer address, unless `trust_proxy` is enabled and the peer is a trusted proxy. The client is then
+    /// the rightmost address in the `X-Forwarded-For` header, or the `for` parameters of the `Forwarded` header,
+    /// which isn't in the `trusted_proxies` setting. Addresses to its left could be set by the client, so they are ignored.
+    pub fn client_ip(&self) -> IpAddr {
+        self.client_ip_with(&get_config().general)
+    }
+
+    fn client_ip_with(&self, config: &General) -> IpAddr {
+        let peer = self.peer().ip();
+
+        let trusted = config.trusted_proxies.is_empty() || config.proxy_trusted(peer);
+        if !config.trust_proxy || !trusted {
+            return peer;
+        }
+
+        let hops = match self.header("x-forwarded-for") {
+            Some(header) => header
+                .split(',')
+                .map(|hop| hop.trim().to_string())
+                .collect(),
+            None => self
+                .header("forwarded")
+                .map(|header| {
+                    forwarded_elements(header)
+                        .into_iter()
+                        .filter_map(|element| {
+                            element
+                                .into_iter()
+                                .find(|(name, _)| name == "for")
+                                .map(|(_, value)| value)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default(),
+        };
+
+        let mut client = peer;
+
+        for hop in hops.iter().rev() {
+            match node_ip(hop) {
+                Some(ip) => {
+                    client = ip;
+                    if !config.proxy_trusted(ip) {
+                        break;
+                    }
+                }
+                // Obfuscated or unknown, e.g. `for=unknown`. Nothing to its left can be trusted.
+                None => break,
+            }
+        }
+
+        client
+    }
+
+    /// Scheme and host the client used to reach the application, e.g. `https://example.com`.
+    ///
+    /// If the `public_url` setting is configured, it's always used. Otherwise, the host comes from the `Host` header, or
+    /// from the `X-Forwarded-Proto`/`X-Forwarded-Host` and `Forwarded` headers if `trust_proxy` is enabled. To prevent
+    /// host header injection, the host must be in the `allowed_hosts` setting.
+    pub fn base_url(&self) -> Result<String, Error> {
+        self.base_url_with(&get_config().general)
+    }
+
+    fn base_url_with(&self, config: &General) -> Result<String, Error> {
+        if let Some(ref public_url) = config.public_url {
+            return Ok(public_url.trim_end_matches('/').to_string());
+        }
+
+        let forwarded = if config.trust_proxy {
+            self.header("forwarded")
+                .and_then(|header| forwarded_elements(header).into_iter().next())
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+        let forwarded = |name: &str, header: &str| {
+            if !config.trust_proxy {
+                return None;
+            }
+
+            self.header(header)
+                .and_then(|value| value.split(',').next())
+                .map(|value| value.trim().to_string())
+                .or_else(|| {
+                    forwarded
+                        .iter()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.to_string())
+                })
+        };
+
+        let scheme = match forwarded("proto", "x-forwarded-proto") {
+            Some(scheme) if scheme.eq_ignore_ascii_case("https") => "https",
+            _ => "http",
+        };
+
+        let host = forwarded("host", "x-forwarded-host")
+            .or_else(|| self.header("host").map(|host| host.trim().to_string()))
+            .ok_or(Error::MalformedRequest("host"))?;
+
+        if config.host_allowed(&host) {
+            Ok(format!("{}://{}", scheme, host))
+        } else {
+            Err(Error::UntrustedHost(host))
+        }
+    }
+
+    /// Build the URL of a route, e.g. `url_for("/users/:id", &[("id", 5)])`
+    /// returns `/users/5`. Parameters are URL-encoded. See [`Url::route`].
+    pub fn url_for(&self, route: &str, params: &[(&str, impl ToString)]) -> Result<Url, Error> {
+        Url::route(route, params)
+    }
+
+    /// Same as [`Request::url_for`], but returns an absolute URL, e.g. `absolute_url("/users/:id", &[("id", 5)])`
+    /// returns `https://example.com/users/5`. Parameters are URL-encoded.
+    ///
+    /// See [`Request::base_url`] for how the scheme and host are determined.
+    pub fn absolute_url(
+        &self,
+        route: &str,
+        params: &[(&str, impl ToString)],
+    ) -> Result<String, Error> {
+        let path = self.url_for(route, params)?;
+        Ok(format!("{}{}", self.base_url()?, path))
+    }
+
     /// Set params on the request.
     pub fn with_params(mut self, params: Arc<Params>) -> Self {
         self.params = Some(params);
         self
     }
 
+    /// API version requested by the client, for routes registered with [`crate::http::Router::versioned`].
+    pub fn api_version(&self) -> Option<&str> {
+        self.api_version.as_deref()
+    }
+
+    pub(crate) fn with_api_version(mut self, version: impl ToString) -> Self {
+        self.api_version = Some(version.to_string());
+        self
+    }
+
+    /// Template variables resolved by [providers](crate::view::provider) for this request.
+    pub(crate) fn provided(&self) -> &Provided {
+        &self.inner.provided
+    }
+
+    /// Keep a value until the last copy of the request is dropped, e.g. to undo
+    /// work started by middleware if the controller panics.
+    pub(crate) fn guard<T: Any + Send + Sync>(&self, guard: T) {
+        self.inner.guards.lock().push(Box::new(guard));
+    }
+
+    /// Take back a value kept with [`Request::guard`].
+    pub(crate) fn take_guard<T: Any + Send + Sync>(&self) -> Option<T> {
+        let mut guards = self.inner.guards.lock();
+        let position = guards.iter().position(|guard| guard.is::<T>())?;
+        guards.remove(position).downcast().ok().map(|guard| *guard)
+    }
+
+    /// Timings recorder for this request, reported
+    /// in the `Server-Timing` response header.
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    /// Custom fields added to the access log entry for this request.
+    pub fn log_fields(&self) -> &LogFields {
+        &self.log_fields
+    }
+
+    /// Add a field to the access log entry for this request, e.g. a tenant or order ID.
+    /// Setting the same field again replaces its value.
+    pub fn log_field(&self, name: &str, value: impl Into<LogValue>) -> &Self {
+        self.log_fields.set(name, value);
+        self
+    }
+
+    /// Nonce for the `Content-Security-Policy` header, generated by the
+    /// [`ContentSecurityPolicy`](crate::controller::middleware::ContentSecurityPolicy) middleware.
+    pub fn nonce(&self) -> &Nonce {
+        &self.nonce
+    }
+
     /// Return requests' head (headers, method, etc.).
     pub fn head(&self) -> &Head {
         &self.head
@@ -134,22 +422,97 @@ impl Request {
         Ok(None)
     }
 
-    /// Request's body as bytes.
+    /// Deserialize the query string into a struct, e.g. `?page=2&tag=rust&tag=web`.
+    ///
+    /// Repeated names fill `Vec` fields, other fields get the last value, and `Option` fields
+    /// are `None` if the name is missing or has no value. Booleans accept `true`/`false`, `1`/`0`,
+    /// `on`/`off` and `yes`/`no`. If the query doesn't match the struct, using the `?` operator
+    /// returns `400 - Bad Request`.
+    ///
+    /// The parsed query, with [`Query::get`](super::Query::get), is available from `request.path().query()`.
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        self.path().query().deserialize()
+    }
+
+    /// All values of each query parameter, e.g. `?tag=rust&tag=web` is `{"tag": ["rust", "web"]}`.
+    pub fn query_raw(&self) -> HashMap<String, Vec<String>> {
+        self.path().query().multimap()
+    }
+
+    /// Extract a parameter from the provided path, parsed according to its constraint,
+    /// e.g. [`ParamValue::Uuid`] for `/items/:id<uuid>`.
+    pub fn param_value(&self, name: &str) -> Option<ParamValue> {
+        self.params
+            .as_ref()
+            .and_then(|params| params.value(self.path().base(), name))
+    }
+
+    /// Request's body, read while the controller runs instead of being loaded into memory first. Enable it
+    /// on the route with [`Handler::stream_body`](super::Handler::stream_body), e.g. for large uploads.
+    ///
+    /// The stream can only be taken once; this returns `None` afterwards. On routes which don't stream the body,
+    /// the stream reads the body already in memory.
+    pub fn body_stream(&self) -> Option<BodyStream> {
+        match self.inner.body_stream.lock().take() {
+            Some(body_stream) => Some(body_stream),
+            None if self.inner.streamed => None,
+            None => Some(BodyStream::buffered(self.body())),
+        }
+    }
+
+    /// Stop receiving the body, if the controller didn't take it.
+    pub(crate) fn discard_body(&self) {
+        self.inner.body_stream.lock().take();
+    }
+
+    /// Request's body as bytes. Empty if the route streams the body, see [`Request::body_stream`].
     ///
     /// It's the job of the caller to handle encoding if any.
     pub fn body(&self) -> &[u8] {
         &self.inner.body
     }
 
-    /// Request's body as JSON value.
+    /// Request's body as JSON value, without checking the `Content-Type` header or the body size.
+    /// Prefer [`Request::json_value`].
     pub fn json_raw(&self) -> Result<Value, serde_json::Error> {
+        let charset = self.charset().map_err(serde::de::Error::custom)?;
+
+        if charset == Charset::Utf8 {
+            Value::deserialize(&mut Deserializer::from_slice(self.body()))
+        } else {
+            let text = self.text().map_err(serde::de::Error::custom)?;
+            Value::deserialize(&mut Deserializer::from_str(text))
+        }
+    }
+
+    /// Request's body as a JSON value, for payloads without a fixed schema. Checked like [`Request::json`].
+    pub fn json_value(&self) -> Result<Value, Error> {
         self.json()
     }
 
-    /// Request's body as a UTF-8 string.
-    /// UTF-8 encoding is assumed, and all incompatible characters are dropped.
+    /// Request's body as a string, decoded with the charset from the `Content-Type` header.
+    /// UTF-8 is used if the charset isn't set or isn't supported, and invalid characters are replaced with `�`.
     pub fn string(&self) -> String {
-        String::from_utf8_lossy(self.body()).to_string()
+        match self.text() {
+            Ok(text) => text.to_string(),
+            Err(_) => String::from_utf8_lossy(self.body()).to_string(),
+        }
+    }
+
+    /// Request's body as a string, decoded with the charset from the `Content-Type` header,
+    /// or UTF-8 if it's not set. If the charset isn't supported, `415 - Unsupported Media Type`
+    /// is returned when using the `?` operator.
+    pub fn text(&self) -> Result<&str, Error> {
+        let charset = self.charset()?;
+        Ok(self.inner.text.get_or_init(|| charset.decode(self.body())))
+    }
+
+    /// Charset of the body, from the `Content-Type` header. UTF-8 if it's not set.
+    pub fn charset(&self) -> Result<Charset, Error> {
+        match self.content_type() {
+            Some(content_type) => content_type.charset(),
+            None => Ok(Charset::Utf8),
+        }
     }
 
     /// Return data submitted via a form.
@@ -161,6 +524,79 @@ impl Request {
         FormData::from_request(self)
     }
 
+    /// Parse a `multipart/form-data` body into text fields and uploaded files, while it's read
+    /// from the [body stream](Request::body_stream), so routes streaming the body don't load it into memory.
+    ///
+    /// Files larger than the `multipart_disk_threshold` setting are written to temporary files as they are received.
+    /// Bodies larger than the `max_request_size` setting return an error which, using the `?` operator,
+    /// responds with `413 - Content Too Large`.
+    pub async fn multipart(&self) -> Result<Multipart, Error> {
+        self.multipart_with(&get_config().general).await
+    }
+
+    async fn multipart_with(&self, config: &General) -> Result<Multipart, Error> {
+        let content_type = self
+            .content_type()
+            .ok_or(Error::MalformedRequest("content-type header is required"))?;
+
+        if content_type.media_type() != "multipart/form-data" {
+            return Err(Error::MalformedRequest(
+                "only \"multipart/form-data\" is supported",
+            ));
+        }
+
+        let boundary = content_type
+            .param("boundary")
+            .ok_or(Error::MalformedRequest("multipart missing boundary"))?;
+
+        let body = self
+            .body_stream()
+            .ok_or(Error::MalformedRequest("request body was already read"))?;
+        let mut body = body.take(config.max_request_size as u64 + 1);
+        let multipart = Multipart::read(&mut body, boundary, config.multipart_disk_threshold).await;
+
+        // The body was cut off at the limit.
+        if body.limit() == 0 {
+            return Err(Error::ContentTooLarge(self.head.clone()));
+        }
+
+        multipart
+    }
+
+    /// Route the request using the method submitted in the `_method` field of an HTML form,
+    /// or in the `X-HTTP-Method-Override` header, if `method_override` is enabled.
+    ///
+    /// Browsers can only submit forms with GET and POST. Only POST requests can be overridden,
+    /// and only to `PUT`, `PATCH` or `DELETE`. The method sent by the client is still
+    /// available from [`Head::original_method`].
+    pub fn with_method_override(self) -> Self {
+        self.method_override_with(&get_config().general)
+    }
+
+    fn method_override_with(mut self, config: &General) -> Self {
+        if !config.method_override || !self.head.post() {
+            return self;
+        }
+
+        let method = match self.header(METHOD_OVERRIDE_HEADER) {
+            Some(method) => Some(method.clone()),
+            None => match self.form_data() {
+                Ok(form_data) => form_data.get::<String>(METHOD_OVERRIDE_INPUT),
+                Err(_) => None,
+            },
+        };
+
+        if let Some(method) = method {
+            if let Ok(method) = Method::try_from(method.trim().to_string()) {
+                if [Method::Put, Method::Patch, Method::Delete].contains(&method) {
+                    self.head.override_method(method);
+                }
+            }
+        }
+
+        self
+    }
+
     /// Return data submitted via a form, type checked
     /// against a Rust struct.
     ///
@@ -170,11 +606,77 @@ impl Request {
         T::from_form_data(&self.form_data()?)
     }
 
-    /// Deserialize request body from JSON into a Rust struct. If deserialization fails,
-    /// an error is returned.
-    pub fn json<'a, T: Deserialize<'a>>(&'a self) -> Result<T, serde_json::Error> {
-        let mut deserializer = Deserializer::from_slice(self.body());
-        T::deserialize(&mut deserializer)
+    /// Deserialize an `application/x-www-form-urlencoded` body into a struct, with the same rules
+    /// as [`Request::query`]: repeated fields, e.g. checkboxes, fill `Vec` fields, and `Option` fields
+    /// are `None` if the field is missing or empty. The CSRF token and method override fields are left out.
+    ///
+    /// If the body doesn't match the struct, [`Error::InvalidForm`] has the name of the field and the reason,
+    /// which can be shown to the user when re-rendering the form. Using the `?` operator, it returns `400 - Bad Request`.
+    pub fn form_urlencoded<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let content_type = self
+            .content_type()
+            .ok_or(Error::MalformedRequest("content-type header is required"))?;
+
+        if content_type.media_type() != "application/x-www-form-urlencoded" {
+            return Err(Error::MalformedRequest(
+                "only \"application/x-www-form-urlencoded\" is supported",
+            ));
+        }
+
+        let query = match self.form_data()? {
+            FormData::UrlEncoded(query) => query,
+            FormData::Multipart(_) => unreachable!("checked content type"),
+        };
+
+        let pairs = query
+            .pairs()
+            .into_iter()
+            .filter(|(name, _)| ![CSRF_INPUT, METHOD_OVERRIDE_INPUT, "_charset_"].contains(name))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        Query::from_pairs(pairs)
+            .deserialize_fields()
+            .map_err(Error::InvalidForm)
+    }
+
+    /// Deserialize request body from JSON into a Rust struct.
+    ///
+    /// The `Content-Type` must be `application/json`, or another JSON type like `application/merge-patch+json`,
+    /// and the body can't be larger than the `max_json_size` setting. Using the `?` operator, they return
+    /// `415 - Unsupported Media Type` and `413 - Content Too Large`.
+    ///
+    /// If the body isn't valid JSON, or is over the limits set by the `max_json_depth`, `max_json_string_size`
+    /// and `reject_duplicate_json_keys` settings, [`Error::InvalidJson`] has the line, column and byte offset
+    /// of the error and returns `400 - Bad Request`. If it doesn't match the struct, it has the path to the field, e.g. `items[0].price`,
+    /// and returns `422 - Unprocessable Content`.
+    ///
+    /// Bodies in another charset than UTF-8, e.g. `application/json; charset=iso-8859-1`, are converted
+    /// to UTF-8 first.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        self.json_with(&get_config().general)
+    }
+
+    fn json_with<T: DeserializeOwned>(&self, config: &General) -> Result<T, Error> {
+        let media_type = self
+            .content_type()
+            .map(|content_type| content_type.media_type().to_string())
+            .unwrap_or_default();
+
+        if media_type != "application/json" && !media_type.ends_with("+json") {
+            return Err(Error::UnsupportedContentType(media_type));
+        }
+
+        if self.body().len() > config.max_json_size {
+            return Err(Error::ContentTooLarge(self.head.clone()));
+        }
+
+        let body = match self.charset()? {
+            Charset::Utf8 => self.body(),
+            _ => self.text()?.as_bytes(),
+        };
+
+        json::from_slice_with(body, &json::Limits::from(config)).map_err(Error::InvalidJson)
     }
 
     /// Return cookies set on the request. If no cookies are set,
@@ -183,6 +685,12 @@ impl Request {
         &self.inner.cookies
     }
 
+    /// Get a cookie set with [`Response::signed_cookie`](super::Response::signed_cookie). Returns `None`
+    /// if the cookie isn't set, or its signature doesn't match.
+    pub fn signed_cookie(&self, name: &str) -> Option<Cookie> {
+        self.inner.cookies.get_signed(name)
+    }
+
     /// Get the session set on the request, if any. While all requests served
     /// by Rwf should have a session (guest or authenticated), the browser
     /// may not send the cookie back (e.g. cURL won't).
@@ -196,6 +704,11 @@ impl Request {
         self.skip_csrf
     }
 
+    /// How the CSRF protection checks this request, set by the controller.
+    pub fn csrf_mode(&self) -> CsrfMode {
+        self.csrf_mode
+    }
+
     /// Return the timestamp of when the request was received by the server.
     pub fn received_at(&self) -> OffsetDateTime {
         self.received_at
@@ -213,20 +726,29 @@ impl Request {
 
     /// Get the authenticated user's ID. Combined with the `?` operator,
     /// will return HTTP `403 - Unauthorized` if not logged in.
+    ///
+    /// While [impersonating](Request::impersonate), this is the impersonated user.
     pub fn user_id(&self) -> Result<i64, Error> {
-        if let Some(session_id) = self.session_id() {
-            match session_id {
-                SessionId::Authenticated(id) => Ok(id),
-                _ => Err(Error::Forbidden),
-            }
-        } else {
-            Err(Error::Forbidden)
-        }
+        self.session
+            .as_ref()
+            .and_then(|session| session.user_id())
+            .ok_or(Error::Forbidden)
+    }
+
+    /// Get the ID of the user who logged in, even while they are impersonating
+    /// another user.
+    pub fn true_user_id(&self) -> Result<i64, Error> {
+        self.session
+            .as_ref()
+            .and_then(|session| session.true_user_id())
+            .ok_or(Error::Forbidden)
     }
 
     /// If a user is logged in, fetch the user's data from the database
     /// using the specified model.
     ///
+    /// While [impersonating](Request::impersonate), this is the impersonated user.
+    ///
     /// #### Example
     ///
     /// ```rust,ignore
@@ -242,12 +764,20 @@ impl Request {
     /// let user = request.user::<User>(&mut conn).await?;
     /// ```
     pub async fn user<T: Model>(&self, conn: &mut ConnectionGuard) -> Result<Option<T>, Error> {
-        match self.session_id() {
-            Some(SessionId::Authenticated(user_id)) => {
-                Ok(Some(T::find(user_id).fetch(conn).await?))
-            }
+        match self.user_id() {
+            Ok(user_id) => Ok(Some(T::find(user_id).fetch(conn).await?)),
+            Err(_) => Ok(None),
+        }
+    }
 
-            _ => Ok(None),
+    /// Fetch the user who logged in, even while they are impersonating another user.
+    pub async fn true_user<T: Model>(
+        &self,
+        conn: &mut ConnectionGuard,
+    ) -> Result<Option<T>, Error> {
+        match self.true_user_id() {
+            Ok(user_id) => Ok(Some(T::find(user_id).fetch(conn).await?)),
+            Err(_) => Ok(None),
         }
     }
 
@@ -276,6 +806,77 @@ impl Request {
         self
     }
 
+    /// Set how the CSRF protection checks this request. *For internal use only.*
+    pub fn set_csrf_mode(mut self, csrf_mode: CsrfMode) -> Self {
+        self.csrf_mode = csrf_mode;
+        self
+    }
+
+    /// Byte ranges requested with the `Range` header. Returns `None` if the header
+    /// is missing or isn't a valid byte range. See [`Response::range`].
+    pub fn ranges(&self) -> Option<Vec<ByteRange>> {
+        self.header("range")
+            .and_then(|range| ByteRange::parse(range))
+    }
+
+    /// Entity tags in the `If-Match` header. Returns `None` if the header is missing or malformed.
+    pub fn if_match(&self) -> Option<IfMatch> {
+        self.header("if-match")
+            .and_then(|value| IfMatch::parse(value))
+    }
+
+    /// Date in the `If-Unmodified-Since` header. Returns `None` if the header is missing or isn't a valid date.
+    pub fn if_unmodified_since(&self) -> Option<OffsetDateTime> {
+        self.header("if-unmodified-since")
+            .and_then(|value| parse_http_date(value))
+    }
+
+    /// Check that the client is changing the current version of the resource, using the `If-Match`
+    /// and `If-Unmodified-Since` headers, to reject lost updates. Pass the resource's current `ETag`
+    /// and last modification time, if it has them. Requests without these headers pass.
+    ///
+    /// `If-Match` uses the strong comparison, so weak `ETag`s, e.g. from [`Response::cacheable`], never match it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let etag = format!("\"{}\"", post.version);
+    ///
+    /// if !request.precondition(Some(&etag), Some(post.updated_at)).passed() {
+    ///     return Ok(Response::precondition_failed());
+    /// }
+    /// ```
+    pub fn precondition(
+        &self,
+        etag: Option<&str>,
+        last_modified: Option<OffsetDateTime>,
+    ) -> Precondition {
+        conditional::precondition(self, etag, last_modified)
+    }
+
+    /// Media ranges accepted by the client, from the `Accept` header, ordered by preference.
+    /// Empty if the header is missing or can't be parsed. See [`Response::negotiate`].
+    pub fn accepts(&self) -> Vec<MediaRange> {
+        self.header("accept")
+            .map(|accept| MediaRange::parse_header(accept))
+            .unwrap_or_default()
+    }
+
+    /// Languages accepted by the client, from the `Accept-Language` header, ordered by preference.
+    /// Empty if the header is missing or can't be parsed.
+    pub fn languages(&self) -> Vec<LanguageRange> {
+        self.header("accept-language")
+            .map(|languages| LanguageRange::parse_header(languages))
+            .unwrap_or_default()
+    }
+
+    /// The language the client prefers among the supported ones. Languages are matched by tag,
+    /// then by primary subtag, e.g. `fr-FR` matches `fr`, see [`crate::http::language`].
+    /// `None` if the client doesn't accept any of them.
+    pub fn preferred_language<'a>(&self, supported: &[&'a str]) -> Option<&'a str> {
+        language::preferred(&self.languages(), supported)
+    }
+
     /// Is the client requesting a connection upgrade to WebSocket?
     pub fn upgrade_websocket(&self) -> bool {
         self.headers()
@@ -296,11 +897,11 @@ impl Request {
     /// let response = request.login(1234);
     /// ```
     pub fn login(&self, user_id: i64) -> Response {
-        let mut session = self
+        let session = self
             .session()
-            .map(|s| s.clone())
-            .unwrap_or(Session::empty());
-        session.session_id = SessionId::Authenticated(user_id);
+            .cloned()
+            .unwrap_or(Session::empty())
+            .authenticate(user_id);
         Response::new().set_session(session).html("")
     }
 
@@ -319,8 +920,100 @@ impl Request {
             .map(|s| s.clone())
             .unwrap_or(Session::empty());
         session.session_id = SessionId::default();
+        session.authenticated_at = None;
+        session.impersonation = None;
         Response::new().set_session(session).html("")
     }
+
+    /// Start acting as another user for the specified duration. The logged in user must be allowed
+    /// to impersonate others and can't impersonate users with a higher privilege level.
+    /// Returns HTTP `403 - Forbidden` otherwise.
+    ///
+    /// The attempt is written to the [audit log](crate::controller::audit), whether it's allowed or not.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let mut conn = Pool::connection().await?;
+    /// let response = request
+    ///     .impersonate::<User>(&mut conn, 42, Duration::minutes(30))
+    ///     .await?;
+    /// ```
+    pub async fn impersonate<T: Model + Impersonate>(
+        &self,
+        conn: &mut ConnectionGuard,
+        user_id: i64,
+        duration: time::Duration,
+    ) -> Result<Response, Error> {
+        let true_user: T = T::find(self.true_user_id()?).fetch(conn).await?;
+        let user: Option<T> = T::find(user_id).fetch_optional(conn).await?;
+        let allowed = user
+            .as_ref()
+            .map(|user| true_user.may_impersonate(user))
+            .unwrap_or(false);
+
+        self.impersonate_checked(allowed, user_id, duration)
+    }
+
+    fn impersonate_checked(
+        &self,
+        allowed: bool,
+        user_id: i64,
+        duration: time::Duration,
+    ) -> Result<Response, Error> {
+        let session = self
+            .session()
+            .filter(|_| allowed)
+            .map(|session| session.clone().impersonate(user_id, duration));
+
+        match session {
+            Some(Ok(session)) => {
+                let impersonation = session.impersonation().expect("impersonation");
+                AuditEvent::new("impersonation_started")
+                    .session(Some(&session))
+                    .detail("expires_at", impersonation.expires_at())
+                    .log();
+
+                Ok(Response::new().set_session(session).html(""))
+            }
+
+            _ => {
+                self.audit("impersonation_denied")
+                    .detail("target", user_id)
+                    .log();
+                Err(Error::Forbidden)
+            }
+        }
+    }
+
+    /// Stop impersonating and go back to the user who logged in.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::prelude::*;
+    /// # let request = Request::default();
+    /// let response = request.stop_impersonating();
+    /// ```
+    pub fn stop_impersonating(&self) -> Response {
+        let session = self.session().cloned().unwrap_or_default();
+
+        if let Some(impersonation) = session.impersonation() {
+            self.audit("impersonation_stopped")
+                .detail("target", impersonation.user_id)
+                .log();
+        }
+
+        Response::new()
+            .set_session(session.stop_impersonating())
+            .html("")
+    }
+
+    /// Create an [audit log](crate::controller::audit) event with the
+    /// effective and true users of this request.
+    pub fn audit(&self, name: impl ToString) -> AuditEvent {
+        AuditEvent::new(name).session(self.session())
+    }
 }
 
 impl Deref for Request {
@@ -331,9 +1024,45 @@ impl Deref for Request {
     }
 }
 
+/// Elements of the `Forwarded` header, with their parameters, e.g.
+/// `for=192.0.2.1;proto=https, for="[2001:db8::1]"`.
+fn forwarded_elements(header: &str) -> Vec<Vec<(String, String)>> {
+    header
+        .split(',')
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(name, value)| {
+                    (
+                        name.trim().to_lowercase(),
+                        value.trim().trim_matches('"').to_string(),
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// IP address of a proxy hop, e.g. `192.0.2.1`, `192.0.2.1:8080` or `[2001:db8::1]:8080`.
+fn node_ip(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|ip| ip.parse().ok())
+}
+
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use time::Duration;
 
     pub async fn dummy_request() -> Result<Request, Error> {
         let body = ("GET /?hello=world HTTP/1.1\r\n".to_owned()
@@ -370,6 +1099,310 @@ pub mod test {
                 "hello": "world",
             })
         );
+        assert_eq!(request.json_value().unwrap()["hello"], "world");
+
+        async fn post(content_type: &str, body: &str) -> Request {
+            let request = format!(
+                "POST /orders HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            );
+            Request::read(dummy_ip(), request.as_bytes()).await.unwrap()
+        }
+
+        #[derive(Deserialize, Debug)]
+        struct Order {
+            #[allow(dead_code)]
+            quantity: u32,
+        }
+
+        let json = "application/json";
+        assert!(post("application/vnd.api+json", r#"{"quantity": 1}"#)
+            .await
+            .json::<Order>()
+            .is_ok());
+
+        let err = post("text/plain", r#"{"quantity": 1}"#)
+            .await
+            .json::<Order>()
+            .unwrap_err();
+        assert_eq!(err.code(), 415);
+
+        let err = post(json, r#"{"quantity": "#)
+            .await
+            .json::<Order>()
+            .unwrap_err();
+        assert_eq!(err.code(), 400);
+
+        let err = post(json, r#"{"quantity": "one"}"#)
+            .await
+            .json::<Order>()
+            .unwrap_err();
+        assert_eq!(err.code(), 422);
+        match err {
+            Error::InvalidJson(err) => assert_eq!(err.path(), "quantity"),
+            err => panic!("{:?}", err),
+        }
+
+        let mut config = General::default();
+        config.max_json_size = 10;
+        let err = post(json, r#"{"quantity": 1}"#)
+            .await
+            .json_with::<Order>(&config)
+            .unwrap_err();
+        assert_eq!(err.code(), 413);
+    }
+
+    #[cfg(feature = "charsets")]
+    #[tokio::test]
+    async fn test_json_charset() {
+        #[derive(Deserialize)]
+        struct Order {
+            name: String,
+        }
+
+        let body = b"{\"name\": \"caf\xe9\"}";
+        let mut req = format!(
+            "POST /orders HTTP/1.1\r\nContent-Type: application/json; charset=iso-8859-1\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        req.extend_from_slice(body);
+
+        let request = Request::read(dummy_ip(), &req[..]).await.unwrap();
+        assert_eq!(request.charset().unwrap(), Charset::Latin1);
+        assert_eq!(request.text().unwrap(), "{\"name\": \"café\"}");
+        assert_eq!(request.json::<Order>().unwrap().name, "café");
+    }
+
+    #[tokio::test]
+    async fn test_base_url() {
+        async fn request(headers: &str) -> Request {
+            let body = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+            Request::read(dummy_ip(), body.as_bytes()).await.unwrap()
+        }
+
+        let mut config = General::default();
+        config.public_url = None;
+        config.trust_proxy = false;
+        config.allowed_hosts = vec!["localhost".into(), ".example.com".into()];
+
+        let req = request("Host: localhost:8000\r\n").await;
+        assert_eq!(req.base_url_with(&config).unwrap(), "http://localhost:8000");
+
+        let req = request("Host: evil.com\r\n").await;
+        assert!(matches!(
+            req.base_url_with(&config),
+            Err(Error::UntrustedHost(_))
+        ));
+
+        let req = request("").await;
+        assert!(req.base_url_with(&config).is_err());
+
+        // Proxy headers are ignored unless the proxy is trusted.
+        let req = request(
+            "Host: localhost\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Host: api.example.com\r\n",
+        )
+        .await;
+        assert_eq!(req.base_url_with(&config).unwrap(), "http://localhost");
+        config.trust_proxy = true;
+        assert_eq!(
+            req.base_url_with(&config).unwrap(),
+            "https://api.example.com"
+        );
+
+        let req = request(
+            "Host: localhost\r\nForwarded: proto=https;host=\"example.com\", host=evil.com\r\n",
+        )
+        .await;
+        assert_eq!(req.base_url_with(&config).unwrap(), "https://example.com");
+
+        let req = request("Host: localhost\r\nX-Forwarded-Host: evil.com\r\n").await;
+        assert!(req.base_url_with(&config).is_err());
+
+        config.public_url = Some("https://example.com/".into());
+        assert_eq!(req.base_url_with(&config).unwrap(), "https://example.com");
+    }
+
+    #[tokio::test]
+    async fn test_client_ip() {
+        async fn request(peer: &str, headers: &str) -> Request {
+            let body = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+            Request::read(peer.parse().unwrap(), body.as_bytes())
+                .await
+                .unwrap()
+        }
+
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        let mut config = General::default();
+        config.trust_proxy = false;
+
+        let req = request("10.0.0.2:4000", "X-Forwarded-For: 1.1.1.1, 2.2.2.2\r\n").await;
+        assert_eq!(req.client_ip_with(&config), ip("10.0.0.2"));
+
+        // Only the peer is trusted, so the rightmost address is the client.
+        config.trust_proxy = true;
+        assert_eq!(req.client_ip_with(&config), ip("2.2.2.2"));
+
+        config.trusted_proxies = vec!["10.0.0.0/8".into()];
+        let req = request(
+            "10.0.0.2:4000",
+            "X-Forwarded-For: 6.6.6.6, 1.1.1.1, 10.0.0.5:8080\r\n",
+        )
+        .await;
+        assert_eq!(req.client_ip_with(&config), ip("1.1.1.1"));
+
+        // Headers from untrusted peers are ignored.
+        let req = request("3.3.3.3:4000", "X-Forwarded-For: 1.1.1.1\r\n").await;
+        assert_eq!(req.client_ip_with(&config), ip("3.3.3.3"));
+
+        let req = request(
+            "10.0.0.2:4000",
+            "Forwarded: for=1.1.1.1, for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.9\r\n",
+        )
+        .await;
+        assert_eq!(req.client_ip_with(&config), ip("2001:db8::1"));
+
+        let req = request(
+            "10.0.0.2:4000",
+            "Forwarded: for=1.1.1.1, for=unknown, for=10.0.0.9\r\n",
+        )
+        .await;
+        assert_eq!(req.client_ip_with(&config), ip("10.0.0.9"));
+
+        let req = request("10.0.0.2:4000", "").await;
+        assert_eq!(req.client_ip_with(&config), ip("10.0.0.2"));
+    }
+
+    #[tokio::test]
+    async fn test_method_override() {
+        async fn request(method: &str, headers: &str, body: &str) -> Request {
+            let request = format!(
+                "{} /users/5 HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+                method,
+                headers,
+                body.len(),
+                body
+            );
+            Request::read(dummy_ip(), request.as_bytes()).await.unwrap()
+        }
+
+        let form = "Content-Type: application/x-www-form-urlencoded\r\n";
+        let mut config = General::default();
+        config.method_override = true;
+
+        // Form field.
+        let req = request("POST", form, "_method=put&email=a")
+            .await
+            .method_override_with(&config);
+        assert_eq!(req.method(), &Method::Put);
+        assert_eq!(req.original_method(), &Method::Post);
+
+        // Header.
+        let req = request("POST", "X-HTTP-Method-Override: DELETE\r\n", "")
+            .await
+            .method_override_with(&config);
+        assert_eq!(req.method(), &Method::Delete);
+        assert_eq!(req.original_method(), &Method::Post);
+
+        // Only POST can be overridden.
+        let req = request("GET", "X-HTTP-Method-Override: DELETE\r\n", "")
+            .await
+            .method_override_with(&config);
+        assert_eq!(req.method(), &Method::Get);
+        let req = request("GET", form, "_method=PATCH")
+            .await
+            .method_override_with(&config);
+        assert_eq!(req.method(), &Method::Get);
+
+        // Only to PUT, PATCH or DELETE.
+        let req = request("POST", form, "_method=GET")
+            .await
+            .method_override_with(&config);
+        assert_eq!(req.method(), &Method::Post);
+
+        // Disabled.
+        config.method_override = false;
+        let req = request("POST", form, "_method=PUT")
+            .await
+            .method_override_with(&config);
+        assert_eq!(req.method(), &Method::Post);
+    }
+
+    #[tokio::test]
+    async fn test_absolute_url() {
+        let req = Request::read(
+            dummy_ip(),
+            &b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            req.absolute_url("/users/:id/posts/:slug", &[("id", "5"), ("slug", "a b")])
+                .unwrap(),
+            "http://localhost/users/5/posts/a%20b"
+        );
+        assert!(matches!(
+            req.absolute_url("/users/:id", &[("name", "5")]),
+            Err(Error::MissingParameter)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget() {
+        let budget: &'static Budget = Box::leak(Box::new(Budget::new(4 * 1024 * 1024)));
+        let barrier = Arc::new(tokio::sync::Barrier::new(32));
+        let mut uploads = tokio::task::JoinSet::new();
+
+        for _ in 0..32 {
+            let barrier = barrier.clone();
+            uploads.spawn(async move {
+                let mut upload =
+                    b"POST /upload HTTP/1.1\r\nContent-Length: 1048576\r\n\r\n".to_vec();
+                upload.extend(vec![b'a'; 1024 * 1024]);
+
+                let request = Request::read_with_budget(dummy_ip(), &upload[..], budget).await;
+                assert!(budget.usage() <= budget.limit());
+
+                // Keep the request until all uploads have been read.
+                barrier.wait().await;
+
+                match request {
+                    Ok(request) => request.body().len() == 1024 * 1024,
+                    Err(err) => {
+                        assert_eq!(err.code(), 503);
+                        false
+                    }
+                }
+            });
+        }
+
+        let accepted = uploads.join_all().await;
+        assert_eq!(accepted.iter().filter(|ok| **ok).count(), 4);
+        assert_eq!(budget.rejected(), 28);
+        assert_eq!(budget.usage(), 0);
+
+        // Released when the last copy of the request is dropped.
+        let mut upload = b"POST /upload HTTP/1.1\r\nContent-Length: 1048576\r\n\r\n".to_vec();
+        upload.extend(vec![b'a'; 1024 * 1024]);
+        let request = Request::read_with_budget(dummy_ip(), &upload[..], budget)
+            .await
+            .unwrap();
+        let copy = request.clone();
+        drop(request);
+        assert_eq!(budget.usage(), 1024 * 1024);
+        drop(copy);
+        assert_eq!(budget.usage(), 0);
+
+        // Released if the body can't be read.
+        upload.truncate(1024);
+        assert!(Request::read_with_budget(dummy_ip(), &upload[..], budget)
+            .await
+            .is_err());
+        assert_eq!(budget.usage(), 0);
     }
 
     #[tokio::test]
@@ -407,6 +1440,62 @@ pub mod test {
         assert!(err.starts_with("ContentTooLarge"));
     }
 
+    #[tokio::test]
+    async fn test_chunked() {
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let req = Request::read(dummy_ip(), chunked.as_bytes()).await.unwrap();
+        assert_eq!(req.body(), b"hello world");
+
+        // Buffered bodies can be read as a stream too.
+        let mut body = String::new();
+        req.body_stream()
+            .unwrap()
+            .read_to_string(&mut body)
+            .await
+            .unwrap();
+        assert_eq!(body, "hello world");
+
+        let mut too_large =
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n600000\r\n".to_vec();
+        too_large.extend(vec![0u8; 0x600000]);
+        too_large.extend(b"\r\n0\r\n\r\n");
+        let err = Request::read(dummy_ip(), too_large.as_slice())
+            .await
+            .expect_err("should err");
+        assert!(format!("{:?}", err).starts_with("ContentTooLarge"));
+
+        let smuggled =
+            "POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        assert!(Request::read(dummy_ip(), smuggled.as_bytes())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_languages() {
+        let req = "GET / HTTP/1.1\r\nAccept-Language: de-AT;q=0.5, fr-CH, fr;q=0.9\r\n\r\n";
+        let req = Request::read(dummy_ip(), req.as_bytes()).await.unwrap();
+        let languages = req.languages();
+        assert_eq!(
+            languages.iter().map(|l| l.tag()).collect::<Vec<_>>(),
+            vec!["fr-CH", "fr", "de-AT"]
+        );
+        assert_eq!(req.preferred_language(&["en", "de", "fr"]), Some("fr"));
+        assert_eq!(req.preferred_language(&["en", "de"]), Some("de"));
+        assert_eq!(req.preferred_language(&["en"]), None);
+
+        for header in [
+            "",
+            "Accept-Language: \r\n",
+            "Accept-Language: ;q=,,\u{1}\r\n",
+        ] {
+            let req = format!("GET / HTTP/1.1\r\n{}\r\n", header);
+            let req = Request::read(dummy_ip(), req.as_bytes()).await.unwrap();
+            assert!(req.languages().is_empty());
+            assert_eq!(req.preferred_language(&["en"]), None);
+        }
+    }
+
     #[tokio::test]
     async fn test_login_logout() {
         let req = "GET / HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
@@ -419,4 +1508,214 @@ pub mod test {
         assert!(response.session().is_some());
         assert!(response.session().as_ref().unwrap().guest());
     }
+
+    struct Staff {
+        privilege: i64,
+    }
+
+    impl Impersonate for Staff {
+        fn privilege(&self) -> i64 {
+            self.privilege
+        }
+
+        fn can_impersonate(&self) -> bool {
+            self.privilege >= 10
+        }
+    }
+
+    #[tokio::test]
+    async fn test_impersonate() {
+        let (admin, support, customer) = (
+            Staff { privilege: 100 },
+            Staff { privilege: 10 },
+            Staff { privilege: 1 },
+        );
+        assert!(admin.may_impersonate(&support));
+        assert!(admin.may_impersonate(&customer));
+        assert!(support.may_impersonate(&customer));
+        assert!(!support.may_impersonate(&admin));
+        assert!(!customer.may_impersonate(&customer));
+
+        let req = Request::default();
+        assert!(req
+            .impersonate_checked(true, 7, Duration::minutes(5))
+            .is_err());
+
+        let session = Session::new_authenticated(serde_json::json!({}), 1).unwrap();
+        let req = Request::default().set_session(Some(session));
+        assert!(req
+            .impersonate_checked(false, 7, Duration::minutes(5))
+            .is_err());
+        assert!(req
+            .impersonate_checked(true, 1, Duration::minutes(5))
+            .is_err());
+
+        let response = req
+            .impersonate_checked(true, 7, Duration::minutes(5))
+            .unwrap();
+        let session = response.session().clone().unwrap();
+        let req = Request::default().set_session(Some(session.clone()));
+        assert_eq!(req.user_id().unwrap(), 7);
+        assert_eq!(req.true_user_id().unwrap(), 1);
+        assert_eq!(req.audit("test").true_user_id(), Some(1));
+
+        let response = req.stop_impersonating();
+        let req = Request::default().set_session(response.session().clone());
+        assert_eq!(req.user_id().unwrap(), 1);
+        assert_eq!(req.true_user_id().unwrap(), 1);
+
+        // Impersonation expires while the session stays valid.
+        let mut expired = session;
+        expired.impersonation.as_mut().unwrap().expiration -= 10 * 60;
+        let req = Request::default().set_session(Some(expired));
+        assert_eq!(req.user_id().unwrap(), 1);
+        let response = Response::new().from_request(&req).unwrap();
+        let session = response.session().clone().unwrap();
+        assert!(session.authenticated());
+        assert!(session.impersonation.is_none());
+
+        // Logging in again ends impersonation.
+        let response = req.login(3);
+        assert!(response.session().as_ref().unwrap().impersonation.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_multipart() {
+        let body = "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\nhello world\r\n--b--";
+        let request = format!(
+            "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let req = Request::read(dummy_ip(), request.as_bytes()).await.unwrap();
+
+        let multipart = req.multipart().await.unwrap();
+        let file = multipart.file("file").unwrap();
+        assert_eq!(file.filename(), "a.txt");
+        assert_eq!(file.bytes().unwrap(), b"hello world");
+
+        let mut config = General::default();
+        config.multipart_disk_threshold = 5;
+        let multipart = req.multipart_with(&config).await.unwrap();
+        let file = multipart.file("file").unwrap();
+        assert!(file.path().is_some());
+        assert_eq!(file.read().await.unwrap(), b"hello world");
+
+        config.max_request_size = 10;
+        let err = req.multipart_with(&config).await.unwrap_err();
+        assert_eq!(err.code(), 413);
+
+        let err = Request::default().multipart().await.unwrap_err();
+        assert!(matches!(err, Error::MalformedRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        #[derive(Deserialize, Debug)]
+        struct Filter {
+            ids: Vec<i64>,
+            active: Option<bool>,
+        }
+
+        let req = Request::read(
+            dummy_ip(),
+            &b"GET /users?ids=1&ids=2&active=1 HTTP/1.1\r\n\r\n"[..],
+        )
+        .await
+        .unwrap();
+
+        let filter = req.query::<Filter>().unwrap();
+        assert_eq!(filter.ids, vec![1, 2]);
+        assert_eq!(filter.active, Some(true));
+        assert_eq!(req.query_raw()["ids"], vec!["1", "2"]);
+
+        let req = Request::read(dummy_ip(), &b"GET /users?ids=a HTTP/1.1\r\n\r\n"[..])
+            .await
+            .unwrap();
+        assert_eq!(req.query::<Filter>().unwrap_err().code(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_form_urlencoded() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(deny_unknown_fields)]
+        struct Signup {
+            email: String,
+            age: u8,
+            #[serde(default)]
+            interests: Vec<String>,
+            newsletter: Option<bool>,
+        }
+
+        async fn request(content_type: &str, body: &str) -> Request {
+            let request = format!(
+                "POST /signup HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            );
+            Request::read(dummy_ip(), request.as_bytes()).await.unwrap()
+        }
+
+        let form = "application/x-www-form-urlencoded";
+
+        let req = request(
+            form,
+            "rwf_csrf_token=abc&email=a%40b.com&age=30&interests=rust&interests=web+dev&newsletter=on",
+        )
+        .await;
+        assert_eq!(
+            req.form_urlencoded::<Signup>().unwrap(),
+            Signup {
+                email: "a@b.com".into(),
+                age: 30,
+                interests: vec!["rust".into(), "web dev".into()],
+                newsletter: Some(true),
+            }
+        );
+
+        // Repeated fields are kept by `form_data` too, which returns the last one.
+        assert_eq!(
+            req.form_data().unwrap().get::<String>("interests").unwrap(),
+            "web dev"
+        );
+
+        // Unchecked checkboxes aren't sent.
+        let req = request(form, "email=a%40b.com&age=30").await;
+        let signup = req.form_urlencoded::<Signup>().unwrap();
+        assert!(signup.interests.is_empty());
+        assert_eq!(signup.newsletter, None);
+
+        for (body, field, reason) in [
+            ("email=a%40b.com", "age", "is required"),
+            ("email=a%40b.com&age=old", "age", "expected u8"),
+            ("email=a%40b.com&age=300", "age", "expected u8"),
+            (
+                "email=a%40b.com&age=1&newsletter=maybe",
+                "newsletter",
+                "expected a boolean",
+            ),
+            ("email=a%40b.com&age=1&admin=1", "admin", "is not allowed"),
+        ] {
+            let err = request(form, body)
+                .await
+                .form_urlencoded::<Signup>()
+                .unwrap_err();
+            assert_eq!(err.code(), 400);
+
+            match err {
+                Error::InvalidForm(err) => {
+                    assert_eq!(err.field(), Some(field), "{}", body);
+                    assert!(err.reason().contains(reason), "{}: {}", body, err.reason());
+                }
+                err => panic!("{}: {:?}", body, err),
+            }
+        }
+
+        let err = request("application/json", "{}")
+            .await
+            .form_urlencoded::<Signup>()
+            .unwrap_err();
+        assert!(matches!(err, Error::MalformedRequest(_)));
+    }
 }
@@ -11,14 +11,36 @@
 //!     .html("<h1>Hello world!</h1>");
 //! ```
 
+use base64::{engine::general_purpose, Engine as _};
 use once_cell::sync::Lazy;
+use parking_lot::RwLock;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::future::Future;
+use std::io::SeekFrom;
 use std::marker::Unpin;
-use tokio::io::{AsyncWrite, AsyncWriteExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio::task_local;
+use tracing::error;
 
-use super::{head::Version, Body, Cookie, Cookies, Error, Headers, Request};
-use crate::view::{Template, TurboStream};
+use super::{
+    compression::{self, Encoding},
+    conditional::{self, format_http_date},
+    csv::{CsvHeader, CsvStream, ToCsvRow},
+    error_hook::InternalError,
+    head::Version,
+    json_stream::JsonArray,
+    url::percent_encode,
+    writer::{body_allowed, WriterError},
+    Body, CacheControl, Charset, ContentType, Cookie, Cookies, Error, Headers, Problem, Request,
+    Respond, ResponseWriter,
+};
+use crate::view::{provider, Context, Template, TurboStream};
 use crate::{config::get_config, controller::Session};
 
 static ERROR_TEMPLATE: Lazy<Template> = Lazy::new(|| {
@@ -26,51 +48,195 @@ static ERROR_TEMPLATE: Lazy<Template> = Lazy::new(|| {
     Template::from_str(template).unwrap()
 });
 
-/// Response status, e.g. 404, 200, etc.
-#[derive(Debug)]
-pub enum Status {
-    NotFound,
-    InternalServerError,
-    MethodNotAllowed,
-    Ok,
-    Created,
-    Code(u16),
+/// Error template set with [`Response::set_error_template`].
+static CUSTOM_ERROR_TEMPLATE: Lazy<RwLock<Option<Arc<Template>>>> = Lazy::new(|| RwLock::new(None));
+
+task_local! {
+    static MAX_BODY_SIZE: usize;
 }
 
-impl Status {
-    pub fn code(&self) -> u16 {
-        use Status::*;
+/// Maximum size of bodies of responses created by this task, from the route if it sets one,
+/// or the `max_response_size` setting. `0` means no limit.
+pub(crate) fn max_body_size() -> usize {
+    MAX_BODY_SIZE
+        .try_with(|limit| *limit)
+        .unwrap_or_else(|_| get_config().general.max_response_size)
+}
 
-        match self {
-            NotFound => 404,
-            InternalServerError => 500,
-            MethodNotAllowed => 405,
-            Ok => 200,
-            Created => 201,
-            Code(code) => *code,
+/// Run the future with this maximum size for the bodies of the responses it creates.
+pub(crate) async fn scope_max_body_size<F: Future>(limit: usize, future: F) -> F::Output {
+    MAX_BODY_SIZE.scope(limit, future).await
+}
+
+/// Defines [`Status`] from the table of codes and reason phrases.
+macro_rules! statuses {
+    ($($name:ident = $code:literal, $reason:literal;)*) => {
+        /// Response status, e.g. 404, 200, etc.
+        ///
+        /// Covers the codes registered with IANA. Other codes are represented by [`Status::Code`].
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Status {
+            $(
+                #[doc = concat!("`", $code, " ", $reason, "`")]
+                $name,
+            )*
+            /// Any other code.
+            Code(u16),
         }
-    }
 
+        impl Status {
+            /// Numeric status code, e.g. `404`.
+            pub fn code(&self) -> u16 {
+                match self {
+                    $(Status::$name => $code,)*
+                    Status::Code(code) => *code,
+                }
+            }
+
+            /// Reason phrase sent after the code in the status line, e.g. `Not Found`.
+            /// Other codes get a generic phrase for their class, e.g. `Client Error` for `499`.
+            pub fn reason(&self) -> &'static str {
+                match self {
+                    $(Status::$name => $reason,)*
+                    Status::Code(code) => match code {
+                        100..=199 => "Informational",
+                        200..=299 => "Success",
+                        300..=399 => "Redirection",
+                        400..=499 => "Client Error",
+                        500..=599 => "Server Error",
+                        _ => "Unknown",
+                    },
+                }
+            }
+        }
+
+        impl From<u16> for Status {
+            fn from(code: u16) -> Status {
+                match code {
+                    $($code => Status::$name,)*
+                    code => Status::Code(code),
+                }
+            }
+        }
+    };
+}
+
+statuses! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    EarlyHints = 103, "Early Hints";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    TooEarly = 425, "Too Early";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
+}
+
+impl Status {
+    /// The status is not an error or a redirect.
     pub fn ok(&self) -> bool {
         self.code() < 300
     }
 }
 
-impl From<u16> for Status {
-    fn from(code: u16) -> Status {
-        use Status::*;
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+/// Which layout to use for a response rendered from a template.
+#[derive(Debug, Clone, PartialEq)]
+enum Layout {
+    /// Use the controller layout, if any.
+    Controller,
+    /// Use this layout.
+    Template(PathBuf),
+    /// Don't use a layout.
+    None,
+}
+
+/// Page rendered with [`Response::template`] which
+/// hasn't been placed inside a layout yet.
+#[derive(Debug)]
+struct Page {
+    content: String,
+    context: Context,
+    layout: Layout,
+    // Rendered again once the variables with providers it uses are resolved.
+    pending: Option<Arc<Template>>,
+}
 
-        match code {
-            404 => NotFound,
-            500 => InternalServerError,
-            405 => MethodNotAllowed,
-            200 => Ok,
-            201 => Created,
-            code => Code(code),
+impl Page {
+    /// Layout to render the page in, if any.
+    fn layout(&self, default: Option<&str>) -> Result<Option<Arc<Template>>, crate::view::Error> {
+        match self.layout {
+            Layout::Template(ref path) => Ok(Some(Template::load(path)?)),
+            Layout::Controller => match default {
+                Some(path) => Ok(Some(Template::load(path)?)),
+                None => Ok(None),
+            },
+            Layout::None => Ok(None),
         }
     }
 }
 
+/// Trailer which value is computed after the last chunk of the body is sent.
+struct DeferredTrailer {
+    name: String,
+    value: Box<dyn FnOnce() -> String + Send + Sync>,
+}
+
+impl std::fmt::Debug for DeferredTrailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeferredTrailer")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
 /// HTTP response.
 #[derive(Debug)]
 pub struct Response {
@@ -80,6 +246,15 @@ pub struct Response {
     body: Body,
     cookies: Cookies,
     session: Option<Session>,
+    page: Option<Page>,
+    send_body: bool,
+    error: Option<InternalError>,
+    trailers: Headers,
+    deferred_trailers: Vec<DeferredTrailer>,
+    max_body_size: usize,
+    oversized: Option<usize>,
+    compression: bool,
+    encoding: Option<Encoding>,
 }
 
 impl Default for Response {
@@ -104,6 +279,15 @@ impl Response {
             version: Version::Http1,
             cookies: Cookies::new(),
             session: None,
+            page: None,
+            send_body: true,
+            error: None,
+            trailers: Headers::new(),
+            deferred_trailers: vec![],
+            max_body_size: 0,
+            oversized: None,
+            compression: true,
+            encoding: None,
         }
     }
 
@@ -112,6 +296,9 @@ impl Response {
     /// This is used internally automatically. It makes sure a valid session cookie is
     /// set on all responses.
     pub fn from_request(mut self, request: &Request) -> Result<Self, Error> {
+        // Don't send the body again if the client has it already.
+        self = self.conditional(request);
+
         // Set an anonymous session if none is set on the request.
         if self.session.is_none() && request.session().is_none() {
             self.session = Some(Session::anonymous());
@@ -125,6 +312,15 @@ impl Response {
 
             if let Some(session) = session {
                 if !session.expired() {
+                    if let Some(ref impersonation) = session.impersonation {
+                        if !session.impersonating() {
+                            request
+                                .audit("impersonation_expired")
+                                .detail("target", impersonation.user_id)
+                                .log();
+                        }
+                    }
+
                     let session = session
                         .clone()
                         .renew(get_config().general.session_duration());
@@ -145,15 +341,92 @@ impl Response {
     /// The body will automatically determine the `Content-Type` and `Content-Length` headers.
     /// If you want to override any of them for some reason, make sure to set them _after_ the body
     /// when building a response.
+    ///
+    /// Streams don't have a known length, so they are sent with `Transfer-Encoding: chunked` instead.
+    ///
+    /// Bodies larger than the `max_response_size` setting, or the route's
+    /// [limit](super::Handler::max_response_size), are dropped, and the server responds with
+    /// `500 - Internal Server Error` instead. Streams are cut off once they reach the limit.
     pub fn body(mut self, body: impl Into<Body>) -> Self {
         self.body = body.into();
-        self.headers
-            .insert("content-length".to_string(), self.body.len().to_string());
+        self.max_body_size = max_body_size();
+        self.oversized = None;
+
+        if !self.body.is_stream() && self.max_body_size > 0 && self.body.len() > self.max_body_size
+        {
+            // Don't hold on to the runaway body until the response is replaced.
+            self.oversized = Some(self.body.len());
+            self.body = Body::bytes(vec![]);
+        }
+
+        if self.body.is_stream() {
+            self.headers.remove("content-length");
+            self.headers.insert("transfer-encoding", "chunked");
+        } else {
+            self.headers.remove("transfer-encoding");
+            self.headers
+                .insert("content-length".to_string(), self.body.len().to_string());
+        }
         self.headers
             .insert("content-type", self.body.mime_type().to_string());
         self
     }
 
+    /// Response headers.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Error if the body was larger than the maximum response size. The body was dropped,
+    /// and the server responds with `500 - Internal Server Error` instead.
+    pub fn body_too_large(&self) -> Option<Error> {
+        self.oversized.map(|size| Error::ResponseTooLarge {
+            size,
+            limit: self.max_body_size,
+        })
+    }
+
+    /// The response body, unless it's a file or a stream.
+    pub fn body_bytes(&self) -> Option<&[u8]> {
+        self.body.as_bytes()
+    }
+
+    /// Rewrite the HTML body, e.g. to inject a script. The body is left unchanged if it's not HTML,
+    /// it's a file or compressed, or it's larger than `max_size` bytes. `Content-Length` is updated
+    /// to the size of the new body.
+    pub fn rewrite_html(mut self, max_size: usize, rewrite: impl FnOnce(&mut String)) -> Self {
+        let html = self
+            .headers
+            .get("content-type")
+            .map(|content_type| content_type.starts_with("text/html"))
+            .unwrap_or(false);
+
+        if !html || self.headers.get("content-encoding").is_some() {
+            return self;
+        }
+
+        let mut html = match std::mem::replace(&mut self.body, Body::bytes(vec![])) {
+            Body::Html(html) | Body::Text(html) if html.len() <= max_size => html,
+            Body::Bytes(bytes) if bytes.len() <= max_size => match String::from_utf8(bytes) {
+                Ok(html) => html,
+                Err(err) => {
+                    self.body = Body::Bytes(err.into_bytes());
+                    return self;
+                }
+            },
+            body => {
+                self.body = body;
+                return self;
+            }
+        };
+
+        rewrite(&mut html);
+
+        self.body = Body::Html(html);
+        self.headers.insert("content-length", self.body.len());
+        self
+    }
+
     /// Get response status, e.g. 200 OK.
     pub fn status(&self) -> Status {
         self.code.into()
@@ -197,6 +470,47 @@ impl Response {
         Ok(self.body(Body::Json(body)))
     }
 
+    /// Create a response with an indented JSON body, e.g. for debugging endpoints.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    /// use serde_json::json;
+    ///
+    /// let response = Response::new().json_pretty(json!({ "status": "ok" })).unwrap();
+    /// assert_eq!(response.body_bytes().unwrap(), b"{\n  \"status\": \"ok\"\n}");
+    /// ```
+    pub fn json_pretty(self, body: impl Serialize) -> Result<Self, Error> {
+        let body = serde_json::to_vec_pretty(&body)?;
+        Ok(self.body(Body::Json(body)))
+    }
+
+    /// Create a response with a JSON array, serializing the items while they are sent to the client.
+    /// Large lists, e.g. tens of thousands of rows, don't have to be serialized into memory first.
+    /// The body is sent with `Transfer-Encoding: chunked`.
+    ///
+    /// The status and headers are sent before the items are serialized, so if an item can't be
+    /// serialized, the connection is closed and the client gets an incomplete array.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    ///
+    /// let response = Response::new().json_stream((0..100_000).map(|id| serde_json::json!({ "id": id })));
+    /// assert_eq!(response.headers().get("content-type").unwrap(), "application/json");
+    /// ```
+    pub fn json_stream<I>(self, items: I) -> Self
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + Sync + Unpin + 'static,
+        I::Item: Serialize,
+    {
+        self.stream(JsonArray::new(items))
+            .header("content-type", "application/json")
+    }
+
     /// Create a response with an HTML body.
     ///
     /// # Example
@@ -223,166 +537,1239 @@ impl Response {
         self.body(Body::Text(body.to_string()))
     }
 
-    /// Add a header to the response.
-    ///
-    /// Header name is lowercased automatically. The value is set as-is.
+    /// Create a response with an XML body.
     ///
     /// # Example
     ///
     /// ```
     /// use rwf::http::Response;
     ///
-    /// let response = Response::new()
-    ///     .text("don't cache me")
-    ///     .header("Cache-Control", "no-cache");
+    /// let response = Response::new().xml("<order><id>5</id></order>");
+    /// assert_eq!(
+    ///     response.headers().get("content-type").unwrap(),
+    ///     "application/xml"
+    /// );
     /// ```
-    pub fn header(mut self, name: impl ToString, value: impl ToString) -> Self {
-        self.headers.insert(name.to_string(), value.to_string());
-        self
+    pub fn xml(self, body: impl ToString) -> Self {
+        self.body(Body::Xml(body.to_string()))
     }
 
-    /// Send the response to a stream, serialized as bytes.
-    pub async fn send(mut self, mut stream: impl AsyncWrite + Unpin) -> Result<(), std::io::Error> {
-        let mut response = format!("{} {}\r\n", self.version, self.code)
-            .as_bytes()
-            .to_vec();
-
-        response.extend_from_slice(&self.headers.to_bytes());
-        response.extend_from_slice(&self.cookies.to_headers());
-        response.extend_from_slice(b"\r\n");
-
-        stream.write_all(&response).await?;
-        self.body.send(stream).await
+    /// Create a response with an XML body, serialized from any Rust type implementing
+    /// [`serde::Serialize`]. The root element is named after the type, and the XML declaration is added.
+    ///
+    /// Requires the `xml` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Order {
+    ///     id: i64,
+    /// }
+    ///
+    /// let response = Response::new().to_xml(Order { id: 5 }).unwrap();
+    /// assert_eq!(
+    ///     response.body_bytes().unwrap(),
+    ///     br#"<?xml version="1.0" encoding="UTF-8"?><Order><id>5</id></Order>"#
+    /// );
+    /// ```
+    #[cfg(feature = "xml")]
+    pub fn to_xml(self, body: impl Serialize) -> Result<Self, Error> {
+        let xml = quick_xml::se::to_string(&body)?;
+        Ok(self.xml(format!(r#"<?xml version="1.0" encoding="UTF-8"?>{}"#, xml)))
     }
 
-    /// Mutable reference to response cookies.
-    pub fn cookies(&mut self) -> &mut Cookies {
-        &mut self.cookies
+    /// Create a response with a CSV file, serialized from the rows with serde. Structs get a header row with
+    /// their field names, taken from the first row. Cells are quoted as needed and guarded against formula
+    /// injection, see [`csv::to_csv`](super::csv::to_csv). Call [`Response::download`] to set the file name,
+    /// and [`Response::csv_unguarded`] if the file isn't meant to be opened in a spreadsheet.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize, rwf::macros::CsvHeader)]
+    /// struct Order {
+    ///     id: i64,
+    ///     note: String,
+    /// }
+    ///
+    /// let orders = vec![Order { id: 1, note: "leave at the door, please".into() }];
+    /// let response = Response::new().csv(orders).unwrap().download("orders.csv");
+    /// assert_eq!(
+    ///     response.body_bytes().unwrap(),
+    ///     b"id,note\r\n1,\"leave at the door, please\"\r\n"
+    /// );
+    /// ```
+    pub fn csv<T: Serialize + CsvHeader>(
+        self,
+        rows: impl IntoIterator<Item = T>,
+    ) -> Result<Self, Error> {
+        self.csv_body(rows, true)
     }
 
-    /// Set a private (encrypted) cookie on the response.
-    pub fn private_cookie(mut self, cookie: Cookie) -> Result<Self, Error> {
-        self.cookies.add_private(cookie)?;
-        Ok(self)
+    /// Same as [`Response::csv`], without the formula guard, so cells are written as they are, except for quoting.
+    pub fn csv_unguarded<T: Serialize + CsvHeader>(
+        self,
+        rows: impl IntoIterator<Item = T>,
+    ) -> Result<Self, Error> {
+        self.csv_body(rows, false)
     }
 
-    /// Set a cookie on the response.
-    pub fn cookie(mut self, cookie: Cookie) -> Self {
-        self.cookies.add(cookie);
-        self
+    fn csv_body<T: Serialize + CsvHeader>(
+        self,
+        rows: impl IntoIterator<Item = T>,
+        formula_guard: bool,
+    ) -> Result<Self, Error> {
+        let csv = super::csv::to_csv(rows, formula_guard)?;
+        Ok(self
+            .body(Body::Text(csv))
+            .header("content-type", "text/csv; charset=utf-8"))
     }
 
-    /// Set the session on the response.
-    pub fn set_session(mut self, session: Session) -> Self {
-        self.session = Some(session);
+    /// Send the text, HTML or XML body in this charset instead of UTF-8. The body is encoded, and
+    /// the charset is added to the `Content-Type` header. Characters that can't be encoded are replaced with `?`.
+    ///
+    /// Call it after setting the body. Other bodies are sent unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::{Charset, Response};
+    ///
+    /// let response = Response::new().text("café").charset(Charset::Latin1);
+    ///
+    /// assert_eq!(response.body_bytes(), Some(&b"caf\xe9"[..]));
+    /// assert_eq!(
+    ///     response.headers().get("content-type").unwrap(),
+    ///     "text/plain; charset=iso-8859-1"
+    /// );
+    /// ```
+    pub fn charset(mut self, charset: Charset) -> Self {
+        let encoded = match self.body {
+            Body::Text(ref text) | Body::Html(ref text) | Body::Xml(ref text) => {
+                charset.encode(text)
+            }
+            _ => return self,
+        };
+
+        let media_type = self
+            .headers
+            .get("content-type")
+            .map(|content_type| ContentType::parse(content_type).media_type().to_string())
+            .unwrap_or_else(|| self.body.mime_type().to_string());
+
+        self.body = Body::Bytes(encoded);
+        self.headers.insert("content-length", self.body.len());
+        self.headers.insert(
+            "content-type",
+            format!("{}; charset={}", media_type, charset.name()),
+        );
         self
     }
 
-    /// Get the response's session, if any is set.
-    pub fn session(&self) -> &Option<Session> {
-        &self.session
+    /// Create a response with the contents of a file. The `Content-Type` is guessed from the file extension,
+    /// and the file is sent without loading it into memory.
+    ///
+    /// Returns `404 - Not Found` if the file doesn't exist or is a directory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use rwf::http::Response;
+    ///
+    /// let response = Response::file("Cargo.toml").await.unwrap();
+    /// assert_eq!(response.headers().get("content-type").unwrap(), "application/octet-stream");
+    ///
+    /// let response = Response::file("missing.pdf").await.unwrap();
+    /// assert_eq!(response.status().code(), 404);
+    /// # });
+    /// ```
+    pub async fn file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        match Self::open(path.as_ref()).await? {
+            Some(body) => Ok(Self::new().body(body)),
+            None => Ok(Self::not_found()),
+        }
     }
 
-    /// Response is an agreement to upgrade the connection to use the WebSocket protocol.
-    pub fn websocket_upgrade(&self) -> bool {
-        self.code == 101 && self.headers.get("upgrade").map(|s| s == "websocket") == Some(true)
+    /// Create a response with the contents of a file, which the browser downloads and saves as `filename`
+    /// instead of displaying it.
+    ///
+    /// Returns `404 - Not Found` if the file doesn't exist or is a directory.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let response = Response::attachment("exports/1234.csv", "orders.csv").await?;
+    /// ```
+    pub async fn attachment(path: impl AsRef<Path>, filename: &str) -> Result<Self, Error> {
+        match Self::open(path.as_ref()).await? {
+            Some(body) => Ok(Self::new().body(body).download(filename)),
+            None => Ok(Self::not_found()),
+        }
     }
 
-    /// Return a response containing one or multiple Turbo Streams.
-    pub fn turbo_stream(self, body: &[TurboStream]) -> Self {
-        let body = body
-            .iter()
-            .map(|b| b.clone().render())
-            .collect::<Vec<_>>()
-            .join("\n");
-        self.html(body)
-            .header("content-type", "text/vnd.turbo-stream.html")
+    /// Ask the browser to download the response and save it as `filename`, by setting
+    /// the `Content-Disposition` header.
+    pub fn download(self, filename: &str) -> Self {
+        self.header("content-disposition", content_disposition(filename))
     }
 
-    /// HTTP `404 - Not Found`.
-    pub fn not_found() -> Self {
-        Self::error_pretty("404 - Not Found", "").code(404)
-    }
+    /// Open the file, if it exists.
+    async fn open(path: &Path) -> Result<Option<Body>, Error> {
+        let file = match File::open(path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
 
-    /// HTTP `405 - Method Not Allowed`.
-    pub fn method_not_allowed() -> Self {
-        Self::error_pretty("405 - Method Not Allowed", "").code(405)
+        let metadata = file.metadata().await?;
+
+        if metadata.is_file() {
+            Ok(Some(Body::from((path.to_path_buf(), file, metadata))))
+        } else {
+            Ok(None)
+        }
     }
 
-    /// HTTP `400 - Bad Request`.
-    pub fn bad_request() -> Self {
-        Self::error_pretty("400 - Bad Request", "").code(400)
+    /// Create a response with a body read from the reader while it's sent to the client, e.g. a large file export.
+    /// The body isn't loaded into memory, and is sent with `Transfer-Encoding: chunked`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use rwf::http::Response;
+    ///
+    /// let export = tokio::fs::File::open("Cargo.toml").await.unwrap();
+    /// let response = Response::new()
+    ///     .stream(export)
+    ///     .header("content-type", "text/csv");
+    /// # });
+    /// ```
+    pub fn stream(self, reader: impl AsyncRead + Send + Sync + Unpin + 'static) -> Self {
+        self.body(Body::stream(reader))
     }
 
-    /// CSRF token validation error. Returns `400 - Bad Request`.
-    pub fn csrf_error() -> Self {
-        Self::error_pretty(
-            "400 - CSRF Token Validation Failed",
-            "The supplied CSRF token is not valid. Reload the page to get a new one.",
-        )
-        .code(400)
+    /// Create a response with a CSV file, written from the rows of the stream while it's sent to the client.
+    /// Cells are quoted as needed and guarded against formula injection, see [`csv`](super::csv).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    /// use futures_util::stream;
+    ///
+    /// let rows = stream::iter(vec![["1", "alice@example.com"], ["2", "bob@example.com"]]);
+    /// let response = Response::csv_stream(["id", "email"], rows).download("users.csv");
+    /// assert_eq!(response.headers().get("content-type").unwrap(), "text/csv; charset=utf-8");
+    /// ```
+    pub fn csv_stream<S>(headers: impl IntoIterator<Item = impl ToString>, rows: S) -> Self
+    where
+        S: futures_util::Stream + Send + Sync + Unpin + 'static,
+        S::Item: ToCsvRow,
+    {
+        Self::new().stream_csv(CsvStream::new(headers, rows))
     }
 
-    /// HTTP `501 - Not Implemented`.
-    pub fn not_implemented() -> Self {
-        Self::error_pretty("501 - Not Implemented", "").code(501)
+    /// Create a response with a streamed CSV file, e.g. one starting with a byte order mark for Excel:
+    ///
+    /// ```rust,ignore
+    /// let response = Response::new().stream_csv(CsvStream::new(headers, rows).bom(true));
+    /// ```
+    pub fn stream_csv<S>(self, csv: CsvStream<S>) -> Self
+    where
+        S: futures_util::Stream + Send + Sync + Unpin + 'static,
+        S::Item: ToCsvRow,
+    {
+        self.stream(csv)
+            .header("content-type", "text/csv; charset=utf-8")
     }
 
-    /// HTTP `403 - Forbidden`.
-    pub fn forbidden() -> Self {
-        Self::error_pretty("403 - Forbidden", "").code(403)
+    /// Set the `ETag` header. The value is quoted if it isn't already, e.g. `v1` becomes `"v1"`. Requests with a
+    /// matching `If-None-Match` header get `304 - Not Modified` instead of the response. See [`conditional`](super::conditional).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    ///
+    /// let response = Response::new()
+    ///     .text("version 5")
+    ///     .etag("v5");
+    /// assert_eq!(response.headers().get("etag").unwrap(), "\"v5\"");
+    /// ```
+    pub fn etag(self, etag: impl ToString) -> Self {
+        let etag = etag.to_string();
+        let etag = if etag.starts_with('"') || etag.starts_with("W/\"") {
+            etag
+        } else {
+            format!("\"{}\"", etag.replace('"', ""))
+        };
+
+        self.header("etag", etag)
     }
 
-    /// HTTP `413 - Content Too Large`.
-    pub fn content_too_large() -> Self {
-        Self::error_pretty("413 - Content Too Large", "").code(413)
+    /// Set the `Last-Modified` header. Requests with an `If-Modified-Since` header
+    /// at or after this time get `304 - Not Modified` instead of the response.
+    pub fn last_modified(self, last_modified: OffsetDateTime) -> Self {
+        self.header("last-modified", format_http_date(last_modified))
     }
 
-    /// HTTP `500 - Internal Server Error`. Requires the error that was caught,
-    /// for debugging purposes. The error is shown in development (debug) and hidden in production (release).
-    pub fn internal_error(err: impl std::error::Error) -> Self {
-        // TODO:
-        #[cfg(debug_assertions)]
-        let err = format!("{}", err);
+    /// Let clients cache the response, and answer with `304 - Not Modified` if they have the current version.
+    ///
+    /// Sets a weak `ETag` computed from the hash of the body. Files get an `ETag` from their size and modification time instead,
+    /// and a `Last-Modified` header, so they don't have to be read. Validators set explicitly are kept, and streams are left unchanged,
+    /// since their body isn't known in advance.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    ///
+    /// let response = Response::new()
+    ///     .html("<h1>Pricing</h1>")
+    ///     .cacheable();
+    /// assert!(response.headers().get("etag").unwrap().starts_with("W/"));
+    /// ```
+    pub fn cacheable(mut self) -> Self {
+        let (etag, last_modified) = match self.body {
+            Body::File { ref metadata, .. } => match metadata.modified() {
+                Ok(modified) => {
+                    let modified = OffsetDateTime::from(modified);
+                    (
+                        format!(
+                            "W/\"{:x}-{:x}\"",
+                            metadata.len(),
+                            modified.unix_timestamp_nanos()
+                        ),
+                        Some(modified),
+                    )
+                }
+                Err(_) => return self,
+            },
 
-        #[cfg(not(debug_assertions))]
-        let err = {
-            let _ = err;
-            ""
+            ref body => match body.as_bytes() {
+                Some(bytes) => {
+                    let hash = Sha256::digest(bytes);
+                    (
+                        format!(
+                            "W/\"{}\"",
+                            general_purpose::URL_SAFE_NO_PAD.encode(&hash[..16])
+                        ),
+                        None,
+                    )
+                }
+                None => return self,
+            },
         };
 
-        Self::error_pretty("500 - Internal Server Error", &err)
-    }
+        if self.headers.get("etag").is_none() {
+            self.headers.insert("etag", etag);
+        }
 
-    /// Use the internal template to render a better looking error page.
-    /// Returns HTTP `500 - Internal Server Error`.
-    pub fn error_pretty(title: &str, message: &str) -> Self {
-        let body = ERROR_TEMPLATE
-            .render([("title", title), ("message", message)])
-            .unwrap();
+        if let Some(last_modified) = last_modified {
+            if self.headers.get("last-modified").is_none() {
+                self.headers
+                    .insert("last-modified", format_http_date(last_modified));
+            }
+        }
 
-        Self::new().html(body).code(500)
+        self
     }
 
-    /// HTTP `401 - Unauthorized`.
-    pub fn unauthorized(auth: &str) -> Self {
-        Self::error_pretty("401 - Unauthorized", "")
-            .code(401)
-            .header("www-authenticate", auth)
-    }
+    /// Replace the response with an empty `304 - Not Modified` if the client has the current version of it,
+    /// according to its `ETag` and `Last-Modified` headers. Done automatically for responses returned by controllers.
+    pub fn conditional(mut self, request: &Request) -> Self {
+        if self.code != 200 {
+            return self;
+        }
 
-    /// HTTP `429 - Too Many`.
-    pub fn too_many() -> Self {
-        Self::error_pretty("429 - Too Many", "").code(429)
+        let not_modified = conditional::not_modified(
+            request,
+            self.headers.get("etag").map(|etag| etag.as_str()),
+            self.headers
+                .get("last-modified")
+                .map(|last_modified| last_modified.as_str()),
+        );
+
+        if not_modified {
+            self.body = Body::bytes(vec![]);
+            self.headers.remove("content-length");
+            self.headers.remove("transfer-encoding");
+            self.code = 304;
+        }
+
+        self
+    }
+
+    /// Answer a [range request](super::range): if the client asked for a single range of bytes, send only
+    /// those with `206 - Partial Content`, or `416 - Range Not Satisfiable` if the range is outside the body.
+    /// Requests for multiple ranges get the whole body.
+    ///
+    /// Only successful responses to `GET` requests with a file or bytes body are changed. They
+    /// get the `Accept-Ranges: bytes` header, so clients know they can ask for ranges.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let response = Response::file("media/intro.mp4").await?.range(request);
+    /// ```
+    pub fn range(mut self, request: &Request) -> Self {
+        if self.code != 200
+            || !request.get()
+            || self.body.is_stream()
+            || self.headers.get("content-encoding").is_some()
+        {
+            return self;
+        }
+
+        self.headers.insert("accept-ranges", "bytes");
+
+        let range = match request.ranges().as_deref() {
+            Some([range]) => *range,
+            _ => return self,
+        };
+
+        if !self.if_range(request) {
+            return self;
+        }
+
+        let len = self.body.len() as u64;
+
+        match range.resolve(len) {
+            Some(range) => {
+                self.headers.insert(
+                    "content-range",
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, len),
+                );
+                self.body = self.body.slice(range);
+                self.headers.insert("content-length", self.body.len());
+                self.code(206)
+            }
+
+            None => {
+                self.headers
+                    .insert("content-range", format!("bytes */{}", len));
+                self.headers.insert("content-length", 0);
+                self.body = Body::bytes(vec![]);
+                self.code(416)
+            }
+        }
+    }
+
+    /// The `If-Range` header, if any, matches the response: a strong `ETag` equal to the response's `ETag`,
+    /// or a date equal to its `Last-Modified`.
+    fn if_range(&self, request: &Request) -> bool {
+        match request.header("if-range") {
+            Some(if_range) if if_range.starts_with('"') => {
+                self.headers.get("etag").map(|etag| etag.as_str()) == Some(if_range.as_str())
+            }
+            Some(if_range) if if_range.starts_with("W/") => false,
+            Some(if_range) => {
+                self.headers
+                    .get("last-modified")
+                    .map(|last_modified| last_modified.as_str())
+                    == Some(if_range.as_str())
+            }
+            None => true,
+        }
+    }
+
+    /// Create a response by rendering a template.
+    ///
+    /// The `Content-Type` is set based on the template file extension. If the controller
+    /// has a [layout](crate::controller::Controller::layout), the page is rendered inside of it
+    /// after the controller returns the response, and the `Content-Type` of the layout is used instead.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let response = Response::new()
+    ///     .template("templates/index.html", &context!("title" => "Home"))?;
+    /// ```
+    pub fn template(
+        self,
+        path: impl AsRef<Path> + Copy,
+        context: impl TryInto<Context, Error = crate::view::Error>,
+    ) -> Result<Self, crate::view::Error> {
+        let template = Template::load(path)?;
+        let mut context: Context = context.try_into()?;
+        context.track_providers();
+        let content = template.render(&context)?;
+        let pending = !context.wanted_providers().is_empty();
+
+        let mut response = self
+            .body(Body::Text(content.clone()))
+            .header("content-type", template.content_type());
+        response.page = Some(Page {
+            content,
+            context,
+            layout: Layout::Controller,
+            pending: pending.then_some(template),
+        });
+
+        Ok(response)
+    }
+
+    /// Render the page created with [`Response::template`] inside this layout
+    /// instead of the controller's layout. This allows to render the same page with different
+    /// layouts, e.g. one for the web and one for email.
+    ///
+    /// Call this after [`Response::template`].
+    pub fn layout(mut self, layout: impl AsRef<Path>) -> Self {
+        if let Some(ref mut page) = self.page {
+            page.layout = Layout::Template(layout.as_ref().to_owned());
+        }
+        self
+    }
+
+    /// Don't use a layout for the page created with [`Response::template`], even
+    /// if the controller has one.
+    pub fn without_layout(mut self) -> Self {
+        if let Some(ref mut page) = self.page {
+            page.layout = Layout::None;
+        }
+        self
+    }
+
+    /// Render the page created with [`Response::template`] inside its layout. If a layout
+    /// wasn't specified, the provided default (typically the controller's) layout is used.
+    ///
+    /// This is called automatically by [`crate::controller::Controller::handle_internal`].
+    /// Responses not created from templates are not modified.
+    pub fn render_layout(mut self, default: Option<&str>) -> Result<Self, crate::view::Error> {
+        let page = match self.page.take() {
+            Some(page) => page,
+            None => return Ok(self),
+        };
+
+        let layout = match page.layout(default)? {
+            Some(layout) => layout,
+            None => return Ok(self),
+        };
+
+        let mut context = page.context;
+        context.set("yield", crate::view::Value::SafeString(page.content))?;
+        let content = layout.render(&context)?;
+
+        Ok(self
+            .body(Body::Text(content))
+            .header("content-type", layout.content_type()))
+    }
+
+    /// Like [`Response::render_layout`], resolving the variables with [providers](crate::view::provider)
+    /// used by the page and its layout for the request.
+    pub(crate) async fn render_page(
+        mut self,
+        default: Option<&str>,
+        request: &Request,
+    ) -> Result<Self, crate::view::Error> {
+        let mut page = match self.page.take() {
+            Some(page) => page,
+            None => return Ok(self),
+        };
+
+        if let Some(template) = page.pending.take() {
+            page.content = provider::render(&template, &mut page.context, request).await?;
+            self = self.body(Body::Text(page.content.clone()));
+        }
+
+        let layout = match page.layout(default)? {
+            Some(layout) => layout,
+            None => return Ok(self),
+        };
+
+        let mut context = page.context;
+        context.set("yield", crate::view::Value::SafeString(page.content))?;
+        let content = provider::render(&layout, &mut context, request).await?;
+
+        Ok(self
+            .body(Body::Text(content))
+            .header("content-type", layout.content_type()))
+    }
+
+    /// Add a header to the response.
+    ///
+    /// Header name is lowercased automatically. The value is set as-is.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    ///
+    /// let response = Response::new()
+    ///     .text("don't cache me")
+    ///     .header("Cache-Control", "no-cache");
+    /// ```
+    pub fn header(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Send this header after the body, e.g. `Server-Timing` or a checksum. The header
+    /// is declared in the `Trailer` header.
+    ///
+    /// Trailers can only be sent after a chunk-encoded body, i.e. a [stream](Body::stream).
+    /// Otherwise, sending the response fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use rwf::http::{Body, Response};
+    ///
+    /// let mut wire = vec![];
+    /// Response::new()
+    ///     .body(Body::stream(&b"hello"[..]))
+    ///     .trailer("Server-Timing", "db;dur=53")
+    ///     .header_order(["content-type"])
+    ///     .send(&mut wire)
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert_eq!(
+    ///     wire,
+    ///     b"HTTP/1.1 200 OK\r\n\
+    ///     content-type: application/octet-stream\r\n\
+    ///     connection: keep-alive\r\n\
+    ///     server: rwf\r\n\
+    ///     trailer: server-timing\r\n\
+    ///     transfer-encoding: chunked\r\n\r\n\
+    ///     5\r\nhello\r\n\
+    ///     0\r\nserver-timing: db;dur=53\r\n\r\n"
+    /// );
+    /// # });
+    /// ```
+    pub fn trailer(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.trailers.insert(name, value);
+        self.declare_trailers();
+        self
+    }
+
+    /// Send this header after the body, with the value computed once the last chunk of the body
+    /// is sent, e.g. the time it took to stream the body. See [`Response::trailer`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// use rwf::http::{Body, Response};
+    ///
+    /// let mut wire = vec![];
+    /// Response::new()
+    ///     .body(Body::stream(&b"hello"[..]))
+    ///     .deferred_trailer("X-Sent", || "yes".to_string())
+    ///     .send(&mut wire)
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert!(wire.ends_with(b"0\r\nx-sent: yes\r\n\r\n"));
+    /// # });
+    /// ```
+    pub fn deferred_trailer(
+        mut self,
+        name: impl ToString,
+        value: impl FnOnce() -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.deferred_trailers.push(DeferredTrailer {
+            name: name.to_string().to_lowercase(),
+            value: Box::new(value),
+        });
+        self.declare_trailers();
+        self
+    }
+
+    /// List the trailers in the `Trailer` header.
+    fn declare_trailers(&mut self) {
+        let mut names = self
+            .trailers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .chain(
+                self.deferred_trailers
+                    .iter()
+                    .map(|trailer| trailer.name.as_str()),
+            )
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        let names = names.join(", ");
+        self.headers.insert("trailer", names);
+    }
+
+    /// Does the response have a body which can be followed by trailers?
+    pub(crate) fn is_stream(&self) -> bool {
+        self.send_body && self.body.is_stream()
+    }
+
+    /// Headers sent after the body.
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    /// Send these headers first, in this order. Other headers are sent in alphabetical order.
+    /// See [`Headers::set_order`].
+    pub fn header_order(mut self, names: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.headers.set_order(names);
+        self
+    }
+
+    /// Set the `Cache-Control` header. Calling it again replaces the previous rules.
+    ///
+    /// Redirects are sent with `no-cache`, unless the rules are set before or after the redirect.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::{CacheControl, Response};
+    /// use time::Duration;
+    ///
+    /// let response = Response::new()
+    ///     .redirect("/latest")
+    ///     .cache(CacheControl::new().public().max_age(Duration::hours(1)));
+    ///
+    /// assert_eq!(
+    ///     response.headers().get("cache-control").unwrap(),
+    ///     "public, max-age=3600"
+    /// );
+    /// ```
+    pub fn cache(self, cache_control: CacheControl) -> Self {
+        self.header("cache-control", cache_control)
+    }
+
+    /// Add a header name to the `Vary` header, which tells caches the response depends on this request header.
+    /// Names already in the header aren't added again.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    ///
+    /// let response = Response::new()
+    ///     .vary("accept")
+    ///     .vary("Accept-Encoding")
+    ///     .vary("accept");
+    /// assert_eq!(
+    ///     response.headers().get("vary").unwrap(),
+    ///     "accept, accept-encoding"
+    /// );
+    /// ```
+    pub fn vary(mut self, name: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        let vary = match self.headers.get("vary") {
+            Some(vary)
+                if vary.split(',').any(|value| {
+                    value.trim() == "*" || value.trim().eq_ignore_ascii_case(&name)
+                }) =>
+            {
+                return self;
+            }
+            Some(vary) => format!("{}, {}", vary, name),
+            None => name,
+        };
+        self.headers.insert("vary", vary);
+        self
+    }
+
+    /// Don't compress this response, e.g. because it contains secrets next to
+    /// text an attacker controls. See [compression](super::compression).
+    pub fn no_compress(mut self) -> Self {
+        self.compression = false;
+        self
+    }
+
+    /// Compress the body with gzip if the client accepts it, and it's text worth compressing.
+    /// Done automatically for all responses, unless disabled with the `compression` setting or [`Response::no_compress`].
+    ///
+    /// Bodies smaller than [`MIN_SIZE`](compression::MIN_SIZE) bytes, files and ranges are sent as they are.
+    /// Streams are compressed while they are sent, see [compression](super::compression).
+    pub fn compress(mut self, request: &Request) -> Self {
+        if !self.compression
+            || !get_config().general.compression
+            || !body_allowed(self.code)
+            || self.code == 206
+            || self.headers.get("content-encoding").is_some()
+            || self.headers.get("content-range").is_some()
+            || matches!(self.body, Body::File { .. })
+        {
+            return self;
+        }
+
+        let compressible = self
+            .headers
+            .get("content-type")
+            .map(|content_type| compression::compressible(content_type))
+            .unwrap_or(false);
+        if !compressible {
+            return self;
+        }
+
+        // Caches must not send a compressed response to clients which don't accept it, or the other way around.
+        self = self.vary("accept-encoding");
+
+        let encoding = match Encoding::accepted(request) {
+            Some(encoding) => encoding,
+            None => return self,
+        };
+
+        if self.body.is_stream() {
+            self.headers.insert("content-encoding", encoding.name());
+            self.encoding = Some(encoding);
+            return self;
+        }
+
+        let compressed = match self.body.as_bytes() {
+            Some(bytes) if bytes.len() >= compression::MIN_SIZE => {
+                match compression::compress(encoding, bytes) {
+                    Ok(compressed) => compressed,
+                    Err(_) => return self,
+                }
+            }
+            _ => return self,
+        };
+
+        self.body = Body::bytes(compressed);
+        self.headers.insert("content-encoding", encoding.name());
+        self.headers.insert("content-length", self.body.len());
+
+        // The compressed body isn't byte-for-byte the same as the original.
+        if let Some(etag) = self.headers.get("etag") {
+            if etag.starts_with('"') {
+                let etag = format!("W/{}", etag);
+                self.headers.insert("etag", etag);
+            }
+        }
+
+        self
+    }
+
+    /// Send only the headers, e.g. in reply to a `HEAD` request. `Content-Length` is the length
+    /// of the body that would have been sent. Done automatically for `HEAD` requests.
+    pub fn without_body(mut self) -> Self {
+        self.send_body = false;
+        self
+    }
+
+    /// Send the response to a stream, serialized as bytes.
+    ///
+    /// `1xx`, `204 - No Content` and `304 - Not Modified` responses are sent without
+    /// a body and without `Content-Length`.
+    pub async fn send(self, stream: impl AsyncWrite + Unpin) -> Result<usize, std::io::Error> {
+        let mut writer = ResponseWriter::new(stream);
+        writer.status(self.code)?.version(self.version)?;
+        writer.max_body_size(self.max_body_size);
+        *writer.headers_mut()? = self.headers;
+        *writer.cookies_mut()? = self.cookies;
+
+        let status = self.code;
+        let body = body_allowed(status);
+
+        // Trailers follow the last chunk of the body.
+        let has_trailers =
+            self.trailers.iter().next().is_some() || !self.deferred_trailers.is_empty();
+        if has_trailers && self.send_body && (!body || !self.body.is_stream()) {
+            return Err(WriterError::TrailersNotAllowed.into());
+        }
+
+        if !body {
+            // 1xx, 204 and 304 responses don't have a body, or its length and type.
+            let headers = writer.headers_mut()?;
+            headers.remove("content-length");
+            headers.remove("transfer-encoding");
+            if status != 304 {
+                headers.remove("content-type");
+            }
+        } else if !self.body.is_stream() {
+            // The body size is known, so it doesn't need to be chunk-encoded.
+            let headers = writer.headers_mut()?;
+            if headers.get("content-length").is_none() {
+                headers.insert("content-length", self.body.len());
+            }
+        }
+
+        if !body || !self.send_body {
+            writer.without_body()?;
+            writer.finish().await?;
+
+            return Ok(writer.bytes_written());
+        }
+
+        writer.write_head().await?;
+
+        match self.body {
+            Body::File {
+                mut file,
+                range: Some(range),
+                ..
+            } => {
+                file.seek(SeekFrom::Start(range.start)).await?;
+                writer.copy_body(file.take(range.end - range.start)).await?;
+            }
+            Body::File { file, .. } => {
+                writer.copy_body(file).await?;
+            }
+            Body::Stream(reader) => match self.encoding {
+                Some(encoding) => {
+                    compression::copy_body(&mut writer, reader, encoding).await?;
+                }
+                None => {
+                    writer.copy_body(reader).await?;
+                }
+            },
+            body => {
+                if let Some(bytes) = body.as_bytes() {
+                    writer.write_body_chunk(bytes).await?;
+                }
+            }
+        }
+
+        let mut trailers = self.trailers;
+        for trailer in self.deferred_trailers {
+            trailers.insert(trailer.name, (trailer.value)());
+        }
+
+        writer.finish_with_trailers(&trailers).await?;
+
+        Ok(writer.bytes_written())
+    }
+
+    /// Mutable reference to response cookies.
+    pub fn cookies(&mut self) -> &mut Cookies {
+        &mut self.cookies
+    }
+
+    /// Set a private (encrypted) cookie on the response.
+    pub fn private_cookie(mut self, cookie: Cookie) -> Result<Self, Error> {
+        self.cookies.add_private(cookie)?;
+        Ok(self)
+    }
+
+    /// Set a signed cookie on the response. The client can read the value, but not change it.
+    /// Read it back with [`Request::signed_cookie`](super::Request::signed_cookie).
+    pub fn signed_cookie(mut self, cookie: Cookie) -> Self {
+        self.cookies.add_signed(cookie);
+        self
+    }
+
+    /// Set a cookie on the response.
+    pub fn cookie(mut self, cookie: Cookie) -> Self {
+        self.cookies.add(cookie);
+        self
+    }
+
+    /// Set the session on the response.
+    pub fn set_session(mut self, session: Session) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Get the response's session, if any is set.
+    pub fn session(&self) -> &Option<Session> {
+        &self.session
+    }
+
+    /// Response is an agreement to upgrade the connection to use the WebSocket protocol.
+    pub fn websocket_upgrade(&self) -> bool {
+        self.code == 101 && self.headers.get("upgrade").map(|s| s == "websocket") == Some(true)
+    }
+
+    /// Return a response containing one or multiple Turbo Streams.
+    pub fn turbo_stream(self, body: &[TurboStream]) -> Self {
+        let body = body
+            .iter()
+            .map(|b| b.clone().render())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.html(body)
+            .header("content-type", "text/vnd.turbo-stream.html")
+    }
+
+    /// HTTP `204 - No Content`, e.g. after deleting a resource.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    ///
+    /// let response = Response::no_content();
+    /// assert_eq!(response.status().code(), 204);
+    /// ```
+    pub fn no_content() -> Self {
+        let mut response = Self::new().code(204);
+        response.headers.remove("content-type");
+        response
+    }
+
+    /// Respond with the format the client prefers, e.g. HTML to browsers and JSON to API clients,
+    /// using the `Accept` header. See [`Respond`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::http::{Error, Request, Response};
+    /// # let request = Request::default();
+    /// let response = Response::negotiate(&request)
+    ///     .html(|| Ok(Response::new().html("<h1>Alice</h1>")))
+    ///     .json(|| Response::new().json(serde_json::json!({"name": "Alice"})))
+    ///     .fallback(|| Ok::<_, Error>(Response::new().text("Alice")))?;
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn negotiate<'a, E>(request: &Request) -> Respond<'a, E> {
+        Respond::new(request)
+    }
+
+    /// HTTP `404 - Not Found`.
+    pub fn not_found() -> Self {
+        Self::error_page(404, "404 - Not Found", "")
+    }
+
+    /// HTTP `405 - Method Not Allowed`.
+    pub fn method_not_allowed() -> Self {
+        Self::error_page(405, "405 - Method Not Allowed", "")
+    }
+
+    /// HTTP `400 - Bad Request`.
+    pub fn bad_request() -> Self {
+        Self::error_page(400, "400 - Bad Request", "")
+    }
+
+    /// CSRF token validation error. Returns `400 - Bad Request`.
+    pub fn csrf_error() -> Self {
+        Self::error_page(
+            400,
+            "400 - CSRF Token Validation Failed",
+            "The supplied CSRF token is not valid. Reload the page to get a new one.",
+        )
+    }
+
+    /// HTTP `501 - Not Implemented`.
+    pub fn not_implemented() -> Self {
+        Self::error_page(501, "501 - Not Implemented", "")
+    }
+
+    /// HTTP `403 - Forbidden`.
+    pub fn forbidden() -> Self {
+        Self::error_page(403, "403 - Forbidden", "")
+    }
+
+    /// HTTP `412 - Precondition Failed`, e.g. when the resource changed since the client last saw it.
+    /// See [`Request::precondition`].
+    pub fn precondition_failed() -> Self {
+        Self::error_page(412, "412 - Precondition Failed", "")
+    }
+
+    /// HTTP `413 - Content Too Large`.
+    pub fn content_too_large() -> Self {
+        Self::error_page(413, "413 - Content Too Large", "")
+    }
+
+    /// HTTP `406 - Not Acceptable`, e.g. when the client doesn't accept any format of the response.
+    pub fn not_acceptable() -> Self {
+        Self::error_page(406, "406 - Not Acceptable", "")
+    }
+
+    /// HTTP `415 - Unsupported Media Type`, e.g. for a body in a character set that isn't supported.
+    pub fn unsupported_media_type() -> Self {
+        Self::error_page(415, "415 - Unsupported Media Type", "")
+    }
+
+    /// HTTP `422 - Unprocessable Content`, e.g. for a body which doesn't match the expected schema.
+    pub fn unprocessable_content() -> Self {
+        Self::error_page(422, "422 - Unprocessable Content", "")
+    }
+
+    /// HTTP `500 - Internal Server Error`. Requires the error that was caught,
+    /// for debugging purposes. The error is shown in development (debug) and hidden in production (release).
+    ///
+    /// The error is passed to the [error hook](super::error_hook) when the response is returned by a controller.
+    pub fn internal_error(err: impl std::error::Error) -> Self {
+        let error = InternalError::new(&err);
+
+        #[cfg(debug_assertions)]
+        let err = format!("{}", err);
+
+        #[cfg(not(debug_assertions))]
+        let err = {
+            let _ = err;
+            ""
+        };
+
+        let mut response = Self::error_pretty("500 - Internal Server Error", &err);
+        response.error = Some(error);
+        response
+    }
+
+    /// Error this response was built from with [`Response::internal_error`], if any.
+    pub fn error(&self) -> Option<&InternalError> {
+        self.error.as_ref()
+    }
+
+    /// Use the error template to render a better looking error page.
+    /// Returns HTTP `500 - Internal Server Error`.
+    pub fn error_pretty(title: &str, message: &str) -> Self {
+        Self::error_page(500, title, message)
+    }
+
+    /// Render an error page with this status code. The page uses the template set with
+    /// [`Response::set_error_template`] or the `error_template_path` setting, and the built-in
+    /// template if neither is set or the custom template fails to render.
+    pub fn error_page(code: u16, title: &str, message: &str) -> Self {
+        let template = Self::error_template();
+        let body = Self::render_error(template.as_deref(), code, title, message);
+
+        Self::new().html(body).code(code)
+    }
+
+    fn render_error(template: Option<&Template>, code: u16, title: &str, message: &str) -> String {
+        let custom = template.and_then(|template| {
+            let mut context = Context::new();
+            context.set("title", title).ok()?;
+            context.set("message", message).ok()?;
+            context.set("code", code as i64).ok()?;
+
+            match template.render(&context) {
+                Ok(body) => Some(body),
+                Err(err) => {
+                    error!("error template failed to render: {}", err);
+                    None
+                }
+            }
+        });
+
+        custom.unwrap_or_else(|| {
+            ERROR_TEMPLATE
+                .render([("title", title), ("message", message)])
+                .expect("built-in error template")
+        })
+    }
+
+    /// Render error pages, e.g. [`Response::not_found`], with this template instead of the built-in one.
+    /// The template is given the `title`, `message` and status `code` of the error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::http::Response;
+    /// # use rwf::view::Template;
+    /// let template = Template::from_str("<h1><%= code %></h1><p><%= title %></p>").unwrap();
+    /// Response::set_error_template(template);
+    ///
+    /// let response = Response::not_found();
+    /// # assert_eq!(response.status().code(), 404);
+    /// ```
+    pub fn set_error_template(template: impl Into<Arc<Template>>) {
+        *CUSTOM_ERROR_TEMPLATE.write() = Some(template.into());
+    }
+
+    fn error_template() -> Option<Arc<Template>> {
+        if let Some(template) = CUSTOM_ERROR_TEMPLATE.read().clone() {
+            return Some(template);
+        }
+
+        let path = get_config().general.error_template_path.as_ref()?;
+
+        match Template::load(path) {
+            Ok(template) => Some(template),
+            Err(err) => {
+                error!("error template failed to load: {}", err);
+                None
+            }
+        }
+    }
+
+    /// HTTP `401 - Unauthorized`.
+    pub fn unauthorized(auth: &str) -> Self {
+        Self::error_page(401, "401 - Unauthorized", "").header("www-authenticate", auth)
+    }
+
+    /// HTTP `503 - Service Unavailable`. The client is asked to retry
+    /// after this many seconds.
+    pub fn service_unavailable(retry_after: u64) -> Self {
+        Self::error_page(503, "503 - Service Unavailable", "").header("retry-after", retry_after)
+    }
+
+    /// HTTP `429 - Too Many`.
+    pub fn too_many() -> Self {
+        Self::error_page(429, "429 - Too Many", "")
+    }
+
+    /// Machine-readable error response, using the `application/problem+json` format
+    /// defined in [RFC 9457](https://www.rfc-editor.org/rfc/rfc9457). See [`Problem`] for details.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    ///
+    /// let response: Response = Response::problem(404, "Order not found")
+    ///     .detail("Order 5 doesn't exist.")
+    ///     .into();
+    /// ```
+    pub fn problem(status: u16, title: impl ToString) -> Problem {
+        Problem::new(status, title)
     }
 
     /// HTTP `302 - Found`, also known as a redirect.
     pub fn redirect(self, to: impl ToString) -> Self {
-        self.html("")
+        self.redirect_with(302, to)
+    }
+
+    /// HTTP `301 - Moved Permanently`. Browsers and search engines remember the new URL.
+    pub fn redirect_permanent(self, to: impl ToString) -> Self {
+        self.redirect_with(301, to)
+    }
+
+    /// HTTP `303 - See Other`. The browser follows the redirect with a `GET` request,
+    /// so this is the redirect to use after handling a form `POST`.
+    pub fn see_other(self, to: impl ToString) -> Self {
+        self.redirect_with(303, to)
+    }
+
+    /// HTTP `307 - Temporary Redirect`. Unlike `302`, the browser repeats the request
+    /// with the same method and body.
+    pub fn temporary_redirect(self, to: impl ToString) -> Self {
+        self.redirect_with(307, to)
+    }
+
+    /// Redirect with the given code, which must be one of `301`, `302`, `303`, `307` or `308`.
+    /// Other codes panic in debug builds, and are replaced with `302` in release builds.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rwf::http::Response;
+    ///
+    /// let response = Response::new().redirect_with(308, "/v2/orders");
+    /// assert_eq!(response.status().code(), 308);
+    /// assert_eq!(response.headers().get("location").unwrap(), "/v2/orders");
+    /// ```
+    pub fn redirect_with(self, code: u16, to: impl ToString) -> Self {
+        let code = match code {
+            301 | 302 | 303 | 307 | 308 => code,
+            _ => {
+                debug_assert!(false, "{} is not a redirect code", code);
+                302
+            }
+        };
+
+        let mut response = self
+            .html("")
             .header("location", to)
-            .code(302)
-            .header("content-length", 0)
-            .header("cache-control", "no-cache")
+            .code(code)
+            .header("content-length", 0);
+
+        if response.headers.get("cache-control").is_none() {
+            response = response.cache(CacheControl::no_cache());
+        }
+
+        response
     }
 
     /// HTTP `101 - Switching Protocols`. Can be used for upgrading the connection
@@ -403,6 +1790,37 @@ impl From<serde_json::Value> for Response {
     }
 }
 
+/// Characters, other than letters, digits and `-._~`, allowed in the `filename*` parameter (RFC 5987).
+const ATTR_CHAR: &[u8] = b"!#$&+^`|";
+
+/// `Content-Disposition` header of a download saved as `filename`.
+///
+/// Names that aren't plain ASCII are also sent in the `filename*` parameter, with a fallback
+/// for older clients.
+fn content_disposition(filename: &str) -> String {
+    let fallback = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    if fallback == filename {
+        format!(r#"attachment; filename="{}""#, filename)
+    } else {
+        let mut encoded = String::new();
+        percent_encode(filename, ATTR_CHAR, &mut encoded);
+        format!(
+            r#"attachment; filename="{}"; filename*=UTF-8''{}"#,
+            fallback, encoded
+        )
+    }
+}
+
 impl From<String> for Response {
     fn from(value: String) -> Response {
         Response::new().html(value)
@@ -420,3 +1838,782 @@ impl From<Vec<TurboStream>> for Response {
         Response::new().turbo_stream(&value)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{fs::File, io::Write};
+    use tempdir::TempDir;
+
+    fn body(response: &Response) -> String {
+        match response.body {
+            Body::Text(ref text) => text.clone(),
+            Body::Html(ref html) => html.clone(),
+            Body::Xml(ref xml) => xml.clone(),
+            Body::Bytes(ref bytes) => String::from_utf8(bytes.clone()).unwrap(),
+            _ => panic!("unexpected body"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let (html, stream) = scope_max_body_size(10, async {
+            (
+                Response::new().html("<p>hello world</p>"),
+                Response::new().stream(&b"hello world"[..]),
+            )
+        })
+        .await;
+
+        // Buffered bodies are dropped when they are set.
+        let err = html.body_too_large().unwrap();
+        assert!(matches!(
+            err,
+            Error::ResponseTooLarge {
+                size: 18,
+                limit: 10
+            }
+        ));
+        assert!(html.body_bytes().unwrap().is_empty());
+
+        // Streams are cut off while they are sent.
+        assert!(stream.body_too_large().is_none());
+        let mut wire = vec![];
+        let err = stream.send(&mut wire).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "response body reached 11 bytes, over the limit of 10 bytes"
+        );
+        assert!(!String::from_utf8(wire).unwrap().contains("hello"));
+
+        // No limit.
+        let response = scope_max_body_size(0, async { Response::new().text("hello world") }).await;
+        assert!(response.body_too_large().is_none());
+        assert_eq!(body(&response), "hello world");
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let mut wire = vec![];
+        let written = Response::new().text("hi").send(&mut wire).await.unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+
+        assert_eq!(written, wire.len());
+        assert!(wire.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(wire.contains("content-length: 2\r\n"));
+        assert!(!wire.contains("transfer-encoding"));
+        assert!(wire.ends_with("\r\n\r\nhi"));
+
+        // Responses without a body still have a known length.
+        let mut wire = vec![];
+        Response::new().send(&mut wire).await.unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.contains("content-length: 0\r\n"));
+        assert!(wire.ends_with("\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_trailers() {
+        let mut wire = vec![];
+        let stream = Body::stream(&b"hello world"[..]);
+        Response::new()
+            .body(stream)
+            .trailer("Server-Timing", "total;dur=12")
+            .trailer("X-Checksum", "5eb63bbb")
+            .header_order(["server", "trailer"])
+            .send(&mut wire)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(wire).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+            server: rwf\r\n\
+            trailer: server-timing, x-checksum\r\n\
+            connection: keep-alive\r\n\
+            content-type: application/octet-stream\r\n\
+            transfer-encoding: chunked\r\n\
+            \r\n\
+            b\r\nhello world\r\n\
+            0\r\n\
+            server-timing: total;dur=12\r\n\
+            x-checksum: 5eb63bbb\r\n\
+            \r\n"
+        );
+
+        // Bodies with a known length aren't chunk-encoded.
+        let mut wire = vec![];
+        let err = Response::new()
+            .text("hello")
+            .trailer("x-checksum", "5d41402a")
+            .send(&mut wire)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "trailers can only be sent with chunked encoding"
+        );
+        assert!(wire.is_empty());
+
+        // Nothing follows the headers of a HEAD response.
+        let mut wire = vec![];
+        Response::new()
+            .body(Body::stream(&b"hello"[..]))
+            .trailer("x-checksum", "5d41402a")
+            .header_order(["trailer"])
+            .without_body()
+            .send(&mut wire)
+            .await
+            .unwrap();
+        assert!(String::from_utf8(wire)
+            .unwrap()
+            .starts_with("HTTP/1.1 200 OK\r\ntrailer: x-checksum\r\nconnection: keep-alive\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_xml() {
+        let mut wire = vec![];
+        Response::new()
+            .xml("<id>5</id>")
+            .send(&mut wire)
+            .await
+            .unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.contains("content-type: application/xml\r\n"));
+        assert!(wire.contains("content-length: 10\r\n"));
+        assert!(wire.ends_with("\r\n\r\n<id>5</id>"));
+
+        #[cfg(feature = "charsets")]
+        {
+            let response = Response::new()
+                .xml("<name>café</name>")
+                .charset(Charset::Latin1);
+            assert_eq!(response.body_bytes(), Some(&b"<name>caf\xe9</name>"[..]));
+            assert_eq!(
+                response.headers().get("content-type").unwrap(),
+                "application/xml; charset=iso-8859-1"
+            );
+        }
+
+        #[cfg(feature = "xml")]
+        {
+            #[derive(Serialize)]
+            struct Order {
+                id: i64,
+                items: Vec<String>,
+            }
+
+            let response = Response::new()
+                .to_xml(Order {
+                    id: 5,
+                    items: vec!["a & b".into()],
+                })
+                .unwrap();
+            assert_eq!(
+                body(&response),
+                r#"<?xml version="1.0" encoding="UTF-8"?><Order><id>5</id><items>a &amp; b</items></Order>"#
+            );
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(Status::from(404), Status::NotFound);
+        assert_eq!(Status::from(418), Status::Code(418));
+        assert_eq!(Status::from(429).reason(), "Too Many Requests");
+        assert_eq!(Status::Code(418).reason(), "Client Error");
+        assert_eq!(Status::Code(299).reason(), "Success");
+        assert_eq!(Status::PermanentRedirect.code(), 308);
+        assert_eq!(Status::from(503).to_string(), "503 Service Unavailable");
+        assert_eq!(Status::Code(999).to_string(), "999 Unknown");
+    }
+
+    #[tokio::test]
+    async fn test_send_no_body() {
+        let mut wire = vec![];
+        Response::no_content().send(&mut wire).await.unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(!wire.contains("content-length"));
+        assert!(!wire.contains("content-type"));
+        assert!(wire.ends_with("\r\n\r\n"));
+
+        // Set manually, the body and its headers are dropped.
+        let mut wire = vec![];
+        Response::new()
+            .text("hello")
+            .code(204)
+            .send(&mut wire)
+            .await
+            .unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(!wire.contains("content-length"));
+        assert!(!wire.contains("content-type"));
+        assert!(wire.ends_with("\r\n\r\n"));
+
+        let mut wire = vec![];
+        Response::new()
+            .html("<h1>hi</h1>")
+            .code(304)
+            .send(&mut wire)
+            .await
+            .unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(!wire.contains("content-length"));
+        assert!(wire.ends_with("\r\n\r\n"));
+
+        // HEAD requests get the length of the body, without the body.
+        let mut wire = vec![];
+        Response::new()
+            .text("hello")
+            .without_body()
+            .send(&mut wire)
+            .await
+            .unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.contains("content-length: 5\r\n"));
+        assert!(wire.ends_with("\r\n\r\n"));
+
+        let mut wire = vec![];
+        Response::new()
+            .stream(&b"hello"[..])
+            .without_body()
+            .send(&mut wire)
+            .await
+            .unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.ends_with("\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_json_stream() {
+        let response = Response::new().json_stream(vec![1, 2, 3]);
+        assert!(response.headers().get("content-length").is_none());
+
+        let mut wire = vec![];
+        response.send(&mut wire).await.unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+
+        assert!(wire.contains("transfer-encoding: chunked\r\n"));
+        assert!(wire.contains("content-type: application/json\r\n"));
+        assert!(wire.ends_with("\r\n\r\n7\r\n[1,2,3]\r\n0\r\n\r\n"));
+
+        let response = Response::new().json_pretty(vec![1]).unwrap();
+        assert_eq!(response.headers().get("content-length").unwrap(), "7");
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        assert!(response.headers().get("transfer-encoding").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_send_stream() {
+        let response = Response::new().stream(&b"hello world"[..]);
+        assert!(response.headers().get("content-length").is_none());
+        assert!(response.body_bytes().is_none());
+
+        let mut wire = vec![];
+        response.send(&mut wire).await.unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+
+        assert!(wire.contains("transfer-encoding: chunked\r\n"));
+        assert!(wire.contains("content-type: application/octet-stream\r\n"));
+        assert!(!wire.contains("content-length"));
+        assert!(wire.ends_with("\r\n\r\nb\r\nhello world\r\n0\r\n\r\n"));
+
+        // Replacing the stream with a buffered body sets the length again.
+        let response = Response::new().stream(&b"hi"[..]).text("hi");
+        assert!(response.headers().get("transfer-encoding").is_none());
+        assert_eq!(response.headers().get("content-length").unwrap(), "2");
+    }
+
+    #[tokio::test]
+    async fn test_file() {
+        let tmp_dir = TempDir::new("files").unwrap();
+        let path = tmp_dir.path().join("report.pdf");
+        File::create(&path).unwrap().write_all(b"%PDF-1.7").unwrap();
+
+        let response = Response::file(&path).await.unwrap();
+        assert_eq!(response.status().code(), 200);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/pdf"
+        );
+        assert_eq!(response.headers().get("content-length").unwrap(), "8");
+        assert!(response.headers().get("content-disposition").is_none());
+
+        let mut wire = vec![];
+        response.send(&mut wire).await.unwrap();
+        assert!(wire.ends_with(b"\r\n\r\n%PDF-1.7"));
+
+        for missing in [tmp_dir.path().join("missing.pdf"), tmp_dir.path().into()] {
+            let response = Response::file(&missing).await.unwrap();
+            assert_eq!(response.status().code(), 404);
+            let response = Response::attachment(&missing, "a.pdf").await.unwrap();
+            assert_eq!(response.status().code(), 404);
+        }
+
+        let response = Response::attachment(&path, "Q3 report.pdf").await.unwrap();
+        assert_eq!(
+            response.headers().get("content-disposition").unwrap(),
+            r#"attachment; filename="Q3 report.pdf""#
+        );
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/pdf"
+        );
+
+        let response = Response::attachment(&path, "résumé \"final\".pdf")
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers().get("content-disposition").unwrap(),
+            r#"attachment; filename="r_sum_ _final_.pdf"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%22final%22.pdf"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_range() {
+        async fn request(headers: &str) -> Request {
+            let request = format!("GET /media HTTP/1.1\r\n{}\r\n", headers);
+            Request::read("127.0.0.1:1234".parse().unwrap(), request.as_bytes())
+                .await
+                .unwrap()
+        }
+
+        let response = Response::new()
+            .text("hello world")
+            .range(&request("Range: bytes=6-\r\n").await);
+        assert_eq!(response.status().code(), 206);
+        assert_eq!(
+            response.headers().get("content-range").unwrap(),
+            "bytes 6-10/11"
+        );
+        assert_eq!(response.headers().get("content-length").unwrap(), "5");
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/plain"
+        );
+        assert_eq!(body(&response), "world");
+
+        let response = Response::new()
+            .text("hello world")
+            .range(&request("Range: bytes=11-\r\n").await);
+        assert_eq!(response.status().code(), 416);
+        assert_eq!(
+            response.headers().get("content-range").unwrap(),
+            "bytes */11"
+        );
+        assert_eq!(body(&response), "");
+
+        // Whole body for multiple ranges, no range or a stale If-Range.
+        for headers in [
+            "Range: bytes=0-1, 3-4\r\n",
+            "",
+            "Range: bytes=0-1\r\nIf-Range: \"v1\"\r\n",
+        ] {
+            let response = Response::new()
+                .text("hello world")
+                .header("etag", "\"v2\"")
+                .range(&request(headers).await);
+            assert_eq!(response.status().code(), 200);
+            assert_eq!(response.headers().get("accept-ranges").unwrap(), "bytes");
+            assert_eq!(body(&response), "hello world");
+        }
+
+        let tmp_dir = TempDir::new("range").unwrap();
+        let path = tmp_dir.path().join("intro.mp4");
+        File::create(&path)
+            .unwrap()
+            .write_all(b"0123456789")
+            .unwrap();
+
+        let response = Response::file(&path)
+            .await
+            .unwrap()
+            .range(&request("Range: bytes=2-4\r\n").await);
+        assert_eq!(response.status().code(), 206);
+        assert_eq!(response.headers().get("content-type").unwrap(), "video/mp4");
+        assert_eq!(
+            response.headers().get("content-range").unwrap(),
+            "bytes 2-4/10"
+        );
+
+        let mut wire = vec![];
+        response.send(&mut wire).await.unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        assert!(wire.starts_with("HTTP/1.1 206"));
+        assert!(wire.contains("content-length: 3\r\n"));
+        assert!(wire.ends_with("\r\n\r\n234"));
+
+        let response = Response::file(&path)
+            .await
+            .unwrap()
+            .range(&request("Range: bytes=-3\r\n").await);
+        let mut wire = vec![];
+        response.send(&mut wire).await.unwrap();
+        assert!(wire.ends_with(b"\r\n\r\n789"));
+    }
+
+    #[tokio::test]
+    async fn test_conditional() {
+        async fn request(headers: &str) -> Request {
+            let request = format!("GET /pricing HTTP/1.1\r\n{}\r\n", headers);
+            Request::read("127.0.0.1:1234".parse().unwrap(), request.as_bytes())
+                .await
+                .unwrap()
+        }
+
+        let response = Response::new().html("<h1>Pricing</h1>").cacheable();
+        let etag = response.headers().get("etag").unwrap().clone();
+        assert!(etag.starts_with("W/\""));
+
+        let response = Response::new()
+            .html("<h1>Pricing</h1>")
+            .cacheable()
+            .conditional(&request(&format!("If-None-Match: {}\r\n", etag)).await);
+        assert_eq!(response.status().code(), 304);
+        assert_eq!(response.headers().get("etag").unwrap(), &etag);
+        assert!(response.headers().get("content-length").is_none());
+        assert_eq!(body(&response), "");
+
+        // Body changed.
+        let response = Response::new()
+            .html("<h1>New pricing</h1>")
+            .cacheable()
+            .conditional(&request(&format!("If-None-Match: {}\r\n", etag)).await);
+        assert_eq!(response.status().code(), 200);
+        assert_eq!(body(&response), "<h1>New pricing</h1>");
+
+        // Explicit ETag is kept.
+        let response = Response::new().text("v5").etag("v5").cacheable();
+        assert_eq!(response.headers().get("etag").unwrap(), "\"v5\"");
+
+        let tmp_dir = TempDir::new("conditional").unwrap();
+        let path = tmp_dir.path().join("style.css");
+        File::create(&path).unwrap().write_all(b"p {}").unwrap();
+
+        let response = Response::file(&path).await.unwrap().cacheable();
+        let last_modified = response.headers().get("last-modified").unwrap().clone();
+        assert!(response.headers().get("etag").unwrap().starts_with("W/\""));
+
+        let response = Response::file(&path)
+            .await
+            .unwrap()
+            .cacheable()
+            .conditional(&request(&format!("If-Modified-Since: {}\r\n", last_modified)).await);
+        assert_eq!(response.status().code(), 304);
+        assert_eq!(
+            response.headers().get("last-modified").unwrap(),
+            &last_modified
+        );
+    }
+
+    #[test]
+    fn test_redirect() {
+        let redirects = [
+            (Response::new().redirect("/a"), 302),
+            (Response::new().redirect_permanent("/a"), 301),
+            (Response::new().see_other("/a"), 303),
+            (Response::new().temporary_redirect("/a"), 307),
+            (Response::new().redirect_with(308, "/a"), 308),
+        ];
+
+        for (response, code) in redirects {
+            assert_eq!(response.status().code(), code);
+            assert_eq!(response.headers().get("location").unwrap(), "/a");
+            assert_eq!(response.headers().get("content-length").unwrap(), "0");
+        }
+    }
+
+    #[test]
+    fn test_error_template() {
+        let template =
+            Template::from_str("<h1><%= code %> <%= title %></h1><p><%= message %></p>").unwrap();
+        let body = Response::render_error(Some(&template), 404, "Not Found", "Nothing here");
+        assert_eq!(body, "<h1>404 Not Found</h1><p>Nothing here</p>");
+
+        // Falls back to the built-in template.
+        let broken = Template::from_str("<%= missing_variable %>").unwrap();
+        let body = Response::render_error(Some(&broken), 500, "Oops", "");
+        assert!(body.contains("<title>Oops</title>"));
+        assert_eq!(body, Response::render_error(None, 500, "Oops", ""));
+
+        let response = Response::not_found();
+        assert_eq!(response.status().code(), 404);
+        assert_eq!(Response::unauthorized("Basic").status().code(), 401);
+    }
+
+    #[test]
+    fn test_cache() {
+        use time::Duration;
+
+        let cache = CacheControl::new().private().max_age(Duration::minutes(1));
+
+        let response = Response::new().redirect("/a");
+        assert_eq!(response.headers().get("cache-control").unwrap(), "no-cache");
+
+        // Either order, the explicit rules win.
+        let before = Response::new().cache(cache.clone()).redirect("/a");
+        let after = Response::new().redirect("/a").cache(cache.clone());
+        for response in [before, after] {
+            assert_eq!(
+                response.headers().get("cache-control").unwrap(),
+                "private, max-age=60"
+            );
+        }
+
+        let response = Response::new().cache(cache).cache(CacheControl::no_store());
+        assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+    }
+
+    #[test]
+    #[should_panic(expected = "200 is not a redirect code")]
+    fn test_redirect_with_wrong_code() {
+        let _ = Response::new().redirect_with(200, "/a");
+    }
+
+    #[test]
+    fn test_rewrite_html() {
+        let inject = |html: &mut String| html.push_str("<script></script>");
+
+        let response = Response::new().html("<p>hi</p>").rewrite_html(1024, inject);
+        assert_eq!(body(&response), "<p>hi</p><script></script>");
+        assert_eq!(response.headers().get("content-length").unwrap(), "26");
+
+        let response = Response::new()
+            .body(Body::bytes(b"<p>hi</p>".to_vec()))
+            .header("content-type", "text/html; charset=utf-8")
+            .rewrite_html(1024, inject);
+        assert_eq!(body(&response), "<p>hi</p><script></script>");
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/html; charset=utf-8"
+        );
+
+        // Not HTML, too large, or compressed.
+        let response = Response::new().text("hi").rewrite_html(1024, inject);
+        assert_eq!(body(&response), "hi");
+        let response = Response::new().html("<p>hi</p>").rewrite_html(8, inject);
+        assert_eq!(body(&response), "<p>hi</p>");
+        assert_eq!(response.headers().get("content-length").unwrap(), "9");
+        let response = Response::new()
+            .html("<p>hi</p>")
+            .header("content-encoding", "gzip")
+            .rewrite_html(1024, inject);
+        assert_eq!(body(&response), "<p>hi</p>");
+    }
+
+    #[tokio::test]
+    async fn test_template_providers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        crate::view::provide("test_cart_size", |_request| async {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, Error>(2)
+        });
+
+        let tmp_dir = TempDir::new("providers").unwrap();
+        let page = tmp_dir.path().join("page.html");
+        let layout = tmp_dir.path().join("layout.html");
+        File::create(&page)
+            .unwrap()
+            .write_all(b"<p><%= test_cart_size %></p>")
+            .unwrap();
+        File::create(&layout)
+            .unwrap()
+            .write_all(b"<nav><%= test_cart_size %></nav><%= yield %>")
+            .unwrap();
+
+        let request = Request::default();
+        let response = Response::new()
+            .template(&page, &Context::new())
+            .unwrap()
+            .render_page(layout.to_str(), &request)
+            .await
+            .unwrap();
+        assert_eq!(body(&response), "<nav>2</nav><p>2</p>");
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_template_layout() {
+        let tmp_dir = TempDir::new("layouts").unwrap();
+        let templates = [
+            ("page.html", "<p><%= name %></p>"),
+            (
+                "web.html",
+                "<html><title><%= name %></title><%= yield %></html>",
+            ),
+            ("email.txt", "Hello <%= name %>, <%= yield %>"),
+        ];
+
+        for (name, content) in templates {
+            let mut file = File::create(tmp_dir.path().join(name)).unwrap();
+            file.write_all(content.as_bytes()).unwrap();
+        }
+
+        let page = tmp_dir.path().join("page.html");
+        let web = tmp_dir.path().join("web.html");
+        let email = tmp_dir.path().join("email.txt");
+        let context = || Context::try_from([("name", "Alice")]).unwrap();
+
+        let response = Response::new()
+            .template(&page, &context())
+            .unwrap()
+            .render_layout(web.to_str())
+            .unwrap();
+        assert_eq!(
+            body(&response),
+            "<html><title>Alice</title><p>Alice</p></html>"
+        );
+        assert_eq!(response.headers.get("content-type").unwrap(), "text/html");
+
+        let response = Response::new()
+            .template(&page, &context())
+            .unwrap()
+            .layout(&email)
+            .render_layout(web.to_str())
+            .unwrap();
+        assert_eq!(body(&response), "Hello Alice, <p>Alice</p>");
+        assert_eq!(response.headers.get("content-type").unwrap(), "text/plain");
+
+        let response = Response::new()
+            .template(&page, &context())
+            .unwrap()
+            .without_layout()
+            .render_layout(web.to_str())
+            .unwrap();
+        assert_eq!(body(&response), "<p>Alice</p>");
+
+        let response = Response::new()
+            .json(serde_json::json!({"name": "Alice"}))
+            .unwrap()
+            .render_layout(web.to_str())
+            .unwrap();
+        assert_eq!(
+            response.headers.get("content-type").unwrap(),
+            "application/json"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_secure_cookies_middleware() {
+        use crate::controller::middleware::{Middleware, MiddlewareSet, Outcome};
+        use crate::http::{request::test::dummy_request, CookieBuilder};
+
+        struct SecureCookies;
+
+        #[async_trait::async_trait]
+        impl Middleware for SecureCookies {
+            async fn handle_request(
+                &self,
+                request: Request,
+            ) -> Result<Outcome, crate::controller::Error> {
+                Ok(Outcome::Forward(request))
+            }
+
+            async fn handle_response(
+                &self,
+                _request: &Request,
+                mut response: Response,
+            ) -> Result<Response, crate::controller::Error> {
+                response
+                    .cookies()
+                    .map_pending(|cookie| CookieBuilder::from(cookie).secure().strict().build());
+                Ok(response)
+            }
+        }
+
+        let request = dummy_request().await.unwrap();
+        let response = Response::new()
+            .cookie(CookieBuilder::new().name("theme").value("dark").build())
+            .from_request(&request)
+            .unwrap();
+
+        // The session cookie is visible to middleware.
+        let session = response
+            .cookies
+            .get_pending("rwf_session")
+            .expect("session");
+        assert!(!session.secure());
+
+        let middleware = MiddlewareSet::without_default(vec![SecureCookies.middleware()]);
+        let response = middleware
+            .handle_response(&request, response, 1)
+            .await
+            .unwrap();
+
+        let mut wire = vec![];
+        response.send(&mut wire).await.unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        let cookies = wire
+            .lines()
+            .filter(|line| line.starts_with("set-cookie: "))
+            .collect::<Vec<_>>();
+
+        assert_eq!(cookies.len(), 2);
+        assert!(cookies.iter().any(|c| c.contains("rwf_session=")));
+        for cookie in cookies {
+            assert!(cookie.contains("; Secure"));
+            assert!(cookie.contains("; SameSite=Strict"));
+        }
+    }
+
+    #[test]
+    fn test_compress() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let mut request = Request::default();
+        request
+            .head_mut()
+            .headers_mut()
+            .insert("accept-encoding", "gzip, br");
+        let html = "<p>hello</p>".repeat(200);
+
+        let response = Response::new().html(&html).etag("v1").compress(&request);
+        let headers = response.headers();
+        assert_eq!(headers.get("content-encoding").unwrap(), "gzip");
+        assert_eq!(headers.get("vary").unwrap(), "accept-encoding");
+        assert_eq!(headers.get("etag").unwrap(), "W/\"v1\"");
+        let compressed = response.body_bytes().unwrap();
+        assert_eq!(
+            headers.get("content-length").unwrap(),
+            &compressed.len().to_string()
+        );
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, html);
+
+        // Opted out.
+        let response = Response::new().html(&html).no_compress().compress(&request);
+        assert!(response.headers().get("content-encoding").is_none());
+        assert_eq!(body(&response), html);
+
+        // Too small, but caches still need to know it depends on the header.
+        let response = Response::new().html("<p>hello</p>").compress(&request);
+        assert!(response.headers().get("content-encoding").is_none());
+        assert_eq!(response.headers().get("vary").unwrap(), "accept-encoding");
+
+        // Not text.
+        let response = Response::new().body(vec![0u8; 4096]).compress(&request);
+        assert!(response.headers().get("content-encoding").is_none());
+        assert!(response.headers().get("vary").is_none());
+
+        // Client doesn't accept gzip.
+        let response = Response::new()
+            .html(&html)
+            .vary("accept")
+            .compress(&Request::default());
+        assert!(response.headers().get("content-encoding").is_none());
+        assert_eq!(
+            response.headers().get("vary").unwrap(),
+            "accept, accept-encoding"
+        );
+    }
+}
@@ -1,15 +1,21 @@
 //! HTTP request routing.
 //!
-use super::{Error, Handler, Path};
+use super::{head::Method, path::PathType, ConcurrencyStats, Error, Handler, Path};
 use crate::colors::MaybeColorize;
 
 use regex::RegexSet;
-use tracing::info;
+use serde::{Serialize, Serializer};
+use tracing::{info, warn};
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 
 #[derive(Default)]
 pub struct Router {
     regex: RegexSet,
     handlers: Vec<Handler>,
+    /// Handlers, by index, in the order they are considered when matching a request.
+    order: Vec<usize>,
 }
 
 impl Router {
@@ -20,31 +26,145 @@ impl Router {
             .collect::<Vec<_>>();
         let regex = RegexSet::new(paths)?;
 
-        Ok(Self { regex, handlers })
+        let mut order = (0..handlers.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| Reverse(Self::specificity(&handlers, *i)));
+
+        for (i, first) in order.iter().enumerate() {
+            for second in &order[i + 1..] {
+                let (first, second) = (&handlers[*first], &handlers[*second]);
+
+                if Self::constraints_differ(first, second) {
+                    warn!(
+                        "routes \"{}\" and \"{}\" only differ in their parameter constraints, \"{}\" is matched first",
+                        first.path().path(),
+                        second.path().path(),
+                        first.path().path(),
+                    );
+                }
+            }
+        }
+
+        Ok(Self {
+            regex,
+            handlers,
+            order,
+        })
     }
 
     pub fn find(&self, path: &Path) -> Option<&Handler> {
         let matches = self.regex.matches(path.base());
-        let mut handlers = self
-            .handlers
+        if !matches.matched_any() {
+            return None;
+        }
+
+        // Get the most specific path (longest match) with parameters satisfying its constraints.
+        self.order
             .iter()
-            .enumerate()
-            .filter(|(i, _h)| matches.matched(*i))
-            .map(|(_i, h)| h)
+            .filter(|i| matches.matched(**i))
+            .map(|i| &self.handlers[*i])
+            .find(|handler| {
+                let path_with_regex = handler.path_with_regex();
+                !path_with_regex.constrained() || path_with_regex.matches(path.base())
+            })
+    }
+
+    /// When several routes match a path, the one with the highest rank handles the request.
+    /// If ranks are equal, the route with the longest path, without constraints, wins. If that's equal too,
+    /// the route added last wins, unless routes have constraints: those are tried first, in the order they were added.
+    ///
+    /// Routes which only differ in their constraints are tried in the order they were added, so a request
+    /// not satisfying the constraints of one route is handled by the next.
+    fn specificity(handlers: &[Handler], i: usize) -> (i64, usize, bool, i64) {
+        let handler = &handlers[i];
+        let constrained = handler.path_with_regex().constrained();
+        let length = handlers
+            .iter()
+            .filter(|other| Self::constraints_differ(handler, other))
+            .chain(std::iter::once(handler))
+            .map(|other| other.path_with_regex().unconstrained().len())
+            .max()
+            .unwrap_or_default();
+        let order = if constrained { -(i as i64) } else { i as i64 };
+
+        (handler.rank(), length, constrained, order)
+    }
+
+    /// The routes match the same paths, and are told apart only by the constraints on their parameters.
+    fn constraints_differ(first: &Handler, second: &Handler) -> bool {
+        let (a, b) = (first.path_with_regex(), second.path_with_regex());
+
+        first.rank() == second.rank()
+            && a.regex().as_str() == b.regex().as_str()
+            && (a.constrained() || b.constrained())
+            && Self::constraints(first) != Self::constraints(second)
+    }
+
+    fn constraints(handler: &Handler) -> BTreeMap<String, String> {
+        handler
+            .path_with_regex()
+            .params()
+            .constraints()
+            .iter()
+            .map(|(name, constraint)| (name.clone(), constraint.to_string()))
+            .collect()
+    }
+
+    /// Describe all routes, in the order they are considered when matching a request.
+    pub fn report(&self) -> RoutesReport {
+        let handlers = self
+            .order
+            .iter()
+            .map(|i| &self.handlers[*i])
             .collect::<Vec<_>>();
-        handlers.sort_by(|a, b| {
-            let a_len = a.path().base().len();
-            let b_len = b.path().base().len();
-            let a_rank = a.rank();
-            let b_rank = b.rank();
-
-            if a_rank == b_rank {
-                a_len.cmp(&b_len)
-            } else {
-                a_rank.cmp(&b_rank)
-            }
-        }); // Get the most specific path (longest match).
-        handlers.last().copied()
+
+        let routes = handlers
+            .iter()
+            .enumerate()
+            .map(|(i, handler)| {
+                let regex = handler.path_with_regex().regex().as_str();
+                let constraints = Self::constraints(handler);
+                // Routes with other constraints only take the requests this route doesn't.
+                let shadowed_by = handlers[..i]
+                    .iter()
+                    .find(|other| {
+                        other.path_with_regex().regex().as_str() == regex
+                            && (!other.path_with_regex().constrained()
+                                || Self::constraints(other) == constraints)
+                    })
+                    .map(|other| other.path().path().to_string());
+
+                RouteReport {
+                    methods: match handler.path_type() {
+                        PathType::Rest => vec![
+                            Method::Get,
+                            Method::Head,
+                            Method::Post,
+                            Method::Put,
+                            Method::Patch,
+                            Method::Delete,
+                        ],
+                        PathType::Route | PathType::Wildcard => vec![],
+                    },
+                    path: handler.path().path().to_string(),
+                    constraints,
+                    controller: handler.controller_name(),
+                    middleware: handler
+                        .middleware()
+                        .handlers()
+                        .iter()
+                        .chain(handler.controller().middleware().handlers().iter())
+                        .map(|m| m.name())
+                        .collect(),
+                    name: handler.route_name().map(|name| name.to_string()),
+                    rank: handler.rank(),
+                    specificity: handler.path_with_regex().unconstrained().len(),
+                    shadowed_by,
+                    concurrency: handler.concurrency().map(|limit| limit.stats()),
+                }
+            })
+            .collect();
+
+        RoutesReport { routes }
     }
 
     pub fn log_routes(&self) {
@@ -60,15 +180,149 @@ impl Router {
     }
 }
 
+/// A registered route, as seen by the router.
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteReport {
+    /// HTTP methods handled by the route. Empty if the controller receives requests with any method.
+    #[serde(serialize_with = "methods")]
+    pub methods: Vec<Method>,
+    /// Path pattern, e.g. `/users/:id<i64>`.
+    pub path: String,
+    /// Constraints on path parameters, e.g. `id` => `i64`.
+    pub constraints: BTreeMap<String, String>,
+    /// Controller serving the route.
+    pub controller: &'static str,
+    /// Middleware running on the controller, in the order it handles requests.
+    pub middleware: Vec<&'static str>,
+    /// Route name, if one is set.
+    pub name: Option<String>,
+    /// Route rank. Routes with a higher rank are matched first.
+    pub rank: i64,
+    /// Length of the path pattern, without constraints. If ranks are equal, longer paths are matched first.
+    pub specificity: usize,
+    /// Path of a route with the same pattern that's matched first, so this route is never used.
+    pub shadowed_by: Option<String>,
+    /// Concurrency limit of the route and its current usage, if the route has a limit.
+    pub concurrency: Option<ConcurrencyStats>,
+}
+
+fn methods<S: Serializer>(methods: &[Method], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(methods.iter().map(|m| m.to_string()))
+}
+
+/// All registered routes, in the order they are considered when matching a request.
+///
+/// Displayed as a table, e.g.:
+///
+/// ```text
+/// #  METHODS  PATH    CONTROLLER  NAME   RANK  MIDDLEWARE
+/// 1  *        /users  app::Users  users  0     -
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutesReport {
+    pub routes: Vec<RouteReport>,
+}
+
+impl std::fmt::Display for RoutesReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut rows = vec![[
+            "#".to_string(),
+            "METHODS".to_string(),
+            "PATH".to_string(),
+            "CONTROLLER".to_string(),
+            "NAME".to_string(),
+            "RANK".to_string(),
+            "MIDDLEWARE".to_string(),
+        ]];
+
+        for (i, route) in self.routes.iter().enumerate() {
+            let methods = if route.methods.is_empty() {
+                "*".to_string()
+            } else {
+                route
+                    .methods
+                    .iter()
+                    .map(|m| m.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+
+            let mut middleware = if route.middleware.is_empty() {
+                "-".to_string()
+            } else {
+                route.middleware.join(", ")
+            };
+
+            if let Some(ref path) = route.shadowed_by {
+                middleware.push_str(&format!(" (shadowed by {})", path));
+            }
+
+            rows.push([
+                (i + 1).to_string(),
+                methods,
+                route.path.clone(),
+                route.controller.to_string(),
+                route.name.clone().unwrap_or("-".to_string()),
+                route.rank.to_string(),
+                middleware,
+            ]);
+        }
+
+        let mut widths = [0; 7];
+        for row in &rows {
+            for (width, column) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(column.len());
+            }
+        }
+
+        for row in rows {
+            let line = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(column, width)| format!("{:width$}", column, width = width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::async_trait;
+    use crate::controller::middleware::{Middleware, MiddlewareSet, Outcome};
     use crate::controller::{Controller, Error as ControllerError};
-    use crate::http::{Request, Response};
+    use crate::http::{ParamValue, Request, Response};
+    use once_cell::sync::Lazy;
 
     struct OrdersControler {}
     struct UsersController {}
+    struct AuditedController {}
+    struct Audit;
+
+    #[async_trait]
+    impl Middleware for Audit {
+        async fn handle_request(&self, request: Request) -> Result<Outcome, ControllerError> {
+            Ok(Outcome::Forward(request))
+        }
+    }
+
+    static AUDITED: Lazy<MiddlewareSet> =
+        Lazy::new(|| MiddlewareSet::without_default(vec![Audit.middleware()]));
+
+    #[async_trait]
+    impl Controller for AuditedController {
+        fn middleware(&self) -> &MiddlewareSet {
+            &AUDITED
+        }
+
+        async fn handle(&self, _request: &Request) -> Result<Response, ControllerError> {
+            Ok(Response::default().text("AuditedController"))
+        }
+    }
 
     #[async_trait]
     impl Controller for OrdersControler {
@@ -98,4 +352,105 @@ mod test {
         let result = handler.handle(&Request::default()).await.unwrap();
         assert_eq!(result.status().code(), 200);
     }
+
+    #[test]
+    fn test_report() {
+        let router = Router::new(vec![
+            UsersController {}.wildcard("/"),
+            OrdersControler {}.route("/api/orders").name("orders"),
+            Handler::rest("/api/users", UsersController {}).name("users"),
+            AuditedController {}.route("/api/orders/:id"),
+            AuditedController {}.route("/api/orders"),
+        ])
+        .expect("to compile");
+
+        let report = router.report();
+
+        // The report agrees with the router.
+        let first = &report.routes[1];
+        let handler = router.find(&Path::parse("/api/orders").unwrap()).unwrap();
+        assert_eq!(first.path, handler.path().path());
+        assert_eq!(first.controller, handler.controller_name());
+
+        assert_eq!(
+            report.to_string(),
+            "\
+#  METHODS                         PATH             CONTROLLER                                  NAME    RANK  MIDDLEWARE
+1  *                               /api/orders/:id  rwf::http::router::test::AuditedController  -       0     rwf::http::router::test::Audit
+2  *                               /api/orders      rwf::http::router::test::AuditedController  -       0     rwf::http::router::test::Audit
+3  *                               /api/orders      rwf::http::router::test::OrdersControler    orders  0     rwf::controller::middleware::csrf::Csrf (shadowed by /api/orders)
+4  GET,HEAD,POST,PUT,PATCH,DELETE  /api/users       rwf::http::router::test::UsersController    users   0     rwf::controller::middleware::csrf::Csrf
+5  *                               /                rwf::http::router::test::UsersController    -       -20   rwf::controller::middleware::csrf::Csrf
+"
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["routes"][3]["methods"][0], "GET");
+        assert_eq!(json["routes"][2]["shadowed_by"], "/api/orders");
+    }
+
+    #[test]
+    fn test_constraints() {
+        let router = Router::new(vec![
+            UsersController {}.route("/items/:id<uuid>"),
+            OrdersControler {}.route("/items/:id<i64>"),
+            AuditedController {}.route("/posts/:slug([a-z0-9-]+)"),
+            UsersController {}.route("/items/new"),
+        ])
+        .expect("to compile");
+
+        let find = |path: &str| {
+            router
+                .find(&Path::parse(path).unwrap())
+                .map(|handler| handler.path().path().to_string())
+        };
+
+        let id = uuid::Uuid::new_v4();
+        assert_eq!(
+            find(&format!("/items/{}", id)).as_deref(),
+            Some("/items/:id<uuid>")
+        );
+        // Not a UUID, so the next route is tried.
+        assert_eq!(find("/items/5").as_deref(), Some("/items/:id<i64>"));
+        assert_eq!(find("/items/new").as_deref(), Some("/items/new"));
+        assert_eq!(find("/items/five"), None);
+        assert_eq!(
+            find("/posts/hello-world").as_deref(),
+            Some("/posts/:slug([a-z0-9-]+)")
+        );
+        assert_eq!(find("/posts/Hello"), None);
+        assert_eq!(find("/posts/hello%20world"), None);
+
+        let handler = router.find(&Path::parse("/items/5").unwrap()).unwrap();
+        let params = handler.path_with_regex().params();
+        assert_eq!(params.value("/items/5", "id"), Some(ParamValue::I64(5)));
+
+        let report = router.report();
+        let routes = report
+            .routes
+            .iter()
+            .map(|route| (route.path.as_str(), route.shadowed_by.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            routes,
+            vec![
+                ("/posts/:slug([a-z0-9-]+)", None),
+                ("/items/:id<uuid>", None),
+                ("/items/:id<i64>", None),
+                ("/items/new", None),
+            ]
+        );
+        assert_eq!(report.routes[1].constraints["id"], "uuid");
+
+        // Same constraints, so the second route never handles a request.
+        let router = Router::new(vec![
+            UsersController {}.route("/items/:id<i64>"),
+            OrdersControler {}.route("/items/:id<i64>"),
+        ])
+        .expect("to compile");
+        assert_eq!(
+            router.report().routes[1].shadowed_by.as_deref(),
+            Some("/items/:id<i64>")
+        );
+    }
 }
@@ -4,20 +4,37 @@
 //! If no handler is matched, return 404 Not Found.
 //!
 //! The server is using Tokio, so it can support millions of concurrent clients.
-use super::{Error, Handler, Request, Response, Router};
+use super::{
+    body_stream::BodyPump, capture, concurrency, error_hook, memory, response, writer::WriterError,
+    Budget, Error, Handler, Head, Method, Problem, Request, Reservation, Response, Router,
+    RoutesReport, Timings,
+};
 
 use crate::colors::MaybeColorize;
+use crate::config::get_config;
+use crate::controller::Impersonation;
+use crate::errors::{panic_message, ErrorKind, ErrorReport};
+use crate::flags::Subject;
+use crate::health;
+use crate::shutdown::{advance, ready, Phase, ShutdownHooks};
 
+use std::fmt::Display;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use futures_util::stream::{self, StreamExt};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
 use tokio::select;
 use tokio::signal::ctrl_c;
+use tokio::sync::Notify;
 use tokio::task::JoinHandle;
-use tracing::{debug, error, info};
+use tokio::time::timeout;
+use tracing::{debug, error, info, warn};
 
 #[derive(Debug)]
 pub enum Stream<'a> {
@@ -32,9 +49,89 @@ impl<'a> Stream<'a> {
     }
 }
 
+/// Largest HTML body, in bytes, passed to the HTML rewriters. Larger bodies are sent unchanged.
+pub const HTML_REWRITER_MAX_SIZE: usize = 1024 * 1024;
+
+/// Function rewriting HTML responses, added with [`Server::html_rewriter`].
+pub type HtmlRewriter = Arc<dyn Fn(&Request, &mut String) + Send + Sync>;
+
+#[derive(Clone)]
+struct Rewriters {
+    rewriters: Vec<HtmlRewriter>,
+    max_size: usize,
+    collect_queries: bool,
+}
+
+impl Rewriters {
+    fn rewrite(&self, request: &Request, response: Response) -> Response {
+        if self.rewriters.is_empty() {
+            return response;
+        }
+
+        response.rewrite_html(self.max_size, |html| {
+            for rewriter in &self.rewriters {
+                rewriter(request, html);
+            }
+        })
+    }
+}
+
+/// Requests the server is serving right now.
+#[derive(Default)]
+struct InFlight {
+    requests: AtomicUsize,
+    done: Notify,
+}
+
+impl InFlight {
+    fn start(self: &Arc<Self>) -> InFlightGuard {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.clone())
+    }
+
+    /// Wait until all requests are finished.
+    async fn drain(&self) {
+        loop {
+            let done = self.done.notified();
+
+            if self.requests.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+
+            done.await;
+        }
+    }
+}
+
+struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.requests.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.done.notify_waiters();
+        }
+    }
+}
+
+/// A request served by the router.
+struct Served<'a> {
+    request: Request,
+    handler: Option<&'a Handler>,
+    response: Response,
+    /// Memory used by the response body until it's sent.
+    memory: Option<Reservation>,
+}
+
+/// Signals which start a [handover](super::handover).
+type HandoverTrigger = Pin<Box<dyn stream::Stream<Item = ()> + Send + Sync>>;
+
 /// HTTP server.
 pub struct Server {
     handlers: Arc<Router>,
+    rewriters: Rewriters,
+    hooks: ShutdownHooks,
+    listener: Option<std::net::TcpListener>,
+    handover: Option<HandoverTrigger>,
 }
 
 impl Server {
@@ -43,13 +140,171 @@ impl Server {
     /// Accepts a list of handlers.
     // Duplicate handlers are overwritten without warning.
     pub fn new(handlers: Vec<Handler>) -> Self {
-        Server {
+        let server = Server {
             handlers: Arc::new(Router::new(handlers).unwrap()),
+            rewriters: Rewriters {
+                rewriters: vec![],
+                max_size: HTML_REWRITER_MAX_SIZE,
+                collect_queries: false,
+            },
+            hooks: ShutdownHooks::default(),
+            listener: None,
+            handover: None,
+        };
+
+        #[cfg(feature = "debug-toolbar")]
+        if get_config().general.debug_toolbar {
+            return server.debug_toolbar();
         }
+
+        server
     }
 
-    /// Launch the server.
+    /// Rewrite the body of HTML responses after the controller returns them, e.g. to inject a script.
+    ///
+    /// Rewriters run in the order they are added. Responses that aren't `text/html`, are files or
+    /// compressed, or are larger than [`HTML_REWRITER_MAX_SIZE`] are sent unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::http::Server;
+    /// let server = Server::new(vec![]).html_rewriter(|_request, body| {
+    ///     *body = body.replace("</body>", "<script src=\"/reload.js\"></script></body>");
+    /// });
+    /// ```
+    pub fn html_rewriter(
+        mut self,
+        rewriter: impl Fn(&Request, &mut String) + Send + Sync + 'static,
+    ) -> Self {
+        self.rewriters.rewriters.push(Arc::new(rewriter));
+        self
+    }
+
+    /// Change the size of the largest HTML body passed to the rewriters. Default is [`HTML_REWRITER_MAX_SIZE`].
+    pub fn html_rewriter_max_size(mut self, bytes: usize) -> Self {
+        self.rewriters.max_size = bytes;
+        self
+    }
+
+    /// Inject the [debug toolbar](super::debug_toolbar) into HTML responses. Added automatically
+    /// when the `debug_toolbar` setting is enabled.
+    #[cfg(feature = "debug-toolbar")]
+    pub fn debug_toolbar(mut self) -> Self {
+        self.rewriters.collect_queries = true;
+        self.html_rewriter(super::debug_toolbar::inject)
+    }
+
+    /// Run the hook when the server shuts down, after the requests in progress are finished.
+    /// See [`shutdown`](crate::shutdown) for details.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::http::Server;
+    /// let server = Server::new(vec![]).on_shutdown(|| async {
+    ///     println!("flushing metrics");
+    ///     Ok::<(), std::io::Error>(())
+    /// });
+    /// ```
+    pub fn on_shutdown<F, Fut, E>(self, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        self.hooks.add(None, hook);
+        self
+    }
+
+    /// Run the hook when the server shuts down, for up to `timeout` instead of `shutdown_hook_timeout`.
+    pub fn on_shutdown_timeout<F, Fut, E>(self, timeout: Duration, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        self.hooks.add(Some(timeout), hook);
+        self
+    }
+
+    /// Listen on this socket instead of binding the address passed to [`Server::launch`].
+    pub fn listener(mut self, listener: std::net::TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Listen on a socket inherited from the process which started this one, e.g. passed with `--fd 3`,
+    /// instead of binding the address passed to [`Server::launch`]. Sockets passed by systemd, or by a [handover](super::handover),
+    /// are used automatically.
+    ///
+    /// # Safety
+    ///
+    /// The descriptor must be a listening TCP socket, owned by the server from now on.
+    #[cfg(unix)]
+    pub unsafe fn from_raw_fd(self, fd: std::os::fd::RawFd) -> Self {
+        use std::os::fd::FromRawFd;
+
+        self.listener(std::net::TcpListener::from_raw_fd(fd))
+    }
+
+    /// Hand the listening socket over to a new process of the same binary when the process
+    /// receives `SIGUSR2`, and drain the requests in progress once the new process accepts connections.
+    /// See [`handover`](super::handover) for details.
+    #[cfg(unix)]
+    pub fn handover(self) -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let trigger = stream::unfold(None, |signals| async move {
+            let mut signals = match signals {
+                Some(signals) => signals,
+                None => match signal(SignalKind::user_defined2()) {
+                    Ok(signals) => signals,
+                    Err(err) => {
+                        error!("can't listen for SIGUSR2: {}", err);
+                        return None;
+                    }
+                },
+            };
+
+            signals.recv().await.map(|_| ((), Some(signals)))
+        });
+
+        self.handover_on(trigger)
+    }
+
+    /// Hand the listening socket over to a new process each time the stream yields, instead of on `SIGUSR2`.
+    #[cfg(unix)]
+    pub fn handover_on(
+        mut self,
+        trigger: impl stream::Stream<Item = ()> + Send + Sync + 'static,
+    ) -> Self {
+        self.handover = Some(Box::pin(trigger));
+        self
+    }
+
+    /// Describe the registered routes, in the order they are considered when matching a request.
+    ///
+    /// Displays as a table, which is logged at startup if `log_routes` is enabled in the configuration.
+    pub fn routes_report(&self) -> RoutesReport {
+        self.handlers.report()
+    }
+
+    /// Launch the server. The server runs until the process receives Ctrl-C.
     pub async fn launch(self, addr: impl ToSocketAddrs) -> Result<(), Error> {
+        self.launch_with_shutdown(addr, async {
+            let _ = ctrl_c().await;
+        })
+        .await
+    }
+
+    /// Launch the server. The server stops accepting connections when the `shutdown` future completes,
+    /// waits for the requests in progress to finish, and runs the shutdown hooks.
+    pub async fn launch_with_shutdown(
+        mut self,
+        addr: impl ToSocketAddrs,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
         info!(
             "Starting {} {} {}",
             "Rwf".green(),
@@ -57,28 +312,88 @@ impl Server {
             "server".red()
         );
 
-        self.handlers.log_routes();
+        if get_config().general.log_routes {
+            info!("Routes:\n{}", self.routes_report());
+        } else {
+            self.handlers.log_routes();
+        }
 
-        let listener = TcpListener::bind(addr).await?;
+        let listener = match self.listener.take() {
+            Some(listener) => Some(listener),
+            #[cfg(unix)]
+            None => super::handover::inherited()?,
+            #[cfg(not(unix))]
+            None => None,
+        };
+        let listener = match listener {
+            Some(listener) => {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)?
+            }
+            None => TcpListener::bind(addr).await?,
+        };
+        let rewriters = Arc::new(self.rewriters);
+        let in_flight = Arc::new(InFlight::default());
 
         info!("Listening on {}", listener.local_addr().unwrap());
 
+        // Let the process this one is replacing stop accepting connections.
+        #[cfg(unix)]
+        super::handover::notify_ready()?;
+
+        tokio::pin!(shutdown);
+
+        // New process started by a handover, until it accepts connections.
+        let mut trigger = self.handover.take();
+        let mut starting: Option<Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>>> = None;
+
         loop {
             select! {
-                _ = ctrl_c() => {
+                _ = &mut shutdown => {
                     info!("Shutting down...");
-                    return Ok(());
+                    break;
+                }
+
+                Some(()) = async { trigger.as_mut()?.next().await }, if starting.is_none() => {
+                    #[cfg(unix)]
+                    match super::handover::Handover::start(&listener) {
+                        Ok(handover) => {
+                            let limit = get_config().general.handover_timeout().unsigned_abs();
+                            starting = Some(Box::pin(handover.ready(limit)));
+                        }
+                        Err(err) => error!("handover failed: {}", err),
+                    }
+                }
+
+                result = async { starting.as_mut().unwrap().await }, if starting.is_some() => {
+                    starting = None;
+
+                    match result {
+                        Ok(()) => {
+                            info!("New process is accepting connections, shutting down...");
+                            break;
+                        }
+                        Err(err) => error!("handover failed, still serving: {}", err),
+                    }
                 }
 
                 result = listener.accept()  => {
                     if let Ok((stream, peer_addr)) = result {
                         let handlers = self.handlers.clone();
+                        let rewriters = rewriters.clone();
+                        let in_flight = in_flight.clone();
 
                         tokio::spawn(async move {
-                            match Self::handle_connection(handlers, stream, peer_addr).await {
+                            match Self::handle_connection(handlers, rewriters, in_flight, stream, peer_addr).await {
                                 Ok(_) => (),
-                                Err(_) => {
+                                Err(err) => {
                                     error!("panic detected, this is a bug; controllers should return an error instead");
+
+                                    if let Ok(payload) = err.try_into_panic() {
+                                        ErrorReport::message(ErrorKind::Panic, panic_message(payload.as_ref()))
+                                            .tag("peer", peer_addr)
+                                            .send();
+                                    }
                                 }
                             }
                         });
@@ -86,10 +401,31 @@ impl Server {
                 }
             }
         }
+
+        // Stop accepting connections while the requests in progress finish.
+        drop(listener);
+        advance(Phase::Draining);
+
+        let drain_timeout = get_config().general.shutdown_drain_timeout().unsigned_abs();
+        if timeout(drain_timeout, in_flight.drain()).await.is_err() {
+            warn!(
+                "{} requests still in progress after {:.3}s, shutting down anyway",
+                in_flight.requests.load(Ordering::SeqCst),
+                drain_timeout.as_secs_f64()
+            );
+        }
+
+        self.hooks.run().await;
+
+        info!("Server stopped");
+
+        Ok(())
     }
 
     fn handle_connection(
         handlers: Arc<Router>,
+        rewriters: Arc<Rewriters>,
+        in_flight: Arc<InFlight>,
         stream: TcpStream,
         peer_addr: SocketAddr,
     ) -> JoinHandle<()> {
@@ -99,7 +435,9 @@ impl Server {
             debug!("{} new connection from {:?}", "http".purple(), peer_addr);
 
             loop {
-                let request = match Request::read(peer_addr, &mut stream).await {
+                let (request, pump) = match Self::read_request(&handlers, peer_addr, &mut stream)
+                    .await
+                {
                     Ok(request) => request,
                     Err(ref err) => {
                         match err {
@@ -115,6 +453,27 @@ impl Server {
                                 );
                             }
 
+                            // The body wasn't read, so the connection can't be reused.
+                            Error::MemoryBudgetExceeded { .. } => {
+                                let response = Response::service_unavailable(memory::RETRY_AFTER);
+                                let _ = Self::send_response(&mut stream, response).await;
+
+                                warn!(
+                                    "{} rejected request from {:?}: {}",
+                                    "http".purple(),
+                                    peer_addr,
+                                    err
+                                );
+                            }
+
+                            Error::Rejected(rejection) => {
+                                rejection.record(&peer_addr);
+
+                                if let Some(response) = rejection.response() {
+                                    let _ = Self::send_response(&mut stream, response).await;
+                                }
+                            }
+
                             _ => (),
                         }
                         debug!(
@@ -127,85 +486,389 @@ impl Server {
                     }
                 };
 
-                let start = Instant::now();
+                let request_in_flight = in_flight.start();
+                let serve = Self::serve(&handlers, &rewriters, request);
 
-                match handlers.find(request.path()) {
-                    Some(handler) => {
-                        // Set the matching regex to extract parameters.
-                        let request = request.with_params(handler.path_with_regex().params());
+                // Read the rest of the body while the controller runs, so the connection
+                // is ready for the next request.
+                let (served, reusable) = match pump {
+                    Some(pump) => {
+                        tokio::join!(
+                            async {
+                                let served = serve.await;
+                                served.request.discard_body();
+                                served
+                            },
+                            pump.run(&mut stream)
+                        )
+                    }
+                    None => (serve.await, true),
+                };
 
-                        // Pass the request to the controller to get a response.
-                        let response = match handler.handle_internal(request.clone()).await {
-                            Ok(response) => response,
-                            Err(err) => {
-                                error!("{}", err);
-                                Response::internal_error(err)
-                            }
+                let Served {
+                    request,
+                    handler,
+                    response,
+                    memory,
+                } = served;
+                let ok = response.status().ok();
+
+                if let Err(err) = Self::send_response(&mut stream, response).await {
+                    // The stream was cut off, so the client doesn't take it for the whole body.
+                    if let Some(cut_off) = Self::cut_off(&err) {
+                        warn!(
+                            "{} {} {}, closing the connection",
+                            "http".purple(),
+                            handler
+                                .map(|handler| handler.controller_name())
+                                .unwrap_or(std::any::type_name::<Self>())
+                                .green(),
+                            cut_off
+                        );
+                    } else {
+                        debug!("{} error {:?}", peer_addr, err);
+                    }
+                    break;
+                }
+
+                if !reusable {
+                    break;
+                }
+
+                // Long-lived streams, like websockets, don't delay the shutdown.
+                drop(memory);
+                drop(request_in_flight);
+
+                if let Some(handler) = handler {
+                    if ok {
+                        match handler
+                            .handle_stream(&request, Stream::Plain(&mut stream))
+                            .await
+                        {
+                            Ok(true) => (),
+                            _ => break,
                         };
+                    } else if !request.keep_alive() {
+                        break;
+                    }
+                }
 
-                        // Set the session on the request before we pass it down
-                        // to the stream handler.
-                        let request = request.set_session(response.session().clone());
-                        let ok = response.status().ok();
+                // Don't wait for more requests on this connection while shutting down.
+                if !ready() {
+                    break;
+                }
+            }
+        })
+    }
 
-                        // Calculate duration.
-                        // We include the time to find the handler in the duration.
-                        let duration = Instant::now() - start;
+    /// Read the head of the request, and its body, unless the route streams it.
+    async fn read_request(
+        handlers: &Router,
+        peer_addr: SocketAddr,
+        mut stream: impl AsyncRead + Unpin,
+    ) -> Result<(Request, Option<BodyPump>), Error> {
+        let head = Head::read(&mut stream).await?;
 
-                        // Log request.
-                        Self::log(&request, handler.controller_name(), &response, duration);
+        let stream_body = handlers
+            .find(head.path())
+            .map(|handler| handler.streams_body())
+            .unwrap_or(false);
 
-                        if let Err(err) = Self::send_response(&mut stream, response).await {
-                            debug!("{} error {:?}", peer_addr, err);
-                            break;
-                        }
+        if stream_body {
+            let (request, pump) = Request::streaming(peer_addr, head)?;
+            Ok((request, Some(pump)))
+        } else {
+            let request = Request::read_body(peer_addr, head, stream, Budget::global()).await?;
+            Ok((request, None))
+        }
+    }
 
-                        if ok {
-                            match handler
-                                .handle_stream(&request, Stream::Plain(&mut stream))
-                                .await
-                            {
-                                Ok(true) => continue,
-                                _ => break,
-                            };
-                        }
-                    }
+    /// Serve a request without a network connection, e.g. in tests. The request goes through
+    /// the router, the controller and its middleware, and the HTML rewriters, like requests
+    /// received by [`Server::launch`]. WebSocket upgrades are not supported.
+    pub async fn handle(&self, request: Request) -> Response {
+        // The controller's future can be large, so keep it off the caller's stack.
+        Box::pin(Self::serve(&self.handlers, &self.rewriters, request))
+            .await
+            .response
+    }
 
-                    None => {
-                        // Log duration of search.
-                        let duration = Instant::now() - start;
+    /// Find the handler for the request and run it.
+    async fn serve<'a>(
+        handlers: &'a Router,
+        rewriters: &Rewriters,
+        request: Request,
+    ) -> Served<'a> {
+        let start = Instant::now();
 
-                        // Generate default not found response.
-                        let response = Response::not_found();
+        match handlers.find(request.path()) {
+            Some(handler) => {
+                // Set the matching regex to extract parameters.
+                let request = request
+                    .with_params(handler.path_with_regex().params())
+                    .with_method_override();
 
-                        // Log the response.
-                        Self::log(&request, std::any::type_name::<Self>(), &response, duration);
+                // Pass the request to the controller to get a response.
+                let timings = request.timings().clone();
 
-                        // Send reply to client.
-                        if let Err(err) = Self::send_response(&mut stream, response).await {
-                            debug!("{} error {:?}", peer_addr, err);
-                            break;
+                if rewriters.collect_queries {
+                    timings.collect_queries();
+                }
+
+                // Wait for a slot if the route limits concurrent requests.
+                let permit = match handler.concurrency() {
+                    Some(limit) => {
+                        let queued = Instant::now();
+                        let permit = limit.acquire().await.map(Some);
+                        timings.record("queue", queued.elapsed());
+                        permit
+                    }
+                    None => Ok(None),
+                };
+
+                let response = match permit {
+                    // Don't run controllers that need the database while it's down.
+                    Ok(_) if !handler.database_free() && !health::database_available() => {
+                        health::unavailable(&request, None)
+                    }
+
+                    // The controller's future can be large, so keep it off the stack.
+                    Ok(_permit) => match timings
+                        .clone()
+                        .scope(Box::pin(Self::scoped(
+                            &request,
+                            handler.response_size_limit(),
+                            handler.handle_internal(request.clone()),
+                        )))
+                        .await
+                    {
+                        Ok(response) => match response.body_too_large() {
+                            // Send an error instead of a body which was dropped.
+                            Some(err) => {
+                                warn!(
+                                    "{} {} {}",
+                                    "http".purple(),
+                                    handler.controller_name().green(),
+                                    err
+                                );
+                                error_hook::call(&err, &request);
+                                Response::internal_error(err)
+                            }
+                            None => response,
+                        },
+                        Err(err) => {
+                            error!("{}", err);
+                            error_hook::call(&err, &request);
+                            ErrorReport::new(ErrorKind::Controller, &err)
+                                .request(&request)
+                                .tag("controller", handler.controller_name())
+                                .send();
+                            Response::internal_error(err)
                         }
+                    },
+
+                    Err(err) => {
+                        warn!("{} {}", "http".purple(), err);
+                        Self::unavailable(&request, &err)
                     }
+                };
+
+                // Set the session on the request before we pass it down
+                // to the rewriters and the stream handler.
+                let request = request.set_session(response.session().clone());
+
+                let response = rewriters.rewrite(&request, response);
+
+                // Account for the response body until it's sent.
+                let (response, memory) = Self::reserve(response);
+
+                // Calculate duration.
+                // We include the time to find the handler in the duration.
+                let duration = Instant::now() - start;
+
+                let response = Self::server_timing(&request, response, &timings, start, duration);
+
+                let response = if capture::active() {
+                    capture::record(&request, response, duration)
+                } else {
+                    response
+                };
+
+                // Log request.
+                Self::log(&request, handler.controller_name(), &response, duration);
+
+                let response = response.compress(&request);
+                let response = Self::head(&request, response);
+
+                Served {
+                    request,
+                    handler: Some(handler),
+                    response,
+                    memory,
                 }
             }
-        })
+
+            None => {
+                // Log duration of search.
+                let duration = Instant::now() - start;
+
+                // Generate default not found response.
+                let response = if Problem::accepted(&request) {
+                    Problem::new(404, "Not Found")
+                        .instance(request.path().path())
+                        .response()
+                } else {
+                    Response::not_found()
+                };
+
+                let response = if capture::active() {
+                    capture::record(&request, response, duration)
+                } else {
+                    response
+                };
+
+                // Log the response.
+                Self::log(&request, std::any::type_name::<Self>(), &response, duration);
+
+                let response = response.compress(&request);
+                let response = Self::head(&request, response);
+
+                Served {
+                    request,
+                    handler: None,
+                    response,
+                    memory: None,
+                }
+            }
+        }
+    }
+
+    /// Run the handler with the request's nonce, impersonation and feature flags subject
+    /// available to templates.
+    async fn scoped<F: Future>(request: &Request, max_body_size: usize, handle: F) -> F::Output {
+        let impersonation = request
+            .session()
+            .and_then(|session| session.impersonation())
+            .cloned();
+        let handle = Subject::from_request(request).scope(handle);
+        let handle = Impersonation::scope(impersonation, handle);
+
+        let handle = response::scope_max_body_size(max_body_size, handle);
+
+        request.nonce().clone().scope(handle).await
+    }
+
+    fn unavailable(request: &Request, err: &Error) -> Response {
+        if Problem::accepted(request) {
+            Problem::from(err)
+                .instance(request.path().path())
+                .extension("retry_after", concurrency::RETRY_AFTER)
+                .response()
+                .header("retry-after", concurrency::RETRY_AFTER)
+        } else {
+            Response::service_unavailable(concurrency::RETRY_AFTER)
+        }
+    }
+
+    fn reserve(response: Response) -> (Response, Option<Reservation>) {
+        let bytes = response.body_bytes().map(|body| body.len()).unwrap_or(0);
+
+        match Reservation::new(bytes) {
+            Ok(reservation) => (response, Some(reservation)),
+            Err(err) => {
+                warn!("{} {}", "http".purple(), err);
+                (Response::service_unavailable(memory::RETRY_AFTER), None)
+            }
+        }
+    }
+
+    fn server_timing(
+        request: &Request,
+        response: Response,
+        timings: &Timings,
+        start: Instant,
+        duration: Duration,
+    ) -> Response {
+        if !get_config().general.server_timing {
+            return response;
+        }
+
+        // Streamed bodies are still being produced, e.g. by queries, so if the client
+        // accepts trailers, send the timings once the last chunk is written.
+        let trailers = request
+            .header("te")
+            .map(|te| {
+                te.split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case("trailers"))
+            })
+            .unwrap_or(false);
+
+        if trailers && response.is_stream() && request.method() != &Method::Head {
+            let timings = timings.clone();
+            return response.deferred_trailer("server-timing", move || {
+                timings.record("total", start.elapsed());
+                timings.header_value().unwrap_or_default()
+            });
+        }
+
+        timings.record("total", duration);
+
+        match timings.header_value() {
+            Some(value) => response.header("server-timing", value),
+            None => response,
+        }
     }
 
     fn log(request: &Request, controller_name: &str, response: &Response, duration: Duration) {
-        let method = request.method().to_string();
+        let method = request.original_method().to_string();
         let path = request.path().path();
         let code = response.status().code() as i32;
         let duration = (duration.as_secs_f64() * 1000.0) as f32;
+        let fields = request.log_fields();
+        let client = request.client_ip();
 
-        info!(
-            "{} {} {} {} ({:.3} ms)",
-            method.purple(),
-            path.purple(),
-            controller_name.green(),
-            code,
-            duration,
-        );
+        if fields.is_empty() {
+            info!(
+                "{} {} {} {} {} ({:.3} ms)",
+                client,
+                method.purple(),
+                path.purple(),
+                controller_name.green(),
+                code,
+                duration,
+            );
+        } else {
+            info!(
+                "{} {} {} {} {} ({:.3} ms) {}",
+                client,
+                method.purple(),
+                path.purple(),
+                controller_name.green(),
+                code,
+                duration,
+                fields,
+            );
+        }
+    }
+
+    /// Responses to `HEAD` requests have the headers only.
+    fn head(request: &Request, response: Response) -> Response {
+        if request.method() == &Method::Head {
+            response.without_body()
+        } else {
+            response
+        }
+    }
+
+    /// The error if the response body was stopped at the maximum response size.
+    fn cut_off(err: &Error) -> Option<&WriterError> {
+        match err {
+            Error::Io(err) => err
+                .get_ref()
+                .and_then(|err| err.downcast_ref::<WriterError>())
+                .filter(|err| matches!(err, WriterError::BodyTooLarge { .. })),
+            _ => None,
+        }
     }
 
     async fn send_response(
@@ -218,3 +881,558 @@ impl Server {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::controller::{Controller, Error as ControllerError, PageController, RestController};
+    use crate::http::ConcurrencyLimit;
+    use crate::prelude::async_trait;
+
+    use tokio::io::AsyncReadExt;
+
+    #[derive(Default)]
+    struct Slow;
+
+    #[async_trait]
+    impl Controller for Slow {
+        async fn handle(&self, _request: &Request) -> Result<Response, ControllerError> {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(Response::new().text("done"))
+        }
+    }
+
+    #[derive(Default)]
+    struct Page;
+
+    #[async_trait]
+    impl Controller for Page {
+        async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+            match request.path().path() {
+                "/text" => Ok(Response::new().text("</body>")),
+                _ => Ok(Response::new().html("<body><p>hello</p></body>")),
+            }
+        }
+    }
+
+    fn free_address() -> String {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        format!("127.0.0.1:{}", port)
+    }
+
+    async fn get(address: &str, path: &str) -> String {
+        send(address, "GET", path).await
+    }
+
+    async fn send(address: &str, method: &str, path: &str) -> String {
+        let mut stream = loop {
+            match TcpStream::connect(address).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        stream
+            .write_all(
+                format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path).as_bytes(),
+            )
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_inherited_listener() {
+        use std::os::fd::IntoRawFd;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let fd = listener.into_raw_fd();
+
+        // The address passed to launch isn't bound.
+        let server = unsafe { Server::new(vec![Page.route("/text")]).from_raw_fd(fd) };
+        tokio::spawn(server.launch("255.255.255.255:1"));
+
+        let response = get(&address, "/text").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n</body>"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_html_rewriter() {
+        let address = free_address();
+        let server = Server::new(vec![Page.route("/"), Page.route("/text")])
+            .html_rewriter(|request, body| {
+                *body = body.replace(
+                    "</body>",
+                    &format!("<footer>{}</footer></body>", request.path().path()),
+                );
+            })
+            .html_rewriter(|_request, body| body.push_str("<!-- done -->"));
+        tokio::spawn(server.launch(address.clone()));
+
+        let response = get(&address, "/").await;
+        let body = "<body><p>hello</p><footer>/</footer></body><!-- done -->";
+        assert!(
+            response.ends_with(&format!("\r\n\r\n{}", body)),
+            "{}",
+            response
+        );
+        assert!(response.contains(&format!("content-length: {}\r\n", body.len())));
+
+        // Only HTML is rewritten.
+        let response = get(&address, "/text").await;
+        assert!(response.ends_with("\r\n\r\n</body>"), "{}", response);
+    }
+
+    #[derive(Default)]
+    struct Login;
+
+    #[async_trait]
+    impl Controller for Login {
+        async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+            PageController::handle(self, request).await
+        }
+    }
+
+    #[async_trait]
+    impl PageController for Login {
+        async fn get(&self, _request: &Request) -> Result<Response, ControllerError> {
+            Ok(Response::new().html("<form></form>"))
+        }
+    }
+
+    #[derive(Default)]
+    struct Users;
+
+    #[async_trait]
+    impl Controller for Users {
+        async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+            RestController::handle(self, request).await
+        }
+    }
+
+    #[async_trait]
+    impl RestController for Users {
+        type Resource = i64;
+
+        async fn list(&self, _request: &Request) -> Result<Response, ControllerError> {
+            Ok(Response::new().json(serde_json::json!([1, 2]))?)
+        }
+
+        async fn get(&self, _request: &Request, id: &i64) -> Result<Response, ControllerError> {
+            Ok(Response::new().text(format!("user #{}", id)))
+        }
+    }
+
+    #[derive(Default)]
+    struct Report;
+
+    #[async_trait]
+    impl Controller for Report {
+        async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+            if request.method() == &Method::Head {
+                Ok(Response::new().header("content-length", 1024))
+            } else {
+                Ok(Response::new().text("x".repeat(1024)))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_head() {
+        let address = free_address();
+        let server = Server::new(vec![
+            Login.route("/login"),
+            Users.rest("/users"),
+            Report.route("/report"),
+            Export.route("/export"),
+        ]);
+        tokio::spawn(server.launch(address.clone()));
+
+        for (path, length) in [
+            ("/login", Some(13)),
+            ("/users", Some(5)),
+            ("/users/5", Some(7)),
+            ("/report", Some(1024)),
+            ("/export", None),
+        ] {
+            let response = send(&address, "HEAD", path).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+            assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+            // Same framing as GET, without the body.
+            let get = get(&address, path).await;
+            for header in ["content-length", "content-type", "transfer-encoding"] {
+                let line = |response: &str| {
+                    response
+                        .lines()
+                        .find(|line| line.starts_with(header))
+                        .map(|line| line.to_string())
+                };
+                assert_eq!(line(&response), line(&get), "{}", header);
+            }
+            assert!(!get.split_once("\r\n\r\n").unwrap().1.is_empty());
+
+            if let Some(length) = length {
+                assert!(
+                    response.contains(&format!("content-length: {}\r\n", length)),
+                    "{}",
+                    response
+                );
+            }
+        }
+
+        // Other methods are still rejected.
+        let response = send(&address, "DELETE", "/login").await;
+        assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+    }
+
+    #[derive(Default)]
+    struct Export;
+
+    #[async_trait]
+    impl Controller for Export {
+        async fn handle(&self, _request: &Request) -> Result<Response, ControllerError> {
+            let rows = (0..10_000)
+                .map(|i| format!("{},row\n", i))
+                .collect::<String>();
+            Ok(Response::new()
+                .stream(std::io::Cursor::new(rows.into_bytes()))
+                .header("content-type", "text/csv"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_keep_alive() {
+        let address = free_address();
+        tokio::spawn(Server::new(vec![Export.route("/export")]).launch(address.clone()));
+
+        let mut stream = loop {
+            match TcpStream::connect(&address).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        let request = b"GET /export HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n";
+        stream.write_all(request).await.unwrap();
+
+        // Read until the last chunk.
+        let mut first = vec![];
+        let mut buf = vec![0u8; 4096];
+        while !first.ends_with(b"\r\n0\r\n\r\n") {
+            let read = stream.read(&mut buf).await.unwrap();
+            assert!(read > 0, "connection closed");
+            first.extend_from_slice(&buf[..read]);
+        }
+        let first = String::from_utf8(first).unwrap();
+        assert!(first.contains("transfer-encoding: chunked\r\n"));
+        assert!(!first.contains("content-length"));
+        assert!(first.contains("9999,row\n"));
+
+        // The connection is reused.
+        stream.write_all(request).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut second = String::new();
+        stream.read_to_string(&mut second).await.unwrap();
+        assert!(second.starts_with("HTTP/1.1 200"), "{}", second);
+        assert!(second.ends_with("\r\n0\r\n\r\n"));
+    }
+
+    #[derive(Default)]
+    struct Upload;
+
+    #[async_trait]
+    impl Controller for Upload {
+        fn skip_csrf(&self) -> bool {
+            true
+        }
+
+        async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+            if request.path().path() == "/ignore" {
+                return Ok(Response::new().text("ignored"));
+            }
+
+            if request.path().path() == "/multipart" {
+                let multipart = request.multipart().await?;
+                let file = multipart.file("file").unwrap();
+                return Ok(Response::new().text(format!(
+                    "{} {} bytes on disk: {}",
+                    multipart.field("title").unwrap_or_default(),
+                    file.size(),
+                    file.path().is_some()
+                )));
+            }
+
+            let mut body = request.body_stream().unwrap();
+            assert!(request.body_stream().is_none());
+            let mut read = 0;
+            while let Some(chunk) = body.chunk().await {
+                read += chunk?.len();
+            }
+
+            Ok(Response::new().text(format!("read {} bytes", read)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_body() {
+        let address = free_address();
+        tokio::spawn(
+            Server::new(vec![
+                Upload.route("/upload").stream_body(),
+                Upload.route("/ignore").stream_body(),
+            ])
+            .launch(address.clone()),
+        );
+
+        let mut stream = loop {
+            match TcpStream::connect(&address).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        let mut requests =
+            b"POST /upload HTTP/1.1\r\nConnection: keep-alive\r\nContent-Length: 200000\r\n\r\n"
+                .to_vec();
+        requests.extend(vec![b'a'; 200_000]);
+        // The unread body is thrown away before the next request.
+        requests.extend(b"POST /ignore HTTP/1.1\r\nConnection: keep-alive\r\nTransfer-Encoding: chunked\r\n\r\n");
+        requests.extend(b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n");
+        requests.extend(b"POST /upload HTTP/1.1\r\nConnection: keep-alive\r\nTransfer-Encoding: chunked\r\n\r\n");
+        requests.extend(b"5\r\nhello\r\n0\r\n\r\n");
+
+        stream.write_all(&requests).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut responses = String::new();
+        stream.read_to_string(&mut responses).await.unwrap();
+
+        assert_eq!(
+            responses.matches("HTTP/1.1 200").count(),
+            3,
+            "{}",
+            responses
+        );
+        let first = responses.find("read 200000 bytes").unwrap();
+        let second = responses.find("ignored").unwrap();
+        assert!(first < second);
+        assert!(responses.ends_with("read 5 bytes"), "{}", responses);
+    }
+
+    #[tokio::test]
+    async fn test_stream_multipart() {
+        let address = free_address();
+        tokio::spawn(
+            Server::new(vec![Upload.route("/multipart").stream_body()]).launch(address.clone()),
+        );
+
+        let mut stream = loop {
+            match TcpStream::connect(&address).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        // Larger than multipart_disk_threshold.
+        let size = get_config().general.multipart_disk_threshold + 1;
+        let mut body = b"--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n"
+            .to_vec();
+        body.extend(vec![b'a'; size]);
+        body.extend(b"\r\n--b--\r\n");
+
+        let mut request = format!(
+            "POST /multipart HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        request.extend(body);
+
+        stream.write_all(&request).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(
+            response.ends_with(&format!("hello {} bytes on disk: true", size)),
+            "{}",
+            response
+        );
+    }
+
+    #[derive(Default)]
+    struct Runaway;
+
+    #[async_trait]
+    impl Controller for Runaway {
+        async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+            let page = "<p>row</p>".repeat(10_000);
+
+            if request.path().path().starts_with("/runaway/stream") {
+                Ok(Response::new().stream(std::io::Cursor::new(page.into_bytes())))
+            } else {
+                Ok(Response::new().html(page))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_timing_trailer() {
+        let address = free_address();
+        tokio::spawn(Server::new(vec![Runaway.route("/runaway/stream")]).launch(address.clone()));
+
+        let mut stream = loop {
+            match TcpStream::connect(&address).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        stream
+            .write_all(
+                b"GET /runaway/stream HTTP/1.1\r\nHost: localhost\r\nTE: gzip, trailers\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("\r\ntrailer: server-timing\r\n"), "{}", head);
+        assert!(
+            head.contains("\r\ntransfer-encoding: chunked\r\n"),
+            "{}",
+            head
+        );
+        assert!(!head.contains("server-timing:"), "{}", head);
+
+        // The last chunk is followed by the trailer.
+        let (_, trailer) = body.rsplit_once("\r\n0\r\n").unwrap();
+        assert!(
+            trailer.starts_with("server-timing: total;dur="),
+            "{}",
+            trailer
+        );
+        assert!(trailer.ends_with("\r\n\r\n"), "{}", trailer);
+
+        // Without TE: trailers, the timings are sent in the header.
+        let response = get(&address, "/runaway/stream").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("\r\nserver-timing: total;dur="), "{}", head);
+        assert!(!head.contains("trailer:"), "{}", head);
+        assert!(body.ends_with("\r\n0\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_max_response_size() {
+        let address = free_address();
+        let server = Server::new(vec![
+            Runaway.route("/runaway").max_response_size(70_000),
+            Runaway
+                .route("/runaway/stream")
+                .max_response_size(70_000)
+                .with_rank(1),
+            Runaway.route("/download").max_response_size(0),
+        ]);
+        tokio::spawn(server.launch(address.clone()));
+
+        // Buffered bodies are replaced with an error.
+        let response = get(&address, "/runaway").await;
+        assert!(response.starts_with("HTTP/1.1 500"), "{}", response);
+        assert!(!response.contains("<p>row</p>"));
+
+        // Streams are cut off and the connection is closed without the last chunk.
+        let response = get(&address, "/runaway/stream").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("transfer-encoding: chunked\r\n"));
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+        assert!(
+            body.len() > 64 * 1024 && body.len() < 70_000,
+            "{}",
+            body.len()
+        );
+        assert!(!response.ends_with("0\r\n\r\n"));
+
+        // Routes can opt out.
+        let response = get(&address, "/download").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with(&"<p>row</p>".repeat(10_000)));
+    }
+
+    async fn status(address: String, path: &str) -> u16 {
+        let mut stream = loop {
+            match TcpStream::connect(&address).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .await
+            .unwrap();
+
+        let mut buf = vec![0u8; 12];
+        stream.read_exact(&mut buf).await.unwrap();
+        String::from_utf8(buf[9..12].to_vec())
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrency() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = format!("127.0.0.1:{}", port);
+
+        let limited = ConcurrencyLimit::new(2);
+        let server = Server::new(vec![
+            Slow.route("/limited").concurrency_limit(limited.clone()),
+            Slow.route("/queued")
+                .max_concurrency(1)
+                .queue_timeout(Duration::from_secs(5)),
+        ]);
+        tokio::spawn(server.launch(address.clone()));
+
+        // Wait for the server to start.
+        assert_eq!(status(address.clone(), "/queued").await, 200);
+
+        let requests = (0..6)
+            .map(|_| tokio::spawn(status(address.clone(), "/limited")))
+            .collect::<Vec<_>>();
+        let mut codes = vec![];
+        for request in requests {
+            codes.push(request.await.unwrap());
+        }
+        codes.sort();
+
+        assert_eq!(codes, vec![200, 200, 503, 503, 503, 503]);
+        assert_eq!(limited.rejected(), 4);
+        assert_eq!(limited.current(), 0);
+
+        // Queued requests are served one at a time.
+        let requests = (0..3)
+            .map(|_| tokio::spawn(status(address.clone(), "/queued")))
+            .collect::<Vec<_>>();
+        for request in requests {
+            assert_eq!(request.await.unwrap(), 200);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Signed URLs, giving temporary access to a page without a session.
+//!
+//! A signed URL includes its expiration time (`exp`) and a signature (`sig`) of the path, the query parameters
+//! and the expiration time, created with the application secret key. Links to private downloads or unsubscribe pages
+//! can be sent by email, and stop working when they expire or if any part of them is changed:
+//!
+//! ```
+//! use rwf::http::signed_url;
+//! use time::Duration;
+//!
+//! let url = signed_url("/unsubscribe", &[("user_id", "5")], Duration::days(7));
+//! assert!(url.starts_with("/unsubscribe?user_id=5&exp="));
+//! ```
+//!
+//! Signed URLs are checked by the [`VerifySignedUrl`](crate::controller::middleware::VerifySignedUrl) middleware,
+//! or with [`verify`]. The signature is checked with the current secret key and all previous ones, so links
+//! keep working when the key is rotated.
+use thiserror::Error as ThisError;
+use time::{Duration, OffsetDateTime};
+
+use std::collections::HashMap;
+
+use super::{urlencode, Path};
+use crate::crypto::{sign, verify_signature};
+
+/// Query parameter with the expiration time, as a UNIX timestamp.
+pub static EXPIRES_PARAM: &str = "exp";
+
+/// Query parameter with the signature.
+pub static SIGNATURE_PARAM: &str = "sig";
+
+/// Signed URL is not valid.
+#[derive(ThisError, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    #[error("url is not signed")]
+    Missing,
+
+    #[error("url signature is not valid")]
+    Invalid,
+
+    #[error("url expired")]
+    Expired,
+}
+
+/// Create a URL to `path` with the query parameters `params`, which is valid for `expires_in`.
+///
+/// Parameters are sorted by name, so the same link is created regardless of their order.
+pub fn signed_url(path: &str, params: &[(&str, &str)], expires_in: Duration) -> String {
+    let expires_at = (OffsetDateTime::now_utc() + expires_in).unix_timestamp();
+    signed_url_at(path, params, expires_at)
+}
+
+fn signed_url_at(path: &str, params: &[(&str, &str)], expires_at: i64) -> String {
+    let expires_at = expires_at.to_string();
+
+    let mut params = params
+        .iter()
+        .filter(|(name, _)| *name != EXPIRES_PARAM && *name != SIGNATURE_PARAM)
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Vec<_>>();
+    params.sort();
+    params.push((EXPIRES_PARAM.to_string(), expires_at));
+
+    let signature = sign(canonical(path, &params).as_bytes());
+
+    let query = params
+        .iter()
+        .map(|(name, value)| format!("{}={}", urlencode(name), urlencode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    format!(
+        "{}?{}&{}={}",
+        encode(&decode(path), true),
+        query,
+        SIGNATURE_PARAM,
+        signature
+    )
+}
+
+/// Check that the URL was created with [`signed_url`], wasn't changed, and hasn't expired yet.
+pub fn verify(path: &Path) -> Result<(), Error> {
+    let query: &HashMap<String, String> = path.query();
+
+    let signature = query.get(SIGNATURE_PARAM).ok_or(Error::Missing)?;
+    let expires_at = query.get(EXPIRES_PARAM).ok_or(Error::Missing)?;
+
+    let params = query
+        .iter()
+        .filter(|(name, _)| !name.is_empty() && name.as_str() != SIGNATURE_PARAM)
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect::<Vec<_>>();
+
+    if !verify_signature(canonical(path.base(), &params).as_bytes(), signature) {
+        return Err(Error::Invalid);
+    }
+
+    // The expiration time is signed, so it can be trusted now.
+    let expires_at = expires_at.parse::<i64>().map_err(|_| Error::Invalid)?;
+
+    if OffsetDateTime::now_utc().unix_timestamp() > expires_at {
+        return Err(Error::Expired);
+    }
+
+    Ok(())
+}
+
+/// Remove the expiration time and the signature from the URL.
+pub fn strip(path: &Path) -> Path {
+    let mut query = path.query().clone();
+    query.remove(EXPIRES_PARAM);
+    query.remove(SIGNATURE_PARAM);
+
+    Path::from_parts(path.base(), &query)
+}
+
+/// The signed data: the path and the parameters sorted by name, encoded the same way regardless
+/// of how the client encoded them.
+fn canonical(path: &str, params: &[(String, String)]) -> String {
+    let mut params = params
+        .iter()
+        .map(|(name, value)| format!("{}={}", encode(name, false), encode(value, false)))
+        .collect::<Vec<_>>();
+    params.sort();
+
+    format!("{}?{}", encode(&decode(path), true), params.join("&"))
+}
+
+/// Percent-encode everything except unreserved characters, and slashes if `path` is set.
+fn encode(value: &str, path: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if path => encoded.push('/'),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+/// Decode a percent-encoded path.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(url: &str) -> Path {
+        Path::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_signed_url() {
+        let url = signed_url(
+            "/downloads/annual report.pdf",
+            &[("user_id", "5"), ("format", "a&b=c")],
+            Duration::hours(1),
+        );
+
+        assert!(
+            url.starts_with("/downloads/annual%20report.pdf?format=a%26b%3Dc&user_id=5&exp="),
+            "{}",
+            url
+        );
+        assert_eq!(verify(&parse(&url)), Ok(()));
+
+        let stripped = strip(&parse(&url));
+        assert_eq!(stripped.query().len(), 2);
+        assert_eq!(stripped.query().get::<String>("format").unwrap(), "a&b=c");
+    }
+
+    #[test]
+    fn test_expired() {
+        let url = signed_url("/unsubscribe", &[("user_id", "5")], Duration::seconds(-1));
+        assert_eq!(verify(&parse(&url)), Err(Error::Expired));
+
+        // Moving the expiration into the future breaks the signature.
+        let exp = parse(&url).query().get::<i64>(EXPIRES_PARAM).unwrap();
+        let extended = url.replace(&format!("exp={}", exp), &format!("exp={}", exp + 3600));
+        assert_eq!(verify(&parse(&extended)), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn test_tampered() {
+        let url = signed_url("/unsubscribe", &[("user_id", "5")], Duration::hours(1));
+
+        for tampered in [
+            url.replace("user_id=5", "user_id=6"),
+            url.replace("/unsubscribe", "/delete"),
+            format!("{}&admin=true", url),
+            url.replace("user_id=5&", ""),
+            url.replace("&sig=", "&sig=a"),
+        ] {
+            assert_eq!(
+                verify(&parse(&tampered)),
+                Err(Error::Invalid),
+                "{}",
+                tampered
+            );
+        }
+
+        assert_eq!(
+            verify(&parse("/unsubscribe?user_id=5")),
+            Err(Error::Missing)
+        );
+    }
+
+    #[test]
+    fn test_reordered() {
+        let expires_at = OffsetDateTime::now_utc().unix_timestamp() + 3600;
+        let url = signed_url_at("/files", &[("b", "2"), ("a", "1")], expires_at);
+        assert_eq!(
+            url,
+            signed_url_at("/files", &[("a", "1"), ("b", "2")], expires_at)
+        );
+
+        // Clients can reorder the parameters and encode them differently.
+        let (path, query) = url.split_once('?').unwrap();
+        let mut params = query.split('&').collect::<Vec<_>>();
+        params.reverse();
+        let reordered = format!("{}?{}", path.replace("files", "%66iles"), params.join("&"));
+
+        assert_eq!(verify(&parse(&reordered)), Ok(()));
+    }
+}
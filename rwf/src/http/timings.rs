@@ -0,0 +1,324 @@
+//! Request timings, reported to the browser with the [`Server-Timing`](https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Server-Timing) header.
+//!
+//! Each request gets its own [`Timings`] recorder, available with [`crate::http::Request::timings`]. Time spent executing
+//! ORM queries (`db`) and rendering templates (`render`) is recorded automatically, together with the total time
+//! it took to handle the request (`total`). Controllers can record their own timings:
+//!
+//! ```
+//! # use rwf::http::Timings;
+//! let timings = Timings::new();
+//!
+//! timings.start("payment");
+//! // Call payment API.
+//! timings.stop("payment");
+//!
+//! {
+//!     let _span = timings.span("search");
+//!     // Search is recorded until `_span` is dropped.
+//! }
+//!
+//! assert!(timings.header_value().unwrap().starts_with("payment;dur="));
+//! ```
+//!
+//! The header is added to responses only if the `server_timing` setting is enabled.
+use parking_lot::Mutex;
+use tokio::task_local;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Maximum number of entries in the `Server-Timing` header.
+/// Browsers ignore very long headers, so extra entries are dropped.
+pub const MAX_ENTRIES: usize = 16;
+
+task_local! {
+    static CURRENT: Timings;
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    duration: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: Vec<Entry>,
+    started: HashMap<String, Instant>,
+    open: HashMap<String, usize>,
+    queries: Option<Vec<Entry>>,
+}
+
+/// Records how long different parts of a request take.
+///
+/// It's safe to clone since the timings are behind an [`std::sync::Arc`].
+#[derive(Debug, Default, Clone)]
+pub struct Timings {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Timings {
+    /// Create new empty timings recorder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Timings of the request currently being handled by this task, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|timings| timings.clone()).ok()
+    }
+
+    /// Run the future with these timings set as [`Timings::current`].
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    /// Start measuring `name`. Call [`Timings::stop`] to record it.
+    pub fn start(&self, name: &str) {
+        self.inner
+            .lock()
+            .started
+            .insert(name.to_string(), Instant::now());
+    }
+
+    /// Stop measuring `name` and record the time elapsed since [`Timings::start`].
+    pub fn stop(&self, name: &str) {
+        let started = self.inner.lock().started.remove(name);
+
+        if let Some(started) = started {
+            self.record(name, started.elapsed());
+        }
+    }
+
+    /// Measure `name` until the returned span is dropped.
+    ///
+    /// Nested spans with the same name, e.g. partials rendered inside a template,
+    /// are counted only once.
+    pub fn span(&self, name: &str) -> TimingSpan {
+        let outermost = {
+            let mut inner = self.inner.lock();
+            let open = inner.open.entry(name.to_string()).or_default();
+            *open += 1;
+            *open == 1
+        };
+
+        TimingSpan {
+            timings: self.clone(),
+            name: name.to_string(),
+            start: Instant::now(),
+            outermost,
+        }
+    }
+
+    /// Record a duration. Durations recorded under the same name are added together.
+    pub fn record(&self, name: &str, duration: Duration) {
+        let mut inner = self.inner.lock();
+
+        match inner.entries.iter_mut().find(|entry| entry.name == name) {
+            Some(entry) => entry.duration += duration,
+            None => inner.entries.push(Entry {
+                name: name.to_string(),
+                duration,
+            }),
+        }
+    }
+
+    /// Keep the SQL of each query executed by the ORM, in addition to the total `db` time.
+    /// Used by the debug toolbar. Disabled by default since it copies every query.
+    pub fn collect_queries(&self) {
+        self.inner.lock().queries.get_or_insert_with(Vec::new);
+    }
+
+    /// Record a query executed by the ORM, if queries are collected.
+    pub fn record_query(&self, sql: impl FnOnce() -> String, duration: Duration) {
+        if let Some(queries) = self.inner.lock().queries.as_mut() {
+            queries.push(Entry {
+                name: sql(),
+                duration,
+            });
+        }
+    }
+
+    /// Queries recorded with [`Timings::record_query`], in the order they were executed.
+    pub fn queries(&self) -> Vec<(String, Duration)> {
+        self.inner
+            .lock()
+            .queries
+            .iter()
+            .flatten()
+            .map(|query| (query.name.clone(), query.duration))
+            .collect()
+    }
+
+    /// All recorded timings, in the order they were first recorded.
+    pub fn entries(&self) -> Vec<(String, Duration)> {
+        self.inner
+            .lock()
+            .entries
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.duration))
+            .collect()
+    }
+
+    /// Total time recorded under `name`.
+    pub fn duration(&self, name: &str) -> Option<Duration> {
+        self.inner
+            .lock()
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.duration)
+    }
+
+    /// Format the timings as the value of the `Server-Timing` header, e.g. `db;dur=1.250, total;dur=3.100`.
+    ///
+    /// At most [`MAX_ENTRIES`] are included, in the order they were first recorded. The `total`
+    /// entry is always kept.
+    pub fn header_value(&self) -> Option<String> {
+        let inner = self.inner.lock();
+        let total = inner.entries.iter().find(|entry| entry.name == "total");
+        let limit = MAX_ENTRIES - total.map(|_| 1).unwrap_or(0);
+
+        let entries = inner
+            .entries
+            .iter()
+            .filter(|entry| entry.name != "total")
+            .take(limit)
+            .chain(total)
+            .map(|entry| {
+                format!(
+                    "{};dur={:.3}",
+                    token(&entry.name),
+                    entry.duration.as_secs_f64() * 1000.0
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if entries.is_empty() {
+            None
+        } else {
+            Some(entries.join(", "))
+        }
+    }
+}
+
+/// Measures time until dropped. Created with [`Timings::span`].
+#[must_use = "the span is recorded when dropped"]
+pub struct TimingSpan {
+    timings: Timings,
+    name: String,
+    start: Instant,
+    outermost: bool,
+}
+
+impl Drop for TimingSpan {
+    fn drop(&mut self) {
+        if let Some(open) = self.timings.inner.lock().open.get_mut(&self.name) {
+            *open = open.saturating_sub(1);
+        }
+
+        if self.outermost {
+            self.timings.record(&self.name, self.start.elapsed());
+        }
+    }
+}
+
+/// Metric names must be valid HTTP tokens.
+fn token(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_value() {
+        let timings = Timings::new();
+        assert!(timings.header_value().is_none());
+
+        timings.record("db", Duration::from_micros(1500));
+        timings.record("db", Duration::from_micros(500));
+        timings.record("my metric", Duration::from_millis(1));
+        timings.record("total", Duration::from_millis(5));
+
+        assert_eq!(
+            timings.header_value().unwrap(),
+            "db;dur=2.000, my_metric;dur=1.000, total;dur=5.000"
+        );
+    }
+
+    #[test]
+    fn test_truncation() {
+        let timings = Timings::new();
+        timings.record("total", Duration::from_millis(1));
+        for i in 0..MAX_ENTRIES * 2 {
+            timings.record(&format!("step{}", i), Duration::from_millis(1));
+        }
+
+        let value = timings.header_value().unwrap();
+        assert_eq!(value.split(", ").count(), MAX_ENTRIES);
+        assert!(value.starts_with("step0;"));
+        assert!(value.ends_with("total;dur=1.000"));
+    }
+
+    #[test]
+    fn test_nested_spans() {
+        let timings = Timings::new();
+        let start = Instant::now();
+
+        {
+            let _outer = timings.span("render");
+            let _inner = timings.span("render");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let elapsed = start.elapsed();
+        let render = timings.duration("render").unwrap();
+        assert!(render >= Duration::from_millis(5));
+        assert!(render <= elapsed);
+    }
+
+    #[test]
+    fn test_queries() {
+        let timings = Timings::new();
+        timings.record_query(|| unreachable!(), Duration::from_millis(1));
+        assert!(timings.queries().is_empty());
+
+        timings.collect_queries();
+        timings.record_query(|| "SELECT 1".into(), Duration::from_millis(2));
+        timings.collect_queries();
+
+        assert_eq!(
+            timings.queries(),
+            vec![("SELECT 1".to_string(), Duration::from_millis(2))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_current() {
+        assert!(Timings::current().is_none());
+
+        let timings = Timings::new();
+        timings
+            .clone()
+            .scope(async {
+                Timings::current()
+                    .unwrap()
+                    .record("db", Duration::from_millis(1));
+            })
+            .await;
+
+        assert_eq!(timings.duration("db"), Some(Duration::from_millis(1)));
+    }
+}
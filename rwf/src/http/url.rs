@@ -1,4 +1,21 @@
 //! URL handling helpers.
+//!
+//! [`Url`] builds URLs from user data, encoding each part the way it needs to be encoded:
+//!
+//! ```
+//! use rwf::http::Url;
+//!
+//! let url = Url::new()
+//!     .segment("search")
+//!     .segment("rock & roll")
+//!     .query("q", "café & crème")
+//!     .fragment("results");
+//!
+//! assert_eq!(url.to_string(), "/search/rock%20&%20roll?q=caf%C3%A9+%26+cr%C3%A8me#results");
+//! ```
+use std::fmt::Display;
+
+use super::{path::constraint, Error};
 
 /// Decode a string encoded with URL encoding.
 ///
@@ -119,10 +136,362 @@ pub fn urlencode(s: &str) -> String {
     result
 }
 
+/// URL, split into editable parts.
+///
+/// Path segments, query pairs and the fragment are stored decoded, and encoded when the URL is displayed:
+/// path segments with percent-encoding, and query pairs with form encoding, so a space is `%20` in the path
+/// and `+` in the query. Parsing a URL and displaying it again gives the same URL, and parsing the result
+/// doesn't change it anymore.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Url {
+    /// Scheme and authority, e.g. `https://example.com:8000`.
+    origin: Option<String>,
+    segments: Vec<String>,
+    query: Vec<(String, String)>,
+    fragment: Option<String>,
+}
+
+impl Url {
+    /// Create a URL pointing to `/`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a URL, absolute (`https://example.com/a?b=c`) or relative to the host (`/a?b=c`).
+    ///
+    /// Malformed percent-encoded sequences are kept as-is.
+    pub fn parse(url: &str) -> Self {
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, Some(percent_decode(fragment, false))),
+            None => (url, None),
+        };
+
+        let (url, query) = match url.split_once('?') {
+            Some((url, query)) => (url, query),
+            None => (url, ""),
+        };
+
+        let (origin, path) = match url.find("://") {
+            Some(scheme_end) if !url[..scheme_end].contains('/') => {
+                let authority = scheme_end + 3;
+                let path = url[authority..]
+                    .find('/')
+                    .map(|start| authority + start)
+                    .unwrap_or(url.len());
+                (Some(url[..path].to_string()), &url[path..])
+            }
+            _ => (None, url),
+        };
+
+        let path = path.strip_prefix('/').unwrap_or(path);
+        let segments = if path.is_empty() {
+            vec![]
+        } else {
+            path.split('/')
+                .map(|segment| percent_decode(segment, false))
+                .collect()
+        };
+
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(name, true), percent_decode(value, true))
+            })
+            .collect();
+
+        Self {
+            origin,
+            segments,
+            query,
+            fragment,
+        }
+    }
+
+    /// Build the URL of a route, replacing its parameters, e.g. `/users/:id`,
+    /// with their values, which are encoded. Parameter constraints, e.g. `:id<i64>`, are ignored.
+    ///
+    /// ```
+    /// use rwf::http::Url;
+    ///
+    /// let url = Url::route("/users/:id/posts/:slug", &[("id", "5"), ("slug", "a/b")]).unwrap();
+    /// assert_eq!(url.to_string(), "/users/5/posts/a%2Fb");
+    ///
+    /// let url = Url::route("/users/:id<i64>", &[("id", 5)]).unwrap();
+    /// assert_eq!(url.to_string(), "/users/5");
+    /// ```
+    pub fn route(route: &str, params: &[(&str, impl ToString)]) -> Result<Self, Error> {
+        let route = route.strip_prefix('/').unwrap_or(route);
+
+        constraint::segments(route)
+            .into_iter()
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => {
+                    let (name, _) = constraint::split(name);
+                    params
+                        .iter()
+                        .find(|(param, _)| *param == name)
+                        .map(|(_, value)| value.to_string())
+                        .ok_or(Error::MissingParameter)
+                }
+                None => Ok(segment.to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|segments| Self::new().segments(segments))
+    }
+
+    /// Append a path segment. Characters with a meaning in the path, like `/` and `?`, are encoded.
+    pub fn segment(mut self, segment: impl ToString) -> Self {
+        self.segments.push(segment.to_string());
+        self
+    }
+
+    /// Append several path segments.
+    pub fn segments(mut self, segments: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.segments
+            .extend(segments.into_iter().map(|segment| segment.to_string()));
+        self
+    }
+
+    /// Append a query parameter, keeping parameters with the same name.
+    pub fn query(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set a query parameter, replacing all parameters with the same name, e.g. the page number of a paginated list.
+    pub fn set_query(self, name: impl ToString, value: impl ToString) -> Self {
+        let name = name.to_string();
+        self.remove_query(&name).query(name, value)
+    }
+
+    /// Remove all query parameters with this name.
+    pub fn remove_query(mut self, name: &str) -> Self {
+        self.query.retain(|(param, _)| param != name);
+        self
+    }
+
+    /// Set the fragment, e.g. `comments` for `/posts/5#comments`.
+    pub fn fragment(mut self, fragment: impl ToString) -> Self {
+        self.fragment = Some(fragment.to_string());
+        self
+    }
+
+    /// Scheme and authority, e.g. `https://example.com`, if the URL is absolute.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Decoded path segments.
+    pub fn path_segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// Encoded path, e.g. `/users/a%20b`.
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+
+        for segment in &self.segments {
+            path.push('/');
+            percent_encode(segment, PATH_SEGMENT, &mut path);
+        }
+
+        if path.is_empty() {
+            path.push('/');
+        }
+
+        path
+    }
+
+    /// Decoded query parameters, in order.
+    pub fn query_pairs(&self) -> &[(String, String)] {
+        &self.query
+    }
+
+    /// Value of the first query parameter with this name.
+    pub fn query_value(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Decoded fragment.
+    pub fn fragment_value(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+}
+
+impl Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut url = self.origin.clone().unwrap_or_default();
+
+        // An absolute URL without a path doesn't need the slash, e.g. `https://example.com?a=b`.
+        if self.origin.is_none() || !self.segments.is_empty() {
+            url.push_str(&self.path());
+        }
+
+        for (i, (name, value)) in self.query.iter().enumerate() {
+            url.push(if i == 0 { '?' } else { '&' });
+            form_encode(name, &mut url);
+            url.push('=');
+            form_encode(value, &mut url);
+        }
+
+        if let Some(ref fragment) = self.fragment {
+            url.push('#');
+            percent_encode(fragment, FRAGMENT, &mut url);
+        }
+
+        write!(f, "{}", url)
+    }
+}
+
+impl From<&str> for Url {
+    fn from(url: &str) -> Self {
+        Self::parse(url)
+    }
+}
+
+/// Characters, other than letters, digits and `-._~`, that don't need encoding in a path segment.
+const PATH_SEGMENT: &[u8] = b"!$&'()*+,;=:@";
+
+/// Characters, other than letters, digits and `-._~`, that don't need encoding in the fragment.
+const FRAGMENT: &[u8] = b"!$&'()*+,;=:@/?";
+
+/// Percent-encode everything except letters, digits, `-._~` and the `allowed` characters.
+pub(crate) fn percent_encode(value: &str, allowed: &[u8], output: &mut String) {
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) || allowed.contains(&byte) {
+            output.push(byte as char);
+        } else {
+            output.push_str(&format!("%{:02X}", byte));
+        }
+    }
+}
+
+/// Encode a query name or value like browsers encode forms.
+fn form_encode(value: &str, output: &mut String) {
+    for byte in value.bytes() {
+        match byte {
+            b' ' => output.push('+'),
+            byte if byte.is_ascii_alphanumeric() || b"*-._".contains(&byte) => {
+                output.push(byte as char)
+            }
+            byte => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+}
+
+/// Decode percent-encoded UTF-8, and `+` as a space if `plus` is set.
+pub(crate) fn percent_decode(value: &str, plus: bool) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(value.as_bytes(), plus)).to_string()
+}
+
+/// Decode percent-encoded bytes, and `+` as a space if `plus` is set.
+pub(crate) fn percent_decode_bytes(bytes: &[u8], plus: bool) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) if plus => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_url_encoding() {
+        let url = Url::new()
+            .segment("a b/c?d#e")
+            .segment("1+1=2&x")
+            .segment("😀")
+            .query("a b/c?d#e", "1+1=2&x")
+            .query("emoji", "😀")
+            .fragment("top 😀");
+
+        assert_eq!(
+            url.to_string(),
+            "/a%20b%2Fc%3Fd%23e/1+1=2&x/%F0%9F%98%80?a+b%2Fc%3Fd%23e=1%2B1%3D2%26x&emoji=%F0%9F%98%80#top%20%F0%9F%98%80"
+        );
+
+        let parsed = Url::parse(&url.to_string());
+        assert_eq!(parsed, url);
+        assert_eq!(parsed.path_segments(), &["a b/c?d#e", "1+1=2&x", "😀"]);
+        assert_eq!(parsed.query_value("a b/c?d#e"), Some("1+1=2&x"));
+        assert_eq!(parsed.query_value("emoji"), Some("😀"));
+        assert_eq!(parsed.fragment_value(), Some("top 😀"));
+    }
+
+    #[test]
+    fn test_url_round_trip() {
+        for url in [
+            "/",
+            "/users/5",
+            "/users/5/",
+            "/search?q=rock+%26+roll&page=2",
+            "/search?q=a%20b&empty=&flag",
+            "/files/%7Euser/r%C3%A9sum%C3%A9.pdf#page=2",
+            "/%E2%9C%93?%E2%9C%93=%E2%9C%93",
+            "/bad%zz/escape?x=%",
+            "https://example.com",
+            "https://example.com:8000/a/b?c=d#e",
+            "https://user@example.com/?",
+            "relative/path",
+        ] {
+            let once = Url::parse(url).to_string();
+            let twice = Url::parse(&once).to_string();
+            assert_eq!(once, twice, "{}", url);
+            assert_eq!(Url::parse(&once), Url::parse(url), "{}", url);
+        }
+
+        assert_eq!(
+            Url::parse("https://example.com:8000/a/b?c=d#e").to_string(),
+            "https://example.com:8000/a/b?c=d#e"
+        );
+        assert_eq!(Url::parse("/search?q=a%20b").to_string(), "/search?q=a+b");
+    }
+
+    #[test]
+    fn test_url_edit() {
+        let url = Url::parse("https://example.com/posts?page=1&tag=a&tag=b")
+            .set_query("page", 2)
+            .segment("rust & go");
+
+        assert_eq!(url.origin(), Some("https://example.com"));
+        assert_eq!(
+            url.to_string(),
+            "https://example.com/posts/rust%20&%20go?tag=a&tag=b&page=2"
+        );
+        assert_eq!(
+            url.remove_query("tag").to_string(),
+            "https://example.com/posts/rust%20&%20go?page=2"
+        );
+    }
+
     #[test]
     fn test_urldecode() {
         let url = "?foo=bar&hello=world";
@@ -0,0 +1,415 @@
+//! API versioning.
+//!
+//! Each version of an API is a group of routes, registered under a common prefix with [`Router::versioned`]:
+//!
+//! ```rust,ignore
+//! Router::versioned("/api", vec![
+//!     ApiVersion::new("v1", vec![
+//!         route!("/users" => UsersV1),
+//!         route!("/orders" => Orders),
+//!     ]),
+//!     ApiVersion::new("v2", vec![
+//!         route!("/users" => UsersV2),
+//!     ]),
+//! ])
+//! .into()
+//! ```
+//!
+//! Versions are listed from the oldest to the newest. A version inherits the routes of the versions before it,
+//! so `/api/v2/orders` is served by `Orders`, without registering it again. Routes registered in a version override
+//! the inherited ones, and [`ApiVersion::remove`] stops a route from being inherited.
+//!
+//! The version is taken from the path, e.g. `/api/v2/users`, or, with [`VersionStrategy::Header`], from a parameter
+//! of the `Accept` header, e.g. `Accept: application/json; version=2`, leaving the path as `/api/users`.
+use time::OffsetDateTime;
+
+use super::{
+    conditional::format_http_date,
+    path::{PathType, PathWithRegex},
+    Handler, Path, Request, Response, Router,
+};
+use crate::controller::{Controller, Error};
+
+/// Where the requested API version comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionStrategy {
+    /// First path segment after the prefix, e.g. `/api/v2/users`.
+    Path,
+    /// Parameter of the `Accept` header, e.g. `version` in `Accept: application/json; version=2`.
+    /// The leading `v` of the version name is optional. Requests without the parameter get the default version.
+    Header(String),
+}
+
+/// A version of the API, with its routes.
+pub struct ApiVersion {
+    name: String,
+    handlers: Vec<Handler>,
+    removed: Vec<PathWithRegex>,
+    deprecated: Option<OffsetDateTime>,
+    sunset: Option<OffsetDateTime>,
+}
+
+impl ApiVersion {
+    /// Create a version, e.g. `v2`, with routes relative to the version prefix.
+    pub fn new(name: impl ToString, handlers: Vec<Handler>) -> Self {
+        Self {
+            name: name.to_string(),
+            handlers,
+            removed: vec![],
+            deprecated: None,
+            sunset: None,
+        }
+    }
+
+    /// Don't inherit this route from previous versions. Requests for it get `404 - Not Found`,
+    /// unless this version registers it again.
+    pub fn remove(mut self, path: &str) -> Self {
+        self.removed.push(
+            Path::pattern(path)
+                .with_regex(PathType::Route)
+                .expect("valid route pattern"),
+        );
+        self
+    }
+
+    /// Mark the version as deprecated since this date. Responses get the `Deprecation` header.
+    pub fn deprecated(mut self, since: OffsetDateTime) -> Self {
+        self.deprecated = Some(since);
+        self
+    }
+
+    /// Date the version stops being served. Responses get the `Sunset` header.
+    pub fn sunset(mut self, date: OffsetDateTime) -> Self {
+        self.sunset = Some(date);
+        self
+    }
+
+    /// Version name, e.g. `v2`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn removes(&self, path: &str) -> bool {
+        self.removed.iter().any(|removed| removed.matches(path))
+    }
+
+    /// Add the `Deprecation` (RFC 9745) and `Sunset` (RFC 8594) headers.
+    fn headers(&self, mut response: Response) -> Response {
+        if let Some(deprecated) = self.deprecated {
+            response = response.header("deprecation", format!("@{}", deprecated.unix_timestamp()));
+        }
+
+        if let Some(sunset) = self.sunset {
+            response = response.header("sunset", format_http_date(sunset));
+        }
+
+        response
+    }
+}
+
+/// Versions of an API, mounted on a prefix. Create with [`Router::versioned`].
+pub struct Versioned {
+    prefix: String,
+    versions: Vec<(ApiVersion, Router)>,
+    strategy: VersionStrategy,
+    default_version: Option<String>,
+}
+
+impl Router {
+    /// Serve several versions of an API under the prefix, e.g. `/api`. Versions are ordered from
+    /// the oldest to the newest, and each one inherits the routes of the versions before it.
+    pub fn versioned(prefix: &str, versions: Vec<ApiVersion>) -> Versioned {
+        let prefix = Path::pattern(prefix)
+            .base()
+            .trim_end_matches('/')
+            .to_string();
+        let versions = versions
+            .into_iter()
+            .map(|mut version| {
+                let handlers = std::mem::take(&mut version.handlers);
+                (version, Router::new(handlers).expect("valid routes"))
+            })
+            .collect();
+
+        Versioned {
+            prefix,
+            versions,
+            strategy: VersionStrategy::Path,
+            default_version: None,
+        }
+    }
+}
+
+impl Versioned {
+    /// Where the requested version comes from. The default is [`VersionStrategy::Path`].
+    pub fn strategy(mut self, strategy: VersionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Version used when the request doesn't ask for one, with [`VersionStrategy::Header`].
+    /// The default is the newest version.
+    pub fn default_version(mut self, name: impl ToString) -> Self {
+        self.default_version = Some(name.to_string());
+        self
+    }
+
+    fn version(&self, name: &str) -> Option<usize> {
+        self.versions.iter().position(|(version, _)| {
+            version.name == name || version.name.strip_prefix('v') == Some(name)
+        })
+    }
+
+    /// Requested version and the path relative to it. `None` if the version doesn't exist.
+    fn resolve(&self, request: &Request) -> Option<(usize, String)> {
+        let rest = request
+            .path()
+            .base()
+            .strip_prefix(&self.prefix)
+            .unwrap_or_default();
+
+        match self.strategy {
+            VersionStrategy::Path => {
+                let rest = rest.strip_prefix('/')?;
+                let (name, path) = match rest.find('/') {
+                    Some(slash) => (&rest[..slash], &rest[slash..]),
+                    None => (rest, "/"),
+                };
+
+                Some((self.version(name)?, path.to_string()))
+            }
+
+            VersionStrategy::Header(ref param) => {
+                let requested = request
+                    .accepts()
+                    .iter()
+                    .find_map(|range| range.param(param).map(|name| name.to_string()));
+
+                let version = match requested.or_else(|| self.default_version.clone()) {
+                    Some(name) => self.version(&name)?,
+                    None => self.versions.len().checked_sub(1)?,
+                };
+                let path = match rest {
+                    "" => "/",
+                    rest if rest.starts_with('/') => rest,
+                    _ => return None,
+                };
+
+                Some((version, path.to_string()))
+            }
+        }
+    }
+
+    /// Route for the path in this version, or inherited from the previous ones.
+    fn find(&self, version: usize, path: &Path) -> Option<&Handler> {
+        for (version, router) in self.versions[..=version].iter().rev() {
+            if let Some(handler) = router.find(path) {
+                return Some(handler);
+            }
+
+            if version.removes(path.base()) {
+                return None;
+            }
+        }
+
+        None
+    }
+}
+
+impl From<Versioned> for Handler {
+    fn from(versioned: Versioned) -> Self {
+        let prefix = if versioned.prefix.is_empty() {
+            "/".to_string()
+        } else {
+            versioned.prefix.clone()
+        };
+
+        Handler::wildcard(&prefix, versioned)
+    }
+}
+
+#[crate::async_trait]
+impl Controller for Versioned {
+    async fn handle(&self, request: &Request) -> Result<Response, Error> {
+        let not_found = match self.strategy {
+            VersionStrategy::Path => Response::not_found(),
+            VersionStrategy::Header(_) => Response::not_acceptable().vary("accept"),
+        };
+
+        let Some((index, path)) = self.resolve(request) else {
+            return Ok(not_found);
+        };
+
+        let path = Path::from_parts(&path, request.path().query());
+        let (version, _) = &self.versions[index];
+
+        let response = match self.find(index, &path) {
+            Some(handler) => {
+                let mut request = request.clone();
+                request.head_mut().replace_path(path);
+                let request = request
+                    .with_params(handler.path_with_regex().params())
+                    .with_api_version(version.name());
+
+                handler.handle_internal(request).await?
+            }
+
+            None => Response::not_found(),
+        };
+
+        let response = match self.strategy {
+            VersionStrategy::Path => response,
+            VersionStrategy::Header(_) => response.vary("accept"),
+        };
+
+        Ok(version.headers(response))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Named(&'static str);
+
+    #[crate::async_trait]
+    impl Controller for Named {
+        async fn handle(&self, request: &Request) -> Result<Response, Error> {
+            let id = request.parameter::<i64>("id")?;
+
+            Ok(Response::new().text(format!(
+                "{} {} {:?} {:?}",
+                self.0,
+                request.path().base(),
+                request.api_version(),
+                id
+            )))
+        }
+    }
+
+    fn api() -> Versioned {
+        Router::versioned(
+            "/api/",
+            vec![
+                ApiVersion::new(
+                    "v1",
+                    vec![
+                        Handler::route("/users", Named("users v1")),
+                        Handler::route("/users/:id", Named("user v1")),
+                        Handler::route("/orders", Named("orders v1")),
+                        Handler::route("/legacy", Named("legacy v1")),
+                    ],
+                )
+                .deprecated(OffsetDateTime::from_unix_timestamp(1767225600).unwrap())
+                .sunset(OffsetDateTime::from_unix_timestamp(1798761600).unwrap()),
+                ApiVersion::new("v2", vec![Handler::route("/users", Named("users v2"))])
+                    .remove("/legacy"),
+                ApiVersion::new("v3", vec![]),
+            ],
+        )
+    }
+
+    async fn get(
+        versioned: &Versioned,
+        path: &str,
+        accept: Option<&str>,
+    ) -> (u16, String, Response) {
+        let mut request = Request::default();
+        request.head_mut().replace_path(Path::parse(path).unwrap());
+        if let Some(accept) = accept {
+            request.head_mut().headers_mut().insert("accept", accept);
+        }
+
+        let response = versioned.handle(&request).await.unwrap();
+        let body = String::from_utf8_lossy(response.body_bytes().unwrap_or_default()).to_string();
+
+        (response.status().code(), body, response)
+    }
+
+    #[tokio::test]
+    async fn test_inheritance() {
+        let api = api();
+
+        let (code, body, _) = get(&api, "/api/v1/users", None).await;
+        assert_eq!(code, 200);
+        assert_eq!(body, r#"users v1 /users Some("v1") None"#);
+
+        // Overridden.
+        let (_, body, _) = get(&api, "/api/v2/users", None).await;
+        assert_eq!(body, r#"users v2 /users Some("v2") None"#);
+
+        // Inherited, with parameters and the query.
+        let (_, body, _) = get(&api, "/api/v2/users/5?page=2", None).await;
+        assert_eq!(body, r#"user v1 /users/5 Some("v2") Some(5)"#);
+        let (_, body, _) = get(&api, "/api/v3/users", None).await;
+        assert_eq!(body, r#"users v2 /users Some("v3") None"#);
+        let (_, body, _) = get(&api, "/api/v3/orders", None).await;
+        assert_eq!(body, r#"orders v1 /orders Some("v3") None"#);
+
+        // Removed from v2 onwards.
+        let (code, _, _) = get(&api, "/api/v1/legacy", None).await;
+        assert_eq!(code, 200);
+        for path in ["/api/v2/legacy", "/api/v3/legacy"] {
+            let (code, _, _) = get(&api, path, None).await;
+            assert_eq!(code, 404, "{}", path);
+        }
+
+        // Unknown versions and routes.
+        for path in ["/api/v4/users", "/api", "/api/users", "/api/v2/nope"] {
+            let (code, _, _) = get(&api, path, None).await;
+            assert_eq!(code, 404, "{}", path);
+        }
+
+        let handler = Handler::from(api);
+        assert!(handler.path_with_regex().matches("/api/v1/users"));
+    }
+
+    #[tokio::test]
+    async fn test_header_strategy() {
+        let api = api().strategy(VersionStrategy::Header("version".into()));
+
+        let (_, body, response) =
+            get(&api, "/api/users", Some("application/json; version=1")).await;
+        assert_eq!(body, r#"users v1 /users Some("v1") None"#);
+        assert_eq!(response.headers().get("vary").unwrap(), "accept");
+
+        let (_, body, _) = get(&api, "/api/orders", Some("application/json; version=v2")).await;
+        assert_eq!(body, r#"orders v1 /orders Some("v2") None"#);
+
+        // Newest by default.
+        let (_, body, _) = get(&api, "/api/users", None).await;
+        assert_eq!(body, r#"users v2 /users Some("v3") None"#);
+
+        let (code, _, _) = get(&api, "/api/users", Some("application/json; version=9")).await;
+        assert_eq!(code, 406);
+
+        let api = api.default_version("v1");
+        let (_, body, _) = get(&api, "/api/users", Some("application/json")).await;
+        assert_eq!(body, r#"users v1 /users Some("v1") None"#);
+    }
+
+    #[tokio::test]
+    async fn test_deprecation_headers() {
+        let api = api();
+
+        let (_, _, response) = get(&api, "/api/v1/users", None).await;
+        assert_eq!(
+            response.headers().get("deprecation").unwrap(),
+            "@1767225600"
+        );
+        assert_eq!(
+            response.headers().get("sunset").unwrap(),
+            "Fri, 01 Jan 2027 00:00:00 GMT"
+        );
+
+        // Also on errors.
+        let (code, _, response) = get(&api, "/api/v1/nope", None).await;
+        assert_eq!(code, 404);
+        assert!(response.headers().get("deprecation").is_some());
+
+        // Not on newer versions, even for inherited routes.
+        let (_, _, response) = get(&api, "/api/v2/orders", None).await;
+        assert!(response.headers().get("deprecation").is_none());
+        assert!(response.headers().get("sunset").is_none());
+    }
+}
@@ -0,0 +1,413 @@
+//! WebSocket client.
+//!
+//! Requires the `websocket-client` feature. Useful for testing WebSocket controllers
+//! and for talking to other services over WebSocket.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use rwf::http::websocket::{Message, WsClient};
+//!
+//! let mut client = WsClient::connect("ws://localhost:8000/websocket").await?;
+//! client.send_text("hello").await?;
+//!
+//! match client.recv().await? {
+//!     Message::Text(text) => println!("received: {}", text),
+//!     Message::Binary(bytes) => println!("received {} bytes", bytes.len()),
+//! }
+//!
+//! client.close().await?;
+//! ```
+use base64::{engine::general_purpose, Engine as _};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use std::marker::Unpin;
+use std::time::Duration;
+
+use super::{write_frame, Error, Incoming, Message, OpCode, Reader, ToMessage, CLOSE_NORMAL};
+
+/// How long to wait for the server by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum size of messages received from the server by default.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// WebSocket client.
+pub struct WsClient<S = BufReader<TcpStream>> {
+    stream: S,
+    reader: Reader,
+    timeout: Duration,
+    closed: Option<Option<u16>>,
+}
+
+impl WsClient {
+    /// Connect to a WebSocket server, e.g. `ws://localhost:8000/websocket`.
+    ///
+    /// Only plain text connections (`ws://`) are supported.
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let (host, _) = parse_url(url)?;
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+
+        let stream = timeout(DEFAULT_TIMEOUT, TcpStream::connect(address)).await??;
+        Self::handshake(stream, url).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WsClient<BufReader<S>> {
+    /// Perform the WebSocket handshake over an existing connection.
+    ///
+    /// This allows using any transport, e.g. an in-memory stream in tests.
+    pub async fn handshake(stream: S, url: &str) -> Result<Self, Error> {
+        let mut stream = BufReader::new(stream);
+        timeout(DEFAULT_TIMEOUT, request_upgrade(&mut stream, url)).await??;
+
+        Ok(Self {
+            stream,
+            reader: Reader::new(DEFAULT_MAX_MESSAGE_SIZE),
+            timeout: DEFAULT_TIMEOUT,
+            closed: None,
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WsClient<S> {
+    /// How long to wait for the server to send or accept a message. Default: 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Maximum size of messages received from the server. Default: 16 MB.
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.reader = Reader::new(max_message_size);
+        self
+    }
+
+    /// Send a message.
+    pub async fn send(&mut self, message: impl ToMessage) -> Result<(), Error> {
+        let message = message.to_message();
+        self.send_frame(message.op_code(), true, message.as_bytes())
+            .await
+    }
+
+    /// Send a text message.
+    pub async fn send_text(&mut self, text: impl ToString) -> Result<(), Error> {
+        self.send(Message::Text(text.to_string())).await
+    }
+
+    /// Send a binary message.
+    pub async fn send_binary(&mut self, bytes: impl Into<Vec<u8>>) -> Result<(), Error> {
+        self.send(Message::Binary(bytes.into())).await
+    }
+
+    /// Send a message split into frames of at most `fragment_size` bytes.
+    pub async fn send_fragmented(
+        &mut self,
+        message: impl ToMessage,
+        fragment_size: usize,
+    ) -> Result<(), Error> {
+        let message = message.to_message();
+        let bytes = message.as_bytes();
+
+        if bytes.is_empty() {
+            return self.send(message).await;
+        }
+
+        let chunks = bytes.chunks(fragment_size.max(1)).collect::<Vec<_>>();
+        let last = chunks.len() - 1;
+
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let op_code = if i == 0 {
+                message.op_code()
+            } else {
+                OpCode::Continuation
+            };
+            self.send_frame(op_code, i == last, chunk).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Receive the next message. Pings are answered automatically.
+    ///
+    /// If the server closes the connection, the close handshake is completed
+    /// and [`Error::WebsocketClosed`] is returned with the status code sent by the server.
+    pub async fn recv(&mut self) -> Result<Message, Error> {
+        if let Some(code) = self.closed {
+            return Err(Error::WebsocketClosed(code));
+        }
+
+        loop {
+            let incoming = timeout(self.timeout, self.reader.read(&mut self.stream)).await??;
+
+            match incoming {
+                Incoming::Message(message) => return Ok(message),
+                Incoming::Ping(payload) => self.send_frame(OpCode::Pong, true, &payload).await?,
+                Incoming::Pong => continue,
+                Incoming::Close(code) => {
+                    let reply = code.unwrap_or(CLOSE_NORMAL).to_be_bytes();
+                    let _ = self.send_frame(OpCode::Close, true, &reply).await;
+                    let _ = self.stream.shutdown().await;
+                    self.closed = Some(code);
+
+                    return Err(Error::WebsocketClosed(code));
+                }
+            }
+        }
+    }
+
+    /// Close the connection normally. Messages received while waiting for the server
+    /// to acknowledge are discarded.
+    pub async fn close(self) -> Result<(), Error> {
+        self.close_with(CLOSE_NORMAL).await
+    }
+
+    /// Close the connection with the status code.
+    pub async fn close_with(mut self, code: u16) -> Result<(), Error> {
+        if self.closed.is_some() {
+            return Ok(());
+        }
+
+        self.send_frame(OpCode::Close, true, &code.to_be_bytes())
+            .await?;
+
+        loop {
+            match self.recv().await {
+                Ok(_) => continue,
+                Err(Error::WebsocketClosed(_)) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn send_frame(
+        &mut self,
+        op_code: OpCode,
+        fin: bool,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let mask = rand::random::<[u8; 4]>();
+        timeout(
+            self.timeout,
+            write_frame(&mut self.stream, op_code, fin, payload, Some(mask)),
+        )
+        .await?
+    }
+}
+
+/// Split a `ws://` URL into host and path.
+fn parse_url(url: &str) -> Result<(&str, &str), Error> {
+    let rest = match url.strip_prefix("ws://") {
+        Some(rest) => rest,
+        None => {
+            return Err(Error::WebsocketHandshake(format!(
+                "unsupported url \"{}\", expected ws://",
+                url
+            )))
+        }
+    };
+
+    Ok(match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    })
+}
+
+/// Send the upgrade request and check the server accepted it.
+async fn request_upgrade(
+    stream: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin>,
+    url: &str,
+) -> Result<(), Error> {
+    let (host, path) = parse_url(url)?;
+    let key = general_purpose::STANDARD.encode(rand::random::<[u8; 16]>());
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+        Host: {}\r\n\
+        Connection: Upgrade\r\n\
+        Upgrade: websocket\r\n\
+        Sec-WebSocket-Version: 13\r\n\
+        Sec-WebSocket-Key: {}\r\n\r\n",
+        path, host, key
+    );
+
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut status = String::new();
+    stream.read_line(&mut status).await?;
+
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(Error::WebsocketHandshake(format!(
+            "server responded with \"{}\"",
+            status.trim()
+        )));
+    }
+
+    let mut accept = None;
+
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(Error::WebsocketHandshake(
+                "connection closed during handshake".into(),
+            ));
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-accept") {
+                accept = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let expected = general_purpose::STANDARD.encode(Sha1::digest(format!(
+        "{}258EAFA5-E914-47DA-95CA-C5AB0DC85B11",
+        key
+    )));
+
+    if accept.as_deref() != Some(expected.as_str()) {
+        return Err(Error::WebsocketHandshake(
+            "invalid sec-websocket-accept".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::comms::Comms;
+    use crate::controller::{Controller, Error as ControllerError, SessionId, WebsocketController};
+    use crate::http::{Request, Response, Server};
+    use crate::prelude::async_trait;
+
+    #[derive(Default)]
+    struct Echo;
+
+    #[async_trait]
+    impl Controller for Echo {
+        async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+            WebsocketController::handle(self, request).await
+        }
+
+        async fn handle_stream(
+            &self,
+            request: &Request,
+            stream: crate::http::Stream<'_>,
+        ) -> Result<bool, ControllerError> {
+            WebsocketController::handle_stream(self, request, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl WebsocketController for Echo {
+        async fn client_message(
+            &self,
+            session_id: &SessionId,
+            message: Message,
+        ) -> Result<(), ControllerError> {
+            Comms::websocket(session_id).send(message)?;
+            Ok(())
+        }
+
+        fn max_message_size(&self) -> usize {
+            1024
+        }
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("ws://localhost:8000/chat").unwrap(),
+            ("localhost:8000", "/chat")
+        );
+        assert_eq!(parse_url("ws://localhost").unwrap(), ("localhost", "/"));
+        assert!(parse_url("wss://localhost").is_err());
+        assert!(parse_url("http://localhost").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_echo() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = format!("127.0.0.1:{}", port);
+
+        let server = Server::new(vec![Echo.route("/echo")]);
+        tokio::spawn(server.launch(address.clone()));
+
+        let url = format!("ws://{}/echo", address);
+        let mut client = loop {
+            match WsClient::connect(&url).await {
+                Ok(client) => break client.timeout(Duration::from_secs(5)),
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        client.send_text("hello").await.unwrap();
+        assert!(matches!(client.recv().await.unwrap(), Message::Text(text) if text == "hello"));
+
+        client.send_binary(vec![1, 2, 3]).await.unwrap();
+        assert!(
+            matches!(client.recv().await.unwrap(), Message::Binary(bytes) if bytes == [1, 2, 3])
+        );
+
+        // Reassembled by the server.
+        let text = "fragmented ".repeat(20);
+        client.send_fragmented(text.as_str(), 16).await.unwrap();
+        assert!(matches!(client.recv().await.unwrap(), Message::Text(echo) if echo == text));
+
+        client.close().await.unwrap();
+
+        // Messages over the server's limit close the connection.
+        let mut client = WsClient::connect(&url)
+            .await
+            .unwrap()
+            .timeout(Duration::from_secs(5));
+        client.send_binary(vec![0; 1025]).await.unwrap();
+
+        match client.recv().await {
+            Err(Error::WebsocketClosed(code)) => assert_eq!(code, Some(1009)),
+            result => panic!("expected close, got {:?}", result),
+        }
+        assert!(matches!(
+            client.recv().await,
+            Err(Error::WebsocketClosed(Some(1009)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejected() {
+        let (client, mut server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 1024];
+            let _ = tokio::io::AsyncReadExt::read(&mut server, &mut buf).await;
+            server
+                .write_all(b"HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        assert!(matches!(
+            WsClient::handshake(client, "ws://localhost/echo").await,
+            Err(Error::WebsocketHandshake(_))
+        ));
+    }
+}
@@ -1,9 +1,25 @@
+use bytes::BytesMut;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use super::Error;
 use crate::view::TurboStream;
 
 use std::marker::Unpin;
+use std::time::Duration;
+
+#[cfg(feature = "websocket-client")]
+pub mod client;
+#[cfg(feature = "websocket-client")]
+pub use client::WsClient;
+
+/// Normal closure, the connection fulfilled its purpose.
+pub const CLOSE_NORMAL: u16 = 1000;
+/// The other side broke the protocol, e.g. with a malformed frame.
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+/// A text message wasn't valid UTF-8.
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+/// A message was too big to process.
+pub const CLOSE_TOO_BIG: u16 = 1009;
 
 #[derive(Debug, Clone)]
 pub struct Headers {
@@ -109,15 +125,29 @@ impl DataFrame {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum OpCode {
     Continuation,
     Text,
     Binary,
+    Close,
     Ping,
     Pong,
 }
 
+impl OpCode {
+    fn byte(&self) -> u8 {
+        match self {
+            OpCode::Continuation => 0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xA,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Header {
     fin: bool,
@@ -135,6 +165,7 @@ impl Header {
             0 => OpCode::Continuation,
             0x1 => OpCode::Text,
             0x2 => OpCode::Binary,
+            0x8 => OpCode::Close,
             0x9 => OpCode::Ping,
             0xA => OpCode::Pong,
             _ => return Err(Error::MalformedRequest("websocket control code")),
@@ -144,13 +175,7 @@ impl Header {
     }
 
     async fn send(self, stream: &mut (impl AsyncWrite + Unpin)) -> Result<(), Error> {
-        let mut byte: u8 = match self.op_code {
-            OpCode::Continuation => 0,
-            OpCode::Text => 0x1,
-            OpCode::Binary => 0x2,
-            OpCode::Ping => 0x9,
-            OpCode::Pong => 0xA,
-        };
+        let mut byte = self.op_code.byte();
 
         if self.fin {
             byte |= 0b10000000;
@@ -240,7 +265,7 @@ impl Meta {
         let mut buf = vec![0u8; 0];
 
         let masked = if self.mask.is_some() {
-            0b1000000
+            0b10000000
         } else {
             0b00000000
         };
@@ -270,6 +295,307 @@ impl Meta {
     }
 }
 
+/// A message or control frame received on a WebSocket connection.
+#[derive(Debug, Clone)]
+pub enum Incoming {
+    /// A complete message, reassembled if it was sent in fragments.
+    Message(Message),
+    /// Ping, with its payload. Should be answered with a pong carrying the same payload.
+    Ping(Vec<u8>),
+    /// Pong.
+    Pong,
+    /// The other side is closing the connection, with the status code, if any.
+    Close(Option<u16>),
+}
+
+/// Reads messages from a WebSocket connection, reassembling fragmented messages.
+///
+/// Reading is cancel-safe: data received by a read that didn't complete is kept
+/// for the next one, so the reader can be used in `tokio::select!`.
+///
+/// Frames which break RFC 6455 return an error with a close code, see [`Error::websocket_close_code`].
+#[derive(Debug)]
+pub struct Reader {
+    max_message_size: usize,
+    max_frame_size: usize,
+    reserved_bits: u8,
+    buffer: BytesMut,
+    // Opcode of the first frame and the data received so far.
+    fragments: Option<(OpCode, Vec<u8>)>,
+}
+
+impl Reader {
+    /// Create a reader accepting messages up to this size, in bytes.
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            max_frame_size: max_message_size,
+            reserved_bits: 0,
+            buffer: BytesMut::new(),
+            fragments: None,
+        }
+    }
+
+    /// Accept frames up to this size, in bytes. Larger frames close the connection with code 1009,
+    /// even if the message they are part of is within the limit. Defaults to the maximum message size.
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Allow frames with these reserved bits set, e.g. `0b100` for RSV1, because a negotiated extension uses them.
+    /// Frames with other reserved bits set close the connection with code 1002.
+    pub fn reserved_bits(mut self, reserved_bits: u8) -> Self {
+        self.reserved_bits = reserved_bits & 0b111;
+        self
+    }
+
+    /// Read the next message or control frame.
+    ///
+    /// Messages larger than the maximum size return an error before their data is read.
+    pub async fn read(&mut self, stream: &mut (impl AsyncRead + Unpin)) -> Result<Incoming, Error> {
+        loop {
+            if let Some(incoming) = self.parse()? {
+                return Ok(incoming);
+            }
+
+            if stream.read_buf(&mut self.buffer).await? == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
+    // Parse frames received so far, until one completes a message or is a control frame.
+    fn parse(&mut self) -> Result<Option<Incoming>, Error> {
+        loop {
+            let (fin, op_code, len, offset, mask) = match self.frame_header()? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+
+            // Checked before the payload is received, so the connection can be closed right away.
+            match op_code {
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    if !fin {
+                        return Err(Error::WebsocketProtocol("fragmented control frame"));
+                    }
+                    if len > 125 {
+                        return Err(Error::WebsocketProtocol("control frame is too long"));
+                    }
+                }
+
+                OpCode::Continuation => {
+                    if self.fragments.is_none() {
+                        return Err(Error::WebsocketProtocol(
+                            "continuation frame without a message",
+                        ));
+                    }
+                }
+
+                OpCode::Text | OpCode::Binary => {
+                    if self.fragments.is_some() {
+                        return Err(Error::WebsocketProtocol(
+                            "new message before the fragmented message is complete",
+                        ));
+                    }
+                }
+            }
+
+            if len > self.max_frame_size {
+                self.fragments = None;
+                return Err(Error::WebsocketFrameTooBig {
+                    size: len,
+                    limit: self.max_frame_size,
+                });
+            }
+
+            let buffered = self
+                .fragments
+                .as_ref()
+                .map(|(_, data)| data.len())
+                .unwrap_or(0);
+            let size = buffered.saturating_add(len);
+
+            if size > self.max_message_size {
+                self.fragments = None;
+                return Err(Error::WebsocketMessageTooBig {
+                    size,
+                    limit: self.max_message_size,
+                });
+            }
+
+            if self.buffer.len() < offset + len {
+                self.buffer.reserve(offset + len - self.buffer.len());
+                return Ok(None);
+            }
+
+            let frame = self.buffer.split_to(offset + len);
+            let mut payload = frame[offset..].to_vec();
+
+            if let Some(mask) = mask {
+                for (i, byte) in payload.iter_mut().enumerate() {
+                    *byte ^= mask[i % 4];
+                }
+            }
+
+            // Control frames can be sent in between fragments of a message.
+            let (op_code, data) = match op_code {
+                OpCode::Ping => return Ok(Some(Incoming::Ping(payload))),
+                OpCode::Pong => return Ok(Some(Incoming::Pong)),
+                OpCode::Close => {
+                    let code = match payload.as_slice() {
+                        [] => None,
+                        [_] => return Err(Error::WebsocketProtocol("close frame is too short")),
+                        [a, b, reason @ ..] => {
+                            if std::str::from_utf8(reason).is_err() {
+                                return Err(Error::WebsocketInvalidUtf8);
+                            }
+                            Some(u16::from_be_bytes([*a, *b]))
+                        }
+                    };
+                    return Ok(Some(Incoming::Close(code)));
+                }
+
+                OpCode::Continuation => match self.fragments.take() {
+                    Some((op_code, mut data)) => {
+                        data.extend(payload);
+                        (op_code, data)
+                    }
+                    None => {
+                        return Err(Error::WebsocketProtocol(
+                            "continuation frame without a message",
+                        ))
+                    }
+                },
+
+                op_code => (op_code, payload),
+            };
+
+            if !fin {
+                self.fragments = Some((op_code, data));
+                continue;
+            }
+
+            // Text is validated once the message is complete, since a fragment can end in the middle of a character.
+            return Ok(Some(Incoming::Message(match op_code {
+                OpCode::Text => {
+                    Message::Text(String::from_utf8(data).map_err(|_| Error::WebsocketInvalidUtf8)?)
+                }
+                _ => Message::Binary(data),
+            })));
+        }
+    }
+
+    // Fin bit, opcode, payload length, payload offset and mask of the next frame, if its header was received.
+    #[allow(clippy::type_complexity)]
+    fn frame_header(&self) -> Result<Option<(bool, OpCode, usize, usize, Option<[u8; 4]>)>, Error> {
+        let buf = &self.buffer[..];
+
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+
+        let fin = buf[0] & 0b10000000 != 0;
+        let reserved = (buf[0] & 0b01110000) >> 4;
+        if reserved & !self.reserved_bits != 0 {
+            return Err(Error::WebsocketProtocol("reserved bits are set"));
+        }
+
+        let op_code = match buf[0] & 0b00001111 {
+            0 => OpCode::Continuation,
+            0x1 => OpCode::Text,
+            0x2 => OpCode::Binary,
+            0x8 => OpCode::Close,
+            0x9 => OpCode::Ping,
+            0xA => OpCode::Pong,
+            _ => return Err(Error::WebsocketProtocol("unknown opcode")),
+        };
+
+        let masked = buf[1] & 0b10000000 != 0;
+        let (len, mut offset) = match buf[1] & 0b01111111 {
+            126 => match buf.get(2..4) {
+                Some(len) => (u16::from_be_bytes([len[0], len[1]]) as usize, 4),
+                None => return Ok(None),
+            },
+            127 => match buf.get(2..10) {
+                Some(len) => {
+                    let len = u64::from_be_bytes(len.try_into().unwrap());
+                    (usize::try_from(len).unwrap_or(usize::MAX), 10)
+                }
+                None => return Ok(None),
+            },
+            len => (len as usize, 2),
+        };
+
+        let mask = if masked {
+            match buf.get(offset..offset + 4) {
+                Some(mask) => {
+                    offset += 4;
+                    Some([mask[0], mask[1], mask[2], mask[3]])
+                }
+                None => return Ok(None),
+            }
+        } else {
+            None
+        };
+
+        Ok(Some((fin, op_code, len, offset, mask)))
+    }
+}
+
+/// Write a single frame. Clients must mask the frames they send.
+async fn write_frame(
+    stream: &mut (impl AsyncWrite + Unpin),
+    op_code: OpCode,
+    fin: bool,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) -> Result<(), Error> {
+    Header { fin, op_code }.send(stream).await?;
+    Meta {
+        len: payload.len(),
+        mask,
+    }
+    .send(stream)
+    .await?;
+
+    match mask {
+        Some(mask) => {
+            let masked = payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4])
+                .collect::<Vec<_>>();
+            stream.write_all(&masked).await?;
+        }
+        None => stream.write_all(payload).await?,
+    }
+
+    stream.flush().await?;
+
+    Ok(())
+}
+
+/// Answer a ping.
+pub async fn send_pong(
+    stream: &mut (impl AsyncWrite + Unpin),
+    payload: &[u8],
+) -> Result<(), Error> {
+    write_frame(stream, OpCode::Pong, true, payload, None).await
+}
+
+/// Send a close frame with the status code.
+pub async fn send_close(stream: &mut (impl AsyncWrite + Unpin), code: u16) -> Result<(), Error> {
+    write_frame(stream, OpCode::Close, true, &code.to_be_bytes(), None).await
+}
+
+/// Discard everything the other side sends until it closes the connection, or the timeout expires.
+///
+/// Used after sending a close frame, so the other side can read it before the connection is closed.
+pub async fn drain(stream: &mut (impl AsyncRead + Unpin), timeout: Duration) {
+    let _ = tokio::time::timeout(timeout, tokio::io::copy(stream, &mut tokio::io::sink())).await;
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     Text(String),
@@ -295,6 +621,13 @@ impl Message {
         }
     }
 
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Binary(bytes) => bytes,
+        }
+    }
+
     async fn read(
         header: &Header,
         meta: &Meta,
@@ -381,3 +714,199 @@ impl ToMessage for TurboStream {
         Message::Text(self.render())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FIN: u8 = 0b10000000;
+
+    // A frame sent by a client, masked.
+    fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![first];
+
+        match payload.len() {
+            len @ 0..=125 => frame.push(0b10000000 | len as u8),
+            len @ 126..=65535 => {
+                frame.push(0b10000000 | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0b10000000 | 127);
+                frame.extend((len as u64).to_be_bytes());
+            }
+        }
+
+        frame.extend(mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    async fn read_all(reader: &mut Reader, frames: &[Vec<u8>]) -> Vec<Result<Incoming, Error>> {
+        let bytes = frames.concat();
+        let mut stream = &bytes[..];
+        let mut incoming = vec![];
+
+        loop {
+            match reader.read(&mut stream).await {
+                Err(Error::Io(_)) => return incoming,
+                Err(err) => {
+                    incoming.push(Err(err));
+                    return incoming;
+                }
+                Ok(message) => incoming.push(Ok(message)),
+            }
+        }
+    }
+
+    async fn close_code(reader: &mut Reader, frames: &[Vec<u8>]) -> Option<u16> {
+        match read_all(reader, frames).await.pop() {
+            Some(Err(err)) => err.websocket_close_code(),
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    fn text(incoming: &Result<Incoming, Error>) -> &str {
+        match incoming {
+            Ok(Incoming::Message(Message::Text(text))) => text,
+            other => panic!("expected text, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fragments() {
+        let mut reader = Reader::new(1024);
+
+        // A ping in the middle of a fragmented message is answered right away.
+        let incoming = read_all(
+            &mut reader,
+            &[
+                frame(0x1, b"Hel"),
+                frame(FIN | 0x9, b"ping"),
+                frame(0x0, b""),
+                frame(FIN | 0x0, b"lo"),
+                frame(FIN | 0x2, &[1, 2]),
+            ],
+        )
+        .await;
+        assert!(matches!(&incoming[0], Ok(Incoming::Ping(payload)) if payload == b"ping"));
+        assert_eq!(text(&incoming[1]), "Hello");
+        assert!(
+            matches!(&incoming[2], Ok(Incoming::Message(Message::Binary(data))) if data == &[1, 2])
+        );
+        assert_eq!(incoming.len(), 3);
+
+        // A character split between two fragments.
+        let bytes = "κόσμε".as_bytes();
+        let incoming = read_all(
+            &mut reader,
+            &[frame(0x1, &bytes[..1]), frame(FIN | 0x0, &bytes[1..])],
+        )
+        .await;
+        assert_eq!(text(&incoming[0]), "κόσμε");
+
+        // Empty fragmented message.
+        let incoming = read_all(&mut reader, &[frame(0x1, b""), frame(FIN | 0x0, b"")]).await;
+        assert_eq!(text(&incoming[0]), "");
+
+        // Close with a code and a reason.
+        let incoming = read_all(&mut reader, &[frame(FIN | 0x8, b"\x03\xe8bye")]).await;
+        assert!(matches!(incoming[0], Ok(Incoming::Close(Some(1000)))));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8() {
+        for frames in [
+            vec![frame(FIN | 0x1, b"\xce\xba\xe1\xbd")],
+            vec![frame(0x1, "κ".as_bytes()), frame(FIN | 0x0, b"\xff")],
+            vec![frame(FIN | 0x8, b"\x03\xe8\xff")],
+        ] {
+            assert_eq!(
+                close_code(&mut Reader::new(1024), &frames).await,
+                Some(CLOSE_INVALID_DATA)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_protocol_errors() {
+        for frames in [
+            // Fragmented ping.
+            vec![frame(0x9, b"ping")],
+            // Control frame over 125 bytes.
+            vec![frame(FIN | 0x9, &[0; 126])],
+            // Continuation without a message.
+            vec![frame(FIN | 0x0, b"a")],
+            // New message before the previous one is complete.
+            vec![frame(0x1, b"a"), frame(FIN | 0x1, b"b")],
+            // Reserved bits.
+            vec![frame(FIN | 0b01000000 | 0x1, b"a")],
+            vec![frame(FIN | 0b00010000 | 0x9, b"")],
+            // Reserved opcodes.
+            vec![frame(FIN | 0x3, b"")],
+            vec![frame(FIN | 0xB, b"")],
+            // Close with half a status code.
+            vec![frame(FIN | 0x8, b"\x03")],
+        ] {
+            assert_eq!(
+                close_code(&mut Reader::new(1024), &frames).await,
+                Some(CLOSE_PROTOCOL_ERROR),
+                "{:?}",
+                frames
+            );
+        }
+
+        // Unless an extension uses them.
+        let mut reader = Reader::new(1024).reserved_bits(0b100);
+        let incoming = read_all(&mut reader, &[frame(FIN | 0b01000000 | 0x1, b"a")]).await;
+        assert_eq!(text(&incoming[0]), "a");
+        assert_eq!(
+            close_code(&mut reader, &[frame(FIN | 0b00100000 | 0x1, b"a")]).await,
+            Some(CLOSE_PROTOCOL_ERROR)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_size_limits() {
+        // Frames under the frame limit add up to a message under the message limit.
+        let mut reader = Reader::new(200).max_frame_size(100);
+        let incoming = read_all(
+            &mut reader,
+            &[frame(0x2, &[0; 100]), frame(FIN | 0x0, &[0; 100])],
+        )
+        .await;
+        assert!(
+            matches!(&incoming[0], Ok(Incoming::Message(Message::Binary(data))) if data.len() == 200)
+        );
+
+        // A single frame over the limit.
+        assert_eq!(
+            close_code(&mut reader, &[frame(FIN | 0x2, &[0; 101])]).await,
+            Some(CLOSE_TOO_BIG)
+        );
+
+        // Fragments adding up to more than the message limit.
+        let mut reader = Reader::new(200).max_frame_size(100);
+        assert_eq!(
+            close_code(
+                &mut reader,
+                &[
+                    frame(0x1, &[b'a'; 100]),
+                    frame(0x0, &[b'a'; 100]),
+                    frame(FIN | 0x0, b"a"),
+                ]
+            )
+            .await,
+            Some(CLOSE_TOO_BIG)
+        );
+
+        // Rejected from the header, before the payload is received.
+        let mut reader = Reader::new(1024);
+        let header = frame(FIN | 0x2, &[0; 70_000])[..14].to_vec();
+        assert_eq!(
+            close_code(&mut reader, &[header]).await,
+            Some(CLOSE_TOO_BIG)
+        );
+    }
+}
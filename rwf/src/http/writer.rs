@@ -0,0 +1,537 @@
+//! Write a response to the client in multiple steps.
+//!
+//! [`ResponseWriter`] sends the status line and headers first, followed by the body in one or more chunks.
+//! This allows to start sending the response before the whole body is known, e.g. for server-sent events
+//! or when proxying another server.
+//!
+//! If the `Content-Length` header isn't set, the body is sent using chunked transfer encoding, which also allows to
+//! send trailers after the body.
+//!
+//! ```
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! use rwf::http::ResponseWriter;
+//!
+//! let mut writer = ResponseWriter::new(Vec::new());
+//! writer.status(201)?;
+//!
+//! writer.write_head().await?;
+//! writer.write_body_chunk(b"hello").await?;
+//! writer.finish().await?;
+//!
+//! assert_eq!(
+//!     writer.into_inner(),
+//!     b"HTTP/1.1 201 Created\r\ntransfer-encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"
+//! );
+//! # Ok::<(), rwf::http::writer::WriterError>(())
+//! # });
+//! ```
+use std::marker::Unpin;
+
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{body::CHUNK_SIZE, head::Version, response::Status, Cookies, Headers};
+
+/// Errors returned by the [`ResponseWriter`].
+#[derive(Debug, Error)]
+pub enum WriterError {
+    #[error("headers have already been sent")]
+    HeadWritten,
+
+    #[error("response has already been finished")]
+    Finished,
+
+    #[error("body is larger than content-length")]
+    ContentLengthExceeded,
+
+    #[error("body is smaller than content-length, {0} bytes missing")]
+    IncompleteBody(usize),
+
+    #[error("response with status {0} can't have a body")]
+    BodyNotAllowed(u16),
+
+    #[error("response body reached {size} bytes, over the limit of {limit} bytes")]
+    BodyTooLarge { size: usize, limit: usize },
+
+    #[error("trailers can only be sent with chunked encoding")]
+    TrailersNotAllowed,
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<WriterError> for std::io::Error {
+    fn from(err: WriterError) -> Self {
+        match err {
+            WriterError::Io(err) => err,
+            err => std::io::Error::other(err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Head,
+    Body,
+    Finished,
+}
+
+/// How the body is delimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Framing {
+    /// `Content-Length` header, with the number of bytes left to send.
+    Length(usize),
+    /// `Transfer-Encoding: chunked`.
+    Chunked,
+    /// The response has no body, e.g. `204 - No Content`.
+    Empty,
+}
+
+/// Writes a response to a stream: status line and headers first, then the body, and finally
+/// the trailers, if any.
+///
+/// Headers can't be changed after they have been sent.
+#[derive(Debug)]
+pub struct ResponseWriter<S> {
+    stream: S,
+    code: u16,
+    version: Version,
+    headers: Headers,
+    cookies: Cookies,
+    state: State,
+    framing: Framing,
+    send_body: bool,
+    bytes_written: usize,
+    body_bytes: usize,
+    max_body_size: usize,
+}
+
+impl<S: AsyncWrite + Unpin> ResponseWriter<S> {
+    /// Create a writer for a `200 - OK` response without any headers.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            code: 200,
+            version: Version::Http1,
+            headers: Headers::new(),
+            cookies: Cookies::new(),
+            state: State::Head,
+            framing: Framing::Chunked,
+            send_body: true,
+            bytes_written: 0,
+            body_bytes: 0,
+            max_body_size: 0,
+        }
+    }
+
+    /// Set the response status code.
+    pub fn status(&mut self, code: u16) -> Result<&mut Self, WriterError> {
+        self.check_head()?;
+        self.code = code;
+        Ok(self)
+    }
+
+    /// Set the HTTP version.
+    pub fn version(&mut self, version: Version) -> Result<&mut Self, WriterError> {
+        self.check_head()?;
+        self.version = version;
+        Ok(self)
+    }
+
+    /// Send the headers only, e.g. in reply to a `HEAD` request. `Content-Length`, if set,
+    /// is sent as-is, so the client knows the size of the body it would have received.
+    pub fn without_body(&mut self) -> Result<&mut Self, WriterError> {
+        self.check_head()?;
+        self.send_body = false;
+        Ok(self)
+    }
+
+    /// Stop sending the body once it's larger than this many bytes. The chunk going over the limit
+    /// isn't sent, so a chunk-encoded body is left incomplete. `0` means no limit, which is the default.
+    pub fn max_body_size(&mut self, limit: usize) -> &mut Self {
+        self.max_body_size = limit;
+        self
+    }
+
+    /// Set a header.
+    pub fn header(
+        &mut self,
+        name: impl ToString,
+        value: impl ToString,
+    ) -> Result<&mut Self, WriterError> {
+        self.headers_mut()?.insert(name, value);
+        Ok(self)
+    }
+
+    /// Get a mutable reference to the headers, if they haven't been sent yet.
+    pub fn headers_mut(&mut self) -> Result<&mut Headers, WriterError> {
+        self.check_head()?;
+        Ok(&mut self.headers)
+    }
+
+    /// Get a mutable reference to the cookies, if they haven't been sent yet.
+    pub fn cookies_mut(&mut self) -> Result<&mut Cookies, WriterError> {
+        self.check_head()?;
+        Ok(&mut self.cookies)
+    }
+
+    /// Send the status line and headers.
+    ///
+    /// If the `Content-Length` header isn't set, `Transfer-Encoding: chunked` is added and the body is
+    /// chunk-encoded automatically.
+    pub async fn write_head(&mut self) -> Result<(), WriterError> {
+        self.check_head()?;
+
+        self.framing = if !body_allowed(self.code) {
+            // These responses never have a body, so they can't have its length either.
+            self.headers.remove("content-length");
+            self.headers.remove("transfer-encoding");
+            Framing::Empty
+        } else if !self.send_body {
+            Framing::Empty
+        } else if let Some(length) = self
+            .headers
+            .get("content-length")
+            .and_then(|length| length.parse().ok())
+        {
+            Framing::Length(length)
+        } else {
+            self.headers.insert("transfer-encoding", "chunked");
+            Framing::Chunked
+        };
+
+        let mut head = format!("{} {}\r\n", self.version, Status::from(self.code)).into_bytes();
+        head.extend_from_slice(&self.headers.to_bytes());
+        head.extend_from_slice(&self.cookies.to_headers());
+        head.extend_from_slice(b"\r\n");
+
+        self.write(&head).await?;
+        self.state = State::Body;
+
+        Ok(())
+    }
+
+    /// Send a part of the body. Headers are sent first if they haven't been already.
+    pub async fn write_body_chunk(&mut self, chunk: &[u8]) -> Result<(), WriterError> {
+        match self.state {
+            State::Head => self.write_head().await?,
+            State::Body => (),
+            State::Finished => return Err(WriterError::Finished),
+        }
+
+        // An empty chunk would end a chunked body. Without a body, the chunk is dropped.
+        if chunk.is_empty() || !self.send_body {
+            return Ok(());
+        }
+
+        let size = self.body_bytes + chunk.len();
+        if self.max_body_size > 0 && size > self.max_body_size {
+            return Err(WriterError::BodyTooLarge {
+                size,
+                limit: self.max_body_size,
+            });
+        }
+
+        match self.framing {
+            Framing::Empty => return Err(WriterError::BodyNotAllowed(self.code)),
+            Framing::Length(remaining) => {
+                if chunk.len() > remaining {
+                    return Err(WriterError::ContentLengthExceeded);
+                }
+                self.framing = Framing::Length(remaining - chunk.len());
+                self.write(chunk).await?;
+            }
+            Framing::Chunked => {
+                self.write(format!("{:x}\r\n", chunk.len()).as_bytes())
+                    .await?;
+                self.write(chunk).await?;
+                self.write(b"\r\n").await?;
+            }
+        }
+
+        self.body_bytes += chunk.len();
+
+        Ok(())
+    }
+
+    /// Send everything the reader returns as the body, e.g. a file, in chunks of up to [`CHUNK_SIZE`] bytes.
+    pub async fn copy_body(
+        &mut self,
+        mut reader: impl AsyncRead + Unpin,
+    ) -> Result<usize, WriterError> {
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut copied = 0;
+
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+
+            self.write_body_chunk(&buffer[..read]).await?;
+            copied += read;
+        }
+
+        Ok(copied)
+    }
+
+    /// Finish the response. Headers are sent first if they haven't been already.
+    pub async fn finish(&mut self) -> Result<(), WriterError> {
+        self.finish_with_trailers(&Headers::new()).await
+    }
+
+    /// Finish the response and send trailers after the body. The body must be chunk-encoded.
+    pub async fn finish_with_trailers(&mut self, trailers: &Headers) -> Result<(), WriterError> {
+        match self.state {
+            State::Head => self.write_head().await?,
+            State::Body => (),
+            State::Finished => return Err(WriterError::Finished),
+        }
+
+        let has_trailers = trailers.iter().next().is_some();
+
+        match self.framing {
+            Framing::Chunked => {
+                let mut end = b"0\r\n".to_vec();
+                end.extend_from_slice(&trailers.to_bytes());
+                end.extend_from_slice(b"\r\n");
+                self.write(&end).await?;
+            }
+            _ if has_trailers => return Err(WriterError::TrailersNotAllowed),
+            Framing::Length(remaining) if remaining > 0 => {
+                return Err(WriterError::IncompleteBody(remaining))
+            }
+            _ => (),
+        }
+
+        self.state = State::Finished;
+
+        Ok(())
+    }
+
+    /// Total number of bytes written to the stream, including the headers.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// Number of body bytes written, excluding chunk encoding.
+    pub fn body_bytes(&self) -> usize {
+        self.body_bytes
+    }
+
+    /// Have the headers been sent?
+    pub fn head_written(&self) -> bool {
+        self.state != State::Head
+    }
+
+    /// Has the response been finished?
+    pub fn finished(&self) -> bool {
+        self.state == State::Finished
+    }
+
+    /// Get the stream back.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn check_head(&self) -> Result<(), WriterError> {
+        if self.state == State::Head {
+            Ok(())
+        } else {
+            Err(WriterError::HeadWritten)
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), WriterError> {
+        self.stream.write_all(bytes).await?;
+        self.bytes_written += bytes.len();
+        Ok(())
+    }
+}
+
+/// Can a response with this status have a body? `1xx`, `204 - No Content` and `304 - Not Modified` can't.
+pub fn body_allowed(code: u16) -> bool {
+    code >= 200 && code != 204 && code != 304
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wire(writer: ResponseWriter<Vec<u8>>) -> String {
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_content_length() {
+        let mut writer = ResponseWriter::new(vec![]);
+        writer
+            .status(201)
+            .unwrap()
+            .header("Content-Length", 5)
+            .unwrap();
+        writer.write_head().await.unwrap();
+        writer.write_body_chunk(b"hel").await.unwrap();
+        writer.write_body_chunk(b"lo").await.unwrap();
+
+        assert!(matches!(
+            writer.write_body_chunk(b"!").await,
+            Err(WriterError::ContentLengthExceeded)
+        ));
+
+        writer.finish().await.unwrap();
+        assert_eq!(writer.bytes_written(), 48);
+        assert_eq!(writer.body_bytes(), 5);
+        assert_eq!(
+            wire(writer),
+            "HTTP/1.1 201 Created\r\ncontent-length: 5\r\n\r\nhello"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        let mut writer = ResponseWriter::new(vec![]);
+        writer.max_body_size(8);
+        writer.write_body_chunk(b"hello").await.unwrap();
+
+        assert!(matches!(
+            writer.write_body_chunk(b"world").await,
+            Err(WriterError::BodyTooLarge { size: 10, limit: 8 })
+        ));
+        assert_eq!(writer.body_bytes(), 5);
+        assert!(!writer.finished());
+
+        // The last chunk isn't sent, so the client knows the body is incomplete.
+        assert_eq!(
+            wire(writer),
+            "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nhello\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunked() {
+        let mut writer = ResponseWriter::new(vec![]);
+        writer.write_body_chunk(b"hello").await.unwrap();
+        writer.write_body_chunk(b"").await.unwrap();
+        writer.write_body_chunk(&[b'a'; 26]).await.unwrap();
+
+        let mut trailers = Headers::new();
+        trailers.insert("Server-Timing", "total;dur=1");
+        writer.finish_with_trailers(&trailers).await.unwrap();
+
+        assert_eq!(writer.body_bytes(), 31);
+        assert_eq!(
+            wire(writer),
+            format!(
+                "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+                5\r\nhello\r\n\
+                1a\r\n{}\r\n\
+                0\r\nserver-timing: total;dur=1\r\n\r\n",
+                "a".repeat(26)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ordering() {
+        let mut writer = ResponseWriter::new(vec![]);
+        writer.header("content-length", 2).unwrap();
+        writer.write_head().await.unwrap();
+
+        assert!(matches!(
+            writer.header("x-late", "1"),
+            Err(WriterError::HeadWritten)
+        ));
+        assert!(matches!(writer.status(500), Err(WriterError::HeadWritten)));
+        assert!(matches!(
+            writer.write_head().await,
+            Err(WriterError::HeadWritten)
+        ));
+
+        writer.write_body_chunk(b"o").await.unwrap();
+        assert!(matches!(
+            writer.finish().await,
+            Err(WriterError::IncompleteBody(1))
+        ));
+
+        writer.write_body_chunk(b"k").await.unwrap();
+        assert!(matches!(
+            writer
+                .finish_with_trailers(&Headers::from(std::collections::HashMap::from([(
+                    "x-trailer".to_string(),
+                    "1".to_string()
+                )])))
+                .await,
+            Err(WriterError::TrailersNotAllowed)
+        ));
+        writer.finish().await.unwrap();
+
+        assert!(matches!(
+            writer.write_body_chunk(b"!").await,
+            Err(WriterError::Finished)
+        ));
+        assert!(matches!(writer.finish().await, Err(WriterError::Finished)));
+        assert_eq!(
+            wire(writer),
+            "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_body() {
+        let mut writer = ResponseWriter::new(vec![]);
+        writer.status(204).unwrap();
+        assert!(matches!(
+            writer.write_body_chunk(b"hello").await,
+            Err(WriterError::BodyNotAllowed(204))
+        ));
+        writer.finish().await.unwrap();
+        assert_eq!(wire(writer), "HTTP/1.1 204 No Content\r\n\r\n");
+
+        // The length of a body that can't exist isn't sent either.
+        let mut writer = ResponseWriter::new(vec![]);
+        writer
+            .status(304)
+            .unwrap()
+            .header("content-length", 5)
+            .unwrap();
+        writer.finish().await.unwrap();
+        assert_eq!(wire(writer), "HTTP/1.1 304 Not Modified\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_without_body() {
+        let mut writer = ResponseWriter::new(vec![]);
+        writer
+            .header("content-length", 5)
+            .unwrap()
+            .without_body()
+            .unwrap();
+        writer.write_body_chunk(b"hello").await.unwrap();
+        writer.finish().await.unwrap();
+
+        assert_eq!(writer.body_bytes(), 0);
+        assert_eq!(wire(writer), "HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n");
+
+        // Streams have no length, and aren't chunk-encoded.
+        let mut writer = ResponseWriter::new(vec![]);
+        writer.without_body().unwrap();
+        writer.write_body_chunk(b"hello").await.unwrap();
+        writer.finish().await.unwrap();
+        assert_eq!(wire(writer), "HTTP/1.1 200 OK\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_copy_body() {
+        let mut writer = ResponseWriter::new(vec![]);
+        writer.header("content-length", 11).unwrap();
+        let copied = writer.copy_body(&b"hello world"[..]).await.unwrap();
+        writer.finish().await.unwrap();
+
+        assert_eq!(copied, 11);
+        assert_eq!(
+            wire(writer),
+            "HTTP/1.1 200 OK\r\ncontent-length: 11\r\n\r\nhello world"
+        );
+    }
+}
@@ -63,7 +63,10 @@ impl WsgiRequest {
             headers.insert(format!("HTTP_{}", key.to_uppercase()), value);
         }
 
-        headers.insert("REQUEST_METHOD".into(), request.method().to_string());
+        headers.insert(
+            "REQUEST_METHOD".into(),
+            request.original_method().to_string(),
+        );
         headers.insert("PATH_INFO".into(), request.path().base().to_owned());
         headers.insert("REQUEST_URI".into(), request.path().to_string());
         headers.insert(
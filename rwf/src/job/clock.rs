@@ -1,5 +1,6 @@
 use super::{Cron, Error, Job, JobHandler};
 use crate::colors::MaybeColorize;
+use crate::timezone;
 
 use std::sync::Arc;
 use time::OffsetDateTime;
@@ -65,7 +66,8 @@ impl Clock {
 
         loop {
             clock.tick().await;
-            let now = OffsetDateTime::now_utc();
+            // Schedules are in the configured time zone.
+            let now = OffsetDateTime::now_utc().to_offset(timezone::default_offset());
 
             let jobs = self.jobs.clone();
 
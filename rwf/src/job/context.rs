@@ -0,0 +1,187 @@
+//! Job execution context.
+//!
+//! Every job receives a [`JobContext`] when it's executed by the worker. The context
+//! gives the job access to the connection pool, application configuration,
+//! WebSocket broadcasts and a tracing span populated with the job's metadata.
+//! Jobs use it to report their progress and to set their result, which can be
+//! retrieved with [`status`](super::status).
+use super::{Error, JobModel};
+use crate::comms::{Broadcast, Comms, IntoSessionId};
+use crate::config::{get_config, Config};
+use crate::model::Pool;
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tracing::{info_span, Span};
+
+/// Context passed to [`crate::job::Job::execute`].
+#[derive(Clone)]
+pub struct JobContext {
+    id: i64,
+    name: String,
+    attempt: i32,
+    pool: Pool,
+    span: Span,
+    checkpoint_at: Arc<Mutex<Option<OffsetDateTime>>>,
+    progress: Arc<Mutex<Progress>>,
+    result: Arc<Mutex<Option<serde_json::Value>>>,
+}
+
+/// Progress reported by the job, and when it was last saved.
+#[derive(Default)]
+struct Progress {
+    completed: Option<i64>,
+    total: Option<i64>,
+    saved_at: Option<Instant>,
+}
+
+/// Progress is saved at most once per interval.
+static PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+impl JobContext {
+    /// Create a context for the job fetched from the queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `job` - The job record.
+    /// * `pool` - Connection pool the job can use.
+    ///
+    pub fn new(job: &JobModel, pool: Pool) -> Self {
+        let id = job.id.unwrap_or_default();
+        let attempt = job.attempts + 1;
+        // Workers subscribe to queues by job name.
+        let span = info_span!("job", id, name = %job.name, queue = %job.name, attempt);
+
+        Self {
+            id,
+            name: job.name.clone(),
+            attempt,
+            pool,
+            span,
+            checkpoint_at: Arc::new(Mutex::new(job.checkpoint_at)),
+            progress: Arc::new(Mutex::new(Progress {
+                completed: job.progress_completed,
+                total: job.progress_total,
+                saved_at: None,
+            })),
+            result: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Job identifier in the queue.
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Name of the job.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Which attempt at running this job this is, starting at 1.
+    pub fn attempt(&self) -> i32 {
+        self.attempt
+    }
+
+    /// Connection pool.
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Application configuration.
+    pub fn config(&self) -> &'static Config {
+        get_config()
+    }
+
+    /// Send a message to all WebSocket connections belonging to the session.
+    pub fn broadcast(&self, session_id: impl IntoSessionId) -> Broadcast {
+        Comms::broadcast(session_id)
+    }
+
+    /// Send a message to all connected WebSocket clients.
+    pub fn notify(&self) -> Broadcast {
+        Comms::notify()
+    }
+
+    /// Tracing span with the job id, name, queue and attempt number. Everything the job
+    /// logs is recorded inside this span automatically.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// When the job last called [`JobContext::checkpoint`].
+    pub fn checkpoint_at(&self) -> Option<OffsetDateTime> {
+        *self.checkpoint_at.lock()
+    }
+
+    /// Let the queue know the job is still making progress.
+    ///
+    /// Jobs that run longer than the visibility timeout (`job_visibility_timeout` setting)
+    /// without calling this method are considered abandoned and are picked up
+    /// by another worker.
+    pub async fn checkpoint(&self) -> Result<(), Error> {
+        let mut conn = self.pool.get().await?;
+        let job = JobModel::checkpoint(self.id)
+            .fetch_optional(&mut conn)
+            .await?;
+
+        if let Some(job) = job {
+            *self.checkpoint_at.lock() = job.checkpoint_at;
+        }
+
+        Ok(())
+    }
+
+    /// Report how much of the job is done, e.g. `progress(250, 1000)` after processing 250 rows out of 1000.
+    ///
+    /// Progress is saved at most once per second, and always when the job reaches the total, so it's cheap
+    /// to call this method in a loop. Saving progress counts as a [checkpoint](JobContext::checkpoint).
+    pub async fn progress(&self, completed: i64, total: i64) -> Result<(), Error> {
+        {
+            let mut progress = self.progress.lock();
+            progress.completed = Some(completed);
+            progress.total = Some(total);
+
+            let recent = progress
+                .saved_at
+                .map(|saved_at| saved_at.elapsed() < PROGRESS_INTERVAL)
+                .unwrap_or(false);
+
+            if recent && completed < total {
+                return Ok(());
+            }
+
+            progress.saved_at = Some(Instant::now());
+        }
+
+        let mut conn = self.pool.get().await?;
+        let job = JobModel::progress(self.id, completed, total)
+            .fetch_optional(&mut conn)
+            .await?;
+
+        if let Some(job) = job {
+            *self.checkpoint_at.lock() = job.checkpoint_at;
+        }
+
+        Ok(())
+    }
+
+    /// Last progress reported by the job, as `(completed, total)`.
+    pub fn last_progress(&self) -> Option<(i64, i64)> {
+        let progress = self.progress.lock();
+        progress.completed.zip(progress.total)
+    }
+
+    /// Set the result of the job, e.g. a link to the exported file. It's saved when the job
+    /// finishes successfully.
+    pub fn set_result(&self, result: serde_json::Value) {
+        *self.result.lock() = Some(result);
+    }
+
+    /// The result set by the job.
+    pub fn result(&self) -> Option<serde_json::Value> {
+        self.result.lock().clone()
+    }
+}
@@ -25,6 +25,12 @@ pub enum Error {
 
     #[error("specified cron schedule is not valid")]
     CronValueError,
+
+    #[error("job signature is not valid")]
+    InvalidSignature,
+
+    #[error("job encryption error: {0}")]
+    EncryptionError(#[from] crate::crypto::Error),
 }
 
 impl From<serde_json::Error> for Error {
@@ -0,0 +1,517 @@
+//! Worker heartbeats and the status of the job workers.
+//!
+//! Each [`Worker`](super::Worker) records that it's alive in the `rwf_workers` table every `job_heartbeat_interval`,
+//! with the ids of the jobs it's running. Workers check each other: a worker that hasn't sent a heartbeat within
+//! `job_worker_timeout` is marked as stale and a warning is logged. If `job_reclaim_stale_workers` is enabled,
+//! its jobs are put back into the queue right away, without waiting for their visibility timeout.
+//!
+//! [`fleet_status`] lists the workers and the jobs they are running, e.g. for a dashboard:
+//!
+//! ```rust,ignore
+//! let fleet = rwf::job::fleet_status().await?;
+//!
+//! for worker in fleet.workers.iter().filter(|worker| worker.stale) {
+//!     println!("worker {} on {} is stale", worker.id, worker.hostname);
+//! }
+//! ```
+use super::{Error, JobModel, JobStatus};
+use crate::colors::MaybeColorize;
+use crate::config::get_config;
+use crate::model::{
+    get_connection, ConnectionGuard, FromRow, GetColumn, Model, Scope, ToValue, Value,
+};
+use crate::view::{ToTemplateValue, Value as TemplateValue};
+
+use serde::Serialize;
+use time::{Duration, OffsetDateTime};
+use tracing::warn;
+
+use std::collections::HashMap;
+
+/// Stale workers that don't come back are removed after this long.
+const PRUNE_AFTER: Duration = Duration::days(1);
+
+/// Heartbeat of a job worker, stored in the `rwf_workers` table.
+#[derive(Clone, Debug)]
+pub struct WorkerModel {
+    /// Unique id of the worker instance.
+    pub id: String,
+    pub hostname: String,
+    pub pid: i64,
+    /// Names of the jobs the worker runs.
+    pub queues: serde_json::Value,
+    /// Ids of the jobs the worker is running.
+    pub jobs: serde_json::Value,
+    pub started_at: OffsetDateTime,
+    pub last_heartbeat: OffsetDateTime,
+    /// The worker stopped sending heartbeats.
+    pub stale: bool,
+}
+
+impl WorkerModel {
+    /// Record that the worker is alive and running the jobs.
+    pub fn heartbeat(id: &str, queues: &[&str], jobs: &[i64]) -> Scope<Self> {
+        Self::find_by_sql(
+            "INSERT INTO rwf_workers (id, hostname, pid, queues, jobs)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (id) DO UPDATE SET
+                jobs = EXCLUDED.jobs,
+                last_heartbeat = NOW(),
+                stale = false
+            RETURNING *",
+            &[
+                id.to_value(),
+                hostname().to_value(),
+                i64::from(std::process::id()).to_value(),
+                Value::Json(serde_json::json!(queues)),
+                Value::Json(serde_json::json!(jobs)),
+            ],
+        )
+    }
+
+    /// Mark workers that haven't sent a heartbeat within the timeout as stale.
+    /// Returns only the workers that weren't stale already.
+    pub fn mark_stale(timeout: Duration) -> Scope<Self> {
+        Self::find_by_sql(
+            "UPDATE rwf_workers SET stale = true
+            WHERE
+                NOT stale
+                AND last_heartbeat < NOW() - make_interval(secs => $1::double precision)
+            RETURNING *",
+            &[timeout.as_seconds_f64().to_value()],
+        )
+    }
+
+    /// Remove stale workers that didn't send a heartbeat for a long time.
+    pub fn prune(age: Duration) -> Scope<Self> {
+        Self::find_by_sql(
+            "DELETE FROM rwf_workers
+            WHERE stale AND last_heartbeat < NOW() - make_interval(secs => $1::double precision)
+            RETURNING *",
+            &[age.as_seconds_f64().to_value()],
+        )
+    }
+
+    /// Remove the worker, e.g. when it shuts down.
+    pub fn unregister(id: &str) -> Scope<Self> {
+        Self::find_by_sql(
+            "DELETE FROM rwf_workers WHERE id = $1 RETURNING *",
+            &[id.to_value()],
+        )
+    }
+
+    /// Ids of the jobs the worker is running.
+    pub fn job_ids(&self) -> Vec<i64> {
+        serde_json::from_value(self.jobs.clone()).unwrap_or_default()
+    }
+
+    /// Names of the jobs the worker runs.
+    pub fn queue_names(&self) -> Vec<String> {
+        serde_json::from_value(self.queues.clone()).unwrap_or_default()
+    }
+}
+
+impl FromRow for WorkerModel {
+    fn from_row(row: tokio_postgres::Row) -> Result<Self, crate::model::Error> {
+        Ok(Self {
+            id: row.get_column("id")?,
+            hostname: row.get_column("hostname")?,
+            pid: row.get_column("pid")?,
+            queues: row.get_column("queues")?,
+            jobs: row.get_column("jobs")?,
+            started_at: row.get_column("started_at")?,
+            last_heartbeat: row.get_column("last_heartbeat")?,
+            stale: row.get_column("stale")?,
+        })
+    }
+}
+
+impl Model for WorkerModel {
+    fn id(&self) -> Value {
+        self.id.to_value()
+    }
+
+    fn table_name() -> &'static str {
+        "rwf_workers"
+    }
+
+    fn primary_key() -> &'static str {
+        "id"
+    }
+
+    fn foreign_key() -> &'static str {
+        "rwf_worker_id"
+    }
+
+    fn column_names() -> &'static [&'static str] {
+        &[
+            "hostname",
+            "pid",
+            "queues",
+            "jobs",
+            "started_at",
+            "last_heartbeat",
+            "stale",
+        ]
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.hostname.to_value(),
+            self.pid.to_value(),
+            Value::Json(self.queues.clone()),
+            Value::Json(self.jobs.clone()),
+            self.started_at.to_value(),
+            self.last_heartbeat.to_value(),
+            self.stale.to_value(),
+        ]
+    }
+}
+
+/// Name of the machine the worker runs on.
+pub(crate) fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Find workers that stopped sending heartbeats, and reclaim their jobs if `reclaim` is set.
+/// Returns the jobs put back into the queue.
+pub(crate) async fn check(
+    conn: &mut ConnectionGuard,
+    timeout: Duration,
+    reclaim: bool,
+) -> Result<Vec<JobModel>, Error> {
+    let stale = WorkerModel::mark_stale(timeout).fetch_all(conn).await?;
+    let mut reclaimed = vec![];
+
+    for worker in stale {
+        warn!(
+            "worker {} on {} (pid: {}) stopped sending heartbeats, last one at {}, running jobs: {:?}",
+            worker.id,
+            worker.hostname,
+            worker.pid,
+            worker.last_heartbeat,
+            worker.job_ids(),
+        );
+
+        if reclaim {
+            let jobs = JobModel::reclaim_worker(&worker.id).fetch_all(conn).await?;
+
+            for job in &jobs {
+                warn!(
+                    "job {} (id: {}) was running on stale worker {}, rescheduling",
+                    job.name.green(),
+                    job.id.unwrap_or_default(),
+                    worker.id
+                );
+            }
+
+            WorkerModel::unregister(&worker.id).execute(conn).await?;
+            reclaimed.extend(jobs);
+        }
+    }
+
+    WorkerModel::prune(PRUNE_AFTER).execute(conn).await?;
+
+    Ok(reclaimed)
+}
+
+/// Job running on a worker.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunningJob {
+    #[serde(flatten)]
+    pub status: JobStatus,
+    /// The job didn't make progress within the visibility timeout.
+    pub stuck: bool,
+}
+
+/// Status of a job worker.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerStatus {
+    pub id: String,
+    pub hostname: String,
+    pub pid: i64,
+    /// Names of the jobs the worker runs.
+    pub queues: Vec<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_heartbeat: OffsetDateTime,
+    /// The worker stopped sending heartbeats.
+    pub stale: bool,
+    /// Jobs the worker is running.
+    pub jobs: Vec<RunningJob>,
+}
+
+/// Status of all job workers.
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetStatus {
+    pub workers: Vec<WorkerStatus>,
+    /// Running jobs not claimed by any worker, e.g. because their worker crashed
+    /// and its heartbeat was removed.
+    pub unassigned: Vec<RunningJob>,
+}
+
+impl FleetStatus {
+    /// Match running jobs to the workers running them.
+    pub fn new(
+        workers: Vec<WorkerModel>,
+        running: Vec<JobModel>,
+        now: OffsetDateTime,
+        worker_timeout: Duration,
+        visibility_timeout: Duration,
+    ) -> Self {
+        let mut running = running
+            .into_iter()
+            .filter_map(|job| job.id.map(|id| (id, job)))
+            .collect::<HashMap<_, _>>();
+
+        let mut job = |job: JobModel| {
+            let last_progress = job.checkpoint_at.max(job.started_at).unwrap_or(now);
+
+            RunningJob {
+                stuck: now - last_progress > visibility_timeout,
+                status: JobStatus::from(job),
+            }
+        };
+
+        let workers = workers
+            .into_iter()
+            .map(|worker| WorkerStatus {
+                jobs: worker
+                    .job_ids()
+                    .into_iter()
+                    .filter_map(|id| running.remove(&id))
+                    .map(&mut job)
+                    .collect(),
+                queues: worker.queue_names(),
+                stale: worker.stale || now - worker.last_heartbeat > worker_timeout,
+                id: worker.id,
+                hostname: worker.hostname,
+                pid: worker.pid,
+                started_at: worker.started_at,
+                last_heartbeat: worker.last_heartbeat,
+            })
+            .collect();
+
+        let mut unassigned = running.into_values().map(job).collect::<Vec<_>>();
+        unassigned.sort_by_key(|job| job.status.id);
+
+        Self {
+            workers,
+            unassigned,
+        }
+    }
+
+    /// Number of workers that stopped sending heartbeats.
+    pub fn stale_workers(&self) -> usize {
+        self.workers.iter().filter(|worker| worker.stale).count()
+    }
+
+    /// Number of running jobs that didn't make progress within the visibility timeout.
+    pub fn stuck_jobs(&self) -> usize {
+        self.workers
+            .iter()
+            .flat_map(|worker| worker.jobs.iter())
+            .chain(self.unassigned.iter())
+            .filter(|job| job.stuck)
+            .count()
+    }
+}
+
+/// Get the status of the job workers and the jobs they are running.
+pub async fn fleet_status() -> Result<FleetStatus, Error> {
+    let mut conn = get_connection().await?;
+    let workers = WorkerModel::all()
+        .order(("started_at", "ASC"))
+        .fetch_all(&mut conn)
+        .await?;
+    let running = JobModel::running().fetch_all(&mut conn).await?;
+    let config = &get_config().general;
+
+    Ok(FleetStatus::new(
+        workers,
+        running,
+        OffsetDateTime::now_utc(),
+        config.job_worker_timeout(),
+        config.job_visibility_timeout(),
+    ))
+}
+
+impl ToTemplateValue for RunningJob {
+    fn to_template_value(&self) -> Result<TemplateValue, crate::view::Error> {
+        let mut hash = HashMap::new();
+        hash.insert("id".into(), self.status.id.to_template_value()?);
+        hash.insert("name".into(), self.status.name.to_template_value()?);
+        hash.insert(
+            "started_at".into(),
+            match self.status.started_at {
+                Some(started_at) => started_at.to_template_value()?,
+                None => TemplateValue::Null,
+            },
+        );
+        hash.insert("stuck".into(), self.stuck.to_template_value()?);
+        Ok(TemplateValue::Hash(hash))
+    }
+}
+
+impl ToTemplateValue for WorkerStatus {
+    fn to_template_value(&self) -> Result<TemplateValue, crate::view::Error> {
+        let mut hash = HashMap::new();
+        hash.insert("id".into(), self.id.to_template_value()?);
+        hash.insert("hostname".into(), self.hostname.to_template_value()?);
+        hash.insert("pid".into(), self.pid.to_template_value()?);
+        hash.insert("queues".into(), self.queues.to_template_value()?);
+        hash.insert("started_at".into(), self.started_at.to_template_value()?);
+        hash.insert(
+            "last_heartbeat".into(),
+            self.last_heartbeat.to_template_value()?,
+        );
+        hash.insert("stale".into(), self.stale.to_template_value()?);
+        hash.insert("jobs".into(), self.jobs.to_template_value()?);
+        Ok(TemplateValue::Hash(hash))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::{migrations::bootstrap, Pool};
+    use serde_json::json;
+
+    fn worker(id: &str, jobs: &[i64], last_heartbeat: OffsetDateTime) -> WorkerModel {
+        WorkerModel {
+            id: id.into(),
+            hostname: "web-1".into(),
+            pid: 1,
+            queues: json!(["export"]),
+            jobs: json!(jobs),
+            started_at: last_heartbeat,
+            last_heartbeat,
+            stale: false,
+        }
+    }
+
+    fn job(id: i64, started_at: OffsetDateTime) -> JobModel {
+        let mut job = JobModel::new("export", json!({}));
+        job.id = Some(id);
+        job.started_at = Some(started_at);
+        job
+    }
+
+    #[test]
+    fn test_fleet_status() {
+        let now = OffsetDateTime::now_utc();
+        let fleet = FleetStatus::new(
+            vec![
+                worker("alive", &[1, 2], now - Duration::seconds(3)),
+                worker("wedged", &[3], now - Duration::minutes(2)),
+            ],
+            vec![
+                job(1, now - Duration::seconds(10)),
+                job(2, now - Duration::hours(1)),
+                job(3, now - Duration::minutes(2)),
+                job(4, now - Duration::hours(1)),
+            ],
+            now,
+            Duration::seconds(30),
+            Duration::minutes(5),
+        );
+
+        let alive = &fleet.workers[0];
+        assert!(!alive.stale);
+        assert_eq!(alive.queues, vec!["export"]);
+        assert_eq!(
+            alive
+                .jobs
+                .iter()
+                .map(|job| (job.status.id, job.stuck))
+                .collect::<Vec<_>>(),
+            vec![(1, false), (2, true)]
+        );
+
+        let wedged = &fleet.workers[1];
+        assert!(wedged.stale);
+        assert_eq!(wedged.jobs[0].status.id, 3);
+
+        assert_eq!(fleet.unassigned[0].status.id, 4);
+        assert_eq!(fleet.stale_workers(), 1);
+        assert_eq!(fleet.stuck_jobs(), 2);
+
+        let status = serde_json::to_value(&fleet).unwrap();
+        assert_eq!(status["workers"][0]["jobs"][1]["stuck"], true);
+        assert_eq!(status["workers"][0]["jobs"][1]["state"], "running");
+    }
+
+    #[tokio::test]
+    async fn test_stale_worker() -> Result<(), Error> {
+        let pool = Pool::from_env();
+        let mut transaction = pool.transaction().await?;
+
+        bootstrap(&mut transaction).await?;
+
+        let mut job = JobModel::new("test_stale_worker", json!({}));
+        job.started_at = Some(OffsetDateTime::now_utc());
+        let job = job.save().fetch(&mut transaction).await?;
+        let id = job.id.unwrap();
+
+        WorkerModel::heartbeat("test_stale_worker", &["test_stale_worker"], &[id])
+            .execute(&mut transaction)
+            .await?;
+
+        // Alive.
+        let reclaimed = check(&mut transaction, Duration::seconds(30), true).await?;
+        assert!(reclaimed.is_empty());
+
+        // The worker stops sending heartbeats.
+        transaction
+            .client()
+            .execute(
+                "UPDATE rwf_workers SET last_heartbeat = NOW() - INTERVAL '1 minute' WHERE id = 'test_stale_worker'",
+                &[],
+            )
+            .await?;
+
+        // Detected once, but jobs are left alone.
+        let stale = WorkerModel::mark_stale(Duration::seconds(30))
+            .fetch_all(&mut transaction)
+            .await?;
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].job_ids(), vec![id]);
+        assert!(check(&mut transaction, Duration::seconds(30), false)
+            .await?
+            .is_empty());
+
+        // Comes back.
+        let worker = WorkerModel::heartbeat("test_stale_worker", &["test_stale_worker"], &[id])
+            .fetch(&mut transaction)
+            .await?;
+        assert!(!worker.stale);
+
+        transaction
+            .client()
+            .execute(
+                "UPDATE rwf_workers SET last_heartbeat = NOW() - INTERVAL '1 minute' WHERE id = 'test_stale_worker'",
+                &[],
+            )
+            .await?;
+
+        // Reclaimed.
+        let reclaimed = check(&mut transaction, Duration::seconds(30), true).await?;
+        assert_eq!(reclaimed.len(), 1);
+        assert_eq!(reclaimed[0].id, Some(id));
+        assert!(reclaimed[0].started_at.is_none());
+        assert_eq!(reclaimed[0].attempts, 1);
+
+        let worker = WorkerModel::find("test_stale_worker")
+            .fetch_optional(&mut transaction)
+            .await?;
+        assert!(worker.is_none());
+
+        Ok(())
+    }
+}
@@ -3,13 +3,19 @@
 //! Implemented using a Postgres table and a fast locking query (`FOR UPDATE SKIP LOCKED`).
 //! This implementation makes the job queue durable (does not lose jobs) and performant.
 pub mod clock;
+pub mod context;
 pub mod cron;
 pub mod error;
+pub mod fleet;
 pub mod model;
+pub mod status;
 pub mod worker;
 
 pub use clock::Clock;
+pub use context::JobContext;
 pub use cron::Cron;
 pub use error::Error;
-pub use model::{queue_async, queue_delay, Job, JobHandler, JobModel};
+pub use fleet::{fleet_status, FleetStatus, WorkerModel, WorkerStatus};
+pub use model::{queue_async, queue_delay, queue_job_encrypted, Job, JobHandler, JobModel};
+pub use status::{status, JobState, JobStatus};
 pub use worker::Worker;
@@ -1,7 +1,11 @@
 use crate::colors::MaybeColorize;
-use crate::job::{clock::ScheduledJob, Error};
-use crate::model::{get_connection, FromRow, Model, Scope, ToValue, Value};
+use crate::crypto::{decrypt, encrypt, sign, verify_signature};
+use crate::job::{clock::ScheduledJob, Error, JobContext};
+use crate::model::{
+    get_connection, ConnectionGuard, FromRow, GetColumn, Model, Scope, ToValue, Value,
+};
 use serde::Serialize;
+use serde_json::json;
 use time::{Duration, OffsetDateTime};
 
 use async_trait::async_trait;
@@ -20,10 +24,20 @@ pub struct JobModel {
     pub retries: i64,
     pub completed_at: Option<OffsetDateTime>,
     pub error: Option<String>,
+    pub checkpoint_at: Option<OffsetDateTime>,
+    pub progress_completed: Option<i64>,
+    pub progress_total: Option<i64>,
+    pub result: Option<serde_json::Value>,
+    /// Arguments are encrypted with the application secret key.
+    pub encrypted: bool,
+    /// Signature of the job id, name and arguments.
+    pub signature: Option<String>,
+    /// Queued before Rwf signed jobs, so there is no signature to check.
+    pub legacy: bool,
 }
 
 impl JobModel {
-    fn new(name: &str, args: serde_json::Value) -> Self {
+    pub(crate) fn new(name: &str, args: serde_json::Value) -> Self {
         Self {
             id: None,
             name: name.to_string(),
@@ -35,9 +49,86 @@ impl JobModel {
             retries: 25,
             completed_at: None,
             error: None,
+            checkpoint_at: None,
+            progress_completed: None,
+            progress_total: None,
+            result: None,
+            encrypted: false,
+            signature: None,
+            legacy: false,
         }
     }
 
+    /// Encrypt the arguments, so they can't be read in the database.
+    pub(crate) fn encrypt(mut self) -> Result<Self, Error> {
+        let ciphertext = encrypt(self.args.to_string().as_bytes())?;
+        self.args = json!({ "ciphertext": ciphertext });
+        self.encrypted = true;
+        Ok(self)
+    }
+
+    fn sign(&mut self) {
+        self.signature = Some(sign(&self.signed_data()));
+    }
+
+    /// The job id, name, if the arguments are encrypted, and the arguments, with object keys sorted,
+    /// so the signature doesn't depend on how the database stores them. The id stops a signed
+    /// row from being copied into a new job.
+    fn signed_data(&self) -> Vec<u8> {
+        format!(
+            "{}\n{}\n{}\n{}",
+            self.id.unwrap_or_default(),
+            self.name,
+            self.encrypted,
+            canonical(&self.args)
+        )
+        .into_bytes()
+    }
+
+    /// Verify the signature of the arguments and decrypt them if they are encrypted.
+    ///
+    /// Jobs changed in the database, or scheduled without a signature, are rejected.
+    /// Jobs queued before Rwf signed jobs are accepted as they are.
+    pub fn payload(&self) -> Result<serde_json::Value, Error> {
+        if self.legacy && self.signature.is_none() && !self.encrypted {
+            return Ok(self.args.clone());
+        }
+
+        let signature = self.signature.as_deref().ok_or(Error::InvalidSignature)?;
+
+        if !verify_signature(&self.signed_data(), signature) {
+            return Err(Error::InvalidSignature);
+        }
+
+        if self.encrypted {
+            let ciphertext = self.args["ciphertext"]
+                .as_str()
+                .ok_or(Error::InvalidSignature)?;
+            Ok(serde_json::from_slice(&decrypt(ciphertext)?)?)
+        } else {
+            Ok(self.args.clone())
+        }
+    }
+
+    /// Add the job to the queue. The id is allocated before the job is inserted,
+    /// so it can be signed.
+    pub(crate) async fn queue(mut self, conn: &mut ConnectionGuard) -> Result<Self, Error> {
+        let row = conn
+            .client()
+            .query_one(
+                "SELECT nextval(pg_get_serial_sequence('rwf_jobs', 'id'))",
+                &[],
+            )
+            .await?;
+        self.id = Some(row.try_get(0)?);
+        self.sign();
+
+        let mut attributes = vec![("id", self.id.to_value())];
+        attributes.extend(Self::column_names().iter().copied().zip(self.values()));
+
+        Ok(Self::create(&attributes).fetch(conn).await?)
+    }
+
     fn new_with_delay(name: &str, args: serde_json::Value, delay: Duration) -> Self {
         let mut job = Self::new(name, args);
         job.start_after = OffsetDateTime::now_utc() + delay;
@@ -58,13 +149,78 @@ impl JobModel {
             .skip_locked()
     }
 
-    ///
+    /// Reschedule all running jobs.
     pub fn reschedule() -> Scope<Self> {
         Self::filter("completed_at", Value::Null)
             .not("started_at", Value::Null)
             .update_all(&[("started_at", Value::Null)])
     }
 
+    /// Return running jobs that haven't made progress within the visibility timeout
+    /// back into the queue. The job's worker most likely crashed.
+    ///
+    /// Reclaiming a job counts as an attempt, so a job that keeps crashing its worker
+    /// will eventually run out of retries.
+    pub fn reclaim(visibility_timeout: Duration) -> Scope<Self> {
+        Self::find_by_sql(
+            "UPDATE rwf_jobs SET
+                started_at = NULL,
+                checkpoint_at = NULL,
+                attempts = attempts + 1,
+                error = 'job visibility timeout expired'
+            WHERE
+                completed_at IS NULL
+                AND started_at IS NOT NULL
+                AND GREATEST(started_at, checkpoint_at) < NOW() - make_interval(secs => $1::double precision)
+            RETURNING *",
+            &[visibility_timeout.as_seconds_f64().to_value()],
+        )
+    }
+
+    /// Return the jobs running on a worker that stopped sending heartbeats back into the queue,
+    /// without waiting for the visibility timeout. Counts as an attempt, like [`JobModel::reclaim`].
+    pub fn reclaim_worker(worker_id: &str) -> Scope<Self> {
+        Self::find_by_sql(
+            "UPDATE rwf_jobs SET
+                started_at = NULL,
+                checkpoint_at = NULL,
+                attempts = attempts + 1,
+                error = 'worker heartbeat expired'
+            WHERE
+                completed_at IS NULL
+                AND started_at IS NOT NULL
+                AND id IN (
+                    SELECT jsonb_array_elements_text(jobs)::bigint
+                    FROM rwf_workers WHERE id = $1
+                )
+            RETURNING *",
+            &[worker_id.to_value()],
+        )
+    }
+
+    /// Record that the running job is still making progress.
+    pub fn checkpoint(id: i64) -> Scope<Self> {
+        Self::find_by_sql(
+            "UPDATE rwf_jobs SET checkpoint_at = NOW()
+            WHERE id = $1 AND completed_at IS NULL AND started_at IS NOT NULL
+            RETURNING *",
+            &[id.to_value()],
+        )
+    }
+
+    /// Record the progress of the running job. Counts as a checkpoint.
+    pub fn progress(id: i64, completed: i64, total: i64) -> Scope<Self> {
+        Self::find_by_sql(
+            "UPDATE rwf_jobs SET
+                progress_completed = $2,
+                progress_total = $3,
+                checkpoint_at = NOW()
+            WHERE id = $1 AND completed_at IS NULL AND started_at IS NOT NULL
+            RETURNING *",
+            &[id.to_value(), completed.to_value(), total.to_value()],
+        )
+    }
+
     pub fn scheduled(&self) -> Scope<Self> {
         Self::filter("completed_at", Value::Null)
             .filter("start_after", self.start_after)
@@ -90,16 +246,23 @@ impl JobModel {
 impl FromRow for JobModel {
     fn from_row(row: tokio_postgres::Row) -> Result<Self, crate::model::Error> {
         Ok(Self {
-            id: row.try_get("id")?,
-            name: row.try_get("name")?,
-            args: row.try_get("args")?,
-            created_at: row.try_get("created_at")?,
-            start_after: row.try_get("start_after")?,
-            started_at: row.try_get("started_at")?,
-            attempts: row.try_get("attempts")?,
-            retries: row.try_get("retries")?,
-            completed_at: row.try_get("completed_at")?,
-            error: row.try_get("error")?,
+            id: row.get_column("id")?,
+            name: row.get_column("name")?,
+            args: row.get_column("args")?,
+            created_at: row.get_column("created_at")?,
+            start_after: row.get_column("start_after")?,
+            started_at: row.get_column("started_at")?,
+            attempts: row.get_column("attempts")?,
+            retries: row.get_column("retries")?,
+            completed_at: row.get_column("completed_at")?,
+            error: row.get_column("error")?,
+            checkpoint_at: row.get_column("checkpoint_at")?,
+            progress_completed: row.get_column("progress_completed")?,
+            progress_total: row.get_column("progress_total")?,
+            result: row.get_column("result")?,
+            encrypted: row.get_column("encrypted")?,
+            signature: row.get_column("signature")?,
+            legacy: row.get_column("legacy")?,
         })
     }
 }
@@ -132,6 +295,13 @@ impl Model for JobModel {
             "retries",
             "completed_at",
             "error",
+            "checkpoint_at",
+            "progress_completed",
+            "progress_total",
+            "result",
+            "encrypted",
+            "signature",
+            "legacy",
         ]
     }
 
@@ -146,6 +316,14 @@ impl Model for JobModel {
             self.retries.to_value(),
             self.completed_at.to_value(),
             self.error.to_value(),
+            self.checkpoint_at.to_value(),
+            self.progress_completed.to_value(),
+            self.progress_total.to_value(),
+            // Stored as JSON, even if the result is a string or a number.
+            self.result.clone().map(Value::Json).to_value(),
+            self.encrypted.to_value(),
+            self.signature.to_value(),
+            self.legacy.to_value(),
         ]
     }
 }
@@ -159,30 +337,48 @@ pub trait Job: Sync + Send {
     /// Execute the job.
     ///
     /// Implement this method with the code you want to run in the background.
-    /// Arguments are passed in using JSON.
-    async fn execute(&self, args: serde_json::Value) -> Result<(), Error>;
+    /// Arguments are passed in using JSON. The context gives access to the connection pool,
+    /// configuration, and allows long-running jobs to report progress with [`JobContext::checkpoint`].
+    async fn execute(&self, context: &JobContext, args: serde_json::Value) -> Result<(), Error>;
 
     /// Schedule this job to run in the background.
     ///
     /// This method schedules the job in the queue and returns immediately without
-    /// running the job.
-    async fn execute_async(&self, args: serde_json::Value) -> Result<(), Error> {
+    /// running the job. The job id can be used to check on the job with [`status`](crate::job::status).
+    async fn execute_async(&self, args: serde_json::Value) -> Result<i64, Error> {
         let mut conn = get_connection().await?;
-        JobModel::new(self.job_name(), args)
-            .save()
-            .execute(&mut conn)
+        let job = JobModel::new(self.job_name(), args)
+            .queue(&mut conn)
             .await?;
 
         info!("job {} scheduled to run now", self.job_name().green());
 
-        Ok(())
+        Ok(job.id.unwrap_or_default())
+    }
+
+    /// Schedule this job to run in the background, with the arguments encrypted.
+    ///
+    /// Use this for jobs with sensitive arguments, e.g. tokens or personal data, which
+    /// shouldn't be readable in the database or in the admin panel.
+    async fn execute_encrypted(&self, args: serde_json::Value) -> Result<i64, Error> {
+        let mut conn = get_connection().await?;
+        let job = JobModel::new(self.job_name(), args)
+            .encrypt()?
+            .queue(&mut conn)
+            .await?;
+
+        info!(
+            "job {} scheduled to run now (encrypted)",
+            self.job_name().green()
+        );
+
+        Ok(job.id.unwrap_or_default())
     }
 
-    async fn execute_delay(&self, args: serde_json::Value, delay: Duration) -> Result<(), Error> {
+    async fn execute_delay(&self, args: serde_json::Value, delay: Duration) -> Result<i64, Error> {
         let mut conn = get_connection().await?;
-        JobModel::new_with_delay(self.job_name(), args, delay)
-            .save()
-            .execute(&mut conn)
+        let job = JobModel::new_with_delay(self.job_name(), args, delay)
+            .queue(&mut conn)
             .await?;
 
         info!(
@@ -191,7 +387,7 @@ pub trait Job: Sync + Send {
             delay.whole_seconds()
         );
 
-        Ok(())
+        Ok(job.id.unwrap_or_default())
     }
 
     fn schedule(self, args: serde_json::Value, schedule: &str) -> Result<ScheduledJob, Error>
@@ -233,19 +429,94 @@ impl JobHandler {
     }
 }
 
+/// Schedule the job to run now. Returns the job id.
 #[inline]
-pub async fn queue<T: Job + Serialize>(job: &T) -> Result<(), Error> {
+pub async fn queue<T: Job + Serialize>(job: &T) -> Result<i64, Error> {
     let args = serde_json::to_value(job)?;
     job.execute_async(args).await
 }
 
+/// Schedule the job to run after a delay. Returns the job id.
 #[inline]
-pub async fn queue_delay<T: Job + Serialize>(job: &T, delay: Duration) -> Result<(), Error> {
+pub async fn queue_delay<T: Job + Serialize>(job: &T, delay: Duration) -> Result<i64, Error> {
     let args = serde_json::to_value(job)?;
     job.execute_delay(args, delay).await
 }
 
+/// Schedule the job to run now, with its arguments encrypted. Returns the job id.
 #[inline]
-pub async fn queue_async<T: Job + Serialize>(job: &T) -> Result<(), Error> {
+pub async fn queue_job_encrypted<T: Job + Serialize>(job: &T) -> Result<i64, Error> {
+    let args = serde_json::to_value(job)?;
+    job.execute_encrypted(args).await
+}
+
+/// Schedule the job to run now. Returns the job id.
+#[inline]
+pub async fn queue_async<T: Job + Serialize>(job: &T) -> Result<i64, Error> {
     queue(job).await
 }
+
+/// Serialize the value to JSON with object keys sorted.
+fn canonical(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut keys = object.keys().collect::<Vec<_>>();
+            keys.sort();
+
+            let fields = keys
+                .into_iter()
+                .map(|key| {
+                    format!(
+                        "{}:{}",
+                        serde_json::Value::String(key.clone()),
+                        canonical(&object[key])
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            format!("{{{}}}", fields.join(","))
+        }
+        serde_json::Value::Array(values) => format!(
+            "[{}]",
+            values.iter().map(canonical).collect::<Vec<_>>().join(",")
+        ),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn signed(mut job: JobModel) -> JobModel {
+        job.id = Some(1);
+        job.sign();
+        job
+    }
+
+    #[test]
+    fn test_payload() {
+        let args = json!({"user_id": 5, "email": "alice@example.com"});
+
+        let job = signed(JobModel::new("export", args.clone()));
+        assert!(!job.encrypted);
+        assert_eq!(job.payload().unwrap(), args);
+
+        let job = signed(JobModel::new("export", args.clone()).encrypt().unwrap());
+        assert!(job.encrypted);
+        assert!(!job.args.to_string().contains("alice"));
+        assert_eq!(job.payload().unwrap(), args);
+
+        // Key order doesn't matter, e.g. after a round trip through JSONB.
+        let mut reordered = signed(JobModel::new(
+            "export",
+            json!({"b": [1, {"d": 1, "c": 2}], "a": null}),
+        ));
+        reordered.args = json!({"a": null, "b": [1, {"c": 2, "d": 1}]});
+        assert!(reordered.payload().is_ok());
+
+        // Not queued yet.
+        let unsigned = JobModel::new("export", args);
+        assert!(matches!(unsigned.payload(), Err(Error::InvalidSignature)));
+    }
+}
@@ -0,0 +1,158 @@
+//! Status of a job in the queue.
+//!
+//! Jobs are identified by the id returned when they are scheduled. Controllers can give
+//! the id to the client, which then checks on the job until it's done:
+//!
+//! ```rust,ignore
+//! let id = queue_async(&Export { user_id }).await?;
+//!
+//! // Later.
+//! if let Some(status) = rwf::job::status(id).await? {
+//!     println!("{:?}: {:?}%", status.state, status.progress.map(|p| p.percent()));
+//! }
+//! ```
+use super::{Error, JobModel};
+use crate::model::{get_connection, Model};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+/// State of the job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting to run, including after a failed attempt.
+    Queued,
+    /// Running now.
+    Running,
+    /// Finished successfully.
+    Completed,
+    /// Failed and ran out of retries.
+    Failed,
+}
+
+/// Progress reported by the job with [`JobContext::progress`](super::JobContext::progress).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub completed: i64,
+    pub total: i64,
+}
+
+impl Progress {
+    /// Percentage of the job that's done, between 0 and 100.
+    pub fn percent(&self) -> f64 {
+        if self.total <= 0 {
+            0.0
+        } else {
+            (self.completed as f64 / self.total as f64 * 100.0).clamp(0.0, 100.0)
+        }
+    }
+}
+
+/// Status of a job.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub id: i64,
+    pub name: String,
+    /// Job arguments, e.g. to check who started the job. Not serialized.
+    #[serde(skip)]
+    pub args: serde_json::Value,
+    pub state: JobState,
+    pub progress: Option<Progress>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub enqueued_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub started_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+    pub attempts: i32,
+    /// Error of the last failed attempt.
+    pub error: Option<String>,
+    /// Set by the job with [`JobContext::set_result`](super::JobContext::set_result).
+    pub result: Option<serde_json::Value>,
+}
+
+impl From<JobModel> for JobStatus {
+    fn from(job: JobModel) -> Self {
+        let state = if job.completed_at.is_some() {
+            JobState::Completed
+        } else if job.started_at.is_some() {
+            JobState::Running
+        } else if i64::from(job.attempts) >= job.retries {
+            JobState::Failed
+        } else {
+            JobState::Queued
+        };
+
+        let progress = job
+            .progress_completed
+            .zip(job.progress_total)
+            .map(|(completed, total)| Progress { completed, total });
+
+        Self {
+            id: job.id.unwrap_or_default(),
+            name: job.name,
+            args: job.args,
+            state,
+            progress,
+            enqueued_at: job.created_at,
+            started_at: job.started_at,
+            finished_at: job.completed_at,
+            attempts: job.attempts,
+            error: job.error,
+            result: job.result,
+        }
+    }
+}
+
+impl JobStatus {
+    /// The job won't run again.
+    pub fn finished(&self) -> bool {
+        matches!(self.state, JobState::Completed | JobState::Failed)
+    }
+}
+
+/// Get the status of the job, if it exists.
+pub async fn status(id: i64) -> Result<Option<JobStatus>, Error> {
+    let mut conn = get_connection().await?;
+    let job = JobModel::find(id).fetch_optional(&mut conn).await?;
+
+    Ok(job.map(|mut job| {
+        // Encrypted arguments are decrypted, so they can be used to authorize the client.
+        job.args = job.payload().unwrap_or_default();
+        JobStatus::from(job)
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_job_status() {
+        let mut job = JobModel::new("export", json!({}));
+        assert_eq!(JobStatus::from(job.clone()).state, JobState::Queued);
+
+        job.started_at = Some(OffsetDateTime::now_utc());
+        job.progress_completed = Some(25);
+        job.progress_total = Some(200);
+        let status = JobStatus::from(job.clone());
+        assert_eq!(status.state, JobState::Running);
+        assert_eq!(status.progress.unwrap().percent(), 12.5);
+
+        job.started_at = None;
+        job.attempts = 25;
+        assert_eq!(JobStatus::from(job.clone()).state, JobState::Failed);
+
+        job.completed_at = Some(OffsetDateTime::now_utc());
+        job.result = Some(json!({"url": "/exports/1.csv"}));
+        let status = JobStatus::from(job);
+        assert!(status.finished());
+
+        let status = serde_json::to_value(&status).unwrap();
+        assert_eq!(status["state"], "completed");
+        assert_eq!(status["result"]["url"], "/exports/1.csv");
+        assert!(status["finished_at"].is_string());
+    }
+}
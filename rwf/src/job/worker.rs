@@ -1,17 +1,25 @@
 use super::{
     clock::{Clock, ScheduledJob},
-    Error, JobHandler, JobModel,
+    fleet::{self, WorkerModel},
+    Error, JobContext, JobHandler, JobModel,
 };
 
 use crate::colors::MaybeColorize;
+use crate::config::get_config;
+use crate::errors::{panic_message, ErrorKind, ErrorReport};
+use crate::shutdown::{advance, Phase, ShutdownHooks};
 use time::OffsetDateTime;
 
+use tokio::select;
 use tokio::time::{sleep, Duration};
-use tracing::{error, info, warn};
+use tracing::{error, info, warn, Instrument};
 
-use crate::model::{get_connection, get_pool, Model};
+use crate::model::{get_connection, get_pool, ConnectionGuard, Model};
 
-use std::collections::HashMap;
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Display;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -19,6 +27,23 @@ use std::time::Instant;
 pub struct Worker {
     jobs: Arc<HashMap<String, JobHandler>>,
     clock: Option<Clock>,
+    hooks: ShutdownHooks,
+    /// Identifies this worker in the heartbeats. Shared by clones of the worker.
+    id: Arc<String>,
+    /// Ids of the jobs the worker is running.
+    running: Arc<Mutex<BTreeSet<i64>>>,
+}
+
+/// Removes the job from the running jobs when it's finished.
+struct RunningGuard {
+    running: Arc<Mutex<BTreeSet<i64>>>,
+    id: i64,
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.running.lock().remove(&self.id);
+    }
 }
 
 impl Worker {
@@ -30,6 +55,9 @@ impl Worker {
         Self {
             jobs: Arc::new(jobs),
             clock: None,
+            hooks: ShutdownHooks::default(),
+            id: Arc::new(uuid::Uuid::new_v4().to_string()),
+            running: Arc::new(Mutex::new(BTreeSet::new())),
         }
     }
 
@@ -38,6 +66,29 @@ impl Worker {
         self
     }
 
+    /// Run the hook when the worker shuts down, after the job it's running is finished.
+    /// See [`shutdown`](crate::shutdown) for details.
+    pub fn on_shutdown<F, Fut, E>(self, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        self.hooks.add(None, hook);
+        self
+    }
+
+    /// Run the hook when the worker shuts down, for up to `timeout` instead of `shutdown_hook_timeout`.
+    pub fn on_shutdown_timeout<F, Fut, E>(self, timeout: Duration, hook: F) -> Self
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        self.hooks.add(Some(timeout), hook);
+        self
+    }
+
     pub async fn start(self) -> Result<Self, Error> {
         let mut conn = get_connection().await?;
         JobModel::reschedule().execute(&mut conn).await?;
@@ -45,6 +96,11 @@ impl Worker {
         // Spawn a single instance of the worker.
         self.spawn();
 
+        let worker = self.clone();
+        tokio::spawn(async move {
+            worker.heartbeat().await;
+        });
+
         if let Some(clock) = self.clock.clone() {
             tokio::spawn(async move {
                 clock.run().await;
@@ -55,14 +111,103 @@ impl Worker {
     }
 
     pub async fn run(&self) {
+        self.work_until(std::future::pending()).await
+    }
+
+    /// Run the worker until the `shutdown` future completes. The worker finishes the job it's running,
+    /// stops taking jobs from the queue, and runs the shutdown hooks.
+    pub async fn run_until(&self, shutdown: impl Future<Output = ()>) {
+        self.work_until(shutdown).await;
+        self.run_hooks().await;
+    }
+
+    /// Run the shutdown hooks. Clones of the worker share the hooks, so they run only once.
+    pub(crate) async fn run_hooks(&self) {
+        if let Err(err) = self.unregister().await {
+            warn!("failed to remove worker heartbeat: {}", err);
+        }
+
+        self.hooks.run().await;
+    }
+
+    /// Unique id of the worker, shared by its clones.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Send heartbeats every `job_heartbeat_interval` and check for workers
+    /// that stopped sending theirs. Runs forever.
+    pub async fn heartbeat(&self) {
+        let interval = get_config().general.job_heartbeat_interval().unsigned_abs();
+
+        loop {
+            if let Err(err) = self.beat().await {
+                warn!("worker heartbeat failed: {}", err);
+            }
+
+            sleep(interval).await;
+        }
+    }
+
+    async fn beat(&self) -> Result<(), Error> {
+        let config = &get_config().general;
+        let queues = self
+            .jobs
+            .keys()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>();
+        let jobs = self.running.lock().iter().copied().collect::<Vec<_>>();
+
+        let mut conn = get_connection().await?;
+        WorkerModel::heartbeat(&self.id, &queues, &jobs)
+            .execute(&mut conn)
+            .await?;
+        fleet::check(
+            &mut conn,
+            config.job_worker_timeout(),
+            config.job_reclaim_stale_workers,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn unregister(&self) -> Result<(), Error> {
+        let mut conn = get_connection().await?;
+        WorkerModel::unregister(&self.id).execute(&mut conn).await?;
+        Ok(())
+    }
+
+    /// Run the worker until the `shutdown` future completes, without running the shutdown hooks.
+    pub(crate) async fn work_until(&self, shutdown: impl Future<Output = ()>) {
         info!("Background jobs worker started");
 
+        tokio::pin!(shutdown);
+        let mut stopping = false;
+
         loop {
             let start = Instant::now();
             let worker = self.clone();
-            let run_result = tokio::spawn(async move {
+            let mut task = tokio::spawn(async move {
                 let pool = get_pool();
 
+                // Return jobs abandoned by crashed workers into the queue.
+                let visibility_timeout = get_config().general.job_visibility_timeout();
+                let reclaimed = {
+                    let mut conn = pool.get().await?;
+                    JobModel::reclaim(visibility_timeout)
+                        .fetch_all(&mut conn)
+                        .await?
+                };
+
+                for job in reclaimed {
+                    warn!(
+                        "job {} (id: {}) exceeded its visibility timeout, rescheduling",
+                        job.name.green(),
+                        job.id.unwrap_or_default()
+                    );
+                }
+
                 let job = pool
                     .with_transaction(|mut transaction| async move {
                         let job = JobModel::next().fetch_optional(&mut transaction).await?;
@@ -81,23 +226,51 @@ impl Worker {
                     .await?;
 
                 if let Some(mut job) = job {
-                    if worker.jobs.get(&job.name).is_some() {
+                    // Don't run jobs which arguments were changed in the database.
+                    let payload = job.payload();
+
+                    if let Err(err) = payload {
+                        let mut conn = get_connection().await?;
+                        reject(job, &err, &mut conn).await?;
+                    } else if worker.jobs.get(&job.name).is_some() {
                         let worker = worker.clone();
-                        let args = job.args.clone();
+                        let args = payload?;
                         let name = job.name.clone();
+                        let context = JobContext::new(&job, pool.clone());
+                        let job_context = context.clone();
                         let now = Instant::now();
+                        let _running = job.id.map(|id| {
+                            worker.running.lock().insert(id);
+                            RunningGuard {
+                                running: worker.running.clone(),
+                                id,
+                            }
+                        });
 
                         // Run the job in a separate task. If the job panics,
                         // we won't crash this task.
                         let result = tokio::spawn(async move {
                             let registered_job = &worker.jobs[&name];
+                            let span = job_context.span().clone();
 
-                            registered_job.job.execute(args).await?;
+                            registered_job
+                                .job
+                                .execute(&job_context, args)
+                                .instrument(span)
+                                .await?;
 
                             Ok::<(), Error>(())
                         })
                         .await;
 
+                        job.checkpoint_at = context.checkpoint_at();
+
+                        // The last progress report may have been throttled.
+                        if let Some((completed, total)) = context.last_progress() {
+                            job.progress_completed = Some(completed);
+                            job.progress_total = Some(total);
+                        }
+
                         let elapsed = now.elapsed();
 
                         let mut conn = get_connection().await?;
@@ -111,13 +284,26 @@ impl Worker {
                                 );
                                 job.completed_at = Some(OffsetDateTime::now_utc());
                                 job.attempts += 1;
+                                job.result = context.result();
                                 job.save().execute(&mut conn).await?;
                             }
 
                             result => {
-                                let err = match result {
-                                    Ok(Err(err)) => err.to_string(),
-                                    Err(_) => "job panicked".to_string(),
+                                let (err, report) = match result {
+                                    Ok(Err(err)) => {
+                                        (err.to_string(), ErrorReport::new(ErrorKind::Job, &err))
+                                    }
+                                    Err(err) => {
+                                        let message = match err.try_into_panic() {
+                                            Ok(payload) => panic_message(payload.as_ref()),
+                                            Err(_) => "job cancelled".to_string(),
+                                        };
+
+                                        (
+                                            "job panicked".to_string(),
+                                            ErrorReport::message(ErrorKind::Panic, message),
+                                        )
+                                    }
                                     Ok(Ok(_)) => unreachable!(), // Captured above.
                                 };
 
@@ -136,6 +322,19 @@ impl Worker {
                                 job.attempts += 1;
                                 job.start_after = job.created_at + delay;
                                 job.started_at = None;
+                                job.checkpoint_at = None;
+
+                                // Panics are bugs, report them every time. Errors are reported
+                                // only once the job runs out of retries.
+                                if report.kind == ErrorKind::Panic
+                                    || i64::from(job.attempts) >= job.retries
+                                {
+                                    report
+                                        .tag("job", &job.name)
+                                        .tag("job_id", job.id.unwrap_or_default())
+                                        .tag("attempts", job.attempts)
+                                        .send();
+                                }
 
                                 job.save().execute(&mut conn).await?;
                             }
@@ -149,8 +348,17 @@ impl Worker {
                 }
 
                 Ok::<(), Error>(())
-            })
-            .await;
+            });
+
+            let run_result = select! {
+                result = &mut task => result,
+                _ = &mut shutdown => {
+                    advance(Phase::Draining);
+                    info!("Background jobs worker stopping, finishing the current job");
+                    stopping = true;
+                    task.await
+                }
+            };
 
             match run_result {
                 Ok(Ok(_)) => (),
@@ -162,6 +370,11 @@ impl Worker {
                     sleep(Duration::from_millis(1000)).await;
                 }
             }
+
+            if stopping {
+                info!("Background jobs worker stopped");
+                return;
+            }
         }
     }
 
@@ -173,3 +386,263 @@ impl Worker {
         self
     }
 }
+
+/// Mark a job which failed the signature check as dead. Retrying won't help.
+async fn reject(mut job: JobModel, err: &Error, conn: &mut ConnectionGuard) -> Result<(), Error> {
+    error!(
+        "job {} (id: {}) rejected: {}",
+        job.name.green(),
+        job.id.unwrap_or_default(),
+        err
+    );
+
+    job.error = Some(err.to_string());
+    job.attempts = i32::try_from(job.retries).unwrap_or(i32::MAX);
+    job.started_at = None;
+
+    ErrorReport::new(ErrorKind::Job, err)
+        .tag("job", &job.name)
+        .tag("job_id", job.id.unwrap_or_default())
+        .send();
+
+    job.save().execute(conn).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::job::fleet::{self, WorkerModel};
+    use crate::job::Job;
+    use crate::model::{migrations::bootstrap, Pool};
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Hangs on the first attempt, like a job on a worker which crashed,
+    /// and finishes on the retry.
+    #[derive(Default)]
+    struct HangingJob {
+        runs: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Job for HangingJob {
+        async fn execute(
+            &self,
+            context: &JobContext,
+            _args: serde_json::Value,
+        ) -> Result<(), Error> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+
+            if context.attempt() == 1 {
+                sleep(Duration::from_secs(3600)).await;
+            }
+
+            Ok(())
+        }
+    }
+
+    async fn running_job(conn: &mut ConnectionGuard, name: &str) -> Result<JobModel, Error> {
+        bootstrap(conn).await?;
+
+        // NOW() is the start of the transaction.
+        let mut job = JobModel::new(name, json!({}));
+        job.start_after = OffsetDateTime::now_utc() - time::Duration::hours(2);
+        job.queue(conn).await?;
+
+        // Claim the job like the worker does.
+        let mut job = JobModel::next().filter("name", name).fetch(conn).await?;
+        job.started_at = Some(OffsetDateTime::now_utc() - time::Duration::hours(1));
+        Ok(job.save().fetch(conn).await?)
+    }
+
+    #[tokio::test]
+    async fn test_reclaim_abandoned_job() -> Result<(), Error> {
+        let pool = Pool::from_env();
+        let mut transaction = pool.transaction().await?;
+        let name = "test_reclaim_abandoned_job";
+
+        let job = running_job(&mut transaction, name).await?;
+        let id = job.id.unwrap();
+        let handler = Arc::new(HangingJob::default());
+
+        // The worker reports the job in its heartbeat, then crashes while running it.
+        WorkerModel::heartbeat(name, &[name], &[id])
+            .execute(&mut transaction)
+            .await?;
+
+        let context = JobContext::new(&job, pool.clone());
+        let hanging = handler.clone();
+        let handle = tokio::spawn(async move { hanging.execute(&context, json!({})).await });
+        sleep(Duration::from_millis(10)).await;
+        handle.abort();
+        assert!(handle.await.unwrap_err().is_cancelled());
+        assert_eq!(handler.runs.load(Ordering::SeqCst), 1);
+
+        // Running jobs are invisible to other workers.
+        let next = JobModel::next()
+            .filter("name", name)
+            .fetch_optional(&mut transaction)
+            .await?;
+        assert!(next.is_none());
+
+        // The worker is alive as far as the fleet knows.
+        let reclaimed = fleet::check(&mut transaction, time::Duration::seconds(30), true).await?;
+        assert!(reclaimed.is_empty());
+
+        // The job hasn't exceeded its visibility timeout yet.
+        let reclaimed = JobModel::reclaim(time::Duration::hours(2))
+            .fetch_all(&mut transaction)
+            .await?;
+        assert!(reclaimed.is_empty());
+
+        // Heartbeats stop.
+        transaction
+            .client()
+            .execute(
+                "UPDATE rwf_workers SET last_heartbeat = NOW() - INTERVAL '1 minute' WHERE id = $1",
+                &[&name],
+            )
+            .await?;
+
+        let reclaimed = fleet::check(&mut transaction, time::Duration::seconds(30), true).await?;
+        assert_eq!(reclaimed.len(), 1);
+        assert_eq!(reclaimed[0].id, job.id);
+        assert_eq!(reclaimed[0].attempts, 1);
+        assert!(reclaimed[0].started_at.is_none());
+
+        // Another worker claims the job and runs it to completion.
+        let mut job = JobModel::next()
+            .filter("name", name)
+            .fetch(&mut transaction)
+            .await?;
+        assert_eq!(job.id, Some(id));
+        job.started_at = Some(OffsetDateTime::now_utc());
+        let mut job = job.save().fetch(&mut transaction).await?;
+
+        let args = job.payload()?;
+        let context = JobContext::new(&job, pool.clone());
+        assert_eq!(context.attempt(), 2);
+        handler.execute(&context, args).await?;
+        assert_eq!(handler.runs.load(Ordering::SeqCst), 2);
+
+        job.completed_at = Some(OffsetDateTime::now_utc());
+        job.attempts += 1;
+        job.save().execute(&mut transaction).await?;
+
+        let next = JobModel::next()
+            .filter("name", name)
+            .fetch_optional(&mut transaction)
+            .await?;
+        assert!(next.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_extends_visibility() -> Result<(), Error> {
+        let pool = Pool::from_env();
+        let mut transaction = pool.transaction().await?;
+
+        let job = running_job(&mut transaction, "test_checkpoint_extends_visibility").await?;
+
+        let job = JobModel::checkpoint(job.id.unwrap())
+            .fetch(&mut transaction)
+            .await?;
+        assert!(job.checkpoint_at.is_some());
+
+        let reclaimed = JobModel::reclaim(time::Duration::minutes(5))
+            .fetch_all(&mut transaction)
+            .await?;
+        assert!(reclaimed.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tampered_job_rejected() -> Result<(), Error> {
+        let pool = Pool::from_env();
+        let mut transaction = pool.transaction().await?;
+        bootstrap(&mut transaction).await?;
+
+        let name = "test_tampered_job_rejected";
+        let args = json!({"user_id": 5});
+
+        let job = JobModel::new(name, args.clone())
+            .queue(&mut transaction)
+            .await?;
+        let id = job.id.unwrap();
+        let encrypted = JobModel::new(name, args.clone())
+            .encrypt()?
+            .queue(&mut transaction)
+            .await?;
+
+        // Signed row copied into a new job.
+        transaction
+            .client()
+            .execute(
+                "INSERT INTO rwf_jobs (name, args, signature)
+                SELECT name, args, signature FROM rwf_jobs WHERE id = $1",
+                &[&id],
+            )
+            .await?;
+
+        // Arguments changed.
+        transaction
+            .client()
+            .execute(
+                "UPDATE rwf_jobs SET args = '{\"user_id\": 6}' WHERE id = $1",
+                &[&id],
+            )
+            .await?;
+        transaction
+            .client()
+            .execute(
+                "UPDATE rwf_jobs SET encrypted = false WHERE id = $1",
+                &[&encrypted.id],
+            )
+            .await?;
+
+        // Not signed.
+        transaction
+            .client()
+            .execute("INSERT INTO rwf_jobs (name) VALUES ($1)", &[&name])
+            .await?;
+
+        // Queued before jobs were signed.
+        transaction
+            .client()
+            .execute(
+                "INSERT INTO rwf_jobs (name, args, legacy) VALUES ($1, '{\"user_id\": 5}', true)",
+                &[&name],
+            )
+            .await?;
+
+        let jobs = JobModel::filter("name", name)
+            .order("id")
+            .fetch_all(&mut transaction)
+            .await?;
+        assert_eq!(jobs.len(), 5);
+
+        let (tampered, legacy) = jobs.split_at(4);
+        assert_eq!(legacy[0].payload()?, args);
+
+        for job in tampered {
+            let err = job.payload().unwrap_err();
+            assert!(matches!(err, Error::InvalidSignature));
+            reject(job.clone(), &err, &mut transaction).await?;
+        }
+
+        // Rejected jobs are dead.
+        let next = JobModel::next()
+            .filter("name", name)
+            .fetch_all(&mut transaction)
+            .await?;
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].id, legacy[0].id);
+
+        Ok(())
+    }
+}
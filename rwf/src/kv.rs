@@ -0,0 +1,657 @@
+//! Embedded key-value store, for apps that run without Postgres or Redis.
+//!
+//! The store is a single file. Every change is appended to it, and all keys and values are kept in memory,
+//! so it's meant for small amounts of data, like rate limit counters and webhook identifiers. Keys can expire
+//! after a TTL. When most of the file is made of old values, it's rewritten with only the current ones.
+//!
+//! ```
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! # let dir = tempdir::TempDir::new("kv").unwrap();
+//! # let path = dir.path().join("rwf.kv");
+//! use rwf::kv::KvStore;
+//! use std::time::Duration;
+//!
+//! let store = KvStore::open(&path).unwrap();
+//!
+//! store.set("greeting", "hello", Some(Duration::from_secs(60))).unwrap();
+//! assert_eq!(store.get("greeting"), Some(b"hello".to_vec()));
+//! # });
+//! ```
+//!
+//! The store implements [`RateLimitStore`](crate::controller::middleware::rate_limiter::RateLimitStore)
+//! and [`ReplayStore`](crate::controller::middleware::replay_guard::ReplayStore). Requires the `kv` feature.
+//!
+//! ### Durability
+//!
+//! Each change is written to the file right away. When the data is flushed to disk is set with [`Fsync`].
+//! Changes that were only partially written when the app crashed are discarded when the file is opened again.
+//!
+//! ### Concurrency
+//!
+//! The store can be shared between tasks. Opening the same file again in the same process returns the same store,
+//! and other processes can't open it while it's open.
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Size of the record header: checksum and payload length.
+const HEADER: usize = 8;
+
+/// Compact the file when it has at least this many old records, and more old records than keys.
+const COMPACT_MIN_GARBAGE: usize = 1_000;
+
+/// How often maintenance runs.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+const SET: u8 = 0;
+const REMOVE: u8 = 1;
+
+/// Stores opened by this process, so each file is opened only once.
+static OPEN: Lazy<Mutex<HashMap<PathBuf, Weak<Inner>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Key-value store error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("\"{0}\" is used by another process")]
+    Locked(PathBuf),
+}
+
+/// When changes are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fsync {
+    /// After every change. Changes are never lost, but writes are slower.
+    Always,
+    /// Every second. If the machine crashes, the changes of the last second can be lost.
+    #[default]
+    Periodic,
+    /// When the operating system decides. Changes survive the app crashing, but not the machine.
+    Never,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Vec<u8>,
+    /// Milliseconds since the UNIX epoch, `0` if the key doesn't expire.
+    expires_at: u64,
+}
+
+impl Entry {
+    fn expired(&self, now: u64) -> bool {
+        self.expires_at != 0 && self.expires_at <= now
+    }
+}
+
+struct State {
+    file: File,
+    entries: HashMap<String, Entry>,
+    /// Records in the file which are no longer current.
+    garbage: usize,
+    /// Changes not flushed to disk yet.
+    dirty: bool,
+    fsync: Fsync,
+}
+
+struct Inner {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+/// Embedded key-value store.
+///
+/// Cheap to clone; clones share the same file.
+#[derive(Clone)]
+pub struct KvStore {
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for KvStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KvStore")
+            .field("path", &self.inner.path)
+            .finish()
+    }
+}
+
+impl KvStore {
+    /// Open the store in the file, creating it if it doesn't exist.
+    ///
+    /// If the store is already open in this process, the same store is returned. Maintenance,
+    /// which flushes and compacts the file, runs in the background if the store is opened in a Tokio runtime.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut open = OPEN.lock();
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path.as_ref())?;
+        let path = path.as_ref().canonicalize()?;
+
+        if let Some(inner) = open.get(&path).and_then(|inner| inner.upgrade()) {
+            return Ok(Self { inner });
+        }
+
+        if file.try_lock().is_err() {
+            return Err(Error::Locked(path));
+        }
+
+        let (entries, garbage) = load(&file, &path)?;
+
+        let inner = Arc::new(Inner {
+            path: path.clone(),
+            state: Mutex::new(State {
+                file,
+                entries,
+                garbage,
+                dirty: false,
+                fsync: Fsync::default(),
+            }),
+        });
+
+        open.retain(|_, inner| inner.strong_count() > 0);
+        open.insert(path, Arc::downgrade(&inner));
+
+        if tokio::runtime::Handle::try_current().is_ok() {
+            let maintenance = Arc::downgrade(&inner);
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(MAINTENANCE_INTERVAL).await;
+
+                    match maintenance.upgrade() {
+                        Some(inner) => KvStore { inner }.maintenance(),
+                        None => break,
+                    }
+                }
+            });
+        }
+
+        Ok(Self { inner })
+    }
+
+    /// When changes are flushed to disk, [`Fsync::Periodic`] by default. Applies to all handles
+    /// of the store.
+    pub fn fsync(self, fsync: Fsync) -> Self {
+        self.inner.state.lock().fsync = fsync;
+        self
+    }
+
+    /// Path of the file.
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Get the value of the key, unless it doesn't exist or has expired.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let state = self.inner.state.lock();
+        state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.expired(now()))
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Set the value of the key. The key is removed after the TTL, if set.
+    pub fn set(
+        &self,
+        key: &str,
+        value: impl AsRef<[u8]>,
+        ttl: Option<Duration>,
+    ) -> Result<(), Error> {
+        let mut state = self.inner.state.lock();
+        state.set(key, value.as_ref().to_vec(), expires_at(ttl))
+    }
+
+    /// Set the value of the key only if it doesn't exist or has expired. Returns `false` if it exists.
+    pub fn insert(
+        &self,
+        key: &str,
+        value: impl AsRef<[u8]>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, Error> {
+        let mut state = self.inner.state.lock();
+
+        if let Some(entry) = state.entries.get(key) {
+            if !entry.expired(now()) {
+                return Ok(false);
+            }
+        }
+
+        state.set(key, value.as_ref().to_vec(), expires_at(ttl))?;
+        Ok(true)
+    }
+
+    /// Replace the value of the key with the value returned by the function, which gets the current value,
+    /// or `None` if the key doesn't exist or has expired. Returns the new value.
+    ///
+    /// No other change to the store can happen while the function runs.
+    pub fn update(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        f: impl FnOnce(Option<&[u8]>) -> Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut state = self.inner.state.lock();
+        let now = now();
+
+        let current = state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.expired(now))
+            .map(|entry| entry.value.as_slice());
+        let value = f(current);
+
+        state.set(key, value.clone(), expires_at(ttl))?;
+        Ok(value)
+    }
+
+    /// Remove the key.
+    pub fn remove(&self, key: &str) -> Result<(), Error> {
+        let mut state = self.inner.state.lock();
+
+        if state.entries.remove(key).is_some() {
+            state.write(&record(REMOVE, key, &[], 0))?;
+            // The removed value and the removal itself.
+            state.garbage += 2;
+        }
+
+        Ok(())
+    }
+
+    /// Number of keys, including expired ones which haven't been removed yet.
+    pub fn len(&self) -> usize {
+        self.inner.state.lock().entries.len()
+    }
+
+    /// The store has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Flush changes to disk.
+    pub fn flush(&self) -> Result<(), Error> {
+        let mut state = self.inner.state.lock();
+        state.file.sync_data()?;
+        state.dirty = false;
+        Ok(())
+    }
+
+    /// Rewrite the file with only the current values. Done automatically by maintenance
+    /// when most of the file is made of old values.
+    ///
+    /// The new file is written next to the old one, and replaces it only once it's complete,
+    /// so a crash during compaction doesn't lose anything.
+    pub fn compact(&self) -> Result<(), Error> {
+        let mut state = self.inner.state.lock();
+        let now = now();
+        let path = &self.inner.path;
+
+        state.entries.retain(|_, entry| !entry.expired(now));
+
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".compact");
+        let tmp = PathBuf::from(tmp);
+
+        {
+            let mut file = File::create(&tmp)?;
+            let mut buffer = vec![];
+            for (key, entry) in &state.entries {
+                buffer.extend(record(SET, key, &entry.value, entry.expires_at));
+            }
+            file.write_all(&buffer)?;
+            file.sync_all()?;
+        }
+
+        std::fs::rename(&tmp, path)?;
+        sync_dir(path)?;
+
+        let file = OpenOptions::new().read(true).append(true).open(path)?;
+        if file.try_lock().is_err() {
+            return Err(Error::Locked(path.clone()));
+        }
+
+        state.file = file;
+        state.garbage = 0;
+        state.dirty = false;
+
+        Ok(())
+    }
+
+    /// Remove expired keys, flush changes if [`Fsync::Periodic`] is used, and compact the file if needed.
+    fn maintenance(&self) {
+        let compact = {
+            let mut state = self.inner.state.lock();
+            let now = now();
+
+            let before = state.entries.len();
+            state.entries.retain(|_, entry| !entry.expired(now));
+            state.garbage += before - state.entries.len();
+
+            if state.dirty && state.fsync == Fsync::Periodic {
+                match state.file.sync_data() {
+                    Ok(()) => state.dirty = false,
+                    Err(err) => error!("kv store \"{}\": {}", self.inner.path.display(), err),
+                }
+            }
+
+            state.garbage >= COMPACT_MIN_GARBAGE && state.garbage > state.entries.len()
+        };
+
+        if compact {
+            if let Err(err) = self.compact() {
+                error!(
+                    "kv store \"{}\" compaction failed: {}",
+                    self.inner.path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+impl State {
+    fn set(&mut self, key: &str, value: Vec<u8>, expires_at: u64) -> Result<(), Error> {
+        self.write(&record(SET, key, &value, expires_at))?;
+
+        if self
+            .entries
+            .insert(key.to_string(), Entry { value, expires_at })
+            .is_some()
+        {
+            self.garbage += 1;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<(), Error> {
+        self.file.write_all(record)?;
+
+        match self.fsync {
+            Fsync::Always => self.file.sync_data()?,
+            Fsync::Periodic => self.dirty = true,
+            Fsync::Never => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Encode a change: checksum and length of the payload, followed by the operation,
+/// expiration, key length, key and value.
+fn record(op: u8, key: &str, value: &[u8], expires_at: u64) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(13 + key.len() + value.len());
+    payload.push(op);
+    payload.extend(expires_at.to_le_bytes());
+    payload.extend((key.len() as u32).to_le_bytes());
+    payload.extend(key.as_bytes());
+    payload.extend(value);
+
+    let mut record = Vec::with_capacity(HEADER + payload.len());
+    record.extend(crc32fast::hash(&payload).to_le_bytes());
+    record.extend((payload.len() as u32).to_le_bytes());
+    record.extend(payload);
+    record
+}
+
+/// Read all changes from the file. A record which is incomplete or doesn't match its checksum
+/// was being written when the app stopped; it and everything after it are removed.
+///
+/// Returns the current values and the number of old records.
+fn load(mut file: &File, path: &Path) -> Result<(HashMap<String, Entry>, usize), Error> {
+    let mut bytes = vec![];
+    file.read_to_end(&mut bytes)?;
+
+    let now = now();
+    let mut entries = HashMap::new();
+    let mut garbage = 0;
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let payload = match parse(&bytes[offset..]) {
+            Some(payload) => payload,
+            None => break,
+        };
+
+        let op = payload[0];
+        let expires_at = u64::from_le_bytes(payload[1..9].try_into().unwrap());
+        let key_len = u32::from_le_bytes(payload[9..13].try_into().unwrap()) as usize;
+        let key = match payload
+            .get(13..13 + key_len)
+            .and_then(|key| std::str::from_utf8(key).ok())
+        {
+            Some(key) => key.to_string(),
+            None => break,
+        };
+        let value = payload[13 + key_len..].to_vec();
+
+        offset += HEADER + payload.len();
+
+        let replaced = match op {
+            SET => entries.insert(key, Entry { value, expires_at }),
+            _ => {
+                garbage += 1;
+                entries.remove(&key)
+            }
+        };
+
+        if replaced.is_some() {
+            garbage += 1;
+        }
+    }
+
+    if offset < bytes.len() {
+        warn!(
+            "kv store \"{}\" has an incomplete record at byte {}, discarding {} bytes",
+            path.display(),
+            offset,
+            bytes.len() - offset
+        );
+        file.set_len(offset as u64)?;
+        file.sync_all()?;
+    }
+
+    let before = entries.len();
+    entries.retain(|_, entry: &mut Entry| !entry.expired(now));
+    garbage += before - entries.len();
+
+    Ok((entries, garbage))
+}
+
+/// Get the payload of the record at the start of the bytes, if it's complete and valid.
+fn parse(bytes: &[u8]) -> Option<&[u8]> {
+    let checksum = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?);
+    let len = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?) as usize;
+    let payload = bytes.get(HEADER..HEADER + len)?;
+
+    if payload.len() < 13 || crc32fast::hash(payload) != checksum {
+        return None;
+    }
+
+    Some(payload)
+}
+
+/// Make the rename of the file durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn expires_at(ttl: Option<Duration>) -> u64 {
+    match ttl {
+        Some(ttl) => now() + (ttl.as_millis() as u64).max(1),
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_kv_store() {
+        let dir = TempDir::new("kv").unwrap();
+        let path = dir.path().join("rwf.kv");
+
+        {
+            let store = KvStore::open(&path).unwrap().fsync(Fsync::Always);
+            store.set("a", "1", None).unwrap();
+            store.set("a", "2", None).unwrap();
+            store.set("b", "3", None).unwrap();
+            store.remove("b").unwrap();
+            store.set("c", "4", Some(Duration::from_millis(1))).unwrap();
+            assert!(!store.insert("a", "5", None).unwrap());
+            assert!(store.insert("d", "6", None).unwrap());
+            assert_eq!(
+                store
+                    .update("d", None, |value| {
+                        let mut value = value.unwrap().to_vec();
+                        value.push(b'7');
+                        value
+                    })
+                    .unwrap(),
+                b"67"
+            );
+
+            // Same file, same store.
+            let again = KvStore::open(&path).unwrap();
+            assert_eq!(again.get("a"), Some(b"2".to_vec()));
+
+            std::thread::sleep(Duration::from_millis(5));
+            assert_eq!(store.get("c"), None);
+            assert!(store.insert("c", "8", None).unwrap());
+        }
+
+        // Everything is read back from the file.
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get("a"), Some(b"2".to_vec()));
+        assert_eq!(store.get("b"), None);
+        assert_eq!(store.get("c"), Some(b"8".to_vec()));
+        assert_eq!(store.get("d"), Some(b"67".to_vec()));
+        assert_eq!(store.len(), 3);
+    }
+
+    #[test]
+    fn test_incomplete_record() {
+        let dir = TempDir::new("kv").unwrap();
+        let path = dir.path().join("rwf.kv");
+
+        {
+            let store = KvStore::open(&path).unwrap();
+            store.set("a", "1", None).unwrap();
+            store.set("b", "2", None).unwrap();
+        }
+
+        // The app crashed while writing the last record.
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 1).unwrap();
+        drop(file);
+
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get("a"), Some(b"1".to_vec()));
+        assert_eq!(store.get("b"), None);
+
+        // The file can be written again.
+        store.set("c", "3", None).unwrap();
+        drop(store);
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get("c"), Some(b"3".to_vec()));
+
+        // Corrupted bytes are detected by the checksum.
+        drop(store);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get("a"), Some(b"1".to_vec()));
+        assert_eq!(store.get("c"), None);
+    }
+
+    #[test]
+    fn test_compact() {
+        let dir = TempDir::new("kv").unwrap();
+        let path = dir.path().join("rwf.kv");
+        let store = KvStore::open(&path).unwrap().fsync(Fsync::Never);
+
+        for i in 0..100 {
+            store.set("counter", i.to_string(), None).unwrap();
+        }
+        store
+            .set("expired", "x", Some(Duration::from_millis(1)))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        let before = std::fs::metadata(&path).unwrap().len();
+        store.compact().unwrap();
+        let after = std::fs::metadata(&path).unwrap().len();
+        assert!(after < before / 50, "{} -> {}", before, after);
+
+        // Writes go to the new file.
+        store.set("name", "rwf", None).unwrap();
+        drop(store);
+
+        let store = KvStore::open(&path).unwrap();
+        assert_eq!(store.get("counter"), Some(b"99".to_vec()));
+        assert_eq!(store.get("name"), Some(b"rwf".to_vec()));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_concurrent() {
+        let dir = TempDir::new("kv").unwrap();
+        let store = KvStore::open(dir.path().join("rwf.kv")).unwrap();
+
+        let threads = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        store
+                            .update("count", None, |value| {
+                                let count = value
+                                    .map(|value| u64::from_le_bytes(value.try_into().unwrap()))
+                                    .unwrap_or(0);
+                                (count + 1).to_le_bytes().to_vec()
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(store.get("count"), Some(800u64.to_le_bytes().to_vec()));
+    }
+}
@@ -81,19 +81,32 @@
 //! ```
 //!
 pub mod analytics;
+pub mod app;
 pub mod colors;
 pub mod comms;
 pub mod config;
 pub mod controller;
 pub mod crypto;
+pub mod doctor;
 pub mod error;
+pub mod errors;
+pub mod flags;
+pub mod generate;
+pub mod health;
 pub mod hmr;
 pub mod http;
 pub mod job;
+#[cfg(feature = "kv")]
+pub mod kv;
 pub mod lock;
 pub mod logging;
 pub mod model;
+#[cfg(feature = "oauth")]
+pub mod oauth;
 pub mod prelude;
+pub mod shutdown;
+pub mod testing;
+pub mod timezone;
 pub mod view;
 
 /// Wrapper around async traits to make them easy to use.
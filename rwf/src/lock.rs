@@ -1,7 +1,7 @@
 //! Distributed locking primitives.
 //!
 //! Status: work in progress, do not use.
-use crate::model::{Column, Error, FromRow, Model, Pool, ToValue, Value};
+use crate::model::{Column, Error, FromRow, GetColumn, Model, Pool, ToValue, Value};
 use time::OffsetDateTime;
 
 #[derive(Clone)]
@@ -16,11 +16,11 @@ pub struct Lock {
 impl FromRow for Lock {
     fn from_row(row: tokio_postgres::Row) -> Result<Lock, Error> {
         Ok(Lock {
-            id: row.try_get("id")?,
-            name: row.try_get("name")?,
-            created_at: row.try_get("created_at")?,
-            expires_at: row.try_get("expires_at")?,
-            database_now: row.try_get("database_now")?,
+            id: row.get_column("id")?,
+            name: row.get_column("name")?,
+            created_at: row.get_column("created_at")?,
+            expires_at: row.get_column("expires_at")?,
+            database_now: row.get_column("database_now")?,
         })
     }
 }
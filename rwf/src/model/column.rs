@@ -58,6 +58,16 @@ impl Column {
         Self::new("", column_name)
     }
 
+    /// Name of the column, without the table name.
+    pub fn column_name(&self) -> &str {
+        &self.column_name
+    }
+
+    /// The column is in this table, or isn't qualified with a table name.
+    pub(crate) fn in_table(&self, table_name: &str) -> bool {
+        !self.qualified() || self.table_name == table_name
+    }
+
     pub fn qualified(&self) -> bool {
         !self.table_name.is_empty()
     }
@@ -0,0 +1,73 @@
+//! Running queries on a database other than the model's own.
+//!
+//! Each model is stored in one database, returned by [`Model::database`]. Queries for the model
+//! are checked against the database of the connection they run on, so a query sent to the wrong database
+//! fails with a clear error instead of a missing table. To run a query on another database, e.g. a replica
+//! with the same schema, use [`Query::on`]:
+//!
+//! ```ignore
+//! let mut conn = Pool::named("replica")?.get().await?;
+//!
+//! let users = User::all()
+//!     .on("replica")
+//!     .fetch_all(&mut conn)
+//!     .await?;
+//! ```
+use super::{ConnectionGuard, Error, Model, Query};
+
+/// Query which runs on a specific database. Created with [`Query::on`].
+///
+/// Only this query is checked against the database. Queries it runs for other models,
+/// e.g. to load associations, are still checked against their own.
+#[derive(Debug, Clone)]
+pub struct On<T: Model> {
+    query: Query<T>,
+    database: String,
+}
+
+impl<T: Model> On<T> {
+    pub(crate) fn new(query: Query<T>, database: impl ToString) -> Self {
+        Self {
+            query,
+            database: database.to_string(),
+        }
+    }
+
+    /// Name of the database the query runs on.
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// See [`Query::fetch`].
+    pub async fn fetch(self, conn: &mut ConnectionGuard) -> Result<T, Error> {
+        match self.execute(conn).await?.into_iter().next() {
+            Some(row) => Ok(row),
+            None => Err(Error::RecordNotFound),
+        }
+    }
+
+    /// See [`Query::fetch_optional`].
+    pub async fn fetch_optional(self, conn: &mut ConnectionGuard) -> Result<Option<T>, Error> {
+        Ok(self.execute(conn).await?.into_iter().next())
+    }
+
+    /// See [`Query::fetch_all`].
+    pub async fn fetch_all(self, conn: &mut ConnectionGuard) -> Result<Vec<T>, Error> {
+        self.execute(conn).await
+    }
+
+    /// See [`Query::execute`].
+    pub async fn execute(self, conn: &mut ConnectionGuard) -> Result<Vec<T>, Error> {
+        self.query.execute_on(conn, &self.database).await
+    }
+
+    /// See [`Query::count`].
+    pub async fn count(self, conn: &mut ConnectionGuard) -> Result<i64, Error> {
+        self.query.count_on(conn, &self.database).await
+    }
+
+    /// See [`Query::exists`].
+    pub async fn exists(self, conn: &mut ConnectionGuard) -> Result<bool, Error> {
+        Ok(self.count(conn).await? > 0)
+    }
+}
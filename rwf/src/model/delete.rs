@@ -0,0 +1,71 @@
+//! Implements the `DELETE` statement.
+use super::{Escape, FromRow, Model, Placeholders, Select, ToSql, WhereClause};
+use std::marker::PhantomData;
+
+/// `DELETE` statement, built from the `WHERE` clause of a `SELECT` query.
+#[derive(Debug, Clone)]
+pub struct Delete<T> {
+    table_name: String,
+    pub placeholders: Placeholders,
+    where_clause: WhereClause,
+    returning: bool,
+    allow_full_table: bool,
+    marker: PhantomData<T>,
+}
+
+impl<T: Model> Delete<T> {
+    pub fn empty() -> Self {
+        Self {
+            table_name: T::table_name().to_string(),
+            placeholders: Placeholders::new(),
+            where_clause: WhereClause::default(),
+            returning: true,
+            allow_full_table: false,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Delete<T> {
+    /// Allow deleting all rows in the table if the statement has no `WHERE` clause.
+    pub fn allow_full_table(mut self) -> Self {
+        self.allow_full_table = true;
+        self
+    }
+
+    /// The statement would delete all rows in the table, without being allowed to.
+    pub fn full_table(&self) -> bool {
+        self.where_clause.is_empty() && !self.allow_full_table
+    }
+
+    /// Don't return the deleted rows.
+    pub fn without_returning(mut self) -> Self {
+        self.returning = false;
+        self
+    }
+
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+}
+
+impl<T: Model> From<Select<T>> for Delete<T> {
+    fn from(select: Select<T>) -> Delete<T> {
+        let mut delete = Delete::empty();
+        delete.where_clause = select.where_clause;
+        delete.placeholders = select.placeholders;
+
+        delete
+    }
+}
+
+impl<T: FromRow> ToSql for Delete<T> {
+    fn to_sql(&self) -> String {
+        format!(
+            r#"DELETE FROM "{}"{}{}"#,
+            self.table_name.escape(),
+            self.where_clause.to_sql(),
+            if self.returning { " RETURNING *" } else { "" },
+        )
+    }
+}
@@ -0,0 +1,136 @@
+//! Encryption of model attributes at rest.
+//!
+//! Columns storing sensitive data, e.g. access tokens, can be encrypted by the application before they are
+//! sent to the database, and decrypted when records are loaded:
+//!
+//! ```
+//! # use rwf::prelude::*;
+//! #[derive(Clone, macros::Model)]
+//! struct User {
+//!     id: Option<i64>,
+//!     email: String,
+//!     #[model(encrypted)]
+//!     access_token: Option<String>,
+//!     #[model(encrypted, deterministic)]
+//!     ssn: String,
+//! }
+//! ```
+//!
+//! Encrypted columns are stored as text, encrypted with the application secret key (see [`crate::crypto`]).
+//! Only `String` and `Option<String>` attributes can be encrypted.
+//!
+//! By default, encrypting the same value twice produces different ciphertexts, so encrypted columns can't be searched.
+//! Columns marked as `deterministic` always produce the same ciphertext for the same value, and can be used in filters,
+//! e.g. `User::find_by("ssn", "123-45-6789")`: the value is encrypted before it's sent to the database. This reveals
+//! which rows have equal values to anyone with access to the database, so use it only for columns that need lookups.
+//!
+//! Rows encrypted with a previous secret key remain readable after the key is rotated. Deterministic lookups only
+//! match rows encrypted with the current key, so rows should be saved again after a rotation.
+use tokio_postgres::Row;
+
+use super::{Error, GetColumn, Value};
+use crate::crypto;
+
+/// How an attribute is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encryption {
+    /// Encrypting the same value produces a different ciphertext each time.
+    Random,
+    /// Encrypting the same value always produces the same ciphertext, allowing equality lookups.
+    Deterministic,
+}
+
+/// Encrypt a string value. Other values, e.g. `NULL`, are returned unchanged.
+pub fn encrypt_value(value: Value, encryption: Encryption) -> Value {
+    match value {
+        Value::String(plaintext) => {
+            let ciphertext = match encryption {
+                Encryption::Random => crypto::encrypt(plaintext.as_bytes()),
+                Encryption::Deterministic => crypto::encrypt_deterministic(plaintext.as_bytes()),
+            };
+
+            Value::String(ciphertext.expect("attribute encryption"))
+        }
+
+        Value::Optional(value) => Value::Optional(Box::new(
+            value.map(|value| encrypt_value(value, encryption)),
+        )),
+
+        Value::List(values) => Value::List(
+            values
+                .into_iter()
+                .map(|value| encrypt_value(value, encryption))
+                .collect(),
+        ),
+
+        value => value,
+    }
+}
+
+/// Type of an attribute which can be encrypted.
+pub trait Decrypt: Sized {
+    /// Create the attribute from the decrypted column value, `None` if the column is `NULL`.
+    fn from_plaintext(plaintext: Option<String>) -> Option<Self>;
+}
+
+impl Decrypt for String {
+    fn from_plaintext(plaintext: Option<String>) -> Option<Self> {
+        plaintext
+    }
+}
+
+impl Decrypt for Option<String> {
+    fn from_plaintext(plaintext: Option<String>) -> Option<Self> {
+        Some(plaintext)
+    }
+}
+
+/// Read and decrypt an encrypted column from a row.
+pub fn decrypt_column<T: Decrypt>(row: &Row, column: &str) -> Result<T, Error> {
+    let error = |error| Error::Decrypt {
+        column: column.to_string(),
+        error,
+    };
+
+    let plaintext = match row.get_column::<Option<String>>(column)? {
+        Some(ciphertext) => {
+            let bytes = crypto::decrypt(&ciphertext).map_err(error)?;
+            Some(
+                String::from_utf8(bytes)
+                    .map_err(|_| error(crypto::Error::Generic("plaintext is not valid UTF-8")))?,
+            )
+        }
+        None => None,
+    };
+
+    T::from_plaintext(plaintext).ok_or_else(|| error(crypto::Error::Generic("column is NULL")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::ToValue;
+
+    #[test]
+    fn test_encrypt_value() {
+        let deterministic = encrypt_value(Value::from("secret"), Encryption::Deterministic);
+        assert_eq!(
+            deterministic,
+            encrypt_value(Value::from("secret"), Encryption::Deterministic)
+        );
+        assert_ne!(
+            encrypt_value(Value::from("secret"), Encryption::Random),
+            encrypt_value(Value::from("secret"), Encryption::Random)
+        );
+
+        match deterministic {
+            Value::String(ciphertext) => {
+                assert_eq!(crypto::decrypt(&ciphertext).unwrap(), b"secret")
+            }
+            value => panic!("unexpected value: {:?}", value),
+        }
+
+        assert_eq!(encrypt_value(Value::Null, Encryption::Random), Value::Null);
+        assert!(encrypt_value(None::<String>.to_value(), Encryption::Random).is_null());
+    }
+}
@@ -2,8 +2,12 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
 use thiserror::Error;
+use tokio_postgres::error::SqlState;
+
+use std::time::Duration;
 
 use super::Value;
+use crate::config::get_config;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -22,9 +26,52 @@ pub enum Error {
     #[error("{0}: {1}")]
     ValueError(&'static str, String),
 
+    #[error("database \"{0}\" is not configured")]
+    UnknownDatabase(String),
+
+    #[error("{model} is stored in database \"{expected}\", but the connection is to database \"{actual}\"")]
+    WrongDatabase {
+        model: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("transaction on database \"{actual}\" can't query {model}, which is stored in database \"{expected}\"; transactions can't span databases")]
+    CrossDatabaseTransaction {
+        model: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("column \"{column}\" can't be decrypted: {error}")]
+    Decrypt {
+        column: String,
+        error: crate::crypto::Error,
+    },
+
+    #[error("refusing to {statement} all rows in \"{table}\" without a WHERE clause, chain .allow_full_table() to allow it")]
+    FullTable {
+        statement: &'static str,
+        table: String,
+    },
+
+    #[error("{model} can't be ordered by \"{column}\", which isn't one of its columns; use .unchecked() for columns of joined tables or computed by the query")]
+    UnknownOrderColumn { model: String, column: String },
+
+    #[error(
+        "pagination cursor has {actual} values, but the query is ordered by {expected} columns"
+    )]
+    InvalidCursor { expected: usize, actual: usize },
+
     #[error("pool timeout")]
     PoolTimeout,
 
+    #[error("database \"{database}\" is unavailable")]
+    DatabaseUnavailable {
+        database: String,
+        retry_after: Duration,
+    },
+
     #[error("pool not configured")]
     PoolNotConfigured,
 
@@ -40,6 +87,12 @@ pub enum Error {
     #[error("migration error: \"{0}\"")]
     MigrationError(String),
 
+    #[error("migrations modified after they were applied: {}", .0.join(", "))]
+    MigrationsModified(Vec<String>),
+
+    #[error("database schema doesn't match the schema file:\n{}", .0.join("\n"))]
+    SchemaDrift(Vec<String>),
+
     #[error("io error: \"{0}\"")]
     IoError(#[from] std::io::Error),
 
@@ -47,12 +100,297 @@ pub enum Error {
         "column \"{0}\" is missing from the row returned by the database,\ndid you forget to specify it in the query?"
     )]
     Column(String),
+
+    #[error("{0}")]
+    FromRow(Box<FromRowError>),
 }
 
 impl Error {
     pub fn boxed(self) -> Box<Self> {
         Box::new(self)
     }
+
+    /// What went wrong in the database, e.g. a unique constraint was violated.
+    /// Errors which didn't come from the database are [`DatabaseErrorKind::Other`].
+    ///
+    /// ```rust,ignore
+    /// match User::create(&[("email", email)]).fetch(&mut conn).await {
+    ///     Err(err) if matches!(err.kind(), DatabaseErrorKind::UniqueViolation { .. }) => {
+    ///         // Email is already taken.
+    ///     }
+    ///     // ...
+    /// }
+    /// ```
+    pub fn kind(&self) -> DatabaseErrorKind {
+        match self {
+            Error::DatabaseError(error) => match error.as_db_error() {
+                Some(db_error) => DatabaseErrorKind::classify(
+                    db_error.code(),
+                    db_error.table(),
+                    db_error.constraint(),
+                    db_error.column(),
+                    db_error.detail(),
+                ),
+                // The database couldn't be reached, or the socket failed.
+                None if error.is_closed()
+                    || std::error::Error::source(error)
+                        .is_some_and(|source| source.is::<std::io::Error>()) =>
+                {
+                    DatabaseErrorKind::ConnectionLost
+                }
+                None => DatabaseErrorKind::Other,
+            },
+            Error::PoolTimeout => DatabaseErrorKind::Timeout,
+            Error::DatabaseUnavailable { .. } => DatabaseErrorKind::ConnectionLost,
+            _ => DatabaseErrorKind::Other,
+        }
+    }
+
+    /// Can the transaction which returned this error be retried? See [`DatabaseErrorKind::is_retryable`].
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+
+    /// How long to wait before trying again, if the database couldn't be reached.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::DatabaseUnavailable { retry_after, .. } => Some(*retry_after),
+            error if error.kind() == DatabaseErrorKind::ConnectionLost => Some(
+                get_config()
+                    .database
+                    .circuit_breaker_cooldown()
+                    .unsigned_abs(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Add the query to the error, if it happened while reading a row.
+    pub fn with_sql(self, sql: impl ToString) -> Self {
+        match self {
+            Error::FromRow(mut error) if error.sql.is_none() => {
+                error.sql = Some(sql.to_string());
+                Error::FromRow(error)
+            }
+            error => error,
+        }
+    }
+}
+
+/// A column couldn't be read from a row returned by the database.
+#[derive(Debug)]
+pub struct FromRowError {
+    /// Column name.
+    pub column: String,
+    /// Rust type the column was read into.
+    pub rust_type: &'static str,
+    /// Postgres type of the column, `None` if the column isn't in the row.
+    pub pg_type: Option<String>,
+    /// Error returned by the Postgres driver.
+    pub reason: String,
+    /// The query which returned the row, if known.
+    pub sql: Option<String>,
+}
+
+impl std::fmt::Display for FromRowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.pg_type {
+            Some(ref pg_type) => write!(
+                f,
+                "column \"{}\" of type \"{}\" can't be read as {}: {}",
+                self.column, pg_type, self.rust_type, self.reason
+            )?,
+            None => write!(
+                f,
+                "column \"{}\" is missing from the row returned by the database, did you forget to select it?",
+                self.column
+            )?,
+        }
+
+        if let Some(ref sql) = self.sql {
+            write!(f, "\nquery: {}", sql)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Classification of an error returned by the database, based on its `SQLSTATE` code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatabaseErrorKind {
+    /// A row with the same value already exists.
+    UniqueViolation {
+        /// Name of the unique constraint or index.
+        constraint: Option<String>,
+        /// Column with the duplicate value, if the constraint covers one column.
+        column_hint: Option<String>,
+    },
+    /// The row references a row that doesn't exist, or is referenced by rows that still exist.
+    ForeignKeyViolation {
+        /// Name of the foreign key constraint.
+        constraint: Option<String>,
+    },
+    /// A `NOT NULL` column is missing a value.
+    NotNullViolation {
+        /// Name of the column.
+        column: Option<String>,
+    },
+    /// A `CHECK` constraint failed.
+    CheckViolation {
+        /// Name of the check constraint.
+        constraint: Option<String>,
+    },
+    /// A concurrent transaction changed the same rows, or a deadlock was detected.
+    /// The transaction can be retried.
+    SerializationFailure,
+    /// The connection to the database was closed, or couldn't be opened.
+    ConnectionLost,
+    /// The query was canceled by `statement_timeout` or `lock_timeout`, or no connection was
+    /// available in the pool in time.
+    Timeout,
+    /// Any other error.
+    Other,
+}
+
+/// Column names in the detail of a unique violation, e.g. `Key (email)=(user@example.com) already exists.`
+static KEY_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"^[^(]*\((.+?)\)="#).unwrap());
+
+impl DatabaseErrorKind {
+    /// Classify the error from its `SQLSTATE` code and the fields reported by the database.
+    pub(crate) fn classify(
+        code: &SqlState,
+        table: Option<&str>,
+        constraint: Option<&str>,
+        column: Option<&str>,
+        detail: Option<&str>,
+    ) -> Self {
+        let constraint = constraint.map(|constraint| constraint.to_string());
+
+        if *code == SqlState::UNIQUE_VIOLATION {
+            let column_hint = Self::unique_column(table, constraint.as_deref(), detail);
+            DatabaseErrorKind::UniqueViolation {
+                constraint,
+                column_hint,
+            }
+        } else if *code == SqlState::FOREIGN_KEY_VIOLATION {
+            DatabaseErrorKind::ForeignKeyViolation { constraint }
+        } else if *code == SqlState::NOT_NULL_VIOLATION {
+            DatabaseErrorKind::NotNullViolation {
+                column: column.map(|column| column.to_string()),
+            }
+        } else if *code == SqlState::CHECK_VIOLATION {
+            DatabaseErrorKind::CheckViolation { constraint }
+        } else if *code == SqlState::T_R_SERIALIZATION_FAILURE
+            || *code == SqlState::T_R_DEADLOCK_DETECTED
+        {
+            DatabaseErrorKind::SerializationFailure
+        } else if *code == SqlState::QUERY_CANCELED || *code == SqlState::LOCK_NOT_AVAILABLE {
+            DatabaseErrorKind::Timeout
+        } else if code.code().starts_with("08")
+            || *code == SqlState::ADMIN_SHUTDOWN
+            || *code == SqlState::CRASH_SHUTDOWN
+        {
+            DatabaseErrorKind::ConnectionLost
+        } else {
+            DatabaseErrorKind::Other
+        }
+    }
+
+    /// Column with the duplicate value. Postgres reports it in the detail of the error,
+    /// otherwise it's guessed from the default constraint name, e.g. `users_email_key`.
+    fn unique_column(
+        table: Option<&str>,
+        constraint: Option<&str>,
+        detail: Option<&str>,
+    ) -> Option<String> {
+        let columns = detail
+            .and_then(|detail| KEY_RE.captures(detail))
+            .and_then(|captures| captures.get(1))
+            .map(|columns| columns.as_str().to_string())
+            .or_else(|| {
+                let name = constraint?.strip_prefix(table?)?.strip_prefix('_')?;
+                let column = name
+                    .strip_suffix("_key")
+                    .or_else(|| name.strip_suffix("_idx"))?;
+                Some(column.to_string())
+            })?;
+
+        // Unique constraints on several columns don't point to one column.
+        if columns.contains(',') {
+            None
+        } else {
+            Some(columns.trim_matches('"').to_string())
+        }
+    }
+
+    /// Retrying the transaction can succeed. Only serialization failures and deadlocks are retryable,
+    /// see [`Pool::with_transaction_retry`](super::Pool::with_transaction_retry).
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, DatabaseErrorKind::SerializationFailure)
+    }
+
+    /// The data violates a constraint, which is usually the client's fault.
+    pub fn is_constraint_violation(&self) -> bool {
+        matches!(
+            self,
+            DatabaseErrorKind::UniqueViolation { .. }
+                | DatabaseErrorKind::ForeignKeyViolation { .. }
+                | DatabaseErrorKind::NotNullViolation { .. }
+                | DatabaseErrorKind::CheckViolation { .. }
+        )
+    }
+
+    /// Column the error is about, if known.
+    pub fn column(&self) -> Option<&str> {
+        match self {
+            DatabaseErrorKind::UniqueViolation { column_hint, .. } => column_hint.as_deref(),
+            DatabaseErrorKind::NotNullViolation { column } => column.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for DatabaseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let constraint = |constraint: &Option<String>| match constraint {
+            Some(constraint) => format!(" \"{}\"", constraint),
+            None => String::new(),
+        };
+
+        match self {
+            DatabaseErrorKind::UniqueViolation {
+                column_hint: Some(column),
+                ..
+            } => write!(f, "{} has already been taken", column),
+            DatabaseErrorKind::UniqueViolation {
+                constraint: name, ..
+            } => {
+                write!(
+                    f,
+                    "duplicate value violates unique constraint{}",
+                    constraint(name)
+                )
+            }
+            DatabaseErrorKind::ForeignKeyViolation { constraint: name } => {
+                write!(f, "violates foreign key constraint{}", constraint(name))
+            }
+            DatabaseErrorKind::NotNullViolation {
+                column: Some(column),
+            } => write!(f, "{} can't be null", column),
+            DatabaseErrorKind::NotNullViolation { column: None } => {
+                write!(f, "violates not-null constraint")
+            }
+            DatabaseErrorKind::CheckViolation { constraint: name } => {
+                write!(f, "violates check constraint{}", constraint(name))
+            }
+            DatabaseErrorKind::SerializationFailure => {
+                write!(f, "transaction conflicts with a concurrent transaction")
+            }
+            DatabaseErrorKind::ConnectionLost => write!(f, "connection to the database was lost"),
+            DatabaseErrorKind::Timeout => write!(f, "database timed out"),
+            DatabaseErrorKind::Other => write!(f, "database error"),
+        }
+    }
 }
 
 static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#""(.*)""#).unwrap());
@@ -72,3 +410,118 @@ impl From<tokio_postgres::Error> for Error {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        use DatabaseErrorKind::*;
+
+        let classify = |code: &str, constraint: Option<&str>, column: Option<&str>, detail| {
+            DatabaseErrorKind::classify(
+                &SqlState::from_code(code),
+                Some("users"),
+                constraint,
+                column,
+                detail,
+            )
+        };
+
+        assert_eq!(
+            classify(
+                "23505",
+                Some("users_email_key"),
+                None,
+                Some("Key (email)=(user@example.com) already exists.")
+            ),
+            UniqueViolation {
+                constraint: Some("users_email_key".into()),
+                column_hint: Some("email".into()),
+            }
+        );
+        // Guessed from the constraint name.
+        assert_eq!(
+            classify("23505", Some("users_email_key"), None, None).column(),
+            Some("email")
+        );
+        // Several columns.
+        assert_eq!(
+            classify(
+                "23505",
+                Some("users_org_id_email_key"),
+                None,
+                Some("Key (org_id, email)=(1, user@example.com) already exists.")
+            )
+            .column(),
+            None
+        );
+        assert_eq!(
+            classify("23503", Some("orders_user_id_fkey"), None, None),
+            ForeignKeyViolation {
+                constraint: Some("orders_user_id_fkey".into())
+            }
+        );
+        assert_eq!(
+            classify("23502", None, Some("name"), None),
+            NotNullViolation {
+                column: Some("name".into())
+            }
+        );
+        assert_eq!(
+            classify("23514", Some("age_positive"), None, None),
+            CheckViolation {
+                constraint: Some("age_positive".into())
+            }
+        );
+        assert_eq!(classify("40001", None, None, None), SerializationFailure);
+        assert_eq!(classify("40P01", None, None, None), SerializationFailure);
+        assert_eq!(classify("57014", None, None, None), Timeout);
+        assert_eq!(classify("08006", None, None, None), ConnectionLost);
+        assert_eq!(classify("57P01", None, None, None), ConnectionLost);
+        assert_eq!(classify("42P01", None, None, None), Other);
+
+        assert!(SerializationFailure.is_retryable());
+        assert!(!Timeout.is_retryable());
+        assert_eq!(
+            classify("23505", Some("users_email_key"), None, None).to_string(),
+            "email has already been taken"
+        );
+        assert_eq!(Error::PoolTimeout.kind(), Timeout);
+        assert_eq!(Error::RecordNotFound.kind(), Other);
+
+        let unavailable = Error::DatabaseUnavailable {
+            database: "main".into(),
+            retry_after: Duration::from_secs(5),
+        };
+        assert_eq!(unavailable.kind(), ConnectionLost);
+        assert_eq!(unavailable.retry_after(), Some(Duration::from_secs(5)));
+        assert_eq!(Error::RecordNotFound.retry_after(), None);
+    }
+
+    #[test]
+    fn test_from_row_error() {
+        let error = Error::FromRow(Box::new(FromRowError {
+            column: "age".into(),
+            rust_type: "i64",
+            pg_type: Some("text".into()),
+            reason: "cannot convert between the Rust type `i64` and the Postgres type `text`"
+                .into(),
+            sql: None,
+        }))
+        .with_sql(r#"SELECT * FROM "users""#)
+        .with_sql("ignored");
+
+        assert_eq!(
+            error.to_string(),
+            "column \"age\" of type \"text\" can't be read as i64: cannot convert between the Rust type `i64` and the Postgres type `text`\nquery: SELECT * FROM \"users\""
+        );
+
+        // Other errors are unchanged.
+        assert!(matches!(
+            Error::RecordNotFound.with_sql("SELECT 1"),
+            Error::RecordNotFound
+        ));
+    }
+}
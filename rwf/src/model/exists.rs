@@ -1,5 +1,5 @@
 //! Represents the result of `Query::exists`.
-use super::{Error, FromRow, Model, Value};
+use super::{Error, FromRow, GetColumn, Model, Value};
 
 #[derive(Debug, Clone)]
 pub struct Exists {
@@ -9,7 +9,7 @@ pub struct Exists {
 impl FromRow for Exists {
     fn from_row(row: tokio_postgres::Row) -> Result<Self, Error> {
         Ok(Self {
-            count: row.try_get("count")?,
+            count: row.get_column("count")?,
         })
     }
 }
@@ -1,5 +1,5 @@
 //! Implements the `WHERE` clause for `SELECT`, `UPDATE`, and `DELETE` statements.
-use super::{Column, ToSql, ToValue, Value};
+use super::{Column, Direction, Nulls, OrderSpec, ToSql, ToValue, Value};
 
 /// The WHERE clause of a SQL query.
 #[derive(Debug, Default, Clone)]
@@ -27,6 +27,10 @@ enum Comparison {
     GreaterEqualThan((Column, Value)),
     /// x <= 1
     LesserEqualThan((Column, Value)),
+    /// (x, y) > (1, 2)
+    Row((Vec<Column>, &'static str, Vec<Value>)),
+    /// FALSE
+    False,
 }
 
 impl Comparison {
@@ -77,6 +81,21 @@ impl ToSql for Comparison {
             LesserEqualThan((column, value)) => {
                 format!("{} <= {}", column.to_sql(), value.to_sql())
             }
+            Row((columns, op, values)) => format!(
+                "({}) {} ({})",
+                columns
+                    .iter()
+                    .map(|column| column.to_sql())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                op,
+                values
+                    .iter()
+                    .map(|value| value.to_sql())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            False => "FALSE".to_string(),
         }
     }
 }
@@ -125,6 +144,11 @@ impl WhereClause {
     pub fn placeholders(&self) -> usize {
         self.filter.placeholders()
     }
+
+    /// The clause has no predicates, i.e. it matches all rows.
+    pub fn is_empty(&self) -> bool {
+        self.filter.is_empty()
+    }
 }
 
 impl ToSql for WhereClause {
@@ -239,6 +263,118 @@ impl Filter {
             .push(Comparison::LesserEqualThan((column, value.to_value())));
     }
 
+    /// Rows that come after the values in the ordering, used by keyset pagination. The values are placeholders,
+    /// or `NULL` if the cursor's column is `NULL`.
+    ///
+    /// Columns ordered without `NULLS FIRST` or `NULLS LAST` are expected not to contain `NULL`s. If that's the case
+    /// for all of them, and they are ordered in the same direction, this is a row comparison, e.g. `(a, b) > ($1, $2)`,
+    /// which can use an index. Otherwise, it's expanded to `a > $1 OR (a = $1 AND b < $2)`, with `IS NULL`
+    /// and `IS NOT NULL` checks on the side of the cursor where the ordering puts the `NULL`s.
+    pub fn keyset(specs: Vec<OrderSpec>, values: Vec<Value>) -> Self {
+        let greater = |spec: &OrderSpec| spec.direction() == Direction::Asc;
+        let direction = specs.first().map(greater).unwrap_or(true);
+
+        let row = specs.iter().zip(values.iter()).all(|(spec, value)| {
+            greater(spec) == direction && spec.nulls().is_none() && !value.is_null()
+        });
+
+        let clause = if row {
+            if specs.len() == 1 {
+                let column = specs[0].column().clone();
+                let value = values[0].clone();
+                if direction {
+                    Comparison::GreaterThan((column, value))
+                } else {
+                    Comparison::LesserThan((column, value))
+                }
+            } else {
+                Comparison::Row((
+                    specs.iter().map(|spec| spec.column().clone()).collect(),
+                    if direction { ">" } else { "<" },
+                    values,
+                ))
+            }
+        } else {
+            let mut alternatives = vec![];
+
+            for (i, (spec, value)) in specs.iter().zip(values.iter()).enumerate() {
+                let after = match Self::after(spec, value) {
+                    Some(after) => after,
+                    None => continue,
+                };
+
+                // Equal to the cursor in all the columns before this one. `Equal` is `IS NULL` for `NULL`s.
+                let mut clauses = specs[..i]
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(spec, value)| Comparison::Equal((spec.column().clone(), value.clone())))
+                    .collect::<Vec<_>>();
+
+                alternatives.push(
+                    if clauses.is_empty() && matches!(after, Comparison::Filter(_)) {
+                        after
+                    } else {
+                        clauses.push(after);
+                        Comparison::Filter(Filter {
+                            clauses,
+                            op: JoinOp::And,
+                        })
+                    },
+                );
+            }
+
+            if alternatives.is_empty() {
+                Comparison::False
+            } else {
+                Comparison::Filter(Filter {
+                    clauses: alternatives,
+                    op: JoinOp::Or,
+                })
+            }
+        };
+
+        Filter {
+            clauses: vec![clause],
+            op: JoinOp::And,
+        }
+    }
+
+    // Values of the column after the cursor's value in the ordering, if there are any.
+    fn after(spec: &OrderSpec, value: &Value) -> Option<Comparison> {
+        let column = spec.column().clone();
+        let asc = spec.direction() == Direction::Asc;
+        // Postgres puts NULLs last in ascending order, and first in descending order.
+        let nulls_last = match spec.nulls() {
+            Some(Nulls::Last) => true,
+            Some(Nulls::First) => false,
+            None => asc,
+        };
+
+        if value.is_null() {
+            // Only values follow NULLs placed first, and nothing follows NULLs placed last.
+            return if nulls_last {
+                None
+            } else {
+                Some(Comparison::NotEqual((column, Value::Null)))
+            };
+        }
+
+        let comparison = if asc {
+            Comparison::GreaterThan((column.clone(), value.clone()))
+        } else {
+            Comparison::LesserThan((column.clone(), value.clone()))
+        };
+
+        if nulls_last && spec.nulls().is_some() {
+            Some(Comparison::Filter(Filter {
+                clauses: vec![comparison, Comparison::Equal((column, Value::Null))],
+                op: JoinOp::Or,
+            }))
+        } else {
+            Some(comparison)
+        }
+    }
+
     /// Append all predicates of the filter into the current filter.
     pub fn concat(&self, filter: Filter) -> Self {
         // Concatenating filters with different operations, e.g. AND and OR
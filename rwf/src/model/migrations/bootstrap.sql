@@ -7,6 +7,8 @@ CREATE TABLE IF NOT EXISTS rwf_migrations (
 	applied_at TIMESTAMPTZ
 );
 
+ALTER TABLE rwf_migrations ADD COLUMN IF NOT EXISTS checksum VARCHAR;
+
 CREATE TABLE IF NOT EXISTS rwf_jobs (
     id BIGSERIAL PRIMARY KEY,
     name VARCHAR NOT NULL,
@@ -20,6 +22,23 @@ CREATE TABLE IF NOT EXISTS rwf_jobs (
     error VARCHAR
 );
 
+ALTER TABLE rwf_jobs ADD COLUMN IF NOT EXISTS checkpoint_at TIMESTAMPTZ;
+
+ALTER TABLE rwf_jobs ADD COLUMN IF NOT EXISTS progress_completed BIGINT;
+
+ALTER TABLE rwf_jobs ADD COLUMN IF NOT EXISTS progress_total BIGINT;
+
+ALTER TABLE rwf_jobs ADD COLUMN IF NOT EXISTS result JSONB;
+
+ALTER TABLE rwf_jobs ADD COLUMN IF NOT EXISTS encrypted BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE rwf_jobs ADD COLUMN IF NOT EXISTS signature VARCHAR;
+
+-- Jobs queued before the signature was added are marked as legacy, new jobs aren't.
+ALTER TABLE rwf_jobs ADD COLUMN IF NOT EXISTS legacy BOOLEAN NOT NULL DEFAULT true;
+
+ALTER TABLE rwf_jobs ALTER COLUMN legacy SET DEFAULT false;
+
 -- Pending jobs
 CREATE INDEX IF NOT EXISTS rwf_jobs_pending_idx ON rwf_jobs USING btree(start_after, created_at) WHERE
     completed_at IS NULL
@@ -34,6 +53,17 @@ CREATE INDEX IF NOT EXISTS rwf_jobs_runnin_idx ON rwf_jobs USING btree(start_aft
 
 CREATE INDEX IF NOT EXISTS rwf_jobs_name_completed_at_idx ON rwf_jobs USING btree(name, completed_at);
 
+CREATE TABLE IF NOT EXISTS rwf_workers (
+    id VARCHAR PRIMARY KEY,
+    hostname VARCHAR NOT NULL,
+    pid BIGINT NOT NULL,
+    queues JSONB NOT NULL DEFAULT '[]'::jsonb,
+    jobs JSONB NOT NULL DEFAULT '[]'::jsonb,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_heartbeat TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    stale BOOLEAN NOT NULL DEFAULT false
+);
+
 CREATE TABLE IF NOT EXISTS rwf_requests (
     id BIGSERIAL PRIMARY KEY,
     path VARCHAR NOT NULL,
@@ -51,3 +81,47 @@ CREATE INDEX IF NOT EXISTS rwf_requests_path_created_at ON rwf_requests USING bt
 CREATE INDEX IF NOT EXISTS rwf_requests_errors ON rwf_requests USING btree(created_at, code, client_id) WHERE code >= 400;
 
 CREATE INDEX IF NOT EXISTS rwf_requests_too_slow ON rwf_requests USING btree(created_at, duration, client_id) WHERE duration >= 1000.0; -- the unit is milliseconds
+
+CREATE TABLE IF NOT EXISTS rwf_idempotency_keys (
+    id BIGSERIAL PRIMARY KEY,
+    key VARCHAR NOT NULL UNIQUE,
+    fingerprint VARCHAR NOT NULL,
+    code INTEGER,
+    headers JSONB NOT NULL DEFAULT '{}'::jsonb,
+    body TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS rwf_idempotency_keys_expires_at_idx ON rwf_idempotency_keys USING btree(expires_at);
+
+CREATE TABLE IF NOT EXISTS rwf_captures (
+    id BIGSERIAL PRIMARY KEY,
+    request_id VARCHAR NOT NULL,
+    rule VARCHAR NOT NULL,
+    request JSONB NOT NULL,
+    response JSONB NOT NULL,
+    duration DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS rwf_captures_request_id_idx ON rwf_captures USING btree(request_id);
+
+CREATE TABLE IF NOT EXISTS rwf_rate_limits (
+    key VARCHAR NOT NULL,
+    window_start BIGINT NOT NULL,
+    count BIGINT NOT NULL DEFAULT 1,
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (key, window_start)
+);
+
+CREATE INDEX IF NOT EXISTS rwf_rate_limits_expires_at_idx ON rwf_rate_limits USING btree(expires_at);
+
+CREATE TABLE IF NOT EXISTS rwf_feature_flags (
+    id BIGSERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE,
+    enabled BOOLEAN NOT NULL DEFAULT false,
+    percentage INTEGER,
+    users JSONB NOT NULL DEFAULT '[]'::jsonb,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
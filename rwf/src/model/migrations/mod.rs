@@ -1,30 +1,73 @@
 //! Implements database migrations, a deterministic mechanism to change the database schema.
 pub mod model;
+pub mod rust;
 use crate::config::get_config;
-use crate::model::{get_connection, get_pool, start_transaction, Model};
+use crate::model::{pool::DEFAULT_DATABASE, Model, Pool};
 use model::Migration;
+pub use rust::{MigrationContext, RustMigration, RustMigrations, RUST_CHECKSUM};
 
 use super::Error;
 
 use std::collections::HashMap;
 use std::env::current_dir;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use once_cell::sync::Lazy;
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use time::OffsetDateTime;
 use tokio::fs::{read_dir, read_to_string};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 /// Migrations found in the `"migrations"` folder. Some of them
 /// may not be applied yet.
+///
+/// Migrations for databases other than `"main"` are in the `"migrations/<database>"` folder,
+/// and are tracked in the `"rwf_migrations"` table of that database.
+///
+/// Migrations written in Rust, see [`RustMigration`], are applied together with the SQL ones,
+/// ordered by version.
 pub struct Migrations {
+    database: String,
     migrations: Vec<Migration>,
+    // Checksums of the up migration files on disk.
+    checksums: HashMap<String, String>,
+    rust: RustMigrations,
 }
 
 static RE: Lazy<Regex> =
     Lazy::new(|| Regex::new("([0-9]+)_([a-zA-Z0-9_]+).(up|down).sql").expect("migration regex"));
 
+/// State of a migration, comparing the database with the files on disk.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum MigrationStatus {
+    /// The migration hasn't been applied yet.
+    Pending,
+    /// The migration has been applied.
+    Applied,
+    /// The migration has been applied, but its up file was changed afterwards.
+    Modified,
+}
+
+/// Checksum of a migration file: SHA-256 of its contents, ignoring differences
+/// in line endings and trailing whitespace.
+pub fn checksum(sql: &str) -> String {
+    let normalized = sql
+        .lines()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut hasher = Sha256::new();
+    hasher.update(normalized.trim_end().as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
 /// Migration direction: up means to apply the migration, down means to revert it.
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Direction {
@@ -99,8 +142,12 @@ impl MigrationFile {
 }
 
 impl Migrations {
-    fn root_path() -> Result<PathBuf, Error> {
-        let path = PathBuf::from(current_dir()?.join(Path::new("migrations")));
+    fn root_path(database: &str) -> Result<PathBuf, Error> {
+        let mut path = current_dir()?.join(Path::new("migrations"));
+
+        if database != DEFAULT_DATABASE {
+            path = path.join(database);
+        }
 
         if !path.is_dir() {
             info!(r#"No migrations available, skipping"#);
@@ -112,11 +159,21 @@ impl Migrations {
         }
     }
 
-    async fn load() -> Result<Self, Error> {
-        let mut conn = get_connection().await?;
-        let migrations = Migration::all().fetch_all(&mut conn).await?;
+    async fn load(
+        database: String,
+        checksums: HashMap<String, String>,
+        rust: RustMigrations,
+    ) -> Result<Self, Error> {
+        let mut conn = Pool::named(&database)?.get().await?;
+        let mut migrations = Migration::all().on(&database).fetch_all(&mut conn).await?;
+        migrations.sort_by_key(|migration| migration.version);
 
-        Ok(Self { migrations })
+        Ok(Self {
+            database,
+            migrations,
+            checksums,
+            rust,
+        })
     }
 
     /// Read the `"migrations"` folder and sync all migrations
@@ -124,7 +181,24 @@ impl Migrations {
     /// actually apply the migrations, only makes sure the entries in the folder
     /// match the database table.
     pub async fn sync() -> Result<Self, Error> {
-        let checks = if let Ok(root_path) = Self::root_path() {
+        Self::sync_database(DEFAULT_DATABASE).await
+    }
+
+    /// Same as [`Migrations::sync`], for the migrations of the named database,
+    /// found in the `"migrations/<database>"` folder.
+    pub async fn sync_database(database: &str) -> Result<Self, Error> {
+        Self::sync_database_with(database, &RustMigrations::default()).await
+    }
+
+    /// Same as [`Migrations::sync`], including the Rust migrations in the registry.
+    pub async fn sync_with(rust: &RustMigrations) -> Result<Self, Error> {
+        Self::sync_database_with(DEFAULT_DATABASE, rust).await
+    }
+
+    /// Same as [`Migrations::sync_database`], including the Rust migrations for the database
+    /// in the registry.
+    pub async fn sync_database_with(database: &str, rust: &RustMigrations) -> Result<Self, Error> {
+        let checks = if let Ok(root_path) = Self::root_path(database) {
             let mut checks = HashMap::new();
 
             let mut dir_entries = read_dir(root_path).await?;
@@ -155,19 +229,23 @@ impl Migrations {
             HashMap::new()
         };
 
+        let rust = rust.database(database);
+        rust.check(
+            &checks
+                .values()
+                .flat_map(|check| check.up.iter().chain(check.down.iter()))
+                .map(|file| file.version as i64)
+                .collect::<Vec<_>>(),
+        )?;
+
         let log_queries = get_config().general.log_queries;
 
-        let mut conn = start_transaction().await?;
+        let mut conn = Pool::named(database)?.transaction().await?;
 
         // Create some necessary tables.
         // TODO: Move jobs to an internal migration.
         // TODO: Add support for internal migrations.
-        let queries = include_str!("bootstrap.sql")
-            .split(";")
-            .map(|q| q.trim())
-            .filter(|q| !q.is_empty());
-
-        for query in queries {
+        for query in bootstrap_queries() {
             if log_queries {
                 info!("{}", query);
             }
@@ -176,6 +254,7 @@ impl Migrations {
         }
 
         let mut migrations = vec![];
+        let mut checksums = HashMap::new();
 
         for (name, check) in checks {
             if !check.valid() {
@@ -186,27 +265,131 @@ impl Migrations {
                 );
                 return Err(Error::MigrationError("migrations file missing".into()));
             } else {
-                let migration = Migration::filter("name", name)
+                let mut migration = Migration::filter("name", name)
                     .filter("version", check.version() as i64)
                     .find_or_create()
+                    .on(database)
                     .fetch(&mut conn)
                     .await?;
+
+                let sql =
+                    read_to_string(Self::root_path(database)?.join(migration.path(Direction::Up)))
+                        .await?;
+                let checksum = checksum(&sql);
+
+                // Migrations applied before checksums were recorded.
+                if migration.applied_at.is_some() && migration.checksum.is_none() {
+                    migration.checksum = Some(checksum.clone());
+                    migration = migration.save().on(database).fetch(&mut conn).await?;
+                }
+
+                checksums.insert(migration.name(), checksum);
                 migrations.push(migration);
             }
         }
 
+        for rust_migration in rust.iter() {
+            let mut migration = Migration::filter("name", rust_migration.name())
+                .filter("version", rust_migration.version())
+                .find_or_create()
+                .on(database)
+                .fetch(&mut conn)
+                .await?;
+
+            if migration.applied_at.is_some() && migration.checksum.is_none() {
+                migration.checksum = Some(RUST_CHECKSUM.to_string());
+                migration = migration.save().on(database).fetch(&mut conn).await?;
+            }
+
+            checksums.insert(migration.name(), RUST_CHECKSUM.to_string());
+            migrations.push(migration);
+        }
+
         migrations.sort_by_key(|migration| migration.version);
 
         conn.commit().await?;
 
-        Ok(Self { migrations })
+        Ok(Self {
+            database: database.to_string(),
+            migrations,
+            checksums,
+            rust,
+        })
+    }
+
+    /// Get the status of a migration.
+    pub fn status(&self, migration: &Migration) -> MigrationStatus {
+        if migration.applied_at.is_none() {
+            return MigrationStatus::Pending;
+        }
+
+        match (
+            migration.checksum.as_ref(),
+            self.checksums.get(&migration.name()),
+        ) {
+            (Some(applied), Some(current)) if applied != current => MigrationStatus::Modified,
+            _ => MigrationStatus::Applied,
+        }
+    }
+
+    /// Applied migrations which files were changed since they were applied.
+    pub fn modified(&self) -> Vec<&Migration> {
+        self.migrations
+            .iter()
+            .filter(|migration| self.status(migration) == MigrationStatus::Modified)
+            .collect()
+    }
+
+    /// Check that none of the applied migrations were changed since they were applied.
+    pub fn verify(&self) -> Result<(), Error> {
+        let modified = self.modified();
+
+        if modified.is_empty() {
+            Ok(())
+        } else {
+            for migration in &modified {
+                error!(
+                    r#"migration "{}" was modified after it was applied"#,
+                    migration.name()
+                );
+            }
+
+            Err(Error::MigrationsModified(
+                modified.iter().map(|migration| migration.name()).collect(),
+            ))
+        }
+    }
+
+    /// Accept changes made to applied migrations, by updating their checksums
+    /// to match the files on disk. This doesn't apply the changes to the database.
+    pub async fn accept_changes(self) -> Result<Self, Error> {
+        let mut conn = Pool::named(&self.database)?.get().await?;
+
+        for migration in self.modified() {
+            let mut migration = migration.clone();
+            warn!(r#"accepting changes to migration "{}""#, migration.name());
+            migration.checksum = self.checksums.get(&migration.name()).cloned();
+            migration.save().on(&self.database).fetch(&mut conn).await?;
+        }
+
+        Self::load(self.database, self.checksums, self.rust).await
     }
 
     /// Apply the migrations, making changes to the database schema.
     ///
     /// The direction argument controllers if we are applying or reverting the migrations. The version
     /// argument means to perform this action up to and including that version.
+    ///
+    /// Migrations aren't applied if any of the applied ones were modified, see [`Migrations::verify`].
+    /// Afterwards, the schema is written to the file set with `schema_dump`, see [`crate::model::schema`].
     pub async fn apply(self, direction: Direction, version: Option<i64>) -> Result<Self, Error> {
+        if direction == Direction::Up {
+            self.verify()?;
+        }
+
+        let database = self.database;
+        let checksums = self.checksums;
+        let rust = self.rust;
         let migrations = match direction {
             Direction::Up => self.migrations.into_iter().collect::<Vec<_>>(),
             Direction::Down => self.migrations.into_iter().rev().collect::<Vec<_>>(),
@@ -240,7 +423,12 @@ impl Migrations {
                 migration.name()
             );
 
-            let path = Self::root_path()?.join(migration.path(direction));
+            if let Some(rust_migration) = rust.get(migration.version) {
+                Self::apply_rust(&database, migration, rust_migration, direction).await?;
+                continue;
+            }
+
+            let path = Self::root_path(&database)?.join(migration.path(direction));
 
             let sql = read_to_string(path).await?;
             let queries = sql
@@ -249,10 +437,11 @@ impl Migrations {
                 .map(|q| q.trim().to_string())
                 .collect::<Vec<_>>();
 
-            let pool = get_pool();
+            let pool = Pool::named(&database)?;
             let log_queries = get_config().general.log_queries;
 
             // Execute the migration in a transaction.
+            let database = &database;
             pool.with_transaction(|mut transaction| async move {
                 transaction
                     .query_cached("SET LOCAL client_min_messages TO WARNING", &[])
@@ -269,11 +458,21 @@ impl Migrations {
                     }
                 }
                 match direction {
-                    Direction::Up => migration.applied_at = Some(OffsetDateTime::now_utc()),
-                    Direction::Down => migration.applied_at = None,
+                    Direction::Up => {
+                        migration.applied_at = Some(OffsetDateTime::now_utc());
+                        migration.checksum = Some(checksum(&sql));
+                    }
+                    Direction::Down => {
+                        migration.applied_at = None;
+                        migration.checksum = None;
+                    }
                 };
 
-                let migration = migration.save().fetch(&mut transaction).await?;
+                let migration = migration
+                    .save()
+                    .on(database)
+                    .fetch(&mut transaction)
+                    .await?;
 
                 transaction.commit().await?;
 
@@ -291,7 +490,84 @@ impl Migrations {
             .await?;
         }
 
-        Self::load().await
+        super::schema::dump_configured(&database).await?;
+
+        Self::load(database, checksums, rust).await
+    }
+
+    /// Apply or revert a Rust migration, in a transaction unless it opted out of it.
+    async fn apply_rust(
+        database: &str,
+        mut migration: Migration,
+        rust_migration: Arc<dyn RustMigration>,
+        direction: Direction,
+    ) -> Result<(), Error> {
+        let pool = Pool::named(database)?;
+
+        let transaction = if rust_migration.transaction() {
+            let mut transaction = pool.transaction().await?;
+            transaction
+                .query_cached("SET LOCAL client_min_messages TO WARNING", &[])
+                .await?;
+            Some(transaction)
+        } else {
+            None
+        };
+
+        let mut ctx = MigrationContext::new(database, transaction);
+        let result = match direction {
+            Direction::Up => rust_migration.up(&mut ctx).await,
+            Direction::Down => rust_migration.down(&mut ctx).await,
+        };
+
+        if let Err(err) = result {
+            error!(r#"migration "{}" failed: {:?}"#, migration.name(), err);
+            return Err(Error::MigrationError("migration failed".into()));
+        }
+
+        match direction {
+            Direction::Up => {
+                migration.applied_at = Some(OffsetDateTime::now_utc());
+                migration.checksum = Some(RUST_CHECKSUM.to_string());
+            }
+            Direction::Down => {
+                migration.applied_at = None;
+                migration.checksum = None;
+            }
+        };
+
+        // Record the migration in its transaction, or once it's done.
+        let migration = match ctx.into_transaction() {
+            Some(mut transaction) => {
+                let migration = migration
+                    .save()
+                    .on(database)
+                    .fetch(&mut transaction)
+                    .await?;
+                transaction.commit().await?;
+                migration
+            }
+            None => {
+                let mut conn = pool.get().await?;
+                migration.save().on(database).fetch(&mut conn).await?
+            }
+        };
+
+        info!(
+            "migration \"{}\" {}",
+            migration.name(),
+            match direction {
+                Direction::Up => "applied",
+                Direction::Down => "reverted",
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Name of the database these migrations are for.
+    pub fn database(&self) -> &str {
+        &self.database
     }
 
     /// Get a list of all migrations currently found in the `"migrations"` folder.
@@ -304,6 +580,14 @@ impl Migrations {
         Migrations::sync().await?.apply(Direction::Up, None).await
     }
 
+    /// Execute all migrations in the up direction, including the Rust migrations in the registry.
+    pub async fn migrate_with(rust: &RustMigrations) -> Result<Migrations, Error> {
+        Migrations::sync_with(rust)
+            .await?
+            .apply(Direction::Up, None)
+            .await
+    }
+
     /// Execute all migrations in the down direction. **This will effectively
     /// destroy all tables and data in your database.**
     pub async fn flush() -> Result<Migrations, Error> {
@@ -311,6 +595,31 @@ impl Migrations {
     }
 }
 
+/// Queries creating the tables used by rwf itself.
+fn bootstrap_queries() -> impl Iterator<Item = &'static str> {
+    include_str!("bootstrap.sql")
+        .split(";")
+        .map(|q| q.trim())
+        .filter(|q| !q.is_empty())
+}
+
+/// Create the tables used by rwf inside a test transaction.
+///
+/// Takes an advisory lock for the rest of the transaction first, so tests
+/// creating the tables concurrently don't conflict.
+#[cfg(test)]
+pub(crate) async fn bootstrap(conn: &mut crate::model::ConnectionGuard) -> Result<(), Error> {
+    conn.client()
+        .execute("SELECT pg_advisory_xact_lock(1)", &[])
+        .await?;
+
+    for query in bootstrap_queries() {
+        conn.client().execute(query, &[]).await?;
+    }
+
+    Ok(())
+}
+
 /// Execute all migrations in the up direction.
 pub async fn migrate() -> Result<Migrations, Error> {
     Migrations::sync().await?.apply(Direction::Up, None).await
@@ -339,4 +648,27 @@ mod test {
         assert_eq!(file.name.as_str(), "Name_short_long234Adf");
         assert_eq!(file.version, 1234534);
     }
+
+    #[test]
+    fn test_checksum() {
+        let sql = "CREATE TABLE users (\n    id BIGINT\n);\n";
+
+        assert_eq!(
+            checksum(sql),
+            checksum("CREATE TABLE users (\r\n    id BIGINT  \r\n);")
+        );
+        assert_eq!(
+            checksum(sql),
+            checksum("CREATE TABLE users (\n    id BIGINT\n);\n\n\n")
+        );
+        assert_ne!(
+            checksum(sql),
+            checksum("CREATE TABLE users (\n    id INT\n);\n")
+        );
+        assert_ne!(
+            checksum(sql),
+            checksum("CREATE TABLE users (\n  id BIGINT\n);\n")
+        );
+        assert_eq!(checksum(sql).len(), 64);
+    }
 }
@@ -1,5 +1,5 @@
 //! The `"rwf_migrations"` model record.
-use crate::model::{Error, FromRow, Model, ToValue, Value};
+use crate::model::{Error, FromRow, GetColumn, Model, ToValue, Value};
 use time::OffsetDateTime;
 
 use std::path::PathBuf;
@@ -17,15 +17,18 @@ pub struct Migration {
     pub name: String,
     /// When the migration was applied to the database.
     pub applied_at: Option<OffsetDateTime>,
+    /// Checksum of the up migration file when the migration was applied.
+    pub checksum: Option<String>,
 }
 
 impl FromRow for Migration {
     fn from_row(row: tokio_postgres::Row) -> Result<Self, Error> {
         Ok(Self {
-            id: row.try_get("id")?,
-            version: row.try_get("version")?,
-            name: row.try_get("name")?,
-            applied_at: row.try_get("applied_at")?,
+            id: row.get_column("id")?,
+            version: row.get_column("version")?,
+            name: row.get_column("name")?,
+            applied_at: row.get_column("applied_at")?,
+            checksum: row.get_column("checksum")?,
         })
     }
 }
@@ -52,11 +55,12 @@ impl Model for Migration {
             self.version.to_value(),
             self.name.to_value(),
             self.applied_at.to_value(),
+            self.checksum.to_value(),
         ]
     }
 
     fn column_names() -> &'static [&'static str] {
-        &["version", "name", "applied_at"]
+        &["version", "name", "applied_at", "checksum"]
     }
 }
 
@@ -0,0 +1,272 @@
+//! Migrations written in Rust, for changes SQL files can't express, e.g. backfilling
+//! a column in batches.
+//!
+//! Rust migrations are registered with [`RustMigrations`] and passed to the migration runner,
+//! see [`Migrations::sync_with`](super::Migrations::sync_with). They are applied together with the SQL migrations,
+//! ordered by version.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio_postgres::types::ToSql;
+
+use crate::model::pool::{ConnectionGuard, Transaction, DEFAULT_DATABASE};
+use crate::model::{Error, Pool};
+
+/// Checksum recorded for Rust migrations. Their code isn't hashed, so changing
+/// a Rust migration after it was applied isn't detected.
+pub const RUST_CHECKSUM: &str = "rust";
+
+/// Migration written in Rust.
+///
+/// ```
+/// use rwf::model::migrations::{MigrationContext, RustMigration};
+/// use rwf::model::Error;
+/// use rwf::async_trait;
+///
+/// struct AddStatus;
+///
+/// #[async_trait]
+/// impl RustMigration for AddStatus {
+///     fn version(&self) -> i64 {
+///         1729119889028371278
+///     }
+///
+///     fn name(&self) -> &str {
+///         "add_status"
+///     }
+///
+///     async fn up(&self, ctx: &mut MigrationContext) -> Result<(), Error> {
+///         ctx.execute("ALTER TABLE users ADD COLUMN status VARCHAR", &[]).await?;
+///         Ok(())
+///     }
+///
+///     async fn down(&self, ctx: &mut MigrationContext) -> Result<(), Error> {
+///         ctx.execute("ALTER TABLE users DROP COLUMN status", &[]).await?;
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait RustMigration: Send + Sync {
+    /// Migration version. Migrations are applied in order of their versions,
+    /// whether they are written in Rust or SQL.
+    fn version(&self) -> i64;
+
+    /// Migration name. Only letters, digits and underscores are allowed.
+    fn name(&self) -> &str;
+
+    /// Apply the migration.
+    async fn up(&self, ctx: &mut MigrationContext) -> Result<(), Error>;
+
+    /// Revert the migration.
+    async fn down(&self, ctx: &mut MigrationContext) -> Result<(), Error>;
+
+    /// Run the migration in a transaction, like SQL migrations. Default is `true`.
+    ///
+    /// Return `false` for migrations that commit as they go, e.g. to update a large table
+    /// in batches with [`MigrationContext::transaction`]. The migration is recorded as applied only
+    /// after it finishes, so if it fails halfway, it runs again from the start and should be written
+    /// to skip work already done.
+    fn transaction(&self) -> bool {
+        true
+    }
+
+    /// Database the migration is for. Default is `"main"`.
+    fn database(&self) -> &str {
+        DEFAULT_DATABASE
+    }
+}
+
+/// Rust migrations passed to the migration runner.
+#[derive(Clone, Default)]
+pub struct RustMigrations {
+    migrations: Vec<Arc<dyn RustMigration>>,
+}
+
+impl RustMigrations {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a migration.
+    pub fn migration(mut self, migration: impl RustMigration + 'static) -> Self {
+        self.migrations.push(Arc::new(migration));
+        self
+    }
+
+    pub(crate) fn extend(&mut self, migrations: RustMigrations) {
+        self.migrations.extend(migrations.migrations);
+    }
+
+    /// Migrations for the database.
+    pub(crate) fn database(&self, database: &str) -> Self {
+        Self {
+            migrations: self
+                .migrations
+                .iter()
+                .filter(|migration| migration.database() == database)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Find the migration with this version.
+    pub(crate) fn get(&self, version: i64) -> Option<Arc<dyn RustMigration>> {
+        self.migrations
+            .iter()
+            .find(|migration| migration.version() == version)
+            .cloned()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Arc<dyn RustMigration>> {
+        self.migrations.iter()
+    }
+
+    /// Check that names are valid and versions aren't used twice, by Rust
+    /// or SQL migrations.
+    pub(crate) fn check(&self, sql_versions: &[i64]) -> Result<(), Error> {
+        let mut versions = sql_versions.to_vec();
+
+        for migration in &self.migrations {
+            let name = migration.name();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(Error::MigrationError(format!(
+                    r#""{}" is not a valid migration name"#,
+                    name
+                )));
+            }
+
+            if versions.contains(&migration.version()) {
+                return Err(Error::MigrationError(format!(
+                    r#"migration version {} is used more than once"#,
+                    migration.version()
+                )));
+            }
+
+            versions.push(migration.version());
+        }
+
+        Ok(())
+    }
+}
+
+/// Database access for a running [`RustMigration`].
+pub struct MigrationContext {
+    database: String,
+    transaction: Option<Transaction>,
+    connection: Option<ConnectionGuard>,
+}
+
+impl MigrationContext {
+    pub(crate) fn new(database: &str, transaction: Option<Transaction>) -> Self {
+        Self {
+            database: database.to_string(),
+            transaction,
+            connection: None,
+        }
+    }
+
+    /// Name of the database being migrated.
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// Is the migration running in a transaction?
+    pub fn transactional(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Connection to the database. If the migration runs in a transaction, queries
+    /// on this connection are part of it, otherwise each query is committed immediately.
+    pub async fn connection(&mut self) -> Result<&mut ConnectionGuard, Error> {
+        if let Some(ref mut transaction) = self.transaction {
+            return Ok(transaction);
+        }
+
+        if self.connection.is_none() {
+            self.connection = Some(Pool::named(&self.database)?.get().await?);
+        }
+
+        Ok(self.connection.as_mut().unwrap())
+    }
+
+    /// Execute a statement, returning the number of rows it changed.
+    pub async fn execute(
+        &mut self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, Error> {
+        self.connection().await?.execute_cached(query, params).await
+    }
+
+    /// Start a new transaction, e.g. for one batch of a backfill. Only migrations which don't run
+    /// in a transaction, see [`RustMigration::transaction`], can start their own.
+    pub async fn transaction(&self) -> Result<Transaction, Error> {
+        if self.transactional() {
+            return Err(Error::MigrationError(
+                "migration is already running in a transaction".into(),
+            ));
+        }
+
+        Pool::named(&self.database)?.transaction().await
+    }
+
+    pub(crate) fn into_transaction(self) -> Option<Transaction> {
+        self.transaction
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Test(i64, &'static str, &'static str);
+
+    #[async_trait]
+    impl RustMigration for Test {
+        fn version(&self) -> i64 {
+            self.0
+        }
+
+        fn name(&self) -> &str {
+            self.1
+        }
+
+        fn database(&self) -> &str {
+            self.2
+        }
+
+        async fn up(&self, _ctx: &mut MigrationContext) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn down(&self, _ctx: &mut MigrationContext) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let migrations = RustMigrations::new()
+            .migration(Test(2, "backfill", "main"))
+            .migration(Test(4, "events", "analytics"));
+
+        assert!(migrations.check(&[1, 3]).is_ok());
+        assert!(migrations.check(&[2]).is_err());
+        assert!(RustMigrations::new()
+            .migration(Test(1, "users", "main"))
+            .migration(Test(1, "orders", "main"))
+            .check(&[])
+            .is_err());
+        assert!(RustMigrations::new()
+            .migration(Test(1, "bad name", "main"))
+            .check(&[])
+            .is_err());
+
+        let main = migrations.database("main");
+        assert_eq!(main.get(2).unwrap().name(), "backfill");
+        assert!(main.get(4).is_none());
+        assert_eq!(migrations.database("analytics").iter().count(), 1);
+    }
+}
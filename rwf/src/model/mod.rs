@@ -3,12 +3,22 @@
 //! See [documentation](https://levkk.github.io/rwf/models/) for detailed examples on how to use the ORM.
 use crate::colors::MaybeColorize;
 use crate::config::get_config;
+use crate::http::Timings;
 
 use std::time::{Duration, Instant};
 use tracing::{error, info};
 
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Placeholders in raw SQL, e.g. `$1`.
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$(\d+)").unwrap());
+
 pub mod callbacks;
 pub mod column;
+pub mod database;
+pub mod delete;
+pub mod encryption;
 pub mod error;
 pub mod escape;
 pub mod exists;
@@ -25,12 +35,16 @@ pub mod placeholders;
 pub mod pool;
 pub mod prelude;
 pub mod row;
+pub mod schema;
 pub mod select;
 pub mod update;
 pub mod value;
 
 pub use column::{Column, Columns, ToColumn};
-pub use error::Error;
+pub use database::On;
+pub use delete::Delete;
+pub use encryption::Encryption;
+pub use error::{DatabaseErrorKind, Error};
 pub use escape::Escape;
 pub use exists::Exists;
 pub use explain::Explain;
@@ -40,14 +54,17 @@ pub use join::{Association, AssociationType, Join, Joined, Joins};
 pub use limit::Limit;
 pub use lock::Lock;
 pub use migrations::{migrate, rollback, Migrations};
-pub use order_by::{OrderBy, OrderColumn, ToOrderBy};
+pub use order_by::{
+    Direction, Direction::Asc, Direction::Desc, Nulls, OrderBy, OrderColumn, OrderSpec, ToOrderBy,
+    ToOrderSpec,
+};
 pub use picked::Picked;
 pub use placeholders::Placeholders;
 pub use pool::{get_connection, get_pool, start_transaction, Connection, ConnectionGuard, Pool};
-pub use row::Row;
+pub use row::{GetColumn, Row};
 pub use select::Select;
 pub use update::Update;
-pub use value::{ToValue, Value};
+pub use value::{AssumeTimezone, FromValue, ToValue, Value};
 
 /// Convert a PostgreSQL row to a Rust struct. Type conversions are handled by `tokio_postgres`. This only
 /// creates a mapping between columns and struct fields.
@@ -58,7 +75,7 @@ pub use value::{ToValue, Value};
 /// # Example
 ///
 /// ```
-/// use rwf::model::{FromRow, Error};
+/// use rwf::model::{FromRow, Error, GetColumn};
 ///
 /// #[derive(Clone)]
 /// struct User {
@@ -69,8 +86,8 @@ pub use value::{ToValue, Value};
 /// impl FromRow for User {
 ///     fn from_row(row: tokio_postgres::Row) -> Result<Self, Error> {
 ///         Ok(User {
-///             id: row.try_get("id")?,
-///             email: row.try_get("email")?
+///             id: row.get_column("id")?,
+///             email: row.get_column("email")?
 ///         })
 ///     }
 /// }
@@ -210,6 +227,8 @@ pub enum Query<T: FromRow + ?Sized = Row> {
     Select(Select<T>),
     /// Represents an `UPDATE` statement.
     Update(Update<T>),
+    /// Represents a `DELETE` statement.
+    Delete(Delete<T>),
     /// Represents an `INSERT` statement.
     Insert(Insert<T>),
     /// Implements [`Model::find_or_create_by`] by building a `SELECT` and an `INSERT` query.
@@ -235,6 +254,7 @@ impl<T: FromRow> ToSql for Query<T> {
             Select(select) => select.to_sql(),
             Raw { query, .. } => query.clone(),
             Update(update) => update.to_sql(),
+            Delete(delete) => delete.to_sql(),
             Insert(insert) => insert.to_sql(),
             InsertIfNotExists { select, insert, .. } => {
                 format!("{}; {};", select.to_sql(), insert.to_sql())
@@ -387,6 +407,8 @@ impl<T: Model> Query<T> {
     pub fn filter(self, column: impl ToColumn, value: impl ToValue) -> Self {
         use Query::*;
 
+        let (column, value) = Self::filter_value(column, value);
+
         match self {
             Select(select) => Select(select.filter_and(column, value)),
             _ => self,
@@ -452,6 +474,8 @@ impl<T: Model> Query<T> {
     pub fn not(self, column: impl ToColumn, value: impl ToValue) -> Self {
         use Query::*;
 
+        let (column, value) = Self::filter_value(column, value);
+
         match self {
             Select(select) => Select(select.filter_not(column, value)),
             _ => self,
@@ -461,6 +485,8 @@ impl<T: Model> Query<T> {
     pub fn or_not(self, column: impl ToColumn, value: impl ToValue) -> Self {
         use Query::*;
 
+        let (column, value) = Self::filter_value(column, value);
+
         match self {
             Select(select) => Select(select.filter_or_not(column, value)),
             _ => self,
@@ -493,15 +519,126 @@ impl<T: Model> Query<T> {
         }
     }
 
+    /// Order rows by a column, or more columns if called again. Typed orderings, e.g. `("name", Asc)`,
+    /// are qualified with the table name, and checked against [`Model::column_names`] before the query runs.
     pub fn order(self, order: impl ToOrderBy) -> Self {
         if let Query::Select(mut select) = self {
-            select.order_by = select.order_by + order.to_order_by();
+            let mut order = order.to_order_by();
+            for column in order.order_by.iter_mut() {
+                if let OrderColumn::Spec(spec) = column {
+                    *spec = spec.clone().qualify(&select.table_name);
+                }
+            }
+            select.order_by = select.order_by + order;
             Query::Select(select)
         } else {
             self
         }
     }
 
+    /// Order rows by an SQL expression. Values are passed as parameters, numbered from `$1`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::prelude::*;
+    /// # use rwf::model::ToSql;
+    /// # #[derive(Clone, macros::Model)]
+    /// # struct User {
+    /// #    id: Option<i64>,
+    /// #    email: String,
+    /// # }
+    /// let users = User::filter("id", 5).order_raw("email = $1 DESC", &["admin@example.com".to_value()]);
+    ///
+    /// assert_eq!(
+    ///     users.to_sql(),
+    ///     r#"SELECT * FROM "users" WHERE "users"."id" = $1 ORDER BY email = $2 DESC"#
+    /// );
+    /// ```
+    pub fn order_raw(self, expression: &str, values: &[Value]) -> Self {
+        if let Query::Select(mut select) = self {
+            let placeholders = values
+                .iter()
+                .map(|value| select.placeholders.add(value).to_sql())
+                .collect::<Vec<_>>();
+
+            let expression = PLACEHOLDER.replace_all(expression, |captures: &regex::Captures| {
+                captures[1]
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| placeholders.get(n.wrapping_sub(1)))
+                    .cloned()
+                    .unwrap_or_else(|| captures[0].to_string())
+            });
+
+            select.order_by = select.order_by
+                + OrderBy {
+                    order_by: vec![OrderColumn::Raw(expression.to_string())],
+                };
+            Query::Select(select)
+        } else {
+            self
+        }
+    }
+
+    /// Keyset pagination: rows that come after the cursor in the query's ordering. The cursor has
+    /// one value for each ordered column, taken from the last row of the current page.
+    ///
+    /// Columns which can be `NULL` should be ordered with [`nulls_first`](ToOrderSpec::nulls_first) or
+    /// [`nulls_last`](ToOrderSpec::nulls_last), so rows with `NULL`s aren't skipped; other columns are compared
+    /// with a row comparison, which can use an index. To make the ordering unique, order by the primary key last.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::prelude::*;
+    /// # use rwf::model::{Asc, Desc, ToSql};
+    /// # #[derive(Clone, macros::Model)]
+    /// # struct User {
+    /// #    id: Option<i64>,
+    /// #    name: String,
+    /// # }
+    /// let next = User::all()
+    ///     .order(("name", Asc))
+    ///     .order(("id", Asc))
+    ///     .after(&["alice".to_value(), 5.to_value()])
+    ///     .limit(25);
+    ///
+    /// assert_eq!(
+    ///     next.to_sql(),
+    ///     r#"SELECT * FROM "users" WHERE ("users"."name", "users"."id") > ($1, $2) ORDER BY "users"."name" ASC, "users"."id" ASC LIMIT 25"#
+    /// );
+    /// ```
+    ///
+    /// If the number of values doesn't match the number of ordered columns, the query returns
+    /// [`Error::InvalidCursor`] when it's executed.
+    ///
+    /// # Panics
+    ///
+    /// If the query is ordered by a raw expression.
+    pub fn after(self, cursor: &[Value]) -> Self {
+        match self {
+            Query::Select(select) => Query::Select(select.keyset(cursor, false)),
+            _ => self,
+        }
+    }
+
+    /// Keyset pagination: rows that come before the cursor in the query's ordering, i.e. the previous page.
+    /// The cursor is taken from the first row of the current page.
+    ///
+    /// The ordering is reversed, so the rows closest to the cursor are fetched first.
+    /// Reverse the fetched rows to show them in the original order.
+    ///
+    /// # Panics
+    ///
+    /// Same as [`Query::after`].
+    pub fn before(self, cursor: &[Value]) -> Self {
+        match self {
+            Query::Select(select) => Query::Select(select.keyset(cursor, true)),
+            _ => self,
+        }
+    }
+
     /// Join this relation with another relation directly related to it, either
     /// through a foreign key.
     ///
@@ -552,6 +689,18 @@ impl<T: Model> Query<T> {
         match self {
             Query::Select(select) => {
                 let (columns, values) = select.insert_columns();
+
+                // Deterministically encrypted values were encrypted by the filter already.
+                let values = columns
+                    .iter()
+                    .zip(values)
+                    .map(|(column, value)| match Self::encryption(column) {
+                        Some(Encryption::Random) => {
+                            encryption::encrypt_value(value, Encryption::Random)
+                        }
+                        _ => value,
+                    })
+                    .collect::<Vec<_>>();
                 let insert = Insert::from_columns(&columns, &values);
                 Query::InsertIfNotExists {
                     select: select.limit(1),
@@ -574,6 +723,30 @@ impl<T: Model> Query<T> {
         }
     }
 
+    /// Update all records matching the query with a single `UPDATE` statement.
+    ///
+    /// The statement is refused if the query has no filters, since it would update the whole table,
+    /// unless [`Query::allow_full_table`] is chained. Model callbacks are not called for bulk updates.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::model::*;
+    /// # use rwf::macros::Model;
+    /// #[derive(Clone, Model)]
+    /// struct Order {
+    ///     id: Option<i64>,
+    ///     status: String,
+    /// }
+    ///
+    /// let query = Order::filter("status", "pending")
+    ///     .update_all(&[("status", "cancelled")]);
+    ///
+    /// assert_eq!(
+    ///     query.to_sql(),
+    ///     r#"UPDATE "orders" SET "status" = $2 WHERE "orders"."status" = $1 RETURNING *"#
+    /// );
+    /// ```
     pub fn update_all(self, attributes: &[(impl ToColumn, impl ToValue)]) -> Self {
         match self {
             Query::Select(select) => {
@@ -582,16 +755,56 @@ impl<T: Model> Query<T> {
                     .iter()
                     .map(|(c, _)| c.to_column())
                     .collect::<Vec<_>>();
-                let values = attributes
-                    .iter()
-                    .map(|(_, v)| v.to_value())
-                    .collect::<Vec<_>>();
+                let values = Self::attribute_values(&columns, attributes);
                 Query::Update(update.columns(&columns, &values))
             }
             _ => self,
         }
     }
 
+    /// Delete all records matching the query with a single `DELETE` statement.
+    ///
+    /// The statement is refused if the query has no filters, since it would delete the whole table,
+    /// unless [`Query::allow_full_table`] is chained. Model callbacks are not called for bulk deletes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::model::*;
+    /// # use rwf::macros::Model;
+    /// # use time::OffsetDateTime;
+    /// #[derive(Clone, Model)]
+    /// struct Session {
+    ///     id: Option<i64>,
+    ///     expires_at: OffsetDateTime,
+    /// }
+    ///
+    /// let query = Session::all()
+    ///     .filter_lt("expires_at", OffsetDateTime::now_utc())
+    ///     .delete_all();
+    ///
+    /// assert_eq!(
+    ///     query.to_sql(),
+    ///     r#"DELETE FROM "sessions" WHERE "sessions"."expires_at" < $1 RETURNING *"#
+    /// );
+    /// ```
+    pub fn delete_all(self) -> Self {
+        match self {
+            Query::Select(select) => Query::Delete(Delete::from(select)),
+            _ => self,
+        }
+    }
+
+    /// Allow [`Query::update_all`] and [`Query::delete_all`] to change all rows in the table, if
+    /// the query has no filters. Must be chained after them.
+    pub fn allow_full_table(self) -> Self {
+        match self {
+            Query::Update(update) => Query::Update(update.allow_full_table()),
+            Query::Delete(delete) => Query::Delete(delete.allow_full_table()),
+            _ => self,
+        }
+    }
+
     pub fn unique_by(self, columns: &[impl ToColumn]) -> Self {
         match self {
             Query::Insert(insert) => Query::Insert(insert.unique_by(columns)),
@@ -611,10 +824,156 @@ impl<T: Model> Query<T> {
         }
     }
 
+    // Encrypt the value compared with a deterministically encrypted column, so it matches the stored ciphertext.
+    fn filter_value(column: impl ToColumn, value: impl ToValue) -> (Column, Value) {
+        let column = column.to_column();
+        let value = match Self::encryption(&column) {
+            Some(Encryption::Deterministic) => {
+                encryption::encrypt_value(value.to_value(), Encryption::Deterministic)
+            }
+            _ => value.to_value(),
+        };
+
+        (column, value)
+    }
+
+    // Values of the attributes written to the database, encrypted if needed.
+    fn attribute_values(
+        columns: &[Column],
+        attributes: &[(impl ToColumn, impl ToValue)],
+    ) -> Vec<Value> {
+        columns
+            .iter()
+            .zip(attributes.iter())
+            .map(|(column, (_, value))| match Self::encryption(column) {
+                Some(encryption) => encryption::encrypt_value(value.to_value(), encryption),
+                None => value.to_value(),
+            })
+            .collect()
+    }
+
+    fn encryption(column: &Column) -> Option<Encryption> {
+        if column.in_table(T::table_name()) {
+            T::encrypted(column.column_name())
+        } else {
+            None
+        }
+    }
+
+    /// Run the query on a connection to this database, instead of the model's [`Model::database`].
+    pub fn on(self, database: impl ToString) -> On<T> {
+        On::new(self, database)
+    }
+
+    // Make sure the query runs on the expected database, usually the model's own.
+    fn check_database(&self, client: &ConnectionGuard, expected: &str) -> Result<(), Error> {
+        // Arbitrary SQL can query any database.
+        if let Query::Raw { .. } = self {
+            return Ok(());
+        }
+
+        if expected == client.database() {
+            return Ok(());
+        }
+
+        let (model, expected, actual) = (
+            Self::type_name(),
+            expected.to_string(),
+            client.database().to_string(),
+        );
+
+        if client.in_transaction() {
+            Err(Error::CrossDatabaseTransaction {
+                model,
+                expected,
+                actual,
+            })
+        } else {
+            Err(Error::WrongDatabase {
+                model,
+                expected,
+                actual,
+            })
+        }
+    }
+
+    // Make sure typed orderings use the model's columns.
+    fn check_order(&self) -> Result<(), Error> {
+        let select = match self {
+            Query::Select(select) => select,
+            _ => return Ok(()),
+        };
+
+        for column in &select.order_by.order_by {
+            if let OrderColumn::Spec(spec) = column {
+                let column = spec.column();
+
+                if spec.checked()
+                    && column.in_table(&select.table_name)
+                    && !T::column_names().contains(&column.column_name())
+                    && column.column_name() != T::primary_key()
+                {
+                    return Err(Error::UnknownOrderColumn {
+                        model: Self::type_name(),
+                        column: column.column_name().to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Keyset cursors come from the client and can have the wrong number of values.
+    fn check_cursor(&self) -> Result<(), Error> {
+        let select = match self {
+            Query::Select(select) => select,
+            Query::Picked(picked) => &picked.select,
+            _ => return Ok(()),
+        };
+
+        match select.invalid_cursor() {
+            Some(actual) => Err(Error::InvalidCursor {
+                expected: select
+                    .order_by
+                    .specs()
+                    .map(|specs| specs.len())
+                    .unwrap_or(0),
+                actual,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Refuse to update or delete all rows in the table by accident.
+    fn check_full_table(&self) -> Result<(), Error> {
+        let (statement, table) = match self {
+            Query::Update(update) if update.full_table() => ("UPDATE", update.table_name()),
+            Query::Delete(delete) if delete.full_table() => ("DELETE", delete.table_name()),
+            _ => return Ok(()),
+        };
+
+        Err(Error::FullTable {
+            statement,
+            table: table.to_string(),
+        })
+    }
+
     async fn execute_internal(
         &self,
         client: &mut ConnectionGuard,
+        database: &str,
     ) -> Result<Vec<tokio_postgres::Row>, Error> {
+        if let Err(err) = self
+            .check_database(client, database)
+            .and_then(|_| self.check_full_table())
+            .and_then(|_| self.check_order())
+            .and_then(|_| self.check_cursor())
+        {
+            self.log_error(&err);
+            return Err(err);
+        }
+
         let result = match self {
             Query::Select(select) => {
                 let query = self.to_sql();
@@ -637,6 +996,12 @@ impl<T: Model> Query<T> {
                 client.query_cached(&query, &values).await
             }
 
+            Query::Delete(delete) => {
+                let query = self.to_sql();
+                let values = delete.placeholders.values();
+                client.query_cached(&query, &values).await
+            }
+
             Query::Insert(insert) => {
                 let query = self.to_sql();
                 let values = insert.placeholders.values();
@@ -712,6 +1077,7 @@ impl<T: Model> Query<T> {
         let placeholders = match self {
             Query::Select(select) => select.placeholders,
             Query::Update(update) => update.placeholders,
+            Query::Delete(delete) => delete.placeholders,
             Query::Insert(insert) => insert.placeholders,
             Query::Picked(picked) => picked.select.placeholders,
             _ => todo!("explain"),
@@ -721,7 +1087,7 @@ impl<T: Model> Query<T> {
             query,
             placeholders,
         };
-        match query.execute_internal(conn).await?.pop() {
+        match query.execute_internal(conn, T::database()).await?.pop() {
             Some(explain) => Ok(Explain::from_row(explain)?),
             None => Err(Error::RecordNotFound),
         }
@@ -732,13 +1098,21 @@ impl<T: Model> Query<T> {
     }
 
     pub async fn count(self, conn: &mut ConnectionGuard) -> Result<i64, Error> {
+        self.count_on(conn, T::database()).await
+    }
+
+    pub(crate) async fn count_on(
+        self,
+        conn: &mut ConnectionGuard,
+        database: &str,
+    ) -> Result<i64, Error> {
         let query = match self {
             Query::Select(select) => Query::Select(select.exists()),
             _ => self,
         };
         let start = Instant::now();
 
-        let result = match query.execute_internal(conn).await?.pop() {
+        let result = match query.execute_internal(conn, database).await?.pop() {
             None => Ok(0),
             Some(exists) => Ok(Exists::from_row(exists)?.count),
         };
@@ -750,11 +1124,19 @@ impl<T: Model> Query<T> {
 
     /// Execute a query and return an optional result.
     pub async fn execute(self, conn: &mut ConnectionGuard) -> Result<Vec<T>, Error> {
+        self.execute_on(conn, T::database()).await
+    }
+
+    pub(crate) async fn execute_on(
+        self,
+        conn: &mut ConnectionGuard,
+        database: &str,
+    ) -> Result<Vec<T>, Error> {
         let start = Instant::now();
         let mut results = vec![];
-        let rows = self.execute_internal(conn).await?;
+        let rows = self.execute_internal(conn, database).await?;
         for row in rows {
-            results.push(T::from_row(row)?)
+            results.push(T::from_row(row).map_err(|err| err.with_sql(self.to_sql()))?)
         }
         let time = start.elapsed();
 
@@ -763,6 +1145,49 @@ impl<T: Model> Query<T> {
         Ok(results)
     }
 
+    /// Execute the query and return the number of rows it changed, without fetching them.
+    /// Used with [`Query::update_all`] and [`Query::delete_all`].
+    pub async fn affected_rows(self, conn: &mut ConnectionGuard) -> Result<u64, Error> {
+        let query = match self {
+            Query::Update(update) => Query::Update(update.without_returning()),
+            Query::Delete(delete) => Query::Delete(delete.without_returning()),
+            query => return Ok(query.execute_internal(conn, T::database()).await?.len() as u64),
+        };
+
+        if let Err(err) = query
+            .check_database(conn, T::database())
+            .and_then(|_| query.check_full_table())
+        {
+            query.log_error(&err);
+            return Err(err);
+        }
+
+        let start = Instant::now();
+        let sql = query.to_sql();
+        let result = match query {
+            Query::Update(ref update) => {
+                conn.execute_cached(&sql, &update.placeholders.values())
+                    .await
+            }
+            Query::Delete(ref delete) => {
+                conn.execute_cached(&sql, &delete.placeholders.values())
+                    .await
+            }
+            _ => unreachable!(),
+        };
+
+        match result {
+            Ok(rows) => {
+                query.log(start.elapsed());
+                Ok(rows)
+            }
+            Err(err) => {
+                query.log_error(&err);
+                Err(err)
+            }
+        }
+    }
+
     fn type_name() -> String {
         std::any::type_name::<T>()
             .split("::")
@@ -775,6 +1200,7 @@ impl<T: Model> Query<T> {
         match self {
             Query::Select(_) | Query::Picked(_) => "load",
             Query::Update(_) => "save",
+            Query::Delete(_) => "delete",
             Query::Raw { .. } => "query",
             Query::Insert(_) => "save",
             Query::InsertIfNotExists { .. } => "load/create",
@@ -782,6 +1208,11 @@ impl<T: Model> Query<T> {
     }
 
     fn log(&self, duration: Duration) {
+        if let Some(timings) = Timings::current() {
+            timings.record("db", duration);
+            timings.record_query(|| self.to_sql(), duration);
+        }
+
         if !get_config().general.log_queries {
             return;
         }
@@ -959,6 +1390,20 @@ pub trait Model: FromRow {
         "id"
     }
 
+    /// Name of the database where records for this model are stored, configured in a `[database.<name>]` section.
+    ///
+    /// Queries for the model must run on a connection to this database, e.g. from `Pool::named(User::database())`,
+    /// unless another database is chosen with [`Query::on`]. Use the `#[database("name")]` derive attribute to override it.
+    fn database() -> &'static str {
+        pool::DEFAULT_DATABASE
+    }
+
+    /// How the column is encrypted, if it's encrypted. Set with the `#[model(encrypted)]` derive attribute,
+    /// see [`encryption`].
+    fn encrypted(_column: &str) -> Option<Encryption> {
+        None
+    }
+
     /// Select one record from the table. The row returned is determined by the database.
     ///
     /// # Example
@@ -1300,10 +1745,7 @@ pub trait Model: FromRow {
             .iter()
             .map(|(c, _)| c.to_column())
             .collect::<Vec<_>>();
-        let values = attributes
-            .iter()
-            .map(|(_, v)| v.to_value())
-            .collect::<Vec<_>>();
+        let values = Query::<Self>::attribute_values(&columns, attributes);
 
         Query::Insert(Insert::from_columns(&columns, &values))
     }
@@ -1330,15 +1772,12 @@ pub trait Model: FromRow {
             .iter()
             .map(|(c, _)| c.to_column())
             .collect::<Vec<_>>();
-        let values = attributes
-            .iter()
-            .map(|(_, v)| v.to_value())
-            .collect::<Vec<_>>();
+        let values = Query::<Self>::attribute_values(&columns, attributes);
 
         let mut select = Query::<Self>::select(Self::table_name());
 
-        for (column, value) in columns.iter().zip(values.iter()) {
-            select = select.filter(column.clone(), value.clone());
+        for (column, value) in attributes {
+            select = select.filter(column.to_column(), value.to_value());
         }
 
         let select = match select {
@@ -1590,9 +2029,9 @@ mod test {
 
     impl FromRow for User {
         fn from_row(row: Row) -> Result<Self, Error> {
-            let id: i64 = row.get("id");
-            let email: String = row.get("email");
-            let password: String = row.get("password");
+            let id: i64 = row.get_column("id")?;
+            let email: String = row.get_column("email")?;
+            let password: String = row.get_column("password")?;
 
             Ok(User {
                 id,
@@ -1604,9 +2043,9 @@ mod test {
 
     impl FromRow for Order {
         fn from_row(row: Row) -> Result<Self, Error> {
-            let id: i64 = row.get("id");
-            let user_id: i64 = row.get("user_id");
-            let amount: f64 = row.get("amount");
+            let id: i64 = row.get_column("id")?;
+            let user_id: i64 = row.get_column("user_id")?;
+            let amount: f64 = row.get_column("amount")?;
 
             Ok(Order {
                 id,
@@ -1618,9 +2057,9 @@ mod test {
 
     impl FromRow for OrderItem {
         fn from_row(row: Row) -> Result<Self, Error> {
-            let id: i64 = row.get("id");
-            let order_id: i64 = row.get("order_id");
-            let product_id: i64 = row.get("product_id");
+            let id: i64 = row.get_column("id")?;
+            let order_id: i64 = row.get_column("order_id")?;
+            let product_id: i64 = row.get_column("product_id")?;
 
             Ok(OrderItem {
                 id,
@@ -1632,8 +2071,8 @@ mod test {
 
     impl FromRow for Product {
         fn from_row(row: Row) -> Result<Self, Error> {
-            let id: i64 = row.get("id");
-            let name: String = row.get("name");
+            let id: i64 = row.get_column("id")?;
+            let name: String = row.get_column("name")?;
 
             Ok(Product { id, name })
         }
@@ -1751,6 +2190,245 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_order() {
+        let query = User::all()
+            .order(("email", Desc).nulls_last())
+            .order(("id", Asc));
+        assert_eq!(
+            query.to_sql(),
+            r#"SELECT * FROM "users" ORDER BY "users"."email" DESC NULLS LAST, "users"."id" ASC"#
+        );
+        assert!(query.check_order().is_ok());
+
+        let query = User::filter("id", 5)
+            .order_raw("similarity(email, $1) DESC", &["bob".to_value()])
+            .order(("password", Asc).nulls_first());
+        assert_eq!(
+            query.to_sql(),
+            r#"SELECT * FROM "users" WHERE "users"."id" = $1 ORDER BY similarity(email, $2) DESC, "users"."password" ASC NULLS FIRST"#
+        );
+
+        // Columns are checked against the model.
+        let query = User::all().order(("emial", Asc));
+        assert!(matches!(
+            query.check_order(),
+            Err(Error::UnknownOrderColumn { column, .. }) if column == "emial"
+        ));
+        assert!(User::all()
+            .order(("score", Desc).unchecked())
+            .check_order()
+            .is_ok());
+        assert!(User::all()
+            .order((Column::new("orders", "amount"), Desc))
+            .check_order()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_keyset() {
+        let cursor = ["bob@test.com".to_value(), 5.to_value()];
+        let page = || User::all().order(("email", Asc)).order(("id", Asc));
+
+        assert_eq!(
+            page().after(&cursor).limit(25).to_sql(),
+            r#"SELECT * FROM "users" WHERE ("users"."email", "users"."id") > ($1, $2) ORDER BY "users"."email" ASC, "users"."id" ASC LIMIT 25"#
+        );
+        assert_eq!(
+            page().before(&cursor).limit(25).to_sql(),
+            r#"SELECT * FROM "users" WHERE ("users"."email", "users"."id") < ($1, $2) ORDER BY "users"."email" DESC, "users"."id" DESC LIMIT 25"#
+        );
+
+        // Mixed directions can't use a row comparison.
+        let page = || {
+            User::filter("password", "secret")
+                .order(("email", Desc).nulls_last())
+                .order(("id", Asc))
+        };
+        assert_eq!(
+            page().after(&cursor).to_sql(),
+            r#"SELECT * FROM "users" WHERE "users"."password" = $1 AND (("users"."email" < $2 OR "users"."email" IS NULL) OR ("users"."email" = $2 AND "users"."id" > $3)) ORDER BY "users"."email" DESC NULLS LAST, "users"."id" ASC"#
+        );
+        assert_eq!(
+            page().before(&cursor).to_sql(),
+            r#"SELECT * FROM "users" WHERE "users"."password" = $1 AND (("users"."email" > $2) OR ("users"."email" = $2 AND "users"."id" < $3)) ORDER BY "users"."email" ASC NULLS FIRST, "users"."id" DESC"#
+        );
+
+        // NULLs in the cursor.
+        let cursor = [Value::Null, 5.to_value()];
+        assert_eq!(
+            page().after(&cursor).to_sql(),
+            r#"SELECT * FROM "users" WHERE "users"."password" = $1 AND (("users"."email" IS NULL AND "users"."id" > $2)) ORDER BY "users"."email" DESC NULLS LAST, "users"."id" ASC"#
+        );
+        assert_eq!(
+            page().before(&cursor).to_sql(),
+            r#"SELECT * FROM "users" WHERE "users"."password" = $1 AND (("users"."email" IS NOT NULL) OR ("users"."email" IS NULL AND "users"."id" < $2)) ORDER BY "users"."email" ASC NULLS FIRST, "users"."id" DESC"#
+        );
+        assert_eq!(
+            User::all()
+                .order(("email", Asc))
+                .after(&[Value::Null])
+                .to_sql(),
+            r#"SELECT * FROM "users" WHERE FALSE ORDER BY "users"."email" ASC"#
+        );
+
+        let query = User::all().order(("id", Desc)).after(&[5.to_value()]);
+        assert_eq!(
+            query.to_sql(),
+            r#"SELECT * FROM "users" WHERE "users"."id" < $1 ORDER BY "users"."id" DESC"#
+        );
+    }
+
+    #[test]
+    fn test_keyset_wrong_cursor() {
+        let query = User::all()
+            .order(("email", Asc))
+            .order(("id", Asc))
+            .after(&[5.to_value()]);
+
+        assert!(matches!(
+            query.check_cursor(),
+            Err(Error::InvalidCursor {
+                expected: 2,
+                actual: 1
+            })
+        ));
+        assert!(User::all()
+            .order(("id", Asc))
+            .after(&[5.to_value()])
+            .check_cursor()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_keyset_nulls() -> Result<(), Error> {
+        #[derive(Debug, Clone)]
+        struct Visit {
+            id: i64,
+            last_seen: Option<i64>,
+        }
+
+        impl FromRow for Visit {
+            fn from_row(row: Row) -> Result<Self, Error> {
+                Ok(Visit {
+                    id: row.get_column("id")?,
+                    last_seen: row.get_column("last_seen")?,
+                })
+            }
+        }
+
+        impl Model for Visit {
+            fn id(&self) -> Value {
+                Value::Integer(self.id)
+            }
+
+            fn table_name() -> &'static str {
+                "visits"
+            }
+
+            fn foreign_key() -> &'static str {
+                "visit_id"
+            }
+
+            fn column_names() -> &'static [&'static str] {
+                &["last_seen"]
+            }
+
+            fn values(&self) -> Vec<Value> {
+                vec![self.last_seen.to_value()]
+            }
+        }
+
+        fn cursor(visit: &Visit) -> [Value; 2] {
+            [visit.last_seen.to_value(), visit.id.to_value()]
+        }
+
+        // Fetch all pages forward, and backward from a cursor past the last row.
+        async fn pages(
+            order: impl Fn() -> Query<Visit>,
+            end: Visit,
+            conn: &mut ConnectionGuard,
+        ) -> Result<(Vec<i64>, Vec<i64>), Error> {
+            let (mut forward, mut backward) = (vec![], vec![]);
+
+            let mut page = order().limit(2).fetch_all(conn).await?;
+            while let Some(last) = page.last().cloned() {
+                forward.extend(page.iter().map(|visit| visit.id));
+                page = order()
+                    .after(&cursor(&last))
+                    .limit(2)
+                    .fetch_all(conn)
+                    .await?;
+            }
+
+            // Pages before the cursor are in reverse order.
+            let mut page = order()
+                .before(&cursor(&end))
+                .limit(2)
+                .fetch_all(conn)
+                .await?;
+            while let Some(first) = page.last().cloned() {
+                backward.extend(page.iter().map(|visit| visit.id));
+                page = order()
+                    .before(&cursor(&first))
+                    .limit(2)
+                    .fetch_all(conn)
+                    .await?;
+            }
+            backward.reverse();
+
+            Ok((forward, backward))
+        }
+
+        let pool = Pool::from_env();
+        let mut transaction = pool.transaction().await?;
+
+        transaction
+            .client()
+            .query(
+                "CREATE TEMPORARY TABLE visits (id BIGINT, last_seen BIGINT) ON COMMIT DROP",
+                &[],
+            )
+            .await?;
+        transaction
+            .client()
+            .query(
+                "INSERT INTO visits VALUES (1, 30), (2, NULL), (3, 10), (4, NULL), (5, 20)",
+                &[],
+            )
+            .await?;
+
+        let end = Visit {
+            id: i64::MAX,
+            last_seen: None,
+        };
+        let order = || {
+            Visit::all()
+                .order(("last_seen", Desc).nulls_last())
+                .order(("id", Asc))
+        };
+        let (forward, backward) = pages(order, end, &mut transaction).await?;
+        assert_eq!(forward, vec![1, 5, 3, 2, 4]);
+        assert_eq!(backward, forward);
+
+        let end = Visit {
+            id: i64::MAX,
+            last_seen: Some(i64::MAX),
+        };
+        let order = || {
+            Visit::all()
+                .order(("last_seen", Asc).nulls_first())
+                .order(("id", Asc))
+        };
+        let (forward, backward) = pages(order, end, &mut transaction).await?;
+        assert_eq!(forward, vec![2, 4, 3, 5, 1]);
+        assert_eq!(backward, forward);
+
+        transaction.rollback().await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_fetch() -> Result<(), Error> {
         let pool = Pool::from_env();
@@ -1790,6 +2468,42 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_fetch_wrong_type() -> Result<(), Error> {
+        let pool = Pool::from_env();
+        let mut transaction = pool.transaction().await?;
+
+        transaction
+            .client()
+            .query("DROP TABLE IF EXISTS users CASCADE", &[])
+            .await?;
+        transaction
+            .client()
+            .query(
+                "CREATE TABLE IF NOT EXISTS users (id VARCHAR, email VARCHAR, password VARCHAR);",
+                &[],
+            )
+            .await?;
+        transaction
+            .client()
+            .query(
+                "INSERT INTO users VALUES ('abc', 'test@test.com', 'not_encrypted');",
+                &[],
+            )
+            .await?;
+
+        match User::all().fetch_all(&mut transaction).await {
+            Err(Error::FromRow(error)) => {
+                assert_eq!(error.column, "id");
+                assert_eq!(error.pg_type.as_deref(), Some("varchar"));
+                assert_eq!(error.sql.as_deref(), Some(r#"SELECT * FROM "users""#));
+            }
+            result => panic!("expected a FromRow error, got {:?}", result.map(|_| ())),
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_explain() -> Result<(), Error> {
         let pool = Pool::from_env();
@@ -1856,6 +2570,42 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_bulk() {
+        let query = User::filter("email", "test@test.com").delete_all();
+        assert_eq!(
+            query.to_sql(),
+            r#"DELETE FROM "users" WHERE "users"."email" = $1 RETURNING *"#
+        );
+        assert!(query.check_full_table().is_ok());
+
+        let query = User::all()
+            .filter_lt("id", 5)
+            .update_all(&[("email", "test@test.com"), ("password", "secret")]);
+        assert_eq!(
+            query.to_sql(),
+            r#"UPDATE "users" SET "email" = $2, "password" = $3 WHERE "users"."id" < $1 RETURNING *"#
+        );
+
+        let query = User::all().delete_all();
+        assert_eq!(query.to_sql(), r#"DELETE FROM "users" RETURNING *"#);
+        assert!(matches!(
+            query.check_full_table(),
+            Err(Error::FullTable {
+                statement: "DELETE",
+                ..
+            })
+        ));
+        assert!(query.allow_full_table().check_full_table().is_ok());
+
+        let query = User::all().update_all(&[("password", "secret")]);
+        assert!(query.check_full_table().is_err());
+        assert!(query.allow_full_table().check_full_table().is_ok());
+
+        // Saving a record is never a full table update.
+        assert!(User::default().save().check_full_table().is_ok());
+    }
+
     // #[test]
     // fn test_or() {
     //     let query = User::all()
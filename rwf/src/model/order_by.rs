@@ -1,4 +1,25 @@
 //! Implements the `ORDER BY` SQL primitive.
+//!
+//! Columns are ordered with a direction and, optionally, where `NULL`s go:
+//!
+//! ```
+//! # use rwf::prelude::*;
+//! # use rwf::model::{Asc, Desc, ToOrderSpec};
+//! # #[derive(Clone, macros::Model)]
+//! # struct User {
+//! #    id: Option<i64>,
+//! #    name: String,
+//! #    last_seen: Option<OffsetDateTime>,
+//! # }
+//! let users = User::all()
+//!     .order(("last_seen", Desc).nulls_last())
+//!     .order(("name", Asc));
+//!
+//! assert_eq!(
+//!     users.to_sql(),
+//!     r#"SELECT * FROM "users" ORDER BY "users"."last_seen" DESC NULLS LAST, "users"."name" ASC"#
+//! );
+//! ```
 use super::{Column, Escape, ToSql};
 
 #[derive(Debug, Clone)]
@@ -6,6 +27,21 @@ pub enum OrderColumn {
     Asc(Column),
     Desc(Column),
     Raw(String),
+    Spec(OrderSpec),
+}
+
+impl OrderColumn {
+    /// Same column in the opposite order. Raw expressions can't be reversed and are returned unchanged.
+    pub fn reverse(&self) -> Self {
+        use OrderColumn::*;
+
+        match self {
+            Asc(column) => Desc(column.clone()),
+            Desc(column) => Asc(column.clone()),
+            Raw(raw) => Raw(raw.clone()),
+            Spec(spec) => Spec(spec.reverse()),
+        }
+    }
 }
 
 impl ToSql for OrderColumn {
@@ -16,10 +52,184 @@ impl ToSql for OrderColumn {
             Asc(column) => format!("{} ASC", column.to_sql()),
             Desc(column) => format!("{} DESC", column.to_sql()),
             Raw(raw) => raw.clone(),
+            Spec(spec) => spec.to_sql(),
+        }
+    }
+}
+
+/// Direction of the ordering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Smallest values first.
+    Asc,
+    /// Largest values first.
+    Desc,
+}
+
+impl Direction {
+    /// The opposite direction.
+    pub fn reverse(self) -> Self {
+        match self {
+            Direction::Asc => Direction::Desc,
+            Direction::Desc => Direction::Asc,
+        }
+    }
+}
+
+/// Where `NULL` values go. By default, Postgres puts them last in ascending order and first in descending order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+impl Nulls {
+    fn reverse(self) -> Self {
+        match self {
+            Nulls::First => Nulls::Last,
+            Nulls::Last => Nulls::First,
+        }
+    }
+}
+
+/// Ordering of a single column.
+///
+/// Columns of the model's table are checked against [`Model::column_names`](super::Model::column_names)
+/// before the query runs. Columns of joined tables aren't; use [`OrderSpec::unchecked`] for columns
+/// the model doesn't know about, e.g. computed by the query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderSpec {
+    column: Column,
+    direction: Direction,
+    nulls: Option<Nulls>,
+    checked: bool,
+}
+
+impl OrderSpec {
+    /// Order by the column in the direction.
+    pub fn new(column: Column, direction: Direction) -> Self {
+        Self {
+            column,
+            direction,
+            nulls: None,
+            checked: true,
+        }
+    }
+
+    /// Put `NULL`s first.
+    pub fn nulls_first(mut self) -> Self {
+        self.nulls = Some(Nulls::First);
+        self
+    }
+
+    /// Put `NULL`s last.
+    pub fn nulls_last(mut self) -> Self {
+        self.nulls = Some(Nulls::Last);
+        self
+    }
+
+    /// Don't check that the column exists in the model.
+    pub fn unchecked(mut self) -> Self {
+        self.checked = false;
+        self
+    }
+
+    /// The column.
+    pub fn column(&self) -> &Column {
+        &self.column
+    }
+
+    /// The direction.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Where `NULL`s go, if set.
+    pub fn nulls(&self) -> Option<Nulls> {
+        self.nulls
+    }
+
+    /// The column should be checked against the model's columns.
+    pub fn checked(&self) -> bool {
+        self.checked
+    }
+
+    /// Qualify the column with the table name, if it isn't already.
+    pub(crate) fn qualify(mut self, table_name: &str) -> Self {
+        if !self.column.qualified() {
+            self.column = self.column.qualify(table_name);
+        }
+        self
+    }
+
+    /// The opposite ordering, e.g. `DESC NULLS FIRST` for `ASC NULLS LAST`. The previous page
+    /// of a list is the next page in the opposite ordering.
+    pub fn reverse(&self) -> Self {
+        Self {
+            column: self.column.clone(),
+            direction: self.direction.reverse(),
+            nulls: self.nulls.map(|nulls| nulls.reverse()),
+            checked: self.checked,
         }
     }
 }
 
+impl ToSql for OrderSpec {
+    fn to_sql(&self) -> String {
+        let direction = match self.direction {
+            Direction::Asc => "ASC",
+            Direction::Desc => "DESC",
+        };
+
+        let nulls = match self.nulls {
+            Some(Nulls::First) => " NULLS FIRST",
+            Some(Nulls::Last) => " NULLS LAST",
+            None => "",
+        };
+
+        format!("{} {}{}", self.column.to_sql(), direction, nulls)
+    }
+}
+
+/// Convert a value to an [`OrderSpec`]. Implemented for `(&str, Direction)` and `(Column, Direction)`,
+/// so `NULL`s ordering can be chained on tuples, e.g. `("last_seen", Desc).nulls_last()`.
+pub trait ToOrderSpec: Sized {
+    fn to_order_spec(self) -> OrderSpec;
+
+    /// Put `NULL`s first.
+    fn nulls_first(self) -> OrderSpec {
+        self.to_order_spec().nulls_first()
+    }
+
+    /// Put `NULL`s last.
+    fn nulls_last(self) -> OrderSpec {
+        self.to_order_spec().nulls_last()
+    }
+
+    /// Don't check that the column exists in the model.
+    fn unchecked(self) -> OrderSpec {
+        self.to_order_spec().unchecked()
+    }
+}
+
+impl ToOrderSpec for OrderSpec {
+    fn to_order_spec(self) -> OrderSpec {
+        self
+    }
+}
+
+impl ToOrderSpec for (&str, Direction) {
+    fn to_order_spec(self) -> OrderSpec {
+        OrderSpec::new(Column::name(self.0), self.1)
+    }
+}
+
+impl ToOrderSpec for (Column, Direction) {
+    fn to_order_spec(self) -> OrderSpec {
+        OrderSpec::new(self.0, self.1)
+    }
+}
+
 pub trait ToOrderBy {
     fn to_order_by(&self) -> OrderBy;
 }
@@ -50,6 +260,26 @@ impl ToOrderBy for (&str, &str) {
     }
 }
 
+impl ToOrderBy for OrderSpec {
+    fn to_order_by(&self) -> OrderBy {
+        OrderBy {
+            order_by: vec![OrderColumn::Spec(self.clone())],
+        }
+    }
+}
+
+impl ToOrderBy for (&str, Direction) {
+    fn to_order_by(&self) -> OrderBy {
+        (*self).to_order_spec().to_order_by()
+    }
+}
+
+impl ToOrderBy for (Column, Direction) {
+    fn to_order_by(&self) -> OrderBy {
+        self.clone().to_order_spec().to_order_by()
+    }
+}
+
 impl ToOrderBy for (Column, &str) {
     fn to_order_by(&self) -> OrderBy {
         OrderBy {
@@ -83,6 +313,30 @@ impl OrderBy {
     pub fn is_empty(&self) -> bool {
         self.order_by.is_empty()
     }
+
+    /// The opposite ordering of all columns.
+    pub fn reverse(&self) -> Self {
+        Self {
+            order_by: self
+                .order_by
+                .iter()
+                .map(|column| column.reverse())
+                .collect(),
+        }
+    }
+
+    /// Typed orderings, or `None` if any column is ordered by a raw expression.
+    pub fn specs(&self) -> Option<Vec<OrderSpec>> {
+        self.order_by
+            .iter()
+            .map(|column| match column {
+                OrderColumn::Asc(column) => Some(OrderSpec::new(column.clone(), Direction::Asc)),
+                OrderColumn::Desc(column) => Some(OrderSpec::new(column.clone(), Direction::Desc)),
+                OrderColumn::Spec(spec) => Some(spec.clone()),
+                OrderColumn::Raw(_) => None,
+            })
+            .collect()
+    }
 }
 
 impl std::ops::Add for OrderBy {
@@ -120,4 +374,29 @@ mod test {
         let _order_by = "created_at ASC".to_order_by();
         let _order_by = ["created_at", "ASC"].to_order_by();
     }
+
+    #[test]
+    fn test_order_spec() {
+        use Direction::*;
+
+        let order_by = ("last_seen", Desc).nulls_last().to_order_by()
+            + ("name", Asc).to_order_by()
+            + (Column::new("teams", "name"), Asc)
+                .nulls_first()
+                .to_order_by();
+        assert_eq!(
+            order_by.to_sql(),
+            r#" ORDER BY "last_seen" DESC NULLS LAST, "name" ASC, "teams"."name" ASC NULLS FIRST"#
+        );
+        assert_eq!(
+            order_by.reverse().to_sql(),
+            r#" ORDER BY "last_seen" ASC NULLS FIRST, "name" DESC, "teams"."name" DESC NULLS LAST"#
+        );
+        assert_eq!(order_by.specs().unwrap().len(), 3);
+        assert!(!("name", Asc).unchecked().checked());
+
+        let raw = order_by + "random()".to_order_by();
+        assert!(raw.specs().is_none());
+        assert!(raw.reverse().to_sql().ends_with(", random()"));
+    }
 }
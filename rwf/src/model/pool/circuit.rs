@@ -0,0 +1,163 @@
+//! Circuit breaker, which stops the pool from connecting to a database that's down.
+//!
+//! After `threshold` connection failures in a row, the circuit opens: checkouts fail right away with
+//! [`Error::DatabaseUnavailable`] instead of each waiting to time out. While it's open, the pool tries to connect
+//! every `cooldown` in the background, and closes the circuit as soon as a connection succeeds.
+use parking_lot::Mutex;
+use tracing::{error, info};
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::Error;
+
+#[derive(Debug, Default)]
+struct State {
+    failures: usize,
+    open: bool,
+    probe_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: Mutex<State>,
+    threshold: usize,
+    cooldown: Duration,
+}
+
+/// Circuit breaker shared by all clones of a pool.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Inner>,
+}
+
+impl CircuitBreaker {
+    /// Create a closed circuit breaker. A `threshold` of `0` never opens it.
+    pub fn new(threshold: usize, cooldown: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State::default()),
+                threshold,
+                cooldown,
+            }),
+        }
+    }
+
+    /// Return an error if the circuit is open.
+    pub fn check(&self, database: &str) -> Result<(), Error> {
+        if self.is_open() {
+            Err(Error::DatabaseUnavailable {
+                database: database.to_string(),
+                retry_after: self.inner.cooldown,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// How often the pool tries to connect while the circuit is open.
+    pub fn cooldown(&self) -> Duration {
+        self.inner.cooldown
+    }
+
+    /// The database is considered down.
+    pub fn is_open(&self) -> bool {
+        self.inner.state.lock().open
+    }
+
+    /// A connection succeeded.
+    pub fn success(&self, database: &str) {
+        let mut state = self.inner.state.lock();
+
+        if state.open {
+            info!(r#"database "{}" is available again"#, database);
+        }
+
+        *state = State::default();
+    }
+
+    /// A connection failed.
+    pub fn failure(&self, database: &str) {
+        let mut state = self.inner.state.lock();
+        state.failures += 1;
+
+        if state.open {
+            state.probe_at = Some(Instant::now() + self.inner.cooldown);
+        } else if self.inner.threshold > 0 && state.failures >= self.inner.threshold {
+            error!(
+                r#"database "{}" is unavailable after {} failed connections, retrying every {:.1}s"#,
+                database,
+                state.failures,
+                self.inner.cooldown.as_secs_f64()
+            );
+            state.open = true;
+            state.probe_at = Some(Instant::now() + self.inner.cooldown);
+        }
+    }
+
+    /// The circuit is open and it's time to try connecting again. Only one caller gets `true`
+    /// until the result of the probe is reported with [`CircuitBreaker::success`] or [`CircuitBreaker::failure`].
+    pub fn probe(&self) -> bool {
+        let mut state = self.inner.state.lock();
+
+        match state.probe_at {
+            Some(probe_at) if state.open && probe_at <= Instant::now() => {
+                state.probe_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let circuit = CircuitBreaker::new(3, Duration::from_millis(50));
+
+        circuit.failure("main");
+        circuit.failure("main");
+        assert!(circuit.check("main").is_ok());
+        assert!(!circuit.probe());
+
+        // A success resets the count.
+        circuit.success("main");
+        circuit.failure("main");
+        circuit.failure("main");
+        assert!(circuit.check("main").is_ok());
+
+        circuit.failure("main");
+        assert!(matches!(
+            circuit.check("main"),
+            Err(Error::DatabaseUnavailable { ref database, retry_after })
+                if database == "main" && retry_after == Duration::from_millis(50)
+        ));
+
+        // Not time to probe yet.
+        assert!(!circuit.probe());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(circuit.probe());
+        // Only one probe at a time.
+        assert!(!circuit.probe());
+
+        circuit.failure("main");
+        assert!(circuit.is_open());
+        assert!(!circuit.probe());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(circuit.probe());
+
+        circuit.success("main");
+        assert!(!circuit.is_open());
+        assert!(circuit.check("main").is_ok());
+
+        // Disabled.
+        let circuit = CircuitBreaker::new(0, Duration::from_millis(50));
+        for _ in 0..100 {
+            circuit.failure("main");
+        }
+        assert!(!circuit.is_open());
+    }
+}
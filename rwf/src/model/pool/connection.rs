@@ -92,29 +92,50 @@ impl Connection {
         query: &str,
         params: &[&(dyn ToSql + Sync)],
     ) -> Result<Vec<Row>, Error> {
-        let statement = if let Some(statement) = self.cache.get(query) {
-            statement
+        let statement = self.prepare_cached(query).await?;
+
+        self.client()
+            .query(&statement, params)
+            .await
+            .map_err(|err| self.database_error(err))
+    }
+
+    /// Execute the statement against the database, preparing it if we haven't seen it before
+    /// on this connection. Returns the number of rows affected by the statement.
+    pub async fn execute_cached(
+        &mut self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, Error> {
+        let statement = self.prepare_cached(query).await?;
+
+        self.client()
+            .execute(&statement, params)
+            .await
+            .map_err(|err| self.database_error(err))
+    }
+
+    async fn prepare_cached(&mut self, query: &str) -> Result<Statement, Error> {
+        if let Some(statement) = self.cache.get(query) {
+            Ok(statement.clone())
         } else {
             let statement = self.client().prepare(query).await?;
-            self.cache.insert(query.to_string(), statement);
-            &self.cache[query]
-        };
+            self.cache.insert(query.to_string(), statement.clone());
+            Ok(statement)
+        }
+    }
 
-        match self.client().query(statement, &params).await {
-            Ok(rows) => Ok(rows),
-            Err(err) => {
-                // If schema changed, we better close this connection entirely
-                // than evicting prepared statements one by one.
-                // TODO: find and use the error code instead of using the English
-                // error message which will be translated on databases running in other locales.
-                if let Some(db_error) = err.as_db_error() {
-                    if db_error.message() == "cached plan must not change result type" {
-                        self.inner.bad.store(true, Ordering::Relaxed);
-                    }
-                }
-                Err(Error::DatabaseError(err))
+    fn database_error(&self, err: tokio_postgres::Error) -> Error {
+        // If schema changed, we better close this connection entirely
+        // than evicting prepared statements one by one.
+        // TODO: find and use the error code instead of using the English
+        // error message which will be translated on databases running in other locales.
+        if let Some(db_error) = err.as_db_error() {
+            if db_error.message() == "cached plan must not change result type" {
+                self.inner.bad.store(true, Ordering::Relaxed);
             }
         }
+        Error::DatabaseError(err)
     }
 
     /// Is the connection broken?
@@ -11,6 +11,9 @@
 //!
 //! This implementation uses FIFO to increase connection re-use.
 //!
+//! If the database goes down, the pool's [circuit breaker](circuit) stops it from trying to connect
+//! after a few failures, so requests fail right away with [`Error::DatabaseUnavailable`] until the database is back.
+//!
 //! ## Get a connection
 //!
 //! ```ignore
@@ -30,7 +33,7 @@ use tokio::time::{sleep, timeout, Duration};
 
 use parking_lot::Mutex;
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::ops::{Deref, DerefMut};
 use std::sync::{
@@ -39,19 +42,25 @@ use std::sync::{
 };
 use std::time::Instant;
 
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 
-use crate::config::get_config;
+use crate::config::{get_config, DatabaseConfig};
 
+pub mod circuit;
 pub mod connection;
 pub mod transaction;
 
-use super::Error;
+use super::{DatabaseErrorKind, Error};
 
+pub use circuit::CircuitBreaker;
 pub use connection::Connection;
 pub use transaction::Transaction;
 
 static POOL: OnceCell<Pool> = OnceCell::new();
+static POOLS: Lazy<Mutex<HashMap<String, Pool>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Name of the database used by models, unless they specify another one with [`crate::model::Model::database`].
+pub const DEFAULT_DATABASE: &str = "main";
 
 /// Get the connection pool.
 ///
@@ -60,6 +69,27 @@ pub fn get_pool() -> Pool {
     POOL.get_or_init(|| Pool::from_env()).clone()
 }
 
+/// Use this pool for all queries, instead of the one configured in the settings.
+///
+/// Must be called before the pool is used. If the pool is already set, it's returned as an error.
+pub fn set_pool(pool: Pool) -> Result<(), Pool> {
+    POOL.set(pool)
+}
+
+/// Use this pool for all queries to the named database, instead of the one configured in the settings.
+///
+/// The pool of the main database can only be set before it's used, see [`set_pool`].
+pub fn set_named_pool(database: &str, mut pool: Pool) -> Result<(), Pool> {
+    pool.database = database.to_string();
+
+    if database == DEFAULT_DATABASE {
+        POOL.set(pool)
+    } else {
+        POOLS.lock().insert(database.to_string(), pool);
+        Ok(())
+    }
+}
+
 /// Get a connection from the pool.
 ///
 /// Use [`Pool::connection`] instead.
@@ -80,6 +110,7 @@ pub struct ConnectionGuard {
     connection: Option<Connection>,
     pool: Pool,
     rollback: bool,
+    transaction: bool,
 }
 
 impl ConnectionGuard {
@@ -95,6 +126,7 @@ impl ConnectionGuard {
             connection: Some(connection),
             pool,
             rollback: false,
+            transaction: false,
         }
     }
 
@@ -102,6 +134,16 @@ impl ConnectionGuard {
         self.rollback = true;
     }
 
+    /// Name of the database this connection is to.
+    pub fn database(&self) -> &str {
+        self.pool.database()
+    }
+
+    /// The connection is used by a [`Transaction`].
+    pub fn in_transaction(&self) -> bool {
+        self.transaction
+    }
+
     /// Get a reference to the underlying database connection.
     pub fn connection(&self) -> &Connection {
         self.connection.as_ref().unwrap()
@@ -167,6 +209,12 @@ pub struct PoolConfig {
 
     /// Maximum time a connection remains open and available while not in use.
     pub idle_timeout: Duration,
+
+    /// Number of connection failures in a row which open the circuit breaker. `0` disables it.
+    pub failure_threshold: usize,
+
+    /// How often to try connecting again while the circuit breaker is open.
+    pub recovery_interval: Duration,
 }
 
 impl Default for PoolConfig {
@@ -175,6 +223,8 @@ impl Default for PoolConfig {
             pool_size: 10,
             checkout_timeout: Duration::from_secs(5),
             idle_timeout: Duration::from_secs(3600),
+            failure_threshold: 5,
+            recovery_interval: Duration::from_secs(5),
         }
     }
 }
@@ -184,10 +234,12 @@ impl Default for PoolConfig {
 pub struct Pool {
     inner: Arc<Mutex<PoolInner>>,
     checkin_notify: Arc<Notify>,
-    database_url: String,
-    config: PoolConfig,
+    database_url: Arc<Mutex<String>>,
+    config: Arc<PoolConfig>,
+    circuit: CircuitBreaker,
     shutdown: Arc<Notify>,
     ref_count: Arc<AtomicUsize>,
+    database: String,
 }
 
 impl Clone for Pool {
@@ -197,8 +249,10 @@ impl Clone for Pool {
             checkin_notify: self.checkin_notify.clone(),
             database_url: self.database_url.clone(),
             config: self.config.clone(),
+            circuit: self.circuit.clone(),
             shutdown: self.shutdown.clone(),
             ref_count: self.ref_count.clone(),
+            database: self.database.clone(),
         };
 
         self.ref_count.fetch_add(1, Ordering::SeqCst);
@@ -222,17 +276,25 @@ impl Pool {
                 expected: 0,
             })),
             checkin_notify: Arc::new(Notify::new()),
-            database_url: database_url.to_string(),
-            config,
+            database_url: Arc::new(Mutex::new(database_url.to_string())),
+            circuit: CircuitBreaker::new(config.failure_threshold, config.recovery_interval),
+            config: Arc::new(config),
             shutdown: Arc::new(Notify::new()),
             ref_count: Arc::new(AtomicUsize::new(1)),
+            database: DEFAULT_DATABASE.to_string(),
         };
 
         let maintenance = pool.clone();
         tokio::spawn(async move {
+            let interval = maintenance
+                .config
+                .recovery_interval
+                .clamp(Duration::from_millis(10), Duration::from_secs(1));
+
             loop {
                 maintenance.maintenance();
-                sleep(Duration::from_secs(1)).await;
+                maintenance.probe().await;
+                sleep(interval).await;
             }
         });
 
@@ -248,20 +310,78 @@ impl Pool {
     /// * `pool_size` - Maximum number of connections.
     ///
     pub fn from_env() -> Self {
-        let config = get_config().database.clone();
-        let database_url = config.database_url();
+        let config = get_config()
+            .database
+            .named(DEFAULT_DATABASE)
+            .unwrap_or_default();
+        Self::from_config(&config.database_url(), &config)
+    }
+
+    /// Create new connection pool to the database at this URL,
+    /// using the pool settings from the configuration.
+    pub fn from_url(database_url: &str) -> Self {
+        Self::from_config(database_url, &get_config().database)
+    }
+
+    /// Get the pool of a database configured in a `[database.<name>]` section.
+    /// Pools are created when they are first used.
+    pub fn named(database: &str) -> Result<Self, Error> {
+        if let Some(pool) = POOLS.lock().get(database) {
+            return Ok(pool.clone());
+        }
+
+        if database == DEFAULT_DATABASE {
+            return Ok(get_pool());
+        }
+
+        let config = get_config()
+            .database
+            .named(database)
+            .ok_or_else(|| Error::UnknownDatabase(database.to_string()))?;
+
+        let mut pool = Self::from_config(&config.database_url(), &config);
+        pool.database = database.to_string();
+
+        Ok(POOLS
+            .lock()
+            .entry(database.to_string())
+            .or_insert(pool)
+            .clone())
+    }
+
+    /// Name of the database this pool connects to.
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    fn from_config(database_url: &str, config: &DatabaseConfig) -> Self {
         Self::new(
-            &database_url,
+            database_url,
             PoolConfig {
                 pool_size: config.pool_size,
                 idle_timeout: config.idle_timeout().unsigned_abs(),
                 checkout_timeout: config.checkout_timeout().unsigned_abs(),
+                failure_threshold: config.circuit_breaker_threshold,
+                recovery_interval: config.circuit_breaker_cooldown().unsigned_abs(),
             },
         )
     }
 
+    /// The circuit breaker is closed, i.e. the database wasn't found to be down.
+    pub fn available(&self) -> bool {
+        !self.circuit.is_open()
+    }
+
+    /// Circuit breaker of this pool.
+    pub fn circuit(&self) -> &CircuitBreaker {
+        &self.circuit
+    }
+
     /// Get a connection from the pool or wait until one is available.
+    /// Fails right away with [`Error::DatabaseUnavailable`] if the circuit breaker is open.
     pub async fn get(&self) -> Result<ConnectionGuard, Error> {
+        self.circuit.check(&self.database)?;
+
         match timeout(self.config.checkout_timeout, self.get_internal()).await {
             Ok(result) => result,
             Err(_) => {
@@ -307,6 +427,44 @@ impl Pool {
         Ok(result)
     }
 
+    /// Run the function in a transaction, and run it again in a new transaction if it fails
+    /// with a [retryable](super::Error::is_retryable) error, e.g. a serialization failure
+    /// of a `SERIALIZABLE` transaction. The function is run at most `retries + 1` times,
+    /// and must commit the transaction itself.
+    ///
+    /// ```rust,ignore
+    /// let order = Pool::pool()
+    ///     .with_transaction_retry(3, |mut transaction| async move {
+    ///         let order = Order::find(5).lock().fetch(&mut transaction).await?;
+    ///         // ...
+    ///         transaction.commit().await?;
+    ///         Ok(order)
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn with_transaction_retry<Fut, R>(
+        &self,
+        retries: usize,
+        mut f: impl FnMut(Transaction) -> Fut,
+    ) -> Result<R, Error>
+    where
+        Fut: Future<Output = Result<R, Error>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let transaction = self.transaction().await?;
+
+            match f(transaction).await {
+                Err(err) if err.is_retryable() && attempt < retries => {
+                    attempt += 1;
+                    tracing::warn!("retrying transaction ({}/{}): {}", attempt, retries, err);
+                }
+                result => return result,
+            }
+        }
+    }
+
     pub async fn with_connection<Fut, R>(
         &self,
         f: impl FnOnce(ConnectionGuard) -> Fut,
@@ -348,7 +506,7 @@ impl Pool {
             };
 
             if need_more {
-                match Connection::new(&self.database_url).await {
+                match self.connect().await {
                     Ok(connection) => return Ok(ConnectionGuard::new(connection, self.clone())),
                     Err(err) => {
                         {
@@ -364,6 +522,55 @@ impl Pool {
         }
     }
 
+    // Open a new connection, reporting the result to the circuit breaker.
+    async fn connect(&self) -> Result<Connection, Error> {
+        let database_url = self.database_url.lock().clone();
+
+        match Connection::new(&database_url).await {
+            Ok(connection) => {
+                self.circuit.success(&self.database);
+                Ok(connection)
+            }
+            Err(err) => {
+                // Errors like a wrong password won't be fixed by waiting.
+                if err.kind() == DatabaseErrorKind::ConnectionLost {
+                    self.circuit.failure(&self.database);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // Try to connect while the circuit breaker is open, and close it if the database is back.
+    async fn probe(&self) {
+        if !self.circuit.probe() {
+            return;
+        }
+
+        match timeout(self.config.checkout_timeout, self.connect()).await {
+            Ok(Ok(connection)) => {
+                let mut inner = self.inner.lock();
+                if inner.expected < self.config.pool_size {
+                    inner.expected += 1;
+                    inner.connections.push_back(connection);
+                }
+            }
+            Ok(Err(err)) => {
+                tracing::debug!("database still unavailable: {}", err);
+                // Schedule the next probe.
+                if err.kind() != DatabaseErrorKind::ConnectionLost {
+                    self.circuit.failure(&self.database);
+                }
+            }
+            Err(_) => self.circuit.failure(&self.database),
+        }
+    }
+
+    #[cfg(test)]
+    fn set_database_url(&self, database_url: &str) {
+        *self.database_url.lock() = database_url.to_string();
+    }
+
     fn checkin(&self, connection: Connection, drop: bool) {
         {
             let mut inner = self.inner.lock();
@@ -441,4 +648,73 @@ mod test {
 
         Ok(())
     }
+
+    // Nothing listens on this port.
+    const CLOSED_PORT: &str = "postgres://rwf@127.0.0.1:1/rwf";
+
+    fn outage_config() -> PoolConfig {
+        PoolConfig {
+            failure_threshold: 2,
+            recovery_interval: Duration::from_millis(50),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_opens() {
+        let pool = Pool::new(CLOSED_PORT, outage_config());
+
+        for _ in 0..2 {
+            let err = pool.get().await.err().unwrap();
+            assert_eq!(err.kind(), DatabaseErrorKind::ConnectionLost);
+            assert!(!matches!(err, Error::DatabaseUnavailable { .. }));
+        }
+
+        // Fails fast without connecting.
+        assert!(!pool.available());
+        let start = Instant::now();
+        let err = pool.get().await.err().unwrap();
+        assert!(
+            matches!(err, Error::DatabaseUnavailable { ref database, .. } if database == "main")
+        );
+        assert_eq!(err.retry_after(), Some(Duration::from_millis(50)));
+        assert!(start.elapsed() < Duration::from_millis(10));
+
+        // Probes keep failing.
+        sleep(Duration::from_millis(200)).await;
+        assert!(!pool.available());
+        assert!(pool.transaction().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_recovers() -> Result<(), Error> {
+        let database_url = get_config()
+            .database
+            .named(DEFAULT_DATABASE)
+            .unwrap_or_default()
+            .database_url();
+        let pool = Pool::new(&database_url, outage_config());
+        pool.get().await?;
+
+        // The database goes away.
+        pool.set_database_url(CLOSED_PORT);
+        pool.inner.lock().connections.clear();
+        pool.inner.lock().expected = 0;
+
+        assert!(pool.get().await.is_err());
+        assert!(pool.get().await.is_err());
+        assert!(matches!(
+            pool.get().await,
+            Err(Error::DatabaseUnavailable { .. })
+        ));
+
+        // And comes back.
+        pool.set_database_url(&database_url);
+        sleep(Duration::from_millis(200)).await;
+        assert!(pool.available());
+        let conn = pool.get().await?;
+        assert_eq!(conn.client().query("SELECT 1", &[]).await?.len(), 1);
+
+        Ok(())
+    }
 }
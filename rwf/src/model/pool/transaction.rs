@@ -23,6 +23,8 @@ impl Transaction {
             info!("BEGIN ({:.3} ms)", start.elapsed().as_secs_f64() * 1000.0);
         }
 
+        connection.transaction = true;
+
         Ok(Self {
             connection,
             rollback: true,
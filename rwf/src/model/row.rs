@@ -1,5 +1,6 @@
 //! Represents a single database row for raw queries.
-use super::{Error, FromRow, Model, Value};
+use super::{error::FromRowError, Error, FromRow, Model, Value};
+use tokio_postgres::types::FromSql;
 
 use std::{collections::HashMap, sync::Arc};
 
@@ -57,13 +58,65 @@ impl Row {
         let mut result = HashMap::new();
         for column in self.columns() {
             let name = column.name();
-            result.insert(name.to_string(), self.try_get(name)?);
+            result.insert(name.to_string(), self.get_column(name)?);
         }
 
         Ok(result)
     }
 }
 
+/// Read columns from a row, with errors describing what went wrong, e.g. which column has an unexpected type.
+///
+/// Used by `#[derive(FromRow)]` and `#[derive(Model)]`. Use it in manual implementations of [`FromRow`]:
+///
+/// ```
+/// use rwf::model::{FromRow, Error, GetColumn};
+///
+/// #[derive(Clone)]
+/// struct User {
+///     id: i64,
+///     email: String,
+/// }
+///
+/// impl FromRow for User {
+///     fn from_row(row: tokio_postgres::Row) -> Result<Self, Error> {
+///         Ok(User {
+///             id: row.get_column("id")?,
+///             email: row.get_column("email")?,
+///         })
+///     }
+/// }
+/// ```
+pub trait GetColumn {
+    /// Read the column, converting it to `T`.
+    fn get_column<'a, T: FromSql<'a>>(&'a self, column: &str) -> Result<T, Error>;
+}
+
+impl GetColumn for tokio_postgres::Row {
+    fn get_column<'a, T: FromSql<'a>>(&'a self, column: &str) -> Result<T, Error> {
+        self.try_get(column).map_err(|error| {
+            let pg_type = self
+                .columns()
+                .iter()
+                .find(|c| c.name() == column)
+                .map(|c| c.type_().to_string());
+
+            let reason = match std::error::Error::source(&error) {
+                Some(source) => source.to_string(),
+                None => error.to_string(),
+            };
+
+            Error::FromRow(Box::new(FromRowError {
+                column: column.to_string(),
+                rust_type: std::any::type_name::<T>(),
+                pg_type,
+                reason,
+                sql: None,
+            }))
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::super::{Query, ToSql};
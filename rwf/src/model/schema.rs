@@ -0,0 +1,665 @@
+//! Dump the database schema to a file, and check that the database matches it.
+//!
+//! The schema is read from the Postgres catalog and written as SQL: sequences, tables with their columns,
+//! constraints and indexes, each sorted by name, so the file only changes when the schema does. Committing
+//! the file next to the migrations makes schema changes easy to review.
+//!
+//! The schema is written after migrations run when `schema_dump` is set in the `[database]` section:
+//!
+//! ```toml
+//! [database]
+//! schema_dump = "db/structure.sql"
+//! ```
+//!
+//! In CI, [`verify`] checks that a freshly migrated database matches the committed file.
+//!
+//! Tables used internally by Rwf, which names start with `rwf_`, are not included.
+use super::{pool::DEFAULT_DATABASE, Error, Pool};
+use crate::config::get_config;
+
+use std::fmt::Write;
+use std::path::Path;
+
+use tracing::{error, info, warn};
+
+/// Schemas created by Postgres.
+static SYSTEM_SCHEMAS: &str =
+    "n.nspname NOT IN ('pg_catalog', 'information_schema') AND n.nspname NOT LIKE 'pg\\_%'";
+
+/// Prefix of tables used by Rwf.
+static INTERNAL_PREFIX: &str = "rwf_";
+
+/// Column of a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    /// Type as written in SQL, e.g. `character varying(255)`.
+    pub data_type: String,
+    pub not_null: bool,
+    pub default: Option<String>,
+    /// `GENERATED ALWAYS AS IDENTITY` or `GENERATED BY DEFAULT AS IDENTITY`.
+    pub identity: Option<String>,
+    /// Expression of a generated column.
+    pub generated: Option<String>,
+    /// Type which definition isn't part of the dump, e.g. an enum.
+    pub warning: Option<String>,
+}
+
+/// Table and its columns, in their order in the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub schema: String,
+    pub name: String,
+    pub columns: Vec<Column>,
+}
+
+/// Primary key, unique, foreign key, check or exclusion constraint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub schema: String,
+    pub table: String,
+    pub name: String,
+    pub definition: String,
+}
+
+/// Index not created by a constraint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub schema: String,
+    pub table: String,
+    pub name: String,
+    pub definition: String,
+}
+
+/// Sequence not created by an identity column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sequence {
+    pub schema: String,
+    pub name: String,
+    pub data_type: String,
+    pub start: i64,
+    pub increment: i64,
+    pub min: i64,
+    pub max: i64,
+    pub cycle: bool,
+}
+
+/// Database schema.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    pub sequences: Vec<Sequence>,
+    pub tables: Vec<Table>,
+    pub constraints: Vec<Constraint>,
+    pub indexes: Vec<Index>,
+}
+
+impl Schema {
+    /// Read the schema of the named database.
+    pub async fn fetch(database: &str) -> Result<Self, Error> {
+        let conn = Pool::named(database)?.get().await?;
+        let client = conn.client();
+
+        let sequences = client
+            .query(
+                &format!(
+                    "SELECT n.nspname, c.relname, format_type(s.seqtypid, NULL), s.seqstart, s.seqincrement, s.seqmin, s.seqmax, s.seqcycle
+                    FROM pg_sequence s
+                    JOIN pg_class c ON c.oid = s.seqrelid
+                    JOIN pg_namespace n ON n.oid = c.relnamespace
+                    WHERE {} AND NOT EXISTS (
+                        SELECT 1 FROM pg_depend d WHERE d.objid = c.oid AND d.deptype = 'i'
+                    )",
+                    SYSTEM_SCHEMAS
+                ),
+                &[],
+            )
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(Sequence {
+                    schema: row.try_get(0)?,
+                    name: row.try_get(1)?,
+                    data_type: row.try_get(2)?,
+                    start: row.try_get(3)?,
+                    increment: row.try_get(4)?,
+                    min: row.try_get(5)?,
+                    max: row.try_get(6)?,
+                    cycle: row.try_get(7)?,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let columns = client
+            .query(
+                &format!(
+                    "SELECT n.nspname, c.relname, a.attname, format_type(a.atttypid, a.atttypmod),
+                        a.attnotnull, pg_get_expr(d.adbin, d.adrelid), a.attidentity::text,
+                        a.attgenerated::text, t.typtype::text, t.typname
+                    FROM pg_attribute a
+                    JOIN pg_class c ON c.oid = a.attrelid
+                    JOIN pg_namespace n ON n.oid = c.relnamespace
+                    JOIN pg_type t ON t.oid = a.atttypid
+                    LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+                    WHERE c.relkind IN ('r', 'p') AND a.attnum > 0 AND NOT a.attisdropped AND {}
+                    ORDER BY n.nspname, c.relname, a.attnum",
+                    SYSTEM_SCHEMAS
+                ),
+                &[],
+            )
+            .await?;
+
+        let mut tables: Vec<Table> = vec![];
+
+        for row in columns {
+            let schema: String = row.try_get(0)?;
+            let table: String = row.try_get(1)?;
+            let expression: Option<String> = row.try_get(5)?;
+            let identity: String = row.try_get(6)?;
+            let generated: String = row.try_get(7)?;
+            let kind: String = row.try_get(8)?;
+            let type_name: String = row.try_get(9)?;
+
+            let identity = match identity.as_str() {
+                "a" => Some("GENERATED ALWAYS AS IDENTITY".to_string()),
+                "d" => Some("GENERATED BY DEFAULT AS IDENTITY".to_string()),
+                _ => None,
+            };
+
+            let (default, generated) = match generated.as_str() {
+                "" => (expression, None),
+                _ => (None, expression),
+            };
+
+            let column = Column {
+                name: row.try_get(2)?,
+                data_type: row.try_get(3)?,
+                not_null: row.try_get(4)?,
+                default,
+                identity,
+                generated,
+                warning: type_warning(&kind, &type_name),
+            };
+
+            match tables.last_mut() {
+                Some(last) if last.schema == schema && last.name == table => {
+                    last.columns.push(column)
+                }
+                _ => tables.push(Table {
+                    schema,
+                    name: table,
+                    columns: vec![column],
+                }),
+            }
+        }
+
+        let constraints = client
+            .query(
+                &format!(
+                    "SELECT n.nspname, c.relname, con.conname, pg_get_constraintdef(con.oid)
+                    FROM pg_constraint con
+                    JOIN pg_class c ON c.oid = con.conrelid
+                    JOIN pg_namespace n ON n.oid = c.relnamespace
+                    WHERE con.contype IN ('p', 'u', 'f', 'c', 'x') AND {}",
+                    SYSTEM_SCHEMAS
+                ),
+                &[],
+            )
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(Constraint {
+                    schema: row.try_get(0)?,
+                    table: row.try_get(1)?,
+                    name: row.try_get(2)?,
+                    definition: row.try_get(3)?,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let indexes = client
+            .query(
+                &format!(
+                    "SELECT n.nspname, t.relname, i.relname, pg_get_indexdef(i.oid)
+                    FROM pg_index x
+                    JOIN pg_class i ON i.oid = x.indexrelid
+                    JOIN pg_class t ON t.oid = x.indrelid
+                    JOIN pg_namespace n ON n.oid = i.relnamespace
+                    WHERE t.relkind IN ('r', 'p') AND {} AND NOT EXISTS (
+                        SELECT 1 FROM pg_constraint con WHERE con.conindid = x.indexrelid
+                    )",
+                    SYSTEM_SCHEMAS
+                ),
+                &[],
+            )
+            .await?
+            .into_iter()
+            .map(|row| {
+                Ok(Index {
+                    schema: row.try_get(0)?,
+                    table: row.try_get(1)?,
+                    name: row.try_get(2)?,
+                    definition: row.try_get(3)?,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let mut schema = Schema {
+            sequences,
+            tables,
+            constraints,
+            indexes,
+        };
+        schema.normalize();
+
+        Ok(schema)
+    }
+
+    /// Remove Rwf tables and sort everything by name, so the output doesn't
+    /// depend on the order the database returns rows in.
+    fn normalize(&mut self) {
+        self.sequences
+            .retain(|sequence| !sequence.name.starts_with(INTERNAL_PREFIX));
+        self.tables
+            .retain(|table| !table.name.starts_with(INTERNAL_PREFIX));
+        self.constraints
+            .retain(|constraint| !constraint.table.starts_with(INTERNAL_PREFIX));
+        self.indexes
+            .retain(|index| !index.table.starts_with(INTERNAL_PREFIX));
+
+        self.sequences
+            .sort_by(|a, b| (&a.schema, &a.name).cmp(&(&b.schema, &b.name)));
+        self.tables
+            .sort_by(|a, b| (&a.schema, &a.name).cmp(&(&b.schema, &b.name)));
+        self.constraints
+            .sort_by(|a, b| (&a.schema, &a.table, &a.name).cmp(&(&b.schema, &b.table, &b.name)));
+        self.indexes
+            .sort_by(|a, b| (&a.schema, &a.name).cmp(&(&b.schema, &b.name)));
+    }
+
+    /// Write the schema as SQL.
+    pub fn to_sql(&self) -> String {
+        let mut sql = String::from("-- Database schema generated by Rwf. Do not edit.\n");
+
+        for sequence in &self.sequences {
+            let _ = write!(
+                sql,
+                "\nCREATE SEQUENCE {} AS {} START WITH {} INCREMENT BY {} MINVALUE {} MAXVALUE {}{};\n",
+                qualified(&sequence.schema, &sequence.name),
+                sequence.data_type,
+                sequence.start,
+                sequence.increment,
+                sequence.min,
+                sequence.max,
+                if sequence.cycle { " CYCLE" } else { "" },
+            );
+        }
+
+        for table in &self.tables {
+            let _ = write!(
+                sql,
+                "\nCREATE TABLE {} (\n",
+                qualified(&table.schema, &table.name)
+            );
+
+            for (i, column) in table.columns.iter().enumerate() {
+                if let Some(ref warning) = column.warning {
+                    let _ = writeln!(sql, "    -- WARNING: {}", warning);
+                }
+
+                let _ = write!(sql, "    {} {}", quote(&column.name), column.data_type);
+
+                if let Some(ref default) = column.default {
+                    let _ = write!(sql, " DEFAULT {}", default);
+                }
+
+                if let Some(ref identity) = column.identity {
+                    let _ = write!(sql, " {}", identity);
+                }
+
+                if let Some(ref generated) = column.generated {
+                    let _ = write!(sql, " GENERATED ALWAYS AS ({}) STORED", generated);
+                }
+
+                if column.not_null {
+                    sql.push_str(" NOT NULL");
+                }
+
+                if i + 1 < table.columns.len() {
+                    sql.push(',');
+                }
+
+                sql.push('\n');
+            }
+
+            sql.push_str(");\n");
+        }
+
+        if !self.constraints.is_empty() {
+            sql.push('\n');
+        }
+
+        for constraint in &self.constraints {
+            let _ = writeln!(
+                sql,
+                "ALTER TABLE {} ADD CONSTRAINT {} {};",
+                qualified(&constraint.schema, &constraint.table),
+                quote(&constraint.name),
+                constraint.definition
+            );
+        }
+
+        if !self.indexes.is_empty() {
+            sql.push('\n');
+        }
+
+        for index in &self.indexes {
+            let _ = writeln!(sql, "{};", index.definition);
+        }
+
+        sql
+    }
+
+    /// Warnings about columns which types aren't included in the dump.
+    pub fn warnings(&self) -> Vec<String> {
+        self.tables
+            .iter()
+            .flat_map(|table| {
+                table.columns.iter().filter_map(move |column| {
+                    column.warning.as_ref().map(|warning| {
+                        format!(
+                            "{}.{}: {}",
+                            qualified(&table.schema, &table.name),
+                            quote(&column.name),
+                            warning
+                        )
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// Explain why a column type can't be recreated from the dump.
+fn type_warning(kind: &str, name: &str) -> Option<String> {
+    let kind = match kind {
+        "e" => "enum",
+        "c" => "composite type",
+        "d" => "domain",
+        "r" | "m" => "range type",
+        "p" => "pseudo-type",
+        _ => return None,
+    };
+
+    Some(format!(
+        "type \"{}\" ({}) is not included in the dump",
+        name, kind
+    ))
+}
+
+/// Quote an identifier, unless it's lowercase letters, digits and underscores only.
+fn quote(name: &str) -> String {
+    let plain = name
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_lowercase() || c == '_')
+        .unwrap_or(false)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+fn qualified(schema: &str, name: &str) -> String {
+    format!("{}.{}", quote(schema), quote(name))
+}
+
+/// Lines which differ between the expected and the actual schema, prefixed with `-` if
+/// they are missing from the database and `+` if they are not in the file.
+pub fn diff(expected: &str, actual: &str) -> Vec<String> {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    let missing = expected
+        .iter()
+        .filter(|line| !actual.contains(line))
+        .map(|line| format!("- {}", line));
+    let unexpected = actual
+        .iter()
+        .filter(|line| !expected.contains(line))
+        .map(|line| format!("+ {}", line));
+
+    missing.chain(unexpected).collect()
+}
+
+/// Write the schema of the `"main"` database to the file.
+pub async fn dump(path: impl AsRef<Path>) -> Result<(), Error> {
+    dump_database(DEFAULT_DATABASE, path).await
+}
+
+/// Write the schema of the named database to the file.
+pub async fn dump_database(database: &str, path: impl AsRef<Path>) -> Result<(), Error> {
+    let path = path.as_ref();
+    let schema = Schema::fetch(database).await?;
+
+    for warning in schema.warnings() {
+        warn!("schema dump: {}", warning);
+    }
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    tokio::fs::write(path, schema.to_sql()).await?;
+
+    info!(
+        r#"schema of database "{}" written to "{}""#,
+        database,
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Write the schema of the named database to the file configured with `schema_dump`, if any.
+pub(crate) async fn dump_configured(database: &str) -> Result<(), Error> {
+    let path = get_config()
+        .database
+        .named(database)
+        .and_then(|config| config.schema_dump);
+
+    match path {
+        Some(path) => dump_database(database, path).await,
+        None => Ok(()),
+    }
+}
+
+/// Check that the `"main"` database matches the schema in the file.
+pub async fn verify(path: impl AsRef<Path>) -> Result<(), Error> {
+    verify_database(DEFAULT_DATABASE, path).await
+}
+
+/// Check that the named database matches the schema in the file.
+pub async fn verify_database(database: &str, path: impl AsRef<Path>) -> Result<(), Error> {
+    let expected = tokio::fs::read_to_string(path.as_ref()).await?;
+    let actual = Schema::fetch(database).await?.to_sql();
+    let drift = diff(&expected, &actual);
+
+    if drift.is_empty() {
+        Ok(())
+    } else {
+        error!(
+            r#"database "{}" doesn't match "{}":"#,
+            database,
+            path.as_ref().display()
+        );
+        for line in &drift {
+            error!("{}", line);
+        }
+
+        Err(Error::SchemaDrift(drift))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn column(name: &str, data_type: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            not_null: false,
+            default: None,
+            identity: None,
+            generated: None,
+            warning: None,
+        }
+    }
+
+    fn schema() -> Schema {
+        Schema {
+            sequences: vec![Sequence {
+                schema: "public".into(),
+                name: "invoice_numbers".into(),
+                data_type: "bigint".into(),
+                start: 1000,
+                increment: 1,
+                min: 1,
+                max: i64::MAX,
+                cycle: false,
+            }],
+            tables: vec![
+                Table {
+                    schema: "public".into(),
+                    name: "users".into(),
+                    columns: vec![
+                        Column {
+                            identity: Some("GENERATED BY DEFAULT AS IDENTITY".into()),
+                            not_null: true,
+                            ..column("id", "bigint")
+                        },
+                        Column {
+                            not_null: true,
+                            ..column("email", "character varying(255)")
+                        },
+                        Column {
+                            warning: type_warning("e", "mood"),
+                            ..column("mood", "mood")
+                        },
+                        Column {
+                            default: Some("now()".into()),
+                            ..column("createdAt", "timestamp with time zone")
+                        },
+                    ],
+                },
+                Table {
+                    schema: "public".into(),
+                    name: "rwf_jobs".into(),
+                    columns: vec![column("id", "bigint")],
+                },
+                Table {
+                    schema: "billing".into(),
+                    name: "invoices".into(),
+                    columns: vec![
+                        column("user_id", "bigint"),
+                        Column {
+                            generated: Some("(total * 2)".into()),
+                            ..column("double", "integer")
+                        },
+                    ],
+                },
+            ],
+            constraints: vec![
+                Constraint {
+                    schema: "public".into(),
+                    table: "users".into(),
+                    name: "users_pkey".into(),
+                    definition: "PRIMARY KEY (id)".into(),
+                },
+                Constraint {
+                    schema: "billing".into(),
+                    table: "invoices".into(),
+                    name: "invoices_user_id_fkey".into(),
+                    definition: "FOREIGN KEY (user_id) REFERENCES users(id)".into(),
+                },
+            ],
+            indexes: vec![Index {
+                schema: "public".into(),
+                table: "users".into(),
+                name: "users_email_idx".into(),
+                definition: "CREATE INDEX users_email_idx ON public.users USING btree (email)"
+                    .into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_to_sql() {
+        let mut schema = schema();
+        schema.normalize();
+
+        assert_eq!(
+            schema.to_sql(),
+            r#"-- Database schema generated by Rwf. Do not edit.
+
+CREATE SEQUENCE public.invoice_numbers AS bigint START WITH 1000 INCREMENT BY 1 MINVALUE 1 MAXVALUE 9223372036854775807;
+
+CREATE TABLE billing.invoices (
+    user_id bigint,
+    double integer GENERATED ALWAYS AS ((total * 2)) STORED
+);
+
+CREATE TABLE public.users (
+    id bigint GENERATED BY DEFAULT AS IDENTITY NOT NULL,
+    email character varying(255) NOT NULL,
+    -- WARNING: type "mood" (enum) is not included in the dump
+    mood mood,
+    "createdAt" timestamp with time zone DEFAULT now()
+);
+
+ALTER TABLE billing.invoices ADD CONSTRAINT invoices_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id);
+ALTER TABLE public.users ADD CONSTRAINT users_pkey PRIMARY KEY (id);
+
+CREATE INDEX users_email_idx ON public.users USING btree (email);
+"#
+        );
+
+        assert_eq!(schema.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let mut a = schema();
+        let mut b = schema();
+        b.tables.reverse();
+        b.constraints.reverse();
+
+        a.normalize();
+        b.normalize();
+
+        assert_eq!(a.to_sql(), b.to_sql());
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut schema = schema();
+        schema.normalize();
+        let expected = schema.to_sql();
+        assert!(diff(&expected, &expected).is_empty());
+
+        schema.tables[1].columns[1].data_type = "text".into();
+        let drift = diff(&expected, &schema.to_sql());
+        assert_eq!(
+            drift,
+            vec![
+                "-     email character varying(255) NOT NULL,",
+                "+     email text NOT NULL,"
+            ]
+        );
+    }
+}
@@ -30,6 +30,8 @@ pub struct Select<T: FromRow + ?Sized> {
     pub joins: Joins,
     lock: Lock,
     group: bool,
+    // Number of values the keyset cursor had, if it didn't match the ordering.
+    invalid_cursor: Option<usize>,
     _phantom: PhantomData<T>,
 }
 
@@ -47,6 +49,7 @@ impl<T: FromRow> Select<T> {
             joins: Joins::default(),
             lock: Lock::default(),
             group: false,
+            invalid_cursor: None,
             _phantom: PhantomData,
         }
     }
@@ -178,6 +181,54 @@ impl<T: FromRow> Select<T> {
         self
     }
 
+    /// Keep rows after the cursor in the query's ordering, or before it if `before` is set.
+    /// The cursor has one value for each ordered column. If it doesn't, the query
+    /// returns an error when it's executed, see [`Select::invalid_cursor`].
+    ///
+    /// # Panics
+    ///
+    /// If the query is ordered by a raw expression.
+    pub fn keyset(mut self, cursor: &[Value], before: bool) -> Self {
+        let specs = self
+            .order_by
+            .specs()
+            .expect("keyset pagination requires ordering by columns, not raw expressions");
+
+        if specs.len() != cursor.len() {
+            self.invalid_cursor = Some(cursor.len());
+            return self;
+        }
+
+        // Rows before the cursor come after it in the opposite ordering.
+        let specs = specs
+            .into_iter()
+            .map(|spec| if before { spec.reverse() } else { spec })
+            .collect();
+        let values = cursor
+            .iter()
+            .map(|value| {
+                if value.is_null() {
+                    Value::Null
+                } else {
+                    self.placeholders.add(value)
+                }
+            })
+            .collect();
+
+        self.where_clause.concat(Filter::keyset(specs, values));
+
+        if before {
+            self.order_by = self.order_by.reverse();
+        }
+
+        self
+    }
+
+    /// Number of values in the keyset cursor, if it didn't have one value for each ordered column.
+    pub fn invalid_cursor(&self) -> Option<usize> {
+        self.invalid_cursor
+    }
+
     pub fn join(mut self, join: Join) -> Self {
         self.joins = self.joins.add(join);
         self.columns = self.columns.table_name(&self.table_name);
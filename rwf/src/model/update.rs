@@ -11,6 +11,8 @@ pub struct Update<T> {
     pub placeholders: Placeholders,
     columns: Vec<Column>,
     where_clause: WhereClause,
+    returning: bool,
+    allow_full_table: bool,
     marker: PhantomData<T>,
 }
 
@@ -22,6 +24,8 @@ impl<T: Model> Update<T> {
             placeholders: Placeholders::new(),
             columns: vec![],
             where_clause: WhereClause::default(),
+            returning: true,
+            allow_full_table: false,
             marker: PhantomData,
         }
     }
@@ -58,6 +62,29 @@ impl<T: Model> Update<T> {
     }
 }
 
+impl<T> Update<T> {
+    /// Allow updating all rows in the table if the statement has no `WHERE` clause.
+    pub fn allow_full_table(mut self) -> Self {
+        self.allow_full_table = true;
+        self
+    }
+
+    /// The statement would update all rows in the table, without being allowed to.
+    pub fn full_table(&self) -> bool {
+        self.where_clause.is_empty() && !self.allow_full_table
+    }
+
+    /// Don't return the updated rows.
+    pub fn without_returning(mut self) -> Self {
+        self.returning = false;
+        self
+    }
+
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+}
+
 impl<T: Model> From<Select<T>> for Update<T> {
     fn from(select: Select<T>) -> Update<T> {
         let mut update = Update::empty();
@@ -82,10 +109,11 @@ impl<T: FromRow> ToSql for Update<T> {
             .join(", ");
 
         format!(
-            r#"UPDATE "{}" SET {}{} RETURNING *"#,
+            r#"UPDATE "{}" SET {}{}{}"#,
             self.table_name.escape(),
             sets,
             self.where_clause.to_sql(),
+            if self.returning { " RETURNING *" } else { "" },
         )
     }
 }
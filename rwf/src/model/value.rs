@@ -1,12 +1,13 @@
 //! Handles conversions between database types and Rust types.
 use bytes::BytesMut;
-use time::{OffsetDateTime, PrimitiveDateTime};
-use tokio_postgres::types::{to_sql_checked, IsNull, Type};
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, Type};
 use uuid::Uuid;
 
-use std::{net::IpAddr, ops::RangeInclusive};
+use std::{borrow::Cow, net::IpAddr, ops::Deref, ops::RangeInclusive};
 
 use super::{Column, Error, Escape, ToSql};
+use crate::timezone;
 
 /// A value that can be converted to and from the database.
 ///
@@ -130,27 +131,28 @@ impl ToValue for String {
     }
 }
 
-impl ToValue for &String {
+impl ToValue for str {
     fn to_value(&self) -> Value {
         Value::String(self.to_string())
     }
 }
 
-impl ToValue for Option<String> {
+impl<T: ToValue + ToOwned + ?Sized> ToValue for Cow<'_, T> {
     fn to_value(&self) -> Value {
-        Value::Optional(Box::new(self.as_ref().map(|v| v.to_value())))
+        self.as_ref().to_value()
     }
 }
 
-impl ToValue for Option<&str> {
+impl<T: ToValue + ?Sized> ToValue for &T {
     fn to_value(&self) -> Value {
-        Value::Optional(Box::new(self.map(|v| v.to_value())))
+        (*self).to_value()
     }
 }
 
-impl ToValue for &str {
+/// `None` is converted to `NULL`.
+impl<T: ToValue> ToValue for Option<T> {
     fn to_value(&self) -> Value {
-        Value::String(self.to_string())
+        Value::Optional(Box::new(self.as_ref().map(|v| v.to_value())))
     }
 }
 
@@ -172,24 +174,6 @@ impl ToValue for i16 {
     }
 }
 
-impl ToValue for Option<i64> {
-    fn to_value(&self) -> Value {
-        Value::Optional(Box::new(self.as_ref().map(|v| v.to_value())))
-    }
-}
-
-impl ToValue for Option<i32> {
-    fn to_value(&self) -> Value {
-        Value::Optional(Box::new(self.as_ref().map(|v| v.to_value())))
-    }
-}
-
-impl ToValue for Option<i16> {
-    fn to_value(&self) -> Value {
-        Value::Optional(Box::new(self.as_ref().map(|v| v.to_value())))
-    }
-}
-
 impl ToValue for f64 {
     fn to_value(&self) -> Value {
         Value::Float(*self)
@@ -208,63 +192,27 @@ impl ToValue for IpAddr {
     }
 }
 
-impl ToValue for Option<IpAddr> {
-    fn to_value(&self) -> Value {
-        Value::Optional(Box::new(self.as_ref().map(|v| v.to_value())))
-    }
-}
-
 impl ToValue for Uuid {
     fn to_value(&self) -> Value {
         Value::Uuid(self.clone())
     }
 }
 
-impl ToValue for Option<Uuid> {
-    fn to_value(&self) -> Value {
-        Value::Optional(Box::new(self.as_ref().map(|v| v.to_value())))
-    }
-}
-
 impl ToValue for Value {
     fn to_value(&self) -> Value {
         self.clone()
     }
 }
 
-impl ToValue for &[&str] {
+impl<T: ToValue> ToValue for [T] {
     fn to_value(&self) -> Value {
         Value::List(self.iter().map(|v| v.to_value()).collect::<Vec<_>>())
     }
 }
 
-impl ToValue for &[i64] {
+impl<T: ToValue> ToValue for Vec<T> {
     fn to_value(&self) -> Value {
-        Value::List(self.iter().map(|v| v.to_value()).collect::<Vec<_>>())
-    }
-}
-
-impl ToValue for &[i32] {
-    fn to_value(&self) -> Value {
-        Value::List(self.iter().map(|v| v.to_value()).collect::<Vec<_>>())
-    }
-}
-
-impl ToValue for &[i16] {
-    fn to_value(&self) -> Value {
-        Value::List(self.iter().map(|v| v.to_value()).collect::<Vec<_>>())
-    }
-}
-
-impl ToValue for &[f32] {
-    fn to_value(&self) -> Value {
-        Value::List(self.iter().map(|v| v.to_value()).collect::<Vec<_>>())
-    }
-}
-
-impl ToValue for &[f64] {
-    fn to_value(&self) -> Value {
-        Value::List(self.iter().map(|v| v.to_value()).collect::<Vec<_>>())
+        self.as_slice().to_value()
     }
 }
 
@@ -274,7 +222,7 @@ impl ToValue for Column {
     }
 }
 
-impl ToValue for RangeInclusive<i64> {
+impl<T: ToValue> ToValue for RangeInclusive<T> {
     fn to_value(&self) -> Value {
         Value::Range((
             Box::new(self.start().to_value()),
@@ -283,30 +231,6 @@ impl ToValue for RangeInclusive<i64> {
     }
 }
 
-impl ToValue for RangeInclusive<i32> {
-    fn to_value(&self) -> Value {
-        Value::Range((
-            Box::new(self.start().to_value()),
-            Box::new(self.end().to_value()),
-        ))
-    }
-}
-
-impl ToValue for RangeInclusive<i16> {
-    fn to_value(&self) -> Value {
-        Value::Range((
-            Box::new(self.start().to_value()),
-            Box::new(self.end().to_value()),
-        ))
-    }
-}
-
-impl ToValue for &[Value] {
-    fn to_value(&self) -> Value {
-        Value::List(self.to_vec())
-    }
-}
-
 impl ToValue for serde_json::Value {
     fn to_value(&self) -> Value {
         match self {
@@ -331,9 +255,9 @@ impl ToValue for OffsetDateTime {
     }
 }
 
-impl ToValue for Option<OffsetDateTime> {
+impl ToValue for PrimitiveDateTime {
     fn to_value(&self) -> Value {
-        Value::Optional(Box::new(self.as_ref().map(|v| v.to_value())))
+        Value::Timestamp(*self)
     }
 }
 
@@ -343,6 +267,200 @@ impl ToValue for bool {
     }
 }
 
+/// Convert a [`Value`] to a Rust type. This is the inverse of [`ToValue`].
+///
+/// Newtypes wrapping a type which already implements this trait can derive it,
+/// which also allows them to be used as model fields:
+///
+/// ```
+/// # use rwf::prelude::*;
+/// # use rwf::model::{FromValue, Value};
+/// #[derive(Clone, Copy, Debug, PartialEq, macros::ToValue, macros::FromValue)]
+/// struct UserId(i64);
+///
+/// #[derive(Clone, Copy, Debug, PartialEq, macros::ToValue, macros::FromValue)]
+/// struct OrderId(i64);
+///
+/// #[derive(Clone, macros::Model)]
+/// struct User {
+///     id: Option<UserId>,
+///     email: String,
+/// }
+///
+/// #[derive(Clone, macros::Model)]
+/// struct Order {
+///     id: Option<OrderId>,
+///     user_id: UserId,
+///     refunded_by: Option<UserId>,
+/// }
+///
+/// let order = Order { id: None, user_id: UserId(1), refunded_by: None };
+/// assert_eq!(order.values()[0], Value::Integer(1));
+///
+/// let user_id = UserId::from_value(Value::Integer(5)).unwrap();
+/// assert_eq!(user_id, UserId(5));
+/// ```
+pub trait FromValue: Sized {
+    /// Convert the value. Returns an error if the value has a different type.
+    fn from_value(value: Value) -> Result<Self, Error>;
+}
+
+fn from_value_error<T>(value: &Value) -> Error {
+    Error::ValueError(std::any::type_name::<T>(), format!("{:?}", value))
+}
+
+macro_rules! impl_from_value {
+    ($ty:ty, $($variant:ident),+) => {
+        impl FromValue for $ty {
+            fn from_value(value: Value) -> Result<Self, Error> {
+                match value {
+                    $(Value::$variant(value) => Ok(value.into()),)+
+                    Value::Optional(value) => match *value {
+                        Some(value) => Self::from_value(value),
+                        None => Err(from_value_error::<Self>(&Value::Null)),
+                    },
+                    value => Err(from_value_error::<Self>(&value)),
+                }
+            }
+        }
+    };
+}
+
+impl_from_value!(String, String);
+impl_from_value!(i64, Integer, BigInt, Int, SmallInt);
+impl_from_value!(i32, Int, SmallInt);
+impl_from_value!(i16, SmallInt);
+impl_from_value!(f64, Float, Real);
+impl_from_value!(f32, Real);
+impl_from_value!(bool, Boolean);
+impl_from_value!(OffsetDateTime, TimestampT);
+impl_from_value!(PrimitiveDateTime, Timestamp);
+impl_from_value!(IpAddr, IpAddr);
+impl_from_value!(Uuid, Uuid);
+impl_from_value!(serde_json::Value, Json);
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        Ok(value)
+    }
+}
+
+/// Timestamp stored without time zone, assumed to be in the time zone configured
+/// with the `default_timezone` setting.
+///
+/// Reading a `TIMESTAMP WITHOUT TIME ZONE` column into an [`OffsetDateTime`] is an error,
+/// since we don't know which time zone the timestamp is in. Use this type instead to opt
+/// into the conversion. `TIMESTAMPTZ` columns can be read into it as well. When saved, the timestamp
+/// is converted to the default time zone and stored without it.
+///
+/// # Example
+///
+/// ```
+/// use rwf::model::{AssumeTimezone, FromRow, Error, GetColumn};
+///
+/// #[derive(Clone)]
+/// struct Event {
+///     id: i64,
+///     happened_at: AssumeTimezone,
+/// }
+///
+/// impl FromRow for Event {
+///     fn from_row(row: tokio_postgres::Row) -> Result<Self, Error> {
+///         Ok(Self {
+///             id: row.get_column("id")?,
+///             happened_at: row.get_column("happened_at")?,
+///         })
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AssumeTimezone(pub OffsetDateTime);
+
+impl AssumeTimezone {
+    fn assume(timestamp: PrimitiveDateTime) -> Self {
+        Self(
+            timestamp
+                .assume_offset(timezone::default_offset())
+                .to_offset(UtcOffset::UTC),
+        )
+    }
+}
+
+impl Deref for AssumeTimezone {
+    type Target = OffsetDateTime;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<OffsetDateTime> for AssumeTimezone {
+    fn from(timestamp: OffsetDateTime) -> Self {
+        Self(timestamp)
+    }
+}
+
+impl<'a> FromSql<'a> for AssumeTimezone {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        if ty == &Type::TIMESTAMP {
+            Ok(Self::assume(PrimitiveDateTime::from_sql(ty, raw)?))
+        } else {
+            Ok(Self(OffsetDateTime::from_sql(ty, raw)?))
+        }
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        [Type::TIMESTAMP, Type::TIMESTAMPTZ].contains(ty)
+    }
+}
+
+impl FromValue for AssumeTimezone {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Timestamp(timestamp) => Ok(Self::assume(timestamp)),
+            Value::TimestampT(timestamp) => Ok(Self(timestamp)),
+            Value::Optional(value) => match *value {
+                Some(value) => Self::from_value(value),
+                None => Err(from_value_error::<Self>(&Value::Null)),
+            },
+            value => Err(from_value_error::<Self>(&value)),
+        }
+    }
+}
+
+impl ToValue for AssumeTimezone {
+    fn to_value(&self) -> Value {
+        let local = self.0.to_offset(timezone::default_offset());
+        Value::Timestamp(PrimitiveDateTime::new(local.date(), local.time()))
+    }
+}
+
+/// `NULL` is converted to `None`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Null => Ok(None),
+            Value::Optional(value) => match *value {
+                Some(value) => Ok(Some(T::from_value(value)?)),
+                None => Ok(None),
+            },
+            value => Ok(Some(T::from_value(value)?)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::List(values) => values.into_iter().map(T::from_value).collect(),
+            value => Err(from_value_error::<Self>(&value)),
+        }
+    }
+}
+
 impl tokio_postgres::types::ToSql for Value {
     fn to_sql(
         &self,
@@ -558,4 +676,102 @@ mod test {
 
         assert_eq!(value.to_sql(), r#""lower"('my string')"#);
     }
+
+    #[test]
+    fn test_references_and_options() {
+        let email = String::from("test@test.com");
+        assert_eq!((&email).to_value(), Value::String(email.clone()));
+        assert_eq!((&&email).to_value(), Value::String(email.clone()));
+        assert_eq!(
+            Cow::Borrowed(email.as_str()).to_value(),
+            Value::String(email.clone())
+        );
+
+        let now = OffsetDateTime::now_utc();
+        assert_eq!((&now).to_value(), Value::TimestampT(now));
+
+        assert!(Option::<&str>::None.to_value().is_null());
+        assert_eq!(
+            Some("hello").to_value(),
+            Value::Optional(Box::new(Some(Value::String("hello".into()))))
+        );
+
+        assert_eq!(
+            vec![1_i64, 2].to_value(),
+            Value::List(vec![Value::Integer(1), Value::Integer(2)])
+        );
+    }
+
+    #[test]
+    fn test_from_value() {
+        assert_eq!(i64::from_value(Value::Int(5)).unwrap(), 5);
+        assert_eq!(Option::<i64>::from_value(Value::Null).unwrap(), None);
+        assert_eq!(
+            Option::<i64>::from_value(Value::Optional(Box::new(None))).unwrap(),
+            None
+        );
+        assert_eq!(
+            Option::<String>::from_value(Value::String("a".into())).unwrap(),
+            Some("a".into())
+        );
+        assert!(i64::from_value(Value::Null).is_err());
+        assert!(String::from_value(Value::Integer(1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_round_trip() {
+        use crate::model::Pool;
+        use crate::view::Template;
+
+        let pool = Pool::from_env();
+        let conn = pool.get().await.unwrap();
+        conn.client()
+            .execute(
+                "CREATE TEMPORARY TABLE rwf_test_timestamps (
+                    created_at TIMESTAMPTZ NOT NULL,
+                    naive TIMESTAMP NOT NULL
+                )",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        // Postgres stores microseconds.
+        let created_at = OffsetDateTime::from_unix_timestamp_nanos(1_710_052_215_123_456_000)
+            .unwrap()
+            .to_offset(UtcOffset::from_hms(-5, 0, 0).unwrap());
+
+        let row = conn
+            .client()
+            .query_one(
+                "INSERT INTO rwf_test_timestamps VALUES ($1, $2) RETURNING *",
+                &[
+                    &created_at.to_value(),
+                    &AssumeTimezone(created_at).to_value(),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let loaded: OffsetDateTime = row.get("created_at");
+        assert_eq!(loaded, created_at);
+        assert_eq!(loaded.offset(), UtcOffset::UTC);
+
+        assert!(row.try_get::<_, OffsetDateTime>("naive").is_err());
+        let naive: AssumeTimezone = row.get("naive");
+        assert_eq!(*naive, created_at);
+        assert_eq!(
+            AssumeTimezone::from_value(Value::TimestampT(created_at)).unwrap(),
+            naive
+        );
+
+        let template = Template::from_str(
+            r#"<%= created_at %>|<%= created_at.in_tz("-05:00").rfc3339 %>|<%= created_at.format("[hour]:[minute]") %>|<%= created_at.timestamp %>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            template.render([("created_at", loaded)]).unwrap(),
+            "Sun, 10 Mar 2024 06:30:15 +0000|2024-03-10T01:30:15.123456-05:00|06:30|1710052215"
+        );
+    }
 }
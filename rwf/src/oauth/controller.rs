@@ -0,0 +1,337 @@
+//! Controllers for the redirect to the provider and the callback from it.
+use async_trait::async_trait;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use std::sync::Arc;
+
+use super::{client, id_token_claims, Claims, Error, Flow, Pending};
+use crate::controller::{Controller, Error as ControllerError, Session};
+use crate::http::{Request, Response};
+
+/// Token endpoint response. Some providers, e.g. GitHub, return errors with a `200 - OK`.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    id_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Redirects the user to the provider's sign in page.
+pub struct Authorize {
+    flow: Arc<Flow>,
+}
+
+impl Authorize {
+    pub(crate) fn new(flow: Arc<Flow>) -> Self {
+        Self { flow }
+    }
+}
+
+#[async_trait]
+impl Controller for Authorize {
+    async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+        let pending = Pending::new(self.flow.provider.name());
+
+        let mut session = request.session().cloned().unwrap_or_default();
+        pending.save(&mut session.payload)?;
+
+        let redirect_uri = format!("{}{}", request.base_url()?, self.flow.callback_path);
+        let url = self.flow.provider.authorization_url(
+            &redirect_uri,
+            pending.state(),
+            pending.nonce(),
+            &pending.challenge(),
+        );
+
+        Ok(Response::new().set_session(session).redirect(url))
+    }
+}
+
+/// Handles the user coming back from the provider: exchanges the code for tokens,
+/// calls the login hook, and logs the user in.
+pub struct Callback {
+    flow: Arc<Flow>,
+}
+
+impl Callback {
+    pub(crate) fn new(flow: Arc<Flow>) -> Self {
+        Self { flow }
+    }
+
+    async fn login(&self, request: &Request) -> Result<i64, Error> {
+        let provider = &self.flow.provider;
+        let query = request.path().query();
+
+        if let Some(error) = query.get::<String>("error") {
+            return Err(Error::Provider {
+                error,
+                description: query.get("error_description").unwrap_or_default(),
+            });
+        }
+
+        let state = query.get::<String>("state").unwrap_or_default();
+        let payload = request
+            .session()
+            .map(|session| session.payload.clone())
+            .unwrap_or_default();
+        let pending = Pending::load(&payload, provider.name(), &state)?;
+
+        let code = query.get::<String>("code").ok_or(Error::MissingCode)?;
+        let redirect_uri = format!("{}{}", request.base_url()?, self.flow.callback_path);
+
+        let tokens = client()
+            .post(provider.token_url())
+            .header("accept", "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", &redirect_uri),
+                ("client_id", provider.client_id()),
+                ("client_secret", provider.client_secret()),
+                ("code_verifier", pending.verifier()),
+            ])
+            .send()
+            .await?
+            .json::<TokenResponse>()
+            .await?;
+
+        if let Some(error) = tokens.error {
+            return Err(Error::Provider {
+                error,
+                description: tokens.error_description.unwrap_or_default(),
+            });
+        }
+
+        let claims = match (
+            tokens.id_token,
+            tokens.access_token,
+            provider.userinfo_url(),
+        ) {
+            (Some(id_token), _, _) => id_token_claims(&id_token, provider, pending.nonce())?,
+            (None, Some(access_token), Some(userinfo)) => {
+                let claims = client()
+                    .get(userinfo)
+                    .bearer_auth(access_token)
+                    .header("accept", "application/json")
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<serde_json::Value>()
+                    .await?;
+                Claims::new(provider.name(), claims)
+            }
+            _ => return Err(Error::NoClaims),
+        };
+
+        Ok(self.flow.hook.on_login(&claims).await?)
+    }
+}
+
+#[async_trait]
+impl Controller for Callback {
+    async fn handle(&self, request: &Request) -> Result<Response, ControllerError> {
+        let provider = self.flow.provider.name();
+
+        match self.login(request).await {
+            Ok(user_id) => {
+                info!(provider, user_id, "oauth sign in");
+
+                // Start a new session, so nothing set before signing in,
+                // e.g. by an attacker, carries over.
+                let session = Session::new_authenticated(serde_json::json!({}), user_id)?;
+                Ok(Response::new()
+                    .set_session(session)
+                    .redirect(&self.flow.after_login))
+            }
+
+            Err(err) => {
+                warn!(provider, error = %err, "oauth sign in failed");
+
+                let code = match err {
+                    Error::Client(_) => 502,
+                    Error::Login(_) => 500,
+                    _ => 400,
+                };
+
+                Ok(Response::error_page(code, "Sign in failed", err.message()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{test::id_token, OAuth, OnLogin, Provider};
+    use super::*;
+    use crate::http::request::test::dummy_ip;
+    use time::OffsetDateTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    struct Hook;
+
+    #[async_trait]
+    impl OnLogin for Hook {
+        async fn on_login(&self, claims: &Claims) -> Result<i64, ControllerError> {
+            assert_eq!(claims.email(), Some("user@example.com"));
+            Ok(42)
+        }
+    }
+
+    /// Token endpoint returning the body and the request it received.
+    async fn token_endpoint(body: String) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = vec![];
+            let mut buf = [0u8; 4096];
+
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&received).to_string();
+
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|l| l.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+
+            String::from_utf8_lossy(&received).to_string()
+        });
+
+        (url, handle)
+    }
+
+    async fn request(path: &str, session: Option<Session>) -> Request {
+        let head = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        Request::read(dummy_ip(), head.as_bytes())
+            .await
+            .unwrap()
+            .set_session(session)
+    }
+
+    #[tokio::test]
+    async fn test_flow() {
+        let (token_url, received) = token_endpoint(String::new()).await;
+        let provider = Provider::new("test", "client", "secret")
+            .issuer("https://issuer.example.com")
+            .authorization_endpoint("https://issuer.example.com/authorize")
+            .token_endpoint(&token_url)
+            .scopes(&["openid", "email"]);
+        let routes = OAuth::new(provider, Hook).routes();
+        assert_eq!(routes[0].path().base(), "/auth/test");
+        assert_eq!(routes[1].path().base(), "/auth/test/callback");
+
+        // Redirect to the provider.
+        let response = routes[0]
+            .handle(&request("/auth/test", None).await)
+            .await
+            .unwrap();
+        assert_eq!(response.status().code(), 302);
+        let location = response.headers().get("location").unwrap().clone();
+        assert!(location.starts_with("https://issuer.example.com/authorize?response_type=code&client_id=client&redirect_uri=http%3A%2F%2Flocalhost%2Fauth%2Ftest%2Fcallback&scope=openid+email&state="));
+        assert!(location.contains("&code_challenge_method=S256"));
+
+        let session = response.session().clone().unwrap();
+        let pending: Pending = serde_json::from_value(session.payload["oauth"].clone()).unwrap();
+        assert!(location.contains(&format!("&state={}&", pending.state())));
+        assert!(location.contains(&format!("&code_challenge={}&", pending.challenge())));
+
+        // Wrong state.
+        let response = routes[1]
+            .handle(
+                &request(
+                    "/auth/test/callback?code=abc&state=forged",
+                    Some(session.clone()),
+                )
+                .await,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status().code(), 400);
+        assert!(response.session().is_none());
+
+        // Provider error.
+        let response = routes[1]
+            .handle(
+                &request(
+                    "/auth/test/callback?error=access_denied",
+                    Some(session.clone()),
+                )
+                .await,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status().code(), 400);
+        assert!(String::from_utf8_lossy(response.body_bytes().unwrap())
+            .contains("Sign in was cancelled."));
+
+        // Successful sign in. Restart the token endpoint with the ID token for this nonce.
+        received.abort();
+        let (token_url, received) = token_endpoint(
+            serde_json::json!({
+                "access_token": "token",
+                "id_token": id_token(serde_json::json!({
+                    "iss": "https://issuer.example.com",
+                    "aud": "client",
+                    "exp": OffsetDateTime::now_utc().unix_timestamp() + 60,
+                    "nonce": pending.nonce(),
+                    "sub": "1",
+                    "email": "user@example.com",
+                })),
+            })
+            .to_string(),
+        )
+        .await;
+        let provider = Provider::new("test", "client", "secret")
+            .issuer("https://issuer.example.com")
+            .token_endpoint(&token_url);
+        let routes = OAuth::new(provider, Hook)
+            .after_login("/dashboard")
+            .routes();
+
+        let path = format!("/auth/test/callback?code=abc%2F1&state={}", pending.state());
+        let response = routes[1]
+            .handle(&request(&path, Some(session)).await)
+            .await
+            .unwrap();
+        assert_eq!(response.status().code(), 302);
+        assert_eq!(response.headers().get("location").unwrap(), "/dashboard");
+
+        let session = response.session().clone().unwrap();
+        assert_eq!(session.session_id.user_id(), Some(42));
+        assert!(session.payload.get("oauth").is_none());
+
+        let received = received.await.unwrap();
+        assert!(received.starts_with("POST /token HTTP/1.1"));
+        assert!(received.contains("grant_type=authorization_code"));
+        assert!(received.contains("code=abc%2F1"));
+        assert!(received.contains(&format!("code_verifier={}", pending.verifier())));
+    }
+}
@@ -0,0 +1,483 @@
+//! Sign in with Google, GitHub, and other OAuth 2.0 and OpenID Connect providers.
+//!
+//! Enable it with the `oauth` feature:
+//!
+//! ```toml
+//! rwf = { version = "0.1", features = ["oauth"] }
+//! ```
+//!
+//! Implement [`OnLogin`] to find or create the local user for the identity returned by the provider,
+//! and add the routes to the server:
+//!
+//! ```rust,ignore
+//! use rwf::oauth::{Claims, OAuth, OnLogin, Provider};
+//!
+//! struct FindOrCreateUser;
+//!
+//! #[rwf::async_trait]
+//! impl OnLogin for FindOrCreateUser {
+//!     async fn on_login(&self, claims: &Claims) -> Result<i64, Error> {
+//!         let user = User::find_or_create_by(&[("email", claims.email().unwrap_or_default())])
+//!             .fetch(&mut Pool::connection().await?)
+//!             .await?;
+//!         Ok(user.id.unwrap())
+//!     }
+//! }
+//!
+//! let google = Provider::google(var("GOOGLE_CLIENT_ID")?, var("GOOGLE_CLIENT_SECRET")?);
+//! let mut routes = vec![route!("/" => Index)];
+//! routes.extend(OAuth::new(google, FindOrCreateUser).routes());
+//! ```
+//!
+//! Visiting `/auth/google` redirects the user to the provider, which sends them back to `/auth/google/callback`.
+//! The sign in uses the authorization code flow with PKCE. The state, nonce, and PKCE verifier are kept in the session
+//! between the two requests.
+//!
+//! ID tokens are received directly from the provider over TLS, so their issuer, audience, expiration, and nonce are
+//! checked, but not their signature.
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use std::sync::Arc;
+
+use crate::crypto::random_string;
+use crate::http::Handler;
+
+pub mod controller;
+pub mod provider;
+
+pub use controller::{Authorize, Callback};
+pub use provider::Provider;
+
+/// How long the user has to sign in with the provider, in seconds.
+const SIGN_IN_TIMEOUT: i64 = 10 * 60;
+
+/// Key in the session payload holding the sign in in progress.
+const SESSION_KEY: &str = "oauth";
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .user_agent(concat!("rwf/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("http client")
+});
+
+pub(crate) fn client() -> &'static reqwest::Client {
+    &CLIENT
+}
+
+/// Sign in error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("http client error: {0}")]
+    Client(#[from] reqwest::Error),
+
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("http error: {0}")]
+    Http(#[from] crate::http::Error),
+
+    #[error("provider returned \"{error}\": {description}")]
+    Provider { error: String, description: String },
+
+    #[error("no sign in in progress for this session")]
+    NotStarted,
+
+    #[error("state doesn't match")]
+    StateMismatch,
+
+    #[error("sign in started more than {SIGN_IN_TIMEOUT} seconds ago")]
+    Expired,
+
+    #[error("authorization code is missing")]
+    MissingCode,
+
+    #[error("invalid ID token: {0}")]
+    IdToken(&'static str),
+
+    #[error("provider returned neither an ID token nor has a userinfo endpoint")]
+    NoClaims,
+
+    #[error("login hook error: {0}")]
+    Login(#[from] crate::controller::Error),
+}
+
+impl Error {
+    /// Message shown to the user. Details are logged instead, since they could
+    /// contain information useful to an attacker.
+    pub fn message(&self) -> &'static str {
+        match self {
+            Error::Provider { error, .. } if error == "access_denied" => "Sign in was cancelled.",
+            Error::Provider { error, .. } if error == "invalid_grant" => {
+                "The sign in link has expired. Please try again."
+            }
+            Error::NotStarted | Error::StateMismatch => {
+                "Your sign in was started in another window or browser. Please try again."
+            }
+            Error::Expired => "Your sign in took too long. Please try again.",
+            _ => "Something went wrong while signing you in. Please try again.",
+        }
+    }
+}
+
+/// Identity of the user returned by the provider, from the ID token or the userinfo endpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claims {
+    provider: String,
+    claims: serde_json::Value,
+}
+
+impl Claims {
+    /// Create claims returned by the provider.
+    pub fn new(provider: impl ToString, claims: serde_json::Value) -> Self {
+        Self {
+            provider: provider.to_string(),
+            claims,
+        }
+    }
+
+    /// Name of the provider, e.g. `google`.
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    /// Unique ID of the user at the provider. This is the `sub` claim in OpenID Connect,
+    /// or the `id` field returned by providers like GitHub.
+    pub fn subject(&self) -> Option<String> {
+        match self.claims.get("sub").or_else(|| self.claims.get("id"))? {
+            serde_json::Value::String(subject) => Some(subject.clone()),
+            serde_json::Value::Number(subject) => Some(subject.to_string()),
+            _ => None,
+        }
+    }
+
+    /// User's email, if the provider shared it.
+    pub fn email(&self) -> Option<&str> {
+        self.get("email")?.as_str()
+    }
+
+    /// The provider verified the user owns the email.
+    pub fn email_verified(&self) -> bool {
+        self.get("email_verified")
+            .and_then(|verified| verified.as_bool())
+            == Some(true)
+    }
+
+    /// User's full name, if the provider shared it.
+    pub fn name(&self) -> Option<&str> {
+        self.get("name")?.as_str()
+    }
+
+    /// Get any other claim.
+    pub fn get(&self, name: &str) -> Option<&serde_json::Value> {
+        self.claims.get(name)
+    }
+
+    /// All claims.
+    pub fn raw(&self) -> &serde_json::Value {
+        &self.claims
+    }
+}
+
+/// Hook called after the user signs in with the provider.
+#[async_trait]
+pub trait OnLogin: Send + Sync {
+    /// Find or create the local user for this identity and return their ID.
+    /// Returning an error aborts the sign in.
+    async fn on_login(&self, claims: &Claims) -> Result<i64, crate::controller::Error>;
+}
+
+/// Sign in flow for one provider.
+pub struct OAuth {
+    provider: Provider,
+    hook: Arc<dyn OnLogin>,
+    path: String,
+    after_login: String,
+}
+
+impl OAuth {
+    /// Create sign in flow for the provider. By default, it's served at `/auth/<provider name>`
+    /// and users are redirected to `/` after signing in.
+    pub fn new(provider: Provider, hook: impl OnLogin + 'static) -> Self {
+        Self {
+            path: format!("/auth/{}", provider.name()),
+            provider,
+            hook: Arc::new(hook),
+            after_login: "/".into(),
+        }
+    }
+
+    /// Serve the flow at this path instead. The callback is served at `<path>/callback`.
+    pub fn path(mut self, path: impl ToString) -> Self {
+        self.path = path.to_string().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Where to redirect the user after they sign in.
+    pub fn after_login(mut self, url: impl ToString) -> Self {
+        self.after_login = url.to_string();
+        self
+    }
+
+    /// Routes for the redirect to the provider and the callback from it.
+    pub fn routes(self) -> Vec<Handler> {
+        let callback_path = format!("{}/callback", self.path);
+        let flow = Arc::new(Flow {
+            provider: self.provider,
+            hook: self.hook,
+            callback_path: callback_path.clone(),
+            after_login: self.after_login,
+        });
+
+        vec![
+            Handler::route(&self.path, Authorize::new(flow.clone())),
+            Handler::route(&callback_path, Callback::new(flow)),
+        ]
+    }
+}
+
+/// Configuration shared by the two controllers.
+pub(crate) struct Flow {
+    pub(crate) provider: Provider,
+    pub(crate) hook: Arc<dyn OnLogin>,
+    pub(crate) callback_path: String,
+    pub(crate) after_login: String,
+}
+
+/// Sign in in progress, stored in the session between the redirect and the callback.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct Pending {
+    provider: String,
+    state: String,
+    nonce: String,
+    verifier: String,
+    started_at: i64,
+}
+
+impl Pending {
+    pub(crate) fn new(provider: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            state: random_string(32),
+            nonce: random_string(32),
+            verifier: random_string(64),
+            started_at: OffsetDateTime::now_utc().unix_timestamp(),
+        }
+    }
+
+    /// Save it in the session payload.
+    pub(crate) fn save(&self, payload: &mut serde_json::Value) -> Result<(), serde_json::Error> {
+        if !payload.is_object() {
+            *payload = serde_json::json!({});
+        }
+
+        payload[SESSION_KEY] = serde_json::to_value(self)?;
+        Ok(())
+    }
+
+    /// Load it from the session and check it belongs to this callback.
+    pub(crate) fn load(
+        payload: &serde_json::Value,
+        provider: &str,
+        state: &str,
+    ) -> Result<Self, Error> {
+        let pending = payload
+            .get(SESSION_KEY)
+            .and_then(|pending| serde_json::from_value::<Pending>(pending.clone()).ok())
+            .filter(|pending| pending.provider == provider)
+            .ok_or(Error::NotStarted)?;
+
+        if !constant_time_eq(pending.state.as_bytes(), state.as_bytes()) {
+            return Err(Error::StateMismatch);
+        }
+
+        if OffsetDateTime::now_utc().unix_timestamp() - pending.started_at > SIGN_IN_TIMEOUT {
+            return Err(Error::Expired);
+        }
+
+        Ok(pending)
+    }
+
+    pub(crate) fn state(&self) -> &str {
+        &self.state
+    }
+
+    pub(crate) fn nonce(&self) -> &str {
+        &self.nonce
+    }
+
+    pub(crate) fn verifier(&self) -> &str {
+        &self.verifier
+    }
+
+    /// PKCE code challenge, using the `S256` method.
+    pub(crate) fn challenge(&self) -> String {
+        challenge(&self.verifier)
+    }
+}
+
+fn challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Read the claims from the ID token and validate them.
+pub(crate) fn id_token_claims(
+    id_token: &str,
+    provider: &Provider,
+    nonce: &str,
+) -> Result<Claims, Error> {
+    let payload = id_token
+        .split('.')
+        .nth(1)
+        .ok_or(Error::IdToken("not a JWT"))?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|_| Error::IdToken("payload isn't base64"))?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload)?;
+
+    if let Some(issuer) = provider.expected_issuer() {
+        if claims.get("iss").and_then(|iss| iss.as_str()) != Some(issuer) {
+            return Err(Error::IdToken("issuer doesn't match"));
+        }
+    }
+
+    let audience = match claims.get("aud") {
+        Some(serde_json::Value::String(aud)) => aud == provider.client_id(),
+        Some(serde_json::Value::Array(aud)) => aud
+            .iter()
+            .any(|aud| aud.as_str() == Some(provider.client_id())),
+        _ => false,
+    };
+
+    if !audience {
+        return Err(Error::IdToken("audience doesn't match"));
+    }
+
+    match claims.get("exp").and_then(|exp| exp.as_i64()) {
+        Some(exp) if exp > OffsetDateTime::now_utc().unix_timestamp() => (),
+        _ => return Err(Error::IdToken("expired")),
+    }
+
+    match claims.get("nonce").and_then(|n| n.as_str()) {
+        Some(n) if constant_time_eq(n.as_bytes(), nonce.as_bytes()) => (),
+        _ => return Err(Error::IdToken("nonce doesn't match")),
+    }
+
+    Ok(Claims::new(provider.name(), claims))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    pub(crate) fn id_token(claims: serde_json::Value) -> String {
+        format!(
+            "{}.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[test]
+    fn test_challenge() {
+        // RFC 7636, Appendix B.
+        assert_eq!(
+            challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_pending() {
+        let pending = Pending::new("google");
+        let mut payload = serde_json::json!({"cart": 5});
+        pending.save(&mut payload).unwrap();
+        assert_eq!(payload["cart"], 5);
+
+        assert_eq!(
+            Pending::load(&payload, "google", pending.state()).unwrap(),
+            pending
+        );
+        assert!(matches!(
+            Pending::load(&payload, "google", "forged"),
+            Err(Error::StateMismatch)
+        ));
+        assert!(matches!(
+            Pending::load(&payload, "github", pending.state()),
+            Err(Error::NotStarted)
+        ));
+        assert!(matches!(
+            Pending::load(&serde_json::json!({}), "google", pending.state()),
+            Err(Error::NotStarted)
+        ));
+
+        payload[SESSION_KEY]["started_at"] = (pending.started_at - SIGN_IN_TIMEOUT - 1).into();
+        assert!(matches!(
+            Pending::load(&payload, "google", pending.state()),
+            Err(Error::Expired)
+        ));
+    }
+
+    #[test]
+    fn test_id_token() {
+        let provider = Provider::google("client", "secret");
+        let exp = OffsetDateTime::now_utc().unix_timestamp() + 60;
+        let valid = serde_json::json!({
+            "iss": "https://accounts.google.com",
+            "aud": "client",
+            "exp": exp,
+            "nonce": "nonce",
+            "sub": "1234",
+            "email": "user@example.com",
+            "email_verified": true,
+        });
+
+        let claims = id_token_claims(&id_token(valid.clone()), &provider, "nonce").unwrap();
+        assert_eq!(claims.provider(), "google");
+        assert_eq!(claims.subject().as_deref(), Some("1234"));
+        assert_eq!(claims.email(), Some("user@example.com"));
+        assert!(claims.email_verified());
+
+        for (claim, value, reason) in [
+            ("iss", "https://evil.com".into(), "issuer doesn't match"),
+            (
+                "aud",
+                serde_json::json!(["other"]),
+                "audience doesn't match",
+            ),
+            ("exp", (exp - 120).into(), "expired"),
+            ("nonce", "replayed".into(), "nonce doesn't match"),
+        ] {
+            let mut claims = valid.clone();
+            claims[claim] = value;
+            match id_token_claims(&id_token(claims), &provider, "nonce") {
+                Err(Error::IdToken(err)) => assert_eq!(err, reason),
+                result => panic!("{}: {:?}", claim, result),
+            }
+        }
+
+        assert!(id_token_claims("garbage", &provider, "nonce").is_err());
+    }
+
+    #[test]
+    fn test_messages() {
+        let cancelled = Error::Provider {
+            error: "access_denied".into(),
+            description: "".into(),
+        };
+        assert_eq!(cancelled.message(), "Sign in was cancelled.");
+        assert!(Error::MissingCode
+            .message()
+            .starts_with("Something went wrong"));
+    }
+}
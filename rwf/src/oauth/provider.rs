@@ -0,0 +1,186 @@
+//! OAuth 2.0 and OpenID Connect identity providers.
+use serde::Deserialize;
+
+use super::{client, Error};
+use crate::http::Url;
+
+/// Identity provider, e.g. Google or GitHub.
+///
+/// Providers that support OpenID Connect can be configured from their discovery document:
+///
+/// ```rust,ignore
+/// let provider = Provider::new("okta", client_id, client_secret)
+///     .discover("https://example.okta.com")
+///     .await?;
+/// ```
+///
+/// Other providers need their endpoints set manually.
+#[derive(Debug, Clone)]
+pub struct Provider {
+    name: String,
+    client_id: String,
+    client_secret: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+    issuer: Option<String>,
+    scopes: Vec<String>,
+}
+
+/// Subset of the OpenID Connect discovery document we use.
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+impl Provider {
+    /// Create a provider with the client credentials issued by it. The name is used in the
+    /// default routes, e.g. `/auth/google`, and is passed to the login hook.
+    pub fn new(
+        name: impl ToString,
+        client_id: impl ToString,
+        client_secret: impl ToString,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            authorization_endpoint: String::new(),
+            token_endpoint: String::new(),
+            userinfo_endpoint: None,
+            issuer: None,
+            scopes: vec![],
+        }
+    }
+
+    /// Sign in with Google. Uses OpenID Connect.
+    pub fn google(client_id: impl ToString, client_secret: impl ToString) -> Self {
+        Self::new("google", client_id, client_secret)
+            .issuer("https://accounts.google.com")
+            .authorization_endpoint("https://accounts.google.com/o/oauth2/v2/auth")
+            .token_endpoint("https://oauth2.googleapis.com/token")
+            .userinfo_endpoint("https://openidconnect.googleapis.com/v1/userinfo")
+            .scopes(&["openid", "email", "profile"])
+    }
+
+    /// Sign in with GitHub. GitHub doesn't support OpenID Connect, so the user's
+    /// profile is fetched from the API instead.
+    pub fn github(client_id: impl ToString, client_secret: impl ToString) -> Self {
+        Self::new("github", client_id, client_secret)
+            .authorization_endpoint("https://github.com/login/oauth/authorize")
+            .token_endpoint("https://github.com/login/oauth/access_token")
+            .userinfo_endpoint("https://api.github.com/user")
+            .scopes(&["read:user", "user:email"])
+    }
+
+    /// Fetch the endpoints from the issuer's OpenID Connect discovery document,
+    /// `/.well-known/openid-configuration`. Adds the `openid` scope if it's missing.
+    pub async fn discover(self, issuer: &str) -> Result<Self, Error> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+
+        let discovery = client()
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Discovery>()
+            .await?;
+
+        let mut provider = self
+            .issuer(discovery.issuer)
+            .authorization_endpoint(discovery.authorization_endpoint)
+            .token_endpoint(discovery.token_endpoint);
+        provider.userinfo_endpoint = discovery.userinfo_endpoint;
+
+        if !provider.scopes.iter().any(|scope| scope == "openid") {
+            provider.scopes.insert(0, "openid".into());
+        }
+
+        Ok(provider)
+    }
+
+    /// Set the URL users are redirected to for signing in.
+    pub fn authorization_endpoint(mut self, url: impl ToString) -> Self {
+        self.authorization_endpoint = url.to_string();
+        self
+    }
+
+    /// Set the URL used to exchange the authorization code for tokens.
+    pub fn token_endpoint(mut self, url: impl ToString) -> Self {
+        self.token_endpoint = url.to_string();
+        self
+    }
+
+    /// Set the URL used to fetch the user's profile if the provider
+    /// doesn't return an ID token.
+    pub fn userinfo_endpoint(mut self, url: impl ToString) -> Self {
+        self.userinfo_endpoint = Some(url.to_string());
+        self
+    }
+
+    /// Set the expected issuer of ID tokens.
+    pub fn issuer(mut self, issuer: impl ToString) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    /// Set the scopes requested from the user.
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = scopes.iter().map(|scope| scope.to_string()).collect();
+        self
+    }
+
+    /// Provider name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Client ID issued by the provider.
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub(crate) fn client_secret(&self) -> &str {
+        &self.client_secret
+    }
+
+    pub(crate) fn token_url(&self) -> &str {
+        &self.token_endpoint
+    }
+
+    pub(crate) fn userinfo_url(&self) -> Option<&str> {
+        self.userinfo_endpoint.as_deref()
+    }
+
+    pub(crate) fn expected_issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    /// URL the user is redirected to, including the state, nonce, and PKCE challenge.
+    pub(crate) fn authorization_url(
+        &self,
+        redirect_uri: &str,
+        state: &str,
+        nonce: &str,
+        challenge: &str,
+    ) -> String {
+        let scope = self.scopes.join(" ");
+
+        Url::parse(&self.authorization_endpoint)
+            .query("response_type", "code")
+            .query("client_id", &self.client_id)
+            .query("redirect_uri", redirect_uri)
+            .query("scope", scope)
+            .query("state", state)
+            .query("nonce", nonce)
+            .query("code_challenge", challenge)
+            .query("code_challenge_method", "S256")
+            .to_string()
+    }
+}
@@ -6,6 +6,7 @@
 //! ```
 //! use rwf::prelude::*;
 //! ```
+pub use crate::app::App;
 pub use crate::comms::Comms;
 pub use crate::config::Config;
 pub use crate::controller::{auth::SessionAuth, AuthHandler};
@@ -13,10 +14,12 @@ pub use crate::controller::{
     Authentication, Controller, Error, ModelController, PageController, RestController, SessionId,
 };
 pub use crate::http::{Cookie, CookieBuilder, Message, Method, Request, Response, ToMessage};
-pub use crate::job::{queue_async, queue_delay, Job};
+pub use crate::job::{queue_async, queue_delay, queue_job_encrypted, Job, JobContext};
 pub use crate::logging::Logger;
 pub use crate::model::{Migrations, Model, Pool, Scope, ToSql, ToValue};
-pub use crate::view::{Template, ToTemplateValue, TurboStream};
+pub use crate::view::{
+    sanitize_html, FormErrors, FormRecord, Template, ToTemplateValue, TurboStream,
+};
 
 /// A macro to easily implement async traits methods.
 pub use async_trait::async_trait;
@@ -0,0 +1,135 @@
+//! Graceful shutdown.
+//!
+//! When the [`Server`](crate::http::Server) is asked to shut down, it stops accepting connections, waits for the requests
+//! it's serving to finish, and runs the shutdown hooks registered with [`Server::on_shutdown`](crate::http::Server::on_shutdown).
+//! The job [`Worker`](crate::job::Worker) does the same: it finishes the job it's running, stops taking jobs from the queue,
+//! and runs its own hooks. Hooks flush the application's resources, like metrics or message queue producers:
+//!
+//! ```rust,ignore
+//! let server = Server::new(routes).on_shutdown(move || async move {
+//!     producer.flush().await
+//! });
+//! ```
+//!
+//! Hooks run in the reverse order they were registered in, each with its own timeout, configured with `shutdown_hook_timeout`.
+//! A hook that fails or times out is logged and the remaining hooks still run.
+//!
+//! The [`phase`] of the shutdown can be used by health checks to report that the application isn't ready
+//! as soon as it starts draining requests.
+use parking_lot::Mutex;
+use tokio::time::timeout;
+use tracing::{error, info};
+
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::get_config;
+
+static PHASE: AtomicU8 = AtomicU8::new(Phase::Running as u8);
+
+/// Phase of the application shutdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    /// Serving requests and running jobs.
+    Running = 0,
+    /// Finishing requests and jobs in progress. New connections and jobs aren't accepted.
+    Draining = 1,
+    /// Running the shutdown hooks.
+    Hooks = 2,
+    /// Shut down.
+    Stopped = 3,
+}
+
+impl Phase {
+    fn from_u8(phase: u8) -> Self {
+        match phase {
+            0 => Phase::Running,
+            1 => Phase::Draining,
+            2 => Phase::Hooks,
+            _ => Phase::Stopped,
+        }
+    }
+}
+
+/// Current phase of the shutdown.
+pub fn phase() -> Phase {
+    Phase::from_u8(PHASE.load(Ordering::Relaxed))
+}
+
+/// The application is running and isn't shutting down.
+pub fn ready() -> bool {
+    phase() == Phase::Running
+}
+
+/// Move the shutdown to the phase. The shutdown never goes back to an earlier phase.
+pub(crate) fn advance(phase: Phase) {
+    PHASE.fetch_max(phase as u8, Ordering::Relaxed);
+}
+
+type Hook = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> + Send>;
+
+struct Registered {
+    hook: Hook,
+    timeout: Option<Duration>,
+}
+
+/// Hooks run when the server or the worker shuts down.
+///
+/// It's cheap to clone, and all clones share the same hooks. Each hook runs only once.
+#[derive(Clone, Default)]
+pub struct ShutdownHooks {
+    hooks: Arc<Mutex<Vec<Registered>>>,
+}
+
+impl ShutdownHooks {
+    /// Register a hook. The hook runs for up to `timeout`, or `shutdown_hook_timeout` if not set.
+    pub fn add<F, Fut, E>(&self, timeout: Option<Duration>, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let hook: Hook =
+            Box::new(move || Box::pin(async move { hook().await.map_err(|err| err.to_string()) }));
+
+        self.hooks.lock().push(Registered { hook, timeout });
+    }
+
+    /// Number of hooks that haven't run yet.
+    pub fn len(&self) -> usize {
+        self.hooks.lock().len()
+    }
+
+    /// All hooks ran, or none were registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Run the hooks, in reverse registration order.
+    pub(crate) async fn run(&self) {
+        advance(Phase::Hooks);
+
+        let hooks = std::mem::take(&mut *self.hooks.lock());
+        let default_timeout = get_config().general.shutdown_hook_timeout().unsigned_abs();
+
+        for (index, registered) in hooks.into_iter().enumerate().rev() {
+            let limit = registered.timeout.unwrap_or(default_timeout);
+
+            match timeout(limit, (registered.hook)()).await {
+                Ok(Ok(())) => info!("shutdown hook {} finished", index),
+                Ok(Err(err)) => error!("shutdown hook {} failed: {}", index, err),
+                Err(_) => error!(
+                    "shutdown hook {} timed out after {:.3}s",
+                    index,
+                    limit.as_secs_f64()
+                ),
+            }
+        }
+
+        advance(Phase::Stopped);
+    }
+}
@@ -0,0 +1,658 @@
+//! Load testing in memory.
+//!
+//! [`load`] runs concurrent clients against a [`TestClient`] for a fixed duration. Each client sends
+//! requests picked from a weighted mix of [`RequestTemplate`]s, with randomized parameters. Latencies
+//! and status codes are collected into a [`LoadReport`], and invariants, e.g. no `5xx` responses,
+//! fail the test when they don't hold:
+//!
+//! ```
+//! # use rwf::prelude::*;
+//! use rwf::testing::{load, RequestTemplate, TestClient};
+//! use rwf::testing::load::{no_server_errors, percentile_under};
+//! use std::time::Duration;
+//!
+//! #[derive(Default)]
+//! struct Users;
+//!
+//! #[async_trait]
+//! impl Controller for Users {
+//!     fn skip_csrf(&self) -> bool {
+//!         true
+//!     }
+//!
+//!     async fn handle(&self, request: &Request) -> Result<Response, Error> {
+//!         let id = request.parameter::<i64>("id")?;
+//!         Ok(Response::new().text(format!("user #{:?}", id)))
+//!     }
+//! }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let client = TestClient::new(vec![route!("/users/:id" => Users)]);
+//!
+//! let report = load(&client)
+//!     .template(RequestTemplate::get("/users/{id}").param("id", 1..=1000).weight(9))
+//!     .template(
+//!         RequestTemplate::post("/users/{id}")
+//!             .header("content-type", "application/json")
+//!             .body(r#"{"name": "{name}"}"#)
+//!             .param("id", 1..=1000)
+//!             .param("name", vec!["alice", "bob"]),
+//!     )
+//!     .clients(8)
+//!     .duration(Duration::from_millis(100))
+//!     .seed(42)
+//!     .invariant(no_server_errors())
+//!     .invariant(percentile_under(99.0, Duration::from_millis(50)))
+//!     .run()
+//!     .await;
+//!
+//! println!("{}", report);
+//! report.assert_passed();
+//! # })
+//! ```
+//!
+//! Every client has its own random number generator, seeded from [`LoadTest::seed`] and its index,
+//! so each client sends the same sequence of requests on every run. How many of them are sent in
+//! the duration depends on the machine; set [`LoadTest::max_requests`] to make the whole run reproducible.
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::task::yield_now;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{TestClient, TestRequest};
+use crate::http::Method;
+
+/// Upper bounds of the latency histogram buckets.
+pub const BUCKETS: [Duration; 12] = [
+    Duration::from_micros(100),
+    Duration::from_micros(250),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_micros(2_500),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_secs(1),
+];
+
+/// Check of a finished load test. Returns a description of the problem if it doesn't hold.
+pub type Invariant = Arc<dyn Fn(&LoadReport) -> Result<(), String> + Send + Sync>;
+
+/// Start a load test against the client.
+pub fn load(client: &TestClient) -> LoadTest {
+    LoadTest {
+        client: client.clone(),
+        templates: vec![],
+        clients: 10,
+        duration: Duration::from_secs(1),
+        seed: 0,
+        max_requests: None,
+        invariants: vec![],
+    }
+}
+
+/// Values of a randomized parameter.
+#[derive(Debug, Clone)]
+pub enum Param {
+    /// An integer in the range.
+    Range(RangeInclusive<i64>),
+    /// One of the values.
+    OneOf(Vec<String>),
+}
+
+impl Param {
+    fn sample(&self, rng: &mut impl Rng) -> String {
+        match self {
+            Param::Range(range) => rng.gen_range(range.clone()).to_string(),
+            Param::OneOf(values) if values.is_empty() => String::new(),
+            Param::OneOf(values) => values[rng.gen_range(0..values.len())].clone(),
+        }
+    }
+}
+
+impl From<RangeInclusive<i64>> for Param {
+    fn from(range: RangeInclusive<i64>) -> Self {
+        Param::Range(range)
+    }
+}
+
+impl From<Range<i64>> for Param {
+    fn from(range: Range<i64>) -> Self {
+        Param::Range(range.start..=range.end - 1)
+    }
+}
+
+impl From<Vec<&str>> for Param {
+    fn from(values: Vec<&str>) -> Self {
+        Param::OneOf(values.into_iter().map(|v| v.to_string()).collect())
+    }
+}
+
+impl From<Vec<String>> for Param {
+    fn from(values: Vec<String>) -> Self {
+        Param::OneOf(values)
+    }
+}
+
+/// Request sent by the load test clients.
+///
+/// The path, header values and body can contain parameters, e.g. `/users/{id}`, which are replaced
+/// with a random value for every request. A parameter has the same value everywhere in a request.
+#[derive(Debug, Clone)]
+pub struct RequestTemplate {
+    method: Method,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    params: Vec<(String, Param)>,
+    weight: u32,
+}
+
+impl RequestTemplate {
+    /// Create a template with a weight of `1`.
+    pub fn new(method: Method, path: impl ToString) -> Self {
+        Self {
+            method,
+            path: path.to_string(),
+            headers: vec![],
+            body: None,
+            params: vec![],
+            weight: 1,
+        }
+    }
+
+    /// `GET` request.
+    pub fn get(path: impl ToString) -> Self {
+        Self::new(Method::Get, path)
+    }
+
+    /// `POST` request.
+    pub fn post(path: impl ToString) -> Self {
+        Self::new(Method::Post, path)
+    }
+
+    /// `PUT` request.
+    pub fn put(path: impl ToString) -> Self {
+        Self::new(Method::Put, path)
+    }
+
+    /// `PATCH` request.
+    pub fn patch(path: impl ToString) -> Self {
+        Self::new(Method::Patch, path)
+    }
+
+    /// `DELETE` request.
+    pub fn delete(path: impl ToString) -> Self {
+        Self::new(Method::Delete, path)
+    }
+
+    /// Set a header.
+    pub fn header(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set the body.
+    pub fn body(mut self, body: impl ToString) -> Self {
+        self.body = Some(body.to_string());
+        self
+    }
+
+    /// Randomize the `{name}` parameter.
+    pub fn param(mut self, name: impl ToString, values: impl Into<Param>) -> Self {
+        self.params.push((name.to_string(), values.into()));
+        self
+    }
+
+    /// How often this template is picked, relative to the other templates.
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Create a request, with random parameter values.
+    pub fn render(&self, rng: &mut impl Rng) -> TestRequest {
+        let values = self
+            .params
+            .iter()
+            .map(|(name, param)| (format!("{{{}}}", name), param.sample(rng)))
+            .collect::<Vec<_>>();
+        let render = |text: &str| {
+            values.iter().fold(text.to_string(), |text, (name, value)| {
+                text.replace(name, value)
+            })
+        };
+
+        let mut request = TestRequest::new(self.method.clone(), render(&self.path));
+        for (name, value) in &self.headers {
+            request = request.header(name, render(value));
+        }
+        if let Some(ref body) = self.body {
+            request = request.body(render(body));
+        }
+
+        request
+    }
+}
+
+/// Load test, created with [`load`].
+pub struct LoadTest {
+    client: TestClient,
+    templates: Vec<RequestTemplate>,
+    clients: usize,
+    duration: Duration,
+    seed: u64,
+    max_requests: Option<usize>,
+    invariants: Vec<Invariant>,
+}
+
+impl LoadTest {
+    /// Add a request to the mix.
+    pub fn template(mut self, template: RequestTemplate) -> Self {
+        self.templates.push(template);
+        self
+    }
+
+    /// Number of concurrent clients. Default is `10`.
+    pub fn clients(mut self, clients: usize) -> Self {
+        self.clients = clients;
+        self
+    }
+
+    /// How long clients send requests for. Default is one second.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Seed of the random number generators. Default is `0`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Stop each client after `requests` requests, even if the duration hasn't passed.
+    pub fn max_requests(mut self, requests: usize) -> Self {
+        self.max_requests = Some(requests);
+        self
+    }
+
+    /// Check the report when the test is finished.
+    pub fn invariant(
+        mut self,
+        invariant: impl Fn(&LoadReport) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.invariants.push(Arc::new(invariant));
+        self
+    }
+
+    /// Run the test and check the invariants.
+    ///
+    /// # Panics
+    ///
+    /// If no templates were added, or all of them have a weight of `0`.
+    pub async fn run(self) -> LoadReport {
+        let weights = WeightedIndex::new(self.templates.iter().map(|t| t.weight))
+            .expect("load test needs at least one template with a weight above 0");
+        let templates = Arc::new(self.templates);
+        let started = Instant::now();
+        let deadline = started + self.duration;
+
+        let clients = (0..self.clients)
+            .map(|i| {
+                let client = self.client.clone();
+                let templates = templates.clone();
+                let weights = weights.clone();
+                let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(i as u64));
+                let max_requests = self.max_requests.unwrap_or(usize::MAX);
+
+                tokio::spawn(async move {
+                    let mut samples = vec![];
+
+                    while samples.len() < max_requests && Instant::now() < deadline {
+                        let template = &templates[weights.sample(&mut rng)];
+                        let request = template.render(&mut rng);
+
+                        let start = Instant::now();
+                        let status = client.send(request).await.ok().map(|r| r.status());
+                        samples.push((status, start.elapsed()));
+
+                        // Controllers in memory may never yield, so let the other clients run.
+                        yield_now().await;
+                    }
+
+                    samples
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut report = LoadReport::default();
+
+        for client in clients {
+            for (status, latency) in client.await.expect("load test client panicked") {
+                match status {
+                    Some(status) => *report.statuses.entry(status).or_default() += 1,
+                    None => report.failures += 1,
+                }
+                report.latencies.push(latency);
+            }
+        }
+
+        report.duration = started.elapsed();
+        report.latencies.sort();
+        report.violations = self
+            .invariants
+            .iter()
+            .filter_map(|invariant| invariant(&report).err())
+            .collect();
+
+        report
+    }
+}
+
+/// Results of a load test.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    duration: Duration,
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    failures: usize,
+    violations: Vec<String>,
+}
+
+impl LoadReport {
+    /// Number of requests sent.
+    pub fn requests(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// How long the test ran.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Requests per second.
+    pub fn throughput(&self) -> f64 {
+        self.requests() as f64 / self.duration.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Number of responses by status code.
+    pub fn statuses(&self) -> &BTreeMap<u16, usize> {
+        &self.statuses
+    }
+
+    /// Number of responses with a `5xx` status code.
+    pub fn server_errors(&self) -> usize {
+        self.statuses.range(500..600).map(|(_, count)| count).sum()
+    }
+
+    /// Number of requests that didn't get a response, e.g. because the request couldn't be parsed.
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// Latency at the percentile, e.g. `99.0`, or zero if no requests were sent.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+
+    /// Slowest request.
+    pub fn max(&self) -> Duration {
+        self.latencies.last().copied().unwrap_or_default()
+    }
+
+    /// Number of requests in each latency bucket, by upper bound. The last bucket, without a bound,
+    /// counts requests slower than the last of [`BUCKETS`].
+    pub fn histogram(&self) -> Vec<(Option<Duration>, usize)> {
+        let mut histogram = BUCKETS
+            .iter()
+            .map(|bound| (Some(*bound), 0))
+            .chain([(None, 0)])
+            .collect::<Vec<_>>();
+
+        for latency in &self.latencies {
+            let bucket = BUCKETS
+                .iter()
+                .position(|bound| latency <= bound)
+                .unwrap_or(BUCKETS.len());
+            histogram[bucket].1 += 1;
+        }
+
+        histogram
+    }
+
+    /// Invariants that don't hold.
+    pub fn violations(&self) -> &[String] {
+        &self.violations
+    }
+
+    /// All invariants hold.
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Panic with the report if an invariant doesn't hold.
+    pub fn assert_passed(&self) {
+        assert!(self.passed(), "load test failed\n{}", self);
+    }
+}
+
+impl Display for LoadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |duration: Duration| format!("{:.2}ms", duration.as_secs_f64() * 1000.0);
+
+        writeln!(
+            f,
+            "{} requests in {:.2}s ({:.1}/s), {} failed",
+            self.requests(),
+            self.duration.as_secs_f64(),
+            self.throughput(),
+            self.failures
+        )?;
+        writeln!(
+            f,
+            "latency: p50 {}, p90 {}, p99 {}, max {}",
+            ms(self.percentile(50.0)),
+            ms(self.percentile(90.0)),
+            ms(self.percentile(99.0)),
+            ms(self.max())
+        )?;
+
+        let statuses = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{} x{}", status, count))
+            .collect::<Vec<_>>();
+        writeln!(f, "status: {}", statuses.join(", "))?;
+
+        for (bound, count) in self.histogram() {
+            if count == 0 {
+                continue;
+            }
+
+            let bound = match bound {
+                Some(bound) => format!("<= {}", ms(bound)),
+                None => format!("> {}", ms(BUCKETS[BUCKETS.len() - 1])),
+            };
+            writeln!(f, "  {:<12} {}", bound, count)?;
+        }
+
+        if !self.violations.is_empty() {
+            writeln!(f, "violations:")?;
+            for violation in &self.violations {
+                writeln!(f, "  {}", violation)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// No responses with a `5xx` status code.
+pub fn no_server_errors() -> impl Fn(&LoadReport) -> Result<(), String> + Send + Sync + 'static {
+    |report| match report.server_errors() {
+        0 => Ok(()),
+        errors => Err(format!("{} responses with a 5xx status code", errors)),
+    }
+}
+
+/// All requests got a response.
+pub fn no_failures() -> impl Fn(&LoadReport) -> Result<(), String> + Send + Sync + 'static {
+    |report| match report.failures() {
+        0 => Ok(()),
+        failures => Err(format!("{} requests didn't get a response", failures)),
+    }
+}
+
+/// Latency at the percentile, e.g. `99.0`, is under the limit.
+pub fn percentile_under(
+    percentile: f64,
+    limit: Duration,
+) -> impl Fn(&LoadReport) -> Result<(), String> + Send + Sync + 'static {
+    move |report| {
+        let latency = report.percentile(percentile);
+
+        if latency < limit {
+            Ok(())
+        } else {
+            Err(format!(
+                "p{} latency is {:.2}ms, limit is {:.2}ms",
+                percentile,
+                latency.as_secs_f64() * 1000.0,
+                limit.as_secs_f64() * 1000.0
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Orders;
+
+    #[async_trait]
+    impl Controller for Orders {
+        async fn handle(&self, request: &Request) -> Result<Response, Error> {
+            match request.parameter::<i64>("id")? {
+                Some(id) if id % 10 == 0 => Ok(Response::new().code(500)),
+                Some(_) => Ok(Response::new().text(request.string())),
+                None => Ok(Response::not_found()),
+            }
+        }
+    }
+
+    fn client() -> TestClient {
+        TestClient::new(vec![route!("/orders/:id" => Orders)])
+    }
+
+    #[test]
+    fn test_render() {
+        let template = RequestTemplate::post("/orders/{id}")
+            .header("x-order", "{id}")
+            .body("{id}:{status}")
+            .param("id", 1..=100)
+            .param("status", vec!["paid", "shipped"]);
+
+        let render = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..10)
+                .map(|_| template.render(&mut rng).to_bytes())
+                .collect::<Vec<_>>()
+        };
+
+        // Same seed, same requests.
+        assert_eq!(render(7), render(7));
+        assert_ne!(render(7), render(8));
+
+        let request = String::from_utf8(render(7).remove(0)).unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        let (id, status) = body.split_once(':').unwrap();
+        assert!(head.starts_with(&format!("POST /orders/{} HTTP/1.1\r\n", id)));
+        assert!(format!("{}\r\n", head).contains(&format!("x-order: {}\r\n", id)));
+        assert!(["paid", "shipped"].contains(&status));
+    }
+
+    #[tokio::test]
+    async fn test_load() {
+        let run = || {
+            load(&client())
+                .template(RequestTemplate::get("/orders/{id}").param("id", 1..=100))
+                .template(RequestTemplate::get("/missing").weight(0))
+                .clients(4)
+                .duration(Duration::from_secs(10))
+                .max_requests(50)
+                .seed(3)
+                .invariant(no_server_errors())
+                .invariant(no_failures())
+                .invariant(percentile_under(99.0, Duration::from_secs(5)))
+                .run()
+        };
+
+        let report = run().await;
+        assert_eq!(report.requests(), 200);
+        assert!(report.server_errors() > 0);
+        assert_eq!(
+            report.server_errors() + report.statuses()[&200],
+            report.requests()
+        );
+        assert_eq!(
+            report.histogram().iter().map(|(_, n)| n).sum::<usize>(),
+            200
+        );
+        assert!(report.percentile(50.0) <= report.percentile(99.0));
+        assert!(report.percentile(99.0) <= report.max());
+
+        // Only the server errors invariant is violated.
+        assert!(!report.passed());
+        assert_eq!(
+            report.violations(),
+            &[format!(
+                "{} responses with a 5xx status code",
+                report.server_errors()
+            )]
+        );
+
+        let text = report.to_string();
+        assert!(text.starts_with("200 requests in "), "{}", text);
+        assert!(text.contains("latency: p50 "));
+        assert!(text.contains(&format!(
+            "status: 200 x{}, 500 x{}\n",
+            report.statuses()[&200],
+            report.server_errors()
+        )));
+        assert!(text.ends_with(&format!(
+            "violations:\n  {} responses with a 5xx status code\n",
+            report.server_errors()
+        )));
+
+        // The seed makes the run reproducible.
+        assert_eq!(run().await.statuses(), report.statuses());
+
+        let result = tokio::spawn(async move { report.assert_passed() }).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "at least one template")]
+    async fn test_no_templates() {
+        load(&client()).run().await;
+    }
+}
@@ -0,0 +1,326 @@
+//! Utilities for testing applications without a network connection.
+//!
+//! [`TestClient`] sends requests to a [`Server`] in memory. Requests go through the router, the
+//! controllers and their middleware like requests received over TCP, and responses are serialized
+//! and parsed back, so the test sees what a client would:
+//!
+//! ```
+//! # use rwf::prelude::*;
+//! use rwf::testing::TestClient;
+//!
+//! #[derive(Default)]
+//! struct Index;
+//!
+//! #[async_trait]
+//! impl Controller for Index {
+//!     async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+//!         Ok(Response::new().text("hello"))
+//!     }
+//! }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let client = TestClient::new(vec![route!("/" => Index)]);
+//! let response = client.get("/").await.unwrap();
+//!
+//! assert_eq!(response.status(), 200);
+//! assert_eq!(response.text(), "hello");
+//! # })
+//! ```
+//!
+//! To soak-test an application with many concurrent clients, see [`load`].
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::http::{Error, Handler, Headers, Method, Request, Server};
+
+pub mod load;
+pub use load::{load, LoadReport, LoadTest, Param, RequestTemplate};
+
+/// Client sending requests to a server in memory.
+#[derive(Clone)]
+pub struct TestClient {
+    server: Arc<Server>,
+    peer: SocketAddr,
+}
+
+impl TestClient {
+    /// Create a client for a server with these routes.
+    pub fn new(handlers: Vec<Handler>) -> Self {
+        Self::from(Server::new(handlers))
+    }
+
+    /// Send requests from this address. Default is `127.0.0.1:1234`.
+    pub fn peer(mut self, peer: SocketAddr) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    /// Send a `GET` request.
+    pub async fn get(&self, path: &str) -> Result<TestResponse, Error> {
+        self.send(TestRequest::get(path)).await
+    }
+
+    /// Send a request and read the response.
+    pub async fn send(&self, request: TestRequest) -> Result<TestResponse, Error> {
+        let request = Request::read(self.peer, &request.to_bytes()[..]).await?;
+        let response = self.server.handle(request).await;
+
+        let mut wire = vec![];
+        response.send(&mut wire).await?;
+
+        TestResponse::parse(&wire).ok_or(Error::MalformedRequest("response"))
+    }
+}
+
+impl From<Server> for TestClient {
+    fn from(server: Server) -> Self {
+        Self {
+            server: Arc::new(server),
+            peer: "127.0.0.1:1234".parse().unwrap(),
+        }
+    }
+}
+
+/// Request sent with a [`TestClient`].
+#[derive(Debug, Clone)]
+pub struct TestRequest {
+    method: Method,
+    path: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl TestRequest {
+    /// Create a request. `Host` is set to `localhost`, unless it's set with [`TestRequest::header`].
+    pub fn new(method: Method, path: impl ToString) -> Self {
+        let mut headers = Headers::new();
+        headers.insert("host", "localhost");
+
+        Self {
+            method,
+            path: path.to_string(),
+            headers,
+            body: vec![],
+        }
+    }
+
+    /// `GET` request.
+    pub fn get(path: impl ToString) -> Self {
+        Self::new(Method::Get, path)
+    }
+
+    /// `POST` request.
+    pub fn post(path: impl ToString) -> Self {
+        Self::new(Method::Post, path)
+    }
+
+    /// `PUT` request.
+    pub fn put(path: impl ToString) -> Self {
+        Self::new(Method::Put, path)
+    }
+
+    /// `PATCH` request.
+    pub fn patch(path: impl ToString) -> Self {
+        Self::new(Method::Patch, path)
+    }
+
+    /// `DELETE` request.
+    pub fn delete(path: impl ToString) -> Self {
+        Self::new(Method::Delete, path)
+    }
+
+    /// Set a header.
+    pub fn header(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Set the body. `Content-Length` is set automatically.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Set a JSON body, with `Content-Type: application/json`.
+    pub fn json(self, body: impl Serialize) -> Result<Self, serde_json::Error> {
+        let body = serde_json::to_vec(&body)?;
+        Ok(self.header("content-type", "application/json").body(body))
+    }
+
+    /// Serialize the request, as it would be sent over the network.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut headers = self.headers.clone();
+        if !self.body.is_empty() {
+            headers.insert("content-length", self.body.len());
+        }
+
+        let mut bytes = format!("{} {} HTTP/1.1\r\n", self.method, self.path).into_bytes();
+        bytes.extend_from_slice(&headers.to_bytes());
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+/// Response received by a [`TestClient`].
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    status: u16,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Parse a response serialized by [`Response::send`](crate::http::Response::send).
+    /// Chunk-encoded bodies are decoded, and trailers are ignored.
+    pub fn parse(wire: &[u8]) -> Option<Self> {
+        let end = find(wire, b"\r\n\r\n")?;
+        let head = std::str::from_utf8(&wire[..end]).ok()?;
+        let mut lines = head.split("\r\n");
+
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())?;
+
+        let mut headers = Headers::new();
+        for line in lines {
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), value.trim());
+            }
+        }
+
+        let body = &wire[end + 4..];
+        let body = if headers.get("transfer-encoding").map(|s| s.as_str()) == Some("chunked") {
+            dechunk(body)
+        } else {
+            body.to_vec()
+        };
+
+        Some(Self {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    /// Response status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Response headers. Names are lowercase.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Response body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Response body as text. Invalid UTF-8 is replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// Deserialize the JSON body.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Decode a chunk-encoded body, up to the last chunk.
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut decoded = vec![];
+
+    while let Some(end) = find(body, b"\r\n") {
+        let size = std::str::from_utf8(&body[..end])
+            .ok()
+            .and_then(|size| usize::from_str_radix(size.split(';').next()?.trim(), 16).ok())
+            .unwrap_or(0);
+
+        if size == 0 {
+            break;
+        }
+
+        let start = end + 2;
+        let chunk_end = (start + size).min(body.len());
+        decoded.extend_from_slice(&body[start..chunk_end]);
+        body = &body[(chunk_end + 2).min(body.len())..];
+    }
+
+    decoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TestClient, TestRequest};
+    use crate::prelude::*;
+
+    #[derive(Default)]
+    struct Echo;
+
+    #[async_trait]
+    impl Controller for Echo {
+        fn skip_csrf(&self) -> bool {
+            true
+        }
+
+        async fn handle(&self, request: &Request) -> Result<Response, Error> {
+            let body = serde_json::json!({
+                "method": request.method().to_string(),
+                "id": request.parameter::<i64>("id")?,
+                "body": request.string(),
+            });
+
+            if request.path().path() == "/stream" {
+                Ok(Response::new().json_stream(vec![1, 2, 3]))
+            } else {
+                Ok(Response::new().json(body)?)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let client = TestClient::new(vec![route!("/echo/:id" => Echo), route!("/stream" => Echo)]);
+
+        let response = client
+            .send(TestRequest::put("/echo/5").body("hello"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(
+            response.json::<serde_json::Value>().unwrap(),
+            serde_json::json!({"method": "PUT", "id": 5, "body": "hello"})
+        );
+
+        // Chunked bodies are decoded.
+        let response = client.get("/stream").await.unwrap();
+        assert_eq!(response.text(), "[1,2,3]");
+
+        let response = client.get("/missing").await.unwrap();
+        assert_eq!(response.status(), 404);
+
+        // HEAD responses have no body.
+        let response = client
+            .send(TestRequest::new(Method::Head, "/echo/5"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.body().is_empty());
+    }
+}
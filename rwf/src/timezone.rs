@@ -0,0 +1,94 @@
+//! Time zones.
+//!
+//! Timestamps are read from the database and passed around in UTC, and converted to a time zone only when they are
+//! formatted, e.g. in templates. Unless another time zone is requested, they are formatted in the time zone
+//! configured with the `default_timezone` setting. The same time zone is used by the [cron scheduler](crate::job::Clock).
+//!
+//! Time zones are fixed offsets from UTC, e.g. `+02:00` or `-05:30`. Named time zones, like `Europe/Paris`,
+//! require a time zone database and aren't supported.
+//!
+//! ```
+//! use rwf::timezone::parse;
+//! use time::UtcOffset;
+//!
+//! assert_eq!(parse("UTC"), Some(UtcOffset::UTC));
+//! assert_eq!(parse("+05:30"), UtcOffset::from_hms(5, 30, 0).ok());
+//! assert_eq!(parse("-08"), UtcOffset::from_hms(-8, 0, 0).ok());
+//! assert_eq!(parse("Europe/Paris"), None);
+//! ```
+use time::UtcOffset;
+
+use crate::config::get_config;
+
+/// Parse a time zone: `UTC`, or an offset from UTC formatted as `+HH`, `+HH:MM` or `+HHMM`.
+pub fn parse(zone: &str) -> Option<UtcOffset> {
+    let zone = zone.trim();
+
+    if ["utc", "z", "gmt"].contains(&zone.to_lowercase().as_str()) {
+        return Some(UtcOffset::UTC);
+    }
+
+    let (sign, offset) = match zone.as_bytes().first()? {
+        b'+' => (1, &zone[1..]),
+        b'-' => (-1, &zone[1..]),
+        _ => return None,
+    };
+
+    let (hours, minutes) = match offset.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if offset.len() == 4 => offset.split_at(2),
+        None => (offset, "0"),
+    };
+
+    if hours.len() > 2
+        || minutes.len() > 2
+        || !(hours.chars().chain(minutes.chars())).all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let hours = hours.parse::<i8>().ok()?;
+    let minutes = minutes.parse::<i8>().ok()?;
+
+    if minutes >= 60 {
+        return None;
+    }
+
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+/// Time zone configured with the `default_timezone` setting.
+pub fn default_offset() -> UtcOffset {
+    get_config().general.timezone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let offset = |h, m| UtcOffset::from_hms(h, m, 0).ok();
+
+        assert_eq!(parse("utc"), Some(UtcOffset::UTC));
+        assert_eq!(parse("Z"), Some(UtcOffset::UTC));
+        assert_eq!(parse("+00:00"), Some(UtcOffset::UTC));
+        assert_eq!(parse("+02:00"), offset(2, 0));
+        assert_eq!(parse(" +0230 "), offset(2, 30));
+        assert_eq!(parse("-03:30"), offset(-3, -30));
+        assert_eq!(parse("+14"), offset(14, 0));
+
+        for invalid in [
+            "",
+            "+",
+            "02:00",
+            "+2:60",
+            "+26:00",
+            "+1:2:3",
+            "+١٢",
+            "America/New_York",
+        ] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+    }
+}
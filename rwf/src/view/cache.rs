@@ -1,4 +1,7 @@
-use super::{template::Error, Template};
+use super::{
+    template::{Error, Mode},
+    Template,
+};
 use crate::config::get_config;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -11,7 +14,7 @@ use parking_lot::{Mutex, MutexGuard};
 static TEMPLATES: Lazy<Mutex<Templates>> = Lazy::new(|| Mutex::new(Templates::new()));
 
 pub struct Templates {
-    templates: HashMap<PathBuf, Arc<Template>>,
+    templates: HashMap<(PathBuf, Mode), Arc<Template>>,
 }
 
 impl Templates {
@@ -22,20 +25,31 @@ impl Templates {
     }
 
     pub fn get(&mut self, path: impl AsRef<Path> + Copy) -> Result<Arc<Template>, Error> {
+        let mode = Mode::from_path(path).unwrap_or_default();
+        self.get_with_mode(path, mode)
+    }
+
+    /// Get the template rendered in the given mode. The same file can be
+    /// cached in several modes.
+    pub fn get_with_mode(
+        &mut self,
+        path: impl AsRef<Path> + Copy,
+        mode: Mode,
+    ) -> Result<Arc<Template>, Error> {
         let cache_templates = get_config().general.cache_templates;
+        let key = (path.as_ref().to_owned(), mode);
 
-        if let Some(t) = self.templates.get(path.as_ref()) {
+        if let Some(t) = self.templates.get(&key) {
             return Ok(t.clone());
         }
 
-        let template = Arc::new(Template::new(path)?);
+        let template = Arc::new(Template::new(path)?.with_mode(mode)?);
 
         if cache_templates {
-            self.templates.insert(path.as_ref().to_owned(), template);
-            Ok(self.templates.get(path.as_ref()).unwrap().clone())
-        } else {
-            Ok(template)
+            self.templates.insert(key, template.clone());
         }
+
+        Ok(template)
     }
 
     pub fn cache() -> MutexGuard<'static, Templates> {
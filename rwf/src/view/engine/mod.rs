@@ -0,0 +1,107 @@
+//! Alternative template engines.
+//!
+//! Templates are rendered with the built-in template language by default. Other engines can be registered
+//! for specific file extensions, which allows to use existing templates written for another engine
+//! alongside Rwf templates:
+//!
+//! ```rust,ignore
+//! use rwf::view::engine::{register, tera::TeraEngine};
+//!
+//! register("tera", TeraEngine::new());
+//!
+//! // Rendered by Tera.
+//! let response = Response::new().template("templates/index.tera", &context!("title" => "Home"))?;
+//! ```
+//!
+//! Engines receive the template context serialized to JSON, so any engine that can
+//! render [`serde_json::Value`] can be used.
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::template::Error;
+
+#[cfg(feature = "tera")]
+pub mod tera;
+
+static ENGINES: Lazy<RwLock<HashMap<String, Arc<dyn ViewEngine>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Template engine.
+pub trait ViewEngine: Send + Sync {
+    /// Read the template from disk and compile it. Called every time [`crate::view::Template::load`]
+    /// reads the template, which is only once if the `cache_templates` setting is enabled.
+    ///
+    /// Does nothing by default.
+    fn load(&self, path: &Path) -> Result<(), Error> {
+        let _ = path;
+        Ok(())
+    }
+
+    /// Render the template with the given context.
+    fn render(&self, path: &Path, context: &serde_json::Value) -> Result<String, Error>;
+}
+
+/// Render templates with the file extension using this engine, e.g. `register("tera", TeraEngine::new())`.
+///
+/// Templates with extensions without a registered engine are rendered using the built-in
+/// template language.
+pub fn register(extension: &str, engine: impl ViewEngine + 'static) {
+    ENGINES
+        .write()
+        .insert(normalize(extension), Arc::new(engine));
+}
+
+/// Engine registered for the template's file extension. Returns `None`
+/// if the template should be rendered with the built-in language.
+pub fn engine(path: impl AsRef<Path>) -> Option<Arc<dyn ViewEngine>> {
+    let extension = path.as_ref().extension()?.to_str()?;
+    ENGINES.read().get(&normalize(extension)).cloned()
+}
+
+fn normalize(extension: &str) -> String {
+    extension.trim_start_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::view::{Context, Template};
+    use std::fs::write;
+    use tempdir::TempDir;
+
+    struct Upcase;
+
+    impl ViewEngine for Upcase {
+        fn render(&self, path: &Path, context: &serde_json::Value) -> Result<String, Error> {
+            let text = std::fs::read_to_string(path).unwrap();
+            let name = context["name"].as_str().unwrap_or_default();
+            Ok(text.replace("NAME", name).to_uppercase())
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        register(".Upcase", Upcase);
+        assert!(engine("page.upcase").is_some());
+        assert!(engine("page.html").is_none());
+        assert!(engine("page").is_none());
+
+        let dir = TempDir::new("engines").unwrap();
+        let path = dir.path().join("page.upcase");
+        write(&path, "hello NAME").unwrap();
+
+        let template = Template::new(&path).unwrap();
+        let context = Context::try_from([("name", "alice")]).unwrap();
+        assert_eq!(template.render(&context).unwrap(), "HELLO ALICE");
+        assert_eq!(template.content_type(), "text/html");
+
+        assert!(matches!(
+            Template::new(&dir.path().join("missing.upcase")),
+            Err(Error::TemplateDoesNotExist(_))
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! [Tera](https://keats.github.io/tera/) template engine.
+//!
+//! Enable it with the `tera` feature:
+//!
+//! ```toml
+//! rwf = { version = "0.1", features = ["tera"] }
+//! ```
+//!
+//! and register it for the extension used by your Tera templates:
+//!
+//! ```
+//! use rwf::view::engine::{register, tera::TeraEngine};
+//!
+//! register("tera", TeraEngine::new());
+//! ```
+//!
+//! Templates are added to Tera using their path as the name. If your templates use `{% extends %}`
+//! or `{% include %}`, load them all upfront and pass the Tera instance to the engine, e.g. `TeraEngine::from(Tera::new("templates/**/*.tera")?)`.
+use ::tera::{Context, Tera};
+use parking_lot::RwLock;
+
+use std::error::Error as StdError;
+use std::path::Path;
+
+use super::ViewEngine;
+use crate::view::template::Error;
+
+/// Renders templates with Tera.
+#[derive(Default)]
+pub struct TeraEngine {
+    tera: RwLock<Tera>,
+}
+
+impl TeraEngine {
+    /// Create engine without any templates. Templates are added
+    /// when they are loaded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl From<Tera> for TeraEngine {
+    fn from(tera: Tera) -> Self {
+        Self {
+            tera: RwLock::new(tera),
+        }
+    }
+}
+
+impl ViewEngine for TeraEngine {
+    fn load(&self, path: &Path) -> Result<(), Error> {
+        self.tera
+            .write()
+            .add_template_file(path, Some(&name(path)))
+            .map_err(convert)
+    }
+
+    fn render(&self, path: &Path, context: &serde_json::Value) -> Result<String, Error> {
+        let name = name(path);
+
+        if !self.tera.read().get_template_names().any(|n| n == name) {
+            self.load(path)?;
+        }
+
+        let context = Context::from_value(context.clone()).map_err(convert)?;
+        self.tera.read().render(&name, &context).map_err(convert)
+    }
+}
+
+fn name(path: &Path) -> String {
+    path.display().to_string()
+}
+
+/// Tera puts the useful part of the error in its sources.
+fn convert(err: ::tera::Error) -> Error {
+    let mut message = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+
+    Error::Runtime(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TeraEngine, ViewEngine};
+    use crate::view::engine::register;
+    use crate::view::{Context, Template, Value};
+    use std::fs::write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_same_data() {
+        register("tera", TeraEngine::new());
+
+        let dir = TempDir::new("tera").unwrap();
+        let rwf = dir.path().join("users.html");
+        let tera = dir.path().join("users.tera");
+        write(
+            &rwf,
+            "<% for user in users %><%= user.name %>: <%= user.age %>;<% end %>",
+        )
+        .unwrap();
+        write(
+            &tera,
+            "{% for user in users %}{{ user.name }}: {{ user.age }};{% endfor %}",
+        )
+        .unwrap();
+
+        let mut alice = std::collections::HashMap::new();
+        alice.insert("name".to_string(), Value::String("Alice".into()));
+        alice.insert("age".to_string(), Value::Integer(30));
+
+        let mut context = Context::new();
+        context.set("users", vec![Value::Hash(alice)]).unwrap();
+
+        let expected = "Alice: 30;";
+        assert_eq!(
+            Template::load(&rwf).unwrap().render(&context).unwrap(),
+            expected
+        );
+        assert_eq!(
+            Template::load(&tera).unwrap().render(&context).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let engine = TeraEngine::new();
+        let dir = TempDir::new("tera").unwrap();
+        let path = dir.path().join("broken.tera");
+        write(&path, "{% for %}").unwrap();
+
+        let err = engine.load(&path).unwrap_err();
+        assert!(err.to_string().contains("broken.tera"));
+
+        let path = dir.path().join("missing.tera");
+        write(&path, "{{ missing }}").unwrap();
+        assert!(engine.render(&path, &serde_json::json!({})).is_err());
+    }
+}
@@ -0,0 +1,566 @@
+//! HTML form helpers driven by models.
+//!
+//! Templates can build forms for a model without writing the markup by hand:
+//!
+//! ```erb
+//! <%= form_for(user, "/users/5") %>
+//!   <%= input(user, "email") %>
+//!   <%= errors_for(user, "email") %>
+//!   <%= select(user, "role", ["admin", "member"]) %>
+//!   <%= checkbox(user, "active") %>
+//!   <%= submit("Save") %>
+//! <%= end_form() %>
+//! ```
+//!
+//! When validation fails, wrap the record in a [`FormRecord`] so the form is re-rendered
+//! with the values the user submitted and the validation errors next to each field.
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::controller::middleware::csrf::CSRF_INPUT;
+use crate::crypto;
+use crate::http::FormData;
+use crate::model::Model;
+
+use super::template::{Error, ToTemplateValue, Value};
+
+/// Name of the hidden form field used to override the request method.
+pub static METHOD_OVERRIDE_INPUT: &str = "_method";
+
+/// Hash key holding validation errors in a record bound to a form.
+static ERRORS_KEY: &str = "_errors";
+
+/// Validation errors, keyed by field name.
+///
+/// Serialized as an object of error messages for each field, e.g. `{"email": ["can't be blank"]}`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct FormErrors {
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+impl FormErrors {
+    /// No errors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an error message for a field.
+    pub fn add(&mut self, field: impl ToString, message: impl ToString) -> &mut Self {
+        self.errors
+            .entry(field.to_string())
+            .or_default()
+            .push(message.to_string());
+        self
+    }
+
+    /// Get error messages for a field.
+    pub fn get(&self, field: &str) -> &[String] {
+        self.errors.get(field).map(|e| e.as_slice()).unwrap_or(&[])
+    }
+
+    /// No errors were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl ToTemplateValue for FormErrors {
+    fn to_template_value(&self) -> Result<Value, Error> {
+        Ok(Value::Hash(
+            self.errors
+                .iter()
+                .map(|(field, messages)| (field.clone(), messages.to_template_value().unwrap()))
+                .collect(),
+        ))
+    }
+}
+
+/// A record bound to a form, with values re-submitted by the user and validation errors.
+///
+/// #### Example
+///
+/// ```rust,ignore
+/// let form_data = request.form_data()?;
+/// let mut errors = FormErrors::new();
+///
+/// if form_data.get::<String>("email").unwrap_or_default().is_empty() {
+///     errors.add("email", "can't be blank");
+/// }
+///
+/// if !errors.is_empty() {
+///     let user = FormRecord::new(&user)?.form_data(&form_data).errors(errors);
+///     return render!(request, "templates/users/edit.html", "user" => user, 422);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FormRecord {
+    values: HashMap<String, Value>,
+    errors: FormErrors,
+}
+
+impl FormRecord {
+    /// Bind a model to a form.
+    pub fn new(record: &impl Model) -> Result<Self, Error> {
+        match record.to_template_value()? {
+            Value::Hash(values) => Ok(Self {
+                values,
+                errors: FormErrors::new(),
+            }),
+            _ => Err(Error::SerializationError),
+        }
+    }
+
+    /// Use the values submitted with the form instead of the record's own.
+    pub fn form_data(mut self, form_data: &FormData) -> Self {
+        for (key, value) in form_data.clone().into_iter() {
+            if key == CSRF_INPUT || key == METHOD_OVERRIDE_INPUT {
+                continue;
+            }
+
+            self.values.insert(key, Value::String(value));
+        }
+
+        self
+    }
+
+    /// Set validation errors.
+    pub fn errors(mut self, errors: FormErrors) -> Self {
+        self.errors = errors;
+        self
+    }
+}
+
+impl ToTemplateValue for FormRecord {
+    fn to_template_value(&self) -> Result<Value, Error> {
+        let mut values = self.values.clone();
+        values.insert(ERRORS_KEY.into(), self.errors.to_template_value()?);
+        Ok(Value::Hash(values))
+    }
+}
+
+/// Escape a string so it can be used inside an HTML attribute or element.
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// HTML attributes of an element, in the order they were added.
+#[derive(Debug, Default)]
+struct Attributes {
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl Attributes {
+    fn set(&mut self, name: &str, value: impl ToString) -> &mut Self {
+        self.insert(name, Some(value.to_string()))
+    }
+
+    fn flag(&mut self, name: &str) -> &mut Self {
+        self.insert(name, None)
+    }
+
+    fn insert(&mut self, name: &str, value: Option<String>) -> &mut Self {
+        match self.attributes.iter_mut().find(|(n, _)| n == name) {
+            Some(attribute) => attribute.1 = value,
+            None => self.attributes.push((name.to_string(), value)),
+        }
+        self
+    }
+
+    /// Add attributes passed to a helper, as a hash or a list of `[name, value]` pairs.
+    /// Attributes passed by the user replace the ones set by the helper.
+    fn extend(&mut self, extra: Option<&Value>) -> Result<&mut Self, Error> {
+        let pairs = match extra {
+            None | Some(Value::Null) => vec![],
+            Some(Value::Hash(hash)) => {
+                let mut pairs = hash
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect::<Vec<_>>();
+                pairs.sort_by(|a, b| a.0.cmp(&b.0));
+                pairs
+            }
+            Some(Value::List(list)) => list
+                .iter()
+                .map(|pair| match pair {
+                    Value::List(pair) if pair.len() == 2 => {
+                        Ok((pair[0].to_string(), pair[1].clone()))
+                    }
+                    _ => Err(Error::Runtime(
+                        "attributes must be a list of [name, value] pairs".into(),
+                    )),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(value) => {
+                return Err(Error::Runtime(format!(
+                    "attributes must be a hash or a list, got {}",
+                    value.type_name()
+                )))
+            }
+        };
+
+        for (name, value) in pairs {
+            match value {
+                Value::Boolean(true) => {
+                    self.flag(&name);
+                }
+                Value::Boolean(false) | Value::Null => {
+                    self.attributes.retain(|(n, _)| n != &name);
+                }
+                value => {
+                    self.set(&name, value);
+                }
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+impl std::fmt::Display for Attributes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, value) in &self.attributes {
+            match value {
+                Some(value) => write!(f, r#" {}="{}""#, escape_html(name), escape_html(value))?,
+                None => write!(f, " {}", escape_html(name))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Call a form helper from a template. Returns `None` if the function isn't a form helper.
+pub(crate) fn call(name: &str, args: &[Value]) -> Option<Result<Value, Error>> {
+    let result = match name {
+        "form_for" => form_for(args),
+        "end_form" => Ok("</form>".to_string()),
+        "input" => input(args),
+        "select" => select(args),
+        "checkbox" => checkbox(args),
+        "errors_for" => errors_for(args),
+        "submit" => submit(args),
+        _ => return None,
+    };
+
+    Some(result.map(Value::SafeString))
+}
+
+/// Get the record and field name passed to a helper.
+fn field<'a>(helper: &str, args: &'a [Value]) -> Result<(&'a Value, String), Error> {
+    match args {
+        [record @ (Value::Hash(_) | Value::Null), Value::String(field), ..] => {
+            Ok((record, field.clone()))
+        }
+        _ => Err(Error::Runtime(format!(
+            "{}() requires a record and a field name",
+            helper
+        ))),
+    }
+}
+
+/// Current value of a record's field.
+fn value(record: &Value, field: &str) -> Value {
+    match record {
+        Value::Hash(hash) => hash.get(field).cloned().unwrap_or(Value::Null),
+        _ => Value::Null,
+    }
+}
+
+fn field_errors(record: &Value, field: &str) -> Vec<String> {
+    match value(record, ERRORS_KEY) {
+        Value::Hash(errors) => match errors.get(field) {
+            Some(Value::List(messages)) => messages.iter().map(|m| m.to_string()).collect(),
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// Values submitted with HTML forms are strings, so `"false"` must not check a checkbox.
+fn checked(value: &Value) -> bool {
+    match value {
+        Value::String(value) => matches!(value.as_str(), "true" | "on" | "1" | "t"),
+        value => value.truthy(),
+    }
+}
+
+fn form_for(args: &[Value]) -> Result<String, Error> {
+    let (record, action) = match args {
+        [record @ (Value::Hash(_) | Value::Null), Value::String(action), ..] => (record, action),
+        _ => {
+            return Err(Error::Runtime(
+                "form_for() requires a record and the form action".into(),
+            ))
+        }
+    };
+
+    let mut attributes = Attributes::default();
+    attributes.set("action", action).set("method", "post");
+    attributes.extend(args.get(2))?;
+
+    let mut form = format!("<form{}>", attributes);
+
+    // Records that were saved are updated, new ones are created.
+    if value(record, "id") != Value::Null {
+        form.push_str(&format!(
+            r#"<input type="hidden" name="{}" value="PUT">"#,
+            METHOD_OVERRIDE_INPUT
+        ));
+    }
+
+    let token = crypto::csrf_token().map_err(|err| Error::Runtime(err.to_string()))?;
+    form.push_str(&format!(
+        r#"<input type="hidden" name="{}" value="{}">"#,
+        CSRF_INPUT,
+        escape_html(&token)
+    ));
+
+    Ok(form)
+}
+
+fn input(args: &[Value]) -> Result<String, Error> {
+    let (record, field) = field("input", args)?;
+
+    let mut attributes = Attributes::default();
+    attributes
+        .set("type", "text")
+        .set("name", &field)
+        .set("id", &field);
+
+    match value(record, &field) {
+        Value::Null => (),
+        value => {
+            attributes.set("value", value);
+        }
+    }
+
+    if !field_errors(record, &field).is_empty() {
+        attributes.set("aria-invalid", "true");
+    }
+
+    attributes.extend(args.get(2))?;
+
+    Ok(format!("<input{}>", attributes))
+}
+
+fn select(args: &[Value]) -> Result<String, Error> {
+    let (record, field) = field("select", args)?;
+    let options = match args.get(2) {
+        Some(Value::List(options)) => options,
+        _ => return Err(Error::Runtime("select() requires a list of options".into())),
+    };
+
+    let mut attributes = Attributes::default();
+    attributes.set("name", &field).set("id", &field);
+
+    if !field_errors(record, &field).is_empty() {
+        attributes.set("aria-invalid", "true");
+    }
+
+    attributes.extend(args.get(3))?;
+
+    let current = match value(record, &field) {
+        Value::Null => None,
+        value => Some(value.to_string()),
+    };
+
+    let mut select = format!("<select{}>", attributes);
+
+    for option in options {
+        // Options are either values or [value, label] pairs.
+        let (value, label) = match option {
+            Value::List(pair) if pair.len() == 2 => (pair[0].to_string(), pair[1].to_string()),
+            option => (option.to_string(), option.to_string()),
+        };
+
+        let mut attributes = Attributes::default();
+        attributes.set("value", &value);
+
+        if current.as_ref() == Some(&value) {
+            attributes.flag("selected");
+        }
+
+        select.push_str(&format!(
+            "<option{}>{}</option>",
+            attributes,
+            escape_html(&label)
+        ));
+    }
+
+    select.push_str("</select>");
+
+    Ok(select)
+}
+
+fn checkbox(args: &[Value]) -> Result<String, Error> {
+    let (record, field) = field("checkbox", args)?;
+
+    let mut attributes = Attributes::default();
+    attributes
+        .set("type", "checkbox")
+        .set("name", &field)
+        .set("id", &field)
+        .set("value", "true");
+
+    if checked(&value(record, &field)) {
+        attributes.flag("checked");
+    }
+
+    attributes.extend(args.get(2))?;
+
+    // Unchecked checkboxes aren't submitted by the browser, so the hidden input
+    // makes sure the field is set to false.
+    Ok(format!(
+        r#"<input type="hidden" name="{}" value="false"><input{}>"#,
+        escape_html(&field),
+        attributes
+    ))
+}
+
+fn errors_for(args: &[Value]) -> Result<String, Error> {
+    let (record, field) = field("errors_for", args)?;
+    let errors = field_errors(record, &field);
+
+    if errors.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut attributes = Attributes::default();
+    attributes.set("class", "errors");
+    attributes.extend(args.get(2))?;
+
+    let mut list = format!("<ul{}>", attributes);
+    for error in errors {
+        list.push_str(&format!("<li>{}</li>", escape_html(&error)));
+    }
+    list.push_str("</ul>");
+
+    Ok(list)
+}
+
+fn submit(args: &[Value]) -> Result<String, Error> {
+    let label = match args.first() {
+        Some(Value::Null) | None => "Submit".to_string(),
+        Some(label) => label.to_string(),
+    };
+
+    let mut attributes = Attributes::default();
+    attributes.set("type", "submit").set("value", label);
+    attributes.extend(args.get(1))?;
+
+    Ok(format!("<input{}>", attributes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record() -> Value {
+        let mut errors = FormErrors::new();
+        errors.add("email", "can't be blank");
+
+        let mut hash = HashMap::new();
+        hash.insert("id".to_string(), Value::Integer(5));
+        hash.insert("name".to_string(), Value::String("\"Bob\" & <co>".into()));
+        hash.insert("role".to_string(), Value::String("admin".into()));
+        hash.insert("active".to_string(), Value::String("false".into()));
+        hash.insert(ERRORS_KEY.to_string(), errors.to_template_value().unwrap());
+        Value::Hash(hash)
+    }
+
+    fn call_helper(name: &str, args: &[Value]) -> String {
+        call(name, args).unwrap().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_input_escapes_value() {
+        let html = call_helper("input", &[record(), Value::String("name".into())]);
+        assert_eq!(
+            html,
+            r#"<input type="text" name="name" id="name" value="&quot;Bob&quot; &amp; &lt;co&gt;">"#
+        );
+    }
+
+    #[test]
+    fn test_attributes() {
+        let attributes = Value::List(vec![
+            Value::List(vec![
+                Value::String("type".into()),
+                Value::String("email".into()),
+            ]),
+            Value::List(vec![
+                Value::String("class".into()),
+                Value::String("\"><script>".into()),
+            ]),
+            Value::List(vec![Value::String("required".into()), Value::Boolean(true)]),
+        ]);
+        let html = call_helper(
+            "input",
+            &[record(), Value::String("email".into()), attributes],
+        );
+        assert_eq!(
+            html,
+            r#"<input type="email" name="email" id="email" aria-invalid="true" class="&quot;&gt;&lt;script&gt;" required>"#
+        );
+    }
+
+    #[test]
+    fn test_select_and_checkbox() {
+        let options = Value::List(vec![
+            Value::String("member".into()),
+            Value::List(vec![
+                Value::String("admin".into()),
+                Value::String("Administrator".into()),
+            ]),
+        ]);
+        let html = call_helper("select", &[record(), Value::String("role".into()), options]);
+        assert_eq!(
+            html,
+            r#"<select name="role" id="role"><option value="member">member</option><option value="admin" selected>Administrator</option></select>"#
+        );
+
+        let html = call_helper("checkbox", &[record(), Value::String("active".into())]);
+        assert_eq!(
+            html,
+            r#"<input type="hidden" name="active" value="false"><input type="checkbox" name="active" id="active" value="true">"#
+        );
+    }
+
+    #[test]
+    fn test_errors_for() {
+        let html = call_helper("errors_for", &[record(), Value::String("email".into())]);
+        assert_eq!(
+            html,
+            r#"<ul class="errors"><li>can&#x27;t be blank</li></ul>"#
+        );
+
+        let html = call_helper("errors_for", &[record(), Value::String("name".into())]);
+        assert_eq!(html, "");
+    }
+
+    #[test]
+    fn test_form_for() {
+        let html = call_helper("form_for", &[record(), Value::String("/users/5".into())]);
+        assert!(html.starts_with(
+            r#"<form action="/users/5" method="post"><input type="hidden" name="_method" value="PUT">"#
+        ));
+        assert!(html.contains(CSRF_INPUT));
+
+        let html = call_helper("form_for", &[Value::Null, Value::String("/users".into())]);
+        assert!(!html.contains(METHOD_OVERRIDE_INPUT));
+    }
+}
@@ -0,0 +1,383 @@
+//! Conservative HTML minification.
+//!
+//! Whitespace between block elements and at the start and the end of blocks is removed, other whitespace is collapsed to a single space, and comments
+//! are removed, except for conditional comments. The content of `<pre>`, `<textarea>`, `<script>` and `<style>`
+//! is kept exactly as it is, and so are tags and their attributes, so the page looks and behaves the same.
+//!
+//! ```
+//! use rwf::view::minify::minify_html;
+//!
+//! let html = minify_html("<ul>\n  <li><b>Hello</b>   <i>world</i></li>\n</ul>\n<!-- todo -->");
+//! assert_eq!(html, "<ul><li><b>Hello</b> <i>world</i></li></ul>");
+//! ```
+//!
+//! Responses are minified by the [`HtmlPipeline`](crate::controller::middleware::HtmlPipeline) middleware.
+
+/// Elements which content is kept as-is.
+static PRESERVE: &[&str] = &["pre", "textarea", "script", "style"];
+
+/// Elements around which whitespace doesn't change how the page looks.
+static BLOCK: &[&str] = &[
+    "!doctype",
+    "address",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "br",
+    "caption",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "noscript",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+#[derive(Debug)]
+enum Token<'a> {
+    /// Tag, including its attributes, and its lowercase name.
+    Tag(&'a str, String),
+    Text(&'a str),
+    /// Comment or content which is kept as-is.
+    Raw(&'a str),
+}
+
+/// Minify HTML. See the [module documentation](self) for what's changed.
+pub fn minify_html(html: &str) -> String {
+    let tokens = tokenize(html);
+    let mut minified = String::with_capacity(html.len());
+
+    let mut i = 0;
+
+    while let Some(token) = tokens.get(i) {
+        match token {
+            Token::Tag(tag, _) | Token::Raw(tag) => {
+                minified.push_str(tag);
+                i += 1;
+            }
+            Token::Text(_) => {
+                // Text around a removed comment is split in several tokens.
+                let start = i;
+                let mut text = String::new();
+                while let Some(Token::Text(part)) = tokens.get(i) {
+                    text.push_str(part);
+                    i += 1;
+                }
+
+                // Whitespace at the start or the end of a block isn't displayed.
+                let mut text = text.as_str();
+                if block(start.checked_sub(1).and_then(|i| tokens.get(i))) {
+                    text = text.trim_start_matches(|c: char| c.is_ascii_whitespace());
+                }
+                if block(tokens.get(i)) {
+                    text = text.trim_end_matches(|c: char| c.is_ascii_whitespace());
+                }
+
+                collapse(text, &mut minified);
+            }
+        }
+    }
+
+    minified
+}
+
+/// Whitespace next to this token can be removed.
+fn block(token: Option<&Token>) -> bool {
+    match token {
+        None => true,
+        Some(Token::Tag(_, name)) => BLOCK.contains(&name.as_str()),
+        _ => false,
+    }
+}
+
+/// Collapse runs of whitespace to a single space.
+fn collapse(text: &str, output: &mut String) {
+    let mut whitespace = false;
+
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !whitespace {
+                output.push(' ');
+            }
+            whitespace = true;
+        } else {
+            output.push(c);
+            whitespace = false;
+        }
+    }
+}
+
+fn tokenize(html: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut text = 0;
+    let mut i = 0;
+
+    while let Some(offset) = html[i..].find('<') {
+        let start = i + offset;
+        let rest = &html[start..];
+
+        let end = if let Some(body) = rest.strip_prefix("<!--") {
+            let end = body
+                .find("-->")
+                .map(|end| start + 4 + end + 3)
+                .unwrap_or(html.len());
+            let comment = &html[start..end];
+
+            // Conditional comments, e.g. <!--[if IE]>, are read by the browser.
+            push_text(&mut tokens, &html[text..start]);
+            if comment.starts_with("<!--[if") || comment.contains("<![endif]") {
+                tokens.push(Token::Raw(comment));
+            }
+            end
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!') {
+            let end = start + tag_end(rest);
+            let tag = &html[start..end];
+            let name = tag_name(tag);
+
+            push_text(&mut tokens, &html[text..start]);
+            tokens.push(Token::Tag(tag, name.clone()));
+
+            let opening = !tag.starts_with("</") && !tag.ends_with("/>");
+
+            if opening && PRESERVE.contains(&name.as_str()) {
+                let close = find_ignore_case(&html[end..], &format!("</{}", name))
+                    .map(|close| end + close)
+                    .unwrap_or(html.len());
+                if close > end {
+                    tokens.push(Token::Raw(&html[end..close]));
+                }
+                close
+            } else {
+                end
+            }
+        } else {
+            // Not a tag, e.g. "1 < 2".
+            i = start + 1;
+            continue;
+        };
+
+        text = end;
+        i = end;
+    }
+
+    push_text(&mut tokens, &html[text..]);
+
+    tokens
+}
+
+fn push_text<'a>(tokens: &mut Vec<Token<'a>>, text: &'a str) {
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+}
+
+/// Find the end of the tag starting at the beginning of `html`, skipping `>` inside quoted attributes.
+fn tag_end(html: &str) -> usize {
+    let mut quote = None;
+
+    for (i, c) in html.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return i + 1,
+            _ => (),
+        }
+    }
+
+    html.len()
+}
+
+/// Lowercase name of the tag, e.g. `div` for `<div class="a">` and `</DIV>`.
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| !c.is_ascii_whitespace() && *c != '>' && *c != '/')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Inline critical CSS into a `<style>` tag at the end of `<head>`, and load the stylesheets linked in `<head>`
+/// asynchronously, so the page is displayed before they are downloaded.
+///
+/// Pages without a `<head>` are returned unchanged.
+pub fn inline_critical_css(html: &str, css: &str, nonce: Option<&str>) -> String {
+    let head_end = match find_ignore_case(html, "</head") {
+        Some(end) => end,
+        None => return html.to_string(),
+    };
+
+    let nonce = nonce
+        .map(|nonce| format!(r#" nonce="{}""#, nonce))
+        .unwrap_or_default();
+
+    let mut inlined = String::with_capacity(html.len() + css.len() + 32);
+    let mut i = 0;
+
+    while let Some(start) = find_ignore_case(&html[i..head_end], "<link") {
+        let start = i + start;
+        let end = start + tag_end(&html[start..]);
+        let link = &html[start..end];
+
+        inlined.push_str(&html[i..start]);
+
+        if let Some((rel_start, rel_end)) = stylesheet(link) {
+            inlined.push_str(&link[..rel_start]);
+            inlined.push_str(
+                r#"rel="preload" as="style" onload="this.onload=null;this.rel='stylesheet'""#,
+            );
+            inlined.push_str(&link[rel_end..]);
+            inlined.push_str("<noscript>");
+            inlined.push_str(link);
+            inlined.push_str("</noscript>");
+        } else {
+            inlined.push_str(link);
+        }
+
+        i = end;
+    }
+
+    inlined.push_str(&html[i..head_end]);
+    inlined.push_str(&format!("<style{}>{}</style>", nonce, css));
+    inlined.push_str(&html[head_end..]);
+
+    inlined
+}
+
+/// Position of the `rel` attribute, if the `<link>` tag loads a stylesheet.
+fn stylesheet(link: &str) -> Option<(usize, usize)> {
+    let start = link.to_ascii_lowercase().find("rel=")?;
+    let value = &link[start + 4..];
+
+    let (rel, len) = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            let end = value[1..].find(quote)?;
+            (&value[1..end + 1], end + 2)
+        }
+        _ => {
+            let end = value
+                .find(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+                .unwrap_or(value.len());
+            (&value[..end], end)
+        }
+    };
+
+    if rel.eq_ignore_ascii_case("stylesheet") {
+        Some((start, start + 4 + len))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_minify_html() {
+        for (html, expected) in [
+            ("<p>\n  Hello\n  world\n</p>\n", "<p>Hello world</p>"),
+            ("<b>a</b>   <i>b</i>", "<b>a</b> <i>b</i>"),
+            (
+                "<div>\n  <span>a</span>\n</div>",
+                "<div><span>a</span></div>",
+            ),
+            ("a <!-- comment --> b", "a b"),
+            (
+                "<!--[if IE]><p>IE</p><![endif]-->",
+                "<!--[if IE]><p>IE</p><![endif]-->",
+            ),
+            ("1 < 2 and 3 > 2", "1 < 2 and 3 > 2"),
+            (
+                r#"<a title="a  >  b"  href="/">x</a>"#,
+                r#"<a title="a  >  b"  href="/">x</a>"#,
+            ),
+            (
+                "<PRE>  a\n\n  b</PRE>\n<p> x </p>",
+                "<PRE>  a\n\n  b</PRE><p>x</p>",
+            ),
+            (
+                "<script>\n  if (a < b) {\n    x();\n  }\n</script>",
+                "<script>\n  if (a < b) {\n    x();\n  }\n</script>",
+            ),
+            (
+                "<textarea>\n  <!-- kept -->\n</textarea>",
+                "<textarea>\n  <!-- kept -->\n</textarea>",
+            ),
+            ("<br/>\n<pre/>  a", "<br/><pre/> a"),
+            ("<p>unterminated <!-- comment", "<p>unterminated"),
+        ] {
+            assert_eq!(minify_html(html), expected, "{}", html);
+        }
+    }
+
+    #[test]
+    fn test_inline_critical_css() {
+        let html = r#"<html><head><link rel="stylesheet" href="/app.css"><link rel=icon href="/favicon.ico"></head><body><link rel="stylesheet" href="/late.css"></body></html>"#;
+
+        assert_eq!(
+            inline_critical_css(html, "h1{color:red}", Some("abc")),
+            r#"<html><head><link rel="preload" as="style" onload="this.onload=null;this.rel='stylesheet'" href="/app.css"><noscript><link rel="stylesheet" href="/app.css"></noscript><link rel=icon href="/favicon.ico"><style nonce="abc">h1{color:red}</style></head><body><link rel="stylesheet" href="/late.css"></body></html>"#
+        );
+
+        assert_eq!(
+            inline_critical_css("<LINK REL=stylesheet href=a.css></HEAD>", "p{}", None),
+            r#"<LINK rel="preload" as="style" onload="this.onload=null;this.rel='stylesheet'" href=a.css><noscript><LINK REL=stylesheet href=a.css></noscript><style>p{}</style></HEAD>"#
+        );
+
+        assert_eq!(
+            inline_critical_css("<p>no head</p>", "p{}", None),
+            "<p>no head</p>"
+        );
+    }
+}
@@ -2,11 +2,23 @@
 //!
 //! See [documentation](https://levkk.github.io/rwf/views/) on how to use templates.
 pub mod cache;
+pub mod engine;
+pub mod form;
+#[cfg(feature = "html-pipeline")]
+pub mod minify;
+pub mod pagination;
 pub mod prelude;
+pub mod provider;
+pub mod sanitize;
 pub mod template;
 pub mod turbo;
 
 pub use cache::Templates;
+pub use engine::ViewEngine;
+pub use form::{FormErrors, FormRecord};
+pub use pagination::Pagination;
+pub use provider::{provide, Provider};
+pub use sanitize::sanitize_html;
 pub use template::Context;
 pub use template::Error;
 pub use template::Template;
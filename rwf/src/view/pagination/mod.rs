@@ -0,0 +1,378 @@
+//! Links to the pages of a paginated list.
+//!
+//! [`Pagination`] describes which page of a list is shown, and the `paginate` template helper
+//! renders the links to the previous, next and nearby pages:
+//!
+//! ```erb
+//! <%= paginate(pagination) %>
+//! ```
+//!
+//! The markup comes from a partial shipped with Rwf. Apps can replace it by creating
+//! `templates/rwf/pagination.html`, see [`PARTIAL`].
+use once_cell::sync::Lazy;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::{Context, Error, Template, ToTemplateValue, Value};
+use crate::http::{Request, Url};
+
+/// Partial used by the `paginate` helper instead of the default one, if it exists.
+pub const PARTIAL: &str = "templates/rwf/pagination.html";
+
+/// Query parameter with the page number.
+pub const DEFAULT_PARAM: &str = "page";
+
+/// Number of pages linked on each side of the current page.
+const WINDOW: usize = 2;
+
+static TEMPLATE: Lazy<Template> =
+    Lazy::new(|| Template::from_str(include_str!("pagination.html")).unwrap());
+
+/// Current page of a paginated list.
+///
+/// # Example
+///
+/// ```
+/// use rwf::view::Pagination;
+///
+/// let pagination = Pagination::new(3, "/orders?status=paid").total(95, 10);
+///
+/// assert_eq!(pagination.offset(10), 20);
+/// assert_eq!(pagination.pages(), Some(10));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pagination {
+    page: usize,
+    pages: Option<usize>,
+    has_next: bool,
+    url: Url,
+    param: String,
+}
+
+impl Pagination {
+    /// Page number `page`, starting at 1, of the list at `url`. The links keep the query parameters
+    /// of the URL, except the page number.
+    pub fn new(page: usize, url: impl Into<Url>) -> Self {
+        Self {
+            page: page.max(1),
+            pages: None,
+            has_next: false,
+            url: url.into(),
+            param: DEFAULT_PARAM.into(),
+        }
+    }
+
+    /// Page of the list the request is for, from the `page` query parameter.
+    pub fn from_request(request: &Request) -> Self {
+        let query = request.path().query();
+        let page = query.get::<usize>(DEFAULT_PARAM).unwrap_or(1);
+
+        // Sorted, so the links are the same on every request.
+        let mut params = query.iter().collect::<Vec<_>>();
+        params.sort();
+
+        let url = params
+            .into_iter()
+            .fold(Url::parse(request.path().base()), |url, (name, value)| {
+                url.query(name, value)
+            });
+
+        Self::new(page, url)
+    }
+
+    /// Query parameter with the page number, `page` by default.
+    pub fn param(mut self, param: impl ToString) -> Self {
+        self.param = param.to_string();
+        self
+    }
+
+    /// Set the number of pages from the number of items in the list.
+    pub fn total(mut self, total: usize, per_page: usize) -> Self {
+        self.pages = Some(total.div_ceil(per_page.max(1)).max(1));
+        self
+    }
+
+    /// Set the number of pages.
+    pub fn with_pages(mut self, pages: usize) -> Self {
+        self.pages = Some(pages.max(1));
+        self
+    }
+
+    /// Is there a page after this one? Used when the number of pages isn't known,
+    /// e.g. with cursor pagination.
+    pub fn has_next(mut self, has_next: bool) -> Self {
+        self.has_next = has_next;
+        self
+    }
+
+    /// Current page, starting at 1.
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    /// Number of pages, if known.
+    pub fn pages(&self) -> Option<usize> {
+        self.pages
+    }
+
+    /// Number of items before the current page, e.g. for `OFFSET`.
+    pub fn offset(&self, per_page: usize) -> usize {
+        (self.page - 1) * per_page
+    }
+}
+
+impl ToTemplateValue for Pagination {
+    fn to_template_value(&self) -> Result<Value, Error> {
+        Ok(Value::Hash(HashMap::from([
+            ("page".into(), Value::Integer(self.page as i64)),
+            (
+                "pages".into(),
+                self.pages
+                    .map(|pages| Value::Integer(pages as i64))
+                    .unwrap_or(Value::Null),
+            ),
+            ("has_next".into(), Value::Boolean(self.has_next)),
+            (
+                "url".into(),
+                Value::String(self.url.clone().remove_query(&self.param).to_string()),
+            ),
+            ("param".into(), Value::String(self.param.clone())),
+        ])))
+    }
+}
+
+/// Link in the list of pages.
+#[derive(Debug, PartialEq)]
+enum Link {
+    Page(usize),
+    Gap,
+}
+
+/// First and last pages, and the pages around the current one. Gaps of one page
+/// are filled with that page.
+fn links(page: usize, pages: Option<usize>) -> Vec<Link> {
+    let last = pages.unwrap_or(page);
+    let start = page.saturating_sub(WINDOW).max(1);
+    let end = (page + WINDOW).min(last);
+
+    let mut numbers = vec![1];
+    numbers.extend(start..=end);
+    if pages.is_some() {
+        numbers.push(last);
+    }
+    numbers.sort();
+    numbers.dedup();
+
+    let mut links = vec![];
+    let mut previous = 0;
+
+    for number in numbers {
+        match number - previous {
+            1 => (),
+            2 => links.push(Link::Page(number - 1)),
+            _ => links.push(Link::Gap),
+        }
+        links.push(Link::Page(number));
+        previous = number;
+    }
+
+    links
+}
+
+/// The `paginate` template helper.
+pub(crate) fn paginate(args: &[Value]) -> Result<Value, Error> {
+    let pagination = match args {
+        [Value::Hash(pagination)] => pagination,
+        _ => {
+            return Err(Error::Runtime(
+                "paginate() requires the pagination of the list".into(),
+            ))
+        }
+    };
+
+    let page = match pagination.get("page") {
+        Some(Value::Integer(page)) if *page > 0 => *page as usize,
+        _ => 1,
+    };
+    let pages = match pagination.get("pages") {
+        Some(Value::Integer(pages)) if *pages > 0 => Some(*pages as usize),
+        _ => None,
+    };
+    let has_next = match pages {
+        Some(pages) => page < pages,
+        None => pagination
+            .get("has_next")
+            .map(|value| value.truthy())
+            .unwrap_or(false),
+    };
+
+    // Nothing to navigate to.
+    if page == 1 && !has_next {
+        return Ok(Value::SafeString(String::new()));
+    }
+
+    let url = match pagination.get("url") {
+        Some(Value::String(url)) => Url::parse(url),
+        _ => Url::new(),
+    };
+    let param = match pagination.get("param") {
+        Some(Value::String(param)) => param.clone(),
+        _ => DEFAULT_PARAM.to_string(),
+    };
+    let url_for = |page: usize| Value::String(url.clone().set_query(&param, page).to_string());
+
+    let links = links(page, pages)
+        .into_iter()
+        .map(|link| {
+            let link = match link {
+                Link::Page(number) => HashMap::from([
+                    ("number".into(), Value::Integer(number as i64)),
+                    ("url".into(), url_for(number)),
+                    ("current".into(), Value::Boolean(number == page)),
+                    ("gap".into(), Value::Boolean(false)),
+                ]),
+                Link::Gap => HashMap::from([("gap".into(), Value::Boolean(true))]),
+            };
+
+            Value::Hash(link)
+        })
+        .collect();
+
+    let mut context = Context::new();
+    context["links"] = Value::List(links);
+    context["previous"] = if page > 1 {
+        url_for(page - 1)
+    } else {
+        Value::Null
+    };
+    context["next"] = if has_next {
+        url_for(page + 1)
+    } else {
+        Value::Null
+    };
+
+    let html = if Path::new(PARTIAL).is_file() {
+        Template::load(PARTIAL)?.render(&context)?
+    } else {
+        TEMPLATE.render(&context)?
+    };
+
+    Ok(Value::SafeString(html))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs::read_to_string;
+
+    fn render(pagination: Pagination) -> String {
+        let template = Template::from_str("<%= paginate(pagination) %>").unwrap();
+        template
+            .render([("pagination", pagination.to_template_value().unwrap())])
+            .unwrap()
+    }
+
+    /// Compare the markup to the golden file in `src/view/pagination/golden`.
+    /// Set `RWF_UPDATE_GOLDEN=1` to update the golden files instead.
+    fn check_golden(name: &str, html: &str) {
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/view/pagination/golden")
+            .join(format!("{}.html", name));
+
+        if std::env::var("RWF_UPDATE_GOLDEN").is_ok() {
+            std::fs::write(&golden, html).unwrap();
+        } else {
+            assert_eq!(
+                read_to_string(&golden).unwrap(),
+                html,
+                "{} doesn't match the golden file",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_links() {
+        use Link::*;
+
+        assert_eq!(links(1, Some(1)), vec![Page(1)]);
+        assert_eq!(
+            links(1, Some(10)),
+            vec![Page(1), Page(2), Page(3), Gap, Page(10)]
+        );
+        assert_eq!(
+            links(5, Some(10)),
+            vec![
+                Page(1),
+                Page(2),
+                Page(3),
+                Page(4),
+                Page(5),
+                Page(6),
+                Page(7),
+                Gap,
+                Page(10)
+            ]
+        );
+        assert_eq!(
+            links(6, Some(20)),
+            vec![
+                Page(1),
+                Gap,
+                Page(4),
+                Page(5),
+                Page(6),
+                Page(7),
+                Page(8),
+                Gap,
+                Page(20)
+            ]
+        );
+        assert_eq!(
+            links(6, None),
+            vec![Page(1), Gap, Page(4), Page(5), Page(6)]
+        );
+    }
+
+    #[test]
+    fn test_paginate() {
+        let url = "/orders?status=paid&page=4";
+
+        assert_eq!(render(Pagination::new(1, url).with_pages(1)), "");
+        assert_eq!(render(Pagination::new(1, url)), "");
+
+        check_golden("first", &render(Pagination::new(1, url).total(95, 10)));
+        check_golden("middle", &render(Pagination::new(6, url).with_pages(20)));
+        check_golden("last", &render(Pagination::new(20, url).with_pages(20)));
+        check_golden(
+            "cursor",
+            &render(
+                Pagination::new(6, "/orders?status=paid&p=6")
+                    .has_next(true)
+                    .param("p"),
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_request() {
+        let request = Request::read(
+            "127.0.0.1:1234".parse().unwrap(),
+            &b"GET /orders?status=paid&page=3&q=rock%20%26%20roll HTTP/1.1\r\n\r\n"[..],
+        )
+        .await
+        .unwrap();
+
+        let pagination = Pagination::from_request(&request).total(50, 10);
+        assert_eq!(pagination.page(), 3);
+
+        let html = render(pagination);
+        assert!(
+            html.contains(r#"href="/orders?q=rock+%26+roll&status=paid&page=2" rel="prev""#),
+            "{}",
+            html
+        );
+    }
+}
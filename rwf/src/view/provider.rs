@@ -0,0 +1,287 @@
+//! Template variables loaded asynchronously, e.g. data needed by layouts.
+//!
+//! Layouts often show data, like the number of unread notifications, which controllers
+//! shouldn't have to fetch themselves. Register a provider for the variable instead:
+//!
+//! ```rust,ignore
+//! use rwf::view::provide;
+//!
+//! provide("unread_count", |request| async move {
+//!     let user = request.user::<User>(...).await?;
+//!     Notification::unread(user.id).count(...).await
+//! });
+//! ```
+//!
+//! Providers are only called when a template evaluates their variable, at most once per request.
+//! If a provider fails or takes longer than its timeout, the variable gets its fallback value.
+//!
+//! Providers are resolved for pages rendered with [`Response::template`](crate::http::Response::template),
+//! for controller layouts, and with [`Template::render_for`]. Templates rendered synchronously,
+//! e.g. with `render!`, get the fallback value.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::OnceCell;
+use tokio::time::timeout;
+use tracing::warn;
+
+use super::{Context, Error, Template, ToTemplateValue, Value};
+use crate::http::Request;
+
+/// How long a provider can take, unless set with [`Provider::timeout`].
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+static PROVIDERS: Lazy<RwLock<HashMap<String, Arc<Provider>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+type Provide = Box<
+    dyn Fn(Request) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send>> + Send + Sync,
+>;
+
+/// Asynchronous provider of a template variable.
+pub struct Provider {
+    name: String,
+    provide: Provide,
+    timeout: Duration,
+    fallback: Value,
+}
+
+impl Provider {
+    /// Create a provider for the variable. The closure receives the request being rendered.
+    pub fn new<F, Fut, T, E>(name: impl ToString, provide: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: ToTemplateValue,
+        E: Display,
+    {
+        Self {
+            name: name.to_string(),
+            provide: Box::new(move |request| {
+                let future = provide(request);
+                Box::pin(async move {
+                    match future.await {
+                        Ok(value) => value.to_template_value().map_err(|err| err.to_string()),
+                        Err(err) => Err(err.to_string()),
+                    }
+                })
+            }),
+            timeout: DEFAULT_TIMEOUT,
+            fallback: Value::Null,
+        }
+    }
+
+    /// How long the provider can take before the fallback value is used instead.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Value used if the provider fails or times out. The default is `null`.
+    pub fn fallback(mut self, fallback: impl ToTemplateValue) -> Self {
+        self.fallback = fallback.to_template_value().unwrap_or(Value::Null);
+        self
+    }
+
+    /// Register the provider, replacing any provider for the same variable.
+    pub fn register(self) {
+        PROVIDERS.write().insert(self.name.clone(), Arc::new(self));
+    }
+
+    async fn call(&self, request: &Request) -> Value {
+        match timeout(self.timeout, (self.provide)(request.clone())).await {
+            Ok(Ok(value)) => value,
+            Ok(Err(err)) => {
+                warn!("template provider \"{}\" failed: {}", self.name, err);
+                self.fallback.clone()
+            }
+            Err(_) => {
+                warn!(
+                    "template provider \"{}\" timed out after {:?}",
+                    self.name, self.timeout
+                );
+                self.fallback.clone()
+            }
+        }
+    }
+}
+
+/// Register a provider for the variable, with the default timeout and a `null` fallback.
+/// Use [`Provider`] to change them.
+pub fn provide<F, Fut, T, E>(name: impl ToString, provide: F)
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T, E>> + Send + 'static,
+    T: ToTemplateValue,
+    E: Display,
+{
+    Provider::new(name, provide).register()
+}
+
+/// Values resolved by providers for one request.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Provided {
+    values: Arc<Mutex<HashMap<String, Arc<OnceCell<Value>>>>>,
+}
+
+/// Fallback value of the provider for the variable, if there is one.
+pub(crate) fn fallback(name: &str) -> Option<Value> {
+    PROVIDERS
+        .read()
+        .get(name)
+        .map(|provider| provider.fallback.clone())
+}
+
+/// Names of all variables with a provider.
+pub(crate) fn names() -> Vec<String> {
+    PROVIDERS.read().keys().cloned().collect()
+}
+
+/// Value of the variable for this request, calling its provider the first time.
+async fn resolve(request: &Request, name: &str) -> Value {
+    let provider = match PROVIDERS.read().get(name) {
+        Some(provider) => provider.clone(),
+        None => return Value::Null,
+    };
+
+    let cell = request
+        .provided()
+        .values
+        .lock()
+        .entry(name.to_string())
+        .or_default()
+        .clone();
+
+    cell.get_or_init(|| provider.call(request)).await.clone()
+}
+
+/// Render the template, resolving the variables with providers it uses. The template is rendered
+/// again each time it uses new ones, until all are resolved.
+pub(crate) async fn render(
+    template: &Template,
+    context: &mut Context,
+    request: &Request,
+) -> Result<String, Error> {
+    loop {
+        context.track_providers();
+        let content = template.render(&*context)?;
+        let wanted = context.wanted_providers();
+
+        if wanted.is_empty() {
+            return Ok(content);
+        }
+
+        for name in wanted {
+            let value = resolve(request, &name).await;
+            context.set(&name, value)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counter(name: &str, value: i64) -> Arc<AtomicUsize> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+
+        provide(name, move |_request| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, Error>(value)
+            }
+        });
+
+        calls
+    }
+
+    #[tokio::test]
+    async fn test_lazy() {
+        let calls = counter("test_lazy_count", 5);
+        let template =
+            Template::from_str("<% if show %><%= test_lazy_count %><% end %>done").unwrap();
+
+        let request = Request::default();
+        let mut context = Context::new();
+        context.set("show", false).unwrap();
+        let html = template.render_for(&request, &context).await.unwrap();
+        assert_eq!(html, "done");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        context.set("show", true).unwrap();
+        let html = template.render_for(&request, &context).await.unwrap();
+        assert_eq!(html, "5done");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Values set by the controller win.
+        context.set("test_lazy_count", 7).unwrap();
+        let html = template
+            .render_for(&Request::default(), &context)
+            .await
+            .unwrap();
+        assert_eq!(html, "7done");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_memoized() {
+        let calls = counter("test_memo_count", 3);
+        let page = Template::from_str("<%= test_memo_count %> and <%= test_memo_count %>").unwrap();
+        let layout = Template::from_str("<%= test_memo_count + 1 %>: <%= yield %>").unwrap();
+
+        let request = Request::default();
+        let mut context = Context::new();
+        let content = render(&page, &mut context, &request).await.unwrap();
+        assert_eq!(content, "3 and 3");
+
+        // Rendered again for the layout, with a new context.
+        let mut context = Context::new();
+        context.set("yield", Value::SafeString(content)).unwrap();
+        let html = render(&layout, &mut context, &request).await.unwrap();
+        assert_eq!(html, "4: 3 and 3");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Once per request.
+        render(&page, &mut Context::new(), &Request::default())
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        Provider::new("test_slow_count", |_request| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok::<_, Error>(1)
+        })
+        .timeout(Duration::from_millis(10))
+        .fallback("?")
+        .register();
+
+        Provider::new("test_failing_count", |_request| async {
+            Err::<i64, _>(Error::Runtime("database is down".into()))
+        })
+        .fallback(0)
+        .register();
+
+        let template =
+            Template::from_str("<%= test_slow_count %> <%= test_failing_count %>").unwrap();
+        let html = template
+            .render_for(&Request::default(), &Context::new())
+            .await
+            .unwrap();
+        assert_eq!(html, "? 0");
+
+        // Rendering without a request uses the fallback right away.
+        assert_eq!(template.render(&Context::new()).unwrap(), "? 0");
+    }
+}
@@ -0,0 +1,692 @@
+//! Sanitize user-generated HTML, e.g. from rich text editors.
+//!
+//! The input is tokenized the same way a browser would, and only the elements and attributes
+//! allowed by the [`Policy`] are kept. The result is serialized again from scratch: text
+//! and attribute values are always escaped, and tags are always closed, so the output is parsed by
+//! the browser exactly as it was by the sanitizer.
+//!
+//! Comments, processing instructions, `<!DOCTYPE>`, event handlers (`on*`), and `style` attributes are always removed.
+//! Elements that can run scripts or that change how their content is parsed,
+//! e.g. `<script>`, `<style>`, `<svg>` or `<textarea>`, are removed with all their content, even if the policy allows them.
+//!
+//! ### Example
+//!
+//! ```
+//! use rwf::view::sanitize::{sanitize_html, Policy};
+//!
+//! let html = sanitize_html(
+//!     r#"<p onclick="steal()">Hello <a href="javascript:alert(1)">there</a></p><script>alert(1)</script>"#,
+//!     &Policy::basic(),
+//! );
+//! assert_eq!(html, "<p>Hello <a>there</a></p>");
+//! ```
+use std::collections::HashMap;
+
+use super::form::escape_html;
+
+/// Elements removed with their content, regardless of the policy.
+static REMOVE_WITH_CONTENT: &[&str] = &[
+    "script",
+    "style",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "noscript",
+    "plaintext",
+    "textarea",
+    "title",
+    "template",
+    "svg",
+    "math",
+    "object",
+    "embed",
+    "applet",
+    "frameset",
+    "frame",
+    "base",
+    "meta",
+    "link",
+];
+
+/// Elements whose content isn't parsed as HTML by the browser.
+static RAW_TEXT: &[&str] = &[
+    "script", "style", "xmp", "iframe", "noembed", "noframes", "noscript", "textarea", "title",
+];
+
+/// Elements without a closing tag.
+static VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "frame", "hr", "img", "input", "link", "meta", "param",
+    "source", "track", "wbr",
+];
+
+/// Attributes containing URLs.
+static URL_ATTRIBUTES: &[&str] = &["href", "src", "cite", "action", "background", "poster"];
+
+/// Elements, attributes and URL schemes allowed in sanitized HTML.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    tags: HashMap<String, Vec<String>>,
+    schemes: Vec<String>,
+}
+
+impl Policy {
+    /// Policy that allows nothing. Text is kept, all tags are removed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Formatted text, links and lists:
+    /// `b`, `i`, `strong`, `em`, `p`, `br`, `ul`, `ol`, `li`, and `a` with `href` using
+    /// the `http`, `https` or `mailto` schemes.
+    pub fn basic() -> Self {
+        Self::new()
+            .tags(&["b", "i", "strong", "em", "p", "br", "ul", "ol", "li"])
+            .tag("a", &["href", "title"])
+            .schemes(&["http", "https", "mailto"])
+    }
+
+    /// Everything allowed by [`Policy::basic`], plus headings, quotes, code blocks, images and tables.
+    pub fn relaxed() -> Self {
+        Self::basic()
+            .tags(&[
+                "h1",
+                "h2",
+                "h3",
+                "h4",
+                "h5",
+                "h6",
+                "blockquote",
+                "code",
+                "pre",
+                "hr",
+                "span",
+                "div",
+                "table",
+                "thead",
+                "tbody",
+                "tfoot",
+                "tr",
+                "caption",
+            ])
+            .tag("img", &["src", "alt", "title", "width", "height"])
+            .tag("th", &["colspan", "rowspan"])
+            .tag("td", &["colspan", "rowspan"])
+    }
+
+    /// Get a built-in policy by name, i.e. `basic` or `relaxed`.
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "basic" => Some(Self::basic()),
+            "relaxed" => Some(Self::relaxed()),
+            _ => None,
+        }
+    }
+
+    /// Allow an element with the given attributes.
+    pub fn tag(mut self, name: &str, attributes: &[&str]) -> Self {
+        let allowed = self.tags.entry(name.to_lowercase()).or_default();
+        for attribute in attributes {
+            let attribute = attribute.to_lowercase();
+            if !allowed.contains(&attribute) {
+                allowed.push(attribute);
+            }
+        }
+        self
+    }
+
+    /// Allow elements without attributes.
+    pub fn tags(self, names: &[&str]) -> Self {
+        names
+            .iter()
+            .fold(self, |policy, name| policy.tag(name, &[]))
+    }
+
+    /// Allow URLs with the given schemes, e.g. `https`, in links and images.
+    /// Relative URLs are always allowed.
+    pub fn schemes(mut self, schemes: &[&str]) -> Self {
+        self.schemes
+            .extend(schemes.iter().map(|scheme| scheme.to_lowercase()));
+        self
+    }
+
+    fn allows_tag(&self, name: &str) -> bool {
+        self.tags.contains_key(name) && !REMOVE_WITH_CONTENT.contains(&name)
+    }
+
+    fn allows_attribute(&self, tag: &str, name: &str, value: &str) -> bool {
+        if name.starts_with("on") || name == "style" {
+            return false;
+        }
+
+        match self.tags.get(tag) {
+            Some(attributes) if attributes.iter().any(|a| a == name) => {
+                !URL_ATTRIBUTES.contains(&name) || self.allows_url(value)
+            }
+            _ => false,
+        }
+    }
+
+    fn allows_url(&self, url: &str) -> bool {
+        // Browsers ignore whitespace and control characters in the scheme.
+        let url = url
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+            .collect::<String>();
+
+        match url.find(|c| [':', '/', '?', '#'].contains(&c)) {
+            Some(position) if url[position..].starts_with(':') => {
+                let scheme = url[..position].to_lowercase();
+                self.schemes.contains(&scheme)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Remove all elements and attributes not allowed by the policy.
+pub fn sanitize_html(input: &str, policy: &Policy) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut open: Vec<String> = vec![];
+
+    for token in Tokenizer::new(input) {
+        match token {
+            Token::Text(text) => output.push_str(&escape_html(&text)),
+
+            Token::StartTag { name, attributes } => {
+                if !policy.allows_tag(&name) {
+                    continue;
+                }
+
+                output.push('<');
+                output.push_str(&name);
+
+                let mut seen = vec![];
+                for (attribute, value) in attributes {
+                    // The browser uses the first one.
+                    if seen.contains(&attribute) {
+                        continue;
+                    }
+
+                    if policy.allows_attribute(&name, &attribute, &value) {
+                        output.push_str(&format!(r#" {}="{}""#, attribute, escape_html(&value)));
+                    }
+
+                    seen.push(attribute);
+                }
+
+                output.push('>');
+
+                if !VOID.contains(&name.as_str()) {
+                    open.push(name);
+                }
+            }
+
+            Token::EndTag { name } => {
+                // Close everything that was opened after the element, so mismatched
+                // tags can't leave the output unbalanced.
+                if let Some(position) = open.iter().rposition(|tag| tag == &name) {
+                    for tag in open.drain(position..).rev() {
+                        output.push_str(&format!("</{}>", tag));
+                    }
+                }
+            }
+        }
+    }
+
+    for tag in open.into_iter().rev() {
+        output.push_str(&format!("</{}>", tag));
+    }
+
+    output
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    StartTag {
+        name: String,
+        attributes: Vec<(String, String)>,
+    },
+    EndTag {
+        name: String,
+    },
+}
+
+/// HTML tokenizer, following the tokenization rules browsers use.
+/// Comments, doctypes and processing instructions are skipped,
+/// and elements removed with their content are skipped entirely.
+struct Tokenizer {
+    input: Vec<char>,
+    position: usize,
+}
+
+impl Tokenizer {
+    fn new(input: &str) -> Self {
+        Self {
+            input: input.chars().filter(|c| *c != '\0').collect(),
+            position: 0,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.input.get(self.position + offset).copied()
+    }
+
+    fn starts_with_ignore_case(&self, needle: &str) -> bool {
+        needle
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.peek(i).map(|p| p.to_ascii_lowercase()) == Some(c))
+    }
+
+    /// Skip past the next occurrence of `needle`, or to the end of input.
+    fn skip_past(&mut self, needle: &str) {
+        while self.position < self.input.len() {
+            if self.starts_with_ignore_case(needle) {
+                self.position += needle.chars().count();
+                return;
+            }
+            self.position += 1;
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(0), Some(c) if c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn text(&mut self) -> String {
+        let start = self.position;
+        self.position += 1;
+        while let Some(c) = self.peek(0) {
+            if c == '<' {
+                break;
+            }
+            self.position += 1;
+        }
+
+        decode_entities(&self.input[start..self.position].iter().collect::<String>())
+    }
+
+    fn tag_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek(0) {
+            if c.is_ascii_whitespace() || c == '/' || c == '>' {
+                break;
+            }
+            name.push(c.to_ascii_lowercase());
+            self.position += 1;
+        }
+        name
+    }
+
+    /// Parse attributes up to and including the closing `>`. Returns `None`
+    /// if the input ends inside the tag, in which case the browser drops the tag.
+    fn attributes(&mut self) -> Option<Vec<(String, String)>> {
+        let mut attributes = vec![];
+
+        loop {
+            while matches!(self.peek(0), Some(c) if c.is_ascii_whitespace() || c == '/') {
+                self.position += 1;
+            }
+
+            match self.peek(0) {
+                None => return None,
+                Some('>') => {
+                    self.position += 1;
+                    return Some(attributes);
+                }
+                Some(_) => (),
+            }
+
+            let mut name = String::new();
+            while let Some(c) = self.peek(0) {
+                if c.is_ascii_whitespace() || c == '/' || c == '>' || (c == '=' && !name.is_empty())
+                {
+                    break;
+                }
+                name.push(c.to_ascii_lowercase());
+                self.position += 1;
+            }
+
+            self.skip_whitespace();
+
+            let mut value = String::new();
+            if self.peek(0) == Some('=') {
+                self.position += 1;
+                self.skip_whitespace();
+
+                match self.peek(0) {
+                    Some(quote @ ('"' | '\'')) => {
+                        self.position += 1;
+                        loop {
+                            match self.peek(0) {
+                                None => return None,
+                                Some(c) if c == quote => {
+                                    self.position += 1;
+                                    break;
+                                }
+                                Some(c) => {
+                                    value.push(c);
+                                    self.position += 1;
+                                }
+                            }
+                        }
+                    }
+                    _ => {
+                        while let Some(c) = self.peek(0) {
+                            if c.is_ascii_whitespace() || c == '>' {
+                                break;
+                            }
+                            value.push(c);
+                            self.position += 1;
+                        }
+                    }
+                }
+            }
+
+            attributes.push((name, decode_entities(&value)));
+        }
+    }
+
+    /// Skip the content of an element the browser doesn't parse as HTML, up to its closing tag.
+    fn skip_raw_text(&mut self, name: &str) {
+        let closing = format!("</{}", name);
+
+        while self.position < self.input.len() {
+            if self.starts_with_ignore_case(&closing) {
+                let next = self.peek(closing.chars().count());
+                if matches!(next, None | Some('>' | '/')) || next.unwrap().is_ascii_whitespace() {
+                    return;
+                }
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Skip an element that's removed with its content, including nested elements with the same name.
+    fn skip_element(&mut self, name: &str) {
+        let mut depth = 1;
+
+        while let Some(token) = self.token() {
+            match token {
+                Token::StartTag { name: ref tag, .. } if tag == name => depth += 1,
+                Token::EndTag { name: ref tag } if tag == name => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn token(&mut self) -> Option<Token> {
+        loop {
+            let c = self.peek(0)?;
+
+            if c != '<' {
+                return Some(Token::Text(self.text()));
+            }
+
+            match self.peek(1) {
+                Some('!') => {
+                    if self.starts_with_ignore_case("<!--") {
+                        self.position += 4;
+                        // `<!-->` and `<!--->` are empty comments.
+                        if self.peek(0) == Some('>') {
+                            self.position += 1;
+                        } else if self.starts_with_ignore_case("->") {
+                            self.position += 2;
+                        } else {
+                            self.skip_comment();
+                        }
+                    } else {
+                        self.skip_past(">");
+                    }
+                }
+
+                Some('?') => self.skip_past(">"),
+
+                Some('/') => match self.peek(2) {
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        self.position += 2;
+                        let name = self.tag_name();
+                        // End tags can have attributes, they are ignored.
+                        self.attributes()?;
+                        return Some(Token::EndTag { name });
+                    }
+                    Some('>') => self.position += 3,
+                    None => return Some(Token::Text(self.text())),
+                    Some(_) => self.skip_past(">"),
+                },
+
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.position += 1;
+                    let name = self.tag_name();
+                    let attributes = self.attributes()?;
+
+                    if name == "plaintext" {
+                        self.position = self.input.len();
+                        return None;
+                    }
+
+                    if RAW_TEXT.contains(&name.as_str()) {
+                        self.skip_raw_text(&name);
+                        // The closing tag is returned next.
+                        continue;
+                    }
+
+                    if REMOVE_WITH_CONTENT.contains(&name.as_str())
+                        && !VOID.contains(&name.as_str())
+                    {
+                        self.skip_element(&name);
+                        continue;
+                    }
+
+                    return Some(Token::StartTag { name, attributes });
+                }
+
+                _ => return Some(Token::Text(self.text())),
+            }
+        }
+    }
+
+    /// Skip a comment, which ends with `-->` or `--!>`.
+    fn skip_comment(&mut self) {
+        while self.position < self.input.len() {
+            if self.starts_with_ignore_case("-->") {
+                self.position += 3;
+                return;
+            }
+            if self.starts_with_ignore_case("--!>") {
+                self.position += 4;
+                return;
+            }
+            self.position += 1;
+        }
+    }
+}
+
+impl Iterator for Tokenizer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.token()
+    }
+}
+
+/// Decode character references. Named references that aren't known are left as they are;
+/// they are escaped when the output is serialized, so the browser won't decode them either.
+fn decode_entities(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(position) = rest.find('&') {
+        output.push_str(&rest[..position]);
+        rest = &rest[position..];
+
+        let (decoded, consumed) = decode_entity(rest).unwrap_or(('&', 1));
+
+        output.push(decoded);
+        rest = &rest[consumed..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Decode the character reference at the start of the input.
+/// Returns the character and the number of bytes consumed.
+fn decode_entity(input: &str) -> Option<(char, usize)> {
+    let body = &input[1..];
+
+    if let Some(number) = body.strip_prefix('#') {
+        let (digits, radix, prefix) = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16, 2),
+            None => (number, 10, 1),
+        };
+
+        let length = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        if length == 0 {
+            return None;
+        }
+
+        // Leading zeros don't change the value, e.g. `&#0000058;` is a colon.
+        let significant = digits[..length].trim_start_matches('0');
+        let value = match significant.len() {
+            0 => 0,
+            1..=8 => u32::from_str_radix(significant, radix).unwrap_or(u32::MAX),
+            _ => u32::MAX,
+        };
+        let decoded = match char::from_u32(value) {
+            Some(c) if value != 0 => c,
+            _ => char::REPLACEMENT_CHARACTER,
+        };
+        let semicolon = digits[length..].starts_with(';') as usize;
+
+        return Some((decoded, 1 + prefix + length + semicolon));
+    }
+
+    let end = body.find(';')?;
+    let decoded = match &body[..end] {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "colon" => ':',
+        "Tab" => '\t',
+        "NewLine" => '\n',
+        "lpar" => '(',
+        "rpar" => ')',
+        "sol" => '/',
+        "num" => '#',
+        "quest" => '?',
+        "equals" => '=',
+        _ => return None,
+    };
+
+    Some((decoded, end + 2))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_basic() {
+        let basic = Policy::basic();
+
+        assert_eq!(
+            sanitize_html(
+                r#"<p class="x">Hello <b>world</b> <a href="https://example.com" target="_blank">link</a></p>"#,
+                &basic
+            ),
+            r#"<p>Hello <b>world</b> <a href="https://example.com">link</a></p>"#
+        );
+        assert_eq!(sanitize_html("<div><img src=x>text</div>", &basic), "text");
+        assert_eq!(
+            sanitize_html(r#"<a href="/users/5?a=1&amp;b=2">x</a>"#, &basic),
+            r#"<a href="/users/5?a=1&amp;b=2">x</a>"#
+        );
+        assert_eq!(
+            sanitize_html(r#"<a href="mailto:a@example.com">x</a>"#, &basic),
+            r#"<a href="mailto:a@example.com">x</a>"#
+        );
+    }
+
+    #[test]
+    fn test_relaxed_and_custom() {
+        assert_eq!(
+            sanitize_html(
+                r#"<table><tr><td colspan="2" onclick="x()"><img src="https://example.com/a.png" alt="a"></td></tr></table>"#,
+                &Policy::relaxed()
+            ),
+            r#"<table><tr><td colspan="2"><img src="https://example.com/a.png" alt="a"></td></tr></table>"#
+        );
+
+        let custom = Policy::new()
+            .tag("a", &["href"])
+            .tags(&["script"])
+            .schemes(&["https"]);
+        assert_eq!(
+            sanitize_html(
+                r#"<a href="http://example.com">x</a><b>y</b><script>alert(1)</script>"#,
+                &custom
+            ),
+            "<a>x</a>y"
+        );
+    }
+
+    #[test]
+    fn test_mismatched_tags() {
+        let basic = Policy::basic();
+        assert_eq!(
+            sanitize_html("<b><i>text</b></i>", &basic),
+            "<b><i>text</i></b>"
+        );
+        assert_eq!(
+            sanitize_html("<ul><li>one", &basic),
+            "<ul><li>one</li></ul>"
+        );
+        assert_eq!(sanitize_html("</p>text</b>", &basic), "text");
+    }
+
+    #[test]
+    fn test_comments_and_instructions() {
+        let basic = Policy::basic();
+        assert_eq!(sanitize_html("a<!-- <script> -->b", &basic), "ab");
+        assert_eq!(sanitize_html("a<!-->b", &basic), "ab");
+        assert_eq!(sanitize_html("a<?xml version=\"1.0\"?>b", &basic), "ab");
+        assert_eq!(sanitize_html("<!DOCTYPE html>a", &basic), "a");
+        assert_eq!(sanitize_html("a<!-- unterminated", &basic), "a");
+    }
+
+    #[test]
+    fn test_text_is_escaped() {
+        assert_eq!(
+            sanitize_html("1 < 2 &lt;script&gt; &unknown; \"q\"", &Policy::basic()),
+            "1 &lt; 2 &lt;script&gt; &amp;unknown; &quot;q&quot;"
+        );
+    }
+
+    #[test]
+    fn test_urls() {
+        let policy = Policy::basic();
+        assert!(policy.allows_url("https://example.com"));
+        assert!(policy.allows_url("/relative:path"));
+        assert!(policy.allows_url("page?next=javascript:x"));
+        assert!(!policy.allows_url("javascript:alert(1)"));
+        assert!(!policy.allows_url(" JaVaScRiPt:alert(1)"));
+        assert!(!policy.allows_url("java\tscript:alert(1)"));
+        assert!(!policy.allows_url("data:text/html,<script>"));
+        assert!(!policy.allows_url("vbscript:msgbox"));
+    }
+}
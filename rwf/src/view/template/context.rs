@@ -1,5 +1,5 @@
-use crate::view::template::{Error, ToTemplateValue, Value};
-use parking_lot::RwLock;
+use crate::view::template::{Error, Mode, ToTemplateValue, Value};
+use parking_lot::{Mutex, RwLock};
 use std::collections::HashMap;
 use std::ops::{Index, IndexMut};
 use std::sync::Arc;
@@ -9,9 +9,18 @@ use once_cell::sync::Lazy;
 static DEFAULTS: Lazy<Arc<RwLock<Context>>> =
     Lazy::new(|| Arc::new(RwLock::new(Context::default())));
 
+/// Results of template functions, shared by the templates rendered in one pass, e.g. a page and its layout.
+type RenderCache = Arc<Mutex<HashMap<String, Value>>>;
+
+/// Variables with a provider used by the templates rendered in one pass, in order.
+type WantedProviders = Arc<Mutex<Vec<String>>>;
+
 #[derive(Debug, Default, Clone)]
 pub struct Context {
     values: HashMap<String, Value>,
+    mode: Mode,
+    cache: Option<RenderCache>,
+    wanted: Option<WantedProviders>,
 }
 
 impl Context {
@@ -32,6 +41,65 @@ impl Context {
     pub fn defaults(context: Self) {
         (*DEFAULTS.write()) = context;
     }
+
+    /// Output mode of the template being rendered.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: Mode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Start a render pass, unless this context is already used by one, e.g. to render a partial.
+    pub(crate) fn start_render(&mut self) -> &mut Self {
+        if self.cache.is_none() {
+            self.cache = Some(RenderCache::default());
+        }
+        self
+    }
+
+    /// Result of a template function already evaluated in this render pass.
+    pub(crate) fn cached(&self, key: &str) -> Option<Value> {
+        self.cache.as_ref()?.lock().get(key).cloned()
+    }
+
+    /// Save the result of a template function for the rest of the render pass.
+    pub(crate) fn cache(&self, key: String, value: Value) {
+        if let Some(ref cache) = self.cache {
+            cache.lock().insert(key, value);
+        }
+    }
+
+    /// Value of a variable with a [provider](crate::view::provider), which isn't set in the context.
+    /// The fallback is used until the provider is resolved, and the variable is recorded if providers are tracked.
+    pub(crate) fn provided(&self, key: &str) -> Option<Value> {
+        let fallback = crate::view::provider::fallback(key)?;
+
+        if let Some(ref wanted) = self.wanted {
+            let mut wanted = wanted.lock();
+            if !wanted.iter().any(|name| name == key) {
+                wanted.push(key.to_string());
+            }
+        }
+
+        Some(fallback)
+    }
+
+    /// Record the variables with providers used by the next render pass.
+    pub(crate) fn track_providers(&mut self) -> &mut Self {
+        self.wanted = Some(WantedProviders::default());
+        self
+    }
+
+    /// Variables with providers used since [`Context::track_providers`].
+    pub(crate) fn wanted_providers(&self) -> Vec<String> {
+        self.wanted
+            .as_ref()
+            .map(|wanted| wanted.lock().clone())
+            .unwrap_or_default()
+    }
 }
 
 impl ToTemplateValue for Context {
@@ -59,7 +127,12 @@ macro_rules! impl_type {
                     result.insert(key.to_string(), value.to_template_value()?);
                 }
 
-                Ok(Context { values: result })
+                Ok(Context {
+                    values: result,
+                    mode: Mode::default(),
+                    cache: None,
+                    wanted: None,
+                })
             }
         }
     };
@@ -1,4 +1,4 @@
-use super::{Token, TokenWithContext};
+use super::{Mode, Token, TokenWithContext};
 use thiserror::Error;
 
 use std::path::{Path, PathBuf};
@@ -17,6 +17,9 @@ pub enum Error {
     #[error("reached end of file while performing \"{0}\", did you forget a closing tag?")]
     Eof(&'static str),
 
+    #[error("unterminated {1} starting at line {}, column {}, did you forget a closing tag?", .0.line(), .0.column())]
+    Unterminated(TokenWithContext, &'static str),
+
     #[error("variable \"{0}\" is not defined or in scope")]
     UndefinedVariable(String),
 
@@ -37,6 +40,9 @@ pub enum Error {
 
     #[error("{0}")]
     Runtime(String),
+
+    #[error("template \"{0}\" is rendered as {1}, but was loaded as {2}")]
+    ModeMismatch(PathBuf, Mode, Mode),
 }
 
 impl Error {
@@ -46,6 +52,7 @@ impl Error {
             Error::Syntax(ref token) => token,
             Error::ExpressionSyntax(ref token) => token,
             Error::WrongToken(ref token, _) => token,
+            Error::Unterminated(ref token, _) => token,
             _ => {
                 if let Some(path) = path {
                     let prefix = "---> ";
@@ -66,6 +73,7 @@ impl Error {
             Error::Syntax(ref _token) => "syntax error".to_string(),
             Error::ExpressionSyntax(ref _token) => "expression syntax error".to_string(),
             Error::WrongToken(ref _token, _) => "unexpected token".to_string(),
+            Error::Unterminated(ref _token, what) => format!("unterminated {}", what),
             _ => "".to_string(),
         };
 
@@ -5,8 +5,13 @@ use super::{
     Op, Term,
 };
 
+use std::cmp::Ordering;
 use std::iter::{Iterator, Peekable};
 
+/// Maximum number of integers in a range, e.g. `0..n`. Ranges are turned into lists
+/// when evaluated, so a large `n` would use a lot of memory.
+const MAX_RANGE_SIZE: i64 = 100_000;
+
 /// An expression, like `5 == 6` or `logged_in == false`,
 /// which when evaluated produces a single value, e.g. `true`.
 #[derive(Debug, Clone)]
@@ -47,6 +52,15 @@ pub enum Expression {
         args: Vec<Expression>,
     },
 
+    // A range of integers, e.g. `1..=5` or `0..n`.
+    //
+    // The bounds can be any expression which evaluates to an integer.
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        inclusive: bool,
+    },
+
     Interpreter,
 }
 
@@ -114,6 +128,13 @@ impl Expression {
                     }
                 };
 
+                // Collection helpers evaluate their argument against each element.
+                if let Value::List(ref list) = value {
+                    if let Some(result) = Self::collection(list, &name, args, context) {
+                        return result;
+                    }
+                }
+
                 // Allow to pass undefined variables to a function.
                 // Typically that's not great, but the purpose of this function
                 // is to catch such cases and replace with a default value (presumably defined).
@@ -138,10 +159,189 @@ impl Expression {
                 Ok(value.call(&name, &args, context)?)
             }
 
+            Expression::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let (start, end) = match (start.evaluate(context)?, end.evaluate(context)?) {
+                    (Value::Integer(start), Value::Integer(end)) => (start, end),
+                    (start, end) => {
+                        return Err(Error::Runtime(format!(
+                            "range bounds should be integers, got {} and {} instead",
+                            start, end
+                        )))
+                    }
+                };
+
+                let (exclusive_end, op) = if *inclusive {
+                    (end.saturating_add(1), "..=")
+                } else {
+                    (end, "..")
+                };
+
+                if start > exclusive_end {
+                    return Err(Error::Runtime(format!(
+                        "range {}{}{} is descending, write it in ascending order and use .rev to reverse it",
+                        start, op, end
+                    )));
+                }
+
+                if exclusive_end
+                    .checked_sub(start)
+                    .is_none_or(|size| size > MAX_RANGE_SIZE)
+                {
+                    return Err(Error::Runtime(format!(
+                        "range {}{}{} is too large, ranges can have at most {} integers",
+                        start, op, end, MAX_RANGE_SIZE
+                    )));
+                }
+
+                Ok(Value::List(
+                    (start..exclusive_end).map(Value::Integer).collect(),
+                ))
+            }
+
             Expression::Interpreter => Ok(Value::Interpreter),
         }
     }
 
+    /// Run a collection helper, e.g. `users.filter(active)`, if `name` is one.
+    ///
+    /// The argument is evaluated separately for each element of the list. Elements
+    /// missing the attribute are skipped by `filter` and `map`, while their `_strict` variants,
+    /// `sort_by` and `sum` return an error instead.
+    fn collection(
+        list: &[Value],
+        name: &str,
+        args: &[Expression],
+        context: &Context,
+    ) -> Option<Result<Value, Error>> {
+        let arg = match args {
+            [arg] => arg,
+            _ => return None,
+        };
+
+        let strict = name.ends_with("_strict");
+
+        let result = match name {
+            "filter" | "filter_strict" => {
+                let mut result = vec![];
+                for element in list {
+                    match arg.evaluate_element(element, context) {
+                        Ok(value) if value.truthy() => result.push(element.clone()),
+                        Ok(_) => (),
+                        Err(Error::UndefinedVariable(_)) if !strict => (),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                Value::List(result)
+            }
+
+            "map" | "map_strict" => {
+                let mut result = vec![];
+                for element in list {
+                    match arg.evaluate_element(element, context) {
+                        Ok(value) => result.push(value),
+                        Err(Error::UndefinedVariable(_)) if !strict => (),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                Value::List(result)
+            }
+
+            "sort_by" => {
+                let mut keyed = vec![];
+                for element in list {
+                    match arg.evaluate_element(element, context) {
+                        Ok(key) => keyed.push((key, element.clone())),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                keyed.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+                Value::List(keyed.into_iter().map(|(_, element)| element).collect())
+            }
+
+            "sum" => {
+                let mut sum = Value::Integer(0);
+                for element in list {
+                    match arg.evaluate_element(element, context) {
+                        Ok(value) => sum = sum.add(&value),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                sum
+            }
+
+            _ => return None,
+        };
+
+        Some(Ok(result))
+    }
+
+    /// Evaluate the expression in the scope of a collection element.
+    ///
+    /// Attribute paths, e.g. `profile.name`, are looked up on the element directly. Any other
+    /// expression is evaluated in a child scope, where the element is available as `it` and, if it's a hash,
+    /// its keys are available as variables.
+    fn evaluate_element(&self, element: &Value, context: &Context) -> Result<Value, Error> {
+        if let Some(path) = self.attribute_path() {
+            let mut value = element.clone();
+
+            for (i, attribute) in path.iter().enumerate() {
+                value = match value {
+                    _ if i == 0 && attribute == "it" && !matches!(value, Value::Hash(_)) => value,
+                    Value::Hash(ref hash) => match hash.get(attribute) {
+                        Some(value) => value.clone(),
+                        None => return Err(Error::UndefinedVariable(path.join("."))),
+                    },
+                    value => match value.call(attribute, &[], context) {
+                        Ok(value) => value,
+                        Err(Error::UnknownMethod(_, _)) => {
+                            return Err(Error::UndefinedVariable(path.join(".")))
+                        }
+                        Err(err) => return Err(err),
+                    },
+                };
+            }
+
+            Ok(value)
+        } else {
+            let mut scope = context.clone();
+            if let Value::Hash(hash) = element {
+                for (key, value) in hash {
+                    scope.set(key, value.clone())?;
+                }
+            }
+            scope.set("it", element.clone())?;
+
+            self.evaluate(&scope)
+        }
+    }
+
+    /// The attribute path, e.g. `["profile", "name"]` for `profile.name`, if the expression
+    /// is just that.
+    fn attribute_path(&self) -> Option<Vec<String>> {
+        match self {
+            Expression::Term {
+                term: Term::Variable(name),
+            } => Some(vec![name.clone()]),
+
+            Expression::Function { term, name, args } if args.is_empty() => match name.as_ref() {
+                Expression::Term {
+                    term: Term::Constant(Value::String(name)),
+                } => {
+                    let mut path = term.attribute_path()?;
+                    path.push(name.clone());
+                    Some(path)
+                }
+                _ => None,
+            },
+
+            _ => None,
+        }
+    }
+
     fn term(iter: &mut Peekable<impl Iterator<Item = TokenWithContext>>) -> Result<Self, Error> {
         let next = iter.next().ok_or(Error::Eof("term next"))?;
         let term = match next.token() {
@@ -248,8 +448,6 @@ impl Expression {
         Ok(term)
     }
 
-    // TODO: Support parsing function arguments between parenthesis, e.g.:
-    // `my_function((another_func(1, 2)), "hello")`
     fn function(
         name: &str,
         expr: Self,
@@ -262,6 +460,9 @@ impl Expression {
                 let mut args = vec![];
                 let _ = iter.next().ok_or(Error::Eof("function args start"));
 
+                // Commas inside lists and nested function calls don't separate arguments.
+                let mut depth = 0;
+
                 loop {
                     let next = match iter.next() {
                         Some(next) => next,
@@ -269,7 +470,7 @@ impl Expression {
                     };
 
                     match next.token() {
-                        Token::RoundBracketEnd => {
+                        Token::RoundBracketEnd if depth == 0 => {
                             if !buffer.is_empty() {
                                 args.push(Self::parse(
                                     &mut std::mem::take(&mut buffer).into_iter().peekable(),
@@ -277,13 +478,20 @@ impl Expression {
                             }
                             break;
                         }
-                        Token::Comma => {
+                        Token::Comma if depth == 0 => {
                             args.push(Self::parse(
                                 &mut std::mem::take(&mut buffer).into_iter().peekable(),
                             )?);
                         }
+                        Token::RoundBracketStart | Token::SquareBracketStart => {
+                            depth += 1;
+                            buffer.push(next);
+                        }
+                        Token::RoundBracketEnd | Token::SquareBracketEnd => {
+                            depth -= 1;
+                            buffer.push(next);
+                        }
 
-                        // TODO: handle function calls inside function calls
                         _ => {
                             buffer.push(next);
                         }
@@ -353,6 +561,26 @@ impl Expression {
     pub fn parse(
         iter: &mut Peekable<impl Iterator<Item = TokenWithContext>>,
     ) -> Result<Self, Error> {
+        let start = Self::binary(iter)?;
+
+        // Ranges bind less tightly than any operator, so `0..n + 1` is `0..(n + 1)`.
+        let inclusive = match iter.peek().map(|t| t.token()) {
+            Some(Token::Range) => false,
+            Some(Token::RangeInclusive) => true,
+            _ => return Ok(start),
+        };
+
+        let _ = iter.next().ok_or(Error::Eof("parse range"))?;
+        let end = Self::binary(iter)?;
+
+        Ok(Expression::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            inclusive,
+        })
+    }
+
+    fn binary(iter: &mut Peekable<impl Iterator<Item = TokenWithContext>>) -> Result<Self, Error> {
         // Get the left term, if one exists.
         // TODO: support unary operations.
         let left = Self::term(iter)?;
@@ -376,11 +604,13 @@ impl Expression {
 
                 match next.map(|t| t.token()) {
                     // Expression is over.
-                    Some(Token::BlockEnd) | None => Ok(Expression::Binary {
-                        left: Box::new(left),
-                        op,
-                        right: Box::new(right),
-                    }),
+                    Some(Token::BlockEnd | Token::Range | Token::RangeInclusive) | None => {
+                        Ok(Expression::Binary {
+                            left: Box::new(left),
+                            op,
+                            right: Box::new(right),
+                        })
+                    }
 
                     // We have an operator.
                     Some(token) => match Op::from_token(token) {
@@ -389,7 +619,7 @@ impl Expression {
                             let _ = iter.next().ok_or(Error::Eof("parse second op"))?;
 
                             // Get the right term.
-                            let right2 = Expression::parse(iter)?;
+                            let right2 = Expression::binary(iter)?;
 
                             // Check operator precendence.
                             if second_op < op {
@@ -677,6 +907,126 @@ mod test {
         Ok(())
     }
 
+    fn users() -> Value {
+        let user = |name: &str, active: bool, total: i64| {
+            Value::Hash(HashMap::from([
+                ("name".to_string(), Value::String(name.into())),
+                ("active".to_string(), Value::Boolean(active)),
+                ("total".to_string(), Value::Integer(total)),
+            ]))
+        };
+
+        Value::List(vec![
+            user("bob", true, 25),
+            user("alice", false, 10),
+            user("carol", true, 5),
+            // No attributes.
+            Value::Hash(HashMap::new()),
+        ])
+    }
+
+    #[test]
+    fn test_collection_hashes() -> Result<(), Error> {
+        let mut context = Context::default();
+        context.set("users", users())?;
+        context.set("min", 6)?;
+
+        let names = |names: &[&str]| {
+            Value::List(
+                names
+                    .iter()
+                    .map(|name| Value::String(name.to_string()))
+                    .collect(),
+            )
+        };
+
+        assert_eq!(
+            "<% users.filter(active).map(name) %>".evaluate(&context)?,
+            names(&["bob", "carol"])
+        );
+        assert_eq!(
+            "<% users.map(name.upcase) %>".evaluate(&context)?,
+            names(&["BOB", "ALICE", "CAROL"])
+        );
+        assert_eq!(
+            "<% users.filter(total > min).map(name) %>".evaluate(&context)?,
+            names(&["bob", "alice"])
+        );
+        assert_eq!(
+            "<% users.take(3).sort_by(total).map(name) %>".evaluate(&context)?,
+            names(&["carol", "alice", "bob"])
+        );
+        assert_eq!(
+            "<% users.take(3).sum(total) %>".evaluate(&context)?,
+            Value::Integer(40)
+        );
+        assert_eq!(
+            "<% users.take(1).map(name) %>".evaluate(&context)?,
+            names(&["bob"])
+        );
+
+        // Strict mode and aggregates don't skip elements missing the attribute.
+        assert!(matches!(
+            "<% users.map_strict(name) %>".evaluate(&context),
+            Err(Error::UndefinedVariable(_))
+        ));
+        assert!(matches!(
+            "<% users.filter_strict(active) %>".evaluate(&context),
+            Err(Error::UndefinedVariable(_))
+        ));
+        assert!("<% users.sum(total) %>".evaluate(&context).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collection_scalars() -> Result<(), Error> {
+        let mut context = Context::default();
+        context.set("numbers", vec![3, -1, 2])?;
+        context.set("words", vec!["one", "three", ""])?;
+
+        assert_eq!(
+            "<% numbers.filter(it > 0) %>".evaluate(&context)?,
+            Value::List(vec![Value::Integer(3), Value::Integer(2)])
+        );
+        assert_eq!(
+            "<% numbers.map(abs).sum %>".evaluate(&context)?,
+            Value::Integer(6)
+        );
+        assert_eq!(
+            "<% numbers.sort_by(it) %>".evaluate(&context)?,
+            Value::List(vec![
+                Value::Integer(-1),
+                Value::Integer(2),
+                Value::Integer(3)
+            ])
+        );
+        assert_eq!(
+            "<% words.map(len) %>".evaluate(&context)?,
+            Value::List(vec![
+                Value::Integer(3),
+                Value::Integer(5),
+                Value::Integer(0)
+            ])
+        );
+        assert_eq!(
+            "<% words.filter(it).map(upcase) %>".evaluate(&context)?,
+            Value::List(vec![
+                Value::String("ONE".into()),
+                Value::String("THREE".into())
+            ])
+        );
+        assert_eq!(
+            "<% words.map(missing) %>".evaluate(&context)?,
+            Value::List(vec![])
+        );
+        assert!("<% words.map_strict(missing) %>"
+            .evaluate(&context)
+            .is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_replace() -> Result<(), Error> {
         let t1 = r#"<% "Some string".sub("string", 1234) %>"#.evaluate_default()?;
@@ -687,4 +1037,62 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_nested_arguments() -> Result<(), Error> {
+        let t1 = r#"<% default(some_var, ["a", ["b", "c"]]) %>"#.evaluate_default()?;
+        assert_eq!(
+            t1,
+            Value::List(vec![
+                Value::String("a".into()),
+                Value::List(vec![Value::String("b".into()), Value::String("c".into())]),
+            ])
+        );
+
+        let t1 = r#"<% default(default(some_var, 1), 2) %>"#.evaluate_default()?;
+        assert_eq!(t1, Value::Integer(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range() -> Result<(), Error> {
+        let mut context = Context::default();
+        context.set("n", 3)?;
+
+        let list =
+            |values: &[i64]| Value::List(values.iter().map(|v| Value::Integer(*v)).collect());
+
+        assert_eq!("<% 1..=3 %>".evaluate_default()?, list(&[1, 2, 3]));
+        assert_eq!("<% 0..n %>".evaluate(&context)?, list(&[0, 1, 2]));
+        assert_eq!("<% 1..n + 1 %>".evaluate(&context)?, list(&[1, 2, 3]));
+        assert_eq!("<% n - 1..=n * 2 - 3 %>".evaluate(&context)?, list(&[2, 3]));
+        assert_eq!("<% (1..=3).rev %>".evaluate_default()?, list(&[3, 2, 1]));
+        assert_eq!("<% (0..n).len %>".evaluate(&context)?, Value::Integer(3));
+
+        // Zero-length and single-element ranges.
+        assert_eq!("<% 3..3 %>".evaluate_default()?, list(&[]));
+        assert_eq!("<% 3..=2 %>".evaluate_default()?, list(&[]));
+        assert_eq!("<% 3..=3 %>".evaluate_default()?, list(&[3]));
+        assert_eq!("<% 3..4 %>".evaluate_default()?, list(&[3]));
+
+        let err = "<% 5..1 %>".evaluate_default().unwrap_err();
+        assert!(err.to_string().contains("descending"));
+        assert!("<% 1..2.5 %>".evaluate_default().is_err());
+        assert!(r#"<% "a"..n %>"#.evaluate(&context).is_err());
+
+        // Ranges are capped, so they don't allocate huge lists.
+        assert_eq!(
+            "<% (0..100000).len %>".evaluate_default()?,
+            Value::Integer(100_000)
+        );
+        let err = "<% 0..=100000 %>".evaluate_default().unwrap_err();
+        assert!(err.to_string().contains("too large"));
+        let err = "<% 0 - 9223372036854775807..=9223372036854775807 %>"
+            .evaluate_default()
+            .unwrap_err();
+        assert!(err.to_string().contains("too large"));
+
+        Ok(())
+    }
 }
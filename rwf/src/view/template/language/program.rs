@@ -1,9 +1,14 @@
+use super::super::lint::{self, ContextShape, LintWarning};
 use super::super::{Context, Error, TokenWithContext, Tokenize};
 use super::Statement;
 
+use std::path::Path;
+
 #[derive(Debug, Clone)]
 pub struct Program {
     statements: Vec<Statement>,
+    // Kept for linting, since statements don't know where they are in the source.
+    tokens: Vec<TokenWithContext>,
 }
 
 impl Program {
@@ -16,8 +21,13 @@ impl Program {
         Ok(result)
     }
 
+    /// Check the program against the shape of the context it will be rendered with.
+    pub fn lint(&self, shape: &ContextShape, path: Option<&Path>) -> Vec<LintWarning> {
+        lint::lint(&self.tokens, shape, path)
+    }
+
     pub fn parse(tokens: Vec<TokenWithContext>) -> Result<Self, Error> {
-        let mut iter = tokens.into_iter().peekable();
+        let mut iter = tokens.clone().into_iter().peekable();
         let mut statements = vec![];
 
         while iter.peek().is_some() {
@@ -25,7 +35,7 @@ impl Program {
             statements.push(statement);
         }
 
-        Ok(Program { statements })
+        Ok(Program { statements, tokens })
     }
 
     pub fn from_str(source: &str) -> Result<Self, Error> {
@@ -51,6 +61,16 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_comment_and_verbatim() -> Result<(), Error> {
+        let program = Program::from_str(
+            r#"<%# <%= undefined.call("%>") %><% verbatim %><%= name %><% end %><%= 1 + 1 %>"#,
+        )?;
+        let output = program.evaluate(&Context::default())?;
+        assert_eq!(output, "<%= name %>2");
+        Ok(())
+    }
+
     #[test]
     fn test_program_print() -> Result<(), Error> {
         let program = r#"
@@ -102,7 +102,7 @@ impl Statement {
                 let value = expression.evaluate(context)?;
                 Ok(match value {
                     Value::SafeString(s) => s,
-                    value => crate::safe_html(&value.to_string()),
+                    value => context.mode().escape(&value.to_string()),
                 })
             }
             Statement::For {
@@ -375,6 +375,40 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_for_range() -> Result<(), Error> {
+        let mut context = Context::default();
+        context.set("n", 3)?;
+
+        let render = |template: &str, context: &Context| -> Result<String, Error> {
+            Statement::from_str(template)?.evaluate(context)
+        };
+
+        assert_eq!(
+            render("<% for i in 1..=5 %><%= i %><% end %>", &context)?,
+            "12345"
+        );
+        assert_eq!(
+            render("<% for i in 0..n %>[<%= i %>]<% end %>", &context)?,
+            "[0][1][2]"
+        );
+        assert_eq!(
+            render("<% for i in (1..=n).rev %><%= i %><% end %>", &context)?,
+            "321"
+        );
+        assert_eq!(
+            render("<% for i in 0..0 %><%= i %><% end %>", &context)?,
+            ""
+        );
+        assert_eq!(
+            render("<% for i in n..=n %><%= i %><% end %>", &context)?,
+            "3"
+        );
+        assert!(render("<% for i in n..0 %><%= i %><% end %>", &context).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_newline() {
         // Make sure lexer doesn't interpret new lines as something.
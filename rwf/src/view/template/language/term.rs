@@ -29,6 +29,7 @@ impl Term {
             Term::Constant(value) => Ok(value.clone()),
             Term::Variable(name) => context
                 .get(&name)
+                .or_else(|| context.provided(name))
                 .ok_or(Error::UndefinedVariable(name.clone())),
             Term::Function(_f) => todo!("function evaluate"),
         }
@@ -6,6 +6,9 @@ pub use value::{ToTemplateValue, Value};
 
 use super::Error;
 
+use std::iter::Peekable;
+use std::str::Chars;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TokenWithContext {
     token: Token,
@@ -92,7 +95,7 @@ impl<'a> Lexer<'a> {
     /// Tokens are processed one character at a time. Multi-character tokens like `if`
     /// or `for` are buffered and parsed as a string.
     pub fn tokens(mut self) -> Result<Vec<TokenWithContext>, Error> {
-        let mut iter = self.source.chars();
+        let mut iter = self.source.chars().peekable();
 
         while let Some(c) = iter.next() {
             self.column += 1;
@@ -108,6 +111,7 @@ impl<'a> Lexer<'a> {
 
                 // Possibly a code block start tag.
                 '<' => {
+                    let start = self.add_token(Token::BlockStart);
                     let n = iter.next();
 
                     match n {
@@ -115,6 +119,28 @@ impl<'a> Lexer<'a> {
                             let m = iter.next();
 
                             match m {
+                                // `<%# comment %>`, skipped entirely.
+                                Some('#') => {
+                                    self.drain_buffer();
+                                    self.comment(&mut iter, start)?;
+                                }
+
+                                // `<% verbatim %>`, contents printed as-is until `<% end %>`.
+                                Some(c) if Self::verbatim_tag(c, &iter).is_some() => {
+                                    let tag = Self::verbatim_tag(c, &iter).unwrap_or_default();
+                                    for _ in 0..tag {
+                                        if let Some(c) = iter.next() {
+                                            self.advance(c);
+                                        }
+                                    }
+
+                                    self.drain_buffer();
+                                    let text = self.verbatim(&mut iter, start)?;
+                                    if !text.is_empty() {
+                                        self.tokens.push(self.add_token(Token::Text(text)));
+                                    }
+                                }
+
                                 // `<%=` (print expression)
                                 Some('=') => {
                                     self.drain_buffer();
@@ -170,7 +196,18 @@ impl<'a> Lexer<'a> {
                     // If we're parsing a number, keep the dot for the floating point
                     // notation. Otherwise, it's an accessor for a method call or object attribute.
                     if self.code_block {
-                        if self.number {
+                        // `..` or `..=` (range)
+                        if iter.peek() == Some(&'.') {
+                            let _ = iter.next();
+                            self.drain_buffer();
+
+                            if iter.peek() == Some(&'=') {
+                                let _ = iter.next();
+                                self.tokens.push(self.add_token(Token::RangeInclusive));
+                            } else {
+                                self.tokens.push(self.add_token(Token::Range));
+                            }
+                        } else if self.number {
                             let next = iter.next();
                             match next {
                                 Some(c) => {
@@ -416,6 +453,97 @@ impl<'a> Lexer<'a> {
             .collect())
     }
 
+    // Keep track of the line and column of characters consumed outside of the main loop.
+    fn advance(&mut self, c: char) {
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if c != '\r' {
+            self.column += 1;
+        }
+    }
+
+    // Skip a comment until the closing `%>`. A `%>` inside a string literal doesn't close it,
+    // so code with strings can be commented out.
+    fn comment(
+        &mut self,
+        iter: &mut Peekable<Chars<'_>>,
+        start: TokenWithContext,
+    ) -> Result<(), Error> {
+        let mut string = false;
+
+        while let Some(c) = iter.next() {
+            self.advance(c);
+
+            match c {
+                '"' => string = !string,
+                '%' if !string && iter.peek() == Some(&'>') => {
+                    let _ = iter.next();
+                    self.advance('>');
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+
+        Err(Error::Unterminated(start, "comment"))
+    }
+
+    // Check if the code block starting with `first` is `<% verbatim %>`. Returns how many
+    // characters are left in the tag after `first`, including the closing `%>`.
+    fn verbatim_tag(first: char, iter: &Peekable<Chars<'_>>) -> Option<usize> {
+        let mut tag = String::from(first);
+        let mut lookahead = iter.clone();
+        let mut len = 0;
+
+        while let Some(c) = lookahead.next() {
+            len += 1;
+
+            if c == '%' && lookahead.peek() == Some(&'>') {
+                return (tag.trim() == "verbatim").then_some(len + 1);
+            }
+
+            tag.push(c);
+
+            // Not worth looking further, it's some other code block.
+            if tag.len() > "verbatim".len() + 16 {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    // Read the contents of a verbatim block, without looking for tags, until `<% end %>`.
+    fn verbatim(
+        &mut self,
+        iter: &mut Peekable<Chars<'_>>,
+        start: TokenWithContext,
+    ) -> Result<String, Error> {
+        let mut text = String::new();
+
+        while let Some(c) = iter.next() {
+            self.advance(c);
+
+            if c == '%' && iter.peek() == Some(&'>') {
+                if let Some(tag) = text.rfind("<%") {
+                    if text[tag + 2..].trim() == "end" {
+                        let _ = iter.next();
+                        self.advance('>');
+                        text.truncate(tag);
+                        return Ok(text);
+                    }
+                }
+            }
+
+            if c != '\r' {
+                text.push(c);
+            }
+        }
+
+        Err(Error::Unterminated(start, "verbatim block"))
+    }
+
     // Handle multi-character tokens.
     fn drain_buffer(&mut self) {
         if !self.buffer.is_empty() {
@@ -487,4 +615,139 @@ impl Tokenize for String {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_range() -> Result<(), Error> {
+        let tokens = "<% 1..=5 %>"
+            .tokenize()?
+            .into_iter()
+            .map(|t| t.token())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::BlockStart,
+                Token::Value(Value::Integer(1)),
+                Token::RangeInclusive,
+                Token::Value(Value::Integer(5)),
+                Token::BlockEnd,
+            ]
+        );
+
+        let tokens = "<% 0..n %>"
+            .tokenize()?
+            .into_iter()
+            .map(|t| t.token())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::BlockStart,
+                Token::Value(Value::Integer(0)),
+                Token::Range,
+                Token::Variable("n".into()),
+                Token::BlockEnd,
+            ]
+        );
+
+        let tokens = "<% 1.5 %>.."
+            .tokenize()?
+            .into_iter()
+            .map(|t| t.token())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::BlockStart,
+                Token::Value(Value::Float(1.5)),
+                Token::BlockEnd,
+                Token::Text("..".into()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_comment() -> Result<(), Error> {
+        let tokens = r#"a<%# if "%>" == x %>b<%#%>c"#
+            .tokenize()?
+            .into_iter()
+            .map(|t| t.token())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("a".into()),
+                Token::Text("b".into()),
+                Token::Text("c".into()),
+            ]
+        );
+
+        let err = "line\n  <%# \"%>\" ".tokenize().unwrap_err();
+        match err {
+            Error::Unterminated(ref token, "comment") => {
+                assert_eq!(token.line(), 2);
+                assert_eq!(token.column(), 4);
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verbatim() -> Result<(), Error> {
+        let tokens = "<h1><% verbatim %><%= name %> <% if a %><%# b %><% verbatim %>%><%end%></h1>"
+            .tokenize()?
+            .into_iter()
+            .map(|t| t.token())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("<h1>".into()),
+                Token::Text("<%= name %> <% if a %><%# b %><% verbatim %>%>".into()),
+                Token::Text("</h1>".into()),
+            ]
+        );
+
+        let tokens = "<%verbatim%>\n{{ x }}\n<% end %><%= 1 %>"
+            .tokenize()?
+            .into_iter()
+            .map(|t| t.token())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text("\n{{ x }}\n".into()),
+                Token::BlockStartPrint,
+                Token::Value(Value::Integer(1)),
+                Token::BlockEnd,
+            ]
+        );
+
+        // `verbatim` as a variable name still works.
+        let tokens = "<%= verbatim %>"
+            .tokenize()?
+            .into_iter()
+            .map(|t| t.token())
+            .collect::<Vec<_>>();
+        assert_eq!(tokens[1], Token::Variable("verbatim".into()));
+
+        let err = "\n\n<% verbatim %><%= x %>".tokenize().unwrap_err();
+        match err {
+            Error::Unterminated(ref token, "verbatim block") => {
+                assert_eq!(token.line(), 3);
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+        assert!(err
+            .to_string()
+            .starts_with("unterminated verbatim block starting at line 3"));
+
+        Ok(())
+    }
+}
@@ -49,6 +49,10 @@ pub enum Token {
     Comma,
     RoundBracketStart,
     RoundBracketEnd,
+    // `..`
+    Range,
+    // `..=`
+    RangeInclusive,
 }
 
 impl Token {
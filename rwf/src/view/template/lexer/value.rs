@@ -6,15 +6,24 @@
 //! or accessing hash keys.
 use once_cell::sync::Lazy;
 
-use super::{super::Context, Error};
+use super::{super::Context, super::Mode, Error};
 
 use std::cmp::Ordering;
 use std::collections::HashMap;
 
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::{OffsetDateTime, UtcOffset};
+use tracing::warn;
+
 use crate::controller::middleware::csrf::CSRF_INPUT;
+use crate::controller::{policy, Impersonation};
 use crate::crypto;
+use crate::flags;
+use crate::http::Nonce;
 use crate::model::Model;
 use crate::model::Value as ModelValue;
+use crate::timezone;
+use crate::view::sanitize::{sanitize_html, Policy};
 use crate::view::template::Template;
 
 static TURBO_STREAM: Lazy<Template> =
@@ -34,6 +43,9 @@ pub enum Value {
     Null,
     Interpreter,
     SafeString(String),
+    /// Timestamp and the time zone it should be formatted in,
+    /// if not the default one, set with `in_tz`.
+    DateTime(OffsetDateTime, Option<UtcOffset>),
 }
 
 impl PartialOrd for Value {
@@ -46,6 +58,7 @@ impl PartialOrd for Value {
             (Value::String(s1), Value::String(s2)) => s1.partial_cmp(s2),
             (Value::Boolean(b1), Value::Boolean(b2)) => b1.partial_cmp(b2),
             (Value::SafeString(s1), Value::SafeString(s2)) => s1.partial_cmp(s2),
+            (Value::DateTime(t1, _), Value::DateTime(t2, _)) => t1.partial_cmp(t2),
             _ => None,
         }
     }
@@ -81,6 +94,13 @@ impl std::fmt::Display for Value {
             Value::Null => write!(f, "null"),
             Value::Interpreter => write!(f, "global"),
             Value::SafeString(s) => write!(f, "{}", s),
+            Value::DateTime(timestamp, zone) => write!(
+                f,
+                "{}",
+                local_time(timestamp, zone)
+                    .format(&Rfc2822)
+                    .unwrap_or_default()
+            ),
         }
     }
 }
@@ -102,6 +122,7 @@ impl Value {
             Value::Hash(hash) => !hash.is_empty(),
             Value::Interpreter => true,
             Value::SafeString(s) => !s.is_empty(),
+            Value::DateTime(_, _) => true,
         }
     }
 
@@ -266,6 +287,7 @@ impl Value {
                 "len" => Value::Integer(value.len() as i64),
                 "is_empty" | "blank" | "empty" => Value::Boolean(value.is_empty()),
                 "br" => Value::SafeString(crate::safe_html(value).replace("\n", "<br>")),
+                "sanitize" => Value::SafeString(sanitize(value, args)?),
                 "replace" | "sub" => match &args {
                     &[v, r] => Value::String(value.replace(&v.to_string(), &r.to_string())),
                     _ => {
@@ -279,9 +301,40 @@ impl Value {
 
             Value::SafeString(value) => match method_name {
                 "to_s" | "to_string" => Value::String(value.clone()),
+                "sanitize" => Value::SafeString(sanitize(value, args)?),
                 _ => return Err(Error::UnknownMethod(method_name.into(), "safe_string")),
             },
 
+            Value::DateTime(timestamp, zone) => match method_name {
+                "in_tz" | "in_time_zone" => match args {
+                    [Value::String(name)] => match timezone::parse(name) {
+                        Some(offset) => Value::DateTime(*timestamp, Some(offset)),
+                        None => {
+                            return Err(Error::Runtime(format!(
+                                "\"{}\" is not a valid time zone",
+                                name
+                            )))
+                        }
+                    },
+                    _ => return Err(Error::Runtime("in_tz requires a time zone".into())),
+                },
+                "utc" => Value::DateTime(*timestamp, Some(UtcOffset::UTC)),
+                "format" | "strftime" => match args {
+                    [Value::String(format)] => {
+                        let format = time::format_description::parse(format)
+                            .map_err(|err| Error::Runtime(err.to_string()))?;
+                        Value::String(local_time(timestamp, zone).format(&format)?)
+                    }
+                    _ => return Err(Error::Runtime("format requires a format string".into())),
+                },
+                "rfc3339" | "iso8601" => {
+                    Value::String(local_time(timestamp, zone).format(&Rfc3339)?)
+                }
+                "to_s" | "to_string" => Value::String(self.to_string()),
+                "timestamp" | "to_i" => Value::Integer(timestamp.unix_timestamp()),
+                _ => return Err(Error::UnknownMethod(method_name.into(), "datetime")),
+            },
+
             Value::List(list) => match method_name.parse::<i64>() {
                 Ok(index) => match list.get(index as usize) {
                     Some(value) => value.clone(),
@@ -323,6 +376,17 @@ impl Value {
 
                     "len" => Value::Integer(list.len() as i64),
 
+                    "take" => match args {
+                        &[Value::Integer(n)] => {
+                            Value::List(list.iter().take(n.max(0) as usize).cloned().collect())
+                        }
+                        _ => return Err(Error::Runtime("take requires a number".into())),
+                    },
+
+                    "sum" => list
+                        .iter()
+                        .fold(Value::Integer(0), |sum, value| sum.add(value)),
+
                     _ => return Err(Error::UnknownMethod(method_name.into(), "list")),
                 },
             },
@@ -377,6 +441,82 @@ impl Value {
                     }
                 },
 
+                "json" => match args {
+                    [value] => {
+                        let json: serde_json::Value = value.clone().try_into()?;
+                        let json = json.to_string();
+
+                        // Safe to print only in JSON templates.
+                        match context.mode() {
+                            Mode::Json => Value::SafeString(json),
+                            _ => Value::String(json),
+                        }
+                    }
+                    _ => return Err(Error::Runtime("json() requires one value".into())),
+                },
+
+                "csp_nonce" => Value::SafeString(Nonce::current().unwrap_or_default()),
+                "flag_enabled" => match args {
+                    [Value::String(name)] => Value::Boolean(flags::check_current(name)),
+                    _ => {
+                        return Err(Error::Runtime(
+                            "flag_enabled() requires the name of the flag".into(),
+                        ))
+                    }
+                },
+                "can" => {
+                    let (action, resource) = match args {
+                        [Value::String(action)] => (action, Value::Null),
+                        [Value::String(action), resource] => (action, resource.clone()),
+                        _ => {
+                            return Err(Error::Runtime(
+                                "can() requires an action and an optional resource".into(),
+                            ))
+                        }
+                    };
+                    // JSON objects have sorted keys, unlike hashes.
+                    let json: Result<serde_json::Value, _> = resource.clone().try_into();
+                    let resource_key = json
+                        .map(|json| json.to_string())
+                        .unwrap_or_else(|_| format!("{:?}", resource));
+
+                    let key = format!("can:{}:{}", action, resource_key);
+
+                    match context.cached(&key) {
+                        Some(allowed) => allowed,
+                        None => {
+                            let allowed = match policy::check_current(action, &resource) {
+                                Ok(allowed) => allowed,
+                                Err(err) if cfg!(debug_assertions) => {
+                                    return Err(Error::Runtime(format!(
+                                        "can(\"{}\"): {}",
+                                        action, err
+                                    )))
+                                }
+                                Err(err) => {
+                                    warn!("can(\"{}\") denied: {}", action, err);
+                                    false
+                                }
+                            };
+                            context.cache(key, Value::Boolean(allowed));
+                            Value::Boolean(allowed)
+                        }
+                    }
+                }
+                "impersonation" => match Impersonation::current() {
+                    Some(impersonation) => Value::Hash(HashMap::from([
+                        ("user_id".into(), Value::Integer(impersonation.user_id)),
+                        (
+                            "true_user_id".into(),
+                            Value::Integer(impersonation.true_user_id),
+                        ),
+                        (
+                            "expires_at".into(),
+                            Value::DateTime(impersonation.expires_at(), None),
+                        ),
+                    ])),
+                    None => Value::Null,
+                },
                 "csrf_token_raw" => Value::SafeString(crypto::csrf_token().unwrap()),
                 "csrf_token" => Value::SafeString(format!(
                     r#"<input type="hidden" name="{}" value="{}">"#,
@@ -399,7 +539,12 @@ impl Value {
                     _ => Value::Null,
                 },
 
-                _ => return Err(Error::UnknownMethod(method_name.into(), "global")),
+                "paginate" => crate::view::pagination::paginate(args)?,
+
+                helper => match crate::view::form::call(helper, args) {
+                    Some(result) => result?,
+                    None => return Err(Error::UnknownMethod(method_name.into(), "global")),
+                },
             },
 
             v => return Err(Error::UnknownMethod(method_name.into(), v.type_name())),
@@ -435,6 +580,7 @@ impl Value {
             Value::Float(f) => f.to_string(),
             Value::Boolean(b) => b.to_string(),
             Value::SafeString(s) => s.to_string(),
+            Value::DateTime(_, _) => format!("{}", self),
             value => format!("{:?}", value),
         }
     }
@@ -450,10 +596,28 @@ impl Value {
             &Value::List(_) => "list",
             &Value::String(_) => "string",
             &Value::SafeString(_) => "safe_string",
+            &Value::DateTime(_, _) => "datetime",
         }
     }
 }
 
+/// Timestamp in the time zone selected with `in_tz`, or in the default one.
+/// Sanitize HTML using the named policy, `basic` by default.
+fn sanitize(value: &str, args: &[Value]) -> Result<String, Error> {
+    let policy = match args {
+        [] => Policy::basic(),
+        [Value::String(name)] => Policy::named(name)
+            .ok_or_else(|| Error::Runtime(format!("\"{}\" is not a sanitize policy", name)))?,
+        _ => return Err(Error::Runtime("sanitize takes the name of a policy".into())),
+    };
+
+    Ok(sanitize_html(value, &policy))
+}
+
+fn local_time(timestamp: &OffsetDateTime, zone: &Option<UtcOffset>) -> OffsetDateTime {
+    timestamp.to_offset(zone.unwrap_or_else(timezone::default_offset))
+}
+
 pub trait ToTemplateValue: Clone {
     fn to_template_value(&self) -> Result<Value, Error>;
 }
@@ -489,10 +653,9 @@ impl_integer!(u32);
 impl_integer!(u16);
 impl_integer!(u8);
 
-impl ToTemplateValue for time::OffsetDateTime {
+impl ToTemplateValue for OffsetDateTime {
     fn to_template_value(&self) -> Result<Value, Error> {
-        let fmt = time::format_description::well_known::Rfc2822;
-        Ok(Value::String(self.format(&fmt)?))
+        Ok(Value::DateTime(self.to_offset(UtcOffset::UTC), None))
     }
 }
 
@@ -576,6 +739,9 @@ impl TryInto<serde_json::Value> for Value {
             Value::Null => Ok(serde_json::Value::Null),
             Value::Interpreter => Ok(serde_json::Value::Null),
             Value::SafeString(s) => Ok(serde_json::Value::String(s)),
+            Value::DateTime(timestamp, zone) => Ok(serde_json::Value::String(
+                local_time(&timestamp, &zone).format(&Rfc3339)?,
+            )),
         }
     }
 }
@@ -591,10 +757,7 @@ impl ToTemplateValue for crate::model::Value {
                 Some(v) => v.to_template_value(),
                 None => Ok(Value::Null),
             },
-            ModelValue::TimestampT(timestamp) => {
-                use time::format_description::well_known::Rfc2822;
-                timestamp.format(&Rfc2822)?.to_template_value()
-            }
+            ModelValue::TimestampT(timestamp) => timestamp.to_template_value(),
             ModelValue::Json(json) => serde_json::to_string(json).unwrap().to_template_value(),
             ModelValue::Int(int) => (*int as i64).to_template_value(),
             ModelValue::Null => Ok(Value::Null),
@@ -602,10 +765,8 @@ impl ToTemplateValue for crate::model::Value {
             ModelValue::SmallInt(int) => (*int as i64).to_template_value(),
             ModelValue::Real(f) => (*f as f64).to_template_value(),
             ModelValue::Boolean(b) => (*b).to_template_value(),
-            ModelValue::Timestamp(timestamp) => {
-                use time::format_description::well_known::Rfc2822;
-                timestamp.format(&Rfc2822)?.to_template_value()
-            }
+            // Without a time zone, we don't know when this was.
+            ModelValue::Timestamp(timestamp) => timestamp.format(&Rfc2822)?.to_template_value(),
             ModelValue::IpAddr(addr) => Ok(Value::String(addr.to_string())),
             ModelValue::Uuid(uuid) => Ok(Value::String(uuid.to_string())),
             ModelValue::List(list) => {
@@ -702,6 +863,31 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_sanitize() {
+        let html = Value::String(r#"<p onclick="x()">Hi <img src=x></p>"#.into());
+        assert_eq!(
+            html.call("sanitize", &[], &Context::default()).unwrap(),
+            Value::SafeString("<p>Hi </p>".into())
+        );
+        assert_eq!(
+            html.call(
+                "sanitize",
+                &[Value::String("relaxed".into())],
+                &Context::default()
+            )
+            .unwrap(),
+            Value::SafeString(r#"<p>Hi <img src="x"></p>"#.into())
+        );
+        assert!(html
+            .call(
+                "sanitize",
+                &[Value::String("none".into())],
+                &Context::default()
+            )
+            .is_err());
+    }
+
     #[test]
     fn test_replace() {
         let v = Value::String("Hey Alice, this is Bob".into())
@@ -0,0 +1,599 @@
+//! Static checks for templates.
+//!
+//! Variables missing from the context are printed as empty strings, so typos in templates
+//! are easy to miss. Given the [shape](ContextShape) of the context the template is rendered with,
+//! [`Template::lint`](super::Template::lint) reports variables which are not in the context, variables in
+//! the context which the template never uses, and comparisons which can never be true, e.g. a string compared
+//! to a list.
+//!
+//! Shapes registered with [`Template::register_shape`](super::Template::register_shape) are checked
+//! automatically when templates are preloaded at startup.
+use super::{Context, ToTemplateValue, Token, TokenWithContext, Value};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+static SHAPES: Lazy<Mutex<HashMap<PathBuf, ContextShape>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Variables provided by Rwf to all templates.
+const GLOBALS: &[&str] = &[
+    "rwf_head",
+    "csp_nonce",
+    "impersonation",
+    "flag_enabled",
+    "can",
+    "csrf_token",
+    "csrf_token_raw",
+    "yield",
+];
+
+/// Collection helpers which evaluate their argument against each element of the list.
+const COLLECTION_HELPERS: &[&str] = &[
+    "filter",
+    "filter_strict",
+    "map",
+    "map_strict",
+    "sort_by",
+    "sum",
+];
+
+/// Names of the variables in a template context.
+///
+/// Values which are hashes can have a shape of their own, so attributes accessed on them,
+/// e.g. `user.email`, are checked too.
+///
+/// # Example
+///
+/// ```
+/// use rwf::view::template::ContextShape;
+///
+/// let shape = ContextShape::new()
+///     .field("title")
+///     .nested("user", ContextShape::new().field("email"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContextShape {
+    fields: BTreeMap<String, Option<ContextShape>>,
+}
+
+impl ContextShape {
+    /// Create an empty shape.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a variable. Its attributes are not checked.
+    pub fn field(mut self, name: impl ToString) -> Self {
+        self.fields.insert(name.to_string(), None);
+        self
+    }
+
+    /// Add a variable which is a hash with the given shape.
+    pub fn nested(mut self, name: impl ToString, shape: ContextShape) -> Self {
+        self.fields.insert(name.to_string(), Some(shape));
+        self
+    }
+
+    /// Names of the variables.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fields.keys().map(|name| name.as_str())
+    }
+
+    /// Get the shape of a variable. Returns `None` if the variable isn't in the shape,
+    /// and `Some(None)` if its attributes aren't known.
+    pub fn get(&self, name: &str) -> Option<Option<&ContextShape>> {
+        self.fields.get(name).map(|shape| shape.as_ref())
+    }
+}
+
+/// Structs which can be used as a template context and know their shape.
+///
+/// Implemented automatically by `#[derive(macros::Context)]`.
+pub trait TemplateContext {
+    /// Shape of the context.
+    fn context_shape() -> ContextShape;
+}
+
+/// Type of lint warning.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LintKind {
+    /// The template uses a variable, or an attribute of one, which is not in the context.
+    UndefinedVariable(String),
+    /// The context has a variable which the template never uses.
+    UnusedVariable(String),
+    /// Two values which can never be equal are compared, e.g. a string and a list.
+    IncompatibleTypes {
+        left: &'static str,
+        right: &'static str,
+    },
+}
+
+/// Problem found by [`Template::lint`](super::Template::lint).
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    /// What's wrong.
+    pub kind: LintKind,
+    /// Path to the template, if it was loaded from a file.
+    pub path: Option<PathBuf>,
+    /// Line in the template, starting at 1. `0` for warnings about the whole template.
+    pub line: usize,
+    /// Column in the template, as reported by template syntax errors. `0` for warnings about the whole template.
+    pub column: usize,
+}
+
+impl std::fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.path, self.line) {
+            (Some(path), 0) => write!(f, "{}: ", path.display())?,
+            (Some(path), line) => write!(f, "{}:{}:{}: ", path.display(), line, self.column)?,
+            (None, 0) => (),
+            (None, line) => write!(f, "{}:{}: ", line, self.column)?,
+        }
+
+        match &self.kind {
+            LintKind::UndefinedVariable(name) => {
+                write!(f, "\"{}\" is not defined in the context", name)
+            }
+            LintKind::UnusedVariable(name) => write!(f, "\"{}\" is never used", name),
+            LintKind::IncompatibleTypes { left, right } => write!(
+                f,
+                "comparing a {} to a {} always gives the same result",
+                left, right
+            ),
+        }
+    }
+}
+
+/// Register the shape of the context the template at this path is rendered with.
+pub fn register_shape(path: impl AsRef<Path>, shape: ContextShape) {
+    SHAPES.lock().insert(path.as_ref().to_owned(), shape);
+}
+
+/// Get the shape registered for the template at this path.
+pub fn registered_shape(path: impl AsRef<Path>) -> Option<ContextShape> {
+    SHAPES.lock().get(path.as_ref()).cloned()
+}
+
+/// How the argument list of a function call is evaluated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Arguments {
+    /// In the template scope.
+    Normal,
+    /// Against each element of a list, e.g. `users.filter(active)`.
+    Element,
+    /// Undefined variables are allowed, e.g. `default(title, "Home")`.
+    Default,
+}
+
+/// Lint the tokens of a template.
+pub(crate) fn lint(
+    tokens: &[TokenWithContext],
+    shape: &ContextShape,
+    path: Option<&Path>,
+) -> Vec<LintWarning> {
+    // Global defaults are available to all templates.
+    let mut defaults = match Context::new().to_template_value() {
+        Ok(Value::Hash(defaults)) => defaults.into_keys().collect(),
+        _ => HashSet::new(),
+    };
+    // So are variables with a provider.
+    defaults.extend(crate::view::provider::names());
+
+    let mut linter = Linter {
+        tokens,
+        shape,
+        path,
+        defaults,
+        used: HashSet::new(),
+        warnings: vec![],
+    };
+
+    linter.run();
+    linter.warnings
+}
+
+struct Linter<'a> {
+    tokens: &'a [TokenWithContext],
+    shape: &'a ContextShape,
+    path: Option<&'a Path>,
+    defaults: HashSet<String>,
+    used: HashSet<String>,
+    warnings: Vec<LintWarning>,
+}
+
+impl<'a> Linter<'a> {
+    fn run(&mut self) {
+        // Loop variables in scope, one frame for each `if` and `for` block.
+        let mut scopes: Vec<Vec<String>> = vec![];
+        let mut arguments: Vec<Arguments> = vec![];
+        let mut i = 0;
+
+        while i < self.tokens.len() {
+            match self.token(i) {
+                Some(Token::BlockEnd) => arguments.clear(),
+                Some(Token::If) => scopes.push(vec![]),
+                Some(Token::End) => {
+                    scopes.pop();
+                }
+                Some(Token::For) => {
+                    // The loop variable is declared, not used.
+                    if let Some(Token::Variable(name)) = self.token(i + 1) {
+                        scopes.push(vec![name]);
+                        i += 1;
+                    } else {
+                        scopes.push(vec![]);
+                    }
+                }
+                Some(Token::RoundBracketStart) => {
+                    let kind = match (self.token(i.wrapping_sub(2)), self.token(i.wrapping_sub(1)))
+                    {
+                        (Some(Token::Dot), Some(Token::Variable(name)))
+                            if COLLECTION_HELPERS.contains(&name.as_str()) =>
+                        {
+                            Arguments::Element
+                        }
+                        (_, Some(Token::Variable(name))) if name == "default" => Arguments::Default,
+                        _ => Arguments::Normal,
+                    };
+                    arguments.push(kind);
+                }
+                Some(Token::RoundBracketEnd) => {
+                    arguments.pop();
+                }
+                Some(Token::Variable(name)) => {
+                    let attribute = self.token(i.wrapping_sub(1)) == Some(Token::Dot);
+                    let function = self.token(i + 1) == Some(Token::RoundBracketStart);
+
+                    if !attribute && !function && !arguments.contains(&Arguments::Element) {
+                        let loop_variable = scopes.iter().flatten().any(|v| v == &name);
+
+                        if !loop_variable {
+                            let path = self.path_at(i, name);
+                            self.variable(i, &path, arguments.contains(&Arguments::Default));
+                        }
+                    }
+                }
+                Some(
+                    Token::Equals
+                    | Token::NotEquals
+                    | Token::GreaterThan
+                    | Token::GreaterEqualThan
+                    | Token::LessThan
+                    | Token::LessEqualThan,
+                ) => self.comparison(i),
+                _ => (),
+            }
+
+            i += 1;
+        }
+
+        let unused = self
+            .shape
+            .names()
+            .filter(|name| !self.used.contains(*name))
+            .map(|name| LintKind::UnusedVariable(name.to_string()))
+            .collect::<Vec<_>>();
+
+        for kind in unused {
+            self.warn(kind, None);
+        }
+    }
+
+    fn token(&self, i: usize) -> Option<Token> {
+        self.tokens.get(i).map(|token| token.token())
+    }
+
+    fn warn(&mut self, kind: LintKind, at: Option<usize>) {
+        let (line, column) = at
+            .and_then(|i| self.tokens.get(i))
+            .map(|token| (token.line(), token.column()))
+            .unwrap_or((0, 0));
+
+        self.warnings.push(LintWarning {
+            kind,
+            path: self.path.map(|path| path.to_owned()),
+            line,
+            column,
+        });
+    }
+
+    /// The variable and the attributes accessed on it, e.g. `["user", "email"]` for
+    /// `user.email`. Stops at method calls, e.g. `user.name.truncate(5)`.
+    fn path_at(&self, i: usize, name: String) -> Vec<String> {
+        let mut path = vec![name];
+        let mut i = i + 1;
+
+        while let (Some(Token::Dot), Some(Token::Variable(attribute))) =
+            (self.token(i), self.token(i + 1))
+        {
+            if self.token(i + 2) == Some(Token::RoundBracketStart) {
+                break;
+            }
+
+            path.push(attribute);
+            i += 2;
+        }
+
+        path
+    }
+
+    fn variable(&mut self, i: usize, path: &[String], allow_undefined: bool) {
+        let name = &path[0];
+
+        if GLOBALS.contains(&name.as_str()) || self.defaults.contains(name) {
+            return;
+        }
+
+        self.used.insert(name.clone());
+
+        let mut shape = match self.shape.get(name) {
+            Some(shape) => shape,
+            None if allow_undefined => return,
+            None => return self.warn(LintKind::UndefinedVariable(name.clone()), Some(i)),
+        };
+
+        for (depth, attribute) in path.iter().enumerate().skip(1) {
+            let nested = match shape {
+                Some(nested) => nested,
+                None => return,
+            };
+
+            shape = match nested.get(attribute) {
+                Some(shape) => shape,
+                None if allow_undefined => return,
+                None => {
+                    let undefined = path[..=depth].join(".");
+                    return self.warn(LintKind::UndefinedVariable(undefined), Some(i));
+                }
+            };
+        }
+    }
+
+    fn comparison(&mut self, i: usize) {
+        if let (Some(left), Some(right)) = (self.literal_before(i), self.literal_after(i)) {
+            if left != right {
+                self.warn(LintKind::IncompatibleTypes { left, right }, Some(i));
+            }
+        }
+    }
+
+    /// Type of the literal just before the operator at `i`, if that's the whole operand.
+    fn literal_before(&self, i: usize) -> Option<&'static str> {
+        let end = i.checked_sub(1)?;
+
+        let (start, kind) = match self.token(end)? {
+            Token::Value(value) => (end, Self::kind(&value)?),
+            Token::SquareBracketEnd => (self.list_start(end)?, "list"),
+            _ => return None,
+        };
+
+        match start.checked_sub(1).and_then(|i| self.token(i)) {
+            Some(token) if Self::operand_boundary(&token) => Some(kind),
+            _ => None,
+        }
+    }
+
+    /// Type of the literal just after the operator at `i`, if that's the whole operand.
+    fn literal_after(&self, i: usize) -> Option<&'static str> {
+        let start = i + 1;
+
+        let (end, kind) = match self.token(start)? {
+            Token::Value(value) => (start, Self::kind(&value)?),
+            Token::SquareBracketStart => (self.list_end(start)?, "list"),
+            _ => return None,
+        };
+
+        match self.token(end + 1) {
+            Some(Token::BlockEnd | Token::And | Token::Or | Token::RoundBracketEnd) | None => {
+                Some(kind)
+            }
+            _ => None,
+        }
+    }
+
+    /// Start of the list literal ending at `end`.
+    fn list_start(&self, end: usize) -> Option<usize> {
+        let mut depth = 0;
+
+        for i in (0..=end).rev() {
+            match self.token(i)? {
+                Token::SquareBracketEnd => depth += 1,
+                Token::SquareBracketStart => {
+                    depth -= 1;
+                    if depth == 0 {
+                        // `user["name"]` is an accessor, not a list.
+                        return match i.checked_sub(1).and_then(|i| self.token(i)) {
+                            Some(token) if Self::operand_boundary(&token) => Some(i),
+                            _ => None,
+                        };
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        None
+    }
+
+    /// End of the list literal starting at `start`.
+    fn list_end(&self, start: usize) -> Option<usize> {
+        let mut depth = 0;
+
+        for i in start..self.tokens.len() {
+            match self.token(i)? {
+                Token::SquareBracketStart => depth += 1,
+                Token::SquareBracketEnd => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        None
+    }
+
+    /// The token can't be part of the operand that follows it.
+    fn operand_boundary(token: &Token) -> bool {
+        matches!(
+            token,
+            Token::BlockStart
+                | Token::BlockStartPrint
+                | Token::BlockStartPrintRaw
+                | Token::If
+                | Token::ElseIf
+                | Token::And
+                | Token::Or
+                | Token::Not
+                | Token::RoundBracketStart
+                | Token::Comma
+        )
+    }
+
+    fn kind(value: &Value) -> Option<&'static str> {
+        match value {
+            Value::String(_) | Value::SafeString(_) => Some("string"),
+            Value::Integer(_) | Value::Float(_) => Some("number"),
+            Value::Boolean(_) => Some("boolean"),
+            Value::List(_) => Some("list"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Template;
+    use super::*;
+
+    fn lint(template: &str, shape: &ContextShape) -> Vec<LintKind> {
+        Template::from_str(template)
+            .unwrap()
+            .lint(shape)
+            .into_iter()
+            .map(|warning| warning.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_undefined_and_unused() {
+        let shape = ContextShape::new().field("title").field("footer");
+        let template = Template::from_str("<h1><%= title %></h1>\n<p><%= titel %></p>").unwrap();
+        let warnings = template.lint(&shape);
+
+        assert_eq!(
+            warnings,
+            vec![
+                LintWarning {
+                    kind: LintKind::UndefinedVariable("titel".into()),
+                    path: None,
+                    line: 2,
+                    column: 11,
+                },
+                LintWarning {
+                    kind: LintKind::UnusedVariable("footer".into()),
+                    path: None,
+                    line: 0,
+                    column: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "2:11: \"titel\" is not defined in the context"
+        );
+    }
+
+    #[test]
+    fn test_nested() {
+        let shape = ContextShape::new()
+            .nested(
+                "user",
+                ContextShape::new()
+                    .field("email")
+                    .nested("profile", ContextShape::new().field("name")),
+            )
+            .field("settings");
+
+        assert_eq!(
+            lint(
+                "<%= user.email.upcase %> <%= user.profile.name %> <%= settings.theme %>",
+                &shape
+            ),
+            vec![]
+        );
+        assert_eq!(
+            lint(
+                "<%= user.emial %> <%= user.profile.nmae.truncate(5) %> <%= settings %>",
+                &shape
+            ),
+            vec![
+                LintKind::UndefinedVariable("user.emial".into()),
+                LintKind::UndefinedVariable("user.profile.nmae".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scopes() {
+        let shape = ContextShape::new().field("users").field("title");
+
+        // Loop variables, collection helpers, globals and default values.
+        let template = r#"
+            <% for user in users.filter(active) %>
+                <%= user.name %>
+                <% for tag in user.tags.map(name) %><%= tag %><% end %>
+            <% end %>
+            <%= default(subtitle, title) %>
+            <%= csrf_token %>
+            <%= encrypt_number(users.first.id) %>
+        "#;
+        assert_eq!(lint(template, &shape), vec![]);
+
+        // Loop variables are not visible after the loop.
+        assert_eq!(
+            lint(
+                "<% for user in users %><%= title %><% end %><%= user %>",
+                &shape
+            ),
+            vec![LintKind::UndefinedVariable("user".into())]
+        );
+    }
+
+    #[test]
+    fn test_incompatible_types() {
+        let shape = ContextShape::new().field("role").field("roles");
+
+        assert_eq!(
+            lint(
+                r#"<% if "admin" == ["admin", "owner"] %><% end %><% if 5 != "5" %><% end %>"#,
+                &ContextShape::new()
+            ),
+            vec![
+                LintKind::IncompatibleTypes {
+                    left: "string",
+                    right: "list"
+                },
+                LintKind::IncompatibleTypes {
+                    left: "number",
+                    right: "string"
+                },
+            ]
+        );
+
+        // Types of variables, accessors and expressions aren't known.
+        assert_eq!(
+            lint(
+                r#"<% if role == ["admin"] %><% end %><% if roles[0] == "admin" %><% end %><% if 1 + 1 == "2" %><% end %><% if ["a"] == ["a"] %><% end %>"#,
+                &shape
+            ),
+            vec![]
+        );
+    }
+}
@@ -2,12 +2,18 @@ pub mod context;
 pub mod error;
 pub mod language;
 pub mod lexer;
+pub mod lint;
+pub mod mode;
 
 pub use context::Context;
 pub use error::Error;
 pub use lexer::{Lexer, ToTemplateValue, Token, TokenWithContext, Tokenize, Value};
+pub use lint::{ContextShape, LintKind, LintWarning, TemplateContext};
+pub use mode::Mode;
 
-use crate::http::Response;
+use crate::http::{Body, Request, Response, Timings};
+use crate::view::engine::{self, ViewEngine};
+use crate::view::provider;
 use crate::view::Templates;
 
 use language::Program;
@@ -18,41 +24,154 @@ use std::sync::Arc;
 
 /// Rwf template.
 ///
-/// Contains the AST for the template.
+/// Contains the AST for the template, or the engine used to render it
+/// if the template was written for another [template engine](crate::view::engine).
+/// The output [mode](mode) decides how printed values are escaped.
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct Template {
-    program: Program,
+    source: Source,
     path: Option<PathBuf>,
+    mode: Mode,
+}
+
+#[derive(Clone)]
+enum Source {
+    Program(Program),
+    Engine(Arc<dyn ViewEngine>),
+}
+
+impl std::fmt::Debug for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Source::Program(program) => f.debug_tuple("Program").field(program).finish(),
+            Source::Engine(_) => f.debug_tuple("Engine").finish(),
+        }
+    }
 }
 
 impl Template {
     /// Read and compile a template from disk.
+    ///
+    /// If an engine is [registered](crate::view::engine::register) for the file extension,
+    /// the template is compiled by that engine instead.
     pub fn new(path: impl AsRef<Path> + std::marker::Copy) -> Result<Self, Error> {
+        if let Some(engine) = engine::engine(path) {
+            if !path.as_ref().is_file() {
+                return Err(Error::TemplateDoesNotExist(path.as_ref().to_owned()));
+            }
+
+            engine.load(path.as_ref())?;
+
+            return Ok(Template {
+                source: Source::Engine(engine),
+                path: Some(path.as_ref().to_owned()),
+                mode: Mode::default(),
+            });
+        }
+
         let text = match read_to_string(path) {
             Ok(text) => text,
             Err(_) => return Err(Error::TemplateDoesNotExist(path.as_ref().to_owned())),
         };
 
         Ok(Template {
-            program: Program::from_str(&text)?,
+            source: Source::Program(Program::from_str(&text)?),
             path: Some(path.as_ref().to_owned()),
+            mode: Mode::from_path(path).unwrap_or_default(),
         })
     }
 
     /// Read and compile a template from a string.
     pub fn from_str(template: &str) -> Result<Self, Error> {
         Ok(Template {
-            program: Program::from_str(template)?,
+            source: Source::Program(Program::from_str(template)?),
             path: None,
+            mode: Mode::default(),
         })
     }
 
+    /// Render the template in this output mode.
+    ///
+    /// Returns an error if the mode doesn't match the one detected from the file name,
+    /// e.g. a `.txt.tmpl` template rendered as JSON.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::view::{Template, template::Mode};
+    /// let template = Template::from_str(r#"{"name": "<%= name %>"}"#)
+    ///     .unwrap()
+    ///     .with_mode(Mode::Json)
+    ///     .unwrap();
+    ///
+    /// let json = template.render([("name", "\"Alice\"")]).unwrap();
+    /// assert_eq!(json, r#"{"name": "\"Alice\""}"#);
+    /// ```
+    pub fn with_mode(mut self, mode: Mode) -> Result<Self, Error> {
+        if let Some(ref path) = self.path {
+            match Mode::from_path(path) {
+                Some(detected) if detected != mode => {
+                    return Err(Error::ModeMismatch(path.clone(), detected, mode))
+                }
+                _ => (),
+            }
+        }
+
+        self.mode = mode;
+        Ok(self)
+    }
+
+    /// Output mode of the template.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Check the template against the shape of the context it will be rendered with.
+    ///
+    /// Reports variables which are not in the context, variables in the context which are never used,
+    /// and comparisons which can never be true. Templates rendered by other [engines](crate::view::engine)
+    /// are not checked.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::view::template::{ContextShape, LintKind, Template};
+    /// let template = Template::from_str("<h1><%= titel %></h1>").unwrap();
+    /// let warnings = template.lint(&ContextShape::new().field("title"));
+    ///
+    /// assert_eq!(warnings[0].kind, LintKind::UndefinedVariable("titel".into()));
+    /// assert_eq!(warnings[1].kind, LintKind::UnusedVariable("title".into()));
+    /// ```
+    pub fn lint(&self, shape: &ContextShape) -> Vec<LintWarning> {
+        match self.source {
+            Source::Program(ref program) => program.lint(shape, self.path.as_deref()),
+            Source::Engine(_) => vec![],
+        }
+    }
+
+    /// Register the shape of the context the template at this path is rendered with. The template is
+    /// [linted](Self::lint) when it's preloaded at startup.
+    pub fn register_shape(path: impl AsRef<Path>, shape: ContextShape) {
+        lint::register_shape(path, shape);
+    }
+
     /// Given a context, execute the template, producing a string.
     pub fn render(&self, context: impl TryInto<Context, Error = Error>) -> Result<String, Error> {
-        let context: Context = context.try_into()?;
+        let mut context: Context = context.try_into()?;
+        context.set_mode(self.mode).start_render();
+        let _span = Timings::current().map(|timings| timings.span("render"));
+
+        let result = match self.source {
+            Source::Program(ref program) => program.evaluate(&context),
+            Source::Engine(ref engine) => {
+                let context: serde_json::Value = context.to_template_value()?.try_into()?;
+                let path = self.path.as_deref().unwrap_or(Path::new(""));
+                engine.render(path, &context)
+            }
+        };
 
-        match self.program.evaluate(&context) {
+        match result {
             Ok(result) => Ok(result),
             Err(err) => {
                 if let Some(path) = &self.path {
@@ -64,6 +183,66 @@ impl Template {
         }
     }
 
+    /// Render the template for the request, resolving the variables it uses
+    /// with [providers](crate::view::provider).
+    pub async fn render_for(
+        &self,
+        request: &Request,
+        context: impl TryInto<Context, Error = Error>,
+    ) -> Result<String, Error> {
+        let mut context: Context = context.try_into()?;
+        provider::render(self, &mut context, request).await
+    }
+
+    /// Render the template and place the result inside a layout.
+    ///
+    /// The layout is rendered with the same context, and the output
+    /// of this template is available in the layout as the `yield` variable.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use rwf::view::{Template, Context};
+    /// let page = Template::from_str("<h1><%= title %></h1>").unwrap();
+    /// let layout = Template::from_str("<title><%= title %></title><%= yield %>").unwrap();
+    ///
+    /// let mut context = Context::new();
+    /// context.set("title", "Home").unwrap();
+    ///
+    /// let html = page.render_with_layout(&context, &layout).unwrap();
+    /// assert_eq!(html, "<title>Home</title><h1>Home</h1>");
+    /// ```
+    pub fn render_with_layout(
+        &self,
+        context: impl TryInto<Context, Error = Error>,
+        layout: &Template,
+    ) -> Result<String, Error> {
+        let mut context: Context = context.try_into()?;
+        context.start_render();
+        let content = self.render(&context)?;
+        context.set("yield", Value::SafeString(content))?;
+        layout.render(&context)
+    }
+
+    /// The `Content-Type` of the rendered template, based
+    /// on the template file extension, e.g. `text/plain` for `.txt` templates.
+    /// Templates without a known extension are considered to be HTML.
+    pub fn content_type(&self) -> &'static str {
+        if self.mode != Mode::Html {
+            return self.mode.content_type();
+        }
+
+        let mime_type = match self.path {
+            Some(ref path) => Body::mime_type_from_path(path),
+            None => "text/html",
+        };
+
+        match mime_type {
+            "application/octet-stream" => "text/html",
+            mime_type => mime_type,
+        }
+    }
+
     pub fn render_default(&self) -> Result<String, Error> {
         self.render(&Context::default())
     }
@@ -79,6 +258,15 @@ impl Template {
         Self::cached(path)
     }
 
+    /// Load the template from the cache, rendering it in the given mode, e.g.
+    /// JSON for a `webhook.tmpl` template.
+    pub fn load_with_mode(path: impl AsRef<Path> + Copy, mode: Mode) -> Result<Arc<Self>, Error> {
+        match Templates::cache().get_with_mode(path, mode) {
+            Ok(template) => Ok(template),
+            Err(err) => Err(err.pretty_from_path(path)),
+        }
+    }
+
     /// Set global default values for variables. If the variable isn't defined
     /// in a template context, and a default exists, the default value will be used instead.
     pub fn defaults(context: Context) {
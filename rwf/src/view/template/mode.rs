@@ -0,0 +1,192 @@
+//! Template output modes.
+//!
+//! The mode decides how values printed with `<%= %>` are escaped. HTML is the default;
+//! templates ending with `.json.tmpl` and `.txt.tmpl` are rendered in JSON and text mode:
+//!
+//! | Mode | Escaping | Content type |
+//! |------|----------|--------------|
+//! | `Html` | HTML entities | `text/html` |
+//! | `Json` | JSON string contents, e.g. `"` becomes `\"` | `application/json` |
+//! | `Text` | None | `text/plain` |
+//!
+//! In JSON mode, `<%= json(value) %>` serializes a whole value, e.g. a list or a hash.
+use std::fmt::Display;
+use std::path::Path;
+
+/// How the template output is escaped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    #[default]
+    Html,
+    Json,
+    Text,
+}
+
+impl Mode {
+    /// Detect the mode from the template file name, e.g. `webhook.json.tmpl`.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let name = path.as_ref().file_name()?.to_str()?;
+
+        if name.ends_with(".json.tmpl") {
+            Some(Mode::Json)
+        } else if name.ends_with(".txt.tmpl") {
+            Some(Mode::Text)
+        } else {
+            None
+        }
+    }
+
+    /// Escape a printed value.
+    pub fn escape(&self, value: &str) -> String {
+        match self {
+            Mode::Html => crate::safe_html(value),
+            Mode::Json => escape_json(value),
+            Mode::Text => value.to_string(),
+        }
+    }
+
+    /// `Content-Type` of the rendered template.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Mode::Html => "text/html",
+            Mode::Json => "application/json",
+            Mode::Text => "text/plain",
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Mode::Html => write!(f, "html"),
+            Mode::Json => write!(f, "json"),
+            Mode::Text => write!(f, "text"),
+        }
+    }
+}
+
+/// Escape the value so it can be placed between quotes in a JSON string.
+pub fn escape_json(value: &str) -> String {
+    let quoted = serde_json::Value::String(value.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::view::template::Error;
+    use crate::view::{Context, Template, Templates};
+    use std::collections::HashMap;
+
+    fn context() -> Context {
+        let mut context = Context::new();
+        context
+            .set("name", "Alice \"Al\" <b>\nSmith")
+            .unwrap()
+            .set("tags", vec!["a\"b", "c\nd"])
+            .unwrap();
+        context
+    }
+
+    #[test]
+    fn test_mode() {
+        assert_eq!(
+            Mode::from_path("templates/webhook.json.tmpl"),
+            Some(Mode::Json)
+        );
+        assert_eq!(Mode::from_path("email.txt.tmpl"), Some(Mode::Text));
+        assert_eq!(Mode::from_path("index.html"), None);
+        assert_eq!(Mode::from_path("data.json"), None);
+
+        assert_eq!(
+            Mode::Json.escape("say \"hi\"\n\t\\ <b>\u{1}"),
+            r#"say \"hi\"\n\t\\ <b>\u0001"#
+        );
+        assert_eq!(Mode::Text.escape("<b>\"a\"</b>"), "<b>\"a\"</b>");
+        assert_eq!(
+            Mode::Html.escape("<b>\"a\"</b>"),
+            "&lt;b&gt;\"a\"&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn test_render_modes() {
+        let source = r#"{"name": "<%= name %>", "tags": <%= json(tags) %>}"#;
+
+        let html = Template::from_str(source).unwrap();
+        assert_eq!(
+            html.render(&context()).unwrap(),
+            "{\"name\": \"Alice \"Al\" &lt;b&gt;\nSmith\", \"tags\": [\"a\\\"b\",\"c\\nd\"]}"
+        );
+
+        let json = Template::from_str(source)
+            .unwrap()
+            .with_mode(Mode::Json)
+            .unwrap();
+        let rendered = json.render(&context()).unwrap();
+        assert_eq!(
+            rendered,
+            r#"{"name": "Alice \"Al\" <b>\nSmith", "tags": ["a\"b","c\nd"]}"#
+        );
+        let parsed: HashMap<String, serde_json::Value> = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed["name"], "Alice \"Al\" <b>\nSmith");
+        assert_eq!(parsed["tags"][1], "c\nd");
+        assert_eq!(json.content_type(), "application/json");
+
+        let text = Template::from_str("Hi <%= name %>,\n")
+            .unwrap()
+            .with_mode(Mode::Text)
+            .unwrap();
+        assert_eq!(
+            text.render(&context()).unwrap(),
+            "Hi Alice \"Al\" <b>\nSmith,\n"
+        );
+        assert_eq!(text.content_type(), "text/plain");
+    }
+
+    #[test]
+    fn test_load_modes() {
+        let dir = tempdir::TempDir::new("template_modes").unwrap();
+        let webhook = dir.path().join("webhook.json.tmpl");
+        let email = dir.path().join("email.txt.tmpl");
+        let page = dir.path().join("page.html");
+        std::fs::write(&webhook, r#"{"name": "<%= name %>"}"#).unwrap();
+        std::fs::write(&email, "Hi <%= name %>").unwrap();
+        std::fs::write(&page, "<%= name %>").unwrap();
+
+        let template = Template::load(&webhook).unwrap();
+        assert_eq!(template.mode(), Mode::Json);
+        assert_eq!(
+            template.render(&context()).unwrap(),
+            r#"{"name": "Alice \"Al\" <b>\nSmith"}"#
+        );
+
+        let template = Template::load(&email).unwrap();
+        assert_eq!(template.mode(), Mode::Text);
+        assert_eq!(
+            template.render(&context()).unwrap(),
+            "Hi Alice \"Al\" <b>\nSmith"
+        );
+
+        // Mismatched modes are an error.
+        let err = Template::new(&email)
+            .unwrap()
+            .with_mode(Mode::Json)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ModeMismatch(_, Mode::Text, Mode::Json)
+        ));
+        assert!(Template::load_with_mode(&email, Mode::Html).is_err());
+
+        // The same file is cached separately in each mode.
+        let mut templates = Templates::new();
+        let html = templates.get(&page).unwrap();
+        let text = templates.get_with_mode(&page, Mode::Text).unwrap();
+        assert_eq!(
+            html.render(&context()).unwrap(),
+            "Alice \"Al\" &lt;b&gt;\nSmith"
+        );
+        assert_eq!(text.render(&context()).unwrap(), "Alice \"Al\" <b>\nSmith");
+    }
+}
@@ -4,8 +4,11 @@
 //! DOM elements, similarly to a single page application written with React or Vue.
 use once_cell::sync::Lazy;
 
+use std::path::Path;
+
 use super::{Context, Template};
 use crate::view::template::lexer::value::ToTemplateValue;
+use crate::view::template::Error;
 
 static TEMPLATE: Lazy<Template> =
     Lazy::new(|| Template::from_str(include_str!("stream.html")).unwrap());
@@ -31,9 +34,15 @@ impl std::fmt::Display for TurboStream {
 impl From<TurboStream> for Context {
     fn from(stream: TurboStream) -> Context {
         let mut context = Context::new();
-        context["action"] = stream.action.to_template_value().unwrap();
-        context["template"] = stream.template.to_template_value().unwrap();
-        context["target"] = stream.target.to_template_value().unwrap();
+        context["action"] = escape_attribute(&stream.action)
+            .to_template_value()
+            .unwrap();
+        context["template"] = escape_fragment(&stream.template)
+            .to_template_value()
+            .unwrap();
+        context["target"] = escape_attribute(&stream.target)
+            .to_template_value()
+            .unwrap();
 
         context
     }
@@ -52,6 +61,55 @@ impl TurboStream {
         }
     }
 
+    /// Append the content to the target's children.
+    pub fn append(target: impl ToString) -> Self {
+        Self::new("").action("append").target(target)
+    }
+
+    /// Prepend the content to the target's children.
+    pub fn prepend(target: impl ToString) -> Self {
+        Self::new("").action("prepend").target(target)
+    }
+
+    /// Replace the target with the content.
+    pub fn replace(target: impl ToString) -> Self {
+        Self::new("").action("replace").target(target)
+    }
+
+    /// Remove the target.
+    pub fn remove(target: impl ToString) -> Self {
+        Self::new("").action("remove").target(target)
+    }
+
+    /// Create a Turbo Stream from a template, loaded from the [template cache](crate::view::Templates).
+    /// The action is `replace`, like [`TurboStream::new`].
+    pub async fn render_template(
+        path: impl AsRef<Path> + Copy,
+        context: impl TryInto<Context, Error = Error>,
+    ) -> Result<Self, Error> {
+        Self::new("").template(path, context).await
+    }
+
+    /// Set the content to a template, loaded from the [template cache](crate::view::Templates).
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let cart = TurboStream::replace("cart")
+    ///     .template("templates/cart/_row.html", [("item", item)])
+    ///     .await?;
+    ///
+    /// Ok(Response::new().turbo_stream(&[cart]))
+    /// ```
+    pub async fn template(
+        mut self,
+        path: impl AsRef<Path> + Copy,
+        context: impl TryInto<Context, Error = Error>,
+    ) -> Result<Self, Error> {
+        self.template = Template::load(path)?.render(context)?;
+        Ok(self)
+    }
+
     /// Turbo Stream action, e.g. "replace", "append", or "remove".
     pub fn action(mut self, action: impl ToString) -> Self {
         self.action = action.to_string();
@@ -70,3 +128,168 @@ impl TurboStream {
         TEMPLATE.render(&context).unwrap()
     }
 }
+
+/// Escape a value placed between double quotes in an attribute.
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Elements whose content isn't parsed as HTML, so `</template>` inside them isn't a tag.
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+/// Escape `</template>` tags in the content which would close the Turbo Stream `<template>` early.
+/// Nested `<template>` elements, and `</template>` inside comments, scripts and other raw text, are kept.
+fn escape_fragment(html: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut result = String::with_capacity(html.len());
+    let mut depth = 0usize;
+    let mut i = 0;
+
+    while let Some(offset) = lower[i..].find('<') {
+        let start = i + offset;
+        result.push_str(&html[i..start]);
+        let rest = &lower[start..];
+
+        // Copy comments and raw text as they are.
+        let skip = if rest.starts_with("<!--") {
+            Some(rest.find("-->").map(|end| end + 3).unwrap_or(rest.len()))
+        } else {
+            RAW_TEXT
+                .iter()
+                .find(|tag| is_tag(rest, "<", tag))
+                .map(|tag| {
+                    let close = format!("</{}", tag);
+                    rest[1..]
+                        .find(&close)
+                        .map(|end| 1 + end + close.len())
+                        .unwrap_or(rest.len())
+                })
+        };
+
+        if let Some(skip) = skip {
+            result.push_str(&html[start..start + skip]);
+            i = start + skip;
+            continue;
+        }
+
+        if is_tag(rest, "<", "template") {
+            depth += 1;
+            result.push('<');
+        } else if is_tag(rest, "</", "template") {
+            if depth == 0 {
+                result.push_str("&lt;");
+            } else {
+                depth -= 1;
+                result.push('<');
+            }
+        } else {
+            result.push('<');
+        }
+
+        i = start + 1;
+    }
+
+    result.push_str(&html[i..]);
+    result
+}
+
+/// The lowercase HTML starts with the tag, e.g. `<template>` or `</template `.
+fn is_tag(html: &str, open: &str, name: &str) -> bool {
+    html.strip_prefix(open)
+        .and_then(|rest| rest.strip_prefix(name))
+        .map(|rest| {
+            rest.is_empty()
+                || rest.starts_with(|c: char| c == '>' || c == '/' || c.is_ascii_whitespace())
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_escape_fragment() {
+        for (fragment, expected) in [
+            ("<p>hi</p>", "<p>hi</p>"),
+            (
+                "<p>hi</p></template><script>alert(1)</script>",
+                "<p>hi</p>&lt;/template><script>alert(1)</script>",
+            ),
+            ("</TEMPLATE >", "&lt;/TEMPLATE >"),
+            // Nested templates are balanced.
+            (
+                "<template id=\"row\"><tr></tr></template></template>",
+                "<template id=\"row\"><tr></tr></template>&lt;/template>",
+            ),
+            // Not tags.
+            (
+                "<!-- </template> --><script>\"</template>\"</script><templates></templates>",
+                "<!-- </template> --><script>\"</template>\"</script><templates></templates>",
+            ),
+            (
+                "<textarea></template></textarea></template>",
+                "<textarea></template></textarea>&lt;/template>",
+            ),
+            ("1 < 2 <", "1 < 2 <"),
+        ] {
+            assert_eq!(escape_fragment(fragment), expected, "{}", fragment);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_template() {
+        let dir = TempDir::new("turbo_stream").unwrap();
+        let path = dir.path().join("_row.html");
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(b"<tr><td><%= name %></td></tr></template>")
+            .unwrap();
+
+        let stream = TurboStream::replace("cart")
+            .template(&path, [("name", "<b>Socks</b>")])
+            .await
+            .unwrap();
+        let html = stream.render();
+        assert!(html.starts_with(r#"<turbo-stream action="replace" target="cart">"#));
+        assert!(html.contains("<tr><td>&lt;b&gt;Socks&lt;/b&gt;</td></tr>&lt;/template>"));
+        assert_eq!(html.matches("</template>").count(), 1);
+
+        let stream = TurboStream::render_template(&path, [("name", "Hats")])
+            .await
+            .unwrap()
+            .target("cart");
+        assert!(stream.render().contains("<td>Hats</td>"));
+
+        for (stream, action) in [
+            (TurboStream::append("list"), "append"),
+            (TurboStream::prepend("list"), "prepend"),
+            (TurboStream::remove("list"), "remove"),
+        ] {
+            assert!(stream.render().starts_with(&format!(
+                r#"<turbo-stream action="{}" target="list">"#,
+                action
+            )));
+        }
+
+        // Targets are escaped.
+        let html = TurboStream::remove(r#"x" onclick="alert(1)"#).render();
+        assert!(
+            html.contains(r#"target="x&quot; onclick=&quot;alert(1)""#),
+            "{}",
+            html
+        );
+
+        assert!(
+            TurboStream::render_template(&dir.path().join("missing.html"), [("name", "")])
+                .await
+                .is_err()
+        );
+    }
+}
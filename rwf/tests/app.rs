@@ -0,0 +1,121 @@
+use rwf::app::App;
+use rwf::config::get_config;
+use rwf::controller::middleware::{Middleware, Outcome};
+use rwf::prelude::*;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+static JOB_DONE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default, Serialize, Deserialize)]
+struct Greet;
+
+#[async_trait]
+impl Job for Greet {
+    async fn execute(
+        &self,
+        _context: &JobContext,
+        _args: serde_json::Value,
+    ) -> Result<(), rwf::job::Error> {
+        JOB_DONE.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Index;
+
+#[async_trait]
+impl Controller for Index {
+    async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+        queue_async(&Greet).await?;
+        Ok(Response::new().text("hello"))
+    }
+}
+
+struct Tag;
+
+#[async_trait]
+impl Middleware for Tag {
+    async fn handle_request(&self, request: Request) -> Result<Outcome, Error> {
+        Ok(Outcome::Forward(request))
+    }
+
+    async fn handle_response(
+        &self,
+        _request: &Request,
+        response: Response,
+    ) -> Result<Response, Error> {
+        Ok(response.header("x-app", "test"))
+    }
+}
+
+#[tokio::test]
+async fn test_app() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address = format!("127.0.0.1:{}", port);
+
+    let templates = std::env::temp_dir().join(format!("rwf_app_test_{}", port));
+    std::fs::create_dir_all(&templates).unwrap();
+    std::fs::write(templates.join("index.html"), "<h1><%= title %></h1>").unwrap();
+
+    let app = App::new()
+        .database(get_config().database.database_url())
+        .routes(vec![Index.route("/")])
+        .middleware(vec![Tag.middleware()])
+        .jobs(vec![Greet.job()])
+        .workers(2)
+        .templates(&templates)
+        .build()
+        .await
+        .unwrap();
+
+    let shutdown = app.shutdown();
+    let running = tokio::spawn(app.serve_and_work(address.clone()));
+
+    let mut stream = loop {
+        match TcpStream::connect(&address).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(
+        response.to_lowercase().contains("x-app: test"),
+        "{}",
+        response
+    );
+    assert!(response.ends_with("hello"), "{}", response);
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !JOB_DONE.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("job didn't run");
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), running)
+        .await
+        .expect("app didn't shut down")
+        .unwrap()
+        .unwrap();
+
+    std::fs::remove_dir_all(&templates).unwrap();
+}
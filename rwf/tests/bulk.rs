@@ -0,0 +1,71 @@
+use rwf::model::Error;
+use rwf::prelude::*;
+
+#[derive(Clone, macros::Model)]
+struct BulkOrder {
+    id: Option<i64>,
+    status: String,
+    updated_at: OffsetDateTime,
+}
+
+#[tokio::test]
+async fn test_bulk_update_and_delete() -> Result<(), Error> {
+    let mut conn = rwf::model::get_connection().await?;
+    conn.client()
+        .execute(
+            "CREATE TABLE IF NOT EXISTS bulk_orders (
+                id BIGSERIAL PRIMARY KEY,
+                status VARCHAR NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )",
+            &[],
+        )
+        .await?;
+    conn.client().execute("TRUNCATE bulk_orders", &[]).await?;
+
+    for status in ["pending", "pending", "pending", "shipped"] {
+        BulkOrder::create(&[("status", status)])
+            .execute(&mut conn)
+            .await?;
+    }
+
+    let updated = BulkOrder::filter("status", "pending")
+        .update_all(&[
+            ("status", "cancelled".to_value()),
+            ("updated_at", OffsetDateTime::now_utc().to_value()),
+        ])
+        .affected_rows(&mut conn)
+        .await?;
+    assert_eq!(updated, 3);
+
+    let deleted = BulkOrder::filter("status", "cancelled")
+        .delete_all()
+        .affected_rows(&mut conn)
+        .await?;
+    assert_eq!(deleted, 3);
+    assert_eq!(BulkOrder::all().count(&mut conn).await?, 1);
+
+    // No filters, no deletes.
+    let err = BulkOrder::all()
+        .delete_all()
+        .affected_rows(&mut conn)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::FullTable { .. }));
+    assert!(BulkOrder::all()
+        .update_all(&[("status", "lost")])
+        .execute(&mut conn)
+        .await
+        .is_err());
+    assert_eq!(BulkOrder::all().count(&mut conn).await?, 1);
+
+    let deleted = BulkOrder::all()
+        .delete_all()
+        .allow_full_table()
+        .fetch_all(&mut conn)
+        .await?;
+    assert_eq!(deleted.len(), 1);
+    assert_eq!(deleted[0].status, "shipped");
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rwf::model::{DatabaseErrorKind, Error};
+use rwf::prelude::*;
+
+#[tokio::test]
+async fn test_database_errors() -> Result<(), Error> {
+    let schema = format!("rwf_test_errors_{}", std::process::id());
+    let conn = Pool::connection().await?;
+
+    conn.client()
+        .batch_execute(&format!(
+            r#"CREATE SCHEMA "{schema}";
+            CREATE TABLE "{schema}".users (
+                id BIGSERIAL PRIMARY KEY,
+                email VARCHAR NOT NULL UNIQUE,
+                age INTEGER CONSTRAINT age_positive CHECK (age > 0)
+            );
+            CREATE TABLE "{schema}".orders (
+                id BIGSERIAL PRIMARY KEY,
+                user_id BIGINT NOT NULL REFERENCES "{schema}".users (id)
+            );
+            INSERT INTO "{schema}".users (email, age) VALUES ('user@example.com', 30);"#
+        ))
+        .await?;
+
+    let kind = |sql: String| {
+        let conn = &conn;
+        async move {
+            let err = conn.client().batch_execute(&sql).await.unwrap_err();
+            Error::from(err).kind()
+        }
+    };
+
+    assert_eq!(
+        kind(format!(
+            r#"INSERT INTO "{schema}".users (email) VALUES ('user@example.com')"#
+        ))
+        .await,
+        DatabaseErrorKind::UniqueViolation {
+            constraint: Some("users_email_key".into()),
+            column_hint: Some("email".into()),
+        }
+    );
+    assert_eq!(
+        kind(format!(
+            r#"INSERT INTO "{schema}".orders (user_id) VALUES (1234)"#
+        ))
+        .await,
+        DatabaseErrorKind::ForeignKeyViolation {
+            constraint: Some("orders_user_id_fkey".into())
+        }
+    );
+    assert_eq!(
+        kind(format!(
+            r#"INSERT INTO "{schema}".orders (user_id) VALUES (NULL)"#
+        ))
+        .await,
+        DatabaseErrorKind::NotNullViolation {
+            column: Some("user_id".into())
+        }
+    );
+    assert_eq!(
+        kind(format!(
+            r#"INSERT INTO "{schema}".users (email, age) VALUES ('other@example.com', -1)"#
+        ))
+        .await,
+        DatabaseErrorKind::CheckViolation {
+            constraint: Some("age_positive".into())
+        }
+    );
+    assert_eq!(
+        kind("SET statement_timeout = 10; SELECT pg_sleep(1)".into()).await,
+        DatabaseErrorKind::Timeout
+    );
+    conn.client()
+        .batch_execute("RESET statement_timeout")
+        .await?;
+
+    // Two serializable transactions updating the same row.
+    let mut first = Pool::begin().await?;
+    let mut second = Pool::begin().await?;
+    for transaction in [&mut first, &mut second] {
+        transaction
+            .client()
+            .batch_execute(&format!(
+                r#"SET TRANSACTION ISOLATION LEVEL SERIALIZABLE;
+                SELECT * FROM "{schema}".users"#
+            ))
+            .await?;
+    }
+    let update = format!(r#"UPDATE "{schema}".users SET age = age + 1"#);
+    first.client().batch_execute(&update).await?;
+    first.commit().await?;
+    let err = Error::from(second.client().batch_execute(&update).await.unwrap_err());
+    assert_eq!(err.kind(), DatabaseErrorKind::SerializationFailure);
+    assert!(err.is_retryable());
+    drop(second);
+
+    // Serialization failures are retried.
+    let attempts = AtomicUsize::new(0);
+    let result = Pool::pool()
+        .with_transaction_retry(2, |transaction| {
+            let attempts = &attempts;
+            async move {
+                if attempts.fetch_add(1, Ordering::Relaxed) == 0 {
+                    transaction
+                        .client()
+                        .batch_execute(
+                            "DO $$ BEGIN RAISE EXCEPTION 'conflict' USING ERRCODE = 'serialization_failure'; END $$",
+                        )
+                        .await?;
+                }
+                transaction.commit().await?;
+                Ok(attempts.load(Ordering::Relaxed))
+            }
+        })
+        .await?;
+    assert_eq!(result, 2);
+
+    // Other errors aren't.
+    let attempts = AtomicUsize::new(0);
+    let result = Pool::pool()
+        .with_transaction_retry(2, |transaction| {
+            let attempts = &attempts;
+            let sql = format!(r#"INSERT INTO "{schema}".orders (user_id) VALUES (1234)"#);
+            async move {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                transaction.client().batch_execute(&sql).await?;
+                Ok(())
+            }
+        })
+        .await;
+    assert!(result.is_err());
+    assert_eq!(attempts.load(Ordering::Relaxed), 1);
+
+    conn.client()
+        .batch_execute(&format!(r#"DROP SCHEMA "{schema}" CASCADE"#))
+        .await?;
+
+    Ok(())
+}
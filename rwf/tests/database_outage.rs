@@ -0,0 +1,139 @@
+use rwf::app::{self, App};
+use rwf::health;
+use rwf::model::pool::{set_pool, PoolConfig};
+use rwf::prelude::*;
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+
+// Nothing listens on this port.
+const CLOSED_PORT: &str = "postgres://rwf@127.0.0.1:1/rwf";
+
+#[derive(Default)]
+struct Orders;
+
+#[async_trait]
+impl Controller for Orders {
+    async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+        Pool::connection().await?;
+        Ok(Response::new().text("orders"))
+    }
+}
+
+#[derive(Default)]
+struct Live;
+
+#[async_trait]
+impl Controller for Live {
+    fn database_free(&self) -> bool {
+        true
+    }
+
+    async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+        Ok(Response::new().text(format!("live, ready: {}", health::ready())))
+    }
+}
+
+async fn get(address: &str, path: &str) -> String {
+    let mut stream = loop {
+        match TcpStream::connect(address).await {
+            Ok(stream) => break stream,
+            Err(_) => sleep(Duration::from_millis(10)).await,
+        }
+    };
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+fn address() -> String {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    format!("127.0.0.1:{}", port)
+}
+
+fn builder() -> app::AppBuilder {
+    App::new().routes(vec![Orders.route("/orders"), Live.route("/live")])
+}
+
+#[tokio::test]
+async fn test_database_outage() {
+    set_pool(Pool::new(
+        CLOSED_PORT,
+        PoolConfig {
+            failure_threshold: 2,
+            recovery_interval: Duration::from_millis(50),
+            ..Default::default()
+        },
+    ))
+    .unwrap();
+
+    // Without a grace period, the application doesn't start.
+    assert!(matches!(
+        builder().build().await,
+        Err(app::Error::Database(_))
+    ));
+
+    // With one, it serves database-free routes while waiting for the database.
+    let app = builder()
+        .startup_grace_period(Duration::from_secs(60))
+        .build()
+        .await
+        .unwrap();
+    assert!(health::starting());
+    assert!(!health::ready());
+    assert!(health::live());
+
+    let address = address();
+    let shutdown = app.shutdown();
+    let running = tokio::spawn(app.serve(address.clone()));
+
+    let response = get(&address, "/live").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("live, ready: false"), "{}", response);
+
+    let response = get(&address, "/orders").await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+    assert!(
+        response.to_lowercase().contains("retry-after: 1"),
+        "{}",
+        response
+    );
+
+    // Meanwhile, the circuit breaker opened.
+    assert!(!Pool::pool().available());
+
+    shutdown.shutdown();
+    timeout(Duration::from_secs(5), running)
+        .await
+        .expect("app didn't shut down")
+        .unwrap()
+        .unwrap();
+
+    // The database doesn't come back in time.
+    let app = builder()
+        .startup_grace_period(Duration::from_millis(300))
+        .build()
+        .await
+        .unwrap();
+    let result = timeout(Duration::from_secs(5), app.serve(address.clone()))
+        .await
+        .expect("app didn't give up on the database");
+    assert!(matches!(result, Err(app::Error::Database(_))));
+}
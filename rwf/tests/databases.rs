@@ -0,0 +1,168 @@
+use rwf::config::get_config;
+use rwf::model::pool::set_named_pool;
+use rwf::model::{Error, Migrations};
+use rwf::prelude::*;
+
+#[derive(Clone, macros::Model, Serialize, Deserialize)]
+#[database("analytics")]
+struct Event {
+    id: Option<i64>,
+    name: String,
+}
+
+#[derive(Clone, macros::Model)]
+struct Account {
+    id: Option<i64>,
+    email: String,
+}
+
+#[derive(Default, macros::ModelController)]
+struct Events;
+
+#[async_trait]
+impl ModelController for Events {
+    type Model = Event;
+}
+
+async fn get(path: &str) -> Response {
+    let request = format!("GET {} HTTP/1.1\r\n\r\n", path);
+    let request = Request::read("127.0.0.1:1234".parse().unwrap(), request.as_bytes())
+        .await
+        .unwrap();
+    let handler = Events::default().crud("/events");
+    let request = request.with_params(handler.path_with_regex().params());
+
+    handler.controller().handle(&request).await.unwrap()
+}
+
+// URL of another database on the same server.
+fn database_url(name: &str) -> String {
+    let url = get_config().database.database_url();
+    let (server, _) = url.rsplit_once('/').unwrap();
+    format!("{}/{}", server, name)
+}
+
+#[tokio::test]
+async fn test_multiple_databases() -> Result<(), Error> {
+    let databases = [
+        ("main", format!("rwf_test_main_{}", std::process::id())),
+        (
+            "analytics",
+            format!("rwf_test_analytics_{}", std::process::id()),
+        ),
+    ];
+
+    let admin = Pool::from_env();
+    let conn = admin.get().await?;
+
+    for (database, name) in &databases {
+        conn.client()
+            .execute(&format!("DROP DATABASE IF EXISTS {}", name), &[])
+            .await?;
+        conn.client()
+            .execute(&format!("CREATE DATABASE {}", name), &[])
+            .await?;
+
+        set_named_pool(database, Pool::from_url(&database_url(name))).unwrap();
+
+        let conn = Pool::named(database)?.get().await?;
+        conn.client()
+            .execute(
+                "CREATE TABLE accounts (id BIGSERIAL PRIMARY KEY, email VARCHAR NOT NULL)",
+                &[],
+            )
+            .await?;
+        conn.client()
+            .execute(
+                "CREATE TABLE events (id BIGSERIAL PRIMARY KEY, name VARCHAR NOT NULL)",
+                &[],
+            )
+            .await?;
+    }
+
+    let mut main = Pool::named("main")?.get().await?;
+    let mut analytics = Pool::named(Event::database())?.get().await?;
+    assert_eq!(main.database(), "main");
+    assert_eq!(analytics.database(), "analytics");
+
+    // Queries run on the model's database.
+    Account::create(&[("email", "user@example.com")])
+        .fetch(&mut main)
+        .await?;
+    let event = Event::create(&[("name", "signup")])
+        .fetch(&mut analytics)
+        .await?;
+
+    assert_eq!(Account::all().count(&mut main).await?, 1);
+    assert_eq!(Event::all().count(&mut analytics).await?, 1);
+
+    // Including the ones run by model controllers.
+    let response = get("/events").await;
+    assert_eq!(response.status().code(), 200);
+    let events: Vec<serde_json::Value> =
+        serde_json::from_slice(response.body_bytes().unwrap()).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["name"], "signup");
+
+    let response = get(&format!("/events/{}", event.id.unwrap())).await;
+    assert_eq!(response.status().code(), 200);
+
+    // And nowhere else.
+    assert!(matches!(
+        Event::all().fetch_all(&mut main).await,
+        Err(Error::WrongDatabase { ref expected, ref actual, .. })
+            if expected == "analytics" && actual == "main"
+    ));
+    assert!(matches!(
+        Account::all().count(&mut analytics).await,
+        Err(Error::WrongDatabase { .. })
+    ));
+
+    // Unless the query chooses another database.
+    assert_eq!(Event::all().on("main").count(&mut main).await?, 0);
+    assert!(matches!(
+        Event::all().on("main").count(&mut analytics).await,
+        Err(Error::WrongDatabase { .. })
+    ));
+
+    // Transactions don't span databases.
+    let mut transaction = Pool::named("main")?.transaction().await?;
+    Account::create(&[("email", "admin@example.com")])
+        .fetch(&mut transaction)
+        .await?;
+    assert!(matches!(
+        Event::create(&[("name", "login")]).fetch(&mut transaction).await,
+        Err(Error::CrossDatabaseTransaction { ref expected, ref actual, .. })
+            if expected == "analytics" && actual == "main"
+    ));
+    transaction.rollback().await?;
+
+    assert_eq!(Account::all().count(&mut main).await?, 1);
+    assert_eq!(Event::all().count(&mut analytics).await?, 1);
+
+    // Each database tracks its own migrations.
+    let migrations = Migrations::sync_database("analytics").await?;
+    assert_eq!(migrations.database(), "analytics");
+    assert!(Pool::named("unknown").is_err());
+
+    let tracked = analytics
+        .client()
+        .query_one(
+            "SELECT COUNT(*) FROM pg_tables WHERE tablename = 'rwf_migrations'",
+            &[],
+        )
+        .await?
+        .get::<_, i64>(0);
+    assert_eq!(tracked, 1);
+
+    drop(main);
+    drop(analytics);
+
+    for (_, name) in &databases {
+        conn.client()
+            .execute(&format!("DROP DATABASE {} WITH (FORCE)", name), &[])
+            .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use rwf::model::Error;
+use rwf::prelude::*;
+
+// Encrypted with the previous secret key.
+const OLD_CIPHERTEXT: &str = "eyJjIjpbMTk0LDQ3LDE0MCwxMzksMTQ3LDQsMzksNTEsMjQ2LDE1MSwxODAsMjQxLDQzLDc2LDU5LDMsMTUsMzksMTYzLDIxMiwxODAsOTksMTgsMTA5LDI0NSwxNTEsMjIsNjcsMjEyLDE4N10sIm4iOlsxMjgsMTMxLDI0MSwxMjcsMjEwLDIxNyw5Nyw2Miw5Nyw2OCwxMjQsNF19";
+
+#[derive(Clone, Debug, macros::Model)]
+#[table_name("rwf_test_encrypted_users")]
+struct User {
+    id: Option<i64>,
+    email: String,
+    #[model(encrypted)]
+    token: Option<String>,
+    #[model(encrypted, deterministic)]
+    ssn: String,
+}
+
+#[tokio::test]
+async fn test_encrypted_attributes() -> Result<(), Error> {
+    // The configuration is loaded on first use.
+    std::env::set_var(
+        "RWF_SECRET_KEY",
+        "AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=",
+    );
+    std::env::set_var(
+        "RWF_PREVIOUS_SECRET_KEYS",
+        "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=",
+    );
+
+    let mut transaction = Pool::begin().await?;
+    transaction
+        .client()
+        .execute(
+            "CREATE TABLE rwf_test_encrypted_users (
+                id BIGSERIAL PRIMARY KEY,
+                email VARCHAR NOT NULL,
+                token VARCHAR,
+                ssn VARCHAR NOT NULL
+            )",
+            &[],
+        )
+        .await?;
+
+    // Round trip.
+    let user = User::create(&[
+        ("email", "user@example.com"),
+        ("token", "tok_secret"),
+        ("ssn", "123-45-6789"),
+    ])
+    .fetch(&mut transaction)
+    .await?;
+    assert_eq!(user.email, "user@example.com");
+    assert_eq!(user.token.as_deref(), Some("tok_secret"));
+    assert_eq!(user.ssn, "123-45-6789");
+
+    // The database only has the ciphertext.
+    let row = transaction
+        .client()
+        .query_one(
+            "SELECT email, token, ssn FROM rwf_test_encrypted_users WHERE id = $1",
+            &[&user.id],
+        )
+        .await?;
+    assert_eq!(row.get::<_, String>("email"), "user@example.com");
+    assert!(!row.get::<_, String>("token").contains("tok_secret"));
+    assert!(!row.get::<_, String>("ssn").contains("123-45-6789"));
+
+    // Deterministic columns can be searched.
+    let found = User::find_by("ssn", "123-45-6789")
+        .fetch(&mut transaction)
+        .await?;
+    assert_eq!(found.id, user.id);
+    assert!(User::filter("ssn", "987-65-4321")
+        .fetch_optional(&mut transaction)
+        .await?
+        .is_none());
+
+    // Randomly encrypted columns can't.
+    assert!(User::filter("token", "tok_secret")
+        .fetch_optional(&mut transaction)
+        .await?
+        .is_none());
+
+    let mut user = found;
+    user.token = None;
+    let user = user.save().fetch(&mut transaction).await?;
+    assert!(user.token.is_none());
+
+    let token: Option<String> = transaction
+        .client()
+        .query_one(
+            "SELECT token FROM rwf_test_encrypted_users WHERE id = $1",
+            &[&user.id],
+        )
+        .await?
+        .get(0);
+    assert!(token.is_none());
+
+    // Rows encrypted before the key was rotated are still readable.
+    let id: i64 = transaction
+        .client()
+        .query_one(
+            "INSERT INTO rwf_test_encrypted_users (email, token, ssn) VALUES ('old@example.com', $1, $1) RETURNING id",
+            &[&OLD_CIPHERTEXT],
+        )
+        .await?
+        .get(0);
+
+    let old = User::find(id).fetch(&mut transaction).await?;
+    assert_eq!(old.token.as_deref(), Some("tok_old_secret"));
+    assert_eq!(old.ssn, "tok_old_secret");
+
+    // And encrypted with the current key when saved again.
+    assert!(User::find_by("ssn", "tok_old_secret")
+        .fetch_optional(&mut transaction)
+        .await?
+        .is_none());
+    old.save().fetch(&mut transaction).await?;
+    let old = User::find_by("ssn", "tok_old_secret")
+        .fetch(&mut transaction)
+        .await?;
+    assert_eq!(old.id, Some(id));
+
+    // Data that wasn't encrypted by us.
+    transaction
+        .client()
+        .execute(
+            "UPDATE rwf_test_encrypted_users SET token = 'plaintext' WHERE id = $1",
+            &[&id],
+        )
+        .await?;
+    assert!(matches!(
+        User::find(id).fetch(&mut transaction).await,
+        Err(Error::Decrypt { ref column, .. }) if column == "token"
+    ));
+
+    transaction.rollback().await?;
+
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use rwf::controller::Error;
+use rwf::http::{urlencode, Server};
+use rwf::prelude::*;
+use rwf::view::template::Context;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+static USER: Mutex<Option<User>> = Mutex::new(None);
+
+static EDIT: &str = r#"<%= form_for(user, "/users/5") %>
+<%= input(user, "email", [["type", "email"]]) %>
+<%= errors_for(user, "email") %>
+<%= select(user, "role", ["admin", "member"]) %>
+<%= checkbox(user, "active") %>
+<%= submit("Save") %>
+<%= end_form() %>"#;
+
+#[derive(Clone, Debug, macros::Model)]
+#[table_name("rwf_test_form_users")]
+struct User {
+    id: Option<i64>,
+    email: String,
+    role: String,
+    active: bool,
+}
+
+#[derive(Default)]
+struct Users;
+
+impl Users {
+    fn edit(user: impl ToTemplateValue, code: u16) -> Result<Response, Error> {
+        let mut context = Context::new();
+        context.set("user", user)?;
+        let html = Template::from_str(EDIT)?.render(&context)?;
+        Ok(Response::new().html(html).code(code))
+    }
+}
+
+#[async_trait]
+impl Controller for Users {
+    async fn handle(&self, request: &Request) -> Result<Response, Error> {
+        let user = USER.lock().unwrap().clone().unwrap();
+
+        match request.method() {
+            Method::Get => Self::edit(user, 200),
+            Method::Put => {
+                let form_data = request.form_data()?;
+                let email = form_data.get::<String>("email").unwrap_or_default();
+                let mut errors = FormErrors::new();
+
+                if !email.contains('@') {
+                    errors.add("email", "must be a valid email address");
+                }
+
+                if !errors.is_empty() {
+                    let user = FormRecord::new(&user)?.form_data(&form_data).errors(errors);
+                    return Self::edit(user, 422);
+                }
+
+                let user = User {
+                    email,
+                    role: form_data.get_required("role")?,
+                    active: form_data.get_required("active")?,
+                    ..user
+                };
+                *USER.lock().unwrap() = Some(user);
+
+                Ok(Response::new().redirect("/users/5"))
+            }
+            _ => Ok(Response::method_not_allowed()),
+        }
+    }
+}
+
+async fn send(address: &str, request: String) -> String {
+    let mut stream = loop {
+        match TcpStream::connect(address).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    stream.write_all(request.as_bytes()).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+fn submit(form: &str, fields: &[(&str, &str)]) -> String {
+    // The CSRF token and method override are read from the rendered form.
+    let hidden = |name: &str| {
+        let marker = format!(r#"name="{}" value=""#, name);
+        let start = form.find(&marker).unwrap() + marker.len();
+        let end = start + form[start..].find('"').unwrap();
+        form[start..end].to_string()
+    };
+
+    let mut body = vec![
+        format!("rwf_csrf_token={}", urlencode(&hidden("rwf_csrf_token"))),
+        format!("_method={}", hidden("_method")),
+    ];
+    body.extend(
+        fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, urlencode(value))),
+    );
+    let body = body.join("&");
+
+    format!(
+        "POST /users/5 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+#[tokio::test]
+async fn test_form_round_trip() {
+    // The configuration is loaded on first use.
+    std::env::set_var("RWF_METHOD_OVERRIDE", "1");
+
+    *USER.lock().unwrap() = Some(User {
+        id: Some(5),
+        email: "user@example.com".into(),
+        role: "member".into(),
+        active: true,
+    });
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address = format!("127.0.0.1:{}", port);
+
+    let (shutdown, stop) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(
+        Server::new(vec![Users::default().route("/users/5")]).launch_with_shutdown(
+            address.clone(),
+            async move {
+                let _ = stop.await;
+            },
+        ),
+    );
+
+    let get = "GET /users/5 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_string();
+
+    // Render.
+    let form = send(&address, get.clone()).await;
+    assert!(form.starts_with("HTTP/1.1 200"), "{}", form);
+    assert!(form.contains(r#"<form action="/users/5" method="post">"#));
+    assert!(form.contains(r#"<input type="hidden" name="_method" value="PUT">"#));
+    assert!(
+        form.contains(r#"<input type="email" name="email" id="email" value="user@example.com">"#)
+    );
+    assert!(form.contains(r#"<option value="member" selected>member</option>"#));
+    assert!(form.contains(r#"value="true" checked>"#));
+
+    // Submit invalid.
+    let invalid = send(
+        &address,
+        submit(
+            &form,
+            &[
+                ("email", "not an \"email\""),
+                ("role", "admin"),
+                ("active", "false"),
+            ],
+        ),
+    )
+    .await;
+    assert!(invalid.starts_with("HTTP/1.1 422"), "{}", invalid);
+    assert!(invalid.contains(
+        r#"<input type="email" name="email" id="email" value="not an &quot;email&quot;" aria-invalid="true">"#
+    ));
+    assert!(invalid.contains(r#"<ul class="errors"><li>must be a valid email address</li></ul>"#));
+    assert!(invalid.contains(r#"<option value="admin" selected>admin</option>"#));
+    assert!(!invalid.contains("checked"));
+    assert_eq!(
+        USER.lock().unwrap().as_ref().unwrap().email,
+        "user@example.com"
+    );
+
+    // Submit valid.
+    let valid = send(
+        &address,
+        submit(
+            &invalid,
+            &[
+                ("email", "new@example.com"),
+                ("role", "admin"),
+                ("active", "false"),
+            ],
+        ),
+    )
+    .await;
+    assert!(valid.starts_with("HTTP/1.1 302"), "{}", valid);
+
+    let user = USER.lock().unwrap().clone().unwrap();
+    assert_eq!(user.email, "new@example.com");
+    assert_eq!(user.role, "admin");
+    assert!(!user.active);
+
+    let form = send(&address, get).await;
+    assert!(form.contains(r#"value="new@example.com">"#));
+    assert!(!form.contains("errors"));
+
+    shutdown.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
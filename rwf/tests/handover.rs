@@ -0,0 +1,69 @@
+#![cfg(unix)]
+use rwf::controller::Error;
+use rwf::http::{handover::LISTEN_FD, Server};
+use rwf::prelude::*;
+use rwf::shutdown::{phase, Phase};
+
+use std::time::Duration;
+
+use futures_util::stream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+
+#[derive(Default)]
+struct Pid;
+
+#[async_trait]
+impl Controller for Pid {
+    async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+        Ok(Response::new().text(std::process::id()))
+    }
+}
+
+async fn pid(address: &str) -> u32 {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response.split("\r\n\r\n").nth(1).unwrap().parse().unwrap()
+}
+
+// The test binary is started again, with the same arguments, as the new process.
+#[tokio::test]
+#[ignore]
+async fn test_handover() {
+    if std::env::var(LISTEN_FD).is_ok() {
+        // New process: serve on the inherited socket for a while.
+        Server::new(vec![Pid.route("/")])
+            .launch_with_shutdown("255.255.255.255:1", sleep(Duration::from_secs(3)))
+            .await
+            .unwrap();
+        return;
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let trigger = stream::once(sleep(Duration::from_millis(500)));
+    let server = Server::new(vec![Pid.route("/")])
+        .listener(listener)
+        .handover_on(trigger);
+    let server = tokio::spawn(server.launch_with_shutdown(address.clone(), std::future::pending()));
+
+    assert_eq!(pid(&address).await, std::process::id());
+
+    // The server stops once the new process accepts connections.
+    timeout(Duration::from_secs(20), server)
+        .await
+        .expect("handover timed out")
+        .unwrap()
+        .unwrap();
+    assert_eq!(phase(), Phase::Stopped);
+
+    let new = pid(&address).await;
+    assert_ne!(new, std::process::id());
+}
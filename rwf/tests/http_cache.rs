@@ -0,0 +1,103 @@
+use rwf::controller::{Error, HttpCache, HttpCacheable};
+use rwf::prelude::*;
+
+#[derive(Clone, macros::Model, Serialize, Deserialize)]
+struct CachedPost {
+    id: Option<i64>,
+    title: String,
+    updated_at: OffsetDateTime,
+}
+
+impl HttpCacheable for CachedPost {
+    fn updated_at(&self) -> Option<OffsetDateTime> {
+        Some(self.updated_at)
+    }
+}
+
+#[derive(Default, macros::ModelController)]
+struct CachedPosts;
+
+#[async_trait]
+impl ModelController for CachedPosts {
+    type Model = CachedPost;
+
+    fn http_cache(&self) -> HttpCache<CachedPost> {
+        HttpCache::enabled().collections()
+    }
+}
+
+async fn get(path: &str, etag: Option<&str>) -> Response {
+    let mut request = format!("GET {} HTTP/1.1\r\n", path);
+    if let Some(etag) = etag {
+        request.push_str(&format!("If-None-Match: {}\r\n", etag));
+    }
+    request.push_str("\r\n");
+
+    let request = Request::read("127.0.0.1:1234".parse().unwrap(), request.as_bytes())
+        .await
+        .unwrap();
+    let handler = CachedPosts::default().crud("/cached_posts");
+    let request = request.with_params(handler.path_with_regex().params());
+
+    handler.controller().handle(&request).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_conditional_get() -> Result<(), Error> {
+    let mut conn = rwf::model::get_connection().await?;
+    conn.client()
+        .execute(
+            "CREATE TABLE IF NOT EXISTS cached_posts (
+                id BIGSERIAL PRIMARY KEY,
+                title VARCHAR NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )",
+            &[],
+        )
+        .await
+        .map_err(rwf::model::Error::from)?;
+
+    let post = CachedPost::create(&[("title", "Hello")])
+        .fetch(&mut conn)
+        .await?;
+    let path = format!("/cached_posts/{}", post.id.unwrap());
+
+    // 200 with validators.
+    let response = get(&path, None).await;
+    assert_eq!(response.status().code(), 200);
+    let etag = response.headers().get("etag").unwrap().clone();
+    assert!(etag.starts_with("W/\""));
+    assert!(response.headers().get("last-modified").is_some());
+
+    // 304 without a body.
+    let response = get(&path, Some(&etag)).await;
+    assert_eq!(response.status().code(), 304);
+    assert_eq!(response.headers().get("etag"), Some(&etag));
+
+    // 200 after the record changes.
+    let mut post = post;
+    post.title = "Hello again".into();
+    post.updated_at = post.updated_at + Duration::seconds(1);
+    post.save().execute(&mut conn).await?;
+
+    let response = get(&path, Some(&etag)).await;
+    assert_eq!(response.status().code(), 200);
+    assert_ne!(response.headers().get("etag"), Some(&etag));
+
+    // Lists are validated by the most recent update and the number of records.
+    let response = get("/cached_posts", None).await;
+    assert_eq!(response.status().code(), 200);
+    let etag = response.headers().get("etag").unwrap().clone();
+
+    let response = get("/cached_posts", Some(&etag)).await;
+    assert_eq!(response.status().code(), 304);
+
+    CachedPost::create(&[("title", "Another one")])
+        .execute(&mut conn)
+        .await?;
+
+    let response = get("/cached_posts", Some(&etag)).await;
+    assert_eq!(response.status().code(), 200);
+
+    Ok(())
+}
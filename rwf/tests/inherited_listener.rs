@@ -0,0 +1,96 @@
+#![cfg(unix)]
+use rwf::controller::Error;
+use rwf::http::{
+    handover::{LISTEN_FD, READY_FD},
+    Server,
+};
+use rwf::prelude::*;
+use rwf::shutdown::{phase, ready, Phase};
+
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+
+#[derive(Default)]
+struct Slow;
+
+#[async_trait]
+impl Controller for Slow {
+    async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+        sleep(Duration::from_millis(500)).await;
+        Ok(Response::new().text("done"))
+    }
+}
+
+async fn send(address: &str, request: &str) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+// Started like the new process of a handover: the listening socket
+// and the readiness socket are passed in the environment.
+#[tokio::test]
+async fn test_inherited_listener() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (old, new) = UnixStream::pair().unwrap();
+
+    std::env::set_var(LISTEN_FD, listener.into_raw_fd().to_string());
+    std::env::set_var(READY_FD, new.into_raw_fd().to_string());
+
+    // The address passed to launch isn't bound.
+    let (shutdown, stop) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(
+        Server::new(vec![Slow::default().route("/slow")]).launch_with_shutdown(
+            "255.255.255.255:1",
+            async move {
+                let _ = stop.await;
+            },
+        ),
+    );
+
+    // The old process is told the server is accepting connections.
+    old.set_nonblocking(true).unwrap();
+    let mut old = tokio::net::UnixStream::from_std(old).unwrap();
+    let mut byte = [0u8; 1];
+    timeout(Duration::from_secs(5), old.read_exact(&mut byte))
+        .await
+        .expect("server didn't accept connections")
+        .unwrap();
+    assert_eq!(&byte, b"1");
+    assert!(ready());
+
+    let request = {
+        let address = address.clone();
+        tokio::spawn(async move {
+            send(
+                &address,
+                "GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n",
+            )
+            .await
+        })
+    };
+
+    // Shut down while the request is in progress.
+    sleep(Duration::from_millis(200)).await;
+    shutdown.send(()).unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(phase(), Phase::Draining);
+
+    let response = request.await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("done"), "{}", response);
+
+    server.await.unwrap().unwrap();
+    assert_eq!(phase(), Phase::Stopped);
+
+    // The inherited socket is closed.
+    assert!(TcpStream::connect(&address).await.is_err());
+}
@@ -0,0 +1,144 @@
+use rwf::app::App;
+use rwf::config::get_config;
+use rwf::controller::JobStatusController;
+use rwf::prelude::*;
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[derive(Default, Serialize, Deserialize)]
+struct Export {
+    user_id: i64,
+    rows: i64,
+}
+
+#[async_trait]
+impl Job for Export {
+    async fn execute(
+        &self,
+        context: &JobContext,
+        args: serde_json::Value,
+    ) -> Result<(), rwf::job::Error> {
+        let export: Export = serde_json::from_value(args)?;
+
+        for row in 0..export.rows {
+            context.progress(row, export.rows).await?;
+            tokio::time::sleep(Duration::from_millis(300)).await;
+        }
+
+        context.progress(export.rows, export.rows).await?;
+        context.set_result(serde_json::json!({ "url": "/exports/1.csv" }));
+
+        Ok(())
+    }
+}
+
+async fn get(address: &str, path: &str) -> (u16, serde_json::Value) {
+    let mut stream = loop {
+        match TcpStream::connect(address).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let code = response[9..12].parse().unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (code, serde_json::from_str(body).unwrap_or_default())
+}
+
+#[tokio::test]
+async fn test_job_progress() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address = format!("127.0.0.1:{}", port);
+
+    let status = JobStatusController::new(|_request, status| status.args["user_id"] == 5);
+
+    let app = App::new()
+        .database(get_config().database.database_url())
+        .routes(vec![status.route("/jobs/:id")])
+        .jobs(vec![Export::default().job()])
+        .workers(1)
+        .build()
+        .await
+        .unwrap();
+
+    let shutdown = app.shutdown();
+    let running = tokio::spawn(app.serve_and_work(address.clone()));
+
+    let id = queue_async(&Export {
+        user_id: 5,
+        rows: 10,
+    })
+    .await
+    .unwrap();
+    let other = queue_delay(
+        &Export {
+            user_id: 6,
+            rows: 1,
+        },
+        time::Duration::hours(1),
+    )
+    .await
+    .unwrap();
+    assert!(other > id);
+
+    // Jobs of other users aren't visible.
+    let (code, _) = get(&address, &format!("/jobs/{}", other)).await;
+    assert_eq!(code, 404);
+
+    let mut progress = vec![];
+    let status = tokio::time::timeout(Duration::from_secs(30), async {
+        loop {
+            let (code, status) = get(&address, &format!("/jobs/{}", id)).await;
+            assert_eq!(code, 200, "{}", status);
+
+            if status["state"] == "completed" {
+                break status;
+            }
+
+            if let Some(completed) = status["progress"]["completed"].as_i64() {
+                progress.push(completed);
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("job didn't finish");
+
+    assert_eq!(status["id"], id);
+    assert_eq!(status["progress"]["completed"], 10);
+    assert_eq!(status["progress"]["total"], 10);
+    assert_eq!(status["result"]["url"], "/exports/1.csv");
+    assert!(status["started_at"].is_string());
+    assert!(status["finished_at"].is_string());
+    assert!(status.get("args").is_none());
+
+    // Progress was reported while the job was running, at most once per second.
+    assert!(!progress.is_empty());
+    assert!(progress.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert!(progress.iter().all(|completed| *completed < 10));
+
+    let finished = rwf::job::status(id).await.unwrap().unwrap();
+    assert!(finished.finished());
+
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), running)
+        .await
+        .expect("app didn't shut down")
+        .unwrap()
+        .unwrap();
+}
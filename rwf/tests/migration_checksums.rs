@@ -0,0 +1,98 @@
+use rwf::model::migrations::{Direction, MigrationStatus};
+use rwf::model::{Error, Migrations};
+use rwf::prelude::*;
+use tempdir::TempDir;
+
+use std::fs::write;
+
+// Only used by this test, far past any timestamp-based version.
+const VERSION: i64 = 9_000_000_000_477;
+const NAME: &str = "rwf_test_checksums";
+
+fn status(migrations: &Migrations) -> MigrationStatus {
+    let migration = migrations
+        .migrations()
+        .iter()
+        .find(|migration| migration.version == VERSION)
+        .unwrap();
+    migrations.status(migration)
+}
+
+#[tokio::test]
+async fn test_modified_migration() -> Result<(), Error> {
+    let conn = Pool::pool().get().await?;
+
+    // Clean up after a previous run which failed. The migrations
+    // table doesn't exist yet in a new database.
+    conn.client()
+        .execute(&format!("DROP TABLE IF EXISTS {}", NAME), &[])
+        .await?;
+    conn.client()
+        .execute("DELETE FROM rwf_migrations WHERE version = $1", &[&VERSION])
+        .await
+        .ok();
+
+    // Migrations are read from the current directory. This is the only test
+    // in this binary, so changing it doesn't affect other tests.
+    let dir = TempDir::new("rwf_migration_checksums")?;
+    let migrations_dir = dir.path().join("migrations");
+    std::fs::create_dir(&migrations_dir)?;
+    std::env::set_current_dir(dir.path())?;
+
+    let up = migrations_dir.join(format!("{}_{}.up.sql", VERSION, NAME));
+    let down = migrations_dir.join(format!("{}_{}.down.sql", VERSION, NAME));
+    write(&up, format!("CREATE TABLE {} (id BIGINT);", NAME))?;
+    write(&down, format!("DROP TABLE {};", NAME))?;
+
+    let migrations = Migrations::sync()
+        .await?
+        .apply(Direction::Up, Some(VERSION))
+        .await?;
+    assert_eq!(status(&migrations), MigrationStatus::Applied);
+    migrations.verify()?;
+
+    // Formatting changes don't count as modifications.
+    write(&up, format!("CREATE TABLE {} (id BIGINT);\r\n\r\n", NAME))?;
+    let migrations = Migrations::sync().await?;
+    assert_eq!(status(&migrations), MigrationStatus::Applied);
+    migrations.verify()?;
+
+    // Edit the migration after it was applied.
+    write(
+        &up,
+        format!("CREATE TABLE {} (id BIGINT, name VARCHAR);", NAME),
+    )?;
+    let migrations = Migrations::sync().await?;
+    assert_eq!(status(&migrations), MigrationStatus::Modified);
+
+    match migrations.verify() {
+        Err(Error::MigrationsModified(names)) => {
+            assert_eq!(names, vec![format!("{}_{}", VERSION, NAME)])
+        }
+        result => panic!("expected modified migrations error, got {:?}", result),
+    }
+
+    // Modified migrations block new ones from being applied.
+    let result = Migrations::sync()
+        .await?
+        .apply(Direction::Up, Some(VERSION))
+        .await;
+    assert!(matches!(result, Err(Error::MigrationsModified(_))));
+
+    let migrations = migrations.accept_changes().await?;
+    assert_eq!(status(&migrations), MigrationStatus::Applied);
+    migrations.verify()?;
+
+    // The new checksum was saved.
+    let migrations = Migrations::sync().await?;
+    assert_eq!(status(&migrations), MigrationStatus::Applied);
+    migrations.verify()?;
+
+    migrations.apply(Direction::Down, Some(VERSION)).await?;
+
+    conn.client()
+        .execute("DELETE FROM rwf_migrations WHERE version = $1", &[&VERSION])
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "html-pipeline")]
+use rwf::view::minify::minify_html;
+
+static PAGE: &str = include_str!("golden/page.html");
+static MINIFIED: &str = include_str!("golden/page.min.html");
+
+fn between<'a>(html: &'a str, open: &str, close: &str) -> &'a str {
+    let start = html.find(open).unwrap() + open.len();
+    let end = html[start..].find(close).unwrap() + start;
+    &html[start..end]
+}
+
+#[test]
+fn test_golden() {
+    assert_eq!(minify_html(PAGE), MINIFIED);
+}
+
+#[test]
+fn test_preserved() {
+    let minified = minify_html(PAGE);
+
+    for (open, close) in [
+        ("<pre>", "</pre>"),
+        ("<textarea name=\"notes\">", "</textarea>"),
+        ("<script>", "</script>"),
+        ("<style>", "</style>"),
+    ] {
+        assert_eq!(
+            between(&minified, open, close),
+            between(PAGE, open, close),
+            "{}",
+            open
+        );
+    }
+}
+
+#[test]
+fn test_idempotent() {
+    let minified = minify_html(PAGE);
+    assert_eq!(minify_html(&minified), minified);
+}
@@ -0,0 +1,150 @@
+use rwf::model::migrations::{
+    Direction, MigrationContext, MigrationStatus, RustMigration, RustMigrations, RUST_CHECKSUM,
+};
+use rwf::model::{Error, Migrations};
+use rwf::prelude::*;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const ROWS: i64 = 2_500;
+const BATCH_SIZE: i64 = 1_000;
+
+// Only used by this test, far past any timestamp-based version.
+const VERSION: i64 = 9_000_000_000_528;
+const TABLE: &str = "rwf_test_backfill";
+
+// Backfill a column in batches, committing each batch, so the table
+// isn't locked for the duration of the whole migration.
+struct BackfillStatus {
+    table: String,
+    batches: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl RustMigration for BackfillStatus {
+    fn version(&self) -> i64 {
+        VERSION
+    }
+
+    fn name(&self) -> &str {
+        &self.table
+    }
+
+    fn transaction(&self) -> bool {
+        false
+    }
+
+    async fn up(&self, ctx: &mut MigrationContext) -> Result<(), Error> {
+        let query = format!(
+            "UPDATE {table} SET status = 'active' WHERE id IN (
+                SELECT id FROM {table} WHERE status IS NULL ORDER BY id LIMIT $1
+            )",
+            table = self.table
+        );
+
+        loop {
+            let mut transaction = ctx.transaction().await?;
+            let updated = transaction.execute_cached(&query, &[&BATCH_SIZE]).await?;
+            transaction.commit().await?;
+
+            if updated == 0 {
+                break;
+            }
+
+            self.batches.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, ctx: &mut MigrationContext) -> Result<(), Error> {
+        ctx.execute(&format!("UPDATE {} SET status = NULL", self.table), &[])
+            .await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_batched_backfill() -> Result<(), Error> {
+    let table = TABLE.to_string();
+    let conn = Pool::pool().get().await?;
+
+    // Clean up after a previous run which failed. The migrations
+    // table doesn't exist yet in a new database.
+    conn.client()
+        .execute(&format!("DROP TABLE IF EXISTS {}", table), &[])
+        .await?;
+    conn.client()
+        .execute("DELETE FROM rwf_migrations WHERE version = $1", &[&VERSION])
+        .await
+        .ok();
+    conn.client()
+        .execute(
+            &format!(
+                "CREATE TABLE {} (id BIGSERIAL PRIMARY KEY, status VARCHAR)",
+                table
+            ),
+            &[],
+        )
+        .await?;
+    conn.client()
+        .execute(
+            &format!(
+                "INSERT INTO {} (status) SELECT NULL FROM generate_series(1, $1::bigint)",
+                table
+            ),
+            &[&ROWS],
+        )
+        .await?;
+
+    let batches = Arc::new(AtomicUsize::new(0));
+    let backfill = BackfillStatus {
+        table: table.clone(),
+        batches: batches.clone(),
+    };
+    let version = backfill.version();
+    let registry = RustMigrations::new().migration(backfill);
+
+    let migrations = Migrations::sync_with(&registry).await?;
+    let migration = migrations
+        .migrations()
+        .iter()
+        .find(|migration| migration.version == version)
+        .unwrap();
+    assert_eq!(migrations.status(migration), MigrationStatus::Pending);
+
+    let migrations = migrations.apply(Direction::Up, None).await?;
+    let migration = migrations
+        .migrations()
+        .iter()
+        .find(|migration| migration.version == version)
+        .unwrap();
+    assert_eq!(migrations.status(migration), MigrationStatus::Applied);
+    assert_eq!(migration.checksum.as_deref(), Some(RUST_CHECKSUM));
+
+    let pending: i64 = conn
+        .client()
+        .query_one(
+            &format!("SELECT COUNT(*) FROM {} WHERE status IS NULL", table),
+            &[],
+        )
+        .await?
+        .get(0);
+    assert_eq!(pending, 0);
+    assert_eq!(batches.load(Ordering::Relaxed), 3);
+
+    Migrations::sync_with(&registry)
+        .await?
+        .apply(Direction::Down, Some(version))
+        .await?;
+
+    conn.client()
+        .execute(&format!("DROP TABLE {}", table), &[])
+        .await?;
+    conn.client()
+        .execute("DELETE FROM rwf_migrations WHERE version = $1", &[&VERSION])
+        .await?;
+
+    Ok(())
+}
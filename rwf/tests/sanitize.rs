@@ -0,0 +1,183 @@
+use regex::Regex;
+use rwf::view::sanitize::{sanitize_html, Policy};
+
+// Payloads from the OWASP XSS filter evasion cheat sheet and known mutation XSS vectors.
+static PAYLOADS: &[&str] = &[
+    r#"<script>alert('XSS')</script>"#,
+    r#"<SCRIPT SRC=https://example.com/xss.js></SCRIPT>"#,
+    r#"<IMG SRC="javascript:alert('XSS');">"#,
+    r#"<IMG SRC=javascript:alert('XSS')>"#,
+    r#"<IMG SRC=JaVaScRiPt:alert('XSS')>"#,
+    r#"<IMG SRC=`javascript:alert("RSnake says, 'XSS'")`>"#,
+    r#"<a onmouseover="alert(document.cookie)">xxs link</a>"#,
+    r#"<a onmouseover=alert(document.cookie)>xxs link</a>"#,
+    r#"<IMG """><SCRIPT>alert("XSS")</SCRIPT>"\>"#,
+    r#"<img src=x onerror="&#0000106&#0000097&#0000118&#0000097&#0000115&#0000099&#0000114&#0000105&#0000112&#0000116&#0000058&#0000097&#0000108&#0000101&#0000114&#0000116&#0000040&#0000039&#0000088&#0000083&#0000083&#0000039&#0000041">"#,
+    r#"<a href="&#106;&#97;&#118;&#97;&#115;&#99;&#114;&#105;&#112;&#116;&#58;&#97;&#108;&#101;&#114;&#116;&#40;&#39;&#88;&#83;&#83;&#39;&#41;">x</a>"#,
+    r#"<a href="&#0000106&#0000097&#0000118&#0000097&#0000115&#0000099&#0000114&#0000105&#0000112&#0000116&#0000058&#0000097&#0000108&#0000101&#0000114&#0000116&#0000040&#0000039&#0000088&#0000083&#0000083&#0000039&#0000041">x</a>"#,
+    r#"<a href="&#x6A&#x61&#x76&#x61&#x73&#x63&#x72&#x69&#x70&#x74&#x3A&#x61&#x6C&#x65&#x72&#x74&#x28&#x27&#x58&#x53&#x53&#x27&#x29">x</a>"#,
+    r#"<a href="jav	ascript:alert('XSS');">x</a>"#,
+    r#"<a href="jav&#x09;ascript:alert('XSS');">x</a>"#,
+    r#"<a href="jav&#x0A;ascript:alert('XSS');">x</a>"#,
+    r#"<a href="jav&#x0D;ascript:alert('XSS');">x</a>"#,
+    r#"<a href="java&Tab;script&colon;alert(1)">x</a>"#,
+    r#"<a href=" &#14;  javascript:alert('XSS');">x</a>"#,
+    "<a href=\"java\0script:alert(1)\">x</a>",
+    r#"<SCRIPT/XSS SRC="http://xss.rocks/xss.js"></SCRIPT>"#,
+    r#"<BODY onload!#$%&()*~+-_.,:;?@[/|\]^`=alert("XSS")>"#,
+    r#"<<SCRIPT>alert("XSS");//\<</SCRIPT>"#,
+    r#"<SCRIPT SRC=http://xss.rocks/xss.js?< B >"#,
+    r#"<SCRIPT SRC=//xss.rocks/.j>"#,
+    r#"<IMG SRC="`<javascript:alert>`('XSS')""#,
+    r#"<iframe src=http://xss.rocks/scriptlet.html <"#,
+    r#"</TITLE><SCRIPT>alert("XSS");</SCRIPT>"#,
+    r#"<INPUT TYPE="IMAGE" SRC="javascript:alert('XSS');">"#,
+    r#"<BODY BACKGROUND="javascript:alert('XSS')">"#,
+    r#"<IMG DYNSRC="javascript:alert('XSS')">"#,
+    r#"<IMG LOWSRC="javascript:alert('XSS')">"#,
+    r#"<STYLE>li {list-style-image: url("javascript:alert('XSS')");}</STYLE><UL><LI>XSS</br>"#,
+    r#"<svg/onload=alert('XSS')>"#,
+    r#"<svg><script>alert(1)</script></svg>"#,
+    r#"<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>"#,
+    r#"<BR SIZE="&{alert('XSS')}">"#,
+    r#"<LINK REL="stylesheet" HREF="javascript:alert('XSS');">"#,
+    r#"<STYLE>@import'http://xss.rocks/xss.css';</STYLE>"#,
+    r#"<META HTTP-EQUIV="Link" Content="<http://xss.rocks/xss.css>; REL=stylesheet">"#,
+    r#"<META HTTP-EQUIV="refresh" CONTENT="0;url=javascript:alert('XSS');">"#,
+    r#"<IFRAME SRC="javascript:alert('XSS');"></IFRAME>"#,
+    r#"<FRAMESET><FRAME SRC="javascript:alert('XSS');"></FRAMESET>"#,
+    r#"<TABLE BACKGROUND="javascript:alert('XSS')">"#,
+    r#"<TABLE><TD BACKGROUND="javascript:alert('XSS')">"#,
+    r#"<DIV STYLE="background-image: url(javascript:alert('XSS'))">"#,
+    r#"<DIV STYLE="width: expression(alert('XSS'));">"#,
+    r#"<IMG STYLE="xss:expr/*XSS*/ession(alert('XSS'))">"#,
+    r#"<BASE HREF="javascript:alert('XSS');//">"#,
+    r#"<OBJECT TYPE="text/x-scriptlet" DATA="http://xss.rocks/scriptlet.html"></OBJECT>"#,
+    r#"<EMBED SRC="data:image/svg+xml;base64,PHN2ZyB4bWxuczpzdmc9Imh0dH A6Ly93d3cudzMub3JnLzIwMDAvc3ZnIiB4bWxucz0iaHR0cDovL3d3dy53My5vcmcv MjAwMC9zdmciIHhtbG5zOnhsaW5rPSJodHRwOi8vd3d3LnczLm9yZy8xOTk5L3hs aW5rIiB2ZXJzaW9uPSIxLjAiIHg9IjAiIHk9IjAiIHdpZHRoPSIxOTQiIGhlaWdodD0iMjAw IiBpZD0ieHNzIj48c2NyaXB0IHR5cGU9InRleHQvZWNtYXNjcmlwdCI+YWxlcnQoIlh TUyIpOzwvc2NyaXB0Pjwvc3ZnPg==" type="image/svg+xml" AllowScriptAccess="always"></EMBED>"#,
+    r#"<!--[if gte IE 4]><SCRIPT>alert('XSS');</SCRIPT><![endif]-->"#,
+    r#"<? echo('<SCR)';echo('IPT>alert("XSS")</SCRIPT>'); ?>"#,
+    r#"<a href="data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==">x</a>"#,
+    r#"<a href="vbscript:msgbox('XSS')">x</a>"#,
+    r#"<form><button formaction=javascript:alert(1)>x</button></form>"#,
+    r#"<form id="test"></form><button form="test" formaction="javascript:alert(1)">X</button>"#,
+    r#"<form><form action="javascript:alert(1)"><input type=submit></form></form>"#,
+    r#"<noscript><p title="</noscript><img src=x onerror=alert(1)>">"#,
+    r#"<textarea><img src=x onerror=alert(1)></textarea>"#,
+    r#"<title><img src=x onerror=alert(1)></title>"#,
+    r#"<xmp><img src=x onerror=alert(1)></xmp>"#,
+    r#"<template><img src=x onerror=alert(1)></template>"#,
+    r#"<p><b><i>nested</p></b></i><img src=x onerror=alert(1)>"#,
+    r#"<a href="https://example.com"<script>alert(1)</script>>x</a>"#,
+    r#"<a href='javascript&colon;alert(1)'>x</a>"#,
+    r#"<a href="  javascript:alert(1)">x</a>"#,
+    r#"<a href="&#x20;javascript:alert(1)">x</a>"#,
+    r#"<a href="javascript&#x3a;alert(1)">x</a>"#,
+    r#"<a href=javascript&#58alert(1)>x</a>"#,
+    r#"<a/href="javascript:alert(1)">x</a>"#,
+    r#"<a href="https://example.com" href="javascript:alert(1)">x</a>"#,
+    r#"<!--><script>alert(1)</script>-->"#,
+    r#"<!---><script>alert(1)</script>-->"#,
+    r#"<!-- --!><script>alert(1)</script>-->"#,
+    r#"<scr<script>ipt>alert(1)</scr</script>ipt>"#,
+    r#"<script>alert(1)</script x>"#,
+    r#"<div onclick=alert(1)//"#,
+    r#"<p =onclick=alert(1)>x</p>"#,
+    r#"<p onclick= alert(1)>x</p>"#,
+    r#"<plaintext><script>alert(1)</script>"#,
+    r#"<style><style/><img src=x onerror=alert(1)>"#,
+    r#"<svg><style><img src=x onerror=alert(1)></style></svg>"#,
+    r#"<img src="x` `<script>alert(1)</script>"` `>"#,
+    r#"<a href="x" title="a&quot; onclick=&quot;alert(1)">x</a>"#,
+];
+
+fn assert_safe(policy: &Policy, allowed_tags: &Regex) {
+    let tag = Regex::new(r"<(/?)([^\s>/]*)").unwrap();
+    let attribute = Regex::new(r#"\s([^\s=>]+)="([^"]*)""#).unwrap();
+    let dangerous_url = Regex::new(r"(?i)^\s*(javascript|vbscript|data)\s*:").unwrap();
+
+    for payload in PAYLOADS {
+        let output = sanitize_html(payload, policy);
+
+        // Sanitizing the output again doesn't change it, i.e. the browser will parse it the same way.
+        assert_eq!(sanitize_html(&output, policy), output, "{}", payload);
+
+        for tag in tag.captures_iter(&output) {
+            assert!(
+                allowed_tags.is_match(&tag[2]),
+                "{} => {} contains <{}>",
+                payload,
+                output,
+                &tag[2]
+            );
+        }
+
+        for element in output.split('<').skip(1) {
+            let element = element.split('>').next().unwrap();
+            for attribute in attribute.captures_iter(element) {
+                let (name, value) = (&attribute[1], &attribute[2]);
+                assert!(
+                    ["href", "title", "src", "alt", "width", "height", "colspan", "rowspan"]
+                        .contains(&name),
+                    "{} => {}",
+                    payload,
+                    output
+                );
+                assert!(!dangerous_url.is_match(value), "{} => {}", payload, output);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_xss_corpus_basic() {
+    assert_safe(
+        &Policy::basic(),
+        &Regex::new(r"^(b|i|strong|em|p|br|ul|ol|li|a)$").unwrap(),
+    );
+}
+
+#[test]
+fn test_xss_corpus_relaxed() {
+    assert_safe(
+        &Policy::relaxed(),
+        &Regex::new(r"^(b|i|strong|em|p|br|ul|ol|li|a|h[1-6]|blockquote|code|pre|hr|span|div|table|thead|tbody|tfoot|tr|th|td|caption|img)$").unwrap(),
+    );
+}
+
+#[test]
+fn test_expected_output() {
+    let basic = Policy::basic();
+
+    for (payload, expected) in [
+        (r#"<script>alert('XSS')</script>"#, ""),
+        (
+            r#"<a onmouseover="alert(document.cookie)">xxs link</a>"#,
+            "<a>xxs link</a>",
+        ),
+        (
+            r#"<a href="java&Tab;script&colon;alert(1)">x</a>"#,
+            "<a>x</a>",
+        ),
+        (r#"<a href=javascript&#58alert(1)>x</a>"#, "<a>x</a>"),
+        (
+            r#"<a href="https://example.com" href="javascript:alert(1)">x</a>"#,
+            r#"<a href="https://example.com">x</a>"#,
+        ),
+        (r#"<textarea><img src=x onerror=alert(1)></textarea>"#, ""),
+        (
+            r#"<noscript><p title="</noscript><img src=x onerror=alert(1)>">"#,
+            "&quot;&gt;",
+        ),
+        (r#"<!--><script>alert(1)</script>-->"#, "--&gt;"),
+        (
+            r#"<p><b><i>nested</p></b></i>"#,
+            "<p><b><i>nested</i></b></p>",
+        ),
+        (
+            r#"<a href="x" title="a&quot; onclick=&quot;alert(1)">x</a>"#,
+            r#"<a href="x" title="a&quot; onclick=&quot;alert(1)">x</a>"#,
+        ),
+    ] {
+        assert_eq!(sanitize_html(payload, &basic), expected, "{}", payload);
+    }
+}
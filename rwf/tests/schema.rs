@@ -0,0 +1,68 @@
+use rwf::model::schema::{self, Schema};
+use rwf::model::Error;
+use rwf::prelude::*;
+use tempdir::TempDir;
+
+#[tokio::test]
+async fn test_fetch_and_verify() -> Result<(), Error> {
+    let conn = Pool::pool().get().await?;
+    let client = conn.client();
+
+    // Committed, so the connection reading the catalog can see it.
+    client
+        .batch_execute(
+            "DROP SCHEMA IF EXISTS rwf_test_schema_dump CASCADE;
+            CREATE SCHEMA rwf_test_schema_dump;
+            CREATE SEQUENCE rwf_test_schema_dump.numbers START WITH 100;
+            CREATE TABLE rwf_test_schema_dump.users (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                email VARCHAR(255) NOT NULL UNIQUE,
+                created_at TIMESTAMPTZ DEFAULT NOW()
+            );
+            CREATE INDEX users_created_at_idx ON rwf_test_schema_dump.users (created_at);",
+        )
+        .await?;
+
+    let schema = Schema::fetch("main").await?;
+    let sql = schema.to_sql();
+
+    assert!(sql.contains("CREATE SEQUENCE rwf_test_schema_dump.numbers AS bigint START WITH 100"));
+    assert!(sql.contains(
+        "CREATE TABLE rwf_test_schema_dump.users (
+    id bigint GENERATED BY DEFAULT AS IDENTITY NOT NULL,
+    email character varying(255) NOT NULL,
+    created_at timestamp with time zone DEFAULT now()
+);"
+    ));
+    assert!(sql.contains(
+        "ALTER TABLE rwf_test_schema_dump.users ADD CONSTRAINT users_pkey PRIMARY KEY (id);"
+    ));
+    assert!(sql.contains("CREATE INDEX users_created_at_idx ON rwf_test_schema_dump.users"));
+
+    let dir = TempDir::new("schema")?;
+    let path = dir.path().join("structure.sql");
+    schema::dump_database("main", &path).await?;
+    schema::verify_database("main", &path).await?;
+
+    client
+        .execute(
+            "ALTER TABLE rwf_test_schema_dump.users ADD COLUMN name TEXT",
+            &[],
+        )
+        .await?;
+
+    let result = schema::verify_database("main", &path).await;
+
+    client
+        .execute("DROP SCHEMA rwf_test_schema_dump CASCADE", &[])
+        .await?;
+
+    match result {
+        Err(Error::SchemaDrift(drift)) => {
+            assert!(drift.contains(&"+     name text".to_string()))
+        }
+        result => panic!("expected schema drift, got {:?}", result),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use rwf::controller::Error;
+use rwf::http::Server;
+use rwf::prelude::*;
+use rwf::shutdown::{phase, ready, Phase};
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+#[derive(Default)]
+struct Slow;
+
+#[async_trait]
+impl Controller for Slow {
+    async fn handle(&self, _request: &Request) -> Result<Response, Error> {
+        sleep(Duration::from_millis(500)).await;
+        Ok(Response::new().text("done"))
+    }
+}
+
+async fn send(address: &str, request: &str) -> String {
+    let mut stream = loop {
+        match TcpStream::connect(address).await {
+            Ok(stream) => break stream,
+            Err(_) => sleep(Duration::from_millis(10)).await,
+        }
+    };
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let address = format!("127.0.0.1:{}", port);
+
+    let ran = Arc::new(Mutex::new(vec![]));
+    let hook = |name: &'static str, result: Result<(), &'static str>, delay: u64| {
+        let ran = ran.clone();
+        move || async move {
+            ran.lock().unwrap().push((name, phase()));
+            sleep(Duration::from_millis(delay)).await;
+            result
+        }
+    };
+
+    let server = Server::new(vec![Slow::default().route("/slow")])
+        .on_shutdown(hook("metrics", Ok(()), 0))
+        .on_shutdown(hook("failing", Err("broker is down"), 0))
+        .on_shutdown_timeout(Duration::from_millis(50), hook("stuck", Ok(()), 60_000))
+        .on_shutdown(hook("webhooks", Ok(()), 0));
+
+    let (shutdown, stop) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(server.launch_with_shutdown(address.clone(), async move {
+        let _ = stop.await;
+    }));
+
+    assert!(ready());
+
+    let request = {
+        let address = address.clone();
+        tokio::spawn(async move {
+            send(
+                &address,
+                "GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n",
+            )
+            .await
+        })
+    };
+
+    // Shut down while the request is in progress.
+    sleep(Duration::from_millis(200)).await;
+    shutdown.send(()).unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    assert!(!ready());
+    assert_eq!(phase(), Phase::Draining);
+    assert!(ran.lock().unwrap().is_empty(), "hooks ran before draining");
+
+    // The request is finished and the keep-alive connection is closed.
+    let response = request.await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("done"), "{}", response);
+
+    server.await.unwrap().unwrap();
+    assert_eq!(phase(), Phase::Stopped);
+
+    // Hooks run in reverse order, and failures don't stop the others.
+    assert_eq!(
+        *ran.lock().unwrap(),
+        vec![
+            ("webhooks", Phase::Hooks),
+            ("stuck", Phase::Hooks),
+            ("failing", Phase::Hooks),
+            ("metrics", Phase::Hooks),
+        ]
+    );
+
+    // New connections are refused.
+    assert!(TcpStream::connect(&address).await.is_err());
+}